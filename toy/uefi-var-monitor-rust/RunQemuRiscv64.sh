@@ -0,0 +1,12 @@
+#!/bin/sh
+# Boots QEMU's RISC-V virt machine with EDK2 (RiscVVirtQemu), with the driver
+# image in the root of a FAT drive. The pflash images must be padded to 32MiB.
+qemu-system-riscv64 \
+  -nodefaults \
+  -machine virt,pflash0=pflash0,pflash1=pflash1,acpi=off \
+  -m 256M \
+  -blockdev node-name=pflash0,driver=file,read-only=on,filename=RISCV_VIRT_CODE.fd \
+  -blockdev node-name=pflash1,driver=file,filename=RISCV_VIRT_VARS.fd \
+  -drive format=raw,file=fat:rw:./target/riscv64gc-unknown-uefi/efi \
+  -serial mon:stdio \
+  -display none
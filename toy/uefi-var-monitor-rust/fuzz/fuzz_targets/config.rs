@@ -0,0 +1,34 @@
+// uefi-var-monitor-rust/fuzz/fuzz_targets/config.rs
+//
+// The framing of the UvmConfig blob: header, size and CRC of the sections,
+// then the sections, and what restoring it reads out of them. Mutations rarely keep the CRC right, so the first byte
+// of the input asks for the size and CRC to be set from the rest, which lets
+// the section walk be reached.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uefi_var_monitor::config_format::{self, CONFIG_HEADER_SIZE, SECTION_HEADER_SIZE};
+use uefi_var_monitor::crc32::crc32;
+use uefi_var_monitor::settings::RuntimeConfig;
+
+fuzz_target!(|data: &[u8]| {
+    let (fix, blob) = match data.split_first() {
+        Some((first, blob)) => (first & 1 != 0, blob),
+        None => return,
+    };
+    let mut blob = blob.to_vec();
+    if fix && blob.len() >= CONFIG_HEADER_SIZE {
+        let crc = crc32(&blob[CONFIG_HEADER_SIZE..]);
+        let length = (blob.len() - CONFIG_HEADER_SIZE) as u32;
+        blob[24..28].copy_from_slice(&length.to_le_bytes());
+        blob[28..32].copy_from_slice(&crc.to_le_bytes());
+    }
+    if let Ok((_, sections)) = config_format::sections(&blob) {
+        let walked: usize = sections
+            .map(|(_, value)| SECTION_HEADER_SIZE + value.len())
+            .sum();
+        assert!(walked + CONFIG_HEADER_SIZE <= blob.len());
+    }
+    let _ = config_format::decode(&blob, &mut RuntimeConfig::from_build_env());
+});
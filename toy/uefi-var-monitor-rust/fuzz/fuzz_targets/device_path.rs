@@ -0,0 +1,16 @@
+// uefi-var-monitor-rust/fuzz/fuzz_targets/device_path.rs
+//
+// The device path walk of the data hints, and the hints around it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uefi_var_monitor::hint;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(nodes) = hint::device_path_nodes(data) {
+        // Each node takes at least its 4-byte header.
+        assert!(nodes >= 1 && nodes <= data.len() / 4);
+    }
+    hint::classify(data);
+});
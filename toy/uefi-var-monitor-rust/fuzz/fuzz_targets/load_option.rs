@@ -0,0 +1,17 @@
+// uefi-var-monitor-rust/fuzz/fuzz_targets/load_option.rs
+//
+// The Boot#### load options and BootOrder option numbers, as shown in the
+// alerts on boot manager writes.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::fmt::Write;
+use uefi_var_monitor::load_option::{LoadOption, OptionNumbers};
+
+fuzz_target!(|data: &[u8]| {
+    let mut shown = String::new();
+    write!(shown, "{}", LoadOption(data)).unwrap();
+    shown.clear();
+    write!(shown, "{}", OptionNumbers(data)).unwrap();
+});
@@ -0,0 +1,20 @@
+// uefi-var-monitor-rust/fuzz/fuzz_targets/name.rs
+//
+// The variable name decoder of the G: and S: records, on the UCS-2 units of
+// the input, little-endian as in memory; an odd last byte is dropped.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use uefi_var_monitor::record::{convert_name, NAME_LENGTH};
+
+fuzz_target!(|data: &[u8]| {
+    let characters: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    let mut buffer = [0u8; NAME_LENGTH];
+    let name = convert_name(&characters, &mut buffer);
+    assert!(name.len() <= NAME_LENGTH);
+    assert!(name.bytes().all(|c| (0x20..0x7f).contains(&c)));
+});
@@ -0,0 +1,27 @@
+// uefi-var-monitor-rust/fuzz/fuzz_targets/signature_list.rs
+//
+// The EFI_SIGNATURE_LIST walk of the appends to db, dbx, dbt and dbr, as a
+// plain write and as a time-based authenticated one. The first byte of the
+// input picks which.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use r_efi::efi;
+use uefi_var_monitor::signature_list::{self, MAX_SIGNATURE_LISTS};
+
+fuzz_target!(|data: &[u8]| {
+    let (attributes, payload) = match data.split_first() {
+        Some((first, payload)) if first & 1 != 0 => {
+            (efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS, payload)
+        }
+        Some((_, payload)) => (0, payload),
+        None => (0, data),
+    };
+    if let Ok(summary) = signature_list::parse(attributes, payload) {
+        assert!(summary.lists >= 1 && summary.lists <= MAX_SIGNATURE_LISTS);
+        // Each entry starts with its 16-byte owner.
+        assert!(summary.entries <= payload.len() / 16);
+    }
+    let _ = signature_list::lists_offset(attributes, payload);
+});
@@ -0,0 +1,296 @@
+// uefi-var-monitor-rust/interface/src/build.rs
+//
+// What a driver was built as, and what it loaded its configuration from: the
+// banner it logs at load (see the driver's src/banner.rs), kept with the data
+// it produced so that a dump or a report read later says which build wrote
+// it. The ring buffer header (see ring.rs) and the boot report (see report.rs)
+// both end with one; the statistics protocol hands it out (see protocol.rs).
+//
+// The texts are ASCII, NUL-padded, cut at the size of their field. The sets
+// are bits of the HOOK_*, SINK_* and CONFIG_FROM_* values below. A BuildInfo
+// of zeroes is one an older format did not have.
+//
+// The banner's lines are written from a BuildInfo by Identity, Names, Serial
+// and Sources, by the driver and by the tools alike:
+//
+//   UefiVarMonitor 0.1.1, custom profile, release, build ID 3f2a9c1
+//   Hooks: GetVariable, SetVariable
+//   Sinks: serial, ring
+//   Serial: I/O port 0x3f8, line set up by the firmware, inline
+//   Configuration: build defaults, UvmConfig, load options
+
+use core::fmt;
+
+// Bytes of BuildInfo::version and BuildInfo::profile.
+pub const BUILD_TEXT_SIZE: usize = 16;
+// Bytes of BuildInfo::build_id, a full SHA-1 commit in hex.
+pub const BUILD_ID_SIZE: usize = 40;
+
+// BuildInfo::flags
+// Built with debug assertions.
+pub const BUILD_FLAG_DEBUG: u32 = 1 << 0;
+// Serial output goes through the drain timer's queue (serial-queue), and per
+// CPU at OS runtime (per-cpu).
+pub const BUILD_FLAG_SERIAL_QUEUE: u32 = 1 << 1;
+pub const BUILD_FLAG_PER_CPU: u32 = 1 << 2;
+
+// BuildInfo::hooks, the runtime services hooked.
+pub const HOOK_GET_VARIABLE: u32 = 1 << 0;
+pub const HOOK_SET_VARIABLE: u32 = 1 << 1;
+pub const HOOK_GET_NEXT_VARIABLE_NAME: u32 = 1 << 2;
+
+// BuildInfo::sinks, where records and alerts are written.
+pub const SINK_SERIAL: u32 = 1 << 0;
+pub const SINK_RING: u32 = 1 << 1;
+pub const SINK_NET: u32 = 1 << 2;
+pub const SINK_GOP: u32 = 1 << 3;
+pub const SINK_TPM: u32 = 1 << 4;
+
+// BuildInfo::config, what the configuration was loaded from on top of the
+// build-time defaults: none of them leaves the defaults alone.
+pub const CONFIG_FROM_VARIABLE: u32 = 1 << 0;
+pub const CONFIG_FROM_LEVEL_VARIABLE: u32 = 1 << 1;
+pub const CONFIG_FROM_LOAD_OPTIONS: u32 = 1 << 2;
+
+// BuildInfo::serial, how the debug serial port is reached.
+pub const SERIAL_NONE: u32 = 0;
+pub const SERIAL_IO_PORT: u32 = 1;
+pub const SERIAL_MMIO: u32 = 2;
+
+pub const HOOK_NAMES: [(u32, &str); 3] = [
+    (HOOK_GET_VARIABLE, "GetVariable"),
+    (HOOK_SET_VARIABLE, "SetVariable"),
+    (HOOK_GET_NEXT_VARIABLE_NAME, "GetNextVariableName"),
+];
+pub const SINK_NAMES: [(u32, &str); 5] = [
+    (SINK_SERIAL, "serial"),
+    (SINK_RING, "ring"),
+    (SINK_NET, "net"),
+    (SINK_GOP, "gop"),
+    (SINK_TPM, "tpm"),
+];
+pub const CONFIG_NAMES: [(u32, &str); 3] = [
+    (CONFIG_FROM_VARIABLE, "UvmConfig"),
+    (CONFIG_FROM_LEVEL_VARIABLE, "UvmLevel"),
+    (CONFIG_FROM_LOAD_OPTIONS, "load options"),
+];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    // The crate version.
+    pub version: [u8; BUILD_TEXT_SIZE],
+    // The build profile: forensics, production, minimal or custom.
+    pub profile: [u8; BUILD_TEXT_SIZE],
+    // UVM_BUILD_ID at build time, empty without one.
+    pub build_id: [u8; BUILD_ID_SIZE],
+    pub flags: u32,
+    pub hooks: u32,
+    pub sinks: u32,
+    pub config: u32,
+    // SERIAL_*, and the port or address.
+    pub serial: u32,
+    pub reserved: u32,
+    pub serial_base: u64,
+}
+
+const _: () = assert!(core::mem::size_of::<BuildInfo>() == 104);
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        BuildInfo::EMPTY
+    }
+}
+
+impl BuildInfo {
+    pub const EMPTY: BuildInfo = BuildInfo {
+        version: [0; BUILD_TEXT_SIZE],
+        profile: [0; BUILD_TEXT_SIZE],
+        build_id: [0; BUILD_ID_SIZE],
+        flags: 0,
+        hooks: 0,
+        sinks: 0,
+        config: 0,
+        serial: SERIAL_NONE,
+        reserved: 0,
+        serial_base: 0,
+    };
+
+    /**
+     * @brief Returns `text` as a field of N bytes, cut and NUL-padded.
+     */
+    pub const fn text<const N: usize>(text: &str) -> [u8; N] {
+        let bytes = text.as_bytes();
+        let mut field = [0u8; N];
+        let mut index = 0;
+        while index < N && index < bytes.len() {
+            field[index] = bytes[index];
+            index += 1;
+        }
+        field
+    }
+
+    pub fn version(&self) -> &str {
+        text_of(&self.version)
+    }
+
+    pub fn profile(&self) -> &str {
+        text_of(&self.profile)
+    }
+
+    pub fn build_id(&self) -> &str {
+        text_of(&self.build_id)
+    }
+
+    pub fn is_recorded(&self) -> bool {
+        self.version[0] != 0
+    }
+}
+
+/**
+ * @brief Returns a text field up to its padding, "?" if it is not ASCII.
+ */
+fn text_of(field: &[u8]) -> &str {
+    let length = field.iter().position(|&c| c == 0).unwrap_or(field.len());
+    match core::str::from_utf8(&field[..length]) {
+        Ok(text) if text.is_ascii() => text,
+        _ => "?",
+    }
+}
+
+// The identity of a build, on one line.
+pub struct Identity<'a>(pub &'a BuildInfo);
+
+impl fmt::Display for Identity<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let build = self.0;
+        let kind = match build.flags & BUILD_FLAG_DEBUG {
+            0 => "release",
+            _ => "debug",
+        };
+        write!(
+            f,
+            "UefiVarMonitor {}, {} profile, {}, ",
+            build.version(),
+            build.profile(),
+            kind
+        )?;
+        match build.build_id() {
+            "" => f.write_str("no build ID"),
+            id => write!(f, "build ID {}", id),
+        }
+    }
+}
+
+// The names of the bits set in a set, comma-separated, or "none". Bits
+// without a name are written in hex.
+pub struct Names<'a>(pub u32, pub &'a [(u32, &'a str)]);
+
+impl fmt::Display for Names<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        let mut unnamed = self.0;
+        for (bit, name) in self.1 {
+            if self.0 & bit != 0 {
+                write!(f, "{}{}", separator, name)?;
+                separator = ", ";
+                unnamed &= !bit;
+            }
+        }
+        match (unnamed, separator) {
+            (0, "") => f.write_str("none"),
+            (0, _) => Ok(()),
+            (unnamed, _) => write!(f, "{}{:#x}", separator, unnamed),
+        }
+    }
+}
+
+// The serial port of a build, as the banner gives it.
+pub struct Serial<'a>(pub &'a BuildInfo);
+
+impl fmt::Display for Serial<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let build = self.0;
+        match build.serial {
+            SERIAL_NONE => return f.write_str("none"),
+            SERIAL_IO_PORT => write!(f, "I/O port {:#x}", build.serial_base)?,
+            SERIAL_MMIO => write!(f, "MMIO {:#x}", build.serial_base)?,
+            serial => return write!(f, "unknown ({})", serial),
+        }
+        // The driver leaves the line settings as the firmware made them.
+        f.write_str(", line set up by the firmware")?;
+        if build.flags & BUILD_FLAG_PER_CPU != 0 {
+            f.write_str(", queued, per CPU at runtime")
+        } else if build.flags & BUILD_FLAG_SERIAL_QUEUE != 0 {
+            f.write_str(", queued")
+        } else {
+            f.write_str(", inline")
+        }
+    }
+}
+
+// What the configuration was loaded from, the build-time defaults first.
+pub struct Sources(pub u32);
+
+impl fmt::Display for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("build defaults")?;
+        match self.0 {
+            0 => Ok(()),
+            sources => write!(f, ", {}", Names(sources, &CONFIG_NAMES)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_and_sets_read_back_as_written() {
+        let build = BuildInfo {
+            version: BuildInfo::text("0.1.1"),
+            profile: BuildInfo::text("production"),
+            build_id: BuildInfo::text("0123456789abcdef0123456789abcdef0123456789"),
+            hooks: HOOK_GET_VARIABLE | HOOK_SET_VARIABLE,
+            sinks: SINK_SERIAL | 1 << 7,
+            serial: SERIAL_IO_PORT,
+            serial_base: 0x3f8,
+            flags: BUILD_FLAG_SERIAL_QUEUE,
+            ..BuildInfo::EMPTY
+        };
+        assert!(build.is_recorded() && !BuildInfo::EMPTY.is_recorded());
+        assert_eq!((build.version(), build.profile()), ("0.1.1", "production"));
+        // Cut at the size of the field.
+        assert_eq!(build.build_id(), "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(BuildInfo::EMPTY.build_id(), "");
+
+        assert_eq!(
+            std::format!("{}", Names(build.hooks, &HOOK_NAMES)),
+            "GetVariable, SetVariable"
+        );
+        assert_eq!(
+            std::format!("{}", Names(build.sinks, &SINK_NAMES)),
+            "serial, 0x80"
+        );
+        assert_eq!(std::format!("{}", Names(0, &SINK_NAMES)), "none");
+        assert_eq!(std::format!("{}", Sources(0)), "build defaults");
+        assert_eq!(
+            std::format!(
+                "{}",
+                Sources(CONFIG_FROM_VARIABLE | CONFIG_FROM_LOAD_OPTIONS)
+            ),
+            "build defaults, UvmConfig, load options"
+        );
+        assert_eq!(
+            std::format!("{}", Identity(&build)),
+            "UefiVarMonitor 0.1.1, production profile, release, build ID \
+             0123456789abcdef0123456789abcdef01234567"
+        );
+        assert_eq!(
+            std::format!("{}", Serial(&build)),
+            "I/O port 0x3f8, line set up by the firmware, queued"
+        );
+        assert_eq!(std::format!("{}", Serial(&BuildInfo::EMPTY)), "none");
+    }
+}
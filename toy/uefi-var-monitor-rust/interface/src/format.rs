@@ -0,0 +1,134 @@
+// uefi-var-monitor-rust/interface/src/format.rs
+//
+// The header every format the driver leaves behind starts with: the ring
+// buffer and its dumps (see ring.rs), the boot-report variable (see
+// report.rs) and the saved configuration (see the driver's
+// src/config_store.rs). Readers check it before anything else.
+//
+//   0    magic, 4 ASCII bytes naming the format
+//   4    major version, u16
+//   6    minor version, u16
+//   8    size of the format's header, this one included, u32
+//   12   size of each record after the header, 0 if there are none, u32
+//   16   flags, per format, u32
+//   20   reserved, zero
+//
+// A minor version only appends: fields at the end of the header, or of the
+// records, whose size fields grow with them. A reader takes what it knows of
+// any minor version, skipping past the sizes given, and what an older one
+// lacks reads as zero. Anything else is a new major version, which readers
+// of another major refuse with FormatError::Major rather than misread.
+//
+// The MM messages are exchanged live between modules, not kept, and carry
+// their own version instead (see mm.rs).
+
+use crate::{read, FormatError};
+
+pub const FORMAT_HEADER_SIZE: usize = 24;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormatHeader {
+    pub magic: [u8; 4],
+    pub major: u16,
+    pub minor: u16,
+    pub size: u32,
+    pub record_size: u32,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+const _: () = assert!(core::mem::size_of::<FormatHeader>() == FORMAT_HEADER_SIZE);
+
+impl FormatHeader {
+    pub const fn new(
+        magic: [u8; 4],
+        major: u16,
+        minor: u16,
+        size: usize,
+        record_size: usize,
+    ) -> Self {
+        FormatHeader {
+            magic,
+            major,
+            minor,
+            size: size as u32,
+            record_size: record_size as u32,
+            flags: 0,
+            reserved: 0,
+        }
+    }
+
+    /**
+     * @brief Reads the header at the start of `bytes`, refusing another
+     *        format than `magic` and another major version than `major`.
+     */
+    pub fn parse(bytes: &[u8], magic: [u8; 4], major: u16) -> Result<Self, FormatError> {
+        let mut header = FormatHeader::default();
+        read(&mut header, bytes)?;
+        if header.magic != magic {
+            return Err(FormatError::Signature);
+        }
+        if header.major != major {
+            return Err(FormatError::Major {
+                found: header.major,
+                supported: major,
+            });
+        }
+        Ok(header)
+    }
+
+    /**
+     * @brief Returns the size of the header, refusing one smaller than the
+     *        `known` fields or larger than `available`.
+     */
+    pub fn header_size(&self, known: usize, available: usize) -> Result<usize, FormatError> {
+        let size = self.size as usize;
+        if size < known || size > available {
+            return Err(FormatError::Truncated);
+        }
+        Ok(size)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAGIC: [u8; 4] = *b"TEST";
+
+    #[test]
+    fn other_formats_and_majors_are_refused() {
+        let mut header = FormatHeader::new(MAGIC, 1, 3, 40, 8);
+        assert_eq!(FormatHeader::parse(header.as_bytes(), MAGIC, 1), Ok(header));
+        assert_eq!(header.header_size(32, 40), Ok(40));
+        assert_eq!(header.header_size(48, 64), Err(FormatError::Truncated));
+        assert_eq!(header.header_size(32, 39), Err(FormatError::Truncated));
+
+        assert_eq!(
+            FormatHeader::parse(header.as_bytes(), *b"UVMX", 1),
+            Err(FormatError::Signature)
+        );
+        assert_eq!(
+            FormatHeader::parse(&header.as_bytes()[..FORMAT_HEADER_SIZE - 1], MAGIC, 1),
+            Err(FormatError::Truncated)
+        );
+        header.major = 2;
+        let error = FormatHeader::parse(header.as_bytes(), MAGIC, 1).unwrap_err();
+        assert_eq!(
+            error,
+            FormatError::Major {
+                found: 2,
+                supported: 1
+            }
+        );
+        assert_eq!(
+            std::format!("{}", error),
+            "format version 2.x, this build reads version 1.x only"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+// uefi-var-monitor-rust/interface/src/hex.rs
+//
+// Text built straight into a byte buffer, for the records written on every
+// variable access: the G: and S: records of the hooks and of RecordText (see
+// ring.rs). Their fields are fixed-width hexadecimal numbers and GUIDs, for
+// which core::fmt goes through formatter state, padding and trait objects
+// per field. Line writes the digits itself, and the bytes are the same as
+// those of the format strings noted on each method. Everything logged less
+// often keeps using core::fmt.
+//
+// The hooks must not panic (see the driver's main.rs), so nothing here
+// indexes where the optimizer cannot tell that it stays in bounds.
+
+use core::fmt;
+use r_efi::efi::Guid;
+
+// Characters of a GUID in registry format.
+pub const GUID_TEXT_LENGTH: usize = 36;
+
+const LOWER: &[u8; 16] = b"0123456789abcdef";
+const UPPER: &[u8; 16] = b"0123456789ABCDEF";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Case {
+    Lower,
+    Upper,
+}
+
+/**
+ * @brief Text of at most N bytes. What does not fit is cut off at a
+ *        character boundary, and nothing is appended after the cut.
+ */
+pub struct Line<const N: usize> {
+    bytes: [u8; N],
+    length: usize,
+    cut: bool,
+}
+
+impl<const N: usize> Line<N> {
+    pub const fn new() -> Self {
+        Line {
+            bytes: [0; N],
+            length: 0,
+            cut: false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are ever pushed.
+        core::str::from_utf8(self.bytes.get(..self.length).unwrap_or(&[])).unwrap_or("")
+    }
+
+    pub fn push_str(&mut self, text: &str) -> &mut Self {
+        if self.cut {
+            return self;
+        }
+        let mut count = core::cmp::min(N.saturating_sub(self.length), text.len());
+        if count < text.len() {
+            self.cut = true;
+            while !text.is_char_boundary(count) {
+                count -= 1;
+            }
+        }
+        self.append(text.as_bytes().get(..count).unwrap_or(&[]));
+        self
+    }
+
+    pub fn push_char(&mut self, c: char) -> &mut Self {
+        let mut bytes = [0u8; 4];
+        self.push_str(c.encode_utf8(&mut bytes))
+    }
+
+    /**
+     * @brief Appends `value` in hexadecimal, zero-padded to `width` digits
+     *        (at most 16) and wider if it needs to be: "{:0<width>x}", or
+     *        "{:0<width>X}" in Case::Upper. Serves u8 to u64 alike.
+     */
+    pub fn push_hex(&mut self, value: u64, width: usize, case: Case) -> &mut Self {
+        let digits = match case {
+            Case::Lower => LOWER,
+            Case::Upper => UPPER,
+        };
+        let mut text = [b'0'; 16];
+        let mut start = 0;
+        let mut rest = value;
+        for (index, digit) in text.iter_mut().enumerate().rev() {
+            *digit = digits[(rest & 0xf) as usize];
+            rest >>= 4;
+            if rest == 0 {
+                start = index;
+                break;
+            }
+        }
+        let start = core::cmp::min(start, text.len() - core::cmp::min(width, text.len()));
+        self.push_ascii(text.get(start..).unwrap_or(&[]))
+    }
+
+    /**
+     * @brief Appends `value` like "{:#x}": 0x, then as many digits as it
+     *        takes.
+     */
+    pub fn push_prefixed_hex(&mut self, value: u64) -> &mut Self {
+        self.push_str("0x").push_hex(value, 1, Case::Lower)
+    }
+
+    /**
+     * @brief Appends a GUID in registry format, as
+     *        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-" and six "{:02X}".
+     */
+    pub fn push_guid(&mut self, guid: &Guid) -> &mut Self {
+        self.push_ascii(&guid_text(guid))
+    }
+
+    // Only for ASCII, which can be cut anywhere.
+    fn push_ascii(&mut self, text: &[u8]) -> &mut Self {
+        if self.cut {
+            return self;
+        }
+        let count = core::cmp::min(N.saturating_sub(self.length), text.len());
+        self.cut = count < text.len();
+        self.append(text.get(..count).unwrap_or(&[]));
+        self
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        if let Some(slot) = self.bytes.get_mut(self.length..self.length + bytes.len()) {
+            slot.copy_from_slice(bytes);
+            self.length += bytes.len();
+        }
+    }
+}
+
+impl<const N: usize> Default for Line<N> {
+    fn default() -> Self {
+        Line::new()
+    }
+}
+
+/**
+ * @brief Returns a GUID in registry format, as Line::push_guid() writes it.
+ */
+pub fn guid_text(guid: &Guid) -> [u8; GUID_TEXT_LENGTH] {
+    let (time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low, node) = guid.as_fields();
+    let mut text = [b'-'; GUID_TEXT_LENGTH];
+    put_hex(&mut text[0..8], u64::from(time_low));
+    put_hex(&mut text[9..13], u64::from(time_mid));
+    put_hex(&mut text[14..18], u64::from(time_hi));
+    put_hex(&mut text[19..21], u64::from(clk_seq_hi));
+    put_hex(&mut text[21..23], u64::from(clk_seq_low));
+    for (index, &byte) in node.iter().enumerate() {
+        put_hex(&mut text[24 + 2 * index..26 + 2 * index], u64::from(byte));
+    }
+    text
+}
+
+/**
+ * @brief Fills `digits` with the low digits of `value`, in upper case.
+ */
+fn put_hex(digits: &mut [u8], value: u64) {
+    let mut rest = value;
+    for digit in digits.iter_mut().rev() {
+        *digit = UPPER[(rest & 0xf) as usize];
+        rest >>= 4;
+    }
+}
+
+// For the records that do go through core::fmt into the same buffer.
+impl<const N: usize> fmt::Write for Line<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [u64; 9] = [
+        0,
+        1,
+        0xf,
+        0x10,
+        0xab,
+        0x1234,
+        0xdead_beef,
+        0x1_0000_0000,
+        u64::MAX,
+    ];
+
+    fn line() -> Line<64> {
+        Line::new()
+    }
+
+    #[test]
+    fn hex_matches_core_fmt() {
+        for &value in VALUES.iter() {
+            for &width in [1, 2, 4, 8, 16].iter() {
+                assert_eq!(
+                    line().push_hex(value, width, Case::Lower).as_str(),
+                    format!("{:0width$x}", value, width = width)
+                );
+                assert_eq!(
+                    line().push_hex(value, width, Case::Upper).as_str(),
+                    format!("{:0width$X}", value, width = width)
+                );
+            }
+            assert_eq!(
+                line().push_prefixed_hex(value).as_str(),
+                format!("{:#x}", value)
+            );
+        }
+    }
+
+    #[test]
+    fn guid_matches_core_fmt() {
+        let guids = [
+            Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+            Guid::from_fields(
+                0x8be4_df61,
+                0x93ca,
+                0x11d2,
+                0xaa,
+                0x0d,
+                &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+            ),
+            Guid::from_fields(u32::MAX, u16::MAX, u16::MAX, u8::MAX, u8::MAX, &[0xff; 6]),
+        ];
+        for guid in guids.iter() {
+            let (a, b, c, d, e, node) = guid.as_fields();
+            let mut expected = format!("{:08X}-{:04X}-{:04X}-{:02X}{:02X}-", a, b, c, d, e);
+            for byte in node.iter() {
+                expected += &format!("{:02X}", byte);
+            }
+            assert_eq!(line().push_guid(guid).as_str(), expected);
+            assert_eq!(expected.len(), GUID_TEXT_LENGTH);
+        }
+    }
+
+    #[test]
+    fn cut_at_a_character_boundary() {
+        let mut short = Line::<5>::new();
+        short
+            .push_str("ab")
+            .push_char('é')
+            .push_str("éx")
+            .push_str("y");
+        assert_eq!(short.as_str(), "abé");
+        let mut short = Line::<4>::new();
+        short.push_hex(0x12345, 1, Case::Lower).push_str("z");
+        assert_eq!(short.as_str(), "1234");
+    }
+}
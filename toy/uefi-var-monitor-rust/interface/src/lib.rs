@@ -0,0 +1,111 @@
+// uefi-var-monitor-rust/interface/src/lib.rs
+//
+// Definitions shared between the driver and the applications and host tools
+// that talk to it (see tools/uvmctl and tools/uvmlog), and its Standalone MM
+// module (see mm/). Only layouts, constants and what is needed to read them
+// back live here, with the efiapi! macro the driver and the MM module declare
+// their firmware callbacks with; what the driver does behind them stays in the
+// driver.
+//
+//   build      what a driver was built as, kept with what it writes
+//   format     the header every kept format starts with, and its versions
+//   hex        hexadecimal and GUID text without core::fmt
+//   mm         the messages of the MM module, through MM Communicate
+//   percpu     the tag of the serial records of the per-CPU queues
+//   protocol   the control and statistics protocols
+//   report     the boot-report variable
+//   ring       the ring buffer header and records, and their chain
+//   sha256     the hash the chain is built with
+//
+// The crate is no_std, for the driver and the shell applications, and host
+// tools use it as it is: a std feature would link std into the driver too
+// whenever the workspace is built as a whole.
+
+#![cfg_attr(not(test), no_std)]
+
+// Declares a function, or function pointer type, with the UEFI calling
+// convention of the target: extern "win64" on x86_64, extern "C" on aarch64.
+// It is the convention r-efi's own service and callback types are declared
+// with, which extern "efiapi" would not be type-compatible with. An ABI string
+// cannot come out of a macro by itself, hence the wrapping. For the driver and
+// its MM module, which both hook firmware tables; the users depend on r-efi.
+#[macro_export]
+macro_rules! efiapi {
+    ($(#[$attr:meta])* $vis:vis fn $name:ident $($rest:tt)*) => {
+        r_efi::eficall_abi! {($(#[$attr])* $vis), (fn $name $($rest)*)}
+    };
+    (fn $($rest:tt)*) => {
+        r_efi::eficall_abi! {(), (fn $($rest)*)}
+    };
+}
+
+pub mod build;
+pub mod format;
+pub mod hex;
+pub mod mm;
+pub mod percpu;
+pub mod protocol;
+pub mod report;
+pub mod ring;
+pub mod sha256;
+
+use core::fmt;
+
+// Why data read back from the driver was refused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    // Shorter than its layout says.
+    Truncated,
+    // Another format, by the magic of its header (see format.rs).
+    Signature,
+    // Records smaller than the layout this crate knows.
+    RecordSize,
+    // A major version other than the one this crate reads.
+    Major { found: u16, supported: u16 },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Truncated => f.write_str("truncated"),
+            FormatError::Signature => f.write_str("bad signature"),
+            FormatError::RecordSize => f.write_str("unexpected record size"),
+            FormatError::Major { found, supported } => write!(
+                f,
+                "format version {}.x, this build reads version {}.x only",
+                found, supported
+            ),
+        }
+    }
+}
+
+impl core::error::Error for FormatError {}
+
+/**
+ * @brief Returns the bytes of a value laid out for the driver.
+ */
+fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    }
+}
+
+/**
+ * @brief Overwrites the start of a value with `bytes`, as many as fit. Only
+ *        for the plain layouts of this crate, which any bytes are valid for.
+ */
+fn read_prefix<T: Copy>(value: &mut T, bytes: &[u8]) {
+    let count = core::cmp::min(bytes.len(), core::mem::size_of::<T>());
+    unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), value as *mut T as *mut u8, count) };
+}
+
+/**
+ * @brief Reads a value from the start of `bytes`.
+ */
+fn read<T: Copy>(value: &mut T, bytes: &[u8]) -> Result<(), FormatError> {
+    if bytes.len() < core::mem::size_of::<T>() {
+        return Err(FormatError::Truncated);
+    }
+    read_prefix(value, bytes);
+    Ok(())
+}
@@ -0,0 +1,198 @@
+// uefi-var-monitor-rust/interface/src/mm.rs
+//
+// Messages between the monitor's Standalone MM module (see mm/) and the DXE
+// driver, sent through MM Communicate. The communicate header names
+// UVM_MM_COMMUNICATE_GUID; its data is an MmRequest, followed by the payload
+// of the function, and the MM module writes the reply over it:
+//
+//   MM_FUNCTION_INFO   no payload; replies with an MmInfo
+//   MM_FUNCTION_READ   an MmRead asking for events from first_sequence on;
+//                      replies with an MmRead giving the sequence number and
+//                      count of the events returned, then the events, oldest
+//                      first, at most MM_READ_MAX
+//
+// MmRequest::status carries the outcome, as the value of an EFI status.
+// A request of another version is refused with INCOMPATIBLE_VERSION. Events
+// older than the MM module still holds are lost: the reply then starts past
+// first_sequence.
+
+use crate::{read, FormatError};
+use r_efi::efi;
+
+// {b1f4e8d2-6c3a-4e97-a5d1-0f8c2b7e4a63}
+pub const UVM_MM_COMMUNICATE_GUID: efi::Guid = efi::Guid::from_fields(
+    0xb1f4e8d2,
+    0x6c3a,
+    0x4e97,
+    0xa5,
+    0xd1,
+    &[0x0f, 0x8c, 0x2b, 0x7e, 0x4a, 0x63],
+);
+
+pub const MM_VERSION: u32 = 1;
+
+pub const MM_FUNCTION_INFO: u32 = 1;
+pub const MM_FUNCTION_READ: u32 = 2;
+
+// Events returned by one read.
+pub const MM_READ_MAX: usize = 16;
+pub const MM_EVENT_SIZE: usize = 128;
+// Bytes of a variable name kept in an MmEvent, as printable ASCII.
+pub const MM_NAME_SIZE: usize = 80;
+
+// MmEvent::source
+// A SetVariable request sent to the MM variable driver through an SMI, seen
+// before that driver handled it.
+pub const MM_SOURCE_COMMUNICATE: u8 = 1;
+// A call to the MM variable protocol by another MM module.
+pub const MM_SOURCE_PROTOCOL: u8 = 2;
+
+// MmEvent::status of an event whose outcome is not known.
+pub const MM_STATUS_UNKNOWN: u64 = u64::MAX;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MmRequest {
+    pub function: u32,
+    pub version: u32,
+    pub status: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MmInfo {
+    pub capacity: u32,
+    pub event_size: u32,
+    // Sequence number of the oldest event held.
+    pub first_sequence: u64,
+    // Sequence number the next event will get.
+    pub next_sequence: u64,
+    // Events not recorded, as the buffer was in use.
+    pub dropped: u64,
+    // Whether the MMI handler is registered and the protocol hooked.
+    pub handler_registered: u32,
+    pub protocol_hooked: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MmRead {
+    pub first_sequence: u64,
+    pub count: u32,
+    pub reserved: u32,
+}
+
+// A variable write seen in MM.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MmEvent {
+    pub sequence: u64,
+    pub status: u64,
+    pub guid: efi::Guid,
+    pub attributes: u32,
+    pub data_size: u32,
+    pub source: u8,
+    pub name_length: u8,
+    pub reserved: [u8; 6],
+    pub name: [u8; MM_NAME_SIZE],
+}
+
+const _: () = assert!(core::mem::size_of::<MmEvent>() == MM_EVENT_SIZE);
+
+// The largest message: a read reply.
+pub const MM_MESSAGE_SIZE: usize = core::mem::size_of::<MmRequest>()
+    + core::mem::size_of::<MmRead>()
+    + MM_READ_MAX * MM_EVENT_SIZE;
+
+impl MmEvent {
+    pub const EMPTY: MmEvent = MmEvent {
+        sequence: 0,
+        status: 0,
+        guid: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        attributes: 0,
+        data_size: 0,
+        source: 0,
+        name_length: 0,
+        reserved: [0; 6],
+        name: [0; MM_NAME_SIZE],
+    };
+
+    /**
+     * @brief Reads an event from the start of `bytes`.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut event = MmEvent::EMPTY;
+        read(&mut event, bytes)?;
+        Ok(event)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+
+    pub fn name(&self) -> &str {
+        let name = &self.name[..core::cmp::min(self.name_length as usize, MM_NAME_SIZE)];
+        core::str::from_utf8(name).unwrap_or("?")
+    }
+}
+
+impl MmRequest {
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut request = MmRequest::default();
+        read(&mut request, bytes)?;
+        Ok(request)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+impl MmInfo {
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut info = MmInfo::default();
+        read(&mut info, bytes)?;
+        Ok(info)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+impl MmRead {
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut read_request = MmRead::default();
+        read(&mut read_request, bytes)?;
+        Ok(read_request)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_is_stable() {
+        // Both modules are built separately, possibly from different trees.
+        assert_eq!(core::mem::size_of::<MmRequest>(), 16);
+        assert_eq!(core::mem::size_of::<MmInfo>(), 40);
+        assert_eq!(core::mem::size_of::<MmRead>(), 16);
+        assert_eq!(MM_MESSAGE_SIZE, 32 + 16 * 128);
+
+        let mut event = MmEvent::EMPTY;
+        event.sequence = 7;
+        event.name[..3].copy_from_slice(b"PK\xff");
+        event.name_length = 2;
+        let parsed = MmEvent::parse(event.as_bytes()).unwrap();
+        assert_eq!((parsed.sequence, parsed.name()), (7, "PK"));
+        assert_eq!(
+            MmEvent::parse(&event.as_bytes()[1..]).err(),
+            Some(FormatError::Truncated)
+        );
+    }
+}
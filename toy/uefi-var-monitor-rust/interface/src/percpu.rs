@@ -0,0 +1,71 @@
+// uefi-var-monitor-rust/interface/src/percpu.rs
+//
+// The tag of the serial records written from the per-CPU queues at OS runtime
+// (see src/percpu.rs in the driver). Each record starts with
+//
+//   @<cpu>#<sequence> <text>
+//
+// where cpu is the ID of the CPU that logged it and sequence a number taken
+// from one counter shared by all CPUs. Records of different CPUs reach serial
+// queue by queue, so only the sequence numbers give their order back; a gap
+// in them is a record dropped by a full or busy queue.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuTag {
+    pub cpu: u32,
+    pub sequence: u64,
+}
+
+impl CpuTag {
+    /**
+     * @brief Splits a tagged record into its tag and text. None if the line
+     *        carries no tag.
+     */
+    pub fn parse(line: &str) -> Option<(CpuTag, &str)> {
+        let rest = line.strip_prefix('@')?;
+        let (tag, text) = match rest.find(' ') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, ""),
+        };
+        let separator = tag.find('#')?;
+        let cpu = tag[..separator].parse().ok()?;
+        let sequence = tag[separator + 1..].parse().ok()?;
+        Some((CpuTag { cpu, sequence }, text))
+    }
+}
+
+impl fmt::Display for CpuTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{}#{}", self.cpu, self.sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_round_trip() {
+        let tag = CpuTag {
+            cpu: 300,
+            sequence: 12,
+        };
+        let line = format!("{} G: BootOrder", tag);
+        assert_eq!(line, "@300#12 G: BootOrder");
+        assert_eq!(CpuTag::parse(&line), Some((tag, "G: BootOrder")));
+        assert_eq!(
+            CpuTag::parse("@1#2"),
+            Some((
+                CpuTag {
+                    cpu: 1,
+                    sequence: 2
+                },
+                ""
+            ))
+        );
+        assert_eq!(CpuTag::parse("G: BootOrder"), None);
+        assert_eq!(CpuTag::parse("@x#1 text"), None);
+    }
+}
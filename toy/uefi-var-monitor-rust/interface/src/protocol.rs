@@ -0,0 +1,838 @@
+// uefi-var-monitor-rust/interface/src/protocol.rs
+//
+// The monitor's protocols, both installed on the driver's image handle:
+//
+//   control      pause / resume logging, set the level and the trace
+//                filter, request a ring buffer dump, read the configuration
+//                and save it for the next boots, export and reset the
+//                statistics, snapshot them and diff against a snapshot,
+//                run the self-test and the benchmark, read the write history
+//   statistics   read the hook states and counters, the most accessed
+//                variables, the ring buffer, and what the driver was built
+//                as
+//
+// Both layouts are append-only: entries are only ever added at the end, with
+// a new revision. Callers check the revision before using later entries.
+//
+// get_statistics copies a Statistics, which starts with the common header of
+// format.rs, into a buffer of the caller's: given too small a buffer, it
+// fails with BUFFER_TOO_SMALL and sets the size needed. The structure grows
+// with minor versions, so callers pass the size of the buffer they have and
+// read what they know of what was copied (see Statistics::parse).
+//
+// snapshot_statistics keeps a copy of the statistics in the monitor and
+// returns its ID; diff_statistics copies, the same way, the statistics minus
+// that copy (see Statistics::since), flagged with STATISTICS_FLAG_DIFF. Only
+// the last few snapshots are kept, and a reset drops them all: diffing one
+// no longer kept fails with NOT_FOUND, while an ID never returned fails with
+// INVALID_PARAMETER. reset_statistics is the plain reset.
+//
+// read_history copies the last writes kept of the variables with a write
+// history, oldest first, into an array of the caller's. Given too few
+// entries, it fails with BUFFER_TOO_SMALL and sets the count needed.
+//
+// benchmark times a number of GetVariable calls made through the hook and as
+// many made around it, and returns both times. It fails with
+// INVALID_PARAMETER for no calls or more than MAX_BENCHMARK_CALLS, and with
+// UNSUPPORTED without a cycle counter.
+//
+// get_ring_header fills the fields of a ring header of minor version 1, which
+// callers of any revision have room for, and marks it as such. The build a
+// later header ends with (see ring.rs) is read with get_build, from
+// UVM_STATS_PROTOCOL_REVISION_BUILD on (see build.rs).
+
+use crate::build::BuildInfo;
+use crate::format::FormatHeader;
+use crate::ring::{RingHeader, RingRecord};
+use crate::{read_prefix, FormatError};
+use r_efi::efi;
+use r_efi::{eficall, eficall_abi};
+
+// {3f0c5b7a-9e21-4d8c-b6a4-51e0d2c7f813}
+pub const UVM_PROTOCOL_GUID: efi::Guid = efi::Guid::from_fields(
+    0x3f0c5b7a,
+    0x9e21,
+    0x4d8c,
+    0xb6,
+    0xa4,
+    &[0x51, 0xe0, 0xd2, 0xc7, 0xf8, 0x13],
+);
+
+// The first revision with the control entries.
+pub const UVM_PROTOCOL_REVISION: u32 = 0x00020000;
+// The first revision with save_config.
+pub const UVM_PROTOCOL_REVISION_SAVE: u32 = 0x00020001;
+// The first revision with get_statistics and reset_statistics.
+pub const UVM_PROTOCOL_REVISION_STATISTICS: u32 = 0x00020002;
+// The first revision with snapshot_statistics and diff_statistics.
+pub const UVM_PROTOCOL_REVISION_SNAPSHOT: u32 = 0x00020003;
+// The first revision with self_test.
+pub const UVM_PROTOCOL_REVISION_SELF_TEST: u32 = 0x00020004;
+// The first revision with read_history.
+pub const UVM_PROTOCOL_REVISION_HISTORY: u32 = 0x00020005;
+// The first revision with benchmark, the one installed.
+pub const UVM_PROTOCOL_REVISION_BENCHMARK: u32 = 0x00020006;
+
+// {9d3e6a41-72c5-4b0f-8e19-c4a7f25b60d8}
+pub const UVM_STATS_PROTOCOL_GUID: efi::Guid = efi::Guid::from_fields(
+    0x9d3e6a41,
+    0x72c5,
+    0x4b0f,
+    0x8e,
+    0x19,
+    &[0xc4, 0xa7, 0xf2, 0x5b, 0x60, 0xd8],
+);
+
+// The first revision.
+pub const UVM_STATS_PROTOCOL_REVISION: u32 = 0x00010000;
+// The first revision with get_build, the one installed.
+pub const UVM_STATS_PROTOCOL_REVISION_BUILD: u32 = 0x00010001;
+
+// Reported for a setting or counter whose feature is not built.
+pub const NOT_BUILT: u32 = u32::MAX;
+pub const NOT_COUNTED: u64 = u64::MAX;
+
+// Levels of records, most severe first, as set_level and get_config number
+// them.
+pub const LEVEL_CRITICAL: u8 = 0;
+pub const LEVEL_WARNING: u8 = 1;
+pub const LEVEL_INFO: u8 = 2;
+pub const LEVEL_TRACE: u8 = 3;
+
+// States of a hook, as Stats reports them.
+pub const HOOK_ACTIVE: u8 = 0;
+// Only forwarding calls, after a failed relocation.
+pub const HOOK_PASS_THROUGH: u8 = 1;
+// Failing calls, with nothing converted left to forward to.
+pub const HOOK_UNUSABLE: u8 = 2;
+
+// Bytes of a variable name kept in a TopEntry, as printable ASCII.
+pub const TOP_NAME_SIZE: usize = 64;
+
+// Bytes at the start of the written data kept in a HistoryEntry.
+pub const HISTORY_DATA_SIZE: usize = 16;
+// In HistoryEntry::flags: the write was made at OS runtime.
+pub const HISTORY_FLAG_RUNTIME: u32 = 1 << 0;
+// The data could be read: crc32 and data are set.
+pub const HISTORY_FLAG_INSPECTED: u32 = 1 << 1;
+// Writes went by unrecorded since the entry before this one.
+pub const HISTORY_FLAG_GAP: u32 = 1 << 2;
+
+pub const STATISTICS_MAGIC: [u8; 4] = *b"UVMS";
+pub const STATISTICS_MAJOR: u16 = 1;
+pub const STATISTICS_MINOR: u16 = 2;
+// In the format flags: the counters are those since a snapshot, not since
+// load or the last reset.
+pub const STATISTICS_FLAG_DIFF: u32 = 1 << 0;
+
+// Outcomes CallCounts::by_status counts calls by.
+pub const OUTCOME_SUCCESS: usize = 0;
+pub const OUTCOME_NOT_FOUND: usize = 1;
+pub const OUTCOME_BUFFER_TOO_SMALL: usize = 2;
+pub const OUTCOME_INVALID_PARAMETER: usize = 3;
+pub const OUTCOME_WRITE_PROTECTED: usize = 4;
+pub const OUTCOME_SECURITY_VIOLATION: usize = 5;
+pub const OUTCOME_OUT_OF_RESOURCES: usize = 6;
+pub const OUTCOME_DEVICE_ERROR: usize = 7;
+// Any other error, or a warning.
+pub const OUTCOME_OTHER: usize = 8;
+pub const OUTCOMES: usize = 9;
+
+// Buckets of CallCounts::by_size: bucket 0 counts empty data, bucket n sizes
+// from 2^(n-1) to 2^n - 1 bytes, and the last one everything larger.
+pub const SIZE_BUCKETS: usize = 16;
+
+// Phases CallCounts::by_phase counts calls by.
+pub const PHASE_BOOT_SERVICES: usize = 0;
+pub const PHASE_RUNTIME: usize = 1;
+pub const PHASES: usize = 2;
+
+// Storage of a variable, by the attributes last seen for it: TopEntry::storage
+// and the index of Statistics::by_storage. A variable's storage is unknown
+// until a successful call shows its attributes.
+pub const STORAGE_UNKNOWN: u32 = 0;
+pub const STORAGE_VOLATILE: u32 = 1;
+pub const STORAGE_NON_VOLATILE: u32 = 2;
+pub const STORAGES: usize = 3;
+
+// Checks of the self-test, as bits of SelfTestResult.
+// The GetVariable slot of the runtime services table points at the hook.
+pub const SELF_TEST_GET_VARIABLE_SLOT: u32 = 1 << 0;
+// So does the SetVariable slot.
+pub const SELF_TEST_SET_VARIABLE_SLOT: u32 = 1 << 1;
+// A GetVariable call through the table was seen by the hook.
+pub const SELF_TEST_CALL_OBSERVED: u32 = 1 << 2;
+// A test record reached the serial port, and the ring buffer.
+pub const SELF_TEST_SERIAL: u32 = 1 << 3;
+pub const SELF_TEST_RING: u32 = 1 << 4;
+
+// Most GetVariable calls benchmark makes each way.
+pub const MAX_BENCHMARK_CALLS: u32 = 100_000;
+
+pub type PauseType = eficall! {fn(*mut Protocol) -> efi::Status};
+pub type ResumeType = eficall! {fn(*mut Protocol) -> efi::Status};
+pub type SetLevelType = eficall! {fn(*mut Protocol, u32) -> efi::Status};
+pub type SetFilterType = eficall! {fn(*mut Protocol, *const u8, usize) -> efi::Status};
+pub type DumpType = eficall! {fn(*mut Protocol) -> efi::Status};
+pub type GetConfigType = eficall! {fn(*mut Protocol, *mut ControlConfig) -> efi::Status};
+pub type SaveConfigType = eficall! {fn(*mut Protocol) -> efi::Status};
+pub type GetStatisticsType =
+    eficall! {fn(*mut Protocol, *mut core::ffi::c_void, *mut usize) -> efi::Status};
+pub type ResetStatisticsType = eficall! {fn(*mut Protocol) -> efi::Status};
+pub type SelfTestType = eficall! {fn(*mut Protocol, *mut SelfTestResult) -> efi::Status};
+pub type SnapshotStatisticsType = eficall! {fn(*mut Protocol, *mut u32) -> efi::Status};
+pub type DiffStatisticsType =
+    eficall! {fn(*mut Protocol, u32, *mut core::ffi::c_void, *mut usize) -> efi::Status};
+pub type ReadHistoryType =
+    eficall! {fn(*mut Protocol, *mut HistoryEntry, *mut usize) -> efi::Status};
+pub type BenchmarkType = eficall! {fn(*mut Protocol, u32, *mut BenchmarkResult) -> efi::Status};
+
+#[repr(C)]
+pub struct Protocol {
+    pub revision: u32,
+    pub image_handle: efi::Handle,
+    pub pause: PauseType,
+    pub resume: ResumeType,
+    pub set_level: SetLevelType,
+    pub set_filter: SetFilterType,
+    pub dump: DumpType,
+    pub get_config: GetConfigType,
+    // From UVM_PROTOCOL_REVISION_SAVE on.
+    pub save_config: SaveConfigType,
+    // From UVM_PROTOCOL_REVISION_STATISTICS on.
+    pub get_statistics: GetStatisticsType,
+    pub reset_statistics: ResetStatisticsType,
+    // From UVM_PROTOCOL_REVISION_SNAPSHOT on.
+    pub snapshot_statistics: SnapshotStatisticsType,
+    pub diff_statistics: DiffStatisticsType,
+    // From UVM_PROTOCOL_REVISION_SELF_TEST on.
+    pub self_test: SelfTestType,
+    // From UVM_PROTOCOL_REVISION_HISTORY on.
+    pub read_history: ReadHistoryType,
+    // From UVM_PROTOCOL_REVISION_BENCHMARK on.
+    pub benchmark: BenchmarkType,
+}
+
+// The current configuration, as get_config returns it. Policies are given by
+// the values of their enums.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ControlConfig {
+    pub level: u32,
+    pub paused: u32,
+    pub filter_entries: u32,
+    pub ring_overflow_policy: u32,
+    pub hook_integrity_policy: u32,
+    pub max_reinstalls: u32,
+    pub runtime_data_access: u32,
+    pub tpm_pcr: u32,
+    pub size_factor: u32,
+    pub size_limit: u32,
+    pub rate_limit: u32,
+    pub rate_sustain: u32,
+    pub lock_absent_policy: u32,
+}
+
+pub type GetStatsType = eficall! {fn(*mut StatsProtocol, *mut Stats) -> efi::Status};
+pub type GetTopType = eficall! {fn(*mut StatsProtocol, *mut TopEntry, *mut usize) -> efi::Status};
+pub type GetRingHeaderType = eficall! {fn(*mut StatsProtocol, *mut RingHeader) -> efi::Status};
+pub type GetRingRecordType = eficall! {fn(*mut StatsProtocol, u64, *mut RingRecord) -> efi::Status};
+pub type GetBuildType = eficall! {fn(*mut StatsProtocol, *mut BuildInfo) -> efi::Status};
+
+#[repr(C)]
+pub struct StatsProtocol {
+    pub revision: u32,
+    pub get_stats: GetStatsType,
+    pub get_top: GetTopType,
+    pub get_ring_header: GetRingHeaderType,
+    pub get_ring_record: GetRingRecordType,
+    // From UVM_STATS_PROTOCOL_REVISION_BUILD on.
+    pub get_build: GetBuildType,
+}
+
+// Hook states and counters since load, as get_stats returns them.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub get_variable_hook: u32,
+    pub set_variable_hook: u32,
+    pub get_next_variable_name_hook: u32,
+    // Whether the GetVariable hook was re-installed on top of another one,
+    // and how many times.
+    pub rehooked: u32,
+    pub reinstalls: u32,
+    pub reserved: u32,
+    // Calls with a variable name.
+    pub get_variable_calls: u64,
+    pub set_variable_calls: u64,
+    pub deletion_attempts: u64,
+    pub blocked_writes: u64,
+    pub rejected_writes: u64,
+    pub hidden_accesses: u64,
+    pub suppressed_alerts: u64,
+    // Records lost, by where they were lost.
+    pub serial_failures: u64,
+    pub ring_dropped: u64,
+    pub ring_overwritten: u64,
+    pub tpm_dropped: u64,
+    pub tpm_failures: u64,
+}
+
+// A variable and the number of times it was read and written.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TopEntry {
+    pub guid: efi::Guid,
+    pub reads: u64,
+    pub writes: u64,
+    pub name_length: u32,
+    // STORAGE_*. Reserved, and so unknown, from drivers before statistics
+    // minor version 2.
+    pub storage: u32,
+    pub name: [u8; TOP_NAME_SIZE],
+}
+
+impl TopEntry {
+    pub const EMPTY: TopEntry = TopEntry {
+        guid: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        reads: 0,
+        writes: 0,
+        name_length: 0,
+        storage: STORAGE_UNKNOWN,
+        name: [0; TOP_NAME_SIZE],
+    };
+
+    pub fn name(&self) -> &str {
+        let name = &self.name[..core::cmp::min(self.name_length as usize, TOP_NAME_SIZE)];
+        core::str::from_utf8(name).unwrap_or("?")
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.reads.saturating_add(self.writes)
+    }
+}
+
+// A write to a variable with a write history, accepted or not.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub guid: efi::Guid,
+    // Of the write among those recorded in every history, from 1.
+    pub sequence: u64,
+    // Cycle counter at the write, 0 without one.
+    pub timestamp: u64,
+    // The 4 KiB page of the caller's return address, 0 if unknown.
+    pub caller_page: u64,
+    // What firmware returned.
+    pub status: u64,
+    pub size: u64,
+    pub attributes: u32,
+    // Of the whole data, with HISTORY_FLAG_INSPECTED.
+    pub crc32: u32,
+    pub flags: u32,
+    // Bytes of data kept.
+    pub data_length: u32,
+    pub name_length: u32,
+    pub reserved: u32,
+    pub name: [u8; TOP_NAME_SIZE],
+    pub data: [u8; HISTORY_DATA_SIZE],
+}
+
+impl HistoryEntry {
+    pub const EMPTY: HistoryEntry = HistoryEntry {
+        guid: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        sequence: 0,
+        timestamp: 0,
+        caller_page: 0,
+        status: 0,
+        size: 0,
+        attributes: 0,
+        crc32: 0,
+        flags: 0,
+        data_length: 0,
+        name_length: 0,
+        reserved: 0,
+        name: [0; TOP_NAME_SIZE],
+        data: [0; HISTORY_DATA_SIZE],
+    };
+
+    pub fn name(&self) -> &str {
+        let name = &self.name[..core::cmp::min(self.name_length as usize, TOP_NAME_SIZE)];
+        core::str::from_utf8(name).unwrap_or("?")
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..core::cmp::min(self.data_length as usize, HISTORY_DATA_SIZE)]
+    }
+}
+
+// What self_test found: the SELF_TEST_* checks run, and those passed. A check
+// is not run when its feature is not built, or what it checks is not there.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SelfTestResult {
+    pub checked: u32,
+    pub passed: u32,
+}
+
+impl SelfTestResult {
+    /**
+     * @brief Returns the checks run that failed.
+     */
+    pub fn failed(&self) -> u32 {
+        self.checked & !self.passed
+    }
+}
+
+// What benchmark measured: the cycle counter ticks taken by `calls`
+// GetVariable calls through the runtime services table, hooked, and by as
+// many made straight to the service the hook forwards to, bypassed.
+// ticks_per_second is 0 if the monitor has not calibrated the counter.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BenchmarkResult {
+    pub calls: u32,
+    pub reserved: u32,
+    pub hooked_ticks: u64,
+    pub bypassed_ticks: u64,
+    pub ticks_per_second: u64,
+}
+
+impl BenchmarkResult {
+    /**
+     * @brief Returns the ticks of a call hooked and bypassed, on average.
+     */
+    pub fn per_call(&self) -> (u64, u64) {
+        let calls = u64::from(self.calls);
+        (
+            self.hooked_ticks.checked_div(calls).unwrap_or(0),
+            self.bypassed_ticks.checked_div(calls).unwrap_or(0),
+        )
+    }
+
+    /**
+     * @brief Converts ticks to nanoseconds, None without a tick rate.
+     */
+    pub fn nanoseconds(&self, ticks: u64) -> Option<u64> {
+        match self.ticks_per_second {
+            0 => None,
+            rate => Some((u128::from(ticks) * 1_000_000_000 / u128::from(rate)) as u64),
+        }
+    }
+}
+
+// GetVariable or SetVariable calls with a variable name, counted three ways.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallCounts {
+    // By outcome, indexed by OUTCOME_*.
+    pub by_status: [u64; OUTCOMES],
+    // Successful calls by the size of the data read or written, indexed by
+    // size bucket.
+    pub by_size: [u64; SIZE_BUCKETS],
+    // By phase, indexed by PHASE_*.
+    pub by_phase: [u64; PHASES],
+}
+
+// Successful GetVariable and SetVariable calls to variables of one storage,
+// and the bytes they read and wrote. Deletions write no bytes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageCounts {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/**
+ * @brief Returns the STORAGE_* of a variable with `attributes`, unknown for
+ *        none, as a deletion may give.
+ */
+pub fn storage_of(attributes: u32) -> u32 {
+    match attributes {
+        0 => STORAGE_UNKNOWN,
+        attributes if attributes & efi::VARIABLE_NON_VOLATILE != 0 => STORAGE_NON_VOLATILE,
+        _ => STORAGE_VOLATILE,
+    }
+}
+
+/**
+ * @brief Returns what was counted between `earlier` and `now`: nothing if
+ *        the counter went back, NOT_COUNTED if it is not counted.
+ */
+fn delta(now: u64, earlier: u64) -> u64 {
+    match now {
+        NOT_COUNTED => NOT_COUNTED,
+        now => now.saturating_sub(earlier),
+    }
+}
+
+impl Stats {
+    /**
+     * @brief Returns these counters minus `earlier`'s, with the hook states
+     *        as they are now.
+     */
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        Stats {
+            get_variable_calls: delta(self.get_variable_calls, earlier.get_variable_calls),
+            set_variable_calls: delta(self.set_variable_calls, earlier.set_variable_calls),
+            deletion_attempts: delta(self.deletion_attempts, earlier.deletion_attempts),
+            blocked_writes: delta(self.blocked_writes, earlier.blocked_writes),
+            rejected_writes: delta(self.rejected_writes, earlier.rejected_writes),
+            hidden_accesses: delta(self.hidden_accesses, earlier.hidden_accesses),
+            suppressed_alerts: delta(self.suppressed_alerts, earlier.suppressed_alerts),
+            serial_failures: delta(self.serial_failures, earlier.serial_failures),
+            ring_dropped: delta(self.ring_dropped, earlier.ring_dropped),
+            ring_overwritten: delta(self.ring_overwritten, earlier.ring_overwritten),
+            tpm_dropped: delta(self.tpm_dropped, earlier.tpm_dropped),
+            tpm_failures: delta(self.tpm_failures, earlier.tpm_failures),
+            ..*self
+        }
+    }
+}
+
+impl CallCounts {
+    /**
+     * @brief Counts a call that returned `efi_status` in the phase `phase`,
+     *        a PHASE_* value, with the size of the data read or written. The
+     *        driver counts the same way, into atomics (see src/counters.rs).
+     */
+    pub fn count(&mut self, efi_status: efi::Status, size: Option<usize>, phase: usize) {
+        self.by_status[outcome(efi_status)] += 1;
+        if let (efi::Status::SUCCESS, Some(size)) = (efi_status, size) {
+            self.by_size[size_bucket(size)] += 1;
+        }
+        if let Some(count) = self.by_phase.get_mut(phase) {
+            *count += 1;
+        }
+    }
+
+    pub fn since(&self, earlier: &CallCounts) -> CallCounts {
+        fn since<const N: usize>(now: &[u64; N], earlier: &[u64; N]) -> [u64; N] {
+            let mut counts = [0; N];
+            for (count, (now, earlier)) in counts.iter_mut().zip(now.iter().zip(earlier)) {
+                *count = delta(*now, *earlier);
+            }
+            counts
+        }
+        CallCounts {
+            by_status: since(&self.by_status, &earlier.by_status),
+            by_size: since(&self.by_size, &earlier.by_size),
+            by_phase: since(&self.by_phase, &earlier.by_phase),
+        }
+    }
+}
+
+impl StorageCounts {
+    pub fn since(&self, earlier: &StorageCounts) -> StorageCounts {
+        StorageCounts {
+            reads: delta(self.reads, earlier.reads),
+            writes: delta(self.writes, earlier.writes),
+            bytes_read: delta(self.bytes_read, earlier.bytes_read),
+            bytes_written: delta(self.bytes_written, earlier.bytes_written),
+        }
+    }
+}
+
+// Successful writes to non-volatile variables since load, each of which
+// costs the flash part at least a program and eventually an erase. Unlike
+// the other counters, these are not zeroed by reset_statistics.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlashWrites {
+    pub writes: u64,
+    pub bytes: u64,
+}
+
+impl FlashWrites {
+    pub fn since(&self, earlier: &FlashWrites) -> FlashWrites {
+        FlashWrites {
+            writes: delta(self.writes, earlier.writes),
+            bytes: delta(self.bytes, earlier.bytes),
+        }
+    }
+}
+
+// What the GetVariable hook added to the calls it wrapped, in cycle counter
+// ticks: the time spent in the hook minus that in the service it forwarded
+// to. Every field reads NOT_COUNTED in builds that do not measure it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overhead {
+    pub calls: u64,
+    pub total_ticks: u64,
+    // The worst call since load or the last reset, also in a diff.
+    pub max_ticks: u64,
+    // Of the cycle counter, 0 if it was not calibrated.
+    pub ticks_per_second: u64,
+}
+
+impl Overhead {
+    pub const NOT_COUNTED: Overhead = Overhead {
+        calls: NOT_COUNTED,
+        total_ticks: NOT_COUNTED,
+        max_ticks: NOT_COUNTED,
+        ticks_per_second: NOT_COUNTED,
+    };
+
+    /**
+     * @brief Returns the calls and ticks since `earlier`, with the worst
+     *        call and the tick rate as they are now.
+     */
+    pub fn since(&self, earlier: &Overhead) -> Overhead {
+        Overhead {
+            calls: delta(self.calls, earlier.calls),
+            total_ticks: delta(self.total_ticks, earlier.total_ticks),
+            ..*self
+        }
+    }
+
+    /**
+     * @brief Converts ticks to microseconds, None without a tick rate.
+     */
+    pub fn microseconds(&self, ticks: u64) -> Option<u64> {
+        match self.ticks_per_second {
+            0 | NOT_COUNTED => None,
+            rate => Some((u128::from(ticks) * 1_000_000 / u128::from(rate)) as u64),
+        }
+    }
+}
+
+// Everything counted since load or the last reset, as get_statistics
+// returns it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub format: FormatHeader,
+    // The hook states, call counters and records lost, as get_stats returns
+    // them.
+    pub stats: Stats,
+    pub get_variable: CallCounts,
+    pub set_variable: CallCounts,
+    // Records skipped while logging was paused.
+    pub paused_skipped: u64,
+    // Since minor version 1.
+    pub overhead: Overhead,
+    // Since minor version 2, indexed by STORAGE_*.
+    pub by_storage: [StorageCounts; STORAGES],
+    pub flash_writes: FlashWrites,
+}
+
+impl Statistics {
+    pub const FORMAT: FormatHeader = FormatHeader::new(
+        STATISTICS_MAGIC,
+        STATISTICS_MAJOR,
+        STATISTICS_MINOR,
+        core::mem::size_of::<Statistics>(),
+        0,
+    );
+
+    /**
+     * @brief Reads statistics as copied by get_statistics, of any minor
+     *        version. What an older one lacks reads as zero.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let format = FormatHeader::parse(bytes, STATISTICS_MAGIC, STATISTICS_MAJOR)?;
+        let size = format.header_size(core::mem::size_of::<FormatHeader>(), bytes.len())?;
+        let mut statistics = Statistics::default();
+        read_prefix(&mut statistics, &bytes[..size]);
+        Ok(statistics)
+    }
+
+    /**
+     * @brief Returns what was counted between the `earlier` statistics and
+     *        these, flagged with STATISTICS_FLAG_DIFF.
+     */
+    pub fn since(&self, earlier: &Statistics) -> Statistics {
+        let mut format = self.format;
+        format.flags |= STATISTICS_FLAG_DIFF;
+        let mut by_storage = self.by_storage;
+        for (counts, earlier) in by_storage.iter_mut().zip(earlier.by_storage.iter()) {
+            *counts = counts.since(earlier);
+        }
+        Statistics {
+            format,
+            stats: self.stats.since(&earlier.stats),
+            get_variable: self.get_variable.since(&earlier.get_variable),
+            set_variable: self.set_variable.since(&earlier.set_variable),
+            paused_skipped: delta(self.paused_skipped, earlier.paused_skipped),
+            overhead: self.overhead.since(&earlier.overhead),
+            by_storage,
+            flash_writes: self.flash_writes.since(&earlier.flash_writes),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+/**
+ * @brief Returns the OUTCOME_* index counting `efi_status`.
+ */
+pub fn outcome(efi_status: efi::Status) -> usize {
+    match efi_status {
+        efi::Status::SUCCESS => OUTCOME_SUCCESS,
+        efi::Status::NOT_FOUND => OUTCOME_NOT_FOUND,
+        efi::Status::BUFFER_TOO_SMALL => OUTCOME_BUFFER_TOO_SMALL,
+        efi::Status::INVALID_PARAMETER => OUTCOME_INVALID_PARAMETER,
+        efi::Status::WRITE_PROTECTED => OUTCOME_WRITE_PROTECTED,
+        efi::Status::SECURITY_VIOLATION => OUTCOME_SECURITY_VIOLATION,
+        efi::Status::OUT_OF_RESOURCES => OUTCOME_OUT_OF_RESOURCES,
+        efi::Status::DEVICE_ERROR => OUTCOME_DEVICE_ERROR,
+        _ => OUTCOME_OTHER,
+    }
+}
+
+/**
+ * @brief Returns the bucket of CallCounts::by_size counting `size`.
+ */
+pub fn size_bucket(size: usize) -> usize {
+    let bits = (usize::BITS - size.leading_zeros()) as usize;
+    core::cmp::min(bits, SIZE_BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_are_read_for_what_is_known() {
+        assert_eq!(core::mem::size_of::<CallCounts>(), 216);
+        assert_eq!(
+            (
+                size_bucket(0),
+                size_bucket(1),
+                size_bucket(4),
+                size_bucket(5)
+            ),
+            (0, 1, 3, 3)
+        );
+        assert_eq!(size_bucket(usize::MAX), SIZE_BUCKETS - 1);
+        assert_eq!(
+            (storage_of(0), storage_of(0x6), storage_of(0x7)),
+            (STORAGE_UNKNOWN, STORAGE_VOLATILE, STORAGE_NON_VOLATILE)
+        );
+
+        let mut statistics = Statistics {
+            format: Statistics::FORMAT,
+            paused_skipped: 3,
+            ..Statistics::default()
+        };
+        statistics.set_variable.by_status[OUTCOME_WRITE_PROTECTED] = 2;
+        statistics.by_storage[STORAGE_NON_VOLATILE as usize].writes = 4;
+        statistics.flash_writes.bytes = 40;
+        assert_eq!(Statistics::parse(statistics.as_bytes()), Ok(statistics));
+
+        // An older minor version, without the last fields.
+        let mut older = statistics;
+        older.format.size -= (core::mem::size_of::<[StorageCounts; STORAGES]>()
+            + core::mem::size_of::<FlashWrites>()) as u32;
+        let parsed = Statistics::parse(&older.as_bytes()[..older.format.size as usize]).unwrap();
+        assert_eq!(parsed.overhead, statistics.overhead);
+        assert_eq!(parsed.by_storage, [StorageCounts::default(); STORAGES]);
+        assert_eq!(parsed.flash_writes, FlashWrites::default());
+        older.overhead.calls = 5;
+        older.format.size -= core::mem::size_of::<Overhead>() as u32;
+        let parsed = Statistics::parse(&older.as_bytes()[..older.format.size as usize]).unwrap();
+        assert_eq!(parsed.paused_skipped, 3);
+        assert_eq!(parsed.overhead, Overhead::default());
+        older.format.size -= 8;
+        let parsed = Statistics::parse(&older.as_bytes()[..older.format.size as usize]).unwrap();
+        assert_eq!(parsed.set_variable, statistics.set_variable);
+        assert_eq!(parsed.paused_skipped, 0);
+
+        older.format.major += 1;
+        assert!(matches!(
+            Statistics::parse(older.as_bytes()),
+            Err(FormatError::Major { .. })
+        ));
+    }
+
+    #[test]
+    fn benchmarks_are_averaged_per_call() {
+        let result = BenchmarkResult {
+            calls: 1000,
+            hooked_ticks: 3_500_000,
+            bypassed_ticks: 1_000_999,
+            ticks_per_second: 2_000_000_000,
+            ..BenchmarkResult::default()
+        };
+        assert_eq!(result.per_call(), (3500, 1000));
+        assert_eq!(result.nanoseconds(2500), Some(1250));
+        let uncalibrated = BenchmarkResult {
+            ticks_per_second: 0,
+            ..result
+        };
+        assert_eq!(uncalibrated.nanoseconds(2500), None);
+        assert_eq!(BenchmarkResult::default().per_call(), (0, 0));
+    }
+
+    #[test]
+    fn diffs_count_what_happened_in_between() {
+        let mut earlier = Statistics {
+            format: Statistics::FORMAT,
+            ..Statistics::default()
+        };
+        earlier.stats.get_variable_calls = 10;
+        earlier.stats.blocked_writes = NOT_COUNTED;
+        earlier.get_variable.by_status[OUTCOME_NOT_FOUND] = 4;
+        let mut now = earlier;
+        now.stats.get_variable_hook = u32::from(HOOK_PASS_THROUGH);
+        now.stats.get_variable_calls = 25;
+        now.stats.serial_failures = 1;
+        now.get_variable.by_status[OUTCOME_NOT_FOUND] = 6;
+        now.paused_skipped = 2;
+        earlier.overhead = Overhead {
+            calls: 4,
+            total_ticks: 400,
+            max_ticks: 150,
+            ticks_per_second: 1_000_000,
+        };
+        earlier.by_storage[STORAGE_VOLATILE as usize].bytes_read = 4;
+        earlier.flash_writes = FlashWrites {
+            writes: 2,
+            bytes: 24,
+        };
+        now.by_storage[STORAGE_VOLATILE as usize].bytes_read = 16;
+        now.flash_writes = FlashWrites {
+            writes: 3,
+            bytes: 32,
+        };
+        now.overhead = Overhead {
+            calls: 6,
+            total_ticks: 700,
+            ..earlier.overhead
+        };
+
+        let diff = now.since(&earlier);
+        assert_eq!(diff.format.flags, STATISTICS_FLAG_DIFF);
+        assert_eq!(diff.stats.get_variable_hook, u32::from(HOOK_PASS_THROUGH));
+        assert_eq!(diff.stats.get_variable_calls, 15);
+        assert_eq!(diff.stats.serial_failures, 1);
+        assert_eq!(diff.stats.blocked_writes, NOT_COUNTED);
+        assert_eq!(diff.get_variable.by_status[OUTCOME_NOT_FOUND], 2);
+        assert_eq!(diff.paused_skipped, 2);
+        assert_eq!(
+            (
+                diff.overhead.calls,
+                diff.overhead.total_ticks,
+                diff.overhead.max_ticks
+            ),
+            (2, 300, 150)
+        );
+        assert_eq!(diff.overhead.microseconds(300), Some(300));
+        assert_eq!(Overhead::NOT_COUNTED.microseconds(300), None);
+        assert_eq!(diff.by_storage[STORAGE_VOLATILE as usize].bytes_read, 12);
+        assert_eq!(
+            diff.flash_writes,
+            FlashWrites {
+                writes: 1,
+                bytes: 8
+            }
+        );
+        // A counter that went back counts nothing.
+        assert_eq!(earlier.since(&now).stats.get_variable_calls, 0);
+    }
+}
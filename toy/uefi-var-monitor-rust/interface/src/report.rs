@@ -0,0 +1,313 @@
+// uefi-var-monitor-rust/interface/src/report.rs
+//
+// Layout of the boot-report variable, "UvmBootReport" under the monitor's
+// vendor GUID, which the driver writes at ReadyToBoot (see the driver's
+// src/report.rs). The OS reads it back, as does the driver on the next boot.
+//
+// The report starts with the common header of format.rs, without records:
+// its size is that of the whole report. Fields are only ever appended, with
+// the minor version bumped; the size lets a reader skip what it does not
+// know, and what a report is too old to have reads as zero. Reports written
+// before the common header, versions 1 to 4 of their own numbering, start
+// with no magic and are refused.
+//
+// Minor version 1 appends the newest writes of the write histories. A
+// firmware refusing a report that large gets the fields of minor version 0
+// alone, marked as such.
+//
+// Minor version 2 appends the space in the variable store, as
+// QueryVariableInfo gave it at load and at ExitBootServices. The report is
+// written again at ExitBootServices for the second.
+//
+// Minor version 3 appends the variables that were not in the store at load,
+// counted by the stage of the boot they were first seen in, with the names
+// of the first MAX_REPORTED_NEW.
+//
+// Minor version 4 appends the build of the driver that wrote the report (see
+// build.rs).
+
+use crate::build::BuildInfo;
+use crate::format::FormatHeader;
+use crate::protocol::{HistoryEntry, TOP_NAME_SIZE};
+use crate::{read_prefix, FormatError};
+use r_efi::efi;
+
+pub const REPORT_MAGIC: [u8; 4] = *b"UVMB";
+pub const REPORT_MAJOR: u16 = 1;
+pub const REPORT_MINOR: u16 = 4;
+// Learned sizes kept, out of the variables the driver tracks.
+pub const MAX_LEARNED_SIZES: usize = 32;
+// Suppressed counts kept, room for the driver's rules to grow.
+pub const MAX_REPORTED_RULES: usize = 32;
+// Write history entries kept, the newest of every history.
+pub const MAX_REPORTED_HISTORY: usize = 8;
+// New variables named, the first seen.
+pub const MAX_REPORTED_NEW: usize = 16;
+// Stages of the boot a new variable can first be seen in, indexing
+// BootReport::new_counts.
+pub const NEW_BEFORE_END_OF_DXE: u32 = 0;
+pub const NEW_BEFORE_EXIT_BOOT_SERVICES: u32 = 1;
+pub const NEW_AT_RUNTIME: u32 = 2;
+pub const NEW_STAGES: usize = 3;
+// The fields of minor version 0.
+pub const REPORT_MINOR_0_SIZE: usize = core::mem::offset_of!(BootReport, history_count);
+
+// The space in the variable store for the variables of `attributes`, as
+// QueryVariableInfo gave it. Attributes of zero mean it was not measured.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreSpace {
+    pub attributes: u32,
+    pub reserved: u32,
+    pub maximum: u64,
+    pub remaining: u64,
+    pub max_variable_size: u64,
+}
+
+impl StoreSpace {
+    pub const EMPTY: StoreSpace = StoreSpace {
+        attributes: 0,
+        reserved: 0,
+        maximum: 0,
+        remaining: 0,
+        max_variable_size: 0,
+    };
+
+    pub fn is_measured(&self) -> bool {
+        self.attributes != 0
+    }
+}
+
+// A variable that was not in the store at load, and the stage of the boot it
+// was first seen in, a NEW_* value.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NewVariable {
+    pub guid: efi::Guid,
+    pub stage: u32,
+    pub name_length: u32,
+    pub name: [u8; TOP_NAME_SIZE],
+}
+
+impl NewVariable {
+    pub const EMPTY: NewVariable = NewVariable {
+        guid: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        stage: 0,
+        name_length: 0,
+        name: [0; TOP_NAME_SIZE],
+    };
+
+    pub fn name(&self) -> &str {
+        let name = &self.name[..core::cmp::min(self.name_length as usize, TOP_NAME_SIZE)];
+        core::str::from_utf8(name).unwrap_or("?")
+    }
+}
+
+// A variable size learned by the driver, and how many reads confirmed it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LearnedSize {
+    pub guid: efi::Guid,
+    pub name_crc32: u32,
+    pub size: u32,
+    pub confirmations: u32,
+    pub reserved: u32,
+}
+
+impl LearnedSize {
+    pub const EMPTY: LearnedSize = LearnedSize {
+        guid: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        name_crc32: 0,
+        size: 0,
+        confirmations: 0,
+        reserved: 0,
+    };
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootReport {
+    pub format: FormatHeader,
+    // Sequence number the next log record will get.
+    pub next_sequence: u64,
+    // Link of the newest log record, zero without the ring buffer.
+    pub chain_head: [u8; 8],
+    // Writes to boot-critical variables firmware accepted at OS runtime, in
+    // this boot and in the one before it.
+    pub runtime_boot_critical_writes: u64,
+    pub previous_runtime_boot_critical_writes: u64,
+    pub learned_size_count: u32,
+    pub reserved: u32,
+    pub learned_sizes: [LearnedSize; MAX_LEARNED_SIZES],
+    // Alerts suppressed, indexed by rule ID.
+    pub suppressed_alerts: [u32; MAX_REPORTED_RULES],
+    // Since minor version 1: writes to the variables with a write history,
+    // oldest first.
+    pub history_count: u32,
+    pub reserved_history: u32,
+    pub history: [HistoryEntry; MAX_REPORTED_HISTORY],
+    // Since minor version 2: the variable store at load and at
+    // ExitBootServices.
+    pub store_at_load: StoreSpace,
+    pub store_at_exit: StoreSpace,
+    // Since minor version 3: the new variables, by stage, and the first ones.
+    pub new_counts: [u32; NEW_STAGES],
+    pub new_variable_count: u32,
+    pub new_variables: [NewVariable; MAX_REPORTED_NEW],
+    // Since minor version 4: the driver that wrote the report.
+    pub build: BuildInfo,
+}
+
+impl BootReport {
+    pub const FORMAT: FormatHeader = FormatHeader::new(
+        REPORT_MAGIC,
+        REPORT_MAJOR,
+        REPORT_MINOR,
+        core::mem::size_of::<BootReport>(),
+        0,
+    );
+
+    pub const EMPTY: BootReport = BootReport {
+        format: FormatHeader::new(*b"\0\0\0\0", 0, 0, 0, 0),
+        next_sequence: 0,
+        chain_head: [0; 8],
+        runtime_boot_critical_writes: 0,
+        previous_runtime_boot_critical_writes: 0,
+        learned_size_count: 0,
+        reserved: 0,
+        learned_sizes: [LearnedSize::EMPTY; MAX_LEARNED_SIZES],
+        suppressed_alerts: [0; MAX_REPORTED_RULES],
+        history_count: 0,
+        reserved_history: 0,
+        history: [HistoryEntry::EMPTY; MAX_REPORTED_HISTORY],
+        store_at_load: StoreSpace::EMPTY,
+        store_at_exit: StoreSpace::EMPTY,
+        new_counts: [0; NEW_STAGES],
+        new_variable_count: 0,
+        new_variables: [NewVariable::EMPTY; MAX_REPORTED_NEW],
+        build: BuildInfo::EMPTY,
+    };
+
+    /**
+     * @brief Reads a report from the variable data, of any minor version.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let format = FormatHeader::parse(bytes, REPORT_MAGIC, REPORT_MAJOR)?;
+        let size = format.header_size(REPORT_MINOR_0_SIZE, bytes.len())?;
+        let mut report = BootReport::EMPTY;
+        read_prefix(&mut report, &bytes[..size]);
+        Ok(report)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+
+    pub fn learned_sizes(&self) -> &[LearnedSize] {
+        let count = core::cmp::min(self.learned_size_count as usize, MAX_LEARNED_SIZES);
+        &self.learned_sizes[..count]
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        let count = core::cmp::min(self.history_count as usize, MAX_REPORTED_HISTORY);
+        &self.history[..count]
+    }
+
+    pub fn new_variables(&self) -> &[NewVariable] {
+        let count = core::cmp::min(self.new_variable_count as usize, MAX_REPORTED_NEW);
+        &self.new_variables[..count]
+    }
+
+    /**
+     * @brief Cuts the report down to the fields of minor version 0.
+     */
+    pub fn truncate_to_minor_0(&mut self) {
+        self.format.minor = 0;
+        self.format.size = REPORT_MINOR_0_SIZE as u32;
+        self.history_count = 0;
+        self.history = [HistoryEntry::EMPTY; MAX_REPORTED_HISTORY];
+        self.store_at_load = StoreSpace::EMPTY;
+        self.store_at_exit = StoreSpace::EMPTY;
+        self.new_counts = [0; NEW_STAGES];
+        self.new_variable_count = 0;
+        self.new_variables = [NewVariable::EMPTY; MAX_REPORTED_NEW];
+        self.build = BuildInfo::EMPTY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_minors_are_read_for_what_is_known() {
+        let mut report = BootReport {
+            format: BootReport::FORMAT,
+            ..BootReport::EMPTY
+        };
+        report.next_sequence = 42;
+        report.runtime_boot_critical_writes = 3;
+        report.learned_size_count = 1;
+        report.suppressed_alerts[1] = 7;
+        assert_eq!(BootReport::parse(report.as_bytes()), Ok(report));
+
+        // A later minor version appends fields.
+        let mut newer = report;
+        newer.format.minor = REPORT_MINOR + 1;
+        newer.format.size += 8;
+        let mut bytes = newer.as_bytes().to_vec();
+        bytes.extend_from_slice(&[0xff; 8]);
+        assert_eq!(BootReport::parse(&bytes), Ok(newer));
+        assert_eq!(
+            BootReport::parse(&bytes[..bytes.len() - 1]),
+            Err(FormatError::Truncated)
+        );
+
+        newer.format.major = REPORT_MAJOR + 1;
+        assert_eq!(
+            BootReport::parse(newer.as_bytes()),
+            Err(FormatError::Major {
+                found: REPORT_MAJOR + 1,
+                supported: REPORT_MAJOR
+            })
+        );
+        // A report of minor version 0 has no history.
+        let mut older = report;
+        older.history_count = 1;
+        older.truncate_to_minor_0();
+        assert_eq!(
+            BootReport::parse(&report.as_bytes()[..REPORT_MINOR_0_SIZE]),
+            Err(FormatError::Truncated)
+        );
+        let parsed = BootReport::parse(&older.as_bytes()[..REPORT_MINOR_0_SIZE]).unwrap();
+        assert_eq!((parsed.format.minor, parsed.history()), (0, &[][..]));
+        assert_eq!(parsed.suppressed_alerts[1], 7);
+
+        // A report of minor version 1 has no store space nor new variables.
+        let mut minor_1 = report;
+        minor_1.store_at_load.attributes = 7;
+        minor_1.new_variable_count = 1;
+        let minor_1_size = core::mem::offset_of!(BootReport, store_at_load);
+        minor_1.format.minor = 1;
+        minor_1.format.size = minor_1_size as u32;
+        let parsed = BootReport::parse(&minor_1.as_bytes()[..minor_1_size]).unwrap();
+        assert!(!parsed.store_at_load.is_measured());
+        assert!(parsed.new_variables().is_empty());
+        assert_eq!(parsed.next_sequence, 42);
+
+        // Nor does one of minor version 3 say which build wrote it.
+        let mut minor_3 = report;
+        minor_3.build.version = BuildInfo::text("0.1.1");
+        let minor_3_size = core::mem::offset_of!(BootReport, build);
+        minor_3.format.minor = 3;
+        minor_3.format.size = minor_3_size as u32;
+        let parsed = BootReport::parse(&minor_3.as_bytes()[..minor_3_size]).unwrap();
+        assert!(!parsed.build.is_recorded());
+
+        // A report of before the common header, version 4 of 1200 bytes.
+        let mut old = std::vec![0u8; 1200];
+        old[0] = 4;
+        old[4..8].copy_from_slice(&1200u32.to_le_bytes());
+        assert_eq!(BootReport::parse(&old), Err(FormatError::Signature));
+    }
+}
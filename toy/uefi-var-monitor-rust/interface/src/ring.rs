@@ -0,0 +1,414 @@
+// uefi-var-monitor-rust/interface/src/ring.rs
+//
+// Layout of the driver's ring buffer of log records: a header followed by
+// RingHeader::capacity records of RING_RECORD_SIZE bytes each. The statistics
+// protocol hands out copies of both (see protocol.rs), and tools dumping the
+// buffer write them out in this layout: a dump is the header followed by the
+// records held, oldest first (see tools/uvmlog). How records are stored and
+// chained is described in the driver's src/ring.rs; the links are computed
+// here, for the driver building the chain and the tools verifying it alike.
+//
+// The header starts with the common one of format.rs, whose sizes give where
+// the records start and how far apart they are, and whose flags are the
+// RING_FLAG_* bits. Readers take the prefix they know of larger headers and
+// records.
+//
+// A record holds either text, or since 1.1 a GetVariable or SetVariable
+// access captured raw by a driver built with log-deferred, as given by its
+// kind. Raw records are turned into the text the driver would have logged by
+// RecordText, wherever they are read.
+//
+// Minor version 2 appends to the header the build of the driver that wrote
+// the buffer (see build.rs). Headers of older minors read with an empty one.
+
+use crate::build::BuildInfo;
+use crate::format::FormatHeader;
+use crate::hex::{Case, Line};
+use crate::sha256::Sha256;
+use crate::{read, read_prefix, FormatError};
+use core::fmt;
+use r_efi::efi::Guid;
+
+pub const RING_MAGIC: [u8; 4] = *b"UVML";
+pub const RING_MAJOR: u16 = 1;
+pub const RING_MINOR: u16 = 2;
+pub const RING_RECORD_SIZE: usize = 128;
+pub const RING_CHAIN_SIZE: usize = 8;
+pub const RING_DATA_SIZE: usize = RING_RECORD_SIZE - 16 - RING_CHAIN_SIZE;
+// The header of minor versions 0 and 1.
+pub const RING_MINOR_1_SIZE: usize = core::mem::offset_of!(RingHeader, build);
+
+// RingHeader::format.flags
+pub const RING_FLAG_PANICKED: u32 = 1 << 0;
+
+// RingRecord::kind
+pub const RECORD_TEXT: u8 = 0;
+pub const RECORD_GET_VARIABLE: u8 = 1;
+pub const RECORD_SET_VARIABLE: u8 = 2;
+
+// Characters of the variable name an AccessRecord keeps.
+pub const ACCESS_NAME_LENGTH: usize = 32;
+// AccessRecord::argument and size of a size that is not known.
+pub const ACCESS_NO_SIZE: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RingHeader {
+    pub format: FormatHeader,
+    pub capacity: u32,
+    pub policy: u32,
+    // Sequence number of the oldest record held.
+    pub first_sequence: u64,
+    // Sequence number the next stored record will get.
+    pub next_sequence: u64,
+    pub dropped: u64,
+    pub overwritten: u64,
+    // Link of the record before first_sequence, zero before any overwrite.
+    pub chain_anchor: [u8; RING_CHAIN_SIZE],
+    // Link of the newest record.
+    pub chain_head: [u8; RING_CHAIN_SIZE],
+    // Since minor version 2: the driver that wrote the buffer.
+    pub build: BuildInfo,
+}
+
+impl RingHeader {
+    pub const FORMAT: FormatHeader = FormatHeader::new(
+        RING_MAGIC,
+        RING_MAJOR,
+        RING_MINOR,
+        core::mem::size_of::<RingHeader>(),
+        RING_RECORD_SIZE,
+    );
+
+    /**
+     * @brief Reads a header from the start of a dump, of any minor version.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let format = FormatHeader::parse(bytes, RING_MAGIC, RING_MAJOR)?;
+        let size = format.header_size(RING_MINOR_1_SIZE, bytes.len())?;
+        if (format.record_size as usize) < RING_RECORD_SIZE {
+            return Err(FormatError::RecordSize);
+        }
+        let mut header = RingHeader::default();
+        read_prefix(&mut header, &bytes[..size]);
+        Ok(header)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RingRecord {
+    pub sequence: u64,
+    pub length: u16,
+    // RECORD_*; text in records of 1.0.
+    pub kind: u8,
+    pub reserved: [u8; 5],
+    pub chain: [u8; RING_CHAIN_SIZE],
+    pub data: [u8; RING_DATA_SIZE],
+}
+
+const _: () = assert!(core::mem::size_of::<RingRecord>() == RING_RECORD_SIZE);
+
+impl RingRecord {
+    pub const EMPTY: RingRecord = RingRecord {
+        sequence: 0,
+        length: 0,
+        kind: RECORD_TEXT,
+        reserved: [0; 5],
+        chain: [0; RING_CHAIN_SIZE],
+        data: [0; RING_DATA_SIZE],
+    };
+
+    /**
+     * @brief Reads a record from the start of `bytes`.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut record = RingRecord::EMPTY;
+        read(&mut record, bytes)?;
+        Ok(record)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..core::cmp::min(self.length as usize, RING_DATA_SIZE)]
+    }
+
+    /**
+     * @brief Returns a raw record holding `access`, to be numbered and
+     *        chained when stored.
+     */
+    pub fn raw(kind: u8, access: &AccessRecord) -> Self {
+        let mut record = RingRecord::EMPTY;
+        record.kind = kind;
+        let bytes = access.stored_bytes();
+        record.length = bytes.len() as u16;
+        record.data[..bytes.len()].copy_from_slice(bytes);
+        record
+    }
+
+    /**
+     * @brief Returns the access a raw record holds, None for text.
+     */
+    pub fn access(&self) -> Option<AccessRecord> {
+        if self.kind == RECORD_TEXT {
+            return None;
+        }
+        let mut access = AccessRecord::EMPTY;
+        read_prefix(&mut access, self.data());
+        Some(access)
+    }
+}
+
+// The data of a raw record. Only the name as far as its terminator is
+// stored, so that there is less to chain; the rest reads as zero.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AccessRecord {
+    pub guid: Guid,
+    pub status: u64,
+    // The cycle counter when the call returned, 0 without one.
+    pub cycles: u64,
+    // GetVariable: the data size passed in; SetVariable: the attributes.
+    pub argument: u32,
+    // GetVariable: the data size returned; SetVariable: the data size.
+    pub size: u32,
+    // UCS-2, cut at ACCESS_NAME_LENGTH characters, NUL-padded.
+    pub name: [u16; ACCESS_NAME_LENGTH],
+}
+
+const _: () = assert!(core::mem::size_of::<AccessRecord>() == RING_DATA_SIZE);
+
+impl AccessRecord {
+    pub const EMPTY: AccessRecord = AccessRecord {
+        guid: Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        status: 0,
+        cycles: 0,
+        argument: ACCESS_NO_SIZE,
+        size: ACCESS_NO_SIZE,
+        name: [0; ACCESS_NAME_LENGTH],
+    };
+
+    /**
+     * @brief Returns the bytes stored: all but the NUL padding of the name.
+     */
+    pub fn stored_bytes(&self) -> &[u8] {
+        let length = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(ACCESS_NAME_LENGTH);
+        &self.as_bytes()[..RING_DATA_SIZE - 2 * (ACCESS_NAME_LENGTH - length)]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        crate::as_bytes(self)
+    }
+}
+
+/**
+ * @brief Returns a size as AccessRecord keeps it.
+ */
+pub fn access_size(size: Option<usize>) -> u32 {
+    match size {
+        Some(size) => core::cmp::min(size, ACCESS_NO_SIZE as usize - 1) as u32,
+        None => ACCESS_NO_SIZE,
+    }
+}
+
+// Longest text of a raw record, 190 bytes: an S: record with a name of
+// ACCESS_NAME_LENGTH characters of 3 bytes each and a 64-bit status.
+const ACCESS_TEXT_SIZE: usize = 192;
+
+// A record as the text the driver logs, whatever its kind. Text cut at
+// RING_DATA_SIZE may end mid-character, and ends at the last whole one. Raw
+// records are written out with hex.rs rather than core::fmt.
+pub struct RecordText<'a>(pub &'a RingRecord);
+
+impl fmt::Display for RecordText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self.0.access() {
+            Some(access) => access,
+            None => {
+                let data = self.0.data();
+                let text = match core::str::from_utf8(data) {
+                    Ok(text) => text,
+                    Err(error) => {
+                        core::str::from_utf8(data.get(..error.valid_up_to()).unwrap_or(&[]))
+                            .unwrap_or("")
+                    }
+                };
+                return f.write_str(text);
+            }
+        };
+        let mut line = Line::<ACCESS_TEXT_SIZE>::new();
+        match self.0.kind {
+            // "G: {} Size={}->{} {}: {:#x}"
+            RECORD_GET_VARIABLE => {
+                line.push_str("G: ")
+                    .push_guid(&access.guid)
+                    .push_str(" Size=");
+                push_size(&mut line, access.argument);
+                line.push_str("->");
+                push_size(&mut line, access.size);
+            }
+            // "S: {} Attributes={:08x} Size={} {}: {:#x}"
+            RECORD_SET_VARIABLE => {
+                line.push_str("S: ")
+                    .push_guid(&access.guid)
+                    .push_str(" Attributes=")
+                    .push_hex(u64::from(access.argument), 8, Case::Lower)
+                    .push_str(" Size=");
+                push_size(&mut line, access.size);
+            }
+            kind => return write!(f, "record of unknown kind {}", kind),
+        }
+        line.push_str(" ");
+        let length = access
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(ACCESS_NAME_LENGTH);
+        for c in core::char::decode_utf16(access.name[..length].iter().copied()) {
+            line.push_char(c.unwrap_or(core::char::REPLACEMENT_CHARACTER));
+        }
+        line.push_str(": ").push_prefixed_hex(access.status);
+        f.write_str(line.as_str())
+    }
+}
+
+/**
+ * @brief Appends a size as AccessRecord keeps it: "n/a", or "{:08x}".
+ */
+fn push_size(line: &mut Line<ACCESS_TEXT_SIZE>, size: u32) {
+    match size {
+        ACCESS_NO_SIZE => line.push_str("n/a"),
+        size => line.push_hex(u64::from(size), 8, Case::Lower),
+    };
+}
+
+/**
+ * @brief Returns the link of a record given the link of the one before it.
+ */
+pub fn link(record: &RingRecord, previous: &[u8; RING_CHAIN_SIZE]) -> [u8; RING_CHAIN_SIZE] {
+    let mut hash = Sha256::new();
+    hash.update(previous);
+    hash.update(&record.sequence.to_le_bytes());
+    // Text records link as they did in 1.0.
+    if record.kind != RECORD_TEXT {
+        hash.update(&[record.kind]);
+    }
+    hash.update(record.data());
+    let mut link = [0u8; RING_CHAIN_SIZE];
+    link.copy_from_slice(&hash.finish()[..RING_CHAIN_SIZE]);
+    link
+}
+
+/**
+ * @brief Verifies a run of consecutive records against the link the first
+ *        one chains from. Returns the sequence number of the first record
+ *        that does not match, if any.
+ */
+pub fn verify_chain<'a>(
+    anchor: [u8; RING_CHAIN_SIZE],
+    records: impl IntoIterator<Item = &'a RingRecord>,
+) -> Result<(), u64> {
+    let mut previous = anchor;
+    for record in records {
+        if link(record, &previous) != record.chain {
+            return Err(record.sequence);
+        }
+        previous = record.chain;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_records_read_as_the_text_they_stand_for() {
+        const GLOBAL: &str = "8BE4DF61-93CA-11D2-AA0D-00E098032B8C";
+        let mut access = AccessRecord::EMPTY;
+        access.guid = Guid::from_fields(
+            0x8be4df61,
+            0x93ca,
+            0x11d2,
+            0xaa,
+            0x0d,
+            &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+        );
+        for (c, next) in access.name.iter_mut().zip("BootOrder".encode_utf16()) {
+            *c = next;
+        }
+        access.status = 0x8000000000000005;
+        access.argument = access_size(Some(0));
+        access.size = access_size(Some(8));
+        let get = RingRecord::raw(RECORD_GET_VARIABLE, &access);
+        assert_eq!(get.access(), Some(access));
+        assert_eq!(
+            RecordText(&get).to_string(),
+            format!(
+                "G: {} Size=00000000->00000008 BootOrder: 0x8000000000000005",
+                GLOBAL
+            )
+        );
+
+        access.argument = 0x07;
+        access.size = access_size(Some(4));
+        access.status = 0;
+        let set = RingRecord::raw(RECORD_SET_VARIABLE, &access);
+        assert_eq!(
+            RecordText(&set).to_string(),
+            format!(
+                "S: {} Attributes=00000007 Size=00000004 BootOrder: 0x0",
+                GLOBAL
+            )
+        );
+
+        // A name filling the record has no terminator.
+        access.name = [b'A' as u16; ACCESS_NAME_LENGTH];
+        access.size = access_size(None);
+        let text = RecordText(&RingRecord::raw(RECORD_GET_VARIABLE, &access)).to_string();
+        assert!(text.ends_with(&format!("->n/a {}: 0x0", "A".repeat(ACCESS_NAME_LENGTH))));
+
+        // The kind is chained along; text records link as in 1.0.
+        let mut retyped = get;
+        retyped.kind = RECORD_SET_VARIABLE;
+        assert_ne!(link(&retyped, &[0; 8]), link(&get, &[0; 8]));
+        assert_eq!(RingRecord::EMPTY.access(), None);
+    }
+
+    #[test]
+    fn headers_of_older_minors_read_without_a_build() {
+        let mut header = RingHeader {
+            format: RingHeader::FORMAT,
+            capacity: 4,
+            next_sequence: 9,
+            ..RingHeader::default()
+        };
+        header.build.version = BuildInfo::text("0.1.1");
+        assert_eq!(RingHeader::parse(header.as_bytes()), Ok(header));
+
+        let mut older = header;
+        older.format.minor = 1;
+        older.format.size = RING_MINOR_1_SIZE as u32;
+        // Followed by a record, as in a dump.
+        let mut bytes = older.as_bytes()[..RING_MINOR_1_SIZE].to_vec();
+        bytes.extend_from_slice(RingRecord::EMPTY.as_bytes());
+        let parsed = RingHeader::parse(&bytes).unwrap();
+        assert_eq!((parsed.format.minor, parsed.next_sequence), (1, 9));
+        assert!(!parsed.build.is_recorded());
+        assert_eq!(
+            RingHeader::parse(&bytes[..RING_MINOR_1_SIZE - 1]),
+            Err(FormatError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// uefi-var-monitor-rust/mm/src/communicate.rs
+//
+// The MMI handler answering the DXE driver, registered for
+// UVM_MM_COMMUNICATE_GUID, with the messages of interface/src/mm.rs. The
+// request is copied into MMRAM before it is looked at; the reply is built in
+// MMRAM too and copied out whole, only into the buffer size the caller gave.
+
+use crate::events;
+use core::sync::atomic::{AtomicBool, Ordering};
+use r_efi::efi;
+use uvm_interface::mm::{
+    MmEvent, MmInfo, MmRead, MmRequest, MM_FUNCTION_INFO, MM_FUNCTION_READ, MM_MESSAGE_SIZE,
+    MM_READ_MAX, MM_VERSION,
+};
+
+const REQUEST_SIZE: usize = core::mem::size_of::<MmRequest>();
+const READ_SIZE: usize = core::mem::size_of::<MmRead>();
+
+pub static HANDLER_REGISTERED: AtomicBool = AtomicBool::new(false);
+pub static PROTOCOL_HOOKED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Builds the reply to the message in `buffer`, of which `size` bytes
+ *        may be written, in place. Returns the size of the reply.
+ */
+pub fn answer(buffer: &mut [u8; MM_MESSAGE_SIZE], size: usize) -> usize {
+    let mut request = match MmRequest::parse(&buffer[..core::cmp::min(size, MM_MESSAGE_SIZE)]) {
+        Ok(request) => request,
+        Err(_) => return 0,
+    };
+    let fits = |length: usize| length <= size;
+    let (status, length) = if request.version != MM_VERSION {
+        (efi::Status::INCOMPATIBLE_VERSION, REQUEST_SIZE)
+    } else {
+        match request.function {
+            MM_FUNCTION_INFO if fits(REQUEST_SIZE + core::mem::size_of::<MmInfo>()) => {
+                let mut info = MmInfo {
+                    handler_registered: HANDLER_REGISTERED.load(Ordering::Acquire) as u32,
+                    protocol_hooked: PROTOCOL_HOOKED.load(Ordering::Acquire) as u32,
+                    ..MmInfo::default()
+                };
+                events::info(&mut info);
+                let bytes = info.as_bytes();
+                buffer[REQUEST_SIZE..REQUEST_SIZE + bytes.len()].copy_from_slice(bytes);
+                (efi::Status::SUCCESS, REQUEST_SIZE + bytes.len())
+            }
+            MM_FUNCTION_READ if fits(REQUEST_SIZE + READ_SIZE) => {
+                let mut read = match MmRead::parse(&buffer[REQUEST_SIZE..]) {
+                    Ok(read) => read,
+                    Err(_) => return 0,
+                };
+                let room = (size - REQUEST_SIZE - READ_SIZE) / core::mem::size_of::<MmEvent>();
+                let mut held = [MmEvent::EMPTY; MM_READ_MAX];
+                let count = core::cmp::min(room, MM_READ_MAX);
+                match events::read(read.first_sequence, &mut held[..count]) {
+                    Some((first, count)) => {
+                        read.first_sequence = first;
+                        read.count = count as u32;
+                        let mut offset = REQUEST_SIZE;
+                        for bytes in core::iter::once(read.as_bytes())
+                            .chain(held[..count].iter().map(MmEvent::as_bytes))
+                        {
+                            buffer[offset..offset + bytes.len()].copy_from_slice(bytes);
+                            offset += bytes.len();
+                        }
+                        (efi::Status::SUCCESS, offset)
+                    }
+                    None => (efi::Status::NOT_READY, REQUEST_SIZE),
+                }
+            }
+            MM_FUNCTION_INFO | MM_FUNCTION_READ => (efi::Status::BAD_BUFFER_SIZE, REQUEST_SIZE),
+            _ => (efi::Status::UNSUPPORTED, REQUEST_SIZE),
+        }
+    };
+    request.status = status.as_usize() as u64;
+    buffer[..REQUEST_SIZE].copy_from_slice(request.as_bytes());
+    length
+}
+
+efiapi! {
+    /**
+     * @brief Answers a message of the DXE driver.
+     */
+    pub fn handle_communicate(
+        _dispatch_handle: efi::Handle,
+        _context: *const core::ffi::c_void,
+        comm_buffer: *mut core::ffi::c_void,
+        comm_buffer_size: *mut usize,
+    ) -> efi::Status {
+        if comm_buffer.is_null() || comm_buffer_size.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let size = unsafe { comm_buffer_size.read_volatile() };
+        let mut buffer = [0u8; MM_MESSAGE_SIZE];
+        let copied = core::cmp::min(size, MM_MESSAGE_SIZE);
+        unsafe {
+            core::ptr::copy_nonoverlapping(comm_buffer as *const u8, buffer.as_mut_ptr(), copied)
+        };
+        let length = answer(&mut buffer, copied);
+        if length == 0 {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(buffer.as_ptr(), comm_buffer as *mut u8, length);
+            comm_buffer_size.write_volatile(length);
+        }
+        efi::Status::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(function: u32, read: Option<MmRead>) -> [u8; MM_MESSAGE_SIZE] {
+        let mut buffer = [0u8; MM_MESSAGE_SIZE];
+        let request = MmRequest {
+            function,
+            version: MM_VERSION,
+            status: 0,
+        };
+        buffer[..REQUEST_SIZE].copy_from_slice(request.as_bytes());
+        if let Some(read) = read {
+            buffer[REQUEST_SIZE..REQUEST_SIZE + READ_SIZE].copy_from_slice(read.as_bytes());
+        }
+        buffer
+    }
+
+    fn status(buffer: &[u8]) -> efi::Status {
+        efi::Status::from_usize(MmRequest::parse(buffer).unwrap().status as usize)
+    }
+
+    #[test]
+    fn info_and_reads_are_answered_within_the_buffer() {
+        events::reset();
+        for size in 0..20 {
+            let mut event = MmEvent::EMPTY;
+            event.data_size = size;
+            events::record(event);
+        }
+
+        let mut buffer = message(MM_FUNCTION_INFO, None);
+        let length = answer(&mut buffer, MM_MESSAGE_SIZE);
+        assert_eq!(status(&buffer), efi::Status::SUCCESS);
+        let info = MmInfo::parse(&buffer[REQUEST_SIZE..length]).unwrap();
+        assert_eq!((info.first_sequence, info.next_sequence), (0, 20));
+
+        // As many events as fit, then the rest.
+        let read = MmRead {
+            first_sequence: 2,
+            ..MmRead::default()
+        };
+        let mut buffer = message(MM_FUNCTION_READ, Some(read));
+        let size = REQUEST_SIZE + READ_SIZE + 3 * core::mem::size_of::<MmEvent>() + 5;
+        assert_eq!(answer(&mut buffer, size), size - 5);
+        let reply = MmRead::parse(&buffer[REQUEST_SIZE..]).unwrap();
+        assert_eq!((reply.first_sequence, reply.count), (2, 3));
+        let last = MmEvent::parse(&buffer[size - 5 - core::mem::size_of::<MmEvent>()..]).unwrap();
+        assert_eq!(last.data_size, 4);
+
+        let mut buffer = message(MM_FUNCTION_READ, Some(read));
+        let length = answer(&mut buffer, MM_MESSAGE_SIZE);
+        let reply = MmRead::parse(&buffer[REQUEST_SIZE..]).unwrap();
+        assert_eq!((reply.first_sequence, reply.count), (2, MM_READ_MAX as u32));
+        assert_eq!(length, MM_MESSAGE_SIZE);
+
+        // Too small for the function, another version, or anything at all.
+        let mut buffer = message(MM_FUNCTION_READ, Some(read));
+        assert_eq!(answer(&mut buffer, REQUEST_SIZE + 4), REQUEST_SIZE);
+        assert_eq!(status(&buffer), efi::Status::BAD_BUFFER_SIZE);
+        let mut buffer = message(MM_FUNCTION_INFO, None);
+        buffer[4] = 2;
+        answer(&mut buffer, MM_MESSAGE_SIZE);
+        assert_eq!(status(&buffer), efi::Status::INCOMPATIBLE_VERSION);
+        assert_eq!(answer(&mut message(9, None), MM_MESSAGE_SIZE), REQUEST_SIZE);
+        assert_eq!(
+            answer(&mut message(MM_FUNCTION_INFO, None), REQUEST_SIZE - 1),
+            0
+        );
+        events::reset();
+    }
+}
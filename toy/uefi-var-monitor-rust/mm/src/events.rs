@@ -0,0 +1,124 @@
+// uefi-var-monitor-rust/mm/src/events.rs
+//
+// The events seen so far, in a fixed buffer in MMRAM. Once full, the oldest
+// event is overwritten; readers ask from a sequence number and learn from the
+// one they get back how many they missed. The buffer is only try-borrowed: an
+// event arriving while it is read, from a nested MMI, is counted as dropped
+// instead.
+
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use uvm_interface::mm::{MmEvent, MmInfo, MM_EVENT_SIZE};
+
+pub const CAPACITY: usize = 256;
+
+pub struct Events<const N: usize> {
+    events: [MmEvent; N],
+    next_sequence: u64,
+}
+
+impl<const N: usize> Events<N> {
+    pub const fn new() -> Self {
+        Events {
+            events: [MmEvent::EMPTY; N],
+            next_sequence: 0,
+        }
+    }
+
+    pub fn first_sequence(&self) -> u64 {
+        self.next_sequence.saturating_sub(N as u64)
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn push(&mut self, mut event: MmEvent) {
+        event.sequence = self.next_sequence;
+        self.events[(self.next_sequence % N as u64) as usize] = event;
+        self.next_sequence += 1;
+    }
+
+    /**
+     * @brief Copies the events held from `first` on into `events`, oldest
+     *        first. Returns the sequence number of the first one copied and
+     *        how many were.
+     */
+    pub fn read(&self, first: u64, events: &mut [MmEvent]) -> (u64, usize) {
+        let start = core::cmp::max(first, self.first_sequence());
+        let mut count = 0;
+        for (sequence, slot) in (start..self.next_sequence).zip(events.iter_mut()) {
+            *slot = self.events[(sequence % N as u64) as usize];
+            count += 1;
+        }
+        (start, count)
+    }
+}
+
+static EVENTS: AtomicRefCell<Events<CAPACITY>> = AtomicRefCell::new(Events::new());
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Records an event, unless the buffer is in use.
+ */
+pub fn record(event: MmEvent) {
+    match EVENTS.try_borrow_mut() {
+        Ok(mut events) => events.push(event),
+        Err(_) => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/**
+ * @brief Copies the events held from `first` on, as Events::read does. None
+ *        if the buffer is in use.
+ */
+pub fn read(first: u64, events: &mut [MmEvent]) -> Option<(u64, usize)> {
+    EVENTS
+        .try_borrow()
+        .ok()
+        .map(|held| held.read(first, events))
+}
+
+/**
+ * @brief Fills the buffer part of `info`.
+ */
+pub fn info(info: &mut MmInfo) {
+    info.capacity = CAPACITY as u32;
+    info.event_size = MM_EVENT_SIZE as u32;
+    info.dropped = DROPPED.load(Ordering::Relaxed);
+    if let Ok(events) = EVENTS.try_borrow() {
+        info.first_sequence = events.first_sequence();
+        info.next_sequence = events.next_sequence();
+    }
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *EVENTS.borrow_mut() = Events::new();
+    DROPPED.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_events_are_overwritten() {
+        let mut events = Events::<3>::new();
+        for size in 0..5 {
+            let mut event = MmEvent::EMPTY;
+            event.data_size = size;
+            events.push(event);
+        }
+        assert_eq!((events.first_sequence(), events.next_sequence()), (2, 5));
+
+        let mut read = [MmEvent::EMPTY; 2];
+        assert_eq!(events.read(0, &mut read), (2, 2));
+        assert_eq!((read[0].sequence, read[0].data_size), (2, 2));
+        assert_eq!((read[1].sequence, read[1].data_size), (3, 3));
+        assert_eq!(events.read(4, &mut read), (4, 1));
+        assert_eq!(events.read(5, &mut read), (5, 0));
+    }
+}
@@ -0,0 +1,119 @@
+// uefi-var-monitor-rust/mm/src/main.rs
+//
+// Standalone MM module of the monitor. On platforms whose variable store is
+// run in MM, the OS writes variables by sending SMIs to the MM variable
+// driver, which never go through the runtime services table the DXE driver
+// hooks. This module watches them where they land (see variable.rs) and
+// keeps them in MMRAM (see events.rs) for the DXE driver to read through MM
+// Communicate (see communicate.rs and interface/src/mm.rs).
+//
+// The two modules are paired by UVM_MM_COMMUNICATE_GUID only. Each runs
+// without the other: the DXE driver built with the mm-events feature relays
+// what this module saw into its own log, at load and at ReadyToBoot (see the
+// driver's src/mm.rs); without this module, it logs once that none answered.
+// Events seen after the last read stay in MMRAM until the next reset.
+//
+// The module must be dispatched before the MM variable driver, e.g. listed
+// first in the MM apriori file, so that its MMI handler runs before the
+// variable driver's ends the dispatch. The MM variable protocol is hooked
+// whenever it is installed, before or after.
+//
+// It runs in MM, with no boot or runtime services: it uses only the MM system
+// table it is given, formats nothing, allocates nothing, and never writes to
+// memory outside MMRAM but the reply buffer of a communicate request. A panic
+// stops the CPU handling the MMI.
+
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
+
+use core::sync::atomic::Ordering;
+use r_efi::efi;
+use uvm_interface::mm::UVM_MM_COMMUNICATE_GUID;
+
+// efiapi!, as the driver declares its callbacks with.
+#[macro_use]
+extern crate uvm_interface;
+
+mod communicate;
+mod events;
+mod table;
+mod variable;
+
+use communicate::{HANDLER_REGISTERED, PROTOCOL_HOOKED};
+use table::{MmSystemTable, SmmVariableProtocol, SMM_VARIABLE_PROTOCOL_GUID};
+
+efiapi! {
+    /**
+     * @brief Hooks the MM variable protocol once it is installed.
+     */
+    fn handle_variable_protocol(
+        _protocol: *const efi::Guid,
+        interface: *mut core::ffi::c_void,
+        _handle: efi::Handle,
+    ) -> efi::Status {
+        if variable::hook(interface as *mut SmmVariableProtocol) {
+            PROTOCOL_HOOKED.store(true, Ordering::Release);
+        }
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief The module entry point.
+     */
+    #[no_mangle]
+    fn efi_main(_image_handle: efi::Handle, mm_system_table: *mut MmSystemTable) -> efi::Status {
+        if mm_system_table.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let mm_system_table = unsafe { &*mm_system_table };
+
+        // Answering the DXE driver is what the module is for.
+        let mut handle: efi::Handle = core::ptr::null_mut();
+        let efi_status = (mm_system_table.mmi_handler_register)(
+            communicate::handle_communicate,
+            &UVM_MM_COMMUNICATE_GUID,
+            &mut handle,
+        );
+        if efi_status.is_error() {
+            return efi_status;
+        }
+
+        // Either source may be missing on a given platform; the DXE driver
+        // reports which are active.
+        let mut handle: efi::Handle = core::ptr::null_mut();
+        let efi_status = (mm_system_table.mmi_handler_register)(
+            variable::handle_variable_request,
+            &SMM_VARIABLE_PROTOCOL_GUID,
+            &mut handle,
+        );
+        HANDLER_REGISTERED.store(!efi_status.is_error(), Ordering::Release);
+
+        let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+        let efi_status = (mm_system_table.mm_locate_protocol)(
+            &SMM_VARIABLE_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+            core::ptr::null_mut(),
+            &mut interface,
+        );
+        if !efi_status.is_error() && variable::hook(interface as *mut SmmVariableProtocol) {
+            PROTOCOL_HOOKED.store(true, Ordering::Release);
+        } else {
+            let mut registration: *mut core::ffi::c_void = core::ptr::null_mut();
+            let _ = (mm_system_table.mm_register_protocol_notify)(
+                &SMM_VARIABLE_PROTOCOL_GUID,
+                handle_variable_protocol,
+                &mut registration,
+            );
+        }
+        efi::Status::SUCCESS
+    }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn panic_handler(_info: &core::panic::PanicInfo) -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,107 @@
+// uefi-var-monitor-rust/mm/src/table.rs
+//
+// The parts of the PI specification's MM interfaces the module uses, which
+// r-efi does not define: the MM system table, the MMI handler and protocol
+// notification types, the PI status codes MMI handlers return, and the MM
+// variable protocol EDK2's variable driver installs. Entries of the system
+// table the module does not call are kept as plain pointers.
+
+use r_efi::efi;
+
+pub type MmiHandlerType = efiapi! {fn(
+    efi::Handle,
+    *const core::ffi::c_void,
+    *mut core::ffi::c_void,
+    *mut usize,
+) -> efi::Status};
+
+pub type MmNotifyType = efiapi! {fn(
+    *const efi::Guid,
+    *mut core::ffi::c_void,
+    efi::Handle,
+) -> efi::Status};
+
+pub type SetVariableType = efiapi! {fn(
+    *mut r_efi::base::Char16,
+    *mut r_efi::base::Guid,
+    u32,
+    usize,
+    *mut core::ffi::c_void,
+) -> efi::Status};
+
+#[repr(C)]
+pub struct MmIoAccess {
+    pub read: *mut core::ffi::c_void,
+    pub write: *mut core::ffi::c_void,
+}
+
+#[repr(C)]
+pub struct MmCpuIo {
+    pub mem: MmIoAccess,
+    pub io: MmIoAccess,
+}
+
+#[repr(C)]
+pub struct MmSystemTable {
+    pub hdr: efi::TableHeader,
+    pub mm_firmware_vendor: *mut r_efi::base::Char16,
+    pub mm_firmware_revision: u32,
+    pub mm_install_configuration_table: *mut core::ffi::c_void,
+    pub mm_io: MmCpuIo,
+    pub mm_allocate_pool: *mut core::ffi::c_void,
+    pub mm_free_pool: *mut core::ffi::c_void,
+    pub mm_allocate_pages: *mut core::ffi::c_void,
+    pub mm_free_pages: *mut core::ffi::c_void,
+    pub mm_startup_this_ap: *mut core::ffi::c_void,
+    pub currently_executing_cpu: usize,
+    pub number_of_cpus: usize,
+    pub cpu_save_state_size: *mut usize,
+    pub cpu_save_state: *mut *mut core::ffi::c_void,
+    pub number_of_table_entries: usize,
+    pub mm_configuration_table: *mut efi::ConfigurationTable,
+    pub mm_install_protocol_interface: *mut core::ffi::c_void,
+    pub mm_uninstall_protocol_interface: *mut core::ffi::c_void,
+    pub mm_handle_protocol: *mut core::ffi::c_void,
+    pub mm_register_protocol_notify: efiapi! {fn(
+        *const efi::Guid,
+        MmNotifyType,
+        *mut *mut core::ffi::c_void,
+    ) -> efi::Status},
+    pub mm_locate_handle: *mut core::ffi::c_void,
+    pub mm_locate_protocol: efiapi! {fn(
+        *mut efi::Guid,
+        *mut core::ffi::c_void,
+        *mut *mut core::ffi::c_void,
+    ) -> efi::Status},
+    pub mmi_manage: *mut core::ffi::c_void,
+    pub mmi_handler_register: efiapi! {fn(
+        MmiHandlerType,
+        *const efi::Guid,
+        *mut efi::Handle,
+    ) -> efi::Status},
+    pub mmi_handler_unregister: efiapi! {fn(efi::Handle) -> efi::Status},
+}
+
+// PI status codes are flagged with the bit two below the error bit.
+const PI_STATUS: usize = 1 << (usize::BITS - 3);
+// Handled, and the next handlers are still called.
+pub const WARN_INTERRUPT_SOURCE_PENDING: efi::Status = efi::Status::from_usize(PI_STATUS);
+
+// {ed32d533-99e6-4209-9cc0-2d72cdd998a7}, also the type of the variable
+// driver's MMI handler.
+pub const SMM_VARIABLE_PROTOCOL_GUID: efi::Guid = efi::Guid::from_fields(
+    0xed32d533,
+    0x99e6,
+    0x4209,
+    0x9c,
+    0xc0,
+    &[0x2d, 0x72, 0xcd, 0xd9, 0x98, 0xa7],
+);
+
+#[repr(C)]
+pub struct SmmVariableProtocol {
+    pub smm_get_variable: *mut core::ffi::c_void,
+    pub smm_get_next_variable_name: *mut core::ffi::c_void,
+    pub smm_set_variable: SetVariableType,
+    pub smm_query_variable_info: *mut core::ffi::c_void,
+}
@@ -0,0 +1,234 @@
+// uefi-var-monitor-rust/mm/src/variable.rs
+//
+// Where variable writes are seen in MM:
+//
+//   - SetVariable requests the OS or DXE sends to the variable driver, in the
+//     communicate buffers of EDK2's variable MMI handler. A second handler of
+//     the same type, registered before that one, sees each request first:
+//     EDK2's ends the dispatch, so it must be, by dispatching this module
+//     before the variable driver (see main.rs). The outcome is not known yet.
+//   - calls to the MM variable protocol by other MM modules, through its
+//     SetVariable entry, replaced once the protocol is installed. EDK2's MMI
+//     handler calls the variable services directly, so nothing is seen twice.
+//
+// A communicate buffer lies outside MMRAM and can change under us: the part
+// read is copied into MMRAM first and only the copy is looked at. Nothing is
+// ever written to it.
+
+use crate::events;
+use crate::table::{SetVariableType, SmmVariableProtocol};
+use core::convert::{TryFrom, TryInto};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use r_efi::efi;
+use uvm_interface::mm::{
+    MmEvent, MM_NAME_SIZE, MM_SOURCE_COMMUNICATE, MM_SOURCE_PROTOCOL, MM_STATUS_UNKNOWN,
+};
+
+// SMM_VARIABLE_COMMUNICATE_HEADER: the function, then the status the variable
+// driver returns.
+const HEADER_SIZE: usize = 2 * core::mem::size_of::<usize>();
+const FUNCTION_SET_VARIABLE: usize = 2;
+// SMM_VARIABLE_COMMUNICATE_ACCESS_VARIABLE up to its name: the GUID, data
+// size, name size in bytes and attributes.
+const ACCESS_SIZE: usize = 16 + 2 * core::mem::size_of::<usize>() + 4;
+// Bytes of a communicate buffer copied, enough for the name kept.
+pub const COPY_SIZE: usize = HEADER_SIZE + ACCESS_SIZE + 2 * MM_NAME_SIZE;
+
+fn read_usize(bytes: &[u8], offset: usize) -> Option<usize> {
+    const SIZE: usize = core::mem::size_of::<usize>();
+    let mut value = [0u8; SIZE];
+    value.copy_from_slice(bytes.get(offset..offset + SIZE)?);
+    Some(usize::from_le_bytes(value))
+}
+
+/**
+ * @brief Keeps the printable ASCII of a UCS-2 name, '?' for the rest.
+ */
+fn keep_name(event: &mut MmEvent, name: impl Iterator<Item = u16>) {
+    let mut length = 0;
+    for (slot, c16) in event.name.iter_mut().zip(name.take_while(|c16| *c16 != 0)) {
+        *slot = if (0x20..0x7f).contains(&c16) {
+            c16 as u8
+        } else {
+            b'?'
+        };
+        length += 1;
+    }
+    event.name_length = length as u8;
+}
+
+/**
+ * @brief Returns the write a copied communicate buffer of `size` bytes asks
+ *        for, None if it is not a well-formed SetVariable request.
+ */
+pub fn parse_request(copy: &[u8], size: usize) -> Option<MmEvent> {
+    if read_usize(copy, 0)? != FUNCTION_SET_VARIABLE {
+        return None;
+    }
+    let access = copy.get(HEADER_SIZE..)?;
+    let data_size = read_usize(access, 16)?;
+    let name_size = read_usize(access, 16 + core::mem::size_of::<usize>())?;
+    let attributes = u32::from_le_bytes(access.get(ACCESS_SIZE - 4..ACCESS_SIZE)?.try_into().ok()?);
+    // The whole request must fit the buffer the caller handed over.
+    let total = (HEADER_SIZE + ACCESS_SIZE)
+        .checked_add(name_size)?
+        .checked_add(data_size)?;
+    if total > size || name_size % 2 != 0 {
+        return None;
+    }
+
+    let mut event = MmEvent::EMPTY;
+    // The access part is known to be longer than a GUID.
+    event.guid = unsafe { core::ptr::read_unaligned(access.as_ptr() as *const efi::Guid) };
+    event.attributes = attributes;
+    event.data_size = u32::try_from(data_size).unwrap_or(u32::MAX);
+    event.source = MM_SOURCE_COMMUNICATE;
+    event.status = MM_STATUS_UNKNOWN;
+    let name = access
+        .get(ACCESS_SIZE..ACCESS_SIZE + name_size)
+        .unwrap_or(&access[ACCESS_SIZE..]);
+    keep_name(
+        &mut event,
+        name.chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]])),
+    );
+    Some(event)
+}
+
+efiapi! {
+    /**
+     * @brief Sees the requests sent to the variable driver, and lets it
+     *        handle them.
+     */
+    pub fn handle_variable_request(
+        _dispatch_handle: efi::Handle,
+        _context: *const core::ffi::c_void,
+        comm_buffer: *mut core::ffi::c_void,
+        comm_buffer_size: *mut usize,
+    ) -> efi::Status {
+        if comm_buffer.is_null() || comm_buffer_size.is_null() {
+            return crate::table::WARN_INTERRUPT_SOURCE_PENDING;
+        }
+        let size = unsafe { comm_buffer_size.read_volatile() };
+        let mut copy = [0u8; COPY_SIZE];
+        let length = core::cmp::min(size, COPY_SIZE);
+        unsafe {
+            core::ptr::copy_nonoverlapping(comm_buffer as *const u8, copy.as_mut_ptr(), length)
+        };
+        if let Some(event) = parse_request(&copy[..length], size) {
+            events::record(event);
+        }
+        crate::table::WARN_INTERRUPT_SOURCE_PENDING
+    }
+}
+
+// The protocol's SetVariable, as it was before being replaced.
+static SET_VARIABLE: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+
+efiapi! {
+    /**
+     * @brief Forwards a SetVariable call made through the protocol, and
+     *        records it with its outcome.
+     */
+    fn handle_set_variable(
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        let original = SET_VARIABLE.load(Ordering::Acquire);
+        if original.is_null() {
+            return efi::Status::NOT_READY;
+        }
+        let original: SetVariableType = unsafe { core::mem::transmute(original) };
+        let efi_status = original(variable_name, vendor_guid, attributes, data_size, data);
+
+        if !variable_name.is_null() && !vendor_guid.is_null() {
+            let mut event = MmEvent::EMPTY;
+            event.guid = unsafe { *vendor_guid };
+            event.attributes = attributes;
+            event.data_size = u32::try_from(data_size).unwrap_or(u32::MAX);
+            event.source = MM_SOURCE_PROTOCOL;
+            event.status = efi_status.as_usize() as u64;
+            keep_name(
+                &mut event,
+                (0..).map(|index| unsafe { variable_name.add(index).read() }),
+            );
+            events::record(event);
+        }
+        efi_status
+    }
+}
+
+/**
+ * @brief Replaces the protocol's SetVariable. Does nothing if it already was.
+ */
+pub fn hook(protocol: *mut SmmVariableProtocol) -> bool {
+    if protocol.is_null() {
+        return false;
+    }
+    let protocol = unsafe { &mut *protocol };
+    if protocol.smm_set_variable as usize == handle_set_variable as SetVariableType as usize {
+        return true;
+    }
+    SET_VARIABLE.store(
+        protocol.smm_set_variable as *mut core::ffi::c_void,
+        Ordering::Release,
+    );
+    protocol.smm_set_variable = handle_set_variable;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A SetVariable request, laid out as the variable driver reads it.
+    fn request(name: &[u16], data_size: usize) -> std::vec::Vec<u8> {
+        let mut bytes = std::vec::Vec::new();
+        bytes.extend_from_slice(&FUNCTION_SET_VARIABLE.to_le_bytes());
+        bytes.extend_from_slice(&0usize.to_le_bytes());
+        bytes.extend_from_slice(&[0x11; 16]);
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        bytes.extend_from_slice(&(2 * name.len()).to_le_bytes());
+        bytes.extend_from_slice(&0x27u32.to_le_bytes());
+        for c16 in name {
+            bytes.extend_from_slice(&c16.to_le_bytes());
+        }
+        bytes.resize(bytes.len() + data_size, 0xaa);
+        bytes
+    }
+
+    #[test]
+    fn set_variable_requests_are_parsed_within_their_buffer() {
+        let bytes = request(&[b'd' as u16, b'b' as u16, 0x263a, 0], 4);
+        let event = parse_request(&bytes, bytes.len()).unwrap();
+        assert_eq!(event.name(), "db?");
+        assert_eq!((event.attributes, event.data_size), (0x27, 4));
+        assert_eq!(event.guid.as_bytes(), &[0x11; 16]);
+        assert_eq!(event.source, MM_SOURCE_COMMUNICATE);
+
+        // Sizes reaching past the buffer, odd name sizes, other functions.
+        assert!(parse_request(&bytes, bytes.len() - 1).is_none());
+        let mut huge = request(&[b'd' as u16, 0], 0);
+        huge[HEADER_SIZE + 16..HEADER_SIZE + 24].copy_from_slice(&(usize::MAX - 8).to_le_bytes());
+        assert!(parse_request(&huge, usize::MAX).is_none());
+        let mut odd = bytes.clone();
+        odd[HEADER_SIZE + 24..HEADER_SIZE + 32].copy_from_slice(&7usize.to_le_bytes());
+        assert!(parse_request(&odd, odd.len()).is_none());
+        let mut get = bytes.clone();
+        get[..8].copy_from_slice(&1usize.to_le_bytes());
+        assert!(parse_request(&get, get.len()).is_none());
+        assert!(parse_request(&bytes[..HEADER_SIZE + 8], bytes.len()).is_none());
+    }
+
+    #[test]
+    fn long_names_are_cut() {
+        let name: std::vec::Vec<u16> = (0..100).map(|_| b'x' as u16).chain([0]).collect();
+        let bytes = request(&name, 0);
+        let length = core::cmp::min(bytes.len(), COPY_SIZE);
+        let event = parse_request(&bytes[..length], bytes.len()).unwrap();
+        assert_eq!(event.name_length as usize, MM_NAME_SIZE);
+    }
+}
@@ -0,0 +1,432 @@
+// uefi-var-monitor-rust/src/alert.rs
+//
+// What the alerts of the driver decide, apart from how they are written:
+// which rule an access to a variable raises, at what severity (see
+// severity), and whether the alert manager shows an occurrence of a rule or
+// suppresses it. The driver's rules.rs writes the alert lines and alerts.rs
+// holds the manager it uses, with the clock and the logging around it;
+// replay.rs runs the same decisions over a captured dump.
+//
+// Each rule has limits deciding whether an occurrence is shown or
+// suppressed:
+//
+//   threshold   the rule fires on its Nth occurrence since it last fired
+//   cooldown    once fired, repeats are suppressed for M occurrences of the
+//               rule, or for M seconds
+//
+// Seconds are measured on a cycle counter and its ticks per second, given
+// with each occurrence; without them, cooldowns in seconds do not suppress
+// anything. Occurrence numbers and cycle counts are compared with wrapping
+// arithmetic, so counters rolling over do not stall a rule.
+//
+// The limits start from DEFAULT_LIMITS and can be overridden with a
+// ';'-separated list of rule=threshold[/cooldown] entries, the cooldown in
+// occurrences or, with an "s" suffix, in seconds:
+//
+//   key-read=1/300s;mor-access=1/10;size-change=3
+
+use crate::classify::VariableClass;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    SecureBootKeyRead = 0,
+    SecureBootKeyWrite = 1,
+    SizeChange = 2,
+    Correlation = 3,
+    HookIntegrity = 4,
+    TableCrc = 5,
+    ModeTransition = 6,
+    MorAccess = 7,
+    MorWeakening = 8,
+    AccessRate = 9,
+    PreviousBoot = 10,
+    AuthenticatedDeletion = 11,
+    BootManagerWrite = 12,
+    BlockedWrite = 13,
+    ShadowDivergence = 14,
+    SignatureAppend = 15,
+    PinMismatch = 16,
+}
+
+pub const RULE_COUNT: usize = 17;
+
+impl Rule {
+    pub const ALL: [Rule; RULE_COUNT] = [
+        Rule::SecureBootKeyRead,
+        Rule::SecureBootKeyWrite,
+        Rule::SizeChange,
+        Rule::Correlation,
+        Rule::HookIntegrity,
+        Rule::TableCrc,
+        Rule::ModeTransition,
+        Rule::MorAccess,
+        Rule::MorWeakening,
+        Rule::AccessRate,
+        Rule::PreviousBoot,
+        Rule::AuthenticatedDeletion,
+        Rule::BootManagerWrite,
+        Rule::BlockedWrite,
+        Rule::ShadowDivergence,
+        Rule::SignatureAppend,
+        Rule::PinMismatch,
+    ];
+
+    /**
+     * @brief Returns the name of the rule in lists of limits and summaries.
+     */
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::SecureBootKeyRead => "key-read",
+            Rule::SecureBootKeyWrite => "key-write",
+            Rule::SizeChange => "size-change",
+            Rule::Correlation => "correlation",
+            Rule::HookIntegrity => "hook-integrity",
+            Rule::TableCrc => "table-crc",
+            Rule::ModeTransition => "mode-transition",
+            Rule::MorAccess => "mor-access",
+            Rule::MorWeakening => "mor-weakening",
+            Rule::AccessRate => "access-rate",
+            Rule::PreviousBoot => "previous-boot",
+            Rule::AuthenticatedDeletion => "auth-deletion",
+            Rule::BootManagerWrite => "boot-manager-write",
+            Rule::BlockedWrite => "blocked-write",
+            Rule::ShadowDivergence => "shadow",
+            Rule::SignatureAppend => "signature-append",
+            Rule::PinMismatch => "pin-mismatch",
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        Rule::ALL.iter().copied().find(|rule| rule.name() == text)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cooldown {
+    None,
+    Occurrences(u32),
+    Seconds(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    // Occurrences since the rule last fired needed to fire it again; 0 is
+    // taken as 1.
+    pub threshold: u32,
+    pub cooldown: Cooldown,
+}
+
+pub type LimitTable = [Limits; RULE_COUNT];
+
+pub const EVERY: Limits = Limits {
+    threshold: 1,
+    cooldown: Cooldown::None,
+};
+
+// Everything fires every time, except Secure Boot key reads, which firmware
+// and loaders make several times on every boot.
+pub const DEFAULT_LIMITS: LimitTable = {
+    let mut limits = [EVERY; RULE_COUNT];
+    limits[Rule::SecureBootKeyRead as usize].cooldown = Cooldown::Seconds(60);
+    limits
+};
+
+/**
+ * @brief Returns DEFAULT_LIMITS overridden by the entries of a list.
+ *        Malformed entries are ignored.
+ */
+pub fn parse_limits(list: &str) -> LimitTable {
+    let mut limits = DEFAULT_LIMITS;
+    for entry in list.split(|c| c == ';') {
+        if let Some((rule, parsed)) = parse_entry(entry.trim()) {
+            limits[rule as usize] = parsed;
+        }
+    }
+    limits
+}
+
+fn parse_entry(entry: &str) -> Option<(Rule, Limits)> {
+    let (rule, value) = entry.split_once(|c| c == '=')?;
+    let rule = Rule::from_str(rule.trim())?;
+    let (threshold, cooldown) = match value.split_once(|c| c == '/') {
+        Some((threshold, cooldown)) => (threshold, Some(cooldown.trim())),
+        None => (value, None),
+    };
+    let cooldown = match cooldown {
+        None => Cooldown::None,
+        Some(seconds) if seconds.ends_with('s') => {
+            Cooldown::Seconds(seconds.trim_end_matches('s').parse().ok()?)
+        }
+        Some(occurrences) => Cooldown::Occurrences(occurrences.parse().ok()?),
+    };
+    Some((
+        rule,
+        Limits {
+            threshold: threshold.trim().parse().ok()?,
+            cooldown,
+        },
+    ))
+}
+
+#[derive(Clone, Copy)]
+struct Counter {
+    // Number of the latest occurrence, and of the one that last fired.
+    occurrences: u32,
+    fired_at: u32,
+    fired: bool,
+    // Cycle counter when it last fired.
+    fired_tick: u64,
+    // Occurrences suppressed since it last fired, and in total.
+    suppressed: u32,
+    total_suppressed: u32,
+}
+
+impl Counter {
+    const NEW: Counter = Counter {
+        occurrences: 0,
+        fired_at: 0,
+        fired: false,
+        fired_tick: 0,
+        suppressed: 0,
+        total_suppressed: 0,
+    };
+}
+
+pub struct Manager {
+    limits: LimitTable,
+    counters: [Counter; RULE_COUNT],
+}
+
+impl Manager {
+    pub const fn new() -> Self {
+        Manager {
+            limits: DEFAULT_LIMITS,
+            counters: [Counter::NEW; RULE_COUNT],
+        }
+    }
+
+    /**
+     * @brief Counts an occurrence of `rule` at `clock`, the cycle counter and
+     *        its ticks per second if calibrated. Returns the number of
+     *        occurrences suppressed since it last fired if it fires now,
+     *        None if this one is suppressed.
+     */
+    pub fn admit(&mut self, rule: Rule, clock: Option<(u64, u64)>) -> Option<u32> {
+        let limits = self.limits[rule as usize];
+        let counter = &mut self.counters[rule as usize];
+        counter.occurrences = counter.occurrences.wrapping_add(1);
+        let since = counter.occurrences.wrapping_sub(counter.fired_at);
+        let mut due = since >= core::cmp::max(limits.threshold, 1);
+        if counter.fired {
+            due &= match (limits.cooldown, clock) {
+                (Cooldown::None, _) => true,
+                (Cooldown::Occurrences(occurrences), _) => since > occurrences,
+                (Cooldown::Seconds(seconds), Some((now, ticks_per_second))) => {
+                    now.wrapping_sub(counter.fired_tick)
+                        >= u64::from(seconds).saturating_mul(ticks_per_second)
+                }
+                (Cooldown::Seconds(_), None) => true,
+            };
+        }
+        if !due {
+            counter.suppressed = counter.suppressed.saturating_add(1);
+            counter.total_suppressed = counter.total_suppressed.saturating_add(1);
+            return None;
+        }
+        let suppressed = counter.suppressed;
+        counter.suppressed = 0;
+        counter.fired = true;
+        counter.fired_at = counter.occurrences;
+        counter.fired_tick = clock.map(|(now, _)| now).unwrap_or(0);
+        Some(suppressed)
+    }
+
+    /**
+     * @brief Sets the limits of every rule.
+     */
+    pub fn set_limits(&mut self, limits: &LimitTable) {
+        self.limits = *limits;
+    }
+
+    /**
+     * @brief Zeroes the occurrence and suppressed counts of every rule,
+     *        keeping the limits.
+     */
+    pub fn reset_counters(&mut self) {
+        self.counters = [Counter::NEW; RULE_COUNT];
+    }
+
+    pub fn total_suppressed(&self, rule: Rule) -> u32 {
+        self.counters[rule as usize].total_suppressed
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Get,
+    Set { attributes: u32, data_size: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Default for Manager {
+    fn default() -> Self {
+        Manager::new()
+    }
+}
+
+/**
+ * @brief Returns the rule raised by an access to a variable of `class`, and
+ *        the severity of its alert, if any.
+ */
+pub fn severity(class: VariableClass, access: Access) -> Option<(Rule, Severity)> {
+    match (class, access) {
+        (VariableClass::SecureBootKey, Access::Get) => {
+            Some((Rule::SecureBootKeyRead, Severity::Info))
+        }
+        (VariableClass::SecureBootKey, Access::Set { .. }) => {
+            Some((Rule::SecureBootKeyWrite, Severity::Critical))
+        }
+        // Only writes at OS runtime are alerted on, with the decoded values
+        // (see set_variable.rs in the driver).
+        (VariableClass::BootManager, _) => None,
+        // Only correlated writes are alerted on (see correlate.rs in the
+        // driver).
+        (VariableClass::OsIndications, _) | (VariableClass::Mor, _) => None,
+        (VariableClass::Other, _) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager(rule: Rule, limits: Limits) -> Manager {
+        let mut manager = Manager::new();
+        manager.limits[rule as usize] = limits;
+        manager
+    }
+
+    // Admits `count` occurrences; returns what each one gave.
+    fn run(manager: &mut Manager, rule: Rule, count: usize) -> std::vec::Vec<Option<u32>> {
+        (0..count).map(|_| manager.admit(rule, None)).collect()
+    }
+
+    #[test]
+    fn secure_boot_key_writes_are_critical() {
+        let write = Access::Set {
+            attributes: 0x27,
+            data_size: 0x400,
+        };
+        assert_eq!(
+            severity(VariableClass::SecureBootKey, Access::Get),
+            Some((Rule::SecureBootKeyRead, Severity::Info))
+        );
+        assert_eq!(
+            severity(VariableClass::SecureBootKey, write),
+            Some((Rule::SecureBootKeyWrite, Severity::Critical))
+        );
+        assert_eq!(severity(VariableClass::Other, write), None);
+    }
+
+    #[test]
+    fn thresholds_and_occurrence_cooldowns() {
+        let rule = Rule::SizeChange;
+        let mut every = Manager::new();
+        assert_eq!(run(&mut every, rule, 3), [Some(0); 3]);
+
+        let mut third = manager(
+            rule,
+            Limits {
+                threshold: 3,
+                cooldown: Cooldown::None,
+            },
+        );
+        assert_eq!(
+            run(&mut third, rule, 6),
+            [None, None, Some(2), None, None, Some(2)]
+        );
+        assert_eq!(third.total_suppressed(rule), 4);
+        assert_eq!(third.total_suppressed(Rule::Correlation), 0);
+
+        let mut cooled = manager(
+            rule,
+            Limits {
+                threshold: 1,
+                cooldown: Cooldown::Occurrences(2),
+            },
+        );
+        assert_eq!(
+            run(&mut cooled, rule, 7),
+            [Some(0), None, None, Some(2), None, None, Some(2)]
+        );
+
+        // The occurrence numbers rolling over change nothing.
+        let counter = &mut cooled.counters[rule as usize];
+        counter.occurrences = u32::MAX - 1;
+        counter.fired_at = u32::MAX - 1;
+        assert_eq!(run(&mut cooled, rule, 4), [None, None, Some(2), None]);
+        assert_eq!(cooled.counters[rule as usize].fired_at, 1);
+    }
+
+    #[test]
+    fn cooldowns_in_seconds() {
+        let rule = Rule::SecureBootKeyRead;
+        let mut manager = manager(
+            rule,
+            Limits {
+                threshold: 1,
+                cooldown: Cooldown::Seconds(2),
+            },
+        );
+        // 1000 ticks per second, starting just before the counter wraps.
+        let start = u64::MAX - 500;
+        let at = |ticks: u64| Some((start.wrapping_add(ticks), 1000));
+        assert_eq!(manager.admit(rule, at(0)), Some(0));
+        assert_eq!(manager.admit(rule, at(1000)), None);
+        assert_eq!(manager.admit(rule, at(1999)), None);
+        assert_eq!(manager.admit(rule, at(2000)), Some(2));
+        // Without a clock, nothing is held back.
+        assert_eq!(manager.admit(rule, None), Some(0));
+    }
+
+    #[test]
+    fn limits_from_the_build_list() {
+        let limits =
+            parse_limits("key-read=1/300s; mor-access=2/10 ;size-change=3;bogus=1;shadow=x");
+        assert_eq!(
+            limits[Rule::SecureBootKeyRead as usize],
+            Limits {
+                threshold: 1,
+                cooldown: Cooldown::Seconds(300)
+            }
+        );
+        assert_eq!(
+            limits[Rule::MorAccess as usize],
+            Limits {
+                threshold: 2,
+                cooldown: Cooldown::Occurrences(10)
+            }
+        );
+        assert_eq!(
+            limits[Rule::SizeChange as usize],
+            Limits {
+                threshold: 3,
+                cooldown: Cooldown::None
+            }
+        );
+        assert_eq!(limits[Rule::ShadowDivergence as usize], EVERY);
+        assert!(Rule::ALL
+            .iter()
+            .enumerate()
+            .all(|(index, rule)| *rule as usize == index
+                && Rule::from_str(rule.name()) == Some(*rule)));
+    }
+}
@@ -0,0 +1,116 @@
+// uefi-var-monitor-rust/src/alerts.rs
+//
+// Alert manager. Every alert is raised under the ID of the rule producing it
+// (see rules::emit), and each rule has limits deciding whether an occurrence
+// is shown or suppressed: a threshold and a cooldown, in occurrences or
+// seconds (see alert.rs in the library, which decides). A fired alert
+// carries the number of occurrences suppressed before it:
+//
+//   ALERT: [info] GetVariable of Secure Boot key PK: 0x0 (12 suppressed)
+//
+// and the suppressed counts of each rule are logged at ReadyToBoot and kept
+// in the boot report (see report.rs). Seconds are measured on the cycle
+// counter calibrated by rate.rs.
+//
+// The limits start from DEFAULT_LIMITS and can be overridden at build time
+// with UVM_ALERT_LIMITS, a ';'-separated list of rule=threshold[/cooldown]
+// entries:
+//
+//   UVM_ALERT_LIMITS="key-read=1/300s;mor-access=1/10;size-change=3"
+//
+// The manager is only ever try-borrowed. An alert raised while it is in use
+// is shown, not suppressed.
+
+use crate::rate;
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use core::fmt;
+
+const TAG: Tag = tag::HOOK;
+
+pub use uefi_var_monitor::alert::{LimitTable, Manager, Rule, RULE_COUNT};
+
+static MANAGER: AtomicRefCell<Manager> = AtomicRefCell::new(Manager::new());
+
+/**
+ * @brief Sets the limits of every rule.
+ */
+pub fn set_limits(limits: &LimitTable) {
+    if let Ok(mut manager) = MANAGER.try_borrow_mut() {
+        manager.set_limits(limits);
+    }
+}
+
+/**
+ * @brief Zeroes the occurrence and suppressed counts of every rule, keeping
+ *        the limits.
+ */
+pub fn reset_counters() {
+    if let Ok(mut manager) = MANAGER.try_borrow_mut() {
+        manager.reset_counters();
+    }
+}
+
+/**
+ * @brief Counts an occurrence of `rule`. Returns the number of occurrences
+ *        suppressed before it if it is to be shown, None if not.
+ */
+pub fn admit(rule: Rule) -> Option<u32> {
+    match MANAGER.try_borrow_mut() {
+        Ok(mut manager) => manager.admit(rule, rate::clock()),
+        Err(_) => Some(0),
+    }
+}
+
+/**
+ * @brief Copies the total suppressed count of each rule into `counts`,
+ *        indexed by rule ID.
+ */
+pub fn suppressed(counts: &mut [u32]) {
+    if let Ok(manager) = MANAGER.try_borrow_mut() {
+        for (rule, count) in Rule::ALL.iter().zip(counts.iter_mut()) {
+            *count = manager.total_suppressed(*rule);
+        }
+    }
+}
+
+/**
+ * @brief Logs the rules that had occurrences suppressed, if any.
+ */
+pub fn log_summary() {
+    let mut counts = [0u32; RULE_COUNT];
+    suppressed(&mut counts);
+    if counts.iter().any(|count| *count != 0) {
+        info!(TAG, "Alerts suppressed:{}", Summary(&counts));
+    }
+}
+
+struct Summary<'a>(&'a [u32; RULE_COUNT]);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (rule, count) in Rule::ALL.iter().zip(self.0.iter()) {
+            if *count != 0 {
+                write!(f, " {}={}", rule.name(), count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The suppressed count appended to a fired alert, nothing if zero.
+pub struct Suppressed(pub u32);
+
+impl fmt::Display for Suppressed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            0 => Ok(()),
+            count => write!(f, " ({} suppressed)", count),
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *MANAGER.borrow_mut() = Manager::new();
+}
@@ -0,0 +1,109 @@
+// uefi-var-monitor-rust/src/arch/mod.rs
+//
+// The few primitives that differ between architectures, behind one trait so
+// that the rest of the driver has no cfg(target_arch) of its own. Current is
+// the implementation for the target being built; architectures without one
+// get Unsupported, which reports everything as unavailable.
+
+use r_efi::efi;
+
+#[cfg(target_arch = "riscv64")]
+mod riscv64;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "riscv64")))]
+mod unsupported;
+#[cfg(target_arch = "x86_64")]
+mod x86_64;
+
+#[cfg(target_arch = "riscv64")]
+pub use self::riscv64::Riscv64 as Current;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "riscv64")))]
+pub use self::unsupported::Unsupported as Current;
+#[cfg(target_arch = "x86_64")]
+pub use self::x86_64::X86_64 as Current;
+
+// Where the debug serial port is, as the load banner gives it (see
+// banner.rs). Each architecture builds one of them.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialPort {
+    None,
+    IoPort(u16),
+    // The physical address, as before SetVirtualAddressMap.
+    Mmio(usize),
+}
+
+// Serial output and the panic handler are not used by host tests.
+pub trait Arch {
+    // The port write_bytes writes to. Its line settings are left as the
+    // firmware made them.
+    const SERIAL_PORT: SerialPort;
+
+    /**
+     * @brief Writes bytes to the debug serial port, each once the transmitter
+     *        can take it. Returns UNSUPPORTED if there is no port, or
+     *        DEVICE_ERROR if it stayed busy and the rest was dropped.
+     */
+    #[cfg_attr(any(test, not(feature = "log-serial")), allow(dead_code))]
+    fn write_bytes(bytes: &[u8]) -> efi::Status;
+
+    /**
+     * @brief Converts the addresses the primitives use at
+     *        SetVirtualAddressMap. Must be called last, as the old ones are
+     *        used until then.
+     */
+    fn relocate(convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status);
+
+    /**
+     * @brief Returns a free-running counter for time stamps, if any.
+     */
+    fn read_cycle_counter() -> Option<u64>;
+
+    /**
+     * @brief Returns the ID of the CPU running the caller, if it can be read
+     *        without firmware help: the x2APIC or APIC ID on x86_64.
+     */
+    #[cfg_attr(not(feature = "per-cpu"), allow(dead_code))]
+    fn cpu_id() -> Option<u32>;
+
+    /**
+     * @brief Returns whether interrupts are enabled on this CPU, if known.
+     */
+    #[allow(dead_code)]
+    fn interrupts_enabled() -> Option<bool>;
+
+    /**
+     * @brief Returns the return address of the function this is inlined
+     *        into, read through the frame pointer. Only meaningful when built
+     *        with frame pointers, which .cargo/config.toml forces.
+     */
+    fn return_address() -> Option<usize>;
+
+    /**
+     * @brief Disables interrupts and stops the CPU for good.
+     */
+    #[cfg_attr(
+        any(
+            test,
+            feature = "mock",
+            not(any(debug_assertions, feature = "allow-panic"))
+        ),
+        allow(dead_code)
+    )]
+    fn halt() -> !;
+}
+
+// These run in user mode on the host; the instructions are allowed there.
+// The in-firmware tests build this module too (see tests/firmware).
+#[cfg(all(test, target_arch = "x86_64", not(target_os = "uefi")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_counter_and_interrupt_state() {
+        let first = Current::read_cycle_counter().unwrap();
+        let second = Current::read_cycle_counter().unwrap();
+        assert!(second >= first);
+        assert_eq!(Current::interrupts_enabled(), Some(true));
+        assert!(Current::cpu_id().is_some());
+    }
+}
@@ -0,0 +1,133 @@
+// uefi-var-monitor-rust/src/arch/riscv64.rs
+//
+// riscv64: there is no port I/O; the console is a memory-mapped NS16550. Its
+// base defaults to the one of QEMU's virt machine and can be set in hex with
+// UVM_UART_BASE at build time. The address is converted at
+// SetVirtualAddressMap like the other runtime pointers, and output is dropped
+// if that fails. Time stamps come from the time CSR. UEFI runs in supervisor
+// mode, so the interrupt state is sstatus.SIE, and the hart ID in mhartid is
+// out of reach.
+
+use super::{Arch, SerialPort};
+use crate::tag::{self, Tag};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::BOOT;
+
+const UART_BASE: usize = match option_env!("UVM_UART_BASE") {
+    Some(base) => parse_hex(base),
+    None => 0x1000_0000,
+};
+static UART: AtomicPtr<u8> = AtomicPtr::new(UART_BASE as *mut u8);
+// Transmit holding register, line status register and its THR-empty bit.
+const UART_THR: usize = 0;
+const UART_LSR: usize = 5;
+const UART_LSR_THRE: u8 = 0x20;
+// How long to wait for the transmitter before dropping a byte.
+const UART_POLL_LIMIT: usize = 100_000;
+
+const SSTATUS_SIE: usize = 1 << 1;
+
+const fn parse_hex(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut value = 0usize;
+    let mut index = 0;
+    if bytes.len() > 2 && bytes[0] == b'0' && (bytes[1] == b'x' || bytes[1] == b'X') {
+        index = 2;
+    }
+    while index < bytes.len() {
+        let digit = match bytes[index] {
+            b'0'..=b'9' => bytes[index] - b'0',
+            b'a'..=b'f' => bytes[index] - b'a' + 10,
+            b'A'..=b'F' => bytes[index] - b'A' + 10,
+            b'_' => {
+                index += 1;
+                continue;
+            }
+            _ => panic!("UVM_UART_BASE is not a hexadecimal address"),
+        };
+        value = value * 16 + digit as usize;
+        index += 1;
+    }
+    value
+}
+
+pub struct Riscv64;
+
+impl Arch for Riscv64 {
+    const SERIAL_PORT: SerialPort = SerialPort::Mmio(UART_BASE);
+
+    fn write_bytes(bytes: &[u8]) -> efi::Status {
+        let base = UART.load(Ordering::Acquire);
+        if base.is_null() {
+            return efi::Status::UNSUPPORTED;
+        }
+        'bytes: for &byte in bytes {
+            for _ in 0..UART_POLL_LIMIT {
+                unsafe {
+                    if base.add(UART_LSR).read_volatile() & UART_LSR_THRE != 0 {
+                        base.add(UART_THR).write_volatile(byte);
+                        continue 'bytes;
+                    }
+                }
+                core::hint::spin_loop();
+            }
+            return efi::Status::DEVICE_ERROR;
+        }
+        efi::Status::SUCCESS
+    }
+
+    fn relocate(convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status) {
+        let mut address = UART.load(Ordering::Acquire) as *mut core::ffi::c_void;
+        let efi_status = convert(&mut address);
+        if efi_status.is_error() {
+            error!(
+                TAG,
+                "UART at {:#08x} could not be relocated, serial output disabled : {:#x}",
+                address as u64,
+                efi_status.as_usize()
+            );
+            address = core::ptr::null_mut();
+        }
+        UART.store(address as *mut u8, Ordering::Release);
+    }
+
+    fn read_cycle_counter() -> Option<u64> {
+        let time: u64;
+        unsafe { core::arch::asm!("rdtime {}", out(reg) time) };
+        Some(time)
+    }
+
+    fn cpu_id() -> Option<u32> {
+        None
+    }
+
+    fn interrupts_enabled() -> Option<bool> {
+        let sstatus: usize;
+        unsafe { core::arch::asm!("csrr {}, sstatus", out(reg) sstatus) };
+        Some(sstatus & SSTATUS_SIE != 0)
+    }
+
+    #[inline(always)]
+    fn return_address() -> Option<usize> {
+        // s0 points past the frame record; RA is saved just below it.
+        let frame: *const usize;
+        unsafe {
+            core::arch::asm!("mv {}, s0", out(reg) frame, options(nomem, nostack, preserves_flags))
+        };
+        if frame.is_null() {
+            return None;
+        }
+        Some(unsafe { frame.sub(1).read() })
+    }
+
+    fn halt() -> ! {
+        unsafe {
+            core::arch::asm!("csrc sstatus, {}", in(reg) SSTATUS_SIE);
+            loop {
+                core::arch::asm!("wfi");
+            }
+        }
+    }
+}
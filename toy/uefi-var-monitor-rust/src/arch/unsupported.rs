@@ -0,0 +1,41 @@
+// uefi-var-monitor-rust/src/arch/unsupported.rs
+//
+// Architectures without a port yet: no serial output, no time stamps, no
+// CPU ID.
+
+use super::{Arch, SerialPort};
+use r_efi::efi;
+
+pub struct Unsupported;
+
+impl Arch for Unsupported {
+    const SERIAL_PORT: SerialPort = SerialPort::None;
+
+    fn write_bytes(_bytes: &[u8]) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+
+    fn relocate(_convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status) {}
+
+    fn read_cycle_counter() -> Option<u64> {
+        None
+    }
+
+    fn cpu_id() -> Option<u32> {
+        None
+    }
+
+    fn interrupts_enabled() -> Option<bool> {
+        None
+    }
+
+    fn return_address() -> Option<usize> {
+        None
+    }
+
+    fn halt() -> ! {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+}
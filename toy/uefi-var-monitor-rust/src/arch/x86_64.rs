@@ -0,0 +1,86 @@
+// uefi-var-monitor-rust/src/arch/x86_64.rs
+//
+// x86_64: the serial port is COM1 through port I/O, each byte written once
+// the line status shows the transmitter empty, time stamps come from
+// the TSC, and the CPU ID is the x2APIC ID of CPUID leaf 0Bh, or the initial
+// APIC ID of leaf 1 where leaf 0Bh is missing.
+
+use super::{Arch, SerialPort};
+use ::x86_64::instructions::interrupts;
+use ::x86_64::instructions::port::{PortReadOnly, PortWriteOnly};
+use r_efi::efi;
+
+// We use COM1 as it is the standard first serial port.
+const COM1: u16 = 0x3f8;
+// Line status register, and its transmitter holding register empty bit.
+#[cfg_attr(any(test, not(feature = "log-serial")), allow(dead_code))]
+const COM1_LSR: u16 = COM1 + 5;
+#[cfg_attr(any(test, not(feature = "log-serial")), allow(dead_code))]
+const LSR_THRE: u8 = 0x20;
+// Polls of the line status before a byte is given up on.
+#[cfg_attr(any(test, not(feature = "log-serial")), allow(dead_code))]
+const POLL_LIMIT: usize = 100_000;
+
+pub struct X86_64;
+
+impl Arch for X86_64 {
+    const SERIAL_PORT: SerialPort = SerialPort::IoPort(COM1);
+
+    fn write_bytes(bytes: &[u8]) -> efi::Status {
+        let mut data = PortWriteOnly::<u8>::new(COM1);
+        let mut line_status = PortReadOnly::<u8>::new(COM1_LSR);
+        'bytes: for &byte in bytes {
+            for _ in 0..POLL_LIMIT {
+                if unsafe { line_status.read() } & LSR_THRE != 0 {
+                    unsafe { data.write(byte) };
+                    continue 'bytes;
+                }
+                core::hint::spin_loop();
+            }
+            return efi::Status::DEVICE_ERROR;
+        }
+        efi::Status::SUCCESS
+    }
+
+    fn relocate(_convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status) {}
+
+    fn read_cycle_counter() -> Option<u64> {
+        Some(unsafe { core::arch::x86_64::_rdtsc() })
+    }
+
+    fn cpu_id() -> Option<u32> {
+        use core::arch::x86_64::{__cpuid, __cpuid_count};
+        if __cpuid(0).eax >= 0xb {
+            let topology = __cpuid_count(0xb, 0);
+            // EBX is zero if the leaf is not implemented.
+            if topology.ebx != 0 {
+                return Some(topology.edx);
+            }
+        }
+        Some(__cpuid(1).ebx >> 24)
+    }
+
+    fn interrupts_enabled() -> Option<bool> {
+        Some(interrupts::are_enabled())
+    }
+
+    #[inline(always)]
+    fn return_address() -> Option<usize> {
+        // The saved RBP of the caller is at [RBP], its return address above.
+        let frame: *const usize;
+        unsafe {
+            core::arch::asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack, preserves_flags))
+        };
+        if frame.is_null() {
+            return None;
+        }
+        Some(unsafe { frame.add(1).read() })
+    }
+
+    fn halt() -> ! {
+        interrupts::disable();
+        loop {
+            ::x86_64::instructions::hlt();
+        }
+    }
+}
@@ -0,0 +1,189 @@
+// uefi-var-monitor-rust/src/banner.rs
+//
+// The banner the driver logs at load, so that every capture starts by saying
+// what wrote it:
+//
+//   [boot] Driver being loaded: UefiVarMonitor 0.1.1, custom profile, release, no build ID
+//   [boot]   Hooks: GetVariable, SetVariable
+//   [boot]   Sinks: serial, ring
+//   [boot]   Serial: I/O port 0x3f8, line set up by the firmware, inline
+//   [cfg] Configuration restored from UvmConfig, version 1.3
+//   [boot]   Configuration: build defaults, UvmConfig
+//
+// All but the last line are known at build time: CARGO_PKG_VERSION, the
+// profile (see profile.rs), UVM_BUILD_ID if set, e.g. to the commit built,
+// and the features picked. They are written first thing at load. The last
+// one is written once the configuration is restored (see config_store.rs,
+// persist.rs and options.rs), after what those wrote, and before the hooks
+// are installed. Every line is written whatever the level.
+//
+// The same, as a BuildInfo (see interface/src/build.rs), is kept from then on
+// in the ring buffer header, and in the boot report (see report.rs). The
+// statistics protocol hands it out, for uvmctl dump to put in the header it
+// writes (see stats.rs).
+
+use crate::arch::{self, Arch, SerialPort};
+use crate::level::Level;
+use crate::profile;
+use crate::tag::{self, Tag};
+use core::sync::atomic::{AtomicU32, Ordering};
+use uvm_interface::build::{
+    BuildInfo, Identity, Names, Serial, Sources, BUILD_FLAG_DEBUG, BUILD_FLAG_PER_CPU,
+    BUILD_FLAG_SERIAL_QUEUE, HOOK_GET_NEXT_VARIABLE_NAME, HOOK_GET_VARIABLE, HOOK_NAMES,
+    HOOK_SET_VARIABLE, SERIAL_IO_PORT, SERIAL_MMIO, SERIAL_NONE, SINK_GOP, SINK_NAMES, SINK_NET,
+    SINK_RING, SINK_SERIAL, SINK_TPM,
+};
+
+const TAG: Tag = tag::BOOT;
+
+const BUILD_ID: &str = match option_env!("UVM_BUILD_ID") {
+    Some(id) => id,
+    None => "",
+};
+
+const FLAGS: u32 = bit(cfg!(debug_assertions), BUILD_FLAG_DEBUG)
+    | bit(cfg!(feature = "serial-queue"), BUILD_FLAG_SERIAL_QUEUE)
+    | bit(cfg!(feature = "per-cpu"), BUILD_FLAG_PER_CPU);
+
+// GetNextVariableName is only hooked to hide variables.
+const HOOKS: u32 = HOOK_GET_VARIABLE
+    | HOOK_SET_VARIABLE
+    | bit(cfg!(feature = "enforce"), HOOK_GET_NEXT_VARIABLE_NAME);
+
+const SINKS: u32 = bit(cfg!(feature = "log-serial"), SINK_SERIAL)
+    | bit(cfg!(feature = "log-ring"), SINK_RING)
+    | bit(cfg!(feature = "log-net"), SINK_NET)
+    | bit(cfg!(feature = "gop-alert"), SINK_GOP)
+    | bit(cfg!(feature = "tpm-measure"), SINK_TPM);
+
+// The CONFIG_FROM_* bits of what the configuration was loaded from.
+static SOURCES: AtomicU32 = AtomicU32::new(0);
+
+const fn bit(built: bool, bit: u32) -> u32 {
+    if built {
+        bit
+    } else {
+        0
+    }
+}
+
+/**
+ * @brief Returns what the driver was built as, and what its configuration
+ *        was loaded from.
+ */
+pub fn info() -> BuildInfo {
+    let (serial, serial_base) = match arch::Current::SERIAL_PORT {
+        _ if !cfg!(feature = "log-serial") => (SERIAL_NONE, 0),
+        SerialPort::None => (SERIAL_NONE, 0),
+        SerialPort::IoPort(port) => (SERIAL_IO_PORT, u64::from(port)),
+        SerialPort::Mmio(address) => (SERIAL_MMIO, address as u64),
+    };
+    BuildInfo {
+        version: BuildInfo::text(env!("CARGO_PKG_VERSION")),
+        profile: BuildInfo::text(profile::NAME),
+        build_id: BuildInfo::text(BUILD_ID),
+        flags: FLAGS,
+        hooks: HOOKS,
+        sinks: SINKS,
+        config: SOURCES.load(Ordering::Acquire),
+        serial,
+        reserved: 0,
+        serial_base,
+    }
+}
+
+/**
+ * @brief Writes the lines of the banner known at build time. Called first
+ *        thing at load.
+ */
+pub fn announce() {
+    let build = info();
+    log_at!(
+        Level::Critical,
+        TAG,
+        "Driver being loaded: {}",
+        Identity(&build)
+    );
+    log_at!(
+        Level::Critical,
+        TAG,
+        "  Hooks: {}",
+        Names(build.hooks, &HOOK_NAMES)
+    );
+    log_at!(
+        Level::Critical,
+        TAG,
+        "  Sinks: {}",
+        Names(build.sinks, &SINK_NAMES)
+    );
+    log_at!(Level::Critical, TAG, "  Serial: {}", Serial(&build));
+}
+
+/**
+ * @brief Records `sources`, the CONFIG_FROM_* bits of what the configuration
+ *        was loaded from, writes the last line of the banner and keeps the
+ *        build in the ring buffer header.
+ */
+pub fn configured(sources: u32) {
+    SOURCES.store(sources, Ordering::Release);
+    let build = info();
+    #[cfg(feature = "log-ring")]
+    crate::ring::set_build(&build);
+    log_at!(
+        Level::Critical,
+        TAG,
+        "  Configuration: {}",
+        Sources(build.config)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use uvm_interface::build::CONFIG_FROM_LOAD_OPTIONS;
+
+    #[test]
+    fn banner_says_what_was_built_and_configured() {
+        let _lock = crate::mock::lock();
+        crate::level::set_level(Level::Critical);
+        crate::serial::start_capture();
+        announce();
+        configured(CONFIG_FROM_LOAD_OPTIONS);
+        let records = crate::serial::without_locations(&crate::serial::take_capture());
+        let lines: Vec<&str> = records.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            std::format!(
+                "[boot] Driver being loaded: UefiVarMonitor {}, {} profile, {}, {}",
+                env!("CARGO_PKG_VERSION"),
+                profile::NAME,
+                if cfg!(debug_assertions) {
+                    "debug"
+                } else {
+                    "release"
+                },
+                match BUILD_ID {
+                    "" => std::string::String::from("no build ID"),
+                    id => std::format!("build ID {}", id),
+                }
+            )
+        );
+        assert!(lines[1].starts_with("[boot]   Hooks: GetVariable, SetVariable"));
+        assert!(lines[3].starts_with("[boot]   Serial: "));
+        assert_eq!(
+            lines[4],
+            "[boot]   Configuration: build defaults, load options"
+        );
+
+        let build = info();
+        assert_eq!(build.version(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(build.config, CONFIG_FROM_LOAD_OPTIONS);
+        assert_eq!(build.hooks & HOOK_GET_VARIABLE, HOOK_GET_VARIABLE);
+        #[cfg(feature = "log-ring")]
+        assert_eq!(crate::ring::header().unwrap().build, build);
+        SOURCES.store(0, Ordering::Release);
+        crate::level::reset();
+    }
+}
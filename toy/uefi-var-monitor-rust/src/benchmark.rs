@@ -0,0 +1,97 @@
+// uefi-var-monitor-rust/src/benchmark.rs
+//
+// What the hook costs a caller, measured in place and on demand, through the
+// monitor's protocol next to the self-test (see protocol.rs and uvmctl
+// bench): a number of GetVariable calls of PlatformLang made back to back
+// through the runtime services table, as any caller makes them, then as many
+// made straight to the service the hook forwards to, each run timed with the
+// cycle counter. The difference is logged, in nanoseconds once the counter is
+// calibrated (see rate.rs).
+//
+// The hook runs as configured: the calls through it are counted, traced if
+// the level and filter say so, and seen by the rate alarm like any other,
+// which is what a caller pays for. overhead.rs measures the same cost from
+// within the hook on every call; benches/ times the pieces it is made of on
+// the host.
+//
+// Timing under emulation is noisy, from run to run and between the two runs
+// of one benchmark, so nothing here judges the result: it is reported.
+
+use crate::arch::{self, Arch};
+use crate::self_test::PROBE_NAME;
+use crate::tag::{self, Tag};
+use crate::{classify, rate, GetVariableType};
+use core::sync::atomic::Ordering;
+use r_efi::efi;
+use uvm_interface::protocol::{BenchmarkResult, MAX_BENCHMARK_CALLS};
+
+const TAG: Tag = tag::HOOK;
+
+/**
+ * @brief Times `calls` GetVariable calls through the hook and as many around
+ *        it. Fails with NOT_READY before the hooks are installed.
+ */
+pub fn run(calls: u32) -> Result<BenchmarkResult, efi::Status> {
+    if calls == 0 || calls > MAX_BENCHMARK_CALLS {
+        return Err(efi::Status::INVALID_PARAMETER);
+    }
+    let runtime_services = crate::RUNTIME_SERVICES.load(Ordering::Acquire);
+    let bypassed = match crate::GET_VARIABLE.get() {
+        Some(get_variable) if !runtime_services.is_null() => get_variable,
+        _ => return Err(efi::Status::NOT_READY),
+    };
+    let hooked = unsafe { (*runtime_services).get_variable };
+    let (hooked_ticks, bypassed_ticks) = match (time(calls, hooked), time(calls, bypassed)) {
+        (Some(hooked_ticks), Some(bypassed_ticks)) => (hooked_ticks, bypassed_ticks),
+        _ => return Err(efi::Status::UNSUPPORTED),
+    };
+    let result = BenchmarkResult {
+        calls,
+        reserved: 0,
+        hooked_ticks,
+        bypassed_ticks,
+        ticks_per_second: rate::ticks_per_second(),
+    };
+    let (hooked, bypassed) = result.per_call();
+    match (result.nanoseconds(hooked), result.nanoseconds(bypassed)) {
+        (Some(hooked), Some(bypassed)) => info!(
+            TAG,
+            "Benchmark: {} GetVariable calls, {} ns each hooked, {} ns bypassed, {} ns added",
+            calls,
+            hooked,
+            bypassed,
+            hooked.saturating_sub(bypassed)
+        ),
+        _ => info!(
+            TAG,
+            "Benchmark: {} GetVariable calls, {} ticks each hooked, {} ticks bypassed, {} ticks added",
+            calls,
+            hooked,
+            bypassed,
+            hooked.saturating_sub(bypassed)
+        ),
+    }
+    Ok(result)
+}
+
+/**
+ * @brief Returns the ticks `calls` reads of PlatformLang through
+ *        `get_variable` took, None without a cycle counter.
+ */
+fn time(calls: u32, get_variable: GetVariableType) -> Option<u64> {
+    let mut name = PROBE_NAME;
+    let mut guid = classify::GLOBAL_VARIABLE_GUID;
+    let mut data = [0u8; 16];
+    let started = arch::Current::read_cycle_counter()?;
+    for _ in 0..calls {
+        let mut size = data.len();
+        let _ = get_variable(
+            name.as_mut_ptr(),
+            &mut guid,
+            core::ptr::null_mut(),
+            &mut size,
+            data.as_mut_ptr() as *mut core::ffi::c_void,
+        );
+    }
+    arch::Current::read_cycle_counter().map(|now| now.wrapping_sub(started))
+}
@@ -0,0 +1,100 @@
+// uefi-var-monitor-rust/src/boot_option.rs
+//
+// The boot manager variables a bootkit rewrites from the OS: BootOrder and
+// BootNext, which hold UINT16 option numbers, and the Boot#### load options
+// they refer to. Any write to one of them after ExitBootServices is alerted
+// on, with the previous value from the last-value cache next to the new one.
+//
+// Only what fits in an alert line is decoded, by load_option.rs in the
+// library: the option numbers, or the description and the first device path
+// node of a load option.
+
+use crate::classify::{self, VariableClass};
+use crate::last_value::{self, MAX_VALUE_SIZE};
+use crate::load_option::{is_load_option, LoadOption, OptionNumbers};
+use crate::safety::{self, Inspection};
+use core::fmt;
+use r_efi::efi;
+
+/**
+ * @brief Returns the caller's data buffer for inspection, limited to what the
+ *        cache would keep (see safety::inspect).
+ */
+pub fn inspect<'a>(
+    name: &str,
+    guid: &efi::Guid,
+    data: *const core::ffi::c_void,
+    data_size: usize,
+) -> Inspection<'a> {
+    safety::inspect(name, guid, data, data_size, MAX_VALUE_SIZE)
+}
+
+/**
+ * @brief Returns how a value passed to or returned by an access is shown.
+ */
+pub fn shown<'a>(
+    name: &'a str,
+    guid: &efi::Guid,
+    data: *const core::ffi::c_void,
+    data_size: usize,
+) -> Shown<'a> {
+    match inspect(name, guid, data, data_size) {
+        Inspection::Data(data) => Shown::Value { name, data },
+        Inspection::NotInspected => Shown::NotInspected,
+        Inspection::Redacted => Shown::Redacted,
+    }
+}
+
+/**
+ * @brief Keeps the value of a boot manager variable in the last-value cache.
+ *        Other variables are not cached.
+ */
+pub fn observe(name: &str, guid: &efi::Guid, data: *const core::ffi::c_void, data_size: usize) {
+    if classify::classify(name, guid) != VariableClass::BootManager {
+        return;
+    }
+    if let Inspection::Data(bytes) = inspect(name, guid, data, data_size) {
+        last_value::record(name, guid, bytes, data_size);
+    }
+}
+
+// A boot manager variable value for an alert line.
+pub enum Shown<'a> {
+    Value { name: &'a str, data: &'a [u8] },
+    Deleted,
+    // The data buffer may not be read in this phase.
+    NotInspected,
+    Redacted,
+    Unknown,
+}
+
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Shown::Value { name, data } if is_load_option(name) => LoadOption(data).fmt(f),
+            Shown::Value { data, .. } => OptionNumbers(data).fmt(f),
+            Shown::Deleted => f.write_str("deleted"),
+            Shown::NotInspected => f.write_str("not inspected"),
+            Shown::Redacted => f.write_str("<redacted>"),
+            Shown::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    fn shown(name: &str, data: &[u8]) -> std::string::String {
+        Shown::Value { name, data }.to_string()
+    }
+
+    #[test]
+    fn values_are_shown_by_variable() {
+        assert_eq!(shown("BootOrder", &[1, 0, 0x10, 0]), "[0001,0010]");
+        assert_eq!(shown("Boot0000", &[1, 0]), "<malformed load option>");
+        assert_eq!(Shown::Deleted.to_string(), "deleted");
+        assert_eq!(Shown::Redacted.to_string(), "<redacted>");
+    }
+}
@@ -0,0 +1,315 @@
+// uefi-var-monitor-rust/src/budget.rs
+//
+// Latency budget: when the hooks cost the platform more than it can spare,
+// from a slow UART or a backed-up queue, detail is dropped rather than time.
+// The overhead of each GetVariable call (see overhead.rs) is averaged over
+// windows of BUDGET_WINDOW calls and compared with the budget, in
+// microseconds per call (UVM_LATENCY_BUDGET, see settings.rs in the
+// library). The hooks then work at one of three steps:
+//
+//   full            everything
+//   reduced         no access records (G: and S:) and no decoding of load
+//                   options for alert lines; every check still runs and
+//                   alerts
+//   counters-only   as profile-minimal: calls are counted by outcome and
+//                   vendor GUID, and nothing else is done, checks included
+//
+// A window above the budget steps down once. Stepping back up takes
+// calm_windows windows in a row below half the budget, so that a load
+// hovering at the budget does not flip the step every window. The overhead
+// at a lower step is that of cheaper work and will look calm; if a step up
+// is followed by a step down in the very next window, the calm windows
+// needed double, up to MAX_CALM_WINDOWS. Each transition is logged once.
+//
+// The guard never touches the level (see level.rs): the level says which
+// records are wanted, the step how much work an access may take. Setting the
+// level, however it is set, is taken as the wish for that detail now: the
+// guard goes back to full detail and starts over with an empty window.
+//
+// A budget of 0 disables the guard, and so does a cycle counter that was not
+// calibrated. Windows are summed with atomics and closed by the call that
+// fills them; a call on another CPU at that moment may count in either
+// window. The guard itself is only ever try-borrowed, and a window closed
+// while it is busy is dropped.
+
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+pub use uefi_var_monitor::settings::DEFAULT_LATENCY_BUDGET;
+
+const TAG: Tag = tag::HOOK;
+
+pub const BUDGET_WINDOW: u32 = 64;
+const CALM_WINDOWS: u32 = 4;
+const MAX_CALM_WINDOWS: u32 = 64;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    Full = 0,
+    Reduced = 1,
+    CountersOnly = 2,
+}
+
+impl Step {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Step::Full,
+            1 => Step::Reduced,
+            _ => Step::CountersOnly,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Step::Full => "full detail",
+            Step::Reduced => "reduced detail",
+            Step::CountersOnly => "counters only",
+        }
+    }
+}
+
+/**
+ * @brief The step and its hysteresis, fed one window at a time.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Guard {
+    step: Step,
+    // Windows in a row below half the budget.
+    calm: u32,
+    // Calm windows needed to step up.
+    calm_windows: u32,
+    // Whether the last window stepped up.
+    stepped_up: bool,
+}
+
+impl Guard {
+    pub const fn new() -> Self {
+        Guard {
+            step: Step::Full,
+            calm: 0,
+            calm_windows: CALM_WINDOWS,
+            stepped_up: false,
+        }
+    }
+
+    /**
+     * @brief Takes a window of calls that cost `average` each, against
+     *        `budget`. Returns the new step if it changed.
+     */
+    pub fn window(&mut self, average: u64, budget: u64) -> Option<Step> {
+        let stepped_up = core::mem::replace(&mut self.stepped_up, false);
+        if average > budget {
+            self.calm = 0;
+            if stepped_up {
+                self.calm_windows = core::cmp::min(self.calm_windows * 2, MAX_CALM_WINDOWS);
+            }
+            let lower = match self.step {
+                Step::Full => Step::Reduced,
+                _ => Step::CountersOnly,
+            };
+            return self.set(lower);
+        }
+        if average >= budget / 2 {
+            self.calm = 0;
+            return None;
+        }
+        self.calm = self.calm.saturating_add(1);
+        if self.calm < self.calm_windows {
+            return None;
+        }
+        self.calm = 0;
+        let higher = match self.step {
+            Step::CountersOnly => Step::Reduced,
+            _ => Step::Full,
+        };
+        self.stepped_up = higher != self.step;
+        self.set(higher)
+    }
+
+    fn set(&mut self, step: Step) -> Option<Step> {
+        if step == self.step {
+            return None;
+        }
+        self.step = step;
+        Some(step)
+    }
+}
+
+static BUDGET: AtomicU32 = AtomicU32::new(DEFAULT_LATENCY_BUDGET);
+static STEP: AtomicU8 = AtomicU8::new(Step::Full as u8);
+static WINDOW_CALLS: AtomicU32 = AtomicU32::new(0);
+static WINDOW_TICKS: AtomicU64 = AtomicU64::new(0);
+static GUARD: AtomicRefCell<Guard> = AtomicRefCell::new(Guard::new());
+
+/**
+ * @brief Sets the budget, in microseconds per call; 0 disables the guard.
+ */
+pub fn set_budget(microseconds: u32) {
+    BUDGET.store(microseconds, Ordering::Release);
+    if microseconds == 0 {
+        restart();
+    }
+}
+
+/**
+ * @brief Returns the step the hooks work at.
+ */
+pub fn step() -> Step {
+    Step::from_u8(STEP.load(Ordering::Relaxed))
+}
+
+/**
+ * @brief Takes a GetVariable call that cost the hook `ticks`, and closes the
+ *        window if it fills it.
+ */
+#[cfg_attr(feature = "profile-minimal", allow(dead_code))]
+pub fn observe(ticks: u64) {
+    let budget = u64::from(BUDGET.load(Ordering::Relaxed));
+    let ticks_per_second = crate::rate::ticks_per_second();
+    if budget == 0 || ticks_per_second == 0 {
+        return;
+    }
+    let total = WINDOW_TICKS.fetch_add(ticks, Ordering::Relaxed) + ticks;
+    if WINDOW_CALLS.fetch_add(1, Ordering::Relaxed) + 1 != BUDGET_WINDOW {
+        return;
+    }
+    WINDOW_CALLS.fetch_sub(BUDGET_WINDOW, Ordering::Relaxed);
+    WINDOW_TICKS.fetch_sub(total, Ordering::Relaxed);
+
+    let budget_ticks = budget.saturating_mul(ticks_per_second) / 1_000_000;
+    let average = total / u64::from(BUDGET_WINDOW);
+    let changed = match GUARD.try_borrow_mut() {
+        Ok(mut guard) => guard.window(average, budget_ticks),
+        Err(_) => return,
+    };
+    if let Some(step) = changed {
+        let previous = Step::from_u8(STEP.swap(step as u8, Ordering::Relaxed));
+        warn!(
+            TAG,
+            "Latency budget: {} -> {}, {} ticks per call against {}",
+            previous.name(),
+            step.name(),
+            average,
+            budget_ticks
+        );
+    }
+}
+
+/**
+ * @brief Goes back to full detail with an empty window, on a level change or
+ *        with the guard disabled. Logs the transition if the step was lower.
+ */
+pub fn restart() {
+    if let Ok(mut guard) = GUARD.try_borrow_mut() {
+        *guard = Guard::new();
+    }
+    WINDOW_CALLS.store(0, Ordering::Relaxed);
+    WINDOW_TICKS.store(0, Ordering::Relaxed);
+    let previous = Step::from_u8(STEP.swap(Step::Full as u8, Ordering::Relaxed));
+    if previous != Step::Full {
+        warn!(
+            TAG,
+            "Latency budget: {} -> {}, restarted",
+            previous.name(),
+            Step::Full.name()
+        );
+    }
+}
+
+#[cfg(all(test, not(feature = "profile-minimal")))]
+pub fn set_step(step: Step) {
+    STEP.store(step as u8, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+
+    const BUDGET: u64 = 1000;
+
+    #[test]
+    fn steps_down_at_once_and_up_after_calm_windows() {
+        let mut guard = Guard::new();
+        assert_eq!(guard.window(BUDGET, BUDGET), None);
+        assert_eq!(guard.window(BUDGET + 1, BUDGET), Some(Step::Reduced));
+        assert_eq!(guard.window(5000, BUDGET), Some(Step::CountersOnly));
+        assert_eq!(guard.window(5000, BUDGET), None);
+
+        // Three calm windows, then one at 60% of the budget: no step.
+        for _ in 0..3 {
+            assert_eq!(guard.window(100, BUDGET), None);
+        }
+        assert_eq!(guard.window(600, BUDGET), None);
+        for _ in 0..3 {
+            assert_eq!(guard.window(100, BUDGET), None);
+        }
+        assert_eq!(guard.window(100, BUDGET), Some(Step::Reduced));
+        for _ in 0..3 {
+            assert_eq!(guard.window(100, BUDGET), None);
+        }
+        assert_eq!(guard.window(100, BUDGET), Some(Step::Full));
+        assert_eq!(guard.window(100, BUDGET), None);
+    }
+
+    #[test]
+    fn relapses_double_the_calm_windows() {
+        let mut guard = Guard::new();
+        assert_eq!(guard.window(2000, BUDGET), Some(Step::Reduced));
+        let mut needed = CALM_WINDOWS;
+        for _ in 0..6 {
+            for _ in 1..needed {
+                assert_eq!(guard.window(0, BUDGET), None);
+            }
+            assert_eq!(guard.window(0, BUDGET), Some(Step::Full));
+            assert_eq!(guard.window(2000, BUDGET), Some(Step::Reduced));
+            needed = core::cmp::min(needed * 2, MAX_CALM_WINDOWS);
+            assert_eq!(guard.calm_windows, needed);
+        }
+        assert_eq!(needed, MAX_CALM_WINDOWS);
+
+        // A step down later than the window after a step up is no relapse.
+        for _ in 0..needed {
+            guard.window(0, BUDGET);
+        }
+        assert_eq!(guard.window(0, BUDGET), None);
+        assert_eq!(guard.window(2000, BUDGET), Some(Step::Reduced));
+        assert_eq!(guard.calm_windows, MAX_CALM_WINDOWS);
+    }
+
+    #[test]
+    fn windows_step_the_hooks_and_a_level_change_restores_them() {
+        let _lock = crate::mock::lock();
+        crate::level::reset();
+        let ticks_per_second = crate::rate::ticks_per_second();
+        if ticks_per_second == 0 {
+            return;
+        }
+        set_budget(DEFAULT_LATENCY_BUDGET);
+        restart();
+        // Twice the budget, in ticks.
+        let slow = u64::from(DEFAULT_LATENCY_BUDGET) * ticks_per_second / 500_000;
+
+        crate::serial::start_capture();
+        for _ in 0..BUDGET_WINDOW * 2 {
+            observe(slow);
+        }
+        assert_eq!(step(), Step::CountersOnly);
+        crate::level::set_level(Level::Info);
+        assert_eq!(step(), Step::Full);
+        let records = crate::serial::take_capture();
+        assert!(records.contains("Latency budget: full detail -> reduced detail"));
+        assert!(records.contains("Latency budget: reduced detail -> counters only"));
+        assert!(records.contains("Latency budget: counters only -> full detail, restarted"));
+
+        set_budget(0);
+        for _ in 0..BUDGET_WINDOW * 2 {
+            observe(slow);
+        }
+        assert_eq!(step(), Step::Full);
+        set_budget(DEFAULT_LATENCY_BUDGET);
+        crate::level::reset();
+    }
+}
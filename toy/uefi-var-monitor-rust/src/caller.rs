@@ -0,0 +1,132 @@
+// uefi-var-monitor-rust/src/caller.rs
+//
+// Reads of the memory a caller of the hooks passes by pointer: its variable
+// name, vendor GUID, DataSize and Attributes, and data buffer. Every such
+// read goes through here, so that the checks that can be made are made once:
+// a null pointer reads as None, a name is read no further than its NUL or a
+// given number of characters, and a buffer no further than the size given,
+// which must fit in an isize. Values are read unaligned, as the name buffer
+// of GetNextVariableName already was (see get_next_variable_name.rs): a
+// misaligned pointer then reads what it points at rather than being undefined
+// behaviour, and on x86_64 and aarch64 the load is the same instruction. A
+// name is handed out as a slice, which must be aligned: a misaligned one
+// reads as None.
+//
+// What cannot be checked is that a pointer that is not null points at memory
+// of the caller's for as long as it is read, hence unsafe: the hooks call
+// these on the pointers they were given, within the call that gave them, as
+// the firmware service they forward to reads them.
+//
+// The tests use only host memory and are meant to pass under Miri as well:
+//
+//   cargo +nightly miri test --lib caller
+
+use core::ffi::c_void;
+
+/**
+ * @brief Reads the value `pointer` points at, or returns None if it is null.
+ *
+ * # Safety
+ *
+ * `pointer` is null or points at a `T` that may be read.
+ */
+#[inline(always)]
+pub unsafe fn value<T: Copy>(pointer: *const T) -> Option<T> {
+    if pointer.is_null() {
+        return None;
+    }
+    Some(pointer.read_unaligned())
+}
+
+/**
+ * @brief Returns the characters of the NUL-terminated name at `pointer`, up
+ *        to `max` of them and without the NUL, or None if it is null or
+ *        misaligned. Reads no character past the NUL or the `max`th.
+ *
+ * # Safety
+ *
+ * `pointer` is null or points at a name of UCS-2 characters, terminated by a
+ * NUL or at least `max` long, that stays unchanged while the slice is held.
+ */
+#[inline(always)]
+pub unsafe fn name<'a>(pointer: *const u16, max: usize) -> Option<&'a [u16]> {
+    if pointer.is_null() || !pointer.is_aligned() {
+        return None;
+    }
+    let mut length = 0;
+    while length < max && pointer.add(length).read() != 0 {
+        length += 1;
+    }
+    Some(core::slice::from_raw_parts(pointer, length))
+}
+
+/**
+ * @brief Returns the `size` bytes at `pointer`, or None if it is null or
+ *        `size` does not fit in an isize.
+ *
+ * # Safety
+ *
+ * `pointer` is null or points at `size` bytes that may be read and stay
+ * unchanged while the slice is held.
+ */
+#[inline(always)]
+pub unsafe fn bytes<'a>(pointer: *const c_void, size: usize) -> Option<&'a [u8]> {
+    if pointer.is_null() || size > isize::MAX as usize {
+        return None;
+    }
+    Some(core::slice::from_raw_parts(pointer as *const u8, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use r_efi::efi;
+
+    #[test]
+    fn values_are_read_unless_null() {
+        let size = 0x1234usize;
+        assert_eq!(unsafe { value(&size) }, Some(0x1234));
+        assert_eq!(unsafe { value(core::ptr::null::<u32>()) }, None);
+
+        // A GUID and an Attributes value one byte off their alignment.
+        let guid = efi::Guid::from_fields(1, 2, 3, 4, 5, &[6; 6]);
+        let mut buffer = [0u32; 5];
+        let misaligned = unsafe { (buffer.as_mut_ptr() as *mut u8).add(1) };
+        unsafe { core::ptr::copy_nonoverlapping(guid.as_bytes().as_ptr(), misaligned, 16) };
+        let read = unsafe { value(misaligned as *const efi::Guid) };
+        assert_eq!(read, Some(guid));
+        unsafe { core::ptr::copy_nonoverlapping(0x27u32.to_le_bytes().as_ptr(), misaligned, 4) };
+        assert_eq!(unsafe { value(misaligned as *const u32) }, Some(0x27));
+    }
+
+    #[test]
+    fn names_are_read_to_the_terminator_or_the_limit() {
+        let characters: Vec<u16> = "Boot0001\0Order".encode_utf16().collect();
+        let read = |max| unsafe { name(characters.as_ptr(), max) };
+        assert_eq!(read(64), Some(&characters[..8]));
+        assert_eq!(read(3), Some(&characters[..3]));
+        assert_eq!(read(0), Some(&[][..]));
+
+        // Unterminated, and read up to its end only: Miri fails the test if
+        // a character past it is read.
+        let unterminated = [u16::from(b'X'); 4];
+        assert_eq!(
+            unsafe { name(unterminated.as_ptr(), 4) },
+            Some(&unterminated[..])
+        );
+
+        assert_eq!(unsafe { name(core::ptr::null(), 64) }, None);
+        let misaligned = unsafe { (characters.as_ptr() as *const u8).add(1) };
+        assert_eq!(unsafe { name(misaligned as *const u16, 2) }, None);
+    }
+
+    #[test]
+    fn buffers_are_read_within_their_size() {
+        let data = [1u8, 2, 3, 4];
+        let pointer = data.as_ptr() as *const c_void;
+        assert_eq!(unsafe { bytes(pointer, 4) }, Some(&data[..]));
+        assert_eq!(unsafe { bytes(pointer, 0) }, Some(&[][..]));
+        assert_eq!(unsafe { bytes(core::ptr::null(), 4) }, None);
+        assert_eq!(unsafe { bytes(pointer, usize::MAX) }, None);
+    }
+}
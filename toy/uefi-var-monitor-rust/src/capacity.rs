@@ -0,0 +1,287 @@
+// uefi-var-monitor-rust/src/capacity.rs
+//
+// How full the variable store is. A store running out of space fails in ways
+// far from the cause, such as a boot option that is not saved or a capsule
+// that is not staged, so the space is measured with QueryVariableInfo at load
+// and again at ExitBootServices, and what the boot used is logged:
+//
+//   [integrity] NV store: 512KiB total, 37KiB consumed this boot, 139KiB remaining, 32KiB largest variable
+//
+// with "freed" instead of "consumed" where reclaim made room. Both
+// measurements go into the boot report (see report.rs).
+//
+// QueryVariableInfo is asked about the variables most writes create,
+// non-volatile with boot and runtime access. Some firmware answers
+// UNSUPPORTED, or INVALID_PARAMETER, for a combination it does not store
+// apart, so the sets of ATTRIBUTE_SETS are tried in turn; the one answered at
+// load is the only one asked at ExitBootServices, so that the two measure the
+// same thing.
+//
+// The calls go to the firmware's QueryVariableInfo, saved at load from the
+// runtime services table, under the GetVariable reentrancy guard like the
+// driver's other reads of its own: whatever the firmware reads on the way is
+// neither logged nor counted as an access.
+
+use crate::hook::HookSlot;
+use crate::level::Level;
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use core::fmt;
+use core::sync::atomic::Ordering;
+use r_efi::efi;
+use uvm_interface::report::StoreSpace;
+
+const TAG: Tag = tag::INTEGRITY;
+
+type QueryVariableInfoType = efiapi! {fn(u32, *mut u64, *mut u64, *mut u64) -> r_efi::base::Status};
+
+const NV_BS_RT: u32 =
+    efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+const NV_BS: u32 = efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS;
+const ATTRIBUTE_SETS: [u32; 2] = [NV_BS_RT, NV_BS];
+
+static QUERY_VARIABLE_INFO: HookSlot<QueryVariableInfoType> = HookSlot::new();
+static AT_LOAD: AtomicRefCell<StoreSpace> = AtomicRefCell::new(StoreSpace::EMPTY);
+static AT_EXIT: AtomicRefCell<StoreSpace> = AtomicRefCell::new(StoreSpace::EMPTY);
+
+// A size in bytes, in KiB from 1 KiB up.
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 1024 {
+            write!(f, "{}B", self.0)
+        } else {
+            write!(f, "{}KiB", self.0 / 1024)
+        }
+    }
+}
+
+/**
+ * @brief Asks QueryVariableInfo about each set of `sets` in turn until one is
+ *        answered. Fails with NOT_READY if a GetVariable call is in progress.
+ */
+fn measure(
+    query_variable_info: QueryVariableInfoType,
+    sets: &[u32],
+) -> Result<StoreSpace, efi::Status> {
+    if crate::IN_GET_VARIABLE.swap(true, Ordering::Acquire) {
+        return Err(efi::Status::NOT_READY);
+    }
+    let mut result = Err(efi::Status::UNSUPPORTED);
+    for &attributes in sets {
+        let mut space = StoreSpace {
+            attributes,
+            ..StoreSpace::EMPTY
+        };
+        let efi_status = query_variable_info(
+            attributes,
+            &mut space.maximum,
+            &mut space.remaining,
+            &mut space.max_variable_size,
+        );
+        if efi_status == efi::Status::SUCCESS {
+            result = Ok(space);
+            break;
+        }
+        result = Err(efi_status);
+        if efi_status != efi::Status::UNSUPPORTED && efi_status != efi::Status::INVALID_PARAMETER {
+            break;
+        }
+    }
+    crate::IN_GET_VARIABLE.store(false, Ordering::Release);
+    result
+}
+
+/**
+ * @brief Measures the store at load. Must be called before anything can
+ *        hook QueryVariableInfo, which this driver does not.
+ */
+pub fn load(runtime_services: &efi::RuntimeServices) {
+    QUERY_VARIABLE_INFO.set(runtime_services.query_variable_info);
+    let space = match measure(runtime_services.query_variable_info, &ATTRIBUTE_SETS) {
+        Ok(space) => space,
+        Err(efi_status) => {
+            error!(
+                TAG,
+                "NV store not measured at load : {:#x}",
+                efi_status.as_usize()
+            );
+            return;
+        }
+    };
+    info!(
+        TAG,
+        "NV store at load: {} total, {} remaining, {} largest variable, attributes {:#x}",
+        Size(space.maximum),
+        Size(space.remaining),
+        Size(space.max_variable_size),
+        space.attributes
+    );
+    if let Ok(mut at_load) = AT_LOAD.try_borrow_mut() {
+        *at_load = space;
+    }
+}
+
+/**
+ * @brief Measures the store again at ExitBootServices and logs what the boot
+ *        consumed. Warns if a variable of the largest size no longer fits.
+ */
+pub fn exit_boot_services() {
+    let at_load = at_load();
+    let sets = if at_load.is_measured() {
+        core::slice::from_ref(&at_load.attributes)
+    } else {
+        &ATTRIBUTE_SETS[..]
+    };
+    let space = match QUERY_VARIABLE_INFO.get().map(|query| measure(query, sets)) {
+        Some(Ok(space)) => space,
+        Some(Err(efi_status)) => {
+            error!(
+                TAG,
+                "NV store not measured at ExitBootServices : {:#x}",
+                efi_status.as_usize()
+            );
+            return;
+        }
+        None => return,
+    };
+    if let Ok(mut at_exit) = AT_EXIT.try_borrow_mut() {
+        *at_exit = space;
+    }
+
+    let level = if space.remaining < space.max_variable_size {
+        Level::Warning
+    } else {
+        Level::Trace
+    };
+    if !at_load.is_measured() {
+        log_at!(
+            level,
+            TAG,
+            "NV store: {} total, {} remaining, {} largest variable",
+            Size(space.maximum),
+            Size(space.remaining),
+            Size(space.max_variable_size)
+        );
+    } else if space.remaining <= at_load.remaining {
+        log_at!(
+            level,
+            TAG,
+            "NV store: {} total, {} consumed this boot, {} remaining, {} largest variable",
+            Size(space.maximum),
+            Size(at_load.remaining - space.remaining),
+            Size(space.remaining),
+            Size(space.max_variable_size)
+        );
+    } else {
+        log_at!(
+            level,
+            TAG,
+            "NV store: {} total, {} freed this boot, {} remaining, {} largest variable",
+            Size(space.maximum),
+            Size(space.remaining - at_load.remaining),
+            Size(space.remaining),
+            Size(space.max_variable_size)
+        );
+    }
+}
+
+/**
+ * @brief Returns the store space measured at load, for the boot report.
+ */
+pub fn at_load() -> StoreSpace {
+    AT_LOAD
+        .try_borrow_mut()
+        .map(|space| *space)
+        .unwrap_or(StoreSpace::EMPTY)
+}
+
+/**
+ * @brief Returns the store space measured at ExitBootServices, for the boot
+ *        report.
+ */
+pub fn at_exit() -> StoreSpace {
+    AT_EXIT
+        .try_borrow_mut()
+        .map(|space| *space)
+        .unwrap_or(StoreSpace::EMPTY)
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *AT_LOAD.borrow_mut() = StoreSpace::EMPTY;
+    *AT_EXIT.borrow_mut() = StoreSpace::EMPTY;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // The sets the fake answers, and what it was asked.
+    static SUPPORTED: Mutex<std::vec::Vec<u32>> = Mutex::new(std::vec::Vec::new());
+    static ASKED: Mutex<std::vec::Vec<u32>> = Mutex::new(std::vec::Vec::new());
+
+    efiapi! {
+        fn fake_query_variable_info(
+            attributes: u32,
+            maximum: *mut u64,
+            remaining: *mut u64,
+            max_variable_size: *mut u64,
+        ) -> efi::Status {
+            ASKED.lock().unwrap().push(attributes);
+            if !SUPPORTED.lock().unwrap().contains(&attributes) {
+                return efi::Status::UNSUPPORTED;
+            }
+            unsafe {
+                *maximum = 0x4_0000;
+                *remaining = 0x1_0000;
+                *max_variable_size = 0x2000;
+            }
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[test]
+    fn unsupported_sets_are_skipped() {
+        let _lock = crate::mock::lock();
+        *SUPPORTED.lock().unwrap() = std::vec![NV_BS];
+        ASKED.lock().unwrap().clear();
+        assert_eq!(
+            measure(fake_query_variable_info, &ATTRIBUTE_SETS),
+            Ok(StoreSpace {
+                attributes: NV_BS,
+                reserved: 0,
+                maximum: 0x4_0000,
+                remaining: 0x1_0000,
+                max_variable_size: 0x2000,
+            })
+        );
+        assert_eq!(*ASKED.lock().unwrap(), [NV_BS_RT, NV_BS]);
+        assert!(!crate::IN_GET_VARIABLE.load(Ordering::Acquire));
+
+        SUPPORTED.lock().unwrap().clear();
+        assert_eq!(
+            measure(fake_query_variable_info, &ATTRIBUTE_SETS),
+            Err(efi::Status::UNSUPPORTED)
+        );
+
+        // Not while a GetVariable call is in progress.
+        crate::IN_GET_VARIABLE.store(true, Ordering::Release);
+        ASKED.lock().unwrap().clear();
+        assert_eq!(
+            measure(fake_query_variable_info, &ATTRIBUTE_SETS),
+            Err(efi::Status::NOT_READY)
+        );
+        assert!(ASKED.lock().unwrap().is_empty());
+        crate::IN_GET_VARIABLE.store(false, Ordering::Release);
+    }
+
+    #[test]
+    fn sizes_are_shown_in_kib() {
+        assert_eq!(std::format!("{}", Size(1023)), "1023B");
+        assert_eq!(std::format!("{}", Size(0x8_0000)), "512KiB");
+        assert_eq!(std::format!("{}", Size(37 * 1024 + 512)), "37KiB");
+    }
+}
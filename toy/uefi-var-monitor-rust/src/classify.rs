@@ -0,0 +1,210 @@
+// uefi-var-monitor-rust/src/classify.rs
+//
+// Classification of variables by well-known name and vendor GUID. A name only
+// means something together with its GUID: PK and KEK are EFI_GLOBAL_VARIABLE
+// variables, while db, dbx, dbt and dbr live under the image security
+// database GUID. The same names under any other GUID are ordinary variables.
+//
+// Some classes are boot-critical: a write to one of them changes what the
+// next boot runs or what it protects. See is_boot_critical.
+//
+// The driver's modules take the GUIDs from here, and mor.rs in the driver
+// the MOR variables, which it decodes.
+
+use crate::load_option;
+
+use r_efi::efi;
+
+// EFI_GLOBAL_VARIABLE {8be4df61-93ca-11d2-aa0d-00e098032b8c}
+pub const GLOBAL_VARIABLE_GUID: efi::Guid = efi::Guid::from_fields(
+    0x8be4df61,
+    0x93ca,
+    0x11d2,
+    0xaa,
+    0x0d,
+    &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+);
+
+// EFI_IMAGE_SECURITY_DATABASE_GUID {d719b2cb-3d3a-4596-a3bc-dad00e67656f}
+pub const IMAGE_SECURITY_DATABASE_GUID: efi::Guid = efi::Guid::from_fields(
+    0xd719b2cb,
+    0x3d3a,
+    0x4596,
+    0xa3,
+    0xbc,
+    &[0xda, 0xd0, 0x0e, 0x67, 0x65, 0x6f],
+);
+
+// MEMORY_ONLY_RESET_CONTROL_GUID {e20939be-32d4-41be-a150-897f85d49829}
+pub const MEMORY_ONLY_RESET_CONTROL_GUID: efi::Guid = efi::Guid::from_fields(
+    0xe20939be,
+    0x32d4,
+    0x41be,
+    0xa1,
+    0x50,
+    &[0x89, 0x7f, 0x85, 0xd4, 0x98, 0x29],
+);
+
+// MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID {bb983ccf-151d-40e1-a07b-4a17be168292}
+pub const MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID: efi::Guid = efi::Guid::from_fields(
+    0xbb983ccf,
+    0x151d,
+    0x40e1,
+    0xa0,
+    0x7b,
+    &[0x4a, 0x17, 0xbe, 0x16, 0x82, 0x92],
+);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VariableClass {
+    // PK, KEK, db, dbx, dbt or dbr.
+    SecureBootKey,
+    // BootOrder, BootNext or Boot####.
+    BootManager,
+    // OsIndications.
+    OsIndications,
+    // MemoryOverwriteRequestControl or MemoryOverwriteRequestControlLock.
+    Mor,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MorVariable {
+    Control,
+    Lock,
+}
+
+/**
+ * @brief Returns which MOR variable `name` under `guid` is, if any.
+ */
+pub fn mor_variable(name: &str, guid: &efi::Guid) -> Option<MorVariable> {
+    if *guid == MEMORY_ONLY_RESET_CONTROL_GUID && name == "MemoryOverwriteRequestControl" {
+        return Some(MorVariable::Control);
+    }
+    if *guid == MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID
+        && name == "MemoryOverwriteRequestControlLock"
+    {
+        return Some(MorVariable::Lock);
+    }
+    None
+}
+
+/**
+ * @brief Returns the class of the variable `name` under `guid`.
+ */
+pub fn classify(name: &str, guid: &efi::Guid) -> VariableClass {
+    if *guid == GLOBAL_VARIABLE_GUID {
+        match name {
+            "PK" | "KEK" => return VariableClass::SecureBootKey,
+            "BootOrder" | "BootNext" => return VariableClass::BootManager,
+            "OsIndications" => return VariableClass::OsIndications,
+            _ if load_option::is_load_option(name) => return VariableClass::BootManager,
+            _ => {}
+        }
+    }
+    if *guid == IMAGE_SECURITY_DATABASE_GUID {
+        match name {
+            "db" | "dbx" | "dbt" | "dbr" => return VariableClass::SecureBootKey,
+            _ => {}
+        }
+    }
+    if mor_variable(name, guid).is_some() {
+        return VariableClass::Mor;
+    }
+    VariableClass::Other
+}
+
+/**
+ * @brief Returns whether variables of `class` decide what the next boot runs
+ *        or protects.
+ */
+pub fn is_boot_critical(class: VariableClass) -> bool {
+    match class {
+        VariableClass::SecureBootKey
+        | VariableClass::BootManager
+        | VariableClass::OsIndications
+        | VariableClass::Mor => true,
+        VariableClass::Other => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secure_boot_keys_are_scoped_by_guid() {
+        for name in ["PK", "KEK"] {
+            assert_eq!(
+                classify(name, &GLOBAL_VARIABLE_GUID),
+                VariableClass::SecureBootKey
+            );
+            assert_eq!(
+                classify(name, &IMAGE_SECURITY_DATABASE_GUID),
+                VariableClass::Other
+            );
+        }
+        for name in ["db", "dbx", "dbt", "dbr"] {
+            assert_eq!(
+                classify(name, &IMAGE_SECURITY_DATABASE_GUID),
+                VariableClass::SecureBootKey
+            );
+            assert_eq!(classify(name, &GLOBAL_VARIABLE_GUID), VariableClass::Other);
+        }
+        assert_eq!(
+            classify("DB", &IMAGE_SECURITY_DATABASE_GUID),
+            VariableClass::Other
+        );
+        assert_eq!(
+            classify("PKDefault", &GLOBAL_VARIABLE_GUID),
+            VariableClass::Other
+        );
+    }
+
+    #[test]
+    fn boot_manager_variables() {
+        for name in ["BootOrder", "BootNext", "Boot0000", "Boot1A2F"] {
+            assert_eq!(
+                classify(name, &GLOBAL_VARIABLE_GUID),
+                VariableClass::BootManager
+            );
+            assert_eq!(
+                classify(name, &IMAGE_SECURITY_DATABASE_GUID),
+                VariableClass::Other
+            );
+        }
+        for name in ["BootCurrent", "BootOptionSupport", "Boot000", "Driver0000"] {
+            assert_eq!(classify(name, &GLOBAL_VARIABLE_GUID), VariableClass::Other);
+        }
+    }
+
+    #[test]
+    fn boot_critical_variables() {
+        let critical = [
+            ("PK", GLOBAL_VARIABLE_GUID),
+            ("dbx", IMAGE_SECURITY_DATABASE_GUID),
+            ("BootNext", GLOBAL_VARIABLE_GUID),
+            ("OsIndications", GLOBAL_VARIABLE_GUID),
+            (
+                "MemoryOverwriteRequestControl",
+                MEMORY_ONLY_RESET_CONTROL_GUID,
+            ),
+            (
+                "MemoryOverwriteRequestControlLock",
+                MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID,
+            ),
+        ];
+        for (name, guid) in critical {
+            assert!(is_boot_critical(classify(name, &guid)), "{}", name);
+        }
+        // The lock lives under its own GUID, not the MOR one.
+        assert!(!is_boot_critical(classify(
+            "MemoryOverwriteRequestControlLock",
+            &MEMORY_ONLY_RESET_CONTROL_GUID
+        )));
+        assert!(!is_boot_critical(classify(
+            "OsIndicationsSupported",
+            &GLOBAL_VARIABLE_GUID
+        )));
+    }
+}
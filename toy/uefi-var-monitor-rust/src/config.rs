@@ -0,0 +1,76 @@
+// uefi-var-monitor-rust/src/config.rs
+//
+// Runtime configuration. It starts from the build-time defaults (see
+// settings.rs in the library, which lists them and parses them), is applied
+// at load, and individual settings can be changed afterwards while the driver
+// is running. The configuration last applied is kept for the monitor's
+// protocol to report (see protocol.rs).
+
+use crate::alerts;
+use crate::budget;
+use crate::control;
+use crate::counters;
+use crate::integrity;
+#[cfg(feature = "enforce")]
+use crate::lock;
+#[cfg(feature = "enforce")]
+use crate::persist;
+use crate::rate;
+#[cfg(feature = "log-ring")]
+use crate::ring;
+use crate::safety;
+use crate::seen;
+#[cfg(feature = "tpm-measure")]
+use crate::tpm;
+use atomic_refcell::AtomicRefCell;
+
+pub use uefi_var_monitor::settings::{RuntimeConfig, UVM_VENDOR_GUID};
+
+/**
+ * @brief Applies `config` to the running driver.
+ */
+pub fn apply(config: &RuntimeConfig) {
+    #[cfg(feature = "log-ring")]
+    ring::set_overflow_policy(config.ring_overflow_policy);
+    integrity::set_policy(config.hook_integrity_policy);
+    integrity::set_max_reinstalls(config.max_reinstalls);
+    safety::set_runtime_data_access(config.runtime_data_access);
+    #[cfg(feature = "tpm-measure")]
+    tpm::set_pcr(config.tpm_pcr);
+    seen::set_size_policy(config.size_policy);
+    rate::set_policy(config.rate_policy);
+    #[cfg(feature = "enforce")]
+    lock::set_absent_policy(config.lock_absent_policy);
+    #[cfg(feature = "enforce")]
+    persist::set_write_policy(config.level_write_policy);
+    alerts::set_limits(&config.alert_limits);
+    control::set_forward_policy(config.control_forward_policy);
+    counters::set_reset_policy(config.stats_reset_policy);
+    budget::set_budget(config.latency_budget);
+    seen::set_read_records(config.read_records);
+    if let Ok(mut current) = CURRENT.try_borrow_mut() {
+        *current = Some(*config);
+    }
+}
+
+static CURRENT: AtomicRefCell<Option<RuntimeConfig>> = AtomicRefCell::new(None);
+
+/**
+ * @brief Returns the configuration last applied, if any.
+ */
+pub fn current() -> Option<RuntimeConfig> {
+    match CURRENT.try_borrow_mut() {
+        Ok(current) => *current,
+        Err(_) => None,
+    }
+}
+
+/**
+ * @brief Changes the configuration last applied, or the build-time one if
+ *        none was, and applies the result.
+ */
+pub fn update(change: impl FnOnce(&mut RuntimeConfig)) {
+    let mut config = current().unwrap_or_else(RuntimeConfig::from_build_env);
+    change(&mut config);
+    apply(&config);
+}
@@ -0,0 +1,678 @@
+// uefi-var-monitor-rust/src/config_format.rs
+//
+// What the configuration is kept in across boots: the blobs of UvmConfig
+// and the data of UvmLevel, which the driver reads at load and writes on
+// each change (see config_store.rs and persist.rs in the driver). A blob is a
+// header, starting with the common one of interface/src/format.rs:
+//
+//   0    magic, "UVMC", CONFIG_MAJOR and CONFIG_MINOR, header size
+//        (CONFIG_HEADER_SIZE), no records
+//   24   size of the sections, u32
+//   28   CRC32 of the sections, u32
+//
+// followed by sections, each a tag and the size of its value, both u16, then
+// the value:
+//
+//   1  level     the level, numbered as in settings.rs, and 1 if paused
+//   2  filter    the trace filter, as an ASCII list of pattern.rs
+//   3  alerts    per rule, 10 bytes: the rule, the cooldown kind (0 none,
+//                1 occurrences, 2 seconds), then the threshold and the
+//                cooldown, u32 each (see alert.rs)
+//   4  runtime   the settings of settings.rs, u32 each, in RuntimeField
+//                order, NOT_BUILT for those of features not built
+//
+// Numbers are little-endian. The format only ever grows: a minor version may
+// add sections, header fields past the header size and fields at the end of a
+// section, and a section whose meaning changes gets a new tag. A blob of an
+// older minor version is thus read for what it holds, the settings added since
+// keeping their current values, and one of a newer one for what this code
+// knows: unknown tags and trailing fields are skipped. A blob is never
+// rewritten when read; it is migrated to the current version by the next
+// save. Values that do not parse, and rules not known here, are skipped the
+// same way. Major version 1 was the format of before the common header.
+//
+// The blob is read back from a variable anyone able to write variables may
+// have set, so it is taken as a slice and its framing checked whole before
+// any section is handed out: magic, major version, header size, sizes within
+// the data, CRC, then section sizes adding up to exactly the size of the
+// sections. A blob failing any of these is ignored whole.
+//
+// UvmLevel holds LEVEL_DATA_SIZE bytes:
+//
+//   0   version (LEVEL_VERSION)
+//   1   level, numbered as in settings.rs
+//
+// Data of any other size, version or level is corrupt.
+
+use crate::alert::{Cooldown, Limits, Rule, RULE_COUNT};
+use crate::crc32::crc32;
+#[cfg(feature = "log-ring")]
+use crate::settings::OverflowPolicy;
+#[cfg(feature = "tpm-measure")]
+use crate::settings::MAX_PCR;
+#[cfg(feature = "enforce")]
+use crate::settings::{AbsentPolicy, WritePolicy};
+use crate::settings::{
+    ForwardPolicy, IntegrityPolicy, Level, ResetPolicy, RuntimeConfig, RuntimeDataAccess,
+};
+use core::convert::TryFrom;
+use core::fmt;
+use r_efi::efi;
+use uvm_interface::format::{FormatHeader, FORMAT_HEADER_SIZE};
+use uvm_interface::FormatError;
+
+pub const CONFIG_MAGIC: [u8; 4] = *b"UVMC";
+pub const CONFIG_MAJOR: u16 = 2;
+pub const CONFIG_MINOR: u16 = 0;
+pub const CONFIG_HEADER_SIZE: usize = FORMAT_HEADER_SIZE + 8;
+// Bytes of a blob written or read.
+pub const MAX_CONFIG_SIZE: usize = 4096;
+
+pub const SECTION_HEADER_SIZE: usize = 4;
+
+const TAG_LEVEL: u16 = 1;
+const TAG_FILTER: u16 = 2;
+const TAG_ALERTS: u16 = 3;
+const TAG_RUNTIME: u16 = 4;
+
+const ALERT_ENTRY_SIZE: usize = 10;
+
+const COOLDOWN_NONE: u8 = 0;
+const COOLDOWN_OCCURRENCES: u8 = 1;
+const COOLDOWN_SECONDS: u8 = 2;
+
+pub const LEVEL_VERSION: u8 = 1;
+pub const LEVEL_DATA_SIZE: usize = 2;
+
+// Fields of the runtime section, in order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RuntimeField {
+    RingOverflow,
+    HookIntegrity,
+    HookReinstalls,
+    RuntimeData,
+    TpmPcr,
+    SizeFactor,
+    SizeLimit,
+    RateLimit,
+    RateSustain,
+    LockAbsent,
+    LevelWrites,
+    CtlForward,
+    StatsReset,
+}
+
+const RUNTIME_FIELDS: [RuntimeField; 13] = [
+    RuntimeField::RingOverflow,
+    RuntimeField::HookIntegrity,
+    RuntimeField::HookReinstalls,
+    RuntimeField::RuntimeData,
+    RuntimeField::TpmPcr,
+    RuntimeField::SizeFactor,
+    RuntimeField::SizeLimit,
+    RuntimeField::RateLimit,
+    RuntimeField::RateSustain,
+    RuntimeField::LockAbsent,
+    RuntimeField::LevelWrites,
+    RuntimeField::CtlForward,
+    RuntimeField::StatsReset,
+];
+
+/**
+ * @brief The settings of a blob kept outside the configuration.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Saved<'a> {
+    pub level: Option<Level>,
+    pub paused: Option<bool>,
+    pub trace_filter: Option<&'a str>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/**
+ * @brief The sections of a checked blob, as tags and values, in order.
+ */
+#[derive(Clone, Debug)]
+pub struct Sections<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tag = read_u16(self.data, self.offset)?;
+        let size = usize::from(read_u16(self.data, self.offset + 2)?);
+        let start = self.offset + SECTION_HEADER_SIZE;
+        let value = self.data.get(start..start + size)?;
+        self.offset = start + size;
+        Some((tag, value))
+    }
+}
+
+/**
+ * @brief Checks the framing of `blob` and returns the minor version it was
+ *        written in, with its sections. Fails with INCOMPATIBLE_VERSION for
+ *        another major version, CRC_ERROR for a bad CRC, and
+ *        COMPROMISED_DATA for anything else out of place.
+ */
+pub fn sections(blob: &[u8]) -> Result<(u16, Sections<'_>), efi::Status> {
+    let format =
+        FormatHeader::parse(blob, CONFIG_MAGIC, CONFIG_MAJOR).map_err(|error| match error {
+            FormatError::Major { .. } => efi::Status::INCOMPATIBLE_VERSION,
+            _ => efi::Status::COMPROMISED_DATA,
+        })?;
+    let header_size = format
+        .header_size(CONFIG_HEADER_SIZE, blob.len())
+        .map_err(|_| efi::Status::COMPROMISED_DATA)?;
+    let length = read_u32(blob, FORMAT_HEADER_SIZE).unwrap_or(0) as usize;
+    let crc = read_u32(blob, FORMAT_HEADER_SIZE + 4).unwrap_or(0);
+    let data = header_size
+        .checked_add(length)
+        .and_then(|end| blob.get(header_size..end))
+        .ok_or(efi::Status::COMPROMISED_DATA)?;
+    if crc32(data) != crc {
+        return Err(efi::Status::CRC_ERROR);
+    }
+
+    // The sizes add up, so that the sections are handed out whole or not at
+    // all.
+    let mut offset = 0;
+    while offset < data.len() {
+        let size = read_u16(data, offset + 2).ok_or(efi::Status::COMPROMISED_DATA)?;
+        offset += SECTION_HEADER_SIZE + usize::from(size);
+    }
+    if offset != data.len() {
+        return Err(efi::Status::COMPROMISED_DATA);
+    }
+    Ok((format.minor, Sections { data, offset: 0 }))
+}
+
+/**
+ * @brief Returns the value of the runtime field in `config`.
+ */
+fn runtime_value(config: &RuntimeConfig, field: RuntimeField) -> u32 {
+    match field {
+        #[cfg(feature = "log-ring")]
+        RuntimeField::RingOverflow => config.ring_overflow_policy as u32,
+        RuntimeField::HookIntegrity => config.hook_integrity_policy as u32,
+        RuntimeField::HookReinstalls => config.max_reinstalls,
+        RuntimeField::RuntimeData => config.runtime_data_access as u32,
+        #[cfg(feature = "tpm-measure")]
+        RuntimeField::TpmPcr => config.tpm_pcr,
+        RuntimeField::SizeFactor => config.size_policy.factor,
+        RuntimeField::SizeLimit => config.size_policy.limit,
+        RuntimeField::RateLimit => config.rate_policy.limit,
+        RuntimeField::RateSustain => config.rate_policy.sustain,
+        #[cfg(feature = "enforce")]
+        RuntimeField::LockAbsent => config.lock_absent_policy as u32,
+        #[cfg(feature = "enforce")]
+        RuntimeField::LevelWrites => config.level_write_policy as u32,
+        RuntimeField::CtlForward => config.control_forward_policy as u32,
+        RuntimeField::StatsReset => config.stats_reset_policy as u32,
+        #[allow(unreachable_patterns)]
+        _ => uvm_interface::protocol::NOT_BUILT,
+    }
+}
+
+/**
+ * @brief Sets the runtime field in `config` to `value`, if it is valid.
+ */
+fn set_runtime_value(config: &mut RuntimeConfig, field: RuntimeField, value: u32) {
+    match field {
+        #[cfg(feature = "log-ring")]
+        RuntimeField::RingOverflow => {
+            if let Some(policy) = OverflowPolicy::from_u32(value) {
+                config.ring_overflow_policy = policy;
+            }
+        }
+        RuntimeField::HookIntegrity => {
+            if let Some(policy) = IntegrityPolicy::from_u32(value) {
+                config.hook_integrity_policy = policy;
+            }
+        }
+        RuntimeField::HookReinstalls => config.max_reinstalls = value,
+        RuntimeField::RuntimeData => {
+            if let Some(access) = RuntimeDataAccess::from_u32(value) {
+                config.runtime_data_access = access;
+            }
+        }
+        #[cfg(feature = "tpm-measure")]
+        RuntimeField::TpmPcr if value <= MAX_PCR => config.tpm_pcr = value,
+        RuntimeField::SizeFactor if value >= 2 => config.size_policy.factor = value,
+        RuntimeField::SizeLimit => config.size_policy.limit = value,
+        RuntimeField::RateLimit => config.rate_policy.limit = value,
+        RuntimeField::RateSustain => config.rate_policy.sustain = value,
+        #[cfg(feature = "enforce")]
+        RuntimeField::LockAbsent => {
+            if let Some(policy) = AbsentPolicy::from_u32(value) {
+                config.lock_absent_policy = policy;
+            }
+        }
+        #[cfg(feature = "enforce")]
+        RuntimeField::LevelWrites => {
+            if let Some(policy) = WritePolicy::from_u32(value) {
+                config.level_write_policy = policy;
+            }
+        }
+        RuntimeField::CtlForward => {
+            if let Some(policy) = ForwardPolicy::from_u32(value) {
+                config.control_forward_policy = policy;
+            }
+        }
+        RuntimeField::StatsReset => {
+            if let Some(policy) = ResetPolicy::from_u32(value) {
+                config.stats_reset_policy = policy;
+            }
+        }
+        #[allow(unreachable_patterns)]
+        _ => {}
+    }
+}
+
+/**
+ * @brief Reads the section `tag` into `config` or `saved`. Unknown tags are
+ *        skipped.
+ */
+fn read_section<'a>(tag: u16, value: &'a [u8], config: &mut RuntimeConfig, saved: &mut Saved<'a>) {
+    match tag {
+        TAG_LEVEL => {
+            if let Some(level) = value
+                .first()
+                .and_then(|level| Level::from_u32(u32::from(*level)))
+            {
+                saved.level = Some(level);
+            }
+            if let Some(paused) = value.get(1) {
+                saved.paused = Some(*paused != 0);
+            }
+        }
+        TAG_FILTER => {
+            if let Ok(list) = core::str::from_utf8(value) {
+                saved.trace_filter = Some(list);
+            }
+        }
+        TAG_ALERTS => {
+            for entry in value.chunks_exact(ALERT_ENTRY_SIZE) {
+                let rule = match Rule::ALL.get(usize::from(entry[0])) {
+                    Some(rule) => *rule,
+                    None => continue,
+                };
+                let (threshold, cooldown) = (read_u32(entry, 2), read_u32(entry, 6));
+                let cooldown = match (entry[1], cooldown) {
+                    (COOLDOWN_NONE, _) => Cooldown::None,
+                    (COOLDOWN_OCCURRENCES, Some(count)) => Cooldown::Occurrences(count),
+                    (COOLDOWN_SECONDS, Some(seconds)) => Cooldown::Seconds(seconds),
+                    _ => continue,
+                };
+                if let Some(threshold) = threshold {
+                    config.alert_limits[rule as usize] = Limits {
+                        threshold,
+                        cooldown,
+                    };
+                }
+            }
+        }
+        TAG_RUNTIME => {
+            for (index, field) in RUNTIME_FIELDS.iter().enumerate() {
+                match read_u32(value, 4 * index) {
+                    Some(value) => set_runtime_value(config, *field, value),
+                    None => break,
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/**
+ * @brief Reads a blob into `config` and the returned settings, with the
+ *        minor version it was written in. `config` is only changed if the blob is
+ *        valid.
+ */
+pub fn decode<'a>(
+    blob: &'a [u8],
+    config: &mut RuntimeConfig,
+) -> Result<(u16, Saved<'a>), efi::Status> {
+    let (minor, sections) = sections(blob)?;
+    let mut saved = Saved::default();
+    for (tag, value) in sections {
+        read_section(tag, value, config, &mut saved);
+    }
+    Ok((minor, saved))
+}
+
+// Builds a blob in a buffer, failing once it is full.
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> fmt::Result {
+        let end = self.length.checked_add(bytes.len()).ok_or(fmt::Error)?;
+        self.buffer
+            .get_mut(self.length..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(bytes);
+        self.length = end;
+        Ok(())
+    }
+
+    /**
+     * @brief Writes a section whose value `value` writes.
+     */
+    fn section(&mut self, tag: u16, value: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        let start = self.length;
+        self.bytes(&tag.to_le_bytes())?;
+        self.bytes(&[0, 0])?;
+        value(self)?;
+        let size =
+            u16::try_from(self.length - start - SECTION_HEADER_SIZE).map_err(|_| fmt::Error)?;
+        self.buffer
+            .get_mut(start + 2..start + 4)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(&size.to_le_bytes());
+        Ok(())
+    }
+}
+
+impl fmt::Write for Writer<'_> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.bytes(text.as_bytes())
+    }
+}
+
+/**
+ * @brief Writes `config` as a blob into `buffer`, with the level, whether
+ *        logging is paused, and the trace filter `trace_filter` writes as a
+ *        list. Returns its size.
+ */
+pub fn encode(
+    config: &RuntimeConfig,
+    level: Level,
+    paused: bool,
+    trace_filter: impl FnOnce(&mut dyn fmt::Write) -> fmt::Result,
+    buffer: &mut [u8],
+) -> Result<usize, efi::Status> {
+    let mut writer = Writer { buffer, length: 0 };
+    let sections = (|writer: &mut Writer| -> fmt::Result {
+        writer.bytes(&[0; CONFIG_HEADER_SIZE])?;
+        writer.section(TAG_LEVEL, |writer| {
+            writer.bytes(&[level as u8, paused as u8])
+        })?;
+        writer.section(TAG_FILTER, |writer| trace_filter(writer))?;
+        writer.section(TAG_ALERTS, |writer| {
+            for (rule, limits) in config.alert_limits.iter().enumerate().take(RULE_COUNT) {
+                let (kind, cooldown) = match limits.cooldown {
+                    Cooldown::None => (COOLDOWN_NONE, 0),
+                    Cooldown::Occurrences(count) => (COOLDOWN_OCCURRENCES, count),
+                    Cooldown::Seconds(seconds) => (COOLDOWN_SECONDS, seconds),
+                };
+                writer.bytes(&[rule as u8, kind])?;
+                writer.bytes(&limits.threshold.to_le_bytes())?;
+                writer.bytes(&cooldown.to_le_bytes())?;
+            }
+            Ok(())
+        })?;
+        writer.section(TAG_RUNTIME, |writer| {
+            for field in RUNTIME_FIELDS.iter() {
+                writer.bytes(&runtime_value(config, *field).to_le_bytes())?;
+            }
+            Ok(())
+        })
+    })(&mut writer);
+    sections.map_err(|_| efi::Status::BUFFER_TOO_SMALL)?;
+
+    let length = writer.length;
+    let buffer = writer.buffer;
+    let crc = crc32(
+        buffer
+            .get(CONFIG_HEADER_SIZE..length)
+            .ok_or(efi::Status::BUFFER_TOO_SMALL)?,
+    );
+    let format = FormatHeader::new(
+        CONFIG_MAGIC,
+        CONFIG_MAJOR,
+        CONFIG_MINOR,
+        CONFIG_HEADER_SIZE,
+        0,
+    );
+    let header = buffer
+        .get_mut(..CONFIG_HEADER_SIZE)
+        .ok_or(efi::Status::BUFFER_TOO_SMALL)?;
+    header[..FORMAT_HEADER_SIZE].copy_from_slice(format.as_bytes());
+    header[24..28].copy_from_slice(&((length - CONFIG_HEADER_SIZE) as u32).to_le_bytes());
+    header[28..32].copy_from_slice(&crc.to_le_bytes());
+    Ok(length)
+}
+
+/**
+ * @brief Returns the level stored in the UvmLevel data `data`, if it is
+ *        valid.
+ */
+pub fn parse_level(data: &[u8]) -> Option<Level> {
+    match data {
+        [LEVEL_VERSION, value] => Level::from_u32(u32::from(*value)),
+        _ => None,
+    }
+}
+
+/**
+ * @brief Returns the UvmLevel data storing `level`.
+ */
+pub fn level_data(level: Level) -> [u8; LEVEL_DATA_SIZE] {
+    [LEVEL_VERSION, level as u8]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::DEFAULT_LIMITS;
+
+    // Builds a blob from its header fields and sections.
+    fn blob(minor: u16, header_size: usize, sections: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in sections {
+            body.extend_from_slice(&tag.to_le_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            body.extend_from_slice(value);
+        }
+        let format = FormatHeader::new(CONFIG_MAGIC, CONFIG_MAJOR, minor, header_size, 0);
+        let mut blob = format.as_bytes().to_vec();
+        blob.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        blob.extend_from_slice(&crc32(&body).to_le_bytes());
+        blob.resize(header_size, 0xee);
+        blob.extend_from_slice(&body);
+        blob
+    }
+
+    const FILTER: &str = "8BE4DF61-93CA-11D2-AA0D-00E098032B8C:Boot*;\
+                          D719B2CB-3D3A-4596-A3BC-DAD00E67656F:db";
+
+    fn saved_blob() -> Vec<u8> {
+        let mut config = RuntimeConfig::from_build_env();
+        config.rate_policy.limit = 7;
+        config.runtime_data_access = RuntimeDataAccess::Allow;
+        config.alert_limits[Rule::MorAccess as usize] = Limits {
+            threshold: 2,
+            cooldown: Cooldown::Occurrences(10),
+        };
+        let mut blob = [0u8; MAX_CONFIG_SIZE];
+        let size = encode(
+            &config,
+            Level::Info,
+            false,
+            |out| out.write_str(FILTER),
+            &mut blob,
+        )
+        .unwrap();
+        blob[..size].to_vec()
+    }
+
+    // The minor version and the sections, as (tag, value).
+    type Sections<'a> = (u16, Vec<(u16, &'a [u8])>);
+
+    fn sections_of(blob: &[u8]) -> Result<Sections<'_>, efi::Status> {
+        sections(blob).map(|(minor, sections)| (minor, sections.collect()))
+    }
+
+    #[test]
+    fn sections_are_handed_out_once_checked() {
+        let good = blob(3, CONFIG_HEADER_SIZE + 4, &[(1, &[2, 0]), (0x99, b"")]);
+        assert_eq!(
+            sections_of(&good),
+            Ok((3, vec![(1, &[2u8, 0][..]), (0x99, &b""[..])]))
+        );
+        assert_eq!(
+            sections_of(&blob(0, CONFIG_HEADER_SIZE, &[])),
+            Ok((0, vec![]))
+        );
+
+        let mut crc = good.clone();
+        *crc.last_mut().unwrap() ^= 1;
+        assert_eq!(sections_of(&crc), Err(efi::Status::CRC_ERROR));
+        let mut major = good.clone();
+        major[4..6].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(sections_of(&major), Err(efi::Status::INCOMPATIBLE_VERSION));
+        assert_eq!(
+            sections_of(&good[..good.len() - 1]),
+            Err(efi::Status::COMPROMISED_DATA)
+        );
+        assert_eq!(
+            sections_of(&blob(0, FORMAT_HEADER_SIZE, &[])),
+            Err(efi::Status::COMPROMISED_DATA)
+        );
+
+        // A section running past the others, under a good CRC.
+        let mut long = blob(0, CONFIG_HEADER_SIZE, &[(1, &[2, 0])]);
+        long[CONFIG_HEADER_SIZE + 2] = 3;
+        let crc = crc32(&long[CONFIG_HEADER_SIZE..]);
+        long[28..32].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(sections_of(&long), Err(efi::Status::COMPROMISED_DATA));
+    }
+
+    #[test]
+    fn saved_configuration_is_read_back() {
+        let blob = saved_blob();
+        let mut config = RuntimeConfig::from_build_env();
+        let (minor, saved) = decode(&blob, &mut config).unwrap();
+        assert_eq!(minor, CONFIG_MINOR);
+        assert_eq!(saved.level, Some(Level::Info));
+        assert_eq!(saved.paused, Some(false));
+        assert_eq!(saved.trace_filter, Some(FILTER));
+        assert_eq!(config.rate_policy.limit, 7);
+        assert_eq!(config.runtime_data_access, RuntimeDataAccess::Allow);
+        assert_eq!(
+            config.alert_limits[Rule::MorAccess as usize].cooldown,
+            Cooldown::Occurrences(10)
+        );
+
+        // Any change to the sections breaks the CRC.
+        let mut changed = blob.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        assert_eq!(decode(&changed, &mut config), Err(efi::Status::CRC_ERROR));
+        assert!(decode(&blob[..blob.len() - 1], &mut config).is_err());
+        let mut other = blob.clone();
+        other[0] = b'X';
+        assert!(decode(&other, &mut config).is_err());
+    }
+
+    #[test]
+    fn older_and_newer_versions_are_read_for_what_they_hold() {
+        // A blob written before the runtime section had its last fields.
+        let runtime: Vec<u8> = [0u32, 0, 9, 1]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let mut config = RuntimeConfig::from_build_env();
+        let older = blob(0, CONFIG_HEADER_SIZE, &[(TAG_RUNTIME, &runtime)]);
+        let (_, saved) = decode(&older, &mut config).unwrap();
+        assert_eq!(saved, Saved::default());
+        assert_eq!(config.max_reinstalls, 9);
+        assert_eq!(
+            config.rate_policy.limit,
+            crate::settings::DEFAULT_RATE_LIMIT
+        );
+
+        // A newer one, with a longer header, an unknown section, a longer
+        // level section and values out of range.
+        let alerts = [
+            [0xff; 10],
+            [Rule::SizeChange as u8, 9, 0, 0, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        let newer = blob(
+            7,
+            CONFIG_HEADER_SIZE + 8,
+            &[
+                (0x99, b"future"),
+                (TAG_LEVEL, &[Level::Warning as u8, 1, 0xaa]),
+                (TAG_ALERTS, &alerts),
+                (TAG_FILTER, &[0xff]),
+            ],
+        );
+        let mut config = RuntimeConfig::from_build_env();
+        let (minor, saved) = decode(&newer, &mut config).unwrap();
+        assert_eq!(minor, 7);
+        assert_eq!(
+            (saved.level, saved.paused),
+            (Some(Level::Warning), Some(true))
+        );
+        assert_eq!(saved.trace_filter, None);
+        assert_eq!(config.alert_limits, DEFAULT_LIMITS);
+
+        // Another major version, as the blobs of before the common header.
+        let mut other = blob(0, CONFIG_HEADER_SIZE, &[]);
+        other[4..6].copy_from_slice(&1u16.to_le_bytes());
+        assert_eq!(
+            decode(&other, &mut config),
+            Err(efi::Status::INCOMPATIBLE_VERSION)
+        );
+        assert!(decode(&blob(0, FORMAT_HEADER_SIZE, &[]), &mut config).is_err());
+    }
+
+    // The fuzz target (see fuzz/) in short, on its seed, for the runs that
+    // do not have cargo-fuzz. Most mangled blobs get their size and CRC fixed
+    // up, so that the sections are walked.
+    #[test]
+    fn mangled_blobs_are_refused_or_read_without_panicking() {
+        // Past the byte that tells the fuzz target whether to fix them up.
+        let seed = &include_bytes!("../fuzz/corpus/config/saved")[1..];
+        assert_eq!(sections(seed).map(|(_, sections)| sections.count()), Ok(4));
+        let mut round = 0;
+        crate::mangle::run(&[seed], 20_000, |data| {
+            let mut mangled = data.to_vec();
+            round += 1;
+            if round % 4 != 0 && mangled.len() >= CONFIG_HEADER_SIZE {
+                let crc = crc32(&mangled[CONFIG_HEADER_SIZE..]);
+                let length = (mangled.len() - CONFIG_HEADER_SIZE) as u32;
+                mangled[24..28].copy_from_slice(&length.to_le_bytes());
+                mangled[28..32].copy_from_slice(&crc.to_le_bytes());
+            }
+            if let Ok((_, sections)) = sections(&mangled) {
+                let walked: usize = sections
+                    .map(|(_, value)| SECTION_HEADER_SIZE + value.len())
+                    .sum();
+                assert!(walked + CONFIG_HEADER_SIZE <= mangled.len());
+            }
+            let mut config = RuntimeConfig::from_build_env();
+            if let Ok((_, saved)) = decode(&mangled, &mut config) {
+                assert!(config.size_policy.factor >= 2);
+                if let Some(list) = saved.trace_filter {
+                    assert!(list.len() < mangled.len());
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,196 @@
+// uefi-var-monitor-rust/src/config_store.rs
+//
+// The whole configuration, kept across boots in "UvmConfig" under
+// UVM_VENDOR_GUID once saved through the monitor's protocol (see
+// protocol.rs, and uvmctl save). The driver reads it back at load, over the
+// build-time configuration and under the level kept in UvmLevel, which is
+// written on every level change and is never older (see persist.rs); load
+// options win over both (see options.rs). The data is a header, starting
+// with the common one of interface/src/format.rs, then sections holding the
+// level, the trace filter, the alert limits and the other settings. The
+// layout, and how blobs of older and newer versions are read, are given in
+// config_format.rs in the library, which decodes and encodes the blobs and
+// which the fuzz targets (see fuzz/) feed directly. A blob with another
+// magic, another major version, sizes running past the data or a bad CRC is
+// ignored whole.
+//
+// Sinks are chosen by features at build time, so there is no sink selection
+// to keep.
+//
+// With enforce, writes by others are blocked as UvmLevel's are; otherwise
+// anyone able to write variables can set the configuration of the next boot.
+
+use crate::config::{self, RuntimeConfig, UVM_VENDOR_GUID};
+use crate::config_format::{self, CONFIG_MAJOR, MAX_CONFIG_SIZE};
+use crate::filter;
+use crate::level::{self, Level};
+use crate::set_variable::SET_VARIABLE;
+use crate::tag::{self, Tag};
+use crate::SetVariableType;
+use core::sync::atomic::{AtomicBool, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::CONFIG;
+
+pub const CONFIG_VARIABLE: &str = "UvmConfig";
+
+// "UvmConfig"
+const CONFIG_VARIABLE_NAME: [u16; 10] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'C' as u16,
+    b'o' as u16,
+    b'n' as u16,
+    b'f' as u16,
+    b'i' as u16,
+    b'g' as u16,
+    0,
+];
+
+const CONFIG_ATTRIBUTES: u32 =
+    efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+
+static WRITING: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Reads the saved configuration through the firmware's GetVariable
+ *        and applies it. Must be called before the hooks are installed.
+ *        Returns whether a configuration was applied.
+ */
+pub fn restore(runtime_services: &efi::RuntimeServices) -> bool {
+    let mut name = CONFIG_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    let mut attributes = 0u32;
+    let mut blob = [0u8; MAX_CONFIG_SIZE];
+    let mut data_size = blob.len();
+    let efi_status = (runtime_services.get_variable)(
+        name.as_mut_ptr(),
+        &mut guid,
+        &mut attributes,
+        &mut data_size,
+        blob.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    match efi_status {
+        efi::Status::NOT_FOUND => return false,
+        efi::Status::SUCCESS => {}
+        _ => {
+            error!(
+                TAG,
+                "{} not read : {:#x}",
+                CONFIG_VARIABLE,
+                efi_status.as_usize()
+            );
+            return false;
+        }
+    }
+
+    let mut config = config::current().unwrap_or_else(RuntimeConfig::from_build_env);
+    let blob = blob.get(..data_size).unwrap_or(&[]);
+    let (minor, saved) = match config_format::decode(blob, &mut config) {
+        Ok(decoded) => decoded,
+        Err(efi::Status::INCOMPATIBLE_VERSION) => {
+            warn!(
+                TAG,
+                "{} of another major version than {}, ignored", CONFIG_VARIABLE, CONFIG_MAJOR
+            );
+            return false;
+        }
+        Err(efi_status) => {
+            log_at!(
+                Level::Critical,
+                TAG,
+                "{} corrupt, ignored : {:#x}",
+                CONFIG_VARIABLE,
+                efi_status.as_usize()
+            );
+            return false;
+        }
+    };
+    config::apply(&config);
+    if let Some(new) = saved.level {
+        level::set_level(new);
+    }
+    if let Some(paused) = saved.paused {
+        level::set_paused(paused);
+    }
+    if let Some(list) = saved.trace_filter {
+        if let Err(efi_status) = filter::replace(list) {
+            warn!(
+                TAG,
+                "Saved trace filter skipped : {:#x}",
+                efi_status.as_usize()
+            );
+        }
+    }
+    // Written whatever the level, as the load line is.
+    log_at!(
+        Level::Critical,
+        TAG,
+        "Configuration restored from {}, version {}.{}",
+        CONFIG_VARIABLE,
+        CONFIG_MAJOR,
+        minor
+    );
+    true
+}
+
+/**
+ * @brief Writes `blob` through `set_variable`, unless a write is already
+ *        in progress.
+ */
+fn write(set_variable: SetVariableType, blob: &mut [u8]) -> efi::Status {
+    if WRITING.swap(true, Ordering::Acquire) {
+        return efi::Status::NOT_READY;
+    }
+    let mut name = CONFIG_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    let efi_status = set_variable(
+        name.as_mut_ptr(),
+        &mut guid,
+        CONFIG_ATTRIBUTES,
+        blob.len(),
+        blob.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    WRITING.store(false, Ordering::Release);
+    efi_status
+}
+
+/**
+ * @brief Saves the current configuration for the next boots, through the
+ *        saved SetVariable. Takes MAX_CONFIG_SIZE bytes of the protocol
+ *        caller's stack; it is never called from a hook.
+ */
+pub fn save() -> efi::Status {
+    let config = match config::current() {
+        Some(config) => config,
+        None => return efi::Status::NOT_READY,
+    };
+    let mut blob = [0u8; MAX_CONFIG_SIZE];
+    let encoded = config_format::encode(
+        &config,
+        level::level(),
+        level::is_paused(),
+        |mut out| filter::write_list(&mut out),
+        &mut blob,
+    );
+    let efi_status = match (encoded, SET_VARIABLE.get()) {
+        (Ok(size), Some(set_variable)) => match blob.get_mut(..size) {
+            Some(blob) => write(set_variable, blob),
+            None => efi::Status::BUFFER_TOO_SMALL,
+        },
+        (Err(efi_status), _) => efi_status,
+        (_, None) => efi::Status::NOT_READY,
+    };
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "Configuration not saved to {} : {:#x}",
+            CONFIG_VARIABLE,
+            efi_status.as_usize()
+        );
+    } else {
+        info!(TAG, "Configuration saved to {}", CONFIG_VARIABLE);
+    }
+    efi_status
+}
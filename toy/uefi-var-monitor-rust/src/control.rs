@@ -0,0 +1,491 @@
+// uefi-var-monitor-rust/src/control.rs
+//
+// Control through a variable, for the OS, which cannot reach the monitor's
+// protocol. Writes to "UvmCtl" under UVM_VENDOR_GUID are taken by the
+// SetVariable hook as commands and applied at once, e.g. from Linux:
+//
+//   printf '\x06\x00\x00\x00\x01\x01\x01\x02' > \
+//       /sys/firmware/efi/efivars/UvmCtl-6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90
+//
+// (the first four bytes being the attributes efivarfs expects, BS+RT). The
+// data is a version byte followed by tag/length/payload commands:
+//
+//   0x01 set level       1 byte: the level, numbered as in level.rs, kept
+//                        for the next boots (see persist.rs)
+//   0x02 set flags       4 bytes mask, 4 bytes values, little-endian: the
+//                        flags in the mask take their bit from the values
+//   0x03 reset counters  no payload
+//   0x04 snapshot        no payload: keep a copy of the statistics, whose
+//                        ID UvmCtlStatus then reports (see snapshot.rs)
+//   0x05 diff            4 bytes, little-endian: the ID of a snapshot, to
+//                        diff the statistics against, for UvmCtlDiff
+//
+// with these flags:
+//
+//   bit 0   logging paused (see level.rs)
+//   bit 1   data buffers inspected at OS runtime (see safety.rs)
+//   bit 2   hook re-installed when replaced (see integrity.rs)
+//
+// The whole write is checked before anything is applied; one malformed or
+// unknown command rejects it with INVALID_PARAMETER. Only whether a snapshot
+// is still kept is checked when the diff is applied, after the commands
+// before it. By UVM_CTL_FORWARD (see settings.rs in the library), an accepted
+// write is either dropped, answered with SUCCESS, or also forwarded to
+// firmware. Dropping is the default, as firmware refuses volatile writes once
+// the OS runs.
+//
+// For the same reason, the outcome of the last write is not stored in
+// firmware: reads of "UvmCtlStatus" are answered by the GetVariable hook
+// itself, with STATUS_SIZE bytes:
+//
+//   0      version
+//   1      outcome of the last write (Outcome), 0 before any
+//   2      index of the command it failed at, 0xff if none
+//   3      current level
+//   4..8   current flags
+//   8..12  number of writes to UvmCtl handled so far
+//   12..16 ID of the last snapshot taken, 0 before any
+//
+// Reads of "UvmCtlDiff" are answered the same way, with the statistics the
+// last diff command computed, laid out as get_statistics copies them (see
+// interface/src/protocol.rs); NOT_FOUND before any.
+//
+// Writes to UvmCtlStatus and UvmCtlDiff are dropped, so that efivarfs can
+// create the files to read them through, and so are deletions of any of the
+// variables.
+//
+// The data of a control write is read whatever the runtime data access
+// setting, as firmware itself would read it: it is the monitor's own
+// variable. Anyone able to write variables can reconfigure the monitor this
+// way, so it is no stronger than the OS's access control on efivarfs.
+
+use crate::caller;
+use crate::config::{self, UVM_VENDOR_GUID};
+use crate::integrity::{self, IntegrityPolicy};
+use crate::level::{self, Level};
+use crate::persist;
+use crate::safety::{self, RuntimeDataAccess};
+use crate::snapshot::{self, SnapshotError};
+use crate::tag::{self, Tag};
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use r_efi::efi;
+
+pub use uefi_var_monitor::settings::ForwardPolicy;
+
+const TAG: Tag = tag::CONFIG;
+
+pub const CONTROL_VERSION: u8 = 1;
+// Bytes of a control write.
+pub const MAX_CONTROL_SIZE: usize = 64;
+pub const STATUS_SIZE: usize = 16;
+
+const CONTROL_VARIABLE: &str = "UvmCtl";
+const STATUS_VARIABLE: &str = "UvmCtlStatus";
+const DIFF_VARIABLE: &str = "UvmCtlDiff";
+
+const TAG_SET_LEVEL: u8 = 0x01;
+const TAG_SET_FLAGS: u8 = 0x02;
+const TAG_RESET_COUNTERS: u8 = 0x03;
+const TAG_SNAPSHOT: u8 = 0x04;
+const TAG_DIFF: u8 = 0x05;
+
+pub const FLAG_PAUSED: u32 = 1 << 0;
+pub const FLAG_RUNTIME_DATA: u32 = 1 << 1;
+pub const FLAG_HOOK_REINSTALL: u32 = 1 << 2;
+const KNOWN_FLAGS: u32 = FLAG_PAUSED | FLAG_RUNTIME_DATA | FLAG_HOOK_REINSTALL;
+
+// Index reported when no command failed.
+const NO_COMMAND: u8 = 0xff;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    None = 0,
+    Applied = 1,
+    // The version byte is missing or not CONTROL_VERSION.
+    BadVersion = 2,
+    // A command runs past the end of the data.
+    Truncated = 3,
+    UnknownTag = 4,
+    // A command's length is not the one of its tag.
+    BadLength = 5,
+    // A level or flag that does not exist.
+    BadValue = 6,
+    // More than MAX_CONTROL_SIZE bytes, or none at all.
+    BadSize = 7,
+    // The snapshot to diff against is no longer kept.
+    StaleSnapshot = 8,
+    // The snapshots were in use, by a concurrent call.
+    Busy = 9,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    SetLevel(Level),
+    SetFlags { mask: u32, values: u32 },
+    ResetCounters,
+    Snapshot,
+    Diff(u32),
+}
+
+static FORWARD_POLICY: AtomicU32 = AtomicU32::new(ForwardPolicy::Drop as u32);
+static OUTCOME: AtomicU8 = AtomicU8::new(Outcome::None as u8);
+static FAILED_AT: AtomicU8 = AtomicU8::new(NO_COMMAND);
+static HANDLED: AtomicU32 = AtomicU32::new(0);
+static LAST_SNAPSHOT: AtomicU32 = AtomicU32::new(0);
+
+/**
+ * @brief Changes whether accepted control writes also reach firmware.
+ */
+pub fn set_forward_policy(policy: ForwardPolicy) {
+    FORWARD_POLICY.store(policy as u32, Ordering::Release);
+}
+
+fn forward_policy() -> ForwardPolicy {
+    ForwardPolicy::from_u32(FORWARD_POLICY.load(Ordering::Acquire)).unwrap_or(ForwardPolicy::Drop)
+}
+
+/**
+ * @brief Walks the commands of a control write, passing each to `f`.
+ *        Returns why and at which command it stopped if it is malformed or
+ *        `f` failed.
+ */
+fn parse(
+    data: &[u8],
+    mut f: impl FnMut(Command) -> Result<(), Outcome>,
+) -> Result<(), (Outcome, u8)> {
+    let mut rest = match data {
+        _ if data.len() > MAX_CONTROL_SIZE => return Err((Outcome::BadSize, NO_COMMAND)),
+        [CONTROL_VERSION, rest @ ..] => rest,
+        [_, ..] => return Err((Outcome::BadVersion, NO_COMMAND)),
+        [] => return Err((Outcome::BadSize, NO_COMMAND)),
+    };
+    let mut index = 0u8;
+    while !rest.is_empty() {
+        let fail = |outcome| Err((outcome, index));
+        let (tag, length) = match rest {
+            [tag, length, ..] => (*tag, usize::from(*length)),
+            _ => return fail(Outcome::Truncated),
+        };
+        let payload = match rest.get(2..2 + length) {
+            Some(payload) => payload,
+            None => return fail(Outcome::Truncated),
+        };
+        let command = match (tag, payload) {
+            (TAG_SET_LEVEL, [value]) => match Level::from_u32(u32::from(*value)) {
+                Some(level) => Command::SetLevel(level),
+                None => return fail(Outcome::BadValue),
+            },
+            (TAG_SET_FLAGS, [m0, m1, m2, m3, v0, v1, v2, v3]) => {
+                let mask = u32::from_le_bytes([*m0, *m1, *m2, *m3]);
+                let values = u32::from_le_bytes([*v0, *v1, *v2, *v3]);
+                if mask & !KNOWN_FLAGS != 0 {
+                    return fail(Outcome::BadValue);
+                }
+                Command::SetFlags { mask, values }
+            }
+            (TAG_RESET_COUNTERS, []) => Command::ResetCounters,
+            (TAG_SNAPSHOT, []) => Command::Snapshot,
+            (TAG_DIFF, [i0, i1, i2, i3]) => Command::Diff(u32::from_le_bytes([*i0, *i1, *i2, *i3])),
+            (TAG_SET_LEVEL, _)
+            | (TAG_SET_FLAGS, _)
+            | (TAG_RESET_COUNTERS, _)
+            | (TAG_SNAPSHOT, _)
+            | (TAG_DIFF, _) => return fail(Outcome::BadLength),
+            _ => return fail(Outcome::UnknownTag),
+        };
+        if let Err(outcome) = f(command) {
+            return fail(outcome);
+        }
+        rest = rest.get(2 + length..).unwrap_or(&[]);
+        index = index.saturating_add(1);
+    }
+    Ok(())
+}
+
+/**
+ * @brief Returns the current flags.
+ */
+fn flags() -> u32 {
+    let mut flags = 0;
+    if level::is_paused() {
+        flags |= FLAG_PAUSED;
+    }
+    if safety::runtime_data_access() == RuntimeDataAccess::Allow {
+        flags |= FLAG_RUNTIME_DATA;
+    }
+    if integrity::policy() == IntegrityPolicy::Reinstall {
+        flags |= FLAG_HOOK_REINSTALL;
+    }
+    flags
+}
+
+fn apply(command: Command) -> Result<(), Outcome> {
+    match command {
+        Command::SetLevel(new) => {
+            info!(TAG, "Log level: {:?} -> {:?}", level::level(), new);
+            level::set_level(new);
+            persist::save(new);
+        }
+        Command::SetFlags { mask, values } => {
+            let flags = (flags() & !mask) | (values & mask);
+            debug!(TAG, "Control flags: {:#x}", flags);
+            if flags & FLAG_PAUSED != 0 {
+                level::pause();
+            } else {
+                level::resume();
+            }
+            config::update(|config| {
+                config.runtime_data_access = match flags & FLAG_RUNTIME_DATA {
+                    0 => RuntimeDataAccess::Deny,
+                    _ => RuntimeDataAccess::Allow,
+                };
+                config.hook_integrity_policy = match flags & FLAG_HOOK_REINSTALL {
+                    0 => IntegrityPolicy::Record,
+                    _ => IntegrityPolicy::Reinstall,
+                };
+            });
+        }
+        Command::ResetCounters => {
+            info!(TAG, "Counters reset");
+            reset_counters();
+        }
+        Command::Snapshot => {
+            let id = snapshot::take().map_err(failure)?;
+            info!(TAG, "Statistics snapshot #{}", id);
+            LAST_SNAPSHOT.store(id, Ordering::Release);
+        }
+        Command::Diff(id) => {
+            info!(TAG, "Statistics diff against snapshot #{}", id);
+            snapshot::diff_and_keep(id).map_err(failure)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * @brief Returns the outcome of a snapshot command that failed with `error`.
+ */
+fn failure(error: SnapshotError) -> Outcome {
+    match error {
+        SnapshotError::Unknown => Outcome::BadValue,
+        SnapshotError::Stale => Outcome::StaleSnapshot,
+        SnapshotError::Busy => Outcome::Busy,
+    }
+}
+
+/**
+ * @brief Zeroes the counters of blocked, rejected, hidden and deleted
+ *        writes, lost records, suppressed alerts, skipped records and calls
+ *        by outcome (see counters.rs), which makes every snapshot stale.
+ */
+pub fn reset_counters() {
+    snapshot::invalidate();
+    crate::set_variable::reset_deletion_attempts();
+    crate::counters::reset();
+    crate::overhead::reset();
+    level::reset_skipped();
+    #[cfg(any(test, not(feature = "no-log")))]
+    crate::serial::reset_failures();
+    crate::alerts::reset_counters();
+    #[cfg(feature = "enforce")]
+    {
+        crate::enforce::reset_blocked();
+        crate::lock::reset_rejected();
+        crate::hide::reset_hidden();
+    }
+}
+
+/**
+ * @brief Returns whether the variable is one of the monitor's own, `name`
+ *        under UVM_VENDOR_GUID.
+ */
+fn is_variable(
+    variable_name: *const r_efi::base::Char16,
+    vendor_guid: *const r_efi::base::Guid,
+    name: &str,
+) -> bool {
+    if variable_name.is_null() {
+        return false;
+    }
+    let mut buffer = [0u8; 64];
+    let guid = unsafe { caller::value(vendor_guid) };
+    guid == Some(UVM_VENDOR_GUID) && crate::convert_name(variable_name, &mut buffer) == name
+}
+
+/**
+ * @brief Handles a write to UvmCtl or UvmCtlStatus instead of forwarding
+ *        it. Returns the status to answer with, or None to forward it.
+ */
+pub fn intercept(
+    variable_name: *const r_efi::base::Char16,
+    vendor_guid: *const r_efi::base::Guid,
+    data_size: usize,
+    data: *const core::ffi::c_void,
+) -> Option<efi::Status> {
+    if is_variable(variable_name, vendor_guid, STATUS_VARIABLE)
+        || is_variable(variable_name, vendor_guid, DIFF_VARIABLE)
+    {
+        return Some(efi::Status::SUCCESS);
+    }
+    if !is_variable(variable_name, vendor_guid, CONTROL_VARIABLE) {
+        return None;
+    }
+    // There is nothing stored to delete.
+    if data_size == 0 {
+        return Some(efi::Status::SUCCESS);
+    }
+    let data = match unsafe { caller::bytes(data, core::cmp::min(data_size, MAX_CONTROL_SIZE + 1)) }
+    {
+        Some(data) => data,
+        None => return Some(efi::Status::INVALID_PARAMETER),
+    };
+    let handled = HANDLED.fetch_add(1, Ordering::AcqRel).wrapping_add(1);
+    let result = parse(data, |_| Ok(())).and_then(|()| parse(data, apply));
+    let (outcome, failed_at) = match result {
+        Ok(()) => (Outcome::Applied, NO_COMMAND),
+        Err(failure) => failure,
+    };
+    OUTCOME.store(outcome as u8, Ordering::Release);
+    FAILED_AT.store(failed_at, Ordering::Release);
+    debug!(
+        TAG,
+        "Control write #{} of {} bytes: {:?} at {}", handled, data_size, outcome, failed_at
+    );
+    match (outcome, forward_policy()) {
+        (Outcome::Applied, ForwardPolicy::Forward) => None,
+        (Outcome::Applied, ForwardPolicy::Drop) => Some(efi::Status::SUCCESS),
+        _ => Some(efi::Status::INVALID_PARAMETER),
+    }
+}
+
+/**
+ * @brief Returns the status of the last control write, as read from
+ *        UvmCtlStatus.
+ */
+fn status() -> [u8; STATUS_SIZE] {
+    let mut status = [0u8; STATUS_SIZE];
+    status[0] = CONTROL_VERSION;
+    status[1] = OUTCOME.load(Ordering::Acquire);
+    status[2] = FAILED_AT.load(Ordering::Acquire);
+    status[3] = level::level() as u8;
+    status[4..8].copy_from_slice(&flags().to_le_bytes());
+    status[8..12].copy_from_slice(&HANDLED.load(Ordering::Acquire).to_le_bytes());
+    status[12..16].copy_from_slice(&LAST_SNAPSHOT.load(Ordering::Acquire).to_le_bytes());
+    status
+}
+
+/**
+ * @brief Answers a read of a variable holding `bytes` as GetVariable would.
+ */
+fn answer_read(
+    bytes: &[u8],
+    attributes: *mut u32,
+    data_size: *mut usize,
+    data: *mut core::ffi::c_void,
+) -> efi::Status {
+    if data_size.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    let size = unsafe { data_size.replace(bytes.len()) };
+    if size < bytes.len() {
+        return efi::Status::BUFFER_TOO_SMALL;
+    }
+    if data.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+        if !attributes.is_null() {
+            *attributes = efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+        }
+    }
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Answers a read of UvmCtlStatus or UvmCtlDiff as GetVariable would.
+ *        Returns None for any other variable.
+ */
+pub fn read_status(
+    variable_name: *const r_efi::base::Char16,
+    vendor_guid: *const r_efi::base::Guid,
+    attributes: *mut u32,
+    data_size: *mut usize,
+    data: *mut core::ffi::c_void,
+) -> Option<efi::Status> {
+    if is_variable(variable_name, vendor_guid, STATUS_VARIABLE) {
+        return Some(answer_read(&status(), attributes, data_size, data));
+    }
+    if !is_variable(variable_name, vendor_guid, DIFF_VARIABLE) {
+        return None;
+    }
+    let diff = match snapshot::last_diff() {
+        Ok(Some(diff)) => diff,
+        Ok(None) => return Some(efi::Status::NOT_FOUND),
+        Err(error) => return Some(error.status()),
+    };
+    Some(answer_read(diff.as_bytes(), attributes, data_size, data))
+}
+
+#[cfg(test)]
+pub fn reset() {
+    set_forward_policy(ForwardPolicy::Drop);
+    OUTCOME.store(Outcome::None as u8, Ordering::Release);
+    FAILED_AT.store(NO_COMMAND, Ordering::Release);
+    HANDLED.store(0, Ordering::Release);
+    LAST_SNAPSHOT.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(data: &[u8]) -> Result<std::vec::Vec<Command>, (Outcome, u8)> {
+        let mut commands = std::vec::Vec::new();
+        parse(data, |command| {
+            commands.push(command);
+            Ok(())
+        })
+        .map(|()| commands)
+    }
+
+    #[test]
+    fn commands_are_bounds_checked() {
+        assert_eq!(
+            commands(&[1, 0x01, 1, 2, 0x02, 8, 1, 0, 0, 0, 1, 0, 0, 0, 0x03, 0]),
+            Ok(std::vec![
+                Command::SetLevel(Level::Info),
+                Command::SetFlags {
+                    mask: FLAG_PAUSED,
+                    values: FLAG_PAUSED
+                },
+                Command::ResetCounters,
+            ])
+        );
+        assert_eq!(
+            commands(&[1, 0x04, 0, 0x05, 4, 2, 1, 0, 0]),
+            Ok(std::vec![Command::Snapshot, Command::Diff(0x102)])
+        );
+        assert_eq!(commands(&[1, 0x05, 2, 2, 1]), Err((Outcome::BadLength, 0)));
+        assert_eq!(commands(&[1]), Ok(std::vec![]));
+        assert_eq!(commands(&[]), Err((Outcome::BadSize, NO_COMMAND)));
+        assert_eq!(
+            commands(&[1; MAX_CONTROL_SIZE + 1]),
+            Err((Outcome::BadSize, NO_COMMAND))
+        );
+        assert_eq!(
+            commands(&[2, 0x03, 0]),
+            Err((Outcome::BadVersion, NO_COMMAND))
+        );
+        assert_eq!(commands(&[1, 0x03, 0, 0x01]), Err((Outcome::Truncated, 1)));
+        assert_eq!(commands(&[1, 0x01, 2, 3]), Err((Outcome::Truncated, 0)));
+        assert_eq!(commands(&[1, 0x01, 2, 3, 0]), Err((Outcome::BadLength, 0)));
+        assert_eq!(commands(&[1, 0x01, 1, 4]), Err((Outcome::BadValue, 0)));
+        assert_eq!(
+            commands(&[1, 0x03, 0, 0x02, 8, 8, 0, 0, 0, 0, 0, 0, 0]),
+            Err((Outcome::BadValue, 1))
+        );
+        assert_eq!(commands(&[1, 0x7f, 0]), Err((Outcome::UnknownTag, 0)));
+    }
+}
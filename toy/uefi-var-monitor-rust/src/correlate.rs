@@ -0,0 +1,212 @@
+// uefi-var-monitor-rust/src/correlate.rs
+//
+// Correlation rules: alerts that depend on more than the variable and the
+// kind of access (see rules.rs), namely the boot phase the access happened in
+// and what firmware made of it. Each rule is a row of CORRELATIONS, and an
+// access matching every condition of a row is alerted on with the row's
+// severity and counted against it. Adding a correlation is adding a row.
+//
+// Rows marked `persist` also rewrite the boot-report variable when they
+// match, so that the count survives a boot that is about to end and the next
+// boot can report it (see report.rs).
+
+use crate::alerts::Rule;
+use crate::classify::{self, VariableClass};
+use crate::rules::{self, Access, Severity};
+use crate::set_variable::ReturnAddress;
+use crate::tag::{self, Tag};
+use crate::{GuidFmt, Phase};
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::BOOT;
+
+// What firmware must have made of the access. Not every outcome has a row
+// yet.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+    Any,
+}
+
+pub struct Correlation {
+    pub description: &'static str,
+    // Phase the access must happen in, None for either.
+    pub phase: Option<Phase>,
+    // Whether the rule is about SetVariable rather than GetVariable.
+    pub writes: bool,
+    pub outcome: Outcome,
+    pub class: fn(VariableClass) -> bool,
+    pub severity: Severity,
+    pub persist: bool,
+}
+
+// Index of each row in CORRELATIONS, for its counter.
+pub const RUNTIME_BOOT_CRITICAL_WRITE: usize = 0;
+const CORRELATION_COUNT: usize = 1;
+
+pub const CORRELATIONS: [Correlation; CORRELATION_COUNT] = [
+    // The signature of a bootkit installing itself from the OS.
+    Correlation {
+        description: "Firmware accepted a write to a boot-critical variable at OS runtime",
+        phase: Some(Phase::Runtime),
+        writes: true,
+        outcome: Outcome::Succeeded,
+        class: classify::is_boot_critical,
+        severity: Severity::Critical,
+        persist: true,
+    },
+];
+
+static MATCHES: [AtomicU64; CORRELATION_COUNT] = [AtomicU64::new(0)];
+
+// A variable access as seen by the hooks, after the firmware returned.
+pub struct Event<'a> {
+    pub name: &'a str,
+    pub guid: &'a efi::Guid,
+    pub phase: Phase,
+    pub access: Access,
+    pub efi_status: efi::Status,
+    pub caller: Option<usize>,
+}
+
+impl Correlation {
+    fn matches(&self, class: VariableClass, event: &Event) -> bool {
+        let writes = matches!(event.access, Access::Set { .. });
+        let succeeded = !event.efi_status.is_error();
+        let phase = match self.phase {
+            Some(phase) => phase == event.phase,
+            None => true,
+        };
+        phase
+            && self.writes == writes
+            && match self.outcome {
+                Outcome::Succeeded => succeeded,
+                Outcome::Failed => !succeeded,
+                Outcome::Any => true,
+            }
+            && (self.class)(class)
+    }
+}
+
+/**
+ * @brief Alerts on and counts each correlation `event` matches.
+ */
+pub fn check(event: &Event) {
+    let class = classify::classify(event.name, event.guid);
+    for (correlation, matches) in CORRELATIONS.iter().zip(MATCHES.iter()) {
+        if !correlation.matches(class, event) {
+            continue;
+        }
+        let count = matches.fetch_add(1, Ordering::AcqRel) + 1;
+        rules::emit(
+            Rule::Correlation,
+            correlation.severity,
+            format_args!(
+                "{} (#{}): {} {} {} from {} in {:?}: {:#x}",
+                correlation.description,
+                count,
+                GuidFmt(event.guid),
+                event.name,
+                AccessFmt(event.access),
+                ReturnAddress(event.caller),
+                event.phase,
+                event.efi_status.as_usize(),
+            ),
+        );
+        if correlation.persist {
+            let efi_status = crate::report::write();
+            if efi_status.is_error() {
+                error!(
+                    TAG,
+                    "Boot report not updated : {:#x}",
+                    efi_status.as_usize()
+                );
+            }
+        }
+    }
+}
+
+/**
+ * @brief Returns how many accesses matched the correlation at `index` so far.
+ */
+pub fn matches(index: usize) -> u64 {
+    MATCHES
+        .get(index)
+        .map_or(0, |matches| matches.load(Ordering::Acquire))
+}
+
+struct AccessFmt(Access);
+
+impl core::fmt::Display for AccessFmt {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Access::Get => f.write_str("GetVariable"),
+            Access::Set {
+                attributes,
+                data_size,
+            } => write!(
+                f,
+                "SetVariable Attributes={:08x} Size={:08x}",
+                attributes, data_size
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "profile-minimal", allow(dead_code))]
+pub fn reset() {
+    for matches in MATCHES.iter() {
+        matches.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+
+    fn write(name: &'static str, phase: Phase, efi_status: efi::Status) -> Event<'static> {
+        Event {
+            name,
+            guid: &GLOBAL_VARIABLE_GUID,
+            phase,
+            access: Access::Set {
+                attributes: 0x07,
+                data_size: 2,
+            },
+            efi_status,
+            caller: None,
+        }
+    }
+
+    #[test]
+    fn accepted_runtime_writes_to_boot_critical_variables() {
+        let rule = &CORRELATIONS[RUNTIME_BOOT_CRITICAL_WRITE];
+        let class = |event: &Event| classify::classify(event.name, event.guid);
+        let matching = [
+            write("BootOrder", Phase::Runtime, efi::Status::SUCCESS),
+            write("OsIndications", Phase::Runtime, efi::Status::SUCCESS),
+            write("PK", Phase::Runtime, efi::Status::SUCCESS),
+        ];
+        for event in &matching {
+            assert!(rule.matches(class(event), event), "{}", event.name);
+        }
+
+        let other = [
+            write("BootOrder", Phase::BootServices, efi::Status::SUCCESS),
+            write("BootOrder", Phase::Runtime, efi::Status::WRITE_PROTECTED),
+            write("Timeout", Phase::Runtime, efi::Status::SUCCESS),
+            Event {
+                access: Access::Get,
+                ..write("BootOrder", Phase::Runtime, efi::Status::SUCCESS)
+            },
+        ];
+        for event in &other {
+            assert!(!rule.matches(class(event), event), "{}", event.name);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+// uefi-var-monitor-rust/src/counter.rs
+//
+// The counters behind the statistics the monitor's protocol exports:
+// GetVariable and SetVariable calls by outcome, by data size and by phase,
+// successful calls and their bytes by the storage of the variable, and the
+// flash writes those make. The driver's counters.rs keeps one set, which its
+// hooks count into and its summaries are logged from; how a call is counted,
+// what moves between storages and what a reset zeroes is decided here. The
+// counters are plain atomics, as calls are counted at OS runtime, possibly on
+// several CPUs.
+//
+// Sizes are those of the data read, as left in DataSize, and of the data
+// written, zero for deletions; only successful calls have one. Phases are
+// numbered as PHASE_* in interface/src/protocol.rs.
+
+use crate::top::{self, Access};
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+use uvm_interface::protocol::{
+    outcome, size_bucket, storage_of, CallCounts, FlashWrites, Overhead, Statistics, Stats,
+    StorageCounts, OUTCOMES, PHASES, SIZE_BUCKETS, STORAGES, STORAGE_NON_VOLATILE, STORAGE_UNKNOWN,
+};
+
+struct Calls {
+    by_status: [AtomicU64; OUTCOMES],
+    by_size: [AtomicU64; SIZE_BUCKETS],
+    by_phase: [AtomicU64; PHASES],
+}
+
+impl Calls {
+    const fn new() -> Self {
+        Calls {
+            by_status: [const { AtomicU64::new(0) }; OUTCOMES],
+            by_size: [const { AtomicU64::new(0) }; SIZE_BUCKETS],
+            by_phase: [const { AtomicU64::new(0) }; PHASES],
+        }
+    }
+
+    fn count(&self, efi_status: efi::Status, size: Option<usize>, phase: usize) {
+        self.by_status[outcome(efi_status)].fetch_add(1, Ordering::Relaxed);
+        if let (efi::Status::SUCCESS, Some(size)) = (efi_status, size) {
+            self.by_size[size_bucket(size)].fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(counter) = self.by_phase.get(phase) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn copy(&self) -> CallCounts {
+        fn load<const N: usize>(counters: &[AtomicU64; N]) -> [u64; N] {
+            let mut values = [0; N];
+            for (value, counter) in values.iter_mut().zip(counters.iter()) {
+                *value = counter.load(Ordering::Relaxed);
+            }
+            values
+        }
+        CallCounts {
+            by_status: load(&self.by_status),
+            by_size: load(&self.by_size),
+            by_phase: load(&self.by_phase),
+        }
+    }
+
+    fn reset(&self) {
+        let counters = self
+            .by_status
+            .iter()
+            .chain(&self.by_size)
+            .chain(&self.by_phase);
+        for counter in counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+struct StorageTotals {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+impl StorageTotals {
+    const fn new() -> Self {
+        StorageTotals {
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+        }
+    }
+
+    fn add(&self, counts: &StorageCounts) {
+        self.reads.fetch_add(counts.reads, Ordering::Relaxed);
+        self.writes.fetch_add(counts.writes, Ordering::Relaxed);
+        self.bytes_read
+            .fetch_add(counts.bytes_read, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(counts.bytes_written, Ordering::Relaxed);
+    }
+
+    /**
+     * @brief Takes `counts` off, without going below zero: what is moved
+     *        may have been counted before the last reset.
+     */
+    fn remove(&self, counts: &StorageCounts) {
+        fn sub(counter: &AtomicU64, count: u64) {
+            let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| {
+                Some(value.saturating_sub(count))
+            });
+        }
+        sub(&self.reads, counts.reads);
+        sub(&self.writes, counts.writes);
+        sub(&self.bytes_read, counts.bytes_read);
+        sub(&self.bytes_written, counts.bytes_written);
+    }
+
+    fn copy(&self) -> StorageCounts {
+        StorageCounts {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.reads,
+            &self.writes,
+            &self.bytes_read,
+            &self.bytes_written,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Counters {
+    get_variable: Calls,
+    set_variable: Calls,
+    by_storage: [StorageTotals; STORAGES],
+    flash_writes: AtomicU64,
+    flash_bytes: AtomicU64,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters {
+            get_variable: Calls::new(),
+            set_variable: Calls::new(),
+            by_storage: [const { StorageTotals::new() }; STORAGES],
+            flash_writes: AtomicU64::new(0),
+            flash_bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn calls_of(&self, access: Access) -> &Calls {
+        match access {
+            Access::Read => &self.get_variable,
+            Access::Write => &self.set_variable,
+        }
+    }
+
+    /**
+     * @brief Counts a call in `phase` that returned `efi_status`, with the
+     *        size of the data read or written.
+     */
+    pub fn count(
+        &self,
+        access: Access,
+        efi_status: efi::Status,
+        size: Option<usize>,
+        phase: usize,
+    ) {
+        self.calls_of(access).count(efi_status, size, phase);
+    }
+
+    fn add_to_storage(&self, storage: u32, counts: &StorageCounts) {
+        if let Some(totals) = self.by_storage.get(storage as usize) {
+            totals.add(counts);
+        }
+        if storage == STORAGE_NON_VOLATILE {
+            self.flash_writes
+                .fetch_add(counts.writes, Ordering::Relaxed);
+            self.flash_bytes
+                .fetch_add(counts.bytes_written, Ordering::Relaxed);
+        }
+    }
+
+    /**
+     * @brief Counts a successful call of `size` bytes by the storage of
+     *        the variable, from the attributes it returned or was given, 0 if
+     *        none.
+     */
+    pub fn count_storage(&self, access: Access, attributes: u32, size: usize) {
+        self.add_to_storage(storage_of(attributes), &top::one_call(access, size));
+    }
+
+    /**
+     * @brief Moves calls counted as of unknown storage to `storage`, now
+     *        that the variable's attributes are known.
+     */
+    pub fn reclassify(&self, storage: u32, counts: &StorageCounts) {
+        if storage == STORAGE_UNKNOWN {
+            return;
+        }
+        self.by_storage[STORAGE_UNKNOWN as usize].remove(counts);
+        self.add_to_storage(storage, counts);
+    }
+
+    /**
+     * @brief Returns the calls counted for `access`.
+     */
+    pub fn calls(&self, access: Access) -> CallCounts {
+        self.calls_of(access).copy()
+    }
+
+    /**
+     * @brief Returns the successful calls counted by storage, indexed by
+     *        STORAGE_*.
+     */
+    pub fn by_storage(&self) -> [StorageCounts; STORAGES] {
+        core::array::from_fn(|storage| self.by_storage[storage].copy())
+    }
+
+    /**
+     * @brief Returns the successful writes to non-volatile variables
+     *        counted.
+     */
+    pub fn flash_writes(&self) -> FlashWrites {
+        FlashWrites {
+            writes: self.flash_writes.load(Ordering::Relaxed),
+            bytes: self.flash_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /**
+     * @brief Returns everything counted, with the counters kept elsewhere,
+     *        as get_statistics copies it.
+     */
+    pub fn statistics(&self, stats: Stats, paused_skipped: u64, overhead: Overhead) -> Statistics {
+        Statistics {
+            format: Statistics::FORMAT,
+            stats,
+            get_variable: self.get_variable.copy(),
+            set_variable: self.set_variable.copy(),
+            paused_skipped,
+            overhead,
+            by_storage: self.by_storage(),
+            flash_writes: self.flash_writes(),
+        }
+    }
+
+    /**
+     * @brief Zeroes the counters, all but the flash writes.
+     */
+    pub fn reset(&self) {
+        self.get_variable.reset();
+        self.set_variable.reset();
+        for totals in self.by_storage.iter() {
+            totals.reset();
+        }
+    }
+
+    /**
+     * @brief Zeroes the flash writes too, for tests that count them.
+     */
+    pub fn reset_flash_writes(&self) {
+        self.flash_writes.store(0, Ordering::Relaxed);
+        self.flash_bytes.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Counters::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uvm_interface::protocol::{
+        OUTCOME_BUFFER_TOO_SMALL, OUTCOME_OTHER, OUTCOME_SUCCESS, OUTCOME_WRITE_PROTECTED,
+        PHASE_BOOT_SERVICES, PHASE_RUNTIME, STORAGE_VOLATILE,
+    };
+
+    #[test]
+    fn calls_are_counted_by_outcome_size_and_phase() {
+        let counters = Counters::new();
+        let phase = PHASE_BOOT_SERVICES;
+        counters.count(Access::Read, efi::Status::SUCCESS, Some(4), phase);
+        counters.count(Access::Read, efi::Status::BUFFER_TOO_SMALL, Some(4), phase);
+        counters.count(Access::Read, efi::Status::ABORTED, None, PHASE_RUNTIME);
+        counters.count(Access::Write, efi::Status::SUCCESS, Some(0), phase);
+        counters.count(
+            Access::Write,
+            efi::Status::WRITE_PROTECTED,
+            Some(300),
+            phase,
+        );
+
+        let statistics = counters.statistics(Stats::default(), 0, Overhead::NOT_COUNTED);
+        let get = statistics.get_variable;
+        assert_eq!(get.by_status[OUTCOME_SUCCESS], 1);
+        assert_eq!(get.by_status[OUTCOME_BUFFER_TOO_SMALL], 1);
+        assert_eq!(get.by_status[OUTCOME_OTHER], 1);
+        assert_eq!(get.by_size.iter().sum::<u64>(), 1);
+        assert_eq!(get.by_size[size_bucket(4)], 1);
+        assert_eq!(
+            (
+                get.by_phase[PHASE_BOOT_SERVICES],
+                get.by_phase[PHASE_RUNTIME]
+            ),
+            (2, 1)
+        );
+        let set = statistics.set_variable;
+        assert_eq!(set.by_status[OUTCOME_WRITE_PROTECTED], 1);
+        assert_eq!((set.by_size[0], set.by_size.iter().sum::<u64>()), (1, 1));
+
+        counters.reset();
+        assert_eq!(counters.calls(Access::Write), CallCounts::default());
+    }
+
+    #[test]
+    fn successful_calls_are_counted_by_storage() {
+        let counters = Counters::new();
+        counters.count_storage(Access::Read, 0x6, 4);
+        counters.count_storage(Access::Write, 0x7, 8);
+        counters.count_storage(Access::Write, 0, 0);
+        counters.count_storage(Access::Read, 0, 16);
+
+        let by_storage = counters.by_storage();
+        assert_eq!(
+            by_storage[STORAGE_VOLATILE as usize],
+            StorageCounts {
+                reads: 1,
+                bytes_read: 4,
+                ..StorageCounts::default()
+            }
+        );
+        assert_eq!(by_storage[STORAGE_NON_VOLATILE as usize].bytes_written, 8);
+        assert_eq!(by_storage[STORAGE_UNKNOWN as usize].writes, 1);
+        assert_eq!(
+            counters.flash_writes(),
+            FlashWrites {
+                writes: 1,
+                bytes: 8
+            }
+        );
+
+        // The deletion and the read turn out to be of a non-volatile
+        // variable.
+        let mut moved = top::one_call(Access::Write, 0);
+        moved.reads = 1;
+        moved.bytes_read = 16;
+        counters.reclassify(STORAGE_NON_VOLATILE, &moved);
+        let by_storage = counters.by_storage();
+        assert_eq!(
+            by_storage[STORAGE_UNKNOWN as usize],
+            StorageCounts::default()
+        );
+        assert_eq!(
+            by_storage[STORAGE_NON_VOLATILE as usize],
+            StorageCounts {
+                reads: 1,
+                writes: 2,
+                bytes_read: 16,
+                bytes_written: 8,
+            }
+        );
+        assert_eq!(
+            counters.flash_writes(),
+            FlashWrites {
+                writes: 2,
+                bytes: 8
+            }
+        );
+
+        // A reset leaves the flash writes of the boot.
+        counters.reset();
+        assert_eq!(counters.by_storage(), [StorageCounts::default(); STORAGES]);
+        assert_eq!(counters.flash_writes().writes, 2);
+        counters.reset_flash_writes();
+        assert_eq!(counters.flash_writes(), FlashWrites::default());
+    }
+}
@@ -0,0 +1,152 @@
+// uefi-var-monitor-rust/src/counters.rs
+//
+// GetVariable and SetVariable calls counted by outcome, by data size and by
+// phase, for the statistics the monitor's protocol exports (see protocol.rs
+// and interface/src/protocol.rs). Like the totals of top.rs, only calls with
+// a variable name are counted, by the hooks once the call returned, into the
+// counters of counter.rs in the library.
+//
+// Successful calls are also counted, with their bytes, by the storage of the
+// variable: volatile or non-volatile by the attributes the call returned or
+// was given, unknown for a read without an Attributes buffer or a deletion
+// without attributes. Such calls count as unknown until the table of top.rs
+// learns the variable's storage, and then move to it; those to a variable
+// that left the table, or never made it in, stay unknown.
+//
+// Successful writes to non-volatile variables, deletions included, also feed
+// the flash write pressure logged at ExitBootServices: the writes the boot
+// made the flash part take, before the OS had a say.
+//
+// The calls counted so far are logged at each ReadyToBoot (see report.rs).
+// Traced at level trace with an empty filter and no latency budget, each has
+// its G: or S: record, which tools/smoke checks the counts against.
+//
+// reset_statistics zeroes these along with the other counters, unless
+// UVM_STATS_RESET denies it (see settings.rs in the library): by default, a
+// counter only goes up until the next boot.
+
+use crate::level;
+use crate::tag::{self, Tag};
+use crate::top::{self, Access};
+use crate::{stats, Phase};
+use core::sync::atomic::{AtomicU32, Ordering};
+use r_efi::efi;
+use uefi_var_monitor::counter::Counters;
+use uvm_interface::protocol::{
+    CallCounts, FlashWrites, Statistics, OUTCOME_SUCCESS, PHASE_BOOT_SERVICES, PHASE_RUNTIME,
+    STORAGE_UNKNOWN,
+};
+
+pub use uefi_var_monitor::settings::ResetPolicy;
+
+const TAG: Tag = tag::HOOK;
+
+static RESET_POLICY: AtomicU32 = AtomicU32::new(ResetPolicy::Deny as u32);
+
+pub fn set_reset_policy(policy: ResetPolicy) {
+    RESET_POLICY.store(policy as u32, Ordering::Release);
+}
+
+pub fn reset_policy() -> ResetPolicy {
+    ResetPolicy::from_u32(RESET_POLICY.load(Ordering::Acquire)).unwrap_or(ResetPolicy::Deny)
+}
+
+static COUNTERS: Counters = Counters::new();
+
+/**
+ * @brief Counts a call that returned `efi_status`, with the size of the data
+ *        read or written.
+ */
+pub fn count(access: Access, efi_status: efi::Status, size: Option<usize>) {
+    let phase = match crate::phase() {
+        Phase::BootServices => PHASE_BOOT_SERVICES,
+        Phase::Runtime => PHASE_RUNTIME,
+    };
+    COUNTERS.count(access, efi_status, size, phase);
+}
+
+/**
+ * @brief Counts a successful call of `size` bytes by the storage of
+ *        the variable, from the attributes it returned or was given, 0 if none.
+ */
+pub fn count_storage(access: Access, attributes: u32, size: usize) {
+    COUNTERS.count_storage(access, attributes, size);
+}
+
+/**
+ * @brief Notes the storage of a variable in the table of top.rs after a
+ *        successful call, and moves what it says was counted as of unknown
+ *        storage.
+ */
+pub fn note_storage(name: &str, guid: &efi::Guid, access: Access, attributes: u32, size: usize) {
+    if let Some((storage, counts)) = top::note_storage(name, guid, access, attributes, size) {
+        COUNTERS.reclassify(storage, &counts);
+    }
+}
+
+/**
+ * @brief Returns the successful writes to non-volatile variables since load.
+ */
+pub fn flash_writes() -> FlashWrites {
+    COUNTERS.flash_writes()
+}
+
+/**
+ * @brief Logs the flash write pressure of the boot, for the
+ *        ExitBootServices summary.
+ */
+pub fn log_flash_pressure() {
+    let flash = flash_writes();
+    let unknown = COUNTERS.by_storage()[STORAGE_UNKNOWN as usize];
+    info!(
+        TAG,
+        "Flash write pressure: {} writes, {} bytes to non-volatile variables, {} writes of unknown storage",
+        flash.writes,
+        flash.bytes,
+        unknown.writes
+    );
+}
+
+/**
+ * @brief Logs the GetVariable and SetVariable calls counted so far, and how
+ *        many succeeded, for the ReadyToBoot summary.
+ */
+pub fn log_calls() {
+    fn total(counts: &CallCounts) -> u64 {
+        counts.by_phase.iter().sum()
+    }
+    let get_variable = COUNTERS.calls(Access::Read);
+    let set_variable = COUNTERS.calls(Access::Write);
+    info!(
+        TAG,
+        "Calls at ReadyToBoot: GetVariable={} ({} ok) SetVariable={} ({} ok)",
+        total(&get_variable),
+        get_variable.by_status[OUTCOME_SUCCESS],
+        total(&set_variable),
+        set_variable.by_status[OUTCOME_SUCCESS]
+    );
+}
+
+/**
+ * @brief Returns everything counted, as get_statistics copies it.
+ */
+pub fn statistics() -> Statistics {
+    COUNTERS.statistics(
+        stats::collect(),
+        level::skipped(),
+        crate::overhead::statistics(),
+    )
+}
+
+/**
+ * @brief Zeroes the counters, all but the flash writes.
+ */
+pub fn reset() {
+    COUNTERS.reset();
+}
+
+// Only the storage test of main.rs needs it, which profile-minimal skips.
+#[cfg(all(test, not(feature = "profile-minimal")))]
+pub fn reset_flash_writes() {
+    COUNTERS.reset_flash_writes();
+}
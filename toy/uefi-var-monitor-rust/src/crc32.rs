@@ -0,0 +1,38 @@
+// uefi-var-monitor-rust/src/crc32.rs
+//
+// The CRC32 used in EFI table headers (IEEE 802.3, reflected, as computed by
+// the CalculateCrc32 boot service). Table headers still have to be updated
+// after ExitBootServices, when that service is gone. Bitwise rather than
+// table-driven, as it only ever runs over a few hundred bytes.
+
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/**
+ * @brief Returns the CRC32 of `data`.
+ */
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (POLYNOMIAL & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_reference_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414f_a339
+        );
+    }
+}
@@ -0,0 +1,254 @@
+// uefi-var-monitor-rust/src/decide.rs
+//
+// What the hooks make of an access, as pure functions of what is known about
+// it and of the settings in force, so that the precedence between the
+// features that hold a record back or let it through is written down once.
+// The hooks ask in two steps, as the first answer lets them skip decoding
+// the name:
+//
+//   plan()     before the name, from the build profile, the latency guard's
+//              step (see budget.rs), the level and the pause (see level.rs)
+//   record()   once the name is known, from the trace filter's answer for it
+//              (see filter.rs), how the newcomer table saw it (see
+//              newcomer.rs) and, for reads in changes mode, how its data
+//              compares with the last read (see seen.rs)
+//
+// From the most binding down:
+//
+//   1. profile-minimal and the counters-only step: the access is counted by
+//      outcome and GUID, and nothing else is done. No name, no record, no
+//      check, no alert, not even for a new variable. So is a read when
+//      only critical alerts can be written, at the critical level or
+//      paused, unless a critical check watches the variable (see watched()
+//      in main.rs); a new variable read meanwhile is first seen later.
+//   2. The first sighting of a new variable: the record is written tagged
+//      NEW, at the info level, whatever the reduced step, the level, the
+//      trace filter or changes mode say.
+//   3. The reduced step and a level above trace: no G: or S: record. Checks
+//      and alerts still run; the level then decides which alerts are
+//      written, and the alert manager which are suppressed (see alerts.rs).
+//   4. The trace filter: no G: or S: record for a variable outside it, and
+//      its GUID alone rules it out before the name is decoded.
+//   5. Changes mode: a read returning the data of the last one is not
+//      recorded.
+//   6. Every other write of a new variable is tagged NEW at the trace level
+//      when it is recorded; reads are only tagged on the first sighting.
+//
+// Redaction (see redact.rs) is not decided here, as it never holds a record
+// back: it keeps the data from every feature reading it, so a redacted
+// variable is recorded as any other, without the note on how its data
+// changed. Hidden variables (see hide.rs) are answered before the call is
+// forwarded, and never get this far.
+
+use crate::budget::Step;
+use crate::level::Level;
+use crate::newcomer::Sighting;
+use crate::seen::Content;
+
+/**
+ * @brief How far the hooks go with an access, decided before the name.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Plan {
+    // Whether the name is decoded, and the checks and alerts run: false
+    // counts the access by GUID and stops.
+    pub named: bool,
+    // Whether the G: or S: record may be written, if the trace filter takes
+    // the variable.
+    pub may_trace: bool,
+    // Whether values are decoded for alert lines (see boot_option.rs).
+    pub decode_values: bool,
+    // Whether only critical alerts can be written.
+    pub quiet: bool,
+}
+
+impl Plan {
+    /**
+     * @brief Returns whether to decode the name of a read, `watched` being
+     *        whether a critical check looks at the variable.
+     */
+    pub fn names_read(&self, watched: bool) -> bool {
+        self.named && (watched || !self.quiet)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Record {
+    None,
+    // The G: or S: record, formatted or captured raw with log-deferred.
+    Access,
+    // The record tagged NEW, formatted at once and logged at the level.
+    New(Level),
+}
+
+/**
+ * @brief Returns how far to go with an access under `minimal`
+ *        (profile-minimal), the guard at `step`, the level at `level` and
+ *        logging `paused` or not.
+ */
+pub fn plan(minimal: bool, step: Step, level: Level, paused: bool) -> Plan {
+    let quiet = paused || level == Level::Critical;
+    if minimal || step == Step::CountersOnly {
+        return Plan {
+            named: false,
+            may_trace: false,
+            decode_values: false,
+            quiet,
+        };
+    }
+    Plan {
+        named: true,
+        may_trace: step == Step::Full && level == Level::Trace,
+        decode_values: step == Step::Full,
+        quiet,
+    }
+}
+
+/**
+ * @brief Returns the record of a named access: a read if `read`, else a
+ *        write. `traced` is whether the plan and the trace filter allow the
+ *        G: or S: record, `sighting` how the newcomer table saw the
+ *        variable, and `content` how a read's data compares with the last
+ *        one in changes mode.
+ */
+pub fn record(
+    read: bool,
+    traced: bool,
+    sighting: Option<Sighting>,
+    content: Option<Content>,
+) -> Record {
+    match sighting {
+        Some(Sighting::First) => return Record::New(Sighting::First.level()),
+        Some(sighting) if traced && !read => return Record::New(sighting.level()),
+        _ => {}
+    }
+    if traced && (!read || content != Some(Content::Unchanged)) {
+        Record::Access
+    } else {
+        Record::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGED: Content = Content::Changed { unchanged_reads: 3 };
+
+    // One access: the settings, the filter's answer on the GUID and on the
+    // name, the sighting, the content, then whether the name is decoded and
+    // the records of a read and of a write.
+    struct Row {
+        minimal: bool,
+        step: Step,
+        level: Level,
+        filtered: bool,
+        sighting: Option<Sighting>,
+        content: Option<Content>,
+        named: bool,
+        get: Record,
+        set: Record,
+    }
+
+    const fn row(
+        (minimal, step, level): (bool, Step, Level),
+        filtered: bool,
+        sighting: Option<Sighting>,
+        content: Option<Content>,
+        named: bool,
+        (get, set): (Record, Record),
+    ) -> Row {
+        Row {
+            minimal,
+            step,
+            level,
+            filtered,
+            sighting,
+            content,
+            named,
+            get,
+            set,
+        }
+    }
+
+    const TRACE: (bool, Step, Level) = (false, Step::Full, Level::Trace);
+    const NEW_INFO: Record = Record::New(Level::Info);
+    const NEW_TRACE: Record = Record::New(Level::Trace);
+    const FIRST: Option<Sighting> = Some(Sighting::First);
+    const AGAIN: Option<Sighting> = Some(Sighting::Again);
+
+    #[rustfmt::skip]
+    const TABLE: &[Row] = &[
+        // Everything admitted.
+        row(TRACE, false, None, None, true, (Record::Access, Record::Access)),
+        row(TRACE, false, None, Some(Content::First), true, (Record::Access, Record::Access)),
+        row(TRACE, false, None, Some(CHANGED), true, (Record::Access, Record::Access)),
+        // 1. profile-minimal and counters-only hold back even the first
+        // sighting, which is not even looked up.
+        row((true, Step::Full, Level::Trace), false, FIRST, None, false, (Record::None, Record::None)),
+        row((false, Step::CountersOnly, Level::Trace), false, FIRST, None, false, (Record::None, Record::None)),
+        row((true, Step::Reduced, Level::Critical), true, None, None, false, (Record::None, Record::None)),
+        // 2. The first sighting beats the reduced step, the level, the
+        // filter and changes mode.
+        row((false, Step::Reduced, Level::Trace), false, FIRST, None, true, (NEW_INFO, NEW_INFO)),
+        row((false, Step::Full, Level::Critical), false, FIRST, None, true, (NEW_INFO, NEW_INFO)),
+        row(TRACE, true, FIRST, None, true, (NEW_INFO, NEW_INFO)),
+        row(TRACE, false, FIRST, Some(Content::Unchanged), true, (NEW_INFO, NEW_INFO)),
+        // 3. The reduced step and any level above trace hold back the record.
+        row((false, Step::Reduced, Level::Trace), false, None, None, true, (Record::None, Record::None)),
+        row((false, Step::Full, Level::Info), false, None, None, true, (Record::None, Record::None)),
+        row((false, Step::Full, Level::Warning), false, AGAIN, None, true, (Record::None, Record::None)),
+        // 4. So does the filter.
+        row(TRACE, true, None, None, true, (Record::None, Record::None)),
+        row(TRACE, true, AGAIN, Some(CHANGED), true, (Record::None, Record::None)),
+        // 5. Changes mode holds back unchanged reads; writes have no content.
+        row(TRACE, false, None, Some(Content::Unchanged), true, (Record::None, Record::Access)),
+        // 6. Later writes of a new variable are tagged; later reads are not,
+        // unless unchanged.
+        row(TRACE, false, AGAIN, None, true, (Record::Access, NEW_TRACE)),
+        row(TRACE, false, AGAIN, Some(Content::Unchanged), true, (Record::None, NEW_TRACE)),
+    ];
+
+    #[test]
+    fn records_follow_the_precedence_table() {
+        for (index, row) in TABLE.iter().enumerate() {
+            let plan = plan(row.minimal, row.step, row.level, false);
+            assert_eq!(plan.named, row.named, "row {}", index);
+            // The hooks stop before the name, and so before the newcomer
+            // table, when the plan says so.
+            let (get, set) = if plan.named {
+                let traced = plan.may_trace && !row.filtered;
+                (
+                    record(true, traced, row.sighting, row.content),
+                    record(false, traced, row.sighting, None),
+                )
+            } else {
+                (Record::None, Record::None)
+            };
+            assert_eq!((get, set), (row.get, row.set), "row {}", index);
+        }
+    }
+
+    #[test]
+    fn values_are_decoded_at_full_step_only() {
+        for &level in [Level::Critical, Level::Trace].iter() {
+            assert!(plan(false, Step::Full, level, false).decode_values);
+            assert!(!plan(false, Step::Reduced, level, false).decode_values);
+            assert!(!plan(false, Step::CountersOnly, level, false).decode_values);
+            assert!(!plan(true, Step::Full, level, false).decode_values);
+        }
+    }
+
+    #[test]
+    fn quiet_reads_are_named_only_if_watched() {
+        for &(level, paused) in [(Level::Critical, false), (Level::Trace, true)].iter() {
+            let quiet = plan(false, Step::Full, level, paused);
+            assert!(quiet.quiet);
+            assert!(quiet.names_read(true));
+            assert!(!quiet.names_read(false));
+        }
+        assert!(plan(false, Step::Full, Level::Warning, false).names_read(false));
+        // profile-minimal names no read at all.
+        assert!(!plan(true, Step::Full, Level::Critical, true).names_read(true));
+    }
+}
@@ -0,0 +1,158 @@
+// uefi-var-monitor-rust/src/deferred.rs
+//
+// Deferred formatting of the G: and S: records, with log-deferred. Instead of
+// formatting the record, the hooks copy the access into a raw record of the
+// ring buffer (see interface/src/ring.rs): the vendor GUID, the status, the
+// cycle counter, the sizes or the attributes, and the first
+// ACCESS_NAME_LENGTH characters of the name as passed. Neither core::fmt nor UTF-16
+// decoding runs for it, nor does anything allocate.
+//
+// The text is produced where the record is read, by RecordText:
+//
+//   - during boot services, with ring-dump, the dump timer writes the raw
+//     records stored since its last tick to serial (see dump.rs), one poll
+//     interval late and after any record written directly meanwhile;
+//   - the ring dump, and uvmctl dump decoded on the host with uvmlog.
+//
+// The other sinks do not get these records at all; the syslog datagrams of
+// log-net in particular carry no G: or S: record in this mode. The level,
+// pause and trace filter apply as they do to the formatted records.
+
+use crate::arch::{self, Arch};
+use crate::caller;
+use crate::level::{self, Level};
+#[cfg(any(test, feature = "ring-dump"))]
+use crate::ring::RecordText;
+#[cfg(all(feature = "ring-dump", not(test)))]
+use crate::ring::RECORD_TEXT;
+use crate::ring::{self, RingRecord};
+#[cfg(feature = "ring-dump")]
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+use uvm_interface::ring::{AccessRecord, ACCESS_NAME_LENGTH};
+
+// The sequence number the next drain starts from.
+#[cfg(feature = "ring-dump")]
+static DRAINED: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Stores the raw record of an access of `kind` (RECORD_GET_VARIABLE
+ *        or RECORD_SET_VARIABLE), if trace records are written.
+ */
+pub fn record(
+    kind: u8,
+    variable_name: *const r_efi::base::Char16,
+    guid: &efi::Guid,
+    argument: u32,
+    size: u32,
+    efi_status: efi::Status,
+) {
+    if !level::is_enabled(Level::Trace) {
+        return;
+    }
+    let mut access = AccessRecord {
+        guid: *guid,
+        status: efi_status.as_usize() as u64,
+        cycles: arch::Current::read_cycle_counter().unwrap_or(0),
+        argument,
+        size,
+        name: [0; ACCESS_NAME_LENGTH],
+    };
+    let name = unsafe { caller::name(variable_name, ACCESS_NAME_LENGTH) };
+    for (c, next) in access.name.iter_mut().zip(name.unwrap_or(&[])) {
+        *c = *next;
+    }
+    let record = RingRecord::raw(kind, &access);
+    ring::push_raw(&record);
+    // log! only captures in tests (see logging.rs); so do the raw records, as
+    // the text they stand for.
+    #[cfg(test)]
+    crate::serial::capture(format_args!("{}", RecordText(&record)));
+}
+
+/**
+ * @brief Writes the raw records stored since the last drain to serial.
+ *        Called from the dump timer, and once more when it stops.
+ */
+#[cfg(feature = "ring-dump")]
+pub fn drain() {
+    let header = match ring::header() {
+        Some(header) => header,
+        None => return,
+    };
+    let from = core::cmp::max(DRAINED.load(Ordering::Acquire), header.first_sequence);
+    for sequence in from..header.next_sequence {
+        match ring::copy_record(sequence) {
+            // Tests have no serial port, and captured the text when the
+            // record was stored.
+            #[cfg(not(test))]
+            Some(record) if record.sequence == sequence && record.kind != RECORD_TEXT => {
+                let _ = crate::serial::write_line(format_args!("{}", RecordText(&record)));
+            }
+            _ => {}
+        }
+    }
+    DRAINED.store(header.next_sequence, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ring::{OverflowPolicy, RingBuffer};
+    use uvm_interface::ring::{access_size, RECORD_GET_VARIABLE};
+
+    // Formatting the G: record in the hook against capturing it raw, both
+    // into a ring buffer of their own:
+    //
+    //   cargo test --release --features log-deferred -- --ignored --nocapture deferred_capture
+    #[test]
+    #[ignore]
+    fn deferred_capture_overhead() {
+        const CALLS: u32 = 1_000_000;
+        let guid = crate::classify::GLOBAL_VARIABLE_GUID;
+        let name: std::vec::Vec<u16> = "BootOrder".encode_utf16().chain([0]).collect();
+        let time = |push: &mut dyn FnMut()| {
+            let start = std::time::Instant::now();
+            for _ in 0..CALLS {
+                push();
+            }
+            start.elapsed().as_nanos() as f64 / CALLS as f64
+        };
+
+        let mut ring = RingBuffer::<256>::new(OverflowPolicy::OverwriteOldest);
+        let direct = time(&mut || {
+            let mut buffer = [0u8; 64];
+            let name = crate::convert_name(name.as_ptr() as *mut _, &mut buffer);
+            let text = uvm_interface::hex::guid_text(&guid);
+            let text = core::str::from_utf8(&text).unwrap();
+            let record = uefi_var_monitor::record::get_record(
+                text,
+                Some(0),
+                Some(8),
+                name,
+                efi::Status::BUFFER_TOO_SMALL,
+            );
+            ring.push_fmt(format_args!("{}", record.as_str()));
+        });
+        let mut ring = RingBuffer::<256>::new(OverflowPolicy::OverwriteOldest);
+        let deferred = time(&mut || {
+            let mut access = AccessRecord {
+                guid,
+                status: efi::Status::BUFFER_TOO_SMALL.as_usize() as u64,
+                cycles: arch::Current::read_cycle_counter().unwrap_or(0),
+                argument: access_size(Some(0)),
+                size: access_size(Some(8)),
+                name: [0; ACCESS_NAME_LENGTH],
+            };
+            for (c, next) in access.name.iter_mut().zip(name.iter()) {
+                *c = *next;
+            }
+            ring.push_raw(&RingRecord::raw(RECORD_GET_VARIABLE, &access));
+        });
+        std::println!(
+            "G: record: {:.1} ns formatted, {:.1} ns deferred",
+            direct,
+            deferred
+        );
+    }
+}
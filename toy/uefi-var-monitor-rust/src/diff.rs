@@ -0,0 +1,274 @@
+// uefi-var-monitor-rust/src/diff.rs
+//
+// What an accepted write changed. The values of the traced variables (see
+// filter.rs) are kept as they are read and written, in a last-value table of
+// their own so that they do not push the boot manager variables out of the
+// shared one (see last_value.rs), and a write to a variable whose value is
+// kept is followed by a D: record next to its S: record:
+//
+//   D: <guid> <name> Size=<old>-><new> CRC32=<old>-><new> Changed=+<offset>:<old>><new>,...
+//
+// The byte-level list is only given when both values are at most
+// DIFF_BYTES_SIZE bytes, e.g. BootOrder or a policy byte; an offset past the
+// end of one of them shows "--" on that side. At most MAX_CHANGED_OFFSETS
+// offsets are listed, followed by how many more changed, and the record is
+// cut at DIFF_LINE_SIZE bytes like the G: and S: records. The CRC32 of an
+// old value that was not read whole is not known and shows as "????????".
+// Without a kept value, the record gives the new value alone, in hex if it
+// is small, followed by a guess at what it holds (see hint.rs):
+//
+//   D: <guid> <name> Size=<new> CRC32=<new> New=<bytes> (<hint>)
+//
+// Values are only read where safety.rs allows it. A write that may not be
+// read, an append, whose result is not what was passed, and a deletion
+// forget the kept value, and so does an accepted write the D: record was not
+// wanted for, so that the next diff is never against a stale value. Writes
+// the hook cannot look at all, at the lowest step of the latency guard (see
+// budget.rs), forget every value at the next look.
+
+use crate::crc32;
+use crate::hint;
+use crate::last_value::{self, Value};
+use crate::safety::{self, Inspection};
+use atomic_refcell::AtomicRefCell;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use r_efi::efi;
+use uvm_interface::hex::{self, Case};
+
+pub const MAX_DIFFED: usize = 16;
+// Largest values diffed byte by byte, or shown whole when new.
+pub const DIFF_BYTES_SIZE: usize = 64;
+pub const MAX_CHANGED_OFFSETS: usize = 8;
+pub const DIFF_LINE_SIZE: usize = 256;
+
+const EFI_VARIABLE_APPEND_WRITE: u32 = 0x0000_0040;
+
+pub type DiffLine = hex::Line<DIFF_LINE_SIZE>;
+
+static VALUES: AtomicRefCell<last_value::Table<MAX_DIFFED>> =
+    AtomicRefCell::new(last_value::Table::new());
+// Set when writes went by unseen.
+static MISSED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Appends what changed from `old` to `new`, or `new` alone without an
+ *        old value, after the GUID and name of a D: record.
+ */
+pub fn push_diff<const N: usize>(line: &mut hex::Line<N>, old: Option<&Value>, new: &[u8]) {
+    let crc32 = crc32::crc32(new);
+    let old = match old {
+        Some(old) => old,
+        None => {
+            line.push_str("Size=")
+                .push_hex(new.len() as u64, 8, Case::Lower)
+                .push_str(" CRC32=")
+                .push_hex(u64::from(crc32), 8, Case::Lower);
+            if new.len() <= DIFF_BYTES_SIZE {
+                line.push_str(" New=");
+                for &byte in new {
+                    line.push_hex(u64::from(byte), 2, Case::Lower);
+                }
+                line.push_str(" ");
+                hint::push_hint(line, new);
+            }
+            return;
+        }
+    };
+    line.push_str("Size=")
+        .push_hex(old.size() as u64, 8, Case::Lower)
+        .push_str("->")
+        .push_hex(new.len() as u64, 8, Case::Lower)
+        .push_str(" CRC32=");
+    match old.crc32() {
+        Some(old_crc32) => line.push_hex(u64::from(old_crc32), 8, Case::Lower),
+        None => line.push_str("????????"),
+    };
+    line.push_str("->")
+        .push_hex(u64::from(crc32), 8, Case::Lower);
+    if !old.is_complete() || old.size() > DIFF_BYTES_SIZE || new.len() > DIFF_BYTES_SIZE {
+        return;
+    }
+
+    let old = old.data();
+    let length = core::cmp::max(old.len(), new.len());
+    let changed = (0..length).filter(|&offset| old.get(offset) != new.get(offset));
+    let mut count = 0;
+    for offset in changed {
+        count += 1;
+        if count > MAX_CHANGED_OFFSETS {
+            continue;
+        }
+        line.push_str(if count == 1 { " Changed=+" } else { ",+" })
+            .push_hex(offset as u64, 4, Case::Lower)
+            .push_str(":");
+        push_byte(line, old.get(offset));
+        line.push_str(">");
+        push_byte(line, new.get(offset));
+    }
+    if count == 0 {
+        line.push_str(" Unchanged");
+    } else if count > MAX_CHANGED_OFFSETS {
+        let _ = write!(line, ",{} more", count - MAX_CHANGED_OFFSETS);
+    }
+}
+
+fn push_byte<const N: usize>(line: &mut hex::Line<N>, byte: Option<&u8>) {
+    match byte {
+        Some(&byte) => line.push_hex(u64::from(byte), 2, Case::Lower),
+        None => line.push_str("--"),
+    };
+}
+
+// Takes the table, forgetting every value if writes went by unseen.
+fn values() -> Option<atomic_refcell::AtomicRefMut<'static, last_value::Table<MAX_DIFFED>>> {
+    let mut values = VALUES.try_borrow_mut().ok()?;
+    if MISSED.swap(false, Ordering::AcqRel) {
+        *values = last_value::Table::new();
+    }
+    Some(values)
+}
+
+/**
+ * @brief Keeps the value a traced variable was read with.
+ */
+pub fn observe_read(name: &str, guid: &efi::Guid, data: *const core::ffi::c_void, size: usize) {
+    if let Inspection::Data(data) = safety::inspect(name, guid, data, size, size) {
+        if let Some(mut values) = values() {
+            values.record(name, guid, data, size);
+        }
+    }
+}
+
+/**
+ * @brief Logs what an accepted write to a traced variable changed, and keeps
+ *        the new value.
+ */
+pub fn observe_write(
+    guid_text: &str,
+    name: &str,
+    guid: &efi::Guid,
+    attributes: u32,
+    data: *const core::ffi::c_void,
+    data_size: usize,
+) {
+    let mut values = match values() {
+        Some(values) => values,
+        None => return,
+    };
+    let new = match safety::inspect(name, guid, data, data_size, data_size) {
+        Inspection::Data(new) => new,
+        _ => return values.remove(name, guid),
+    };
+    if attributes & EFI_VARIABLE_APPEND_WRITE != 0
+        || crate::rules::is_deletion(attributes, data_size)
+    {
+        return values.remove(name, guid);
+    }
+    let mut line = DiffLine::new();
+    line.push_str("D: ")
+        .push_str(guid_text)
+        .push_str(" ")
+        .push_str(name)
+        .push_str(" ");
+    push_diff(&mut line, values.get(name, guid), new);
+    values.record(name, guid, new, data_size);
+    drop(values);
+    log_record!("{}", line.as_str());
+}
+
+/**
+ * @brief Forgets the value of a variable written without a D: record.
+ */
+pub fn forget(name: &str, guid: &efi::Guid) {
+    if let Some(mut values) = values() {
+        values.remove(name, guid);
+    }
+}
+
+/**
+ * @brief Notes that writes went by without their names being looked at.
+ */
+pub fn missed() {
+    MISSED.store(true, Ordering::Release);
+}
+
+#[cfg(all(test, not(feature = "profile-minimal")))]
+pub fn reset() {
+    *VALUES.borrow_mut() = last_value::Table::new();
+    MISSED.store(false, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+
+    fn diff(old: Option<(&[u8], usize)>, new: &[u8]) -> std::string::String {
+        let mut table = last_value::Table::<1>::new();
+        if let Some((data, size)) = old {
+            table.record("BootOrder", &GLOBAL_VARIABLE_GUID, data, size);
+        }
+        let mut line = DiffLine::new();
+        push_diff(
+            &mut line,
+            table.get("BootOrder", &GLOBAL_VARIABLE_GUID),
+            new,
+        );
+        line.as_str().into()
+    }
+
+    #[test]
+    fn small_values_are_diffed_byte_by_byte() {
+        let old = [0x01, 0x00, 0x02, 0x00];
+        let new = [0x02, 0x00, 0x01, 0x00, 0x03, 0x00];
+        assert_eq!(
+            diff(Some((&old, old.len())), &new),
+            format!(
+                "Size=00000004->00000006 CRC32={:08x}->{:08x} \
+                 Changed=+0000:01>02,+0002:02>01,+0004:-->03,+0005:-->00",
+                crc32::crc32(&old),
+                crc32::crc32(&new)
+            )
+        );
+        assert!(diff(Some((&new, new.len())), &old).ends_with(",+0004:03>--,+0005:00>--"));
+        assert!(diff(Some((&old, old.len())), &old).ends_with(" Unchanged"));
+    }
+
+    #[test]
+    fn diffs_are_bounded() {
+        let old = [0u8; DIFF_BYTES_SIZE];
+        let new = [0xffu8; DIFF_BYTES_SIZE];
+        let text = diff(Some((&old, old.len())), &new);
+        assert_eq!(text.matches(",+").count(), MAX_CHANGED_OFFSETS - 1);
+        assert!(text.ends_with("+0007:00>ff,56 more"), "{}", text);
+
+        // Larger values, or an old value not read whole: sizes and CRCs only.
+        let large = [0u8; DIFF_BYTES_SIZE + 1];
+        let text = diff(Some((&old, old.len())), &large);
+        assert!(text.ends_with(&format!("->{:08x}", crc32::crc32(&large))));
+        let text = diff(Some((&old[..4], 16)), &new[..4]);
+        assert!(text.starts_with("Size=00000010->00000004 CRC32=????????->"));
+        assert!(!text.contains("Changed"));
+
+        let mut line = DiffLine::new();
+        line.push_str(&"x".repeat(DIFF_LINE_SIZE - 8));
+        let table = last_value::Table::<1>::new();
+        push_diff(&mut line, table.get("", &GLOBAL_VARIABLE_GUID), &new);
+        assert_eq!(line.as_str().len(), DIFF_LINE_SIZE);
+    }
+
+    #[test]
+    fn new_values_are_shown_alone() {
+        assert_eq!(
+            diff(None, &[0x01, 0x00, 0xab]),
+            format!(
+                "Size=00000003 CRC32={:08x} New=0100ab (binary)",
+                crc32::crc32(&[0x01, 0x00, 0xab])
+            )
+        );
+        let large = [0u8; DIFF_BYTES_SIZE + 1];
+        assert!(!diff(None, &large).contains("New="));
+        assert!(diff(None, b"en-US\0").ends_with(" New=656e2d555300 (looks like: ASCII string)"));
+    }
+}
@@ -0,0 +1,437 @@
+// uefi-var-monitor-rust/src/dump.rs
+//
+// On-demand replay of the ring buffer to serial during the boot-services
+// phase. A periodic timer polls two triggers:
+//
+//   - the volatile "UvmDump" variable under UVM_VENDOR_GUID, e.g. from the
+//     UEFI shell: setvar UvmDump -guid 6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90 -bs -rt =01
+//   - the F12 hotkey, once ConIn supports the Simple Text Input Ex protocol.
+//   - a request through the monitor's protocol (see protocol.rs).
+//
+// The F11 hotkey pauses or resumes logging the same way (see level.rs). With
+// log-deferred, the timer also drains the raw records to serial (see
+// deferred.rs).
+//
+// The hotkeys are registered with RegisterKeyNotify rather than polled with
+// ReadKeyStroke, as the latter would consume keystrokes meant for the shell or
+// setup UI. Everything here is torn down at ExitBootServices.
+
+use crate::config::UVM_VENDOR_GUID;
+use crate::level;
+use crate::ring;
+use crate::serial;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use r_efi::efi;
+use r_efi::protocols::simple_text_input_ex;
+
+// Polling interval of the trigger timer in 100ns units (500ms).
+const POLL_INTERVAL: u64 = 5_000_000;
+
+// SCAN_F12
+const DUMP_HOTKEY_SCAN_CODE: u16 = 0x16;
+// SCAN_F11
+const PAUSE_HOTKEY_SCAN_CODE: u16 = 0x15;
+
+// "UvmDump"
+const DUMP_VARIABLE_NAME: [u16; 8] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'D' as u16,
+    b'u' as u16,
+    b'm' as u16,
+    b'p' as u16,
+    0,
+];
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static HOTKEY_PRESSED: AtomicBool = AtomicBool::new(false);
+static PAUSE_HOTKEY_PRESSED: AtomicBool = AtomicBool::new(false);
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static SYSTEM_TABLE: AtomicPtr<efi::SystemTable> = AtomicPtr::new(core::ptr::null_mut());
+static TIMER_EVENT: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+static TEXT_INPUT_EX: AtomicPtr<simple_text_input_ex::Protocol> =
+    AtomicPtr::new(core::ptr::null_mut());
+static HOTKEY_HANDLE: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+static PAUSE_HOTKEY_HANDLE: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+
+/**
+ * @brief Starts the trigger timer. Must be called after the GetVariable hook
+ *        is installed, as the control variable is read through the saved
+ *        original pointer.
+ */
+pub fn start(system_table: &mut efi::SystemTable) -> efi::Status {
+    let boot_services = unsafe { &mut *system_table.boot_services };
+
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let mut efi_status = (boot_services.create_event)(
+        efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_timer,
+        core::ptr::null_mut(),
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+
+    SYSTEM_TABLE.store(system_table, Ordering::Release);
+    ACTIVE.store(true, Ordering::Release);
+    efi_status = (boot_services.set_timer)(event, efi::TimerDelay::TimerPeriodic, POLL_INTERVAL);
+    if efi_status.is_error() {
+        ACTIVE.store(false, Ordering::Release);
+        (boot_services.close_event)(event);
+        return efi_status;
+    }
+    TIMER_EVENT.store(event, Ordering::Release);
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Stops the timer and unregisters the hotkeys. Called at
+ *        ExitBootServices, while boot services are still usable.
+ */
+pub fn stop() {
+    if !ACTIVE.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    // The raw records of the last poll interval.
+    #[cfg(feature = "log-deferred")]
+    crate::deferred::drain();
+    let system_table = SYSTEM_TABLE.load(Ordering::Acquire);
+    let boot_services = unsafe { &mut *(*system_table).boot_services };
+
+    let event = TIMER_EVENT.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    if !event.is_null() {
+        (boot_services.close_event)(event);
+    }
+
+    let text_input_ex = TEXT_INPUT_EX.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    for handle in [&HOTKEY_HANDLE, &PAUSE_HOTKEY_HANDLE].iter() {
+        let hotkey_handle = handle.swap(core::ptr::null_mut(), Ordering::AcqRel);
+        if !text_input_ex.is_null() && !hotkey_handle.is_null() {
+            unsafe { ((*text_input_ex).unregister_key_notify)(text_input_ex, hotkey_handle) };
+        }
+    }
+}
+
+/**
+ * @brief Requests a dump, which runs from the timer like the other
+ *        triggers. Fails once the timer has been stopped.
+ */
+pub fn request() -> efi::Status {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return efi::Status::NOT_READY;
+    }
+    REQUESTED.store(true, Ordering::Release);
+    efi::Status::SUCCESS
+}
+
+efiapi! {
+    /**
+     * @brief Records the hotkey press; the dump itself runs from the timer.
+     */
+    fn handle_hotkey(_key_data: *mut simple_text_input_ex::KeyData) -> efi::Status {
+        HOTKEY_PRESSED.store(true, Ordering::Release);
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Records the pause hotkey press; the pause itself is toggled
+     *        from the timer.
+     */
+    fn handle_pause_hotkey(_key_data: *mut simple_text_input_ex::KeyData) -> efi::Status {
+        PAUSE_HOTKEY_PRESSED.store(true, Ordering::Release);
+        efi::Status::SUCCESS
+    }
+}
+
+/**
+ * @brief Registers `handler` for the key with `scan_code`. Returns the
+ *        notification handle, null on failure.
+ */
+fn register_key(
+    text_input_ex: *mut simple_text_input_ex::Protocol,
+    scan_code: u16,
+    handler: simple_text_input_ex::KeyNotifyFunction,
+) -> *mut core::ffi::c_void {
+    let mut key_data = simple_text_input_ex::KeyData::default();
+    key_data.key.scan_code = scan_code;
+    let mut hotkey_handle: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = unsafe {
+        ((*text_input_ex).register_key_notify)(
+            text_input_ex,
+            &mut key_data,
+            handler,
+            &mut hotkey_handle,
+        )
+    };
+    if efi_status.is_error() {
+        return core::ptr::null_mut();
+    }
+    hotkey_handle
+}
+
+/**
+ * @brief Registers the hotkeys once ConIn provides Simple Text Input Ex.
+ */
+fn try_register_hotkey(system_table: &mut efi::SystemTable) {
+    if !TEXT_INPUT_EX.load(Ordering::Acquire).is_null() || system_table.console_in_handle.is_null()
+    {
+        return;
+    }
+    let boot_services = unsafe { &mut *system_table.boot_services };
+
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.handle_protocol)(
+        system_table.console_in_handle,
+        &simple_text_input_ex::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        return;
+    }
+
+    let text_input_ex = interface as *mut simple_text_input_ex::Protocol;
+    let hotkey_handle = register_key(text_input_ex, DUMP_HOTKEY_SCAN_CODE, handle_hotkey);
+    if hotkey_handle.is_null() {
+        return;
+    }
+    HOTKEY_HANDLE.store(hotkey_handle, Ordering::Release);
+    // The dump hotkey alone is enough to keep going.
+    let pause_handle = register_key(text_input_ex, PAUSE_HOTKEY_SCAN_CODE, handle_pause_hotkey);
+    PAUSE_HOTKEY_HANDLE.store(pause_handle, Ordering::Release);
+    TEXT_INPUT_EX.store(text_input_ex, Ordering::Release);
+}
+
+/**
+ * @brief Returns whether the control variable is set, and deletes it so that
+ *        each setvar triggers exactly one dump.
+ */
+fn take_variable_trigger(system_table: &mut efi::SystemTable) -> bool {
+    let mut name = DUMP_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    let mut attributes = 0u32;
+    let mut value = 0u8;
+    let mut data_size = core::mem::size_of_val(&value);
+    let efi_status = crate::GET_VARIABLE.call(
+        name.as_mut_ptr(),
+        &mut guid,
+        &mut attributes,
+        &mut data_size,
+        &mut value as *mut _ as *mut core::ffi::c_void,
+    );
+    if efi_status == efi::Status::NOT_FOUND {
+        return false;
+    }
+
+    // Zero attributes and size delete the variable whatever it was created
+    // with.
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
+    (runtime_services.set_variable)(name.as_mut_ptr(), &mut guid, 0, 0, core::ptr::null_mut());
+    !efi_status.is_error() || efi_status == efi::Status::BUFFER_TOO_SMALL
+}
+
+efiapi! {
+    /**
+     * @brief Polls the triggers and replays the ring buffer on demand.
+     */
+    fn handle_timer(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        if !ACTIVE.load(Ordering::Acquire) {
+            return;
+        }
+        let system_table = unsafe { &mut *SYSTEM_TABLE.load(Ordering::Acquire) };
+
+        try_register_hotkey(system_table);
+        if PAUSE_HOTKEY_PRESSED.swap(false, Ordering::AcqRel) {
+            if level::is_paused() {
+                level::resume();
+            } else {
+                level::pause();
+            }
+        }
+        #[cfg(feature = "log-deferred")]
+        crate::deferred::drain();
+        let hotkey = HOTKEY_PRESSED.swap(false, Ordering::AcqRel);
+        let requested = REQUESTED.swap(false, Ordering::AcqRel);
+        if take_variable_trigger(system_table) || hotkey || requested {
+            replay();
+        }
+    }
+}
+
+/**
+ * @brief Writes the records held in the ring buffer to serial, bracketed by
+ *        BEGIN/END markers. The range is snapshotted first; records that are
+ *        overwritten while replaying are reported as lost, and records that
+ *        arrive meanwhile are left for the next dump. The log chain is
+ *        checked along the way, and the first record that does not chain
+ *        from the one before it is reported; a lost record leaves the one
+ *        after it unchecked.
+ */
+fn replay() {
+    let header = match ring::header() {
+        Some(header) => header,
+        None => return,
+    };
+
+    write_line(format_args!(
+        "==== UVM RING DUMP BEGIN #{}..#{} dropped={} overwritten={} ====",
+        header.first_sequence, header.next_sequence, header.dropped, header.overwritten,
+    ));
+    let mut replayed = 0u64;
+    let mut lost = 0u64;
+    let mut previous = Some(header.chain_anchor);
+    let mut first_break = None;
+    for sequence in header.first_sequence..header.next_sequence {
+        match ring::copy_record(sequence) {
+            Some(record) if record.sequence == sequence => {
+                write_line(format_args!("#{} {}", sequence, ring::RecordText(&record)));
+                if let Some(previous) = previous {
+                    if first_break.is_none() && ring::link(&record, &previous) != record.chain {
+                        first_break = Some(sequence);
+                    }
+                }
+                previous = Some(record.chain);
+                replayed += 1;
+            }
+            _ => {
+                previous = None;
+                lost += 1;
+            }
+        }
+    }
+    match first_break {
+        Some(sequence) => write_line(format_args!(
+            "==== UVM RING DUMP END replayed={} lost={} chain=broken@#{} ====",
+            replayed, lost, sequence,
+        )),
+        None => write_line(format_args!(
+            "==== UVM RING DUMP END replayed={} lost={} chain=ok ====",
+            replayed, lost,
+        )),
+    }
+}
+
+// Tests have no serial port; the dump is captured as log! records are (see
+// logging.rs).
+fn write_line(args: core::fmt::Arguments) {
+    #[cfg(not(test))]
+    let _ = serial::write_line(args);
+    #[cfg(test)]
+    serial::capture(args);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, keys, variables, MockFirmware};
+    use std::string::String;
+    use std::vec::Vec;
+
+    // Any non-null handle; the mock's HandleProtocol ignores it.
+    const CONSOLE_IN_HANDLE: efi::Handle = 0x3000 as efi::Handle;
+
+    fn tick() -> String {
+        crate::serial::start_capture();
+        handle_timer(core::ptr::null_mut(), core::ptr::null_mut());
+        crate::serial::take_capture()
+    }
+
+    // Checks that `dump` is one replay framed by its BEGIN and END lines, with
+    // a line for each record replayed, and returns those lines.
+    fn replayed_lines(dump: &str) -> Vec<&str> {
+        let number = |text: &str| text.parse::<u64>().unwrap();
+        let lines: Vec<&str> = dump.lines().collect();
+        let (begin, records, end) = match lines.as_slice() {
+            [begin, records @ .., end] => (*begin, records, *end),
+            _ => panic!("no dump in {:?}", dump),
+        };
+        let range = begin
+            .strip_prefix("==== UVM RING DUMP BEGIN #")
+            .and_then(|rest| rest.split(' ').next())
+            .and_then(|range| range.split_once("..#"))
+            .map(|(first, next)| (number(first), number(next)))
+            .unwrap_or_else(|| panic!("no BEGIN line in {:?}", dump));
+        let counts = end
+            .strip_prefix("==== UVM RING DUMP END replayed=")
+            .and_then(|rest| rest.strip_suffix(" chain=ok ===="))
+            .and_then(|rest| rest.split_once(" lost="))
+            .map(|(replayed, lost)| (number(replayed), number(lost)))
+            .unwrap_or_else(|| panic!("no intact END line in {:?}", dump));
+        assert_eq!(counts.0 + counts.1, range.1 - range.0);
+        assert_eq!(records.len() as u64, counts.0);
+        assert!(records.iter().all(|record| record.starts_with('#')));
+        records.to_vec()
+    }
+
+    #[test]
+    fn triggers_replay_the_ring_buffer_until_stopped() {
+        let _lock = mock::lock();
+        crate::level::reset();
+        let mut firmware = MockFirmware::new(variables::get_variable);
+        firmware.system_table.console_in_handle = CONSOLE_IN_HANDLE;
+        crate::GET_VARIABLE.set(variables::get_variable);
+
+        assert_eq!(request(), efi::Status::NOT_READY);
+        assert_eq!(start(&mut firmware.system_table), efi::Status::SUCCESS);
+        assert_eq!(mock::open_events(), 1);
+        ring::push_record(format_args!("dump test record 1"));
+        ring::push_record(format_args!("dump test record 2"));
+
+        // The first tick registers F12 and F11, and without a trigger dumps
+        // nothing.
+        assert_eq!(tick(), "");
+        assert_eq!(keys::registered(), 2);
+
+        // The variable is read and deleted, so that it triggers one dump.
+        variables::put("UvmDump", UVM_VENDOR_GUID, 0, &[1]);
+        let dump = tick();
+        let lines = replayed_lines(&dump);
+        assert!(lines[lines.len() - 2].ends_with(" dump test record 1"));
+        assert!(lines[lines.len() - 1].ends_with(" dump test record 2"));
+        assert_eq!(
+            mock::take_writes(),
+            [mock::Write {
+                name: String::from("UvmDump"),
+                guid: UVM_VENDOR_GUID,
+                attributes: 0,
+                data: Vec::new(),
+            }]
+        );
+        variables::clear();
+        assert_eq!(tick(), "");
+
+        // F12 and a request each dump once, including the records since.
+        assert_eq!(keys::press(DUMP_HOTKEY_SCAN_CODE), 1);
+        ring::push_record(format_args!("dump test record 3"));
+        let lines = replayed_lines(&tick()).len();
+        assert!(lines >= 3);
+        assert_eq!(tick(), "");
+        assert_eq!(request(), efi::Status::SUCCESS);
+        assert_eq!(replayed_lines(&tick()).len(), lines);
+        assert_eq!(tick(), "");
+
+        // F11 pauses logging, and again resumes it, without dumping.
+        assert_eq!(keys::press(PAUSE_HOTKEY_SCAN_CODE), 1);
+        assert_eq!(tick(), "---- Logging paused ----\n");
+        assert!(level::is_paused());
+        assert_eq!(keys::press(PAUSE_HOTKEY_SCAN_CODE), 1);
+        assert!(tick().starts_with("---- Logging resumed"));
+        assert!(!level::is_paused());
+
+        // Stopping closes the timer and unregisters both keys; triggers are
+        // ignored from then on.
+        stop();
+        assert_eq!(mock::open_events(), 0);
+        assert_eq!(keys::registered(), 0);
+        assert_eq!(request(), efi::Status::NOT_READY);
+        assert_eq!(keys::press(DUMP_HOTKEY_SCAN_CODE), 0);
+        variables::put("UvmDump", UVM_VENDOR_GUID, 0, &[1]);
+        assert_eq!(tick(), "");
+        assert!(mock::take_writes().is_empty());
+        variables::clear();
+    }
+}
@@ -0,0 +1,152 @@
+// uefi-var-monitor-rust/src/enforce.rs
+//
+// Enforcement mode, only built with the `enforce` feature. SetVariable calls
+// targeting a protected variable are not forwarded to firmware: the hook
+// returns SECURITY_VIOLATION and raises a critical alert instead. Without the
+// feature the hook never blocks anything. Variables can also be hidden from
+// readers (see hide.rs).
+//
+// The protected set is the UVM_PROTECTED list given at build time plus the
+// entries of the "UvmProtect" variable under UVM_VENDOR_GUID, read once at
+// load, before the SetVariable hook is installed. Both are lists as described
+// in pattern.rs:
+//
+//   UVM_PROTECTED="8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*;d719b2cb-3d3a-4596-a3bc-dad00e67656f:dbx"
+//
+// UvmProtect itself is always protected, so that it cannot be rewritten to
+// lift the protection while the driver runs; it has to be provisioned before
+// the driver loads. Under the protect write policy, the default, UvmLevel and
+// UvmConfig are protected too (see persist.rs). How names are compared can
+// only make more writes blocked, never fewer.
+
+use crate::config::UVM_VENDOR_GUID;
+use crate::pattern::{self, PatternTable};
+use crate::persist;
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::ENFORCE;
+
+pub const MAX_PROTECTED: usize = 32;
+
+const PROTECT_VARIABLE: &str = "UvmProtect";
+
+// "UvmProtect"
+const PROTECT_VARIABLE_NAME: [u16; 11] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'P' as u16,
+    b'r' as u16,
+    b'o' as u16,
+    b't' as u16,
+    b'e' as u16,
+    b'c' as u16,
+    b't' as u16,
+    0,
+];
+
+static TABLE: AtomicRefCell<PatternTable<MAX_PROTECTED>> = AtomicRefCell::new(PatternTable::new());
+static BLOCKED: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Loads the protected set. Must be called after the GetVariable hook
+ *        is installed, as UvmProtect is read through the saved original
+ *        pointer, and before the SetVariable hook is.
+ */
+pub fn load() {
+    let mut table = match TABLE.try_borrow_mut() {
+        Ok(table) => table,
+        Err(_) => return,
+    };
+    if let Some(list) = option_env!("UVM_PROTECTED") {
+        let (added, rejected) = table.add_list(list);
+        info!(
+            TAG,
+            "Protected from build: {} entries, {} rejected", added, rejected
+        );
+    }
+
+    let mut buffer = [0u8; pattern::MAX_LIST_SIZE];
+    match crate::read_list_variable(&PROTECT_VARIABLE_NAME, &mut buffer) {
+        Ok(list) => {
+            let (added, rejected) = table.add_list(list);
+            info!(
+                TAG,
+                "Protected from {}: {} entries, {} rejected", PROTECT_VARIABLE, added, rejected
+            );
+        }
+        Err(efi::Status::NOT_FOUND) => {}
+        Err(efi_status) => error!(
+            TAG,
+            "{} not read : {:#x}",
+            PROTECT_VARIABLE,
+            efi_status.as_usize()
+        ),
+    }
+}
+
+/**
+ * @brief Returns whether writes to the variable must be blocked. Fails
+ *        closed if the set is being loaded.
+ */
+pub fn is_protected(name: &str, guid: &efi::Guid) -> bool {
+    if (*guid == UVM_VENDOR_GUID && name == PROTECT_VARIABLE) || persist::is_protected(name, guid) {
+        return true;
+    }
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.matches(name, guid),
+        Err(_) => true,
+    }
+}
+
+/**
+ * @brief Counts a blocked write and returns how many there were so far.
+ */
+pub fn count_blocked() -> u64 {
+    BLOCKED.fetch_add(1, Ordering::AcqRel) + 1
+}
+
+/**
+ * @brief Returns the number of writes blocked so far.
+ */
+pub fn blocked() -> u64 {
+    BLOCKED.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Zeroes the count of blocked writes.
+ */
+pub fn reset_blocked() {
+    BLOCKED.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *TABLE.borrow_mut() = PatternTable::new();
+    BLOCKED.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn protect(list: &str) {
+    TABLE.borrow_mut().add_list(list);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configuration_variable_is_always_protected() {
+        let _lock = crate::mock::lock();
+        reset();
+        assert!(is_protected("UvmProtect", &UVM_VENDOR_GUID));
+        assert!(!is_protected("UvmDump", &UVM_VENDOR_GUID));
+        assert!(!is_protected(
+            "UvmProtect",
+            &crate::classify::GLOBAL_VARIABLE_GUID
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+// uefi-var-monitor-rust/src/fields.rs
+//
+// The accesses the hooks log, as fields rather than format strings: a call
+// site names what it knows with record! (see macros.rs), and emit() writes it
+// in the form the build writes records in. A field added here is then
+// written by every form, instead of by the one hook that thought of it.
+//
+// Nothing allocates, so the schema is fixed: the service, the variable (its
+// GUID and name as passed, and as rendered, see names.rs), the sizes, the
+// status, and a few typed extras in the order given. The operations, sizes
+// and extras are those of record.rs in the library. The forms:
+//
+//   text     the G: and S: records line() in record.rs writes, the extras
+//            appended; a call with a null name or GUID, and the note of a
+//            read (Op::Accessed), as the lines they replaced
+//   binary   with log-deferred, the raw record of deferred.rs, for a G: or
+//            S: record at the trace level whose extras all write nothing; a
+//            raw record has no room for them, so any other is written as
+//            text, as records tagged NEW always were
+//
+// There is no key=value, CSV or JSON form in this tree: the sinks take the
+// text (the syslog datagrams of net.rs wrap it), and uvmlog parses it.
+
+use crate::level::Level;
+use crate::names::Rendered;
+use r_efi::efi;
+use uefi_var_monitor::record::{self, RecordLine};
+
+pub use uefi_var_monitor::record::{Extra, Op, Size};
+
+// The variable of an access.
+#[derive(Clone, Copy)]
+// Only the binary form reads the name as passed and the GUID.
+#[cfg_attr(not(feature = "log-deferred"), allow(dead_code))]
+pub struct Variable<'a> {
+    // The name as passed, which a raw record copies.
+    pub variable_name: *const r_efi::base::Char16,
+    pub guid: &'a efi::Guid,
+    pub rendered: &'a Rendered,
+}
+
+pub struct Fields<'a> {
+    pub op: Op,
+    // None for a call with a null name or GUID.
+    pub variable: Option<Variable<'a>>,
+    pub size: Size,
+    pub efi_status: efi::Status,
+    pub extras: &'a [Extra],
+}
+
+/**
+ * @brief Writes `fields` at `level`, if records of that level are written.
+ */
+pub fn emit(level: Level, fields: &Fields) {
+    #[cfg(feature = "log-deferred")]
+    if let (Some(variable), Level::Trace, Op::Get | Op::Set) = (fields.variable, level, fields.op) {
+        if fields.extras.iter().all(|extra| extra.is_empty()) {
+            let (kind, argument, size) = match fields.size {
+                Size::Read { before, after } => (
+                    crate::ring::RECORD_GET_VARIABLE,
+                    crate::ring::access_size(before),
+                    crate::ring::access_size(after),
+                ),
+                Size::Write {
+                    attributes,
+                    data_size,
+                } => (
+                    crate::ring::RECORD_SET_VARIABLE,
+                    attributes,
+                    crate::ring::access_size(Some(data_size)),
+                ),
+            };
+            crate::deferred::record(
+                kind,
+                variable.variable_name,
+                variable.guid,
+                argument,
+                size,
+                fields.efi_status,
+            );
+            return;
+        }
+    }
+    log_record_at!(level, "{}", text(fields).as_str());
+}
+
+/**
+ * @brief Returns the text form of `fields`.
+ */
+pub fn text(fields: &Fields) -> RecordLine {
+    let variable = fields
+        .variable
+        .map(|variable| (variable.rendered.guid(), variable.rendered.name()));
+    record::line(
+        fields.op,
+        variable,
+        fields.size,
+        fields.efi_status,
+        fields.extras,
+    )
+}
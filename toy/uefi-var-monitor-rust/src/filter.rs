@@ -0,0 +1,122 @@
+// uefi-var-monitor-rust/src/filter.rs
+//
+// Which variables have their accesses traced. With an empty set, the
+// default, the G: and S: record of every access is written; otherwise only
+// those of variables in the set are. Alerts and all other records are not
+// affected.
+//
+// The set is in the format of pattern.rs. It starts as the one of the build
+// profile (see profile.rs) and is swapped as a whole through the monitor's
+// protocol (see protocol.rs). A list with any malformed entry,
+// or more entries than fit, is rejected and leaves the current set in place.
+// If the set is being swapped, every access is traced.
+
+use crate::pattern::PatternTable;
+use atomic_refcell::AtomicRefCell;
+use core::fmt;
+use r_efi::efi;
+
+pub const MAX_FILTERED: usize = 32;
+
+static TABLE: AtomicRefCell<PatternTable<MAX_FILTERED>> = AtomicRefCell::new(PatternTable::new());
+
+/**
+ * @brief Returns whether accesses to the variable are traced.
+ */
+pub fn is_traced(name: &str, guid: &efi::Guid) -> bool {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => traces(&table, name, guid),
+        Err(_) => true,
+    }
+}
+
+/**
+ * @brief Returns whether `table`, taken as the set, traces accesses to the
+ *        variable.
+ */
+pub fn traces(table: &PatternTable<MAX_FILTERED>, name: &str, guid: &efi::Guid) -> bool {
+    table.is_empty() || table.matches(name, guid)
+}
+
+/**
+ * @brief Returns whether accesses to some variable under `guid` are traced:
+ *        false means is_traced() is false whatever the name, so that it
+ *        need not be decoded.
+ */
+pub fn may_trace(guid: &efi::Guid) -> bool {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.is_empty() || table.has_guid(guid),
+        Err(_) => true,
+    }
+}
+
+/**
+ * @brief Replaces the set with the entries of a ';'-separated list; an empty
+ *        list traces everything again. Returns the number of entries.
+ */
+pub fn replace(list: &str) -> Result<usize, efi::Status> {
+    let mut table = PatternTable::new();
+    let (added, rejected) = table.add_list(list);
+    if rejected != 0 {
+        return Err(efi::Status::INVALID_PARAMETER);
+    }
+    *TABLE.try_borrow_mut().map_err(|_| efi::Status::NOT_READY)? = table;
+    Ok(added)
+}
+
+/**
+ * @brief Sets the build profile's set, given as a list. Called at load.
+ */
+pub fn start(list: &str) {
+    let _ = replace(list);
+}
+
+/**
+ * @brief Returns the number of entries in the set.
+ */
+pub fn len() -> usize {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.len(),
+        Err(_) => 0,
+    }
+}
+
+/**
+ * @brief Writes the set as a list replace() takes back.
+ */
+pub fn write_list(out: &mut impl fmt::Write) -> fmt::Result {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.write_list(out),
+        Err(_) => Err(fmt::Error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guids::{GLOBAL_VARIABLE_GUID, UVM_VENDOR_GUID};
+
+    #[test]
+    fn malformed_lists_leave_the_set_alone() {
+        let guid = &GLOBAL_VARIABLE_GUID;
+        assert_eq!(replace(""), Ok(0));
+        assert!(is_traced("Boot0001", guid));
+
+        assert_eq!(replace("8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*"), Ok(1));
+        assert!(is_traced("Boot0001", guid));
+        assert!(!is_traced("PK", guid));
+        assert!(may_trace(guid));
+        assert!(!may_trace(&UVM_VENDOR_GUID));
+
+        assert_eq!(
+            replace("8be4df61-93ca-11d2-aa0d-00e098032b8c:PK;8be4df61-93ca:KEK"),
+            Err(efi::Status::INVALID_PARAMETER)
+        );
+        assert_eq!(len(), 1);
+        assert!(!is_traced("PK", guid));
+
+        assert_eq!(replace(""), Ok(0));
+        assert!(is_traced("PK", guid));
+        assert!(may_trace(&UVM_VENDOR_GUID));
+    }
+}
@@ -0,0 +1,152 @@
+// uefi-var-monitor-rust/src/footprint.rs
+//
+// What the hooks take from their callers besides time: memory. They run on
+// the stack of whoever calls the runtime services, firmware drivers at boot
+// and the OS after, and there is no heap to take from.
+//
+// No heap. The driver is a no_std binary that does not declare the alloc
+// crate and defines no global allocator. Should anything link alloc anyway,
+// a dependency or a dependency feature, the build fails for want of an
+// allocator, and for std on the duplicate panic handler; the tests below keep
+// both doors shut: neither crate declared and no allocator defined in the
+// sources, and no alloc or std feature asked of a dependency.
+//
+// Little stack. Nothing on the hook paths recurses: a nested GetVariable goes
+// straight to firmware (see main.rs), and the parsers walk their input in
+// loops (see signature_list.rs, load_option.rs). The largest frames on the
+// paths are, with the largest arrays in them:
+//
+//   GetVariable          the rendered name and GUID, 110 bytes (names.rs),
+//                        and the G: record, 224 (main.rs)
+//   SetVariable          the same, with the S: record; the MOR and lock
+//                        checks copy names of up to 64 characters (lock.rs)
+//   GetNextVariableName  two names of 64 bytes; the caller's name, 512
+//                        bytes, is saved in a static buffer (see
+//                        get_next_variable_name.rs)
+//   log!                 with serial-queue, the record is formatted into 256
+//                        bytes before it is queued (queue.rs)
+//
+// An accepted write to a boot-critical variable at OS runtime rewrites the
+// boot report from the SetVariable hook (see correlate.rs), and so does the
+// first access to a new variable from either hook (see newcomer.rs), with the
+// report, 4 KiB with its write history and new variables, on the stack.
+//
+// Outside the hook paths, the configuration blob of 4 KiB is built on the
+// stack of the protocol call that saves it (config_store.rs), the load
+// options are read into 512 bytes at load (options.rs), and the walk of the
+// store at load, and again at ReadyToBoot, reads names into 1 KiB and values
+// into 4 KiB (inventory.rs).
+//
+// The hook tests paint the stack below them, run a hook, and see how deep
+// the paint was overwritten (high_water below). Unoptimized on the host, with
+// every feature, a traced GetVariable went 5.5 KiB deep, an append to db
+// through SetVariable 6.3 KiB, and GetNextVariableName stepping over a hidden
+// name 5 KiB, most of it in core::fmt and the test capture of the records.
+// HOOK_STACK_LIMIT is a tripwire for a large array or a recursion slipping
+// into the hooks, not what firmware sees, which was not measured.
+
+#[cfg(test)]
+mod painted {
+    // Bytes painted below the caller's frame.
+    const PAINTED_SIZE: usize = 256 * 1024;
+    const PAINT: u8 = 0xa5;
+
+    #[inline(never)]
+    fn paint() -> *const u8 {
+        let mut area = [PAINT; PAINTED_SIZE];
+        core::hint::black_box(area.as_mut_ptr()) as *const u8
+    }
+
+    // Keeps `run` out of high_water's own frame, which is above the area.
+    #[inline(never)]
+    fn call(run: impl FnOnce()) {
+        run()
+    }
+
+    /**
+     * @brief Returns how many bytes of stack `run` used, at most, below its
+     *        caller's frame.
+     */
+    #[inline(never)]
+    pub fn high_water(run: impl FnOnce()) -> usize {
+        let area = paint();
+        call(run);
+        // The area is what paint() and run() left below this frame: read
+        // back through a pointer the compiler cannot follow. Not for Miri.
+        let untouched = (0..PAINTED_SIZE)
+            .take_while(|&offset| unsafe { area.add(offset).read_volatile() } == PAINT)
+            .count();
+        PAINTED_SIZE - untouched
+    }
+}
+
+#[cfg(test)]
+pub use painted::high_water;
+
+// Deepest a hook may go in the tests.
+#[cfg(all(test, not(feature = "profile-minimal")))]
+pub const HOOK_STACK_LIMIT: usize = 12 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+    use std::vec::Vec;
+
+    fn sources() -> Vec<(String, String)> {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        let mut sources = Vec::new();
+        let mut directories = std::vec![std::path::PathBuf::from(directory)];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    directories.push(path);
+                } else if path.extension().and_then(|extension| extension.to_str()) == Some("rs") {
+                    let text = std::fs::read_to_string(&path).unwrap();
+                    sources.push((path.display().to_string(), text));
+                }
+            }
+        }
+        sources
+    }
+
+    #[test]
+    fn nothing_links_a_heap() {
+        // Spelled in two, so that this file does not match itself.
+        let forbidden = [
+            ["extern crate ", "alloc"].concat(),
+            ["extern crate ", "std"].concat(),
+            ["#[global_", "allocator]"].concat(),
+        ];
+        for (path, text) in sources() {
+            for word in forbidden.iter() {
+                assert!(!text.contains(word.as_str()), "{} in {}", word, path);
+            }
+        }
+
+        // The tests' own dependencies do not link into the driver.
+        let manifest = include_str!("../Cargo.toml");
+        let dependencies = manifest
+            .split("\n[")
+            .filter(|section| section.contains("dependencies]") && !section.starts_with("dev-"))
+            .flat_map(|section| section.lines().skip(1));
+        for line in dependencies {
+            assert!(
+                !line.contains("\"alloc\"") && !line.contains("\"std\""),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn high_water_sees_a_frame() {
+        let used = high_water(|| {
+            let mut area = [0u8; 4096];
+            core::hint::black_box(&mut area);
+        });
+        assert!((4096..2 * 4096).contains(&used), "{}", used);
+        assert!(high_water(|| {}) < 4096);
+    }
+}
@@ -0,0 +1,533 @@
+// uefi-var-monitor-rust/src/get_next_variable_name.rs
+//
+// The GetNextVariableName hook, only built with the `enforce` feature. It
+// takes the variables hidden by hide.rs out of enumeration, and only forwards
+// calls while nothing is hidden. It is installed, relocated, followed and
+// removed like the SetVariable one (see set_variable.rs).
+//
+// Each call hands firmware the name the previous one returned, so a hidden
+// name cannot just be withheld: the caller would have nothing to continue
+// from. Instead, when firmware returns a hidden name, the hook calls it again
+// with that name, in the caller's buffer and with the caller's buffer size,
+// until a visible name comes back or the enumeration ends, at most MAX_SKIPS
+// times. When it ends, or the next visible name needs a larger buffer, the
+// caller's own name and GUID are put back: no hidden name is left in the
+// caller's buffer, and the retry with a larger buffer steps over the same
+// hidden names again. Only while the call is in progress does the buffer hold
+// them.
+//
+// Asking for the name following a hidden one fails with INVALID_PARAMETER,
+// as it does for a variable that does not exist. Names longer than
+// MAX_INPUT_NAME cannot be put back and are passed through unfiltered.
+//
+// The caller's name is saved in INPUT rather than on the caller's stack (see
+// footprint.rs), held by IN_GET_NEXT_VARIABLE_NAME for the whole call. A call
+// that finds it held, from a notification that interrupted an enumeration,
+// fails with DEVICE_ERROR rather than see a hidden name.
+
+use crate::arch::{self, Arch};
+use crate::caller;
+use crate::hide;
+use crate::hook::HookSlot;
+use crate::set_variable::ReturnAddress;
+use crate::tag::{self, Tag};
+use crate::{
+    GetNextVariableNameType, GuidFmt, Phase, HOOK_ACTIVE, HOOK_PASS_THROUGH, HOOK_UNUSABLE,
+};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::HOOK;
+
+pub static GET_NEXT_VARIABLE_NAME: HookSlot<GetNextVariableNameType> = HookSlot::new();
+
+// What handle_get_next_variable_name may still do after a failed relocation,
+// as HOOK_STATE is for GetVariable.
+static STATE: AtomicU8 = AtomicU8::new(HOOK_ACTIVE);
+
+/**
+ * @brief Returns the state of the hook.
+ */
+pub fn state() -> u8 {
+    STATE.load(Ordering::Acquire)
+}
+
+// Hidden names stepped over in one call before giving up.
+pub const MAX_SKIPS: usize = 256;
+// Longest input name, in characters with the terminator, that can be put
+// back.
+const MAX_INPUT_NAME: usize = 256;
+
+static IN_GET_NEXT_VARIABLE_NAME: AtomicBool = AtomicBool::new(false);
+// Only accessed with IN_GET_NEXT_VARIABLE_NAME held.
+static mut INPUT: [u16; MAX_INPUT_NAME] = [0; MAX_INPUT_NAME];
+
+efiapi! {
+    /**
+     * @brief Handles GetNextVariableName runtime service calls.
+     */
+    pub fn handle_get_next_variable_name(
+        variable_name_size: *mut usize,
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+    ) -> efi::Status {
+        match STATE.load(Ordering::Acquire) {
+            HOOK_ACTIVE => {}
+            HOOK_PASS_THROUGH => {
+                return GET_NEXT_VARIABLE_NAME.call(variable_name_size, variable_name, vendor_guid)
+            }
+            _ => return efi::Status::DEVICE_ERROR,
+        }
+
+        if !hide::is_active()
+            || variable_name_size.is_null()
+            || variable_name.is_null()
+            || vendor_guid.is_null()
+        {
+            return GET_NEXT_VARIABLE_NAME.call(variable_name_size, variable_name, vendor_guid);
+        }
+        let caller = arch::Current::return_address();
+        if IN_GET_NEXT_VARIABLE_NAME.swap(true, Ordering::Acquire) {
+            return efi::Status::DEVICE_ERROR;
+        }
+        let input = unsafe { &mut *core::ptr::addr_of_mut!(INPUT) };
+        let efi_status = filter(variable_name_size, variable_name, vendor_guid, caller, input);
+        IN_GET_NEXT_VARIABLE_NAME.store(false, Ordering::Release);
+        return efi_status;
+    }
+}
+
+/**
+ * @brief Forwards the call, stepping over hidden names, with the caller's
+ *        name saved in `input`.
+ */
+fn filter(
+    variable_name_size: *mut usize,
+    variable_name: *mut r_efi::base::Char16,
+    vendor_guid: *mut r_efi::base::Guid,
+    caller: Option<usize>,
+    input: &mut [u16; MAX_INPUT_NAME],
+) -> efi::Status {
+    let (capacity, input_guid) = match unsafe {
+        (
+            caller::value(variable_name_size),
+            caller::value(vendor_guid),
+        )
+    } {
+        (Some(capacity), Some(guid)) => (capacity, guid),
+        _ => return efi::Status::INVALID_PARAMETER,
+    };
+    let length = match save_name(variable_name, capacity, input) {
+        Some(length) => length,
+        None => return GET_NEXT_VARIABLE_NAME.call(variable_name_size, variable_name, vendor_guid),
+    };
+    let input = input.get(..length).unwrap_or(&[]);
+
+    // An enumeration only reaches a hidden name through the hook, which
+    // never returns one.
+    if length > 1 {
+        let mut name = [0u8; 64];
+        let name = crate::convert_name(input.as_ptr(), &mut name);
+        if hide::is_hidden(name, &input_guid, caller) {
+            log_hidden(name, &input_guid, caller);
+            return efi::Status::INVALID_PARAMETER;
+        }
+    }
+
+    for _ in 0..MAX_SKIPS {
+        let efi_status =
+            GET_NEXT_VARIABLE_NAME.call(variable_name_size, variable_name, vendor_guid);
+        if efi_status != efi::Status::SUCCESS {
+            restore_name(variable_name, capacity, vendor_guid, input, &input_guid);
+            return efi_status;
+        }
+        let mut name = [0u8; 64];
+        let name = crate::convert_name(variable_name, &mut name);
+        let guid = match unsafe { caller::value(vendor_guid) } {
+            Some(guid) => guid,
+            None => return efi::Status::INVALID_PARAMETER,
+        };
+        if !hide::is_hidden(name, &guid, caller) {
+            return efi::Status::SUCCESS;
+        }
+        log_hidden(name, &guid, caller);
+        unsafe { *variable_name_size = capacity };
+    }
+
+    warn!(
+        TAG,
+        "GetNextVariableName from {} gave up after {} hidden names",
+        ReturnAddress(caller),
+        MAX_SKIPS
+    );
+    restore_name(variable_name, capacity, vendor_guid, input, &input_guid);
+    efi::Status::DEVICE_ERROR
+}
+
+/**
+ * @brief Copies the NUL-terminated name in the caller's buffer of `capacity`
+ *        bytes into `saved`. Returns its length with the terminator, or None
+ *        if it is not terminated within the buffer or does not fit.
+ */
+fn save_name(
+    variable_name: *const r_efi::base::Char16,
+    capacity: usize,
+    saved: &mut [u16; MAX_INPUT_NAME],
+) -> Option<usize> {
+    let limit = core::cmp::min(capacity / 2, MAX_INPUT_NAME);
+    for (index, slot) in saved.iter_mut().take(limit).enumerate() {
+        *slot = unsafe { caller::value(variable_name.wrapping_add(index)) }?;
+        if *slot == 0 {
+            return Some(index + 1);
+        }
+    }
+    None
+}
+
+/**
+ * @brief Puts the caller's name and GUID back after stepping over hidden
+ *        names, clearing the rest of the buffer of `capacity` bytes. The name
+ *        was in the same buffer before, so it fits.
+ */
+fn restore_name(
+    variable_name: *mut r_efi::base::Char16,
+    capacity: usize,
+    vendor_guid: *mut r_efi::base::Guid,
+    input: &[u16],
+    input_guid: &efi::Guid,
+) {
+    let chars = input.iter().copied().chain(core::iter::repeat(0));
+    for (index, c) in chars.take(capacity / 2).enumerate() {
+        unsafe { variable_name.add(index).write_unaligned(c) };
+    }
+    unsafe { *vendor_guid = *input_guid };
+}
+
+fn log_hidden(name: &str, guid: &efi::Guid, caller: Option<usize>) {
+    let hidden = hide::count_hidden();
+    log_record!(
+        "N: {} {} hidden from {} (#{})",
+        GuidFmt(guid),
+        name,
+        ReturnAddress(caller),
+        hidden
+    );
+}
+
+fn slot(runtime_services: &mut efi::RuntimeServices) -> *mut *mut core::ffi::c_void {
+    &mut runtime_services.get_next_variable_name as *mut _ as *mut *mut core::ffi::c_void
+}
+
+/**
+ * @brief Hooks GetNextVariableName in the system table's Runtime Services
+ *        Table.
+ */
+pub fn install(system_table: &mut efi::SystemTable) -> efi::Status {
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        slot(runtime_services),
+        handle_get_next_variable_name as GetNextVariableNameType as *mut core::ffi::c_void,
+        GET_NEXT_VARIABLE_NAME.as_mut_ptr(),
+        Phase::BootServices,
+    );
+    if efi_status == efi::Status::ALREADY_STARTED {
+        warn!(TAG, "GetNextVariableName is already hooked by this driver");
+    } else if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+    }
+    efi_status
+}
+
+/**
+ * @brief Puts the saved GetNextVariableName back into the Runtime Services
+ *        Table.
+ */
+pub fn unhook(system_table: &mut efi::SystemTable) -> efi::Status {
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
+    let mut hook: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        slot(runtime_services),
+        GET_NEXT_VARIABLE_NAME.as_raw(),
+        &mut hook,
+        crate::phase(),
+    );
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+    }
+    efi_status
+}
+
+/**
+ * @brief Returns whether the GetNextVariableName slot of a table points at
+ *        our hook.
+ */
+pub fn is_head(runtime_services: &efi::RuntimeServices) -> bool {
+    runtime_services.get_next_variable_name as usize
+        == handle_get_next_variable_name as GetNextVariableNameType as usize
+}
+
+/**
+ * @brief Moves the hook into the republished table `live`. Called by
+ *        follow_runtime_services_table once GetVariable was moved.
+ */
+pub fn follow(system_table: *mut efi::SystemTable, live: *mut efi::RuntimeServices, phase: Phase) {
+    let slot = slot(unsafe { &mut *live });
+    let mut found = unsafe { *slot };
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        slot,
+        handle_get_next_variable_name as GetNextVariableNameType as *mut core::ffi::c_void,
+        &mut found,
+        phase,
+    );
+    if efi_status == efi::Status::ALREADY_STARTED {
+        return;
+    }
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return;
+    }
+    GET_NEXT_VARIABLE_NAME
+        .storage()
+        .store(found, Ordering::Release);
+    info!(
+        TAG,
+        "GetNextVariableName hook moved on top of {:#08x}", found as u64
+    );
+}
+
+/**
+ * @brief Registers GET_NEXT_VARIABLE_NAME for conversion at
+ *        SetVirtualAddressMap.
+ */
+pub fn register_relocations() -> efi::Status {
+    crate::relocate::register("GetNextVariableName", GET_NEXT_VARIABLE_NAME.storage())
+}
+
+/**
+ * @brief Picks what the hook may still do after a failed relocation, as
+ *        set_variable::degrade does.
+ */
+pub fn degrade(runtime_services: &mut efi::RuntimeServices) {
+    if !crate::relocate::failed(GET_NEXT_VARIABLE_NAME.storage()) {
+        STATE.store(HOOK_PASS_THROUGH, Ordering::Release);
+        return;
+    }
+    STATE.store(HOOK_UNUSABLE, Ordering::Release);
+    if !is_head(runtime_services) {
+        warn!(
+            TAG,
+            "GetNextVariableName not restored, {:#08x} is hooked after this driver",
+            runtime_services.get_next_variable_name as usize
+        );
+        return;
+    }
+    let firmware = GET_NEXT_VARIABLE_NAME.as_raw();
+    if firmware.is_null() {
+        return;
+    }
+    let slot = unsafe { &*(slot(runtime_services) as *const AtomicPtr<core::ffi::c_void>) };
+    slot.store(firmware, Ordering::Release);
+    let _ = crate::update_table_crc32(None, &mut runtime_services.hdr);
+    info!(
+        TAG,
+        "GetNextVariableName restored to the firmware's {:#08x}", firmware as u64
+    );
+}
+
+/**
+ * @brief Makes the hook fail every call. Used when SetVirtualAddressMap
+ *        found nothing to convert GET_NEXT_VARIABLE_NAME with.
+ */
+pub fn disable() {
+    STATE.store(HOOK_UNUSABLE, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn reset() {
+    STATE.store(HOOK_ACTIVE, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+    use crate::config::UVM_VENDOR_GUID;
+    use crate::mock::store;
+    use std::string::String;
+    use std::vec::Vec;
+
+    const GLOBAL: efi::Guid = GLOBAL_VARIABLE_GUID;
+    const VENDOR: efi::Guid = UVM_VENDOR_GUID;
+
+    // Calls the hook with a buffer of `capacity` bytes holding `name`.
+    fn next(
+        name: &str,
+        guid: efi::Guid,
+        capacity: usize,
+    ) -> (efi::Status, String, efi::Guid, usize) {
+        let mut buffer = std::vec![0x5555u16; capacity / 2];
+        for (slot, c) in buffer.iter_mut().zip(name.encode_utf16().chain([0])) {
+            *slot = c;
+        }
+        let mut guid = guid;
+        let mut size = capacity;
+        let efi_status = handle_get_next_variable_name(&mut size, buffer.as_mut_ptr(), &mut guid);
+        let end = buffer.iter().position(|c| *c == 0).unwrap_or(buffer.len());
+        let name = String::from_utf16(&buffer[..end]).unwrap();
+        // Nothing is left behind the terminator but what the caller had.
+        if efi_status != efi::Status::SUCCESS {
+            assert!(buffer[end..].iter().all(|c| *c == 0 || *c == 0x5555));
+        }
+        (efi_status, name, guid, size)
+    }
+
+    // Enumerates the whole store through the hook.
+    fn enumerate() -> Vec<(efi::Guid, String)> {
+        let mut names = Vec::new();
+        let (mut name, mut guid) = (String::new(), GLOBAL);
+        loop {
+            let (efi_status, next_name, next_guid, _) = next(&name, guid, 64);
+            if efi_status == efi::Status::NOT_FOUND {
+                // The end leaves the caller's last name in place.
+                assert_eq!((next_name, next_guid), (name, guid));
+                return names;
+            }
+            assert_eq!(efi_status, efi::Status::SUCCESS);
+            names.push((next_guid, next_name.clone()));
+            name = next_name;
+            guid = next_guid;
+        }
+    }
+
+    fn setup() {
+        reset();
+        hide::reset();
+        GET_NEXT_VARIABLE_NAME.set(store::get_next_variable_name);
+        store::fill(&[
+            (VENDOR, "UvmHoney"),
+            (GLOBAL, "Boot0000"),
+            (GLOBAL, "SecureBoot"),
+            (VENDOR, "UvmHoneyKey"),
+            (VENDOR, "UvmTrap"),
+            (GLOBAL, "Timeout"),
+            (VENDOR, "UvmBait"),
+        ]);
+    }
+
+    #[test]
+    fn enumeration_steps_over_hidden_names() {
+        let _lock = crate::mock::lock();
+        setup();
+
+        // Nothing hidden: one firmware call per name, plus the end.
+        assert_eq!(enumerate().len(), 7);
+        assert_eq!(store::calls(), 8);
+
+        // Leading, consecutive and trailing hidden names.
+        hide::hide(
+            "6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90:Uvm*;8be4df61-93ca-11d2-aa0d-00e098032b8c:SecureBoot",
+        );
+        assert_eq!(
+            enumerate(),
+            [(GLOBAL, "Boot0000".into()), (GLOBAL, "Timeout".into())]
+        );
+        assert_eq!(hide::hidden(), 5);
+
+        // Everything hidden.
+        hide::hide("8be4df61-93ca-11d2-aa0d-00e098032b8c:*");
+        assert!(enumerate().is_empty());
+
+        hide::reset();
+    }
+
+    #[test]
+    fn small_buffers_never_hold_hidden_names() {
+        let _lock = crate::mock::lock();
+        setup();
+        hide::hide("8be4df61-93ca-11d2-aa0d-00e098032b8c:SecureBoot");
+
+        // "SecureBoot" fits 22 bytes but "UvmHoneyKey" after it does not: the
+        // size needed is reported with the caller's name put back.
+        let (efi_status, name, guid, size) = next("Boot0000", GLOBAL, 22);
+        assert_eq!(efi_status, efi::Status::BUFFER_TOO_SMALL);
+        assert_eq!((name.as_str(), guid), ("Boot0000", GLOBAL));
+        assert_eq!(size, 24);
+        let (efi_status, name, guid, _) = next("Boot0000", GLOBAL, size);
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        assert_eq!((name.as_str(), guid), ("UvmHoneyKey", VENDOR));
+
+        // A hidden name as input behaves as a variable that does not exist.
+        assert_eq!(
+            next("SecureBoot", GLOBAL, 64).0,
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(next("Bogus", GLOBAL, 64).0, efi::Status::INVALID_PARAMETER);
+
+        hide::reset();
+    }
+
+    #[test]
+    fn endless_hidden_runs_give_up() {
+        let _lock = crate::mock::lock();
+        setup();
+        let names: Vec<&'static str> = (0..MAX_SKIPS + 1)
+            .map(|index| &*std::boxed::Box::leak(std::format!("Uvm{}", index).into_boxed_str()))
+            .collect();
+        let store: Vec<_> = names.iter().map(|name| (VENDOR, *name)).collect();
+        store::fill(&store);
+        hide::hide("6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90:Uvm*");
+
+        let (efi_status, name, guid, size) = next("", GLOBAL, 64);
+        assert_eq!(efi_status, efi::Status::DEVICE_ERROR);
+        assert_eq!((name.as_str(), guid), ("", GLOBAL));
+        assert_eq!(size, 64);
+        assert_eq!(store::calls(), MAX_SKIPS);
+
+        hide::reset();
+    }
+
+    #[test]
+    fn calls_within_a_call_are_refused() {
+        let _lock = crate::mock::lock();
+        setup();
+        hide::hide("8be4df61-93ca-11d2-aa0d-00e098032b8c:SecureBoot");
+
+        IN_GET_NEXT_VARIABLE_NAME.store(true, Ordering::Release);
+        let (efi_status, name, _, _) = next("Boot0000", GLOBAL, 64);
+        assert_eq!(
+            (efi_status, name.as_str()),
+            (efi::Status::DEVICE_ERROR, "Boot0000")
+        );
+        assert_eq!(store::calls(), 0);
+        IN_GET_NEXT_VARIABLE_NAME.store(false, Ordering::Release);
+
+        // The saved name is not on the stack.
+        let mut used = 0;
+        let depth =
+            crate::footprint::high_water(|| used = next("Boot0000", GLOBAL, 64).0.as_usize());
+        assert_eq!(used, efi::Status::SUCCESS.as_usize());
+        assert!(
+            depth < crate::footprint::HOOK_STACK_LIMIT,
+            "{} bytes",
+            depth
+        );
+        assert!(!IN_GET_NEXT_VARIABLE_NAME.load(Ordering::Acquire));
+
+        hide::reset();
+    }
+}
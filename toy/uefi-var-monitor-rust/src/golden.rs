@@ -0,0 +1,362 @@
+// uefi-var-monitor-rust/src/golden.rs
+//
+// The records of a table of accesses, pinned byte for byte against files
+// under fixtures/records:
+//
+//   text.txt      the G: and S: records as the hooks format them (see
+//                 record.rs)
+//   deferred.txt  the same accesses captured raw with log-deferred, as the
+//                 dump timer and uvmlog write them out (RecordText of
+//                 interface/src/ring.rs)
+//
+// and tools/uvmlog/fixtures/records.bin, the raw records as uvmctl dump
+// writes them, which uvmlog's tests decode back to deferred.txt. The table
+// covers the edges: an empty name, names of NAME_LENGTH and
+// ACCESS_NAME_LENGTH characters and past them, names outside ASCII, each
+// class of status, and sizes past 32 bits. The two texts differ on purpose
+// where the raw record keeps less: names are cut at ACCESS_NAME_LENGTH
+// characters rather than NAME_LENGTH and decoded rather than shown with '?',
+// and sizes are capped at 0xfffffffe.
+//
+// A change to a format fails these tests. When it is meant, the files are
+// regenerated with UVM_BLESS set, and the diff reviewed with them:
+//
+//   UVM_BLESS=1 cargo test --lib golden
+//   UVM_BLESS=1 cargo test -p uvmlog golden
+
+use crate::guids::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID, UVM_VENDOR_GUID};
+use crate::record::{convert_name, get_record, set_record, GuidFmt, NAME_LENGTH};
+use r_efi::efi;
+use std::path::Path;
+use uvm_interface::ring::{
+    self, access_size, AccessRecord, RecordText, RingHeader, RingRecord, ACCESS_NAME_LENGTH,
+    RECORD_GET_VARIABLE, RECORD_SET_VARIABLE, RING_CHAIN_SIZE,
+};
+
+enum Access {
+    Get {
+        guid: efi::Guid,
+        name: &'static str,
+        before: Option<usize>,
+        after: Option<usize>,
+        status: efi::Status,
+    },
+    Set {
+        guid: efi::Guid,
+        name: &'static str,
+        attributes: u32,
+        size: usize,
+        status: efi::Status,
+    },
+}
+
+// NAME_LENGTH characters.
+const LONGEST_NAME: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+const PAST_LONGEST_NAME: &str =
+    "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_Overflow";
+// ACCESS_NAME_LENGTH characters.
+const LONGEST_RAW_NAME: &str = "UvmGoldenNameOfThirtyTwoCharacte";
+
+const ZERO_GUID: efi::Guid = efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]);
+const ONES_GUID: efi::Guid =
+    efi::Guid::from_fields(0xffff_ffff, 0xffff, 0xffff, 0xff, 0xff, &[0xff; 6]);
+
+// OEM statuses, with the bit under the error bit set.
+const OEM_ERROR: usize = !(usize::MAX >> 1) | (1 << (usize::BITS - 2)) | 1;
+const OEM_WARNING: usize = (1 << (usize::BITS - 2)) | 1;
+
+const TABLE: [Access; 18] = [
+    // Success, and the usual errors of GetVariable.
+    Access::Get {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "BootOrder",
+        before: Some(0),
+        after: Some(4),
+        status: efi::Status::BUFFER_TOO_SMALL,
+    },
+    Access::Get {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "BootOrder",
+        before: Some(4),
+        after: Some(4),
+        status: efi::Status::SUCCESS,
+    },
+    Access::Get {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "Boot0002",
+        before: Some(0),
+        after: None,
+        status: efi::Status::NOT_FOUND,
+    },
+    // An empty name, and no size at all.
+    Access::Get {
+        guid: UVM_VENDOR_GUID,
+        name: "",
+        before: None,
+        after: None,
+        status: efi::Status::INVALID_PARAMETER,
+    },
+    // Names at and past the lengths kept.
+    Access::Get {
+        guid: UVM_VENDOR_GUID,
+        name: LONGEST_RAW_NAME,
+        before: Some(8),
+        after: Some(8),
+        status: efi::Status::SUCCESS,
+    },
+    Access::Get {
+        guid: UVM_VENDOR_GUID,
+        name: LONGEST_NAME,
+        before: Some(8),
+        after: Some(8),
+        status: efi::Status::SUCCESS,
+    },
+    Access::Set {
+        guid: UVM_VENDOR_GUID,
+        name: PAST_LONGEST_NAME,
+        attributes: 0x7,
+        size: 8,
+        status: efi::Status::SUCCESS,
+    },
+    // Outside ASCII: Latin-1, CJK, and a surrogate pair.
+    Access::Get {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "PlatformLang\u{e9}",
+        before: Some(0x100),
+        after: Some(6),
+        status: efi::Status::SUCCESS,
+    },
+    Access::Set {
+        guid: UVM_VENDOR_GUID,
+        name: "\u{53d8}\u{91cf}",
+        attributes: 0x3,
+        size: 2,
+        status: efi::Status::SUCCESS,
+    },
+    Access::Get {
+        guid: ZERO_GUID,
+        name: "Boot\u{1f600}",
+        before: Some(0),
+        after: Some(0),
+        status: efi::Status::NOT_FOUND,
+    },
+    // The errors of SetVariable.
+    Access::Set {
+        guid: IMAGE_SECURITY_DATABASE_GUID,
+        name: "db",
+        attributes: 0x67,
+        size: 0xa8c,
+        status: efi::Status::SECURITY_VIOLATION,
+    },
+    Access::Set {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "BootNext",
+        attributes: 0x7,
+        size: 2,
+        status: efi::Status::WRITE_PROTECTED,
+    },
+    Access::Set {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "Boot0003",
+        attributes: 0x7,
+        size: 0,
+        status: efi::Status::NOT_FOUND,
+    },
+    // Warnings, and OEM statuses of both kinds.
+    Access::Get {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "ConOut",
+        before: Some(0x40),
+        after: Some(0x3c),
+        status: efi::Status::WARN_BUFFER_TOO_SMALL,
+    },
+    Access::Set {
+        guid: GLOBAL_VARIABLE_GUID,
+        name: "ConOut",
+        attributes: 0x7,
+        size: 0x3c,
+        status: efi::Status::WARN_WRITE_FAILURE,
+    },
+    Access::Get {
+        guid: ONES_GUID,
+        name: "Oem",
+        before: Some(1),
+        after: Some(1),
+        status: efi::Status::from_usize(OEM_ERROR),
+    },
+    // Sizes past 32 bits, every attribute, and the largest status.
+    Access::Get {
+        guid: ONES_GUID,
+        name: "Huge",
+        before: Some(usize::MAX),
+        after: Some(0x1_0000_0000),
+        status: efi::Status::from_usize(OEM_WARNING),
+    },
+    Access::Set {
+        guid: ONES_GUID,
+        name: "Huge",
+        attributes: u32::MAX,
+        size: usize::MAX,
+        status: efi::Status::from_usize(usize::MAX),
+    },
+];
+
+/**
+ * @brief Compares `actual` with the file at `path` under the crate, or with
+ *        UVM_BLESS set, writes it there.
+ */
+fn check_golden(path: &str, actual: &[u8]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(path);
+    if std::env::var_os("UVM_BLESS").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected =
+        std::fs::read(&path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+    if expected == actual {
+        return;
+    }
+    let (expected, actual) = (
+        String::from_utf8_lossy(&expected),
+        String::from_utf8_lossy(actual),
+    );
+    let line = expected
+        .lines()
+        .zip(actual.lines())
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+    panic!(
+        "{} differs from line {}:\n  expected: {:?}\n  actual:   {:?}\n\
+         if the change is meant, rerun with UVM_BLESS=1 (see golden.rs)",
+        path.display(),
+        line + 1,
+        expected.lines().nth(line),
+        actual.lines().nth(line)
+    );
+}
+
+/**
+ * @brief Returns the record of `access` as the hooks format it.
+ */
+fn text(access: &Access) -> String {
+    let mut buffer = [0u8; NAME_LENGTH];
+    let (guid, name) = match access {
+        Access::Get { guid, name, .. } | Access::Set { guid, name, .. } => (guid, name),
+    };
+    let name: Vec<u16> = name.encode_utf16().collect();
+    let name = convert_name(&name, &mut buffer);
+    let guid = GuidFmt(guid).to_string();
+    let line = match *access {
+        Access::Get {
+            before,
+            after,
+            status,
+            ..
+        } => get_record(&guid, before, after, name, status),
+        Access::Set {
+            attributes,
+            size,
+            status,
+            ..
+        } => set_record(&guid, attributes, size, name, status),
+    };
+    line.as_str().into()
+}
+
+/**
+ * @brief Returns the raw record of `access` as the hooks capture it with
+ *        log-deferred (see deferred.rs).
+ */
+fn raw(access: &Access) -> RingRecord {
+    let (kind, guid, name, argument, size, status) = match *access {
+        Access::Get {
+            guid,
+            name,
+            before,
+            after,
+            status,
+        } => (
+            RECORD_GET_VARIABLE,
+            guid,
+            name,
+            access_size(before),
+            access_size(after),
+            status,
+        ),
+        Access::Set {
+            guid,
+            name,
+            attributes,
+            size,
+            status,
+        } => (
+            RECORD_SET_VARIABLE,
+            guid,
+            name,
+            attributes,
+            access_size(Some(size)),
+            status,
+        ),
+    };
+    let mut access = AccessRecord {
+        guid,
+        status: status.as_usize() as u64,
+        cycles: 0,
+        argument,
+        size,
+        name: [0; ACCESS_NAME_LENGTH],
+    };
+    for (c, next) in access.name.iter_mut().zip(name.encode_utf16()) {
+        *c = next;
+    }
+    RingRecord::raw(kind, &access)
+}
+
+/**
+ * @brief Returns the raw records of the table as uvmctl dump writes them:
+ *        numbered from 0 and chained from a zero anchor.
+ */
+fn dump() -> Vec<u8> {
+    let mut header = RingHeader {
+        format: RingHeader::FORMAT,
+        capacity: TABLE.len() as u32,
+        next_sequence: TABLE.len() as u64,
+        ..RingHeader::default()
+    };
+    let mut records = Vec::new();
+    let mut previous = [0u8; RING_CHAIN_SIZE];
+    for (sequence, access) in TABLE.iter().enumerate() {
+        let mut record = raw(access);
+        record.sequence = sequence as u64;
+        record.chain = ring::link(&record, &previous);
+        previous = record.chain;
+        records.push(record);
+    }
+    header.chain_head = previous;
+    let mut bytes = header.as_bytes().to_vec();
+    for record in &records {
+        bytes.extend_from_slice(record.as_bytes());
+    }
+    bytes
+}
+
+#[test]
+fn golden_text_records() {
+    let lines: String = TABLE.iter().map(|access| text(access) + "\n").collect();
+    check_golden("fixtures/records/text.txt", lines.as_bytes());
+}
+
+#[test]
+fn golden_raw_records() {
+    let mut lines = String::new();
+    for access in TABLE.iter() {
+        // Through the bytes, as the ring and dumps hold the record.
+        let record = raw(access);
+        let read = RingRecord::parse(record.as_bytes()).unwrap();
+        assert_eq!(read.access(), record.access());
+        lines += &RecordText(&read).to_string();
+        lines.push('\n');
+    }
+    check_golden("fixtures/records/deferred.txt", lines.as_bytes());
+    check_golden("tools/uvmlog/fixtures/records.bin", &dump());
+}
@@ -0,0 +1,725 @@
+// uefi-var-monitor-rust/src/gop.rs
+//
+// Optional on-screen banner for alert-class records, for machines without a
+// serial console. The banner is drawn across the top of the framebuffer with
+// a built-in 8x8 font. It is only used during the boot-services phase, and
+// silently does nothing if GOP is absent or reports a mode we do not
+// understand.
+//
+// Known 32-bit pixel formats (RGBx, BGRx, and contiguous bit masks) are drawn
+// straight into the framebuffer. BltOnly modes, which have no linear
+// framebuffer, are drawn through GOP's Blt() one glyph cell at a time.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use r_efi::efi;
+use r_efi::protocols::graphics_output;
+
+// Glyphs are scaled up to 16x16 pixels to be readable on high resolutions.
+const GLYPH_SCALE: usize = 2;
+const CELL_SIZE: usize = 8 * GLYPH_SCALE;
+const BANNER_PADDING: usize = 4;
+const BANNER_HEIGHT: usize = CELL_SIZE + 2 * BANNER_PADDING;
+const MAX_TEXT_LENGTH: usize = 160;
+
+const PIXEL_FORMAT_RGBX: u32 = 0;
+const PIXEL_FORMAT_BGRX: u32 = 1;
+const PIXEL_FORMAT_BIT_MASK: u32 = 2;
+const PIXEL_FORMAT_BLT_ONLY: u32 = 3;
+
+const BANNER_BACKGROUND: Rgb = Rgb {
+    red: 0xc0,
+    green: 0x00,
+    blue: 0x00,
+};
+const BANNER_FOREGROUND: Rgb = Rgb {
+    red: 0xff,
+    green: 0xff,
+    blue: 0xff,
+};
+
+// Printable ASCII (U+0020 to U+007E) from the public domain font8x8 by
+// Daniel Hepper. Each byte is one row, top to bottom; bit 0 is the leftmost
+// pixel.
+#[rustfmt::skip]
+const FONT_8X8: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // '#'
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // '%'
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // '('
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // '0'
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // '1'
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // '2'
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // '3'
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // '4'
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // '5'
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // '6'
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // '7'
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // '8'
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // '9'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // ':'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ';'
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // '='
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // '>'
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // '?'
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // '@'
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // 'A'
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // 'B'
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // 'C'
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // 'D'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // 'E'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // 'F'
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // 'L'
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // 'O'
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // 'P'
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // 'Q'
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // 'S'
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // 'Y'
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // 'Z'
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // '['
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // '\\'
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ']'
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // '_'
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // 'b'
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6E, 0x00], // 'd'
+    [0x00, 0x00, 0x1E, 0x33, 0x3f, 0x03, 0x1E, 0x00], // 'e'
+    [0x1C, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0F, 0x00], // 'f'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'g'
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // 'k'
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // 'o'
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // 'p'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // 'r'
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // 's'
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'y'
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // 'z'
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // '}'
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+];
+
+/**
+ * @brief Returns the glyph for the character, or '?' if it has none.
+ */
+fn glyph(c: char) -> &'static [u8; 8] {
+    let index = match c {
+        ' '..='~' => c as usize - 0x20,
+        _ => '?' as usize - 0x20,
+    };
+    &FONT_8X8[index]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelLayout {
+    // Byte 0 is red (PixelRedGreenBlueReserved8BitPerColor).
+    Rgbx,
+    // Byte 0 is blue (PixelBlueGreenRedReserved8BitPerColor).
+    Bgrx,
+    // Arbitrary contiguous masks within a 32-bit pixel (PixelBitMask).
+    BitMask { red: u32, green: u32, blue: u32 },
+}
+
+impl PixelLayout {
+    /**
+     * @brief Returns the layout for the raw GOP pixel format, or None for
+     *        BltOnly and anything we cannot draw into directly.
+     */
+    pub fn from_mode(pixel_format: u32, masks: &graphics_output::PixelBitmask) -> Option<Self> {
+        match pixel_format {
+            PIXEL_FORMAT_RGBX => Some(PixelLayout::Rgbx),
+            PIXEL_FORMAT_BGRX => Some(PixelLayout::Bgrx),
+            PIXEL_FORMAT_BIT_MASK => {
+                let contiguous = |mask: u32| {
+                    mask != 0
+                        && (mask >> mask.trailing_zeros()).trailing_ones() == mask.count_ones()
+                };
+                if !contiguous(masks.red_mask)
+                    || !contiguous(masks.green_mask)
+                    || !contiguous(masks.blue_mask)
+                    || (masks.red_mask & masks.green_mask) != 0
+                    || (masks.red_mask & masks.blue_mask) != 0
+                    || (masks.green_mask & masks.blue_mask) != 0
+                {
+                    return None;
+                }
+                Some(PixelLayout::BitMask {
+                    red: masks.red_mask,
+                    green: masks.green_mask,
+                    blue: masks.blue_mask,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * @brief Encodes the color as a 32-bit framebuffer pixel.
+     */
+    pub fn encode(&self, color: Rgb) -> u32 {
+        match *self {
+            PixelLayout::Rgbx => u32::from_le_bytes([color.red, color.green, color.blue, 0]),
+            PixelLayout::Bgrx => u32::from_le_bytes([color.blue, color.green, color.red, 0]),
+            PixelLayout::BitMask { red, green, blue } => {
+                scale_to_mask(color.red, red)
+                    | scale_to_mask(color.green, green)
+                    | scale_to_mask(color.blue, blue)
+            }
+        }
+    }
+}
+
+/**
+ * @brief Scales an 8-bit channel value to the width of the contiguous mask
+ *        and shifts it into place.
+ */
+fn scale_to_mask(value: u8, mask: u32) -> u32 {
+    let shift = mask.trailing_zeros();
+    let width = mask.count_ones();
+    let scaled = if width >= 8 {
+        u32::from(value) << (width - 8)
+    } else {
+        u32::from(value) >> (8 - width)
+    };
+    (scaled << shift) & mask
+}
+
+/**
+ * @brief Something the banner can be drawn on.
+ */
+trait Surface {
+    fn width(&self) -> usize;
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb);
+    fn draw_glyph(&mut self, x: usize, y: usize, glyph: &[u8; 8], fg: Rgb, bg: Rgb);
+}
+
+/**
+ * @brief A linear 32-bit framebuffer. The constructor validates the geometry
+ *        against the framebuffer size, so drawing never goes out of bounds.
+ */
+struct FrameBuffer {
+    base: *mut u32,
+    width: usize,
+    height: usize,
+    stride: usize,
+    layout: PixelLayout,
+}
+
+impl FrameBuffer {
+    fn new(
+        base: *mut u32,
+        size: usize,
+        width: usize,
+        height: usize,
+        stride: usize,
+        layout: PixelLayout,
+    ) -> Option<Self> {
+        if base.is_null() || width == 0 || height == 0 || stride < width {
+            return None;
+        }
+        let required = stride.checked_mul(height)?.checked_mul(4)?;
+        if required > size {
+            return None;
+        }
+        Some(FrameBuffer {
+            base,
+            width,
+            height,
+            stride,
+            layout,
+        })
+    }
+
+    fn put(&mut self, x: usize, y: usize, pixel: u32) {
+        if x < self.width && y < self.height {
+            unsafe { self.base.add(y * self.stride + x).write_volatile(pixel) };
+        }
+    }
+}
+
+impl Surface for FrameBuffer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let pixel = self.layout.encode(color);
+        for row in y..y + height {
+            for column in x..x + width {
+                self.put(column, row, pixel);
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, x: usize, y: usize, glyph: &[u8; 8], fg: Rgb, bg: Rgb) {
+        let fg = self.layout.encode(fg);
+        let bg = self.layout.encode(bg);
+        for row in 0..CELL_SIZE {
+            let bits = glyph[row / GLYPH_SCALE];
+            for column in 0..CELL_SIZE {
+                let on = (bits >> (column / GLYPH_SCALE)) & 1 != 0;
+                self.put(x + column, y + row, if on { fg } else { bg });
+            }
+        }
+    }
+}
+
+/**
+ * @brief Drawing through Blt() for modes without a linear framebuffer.
+ */
+struct BltSurface {
+    gop: *mut graphics_output::Protocol,
+    width: usize,
+}
+
+fn blt_pixel(color: Rgb) -> graphics_output::BltPixel {
+    graphics_output::BltPixel {
+        blue: color.blue,
+        green: color.green,
+        red: color.red,
+        reserved: 0,
+    }
+}
+
+impl Surface for BltSurface {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let mut pixel = blt_pixel(color);
+        unsafe {
+            ((*self.gop).blt)(
+                self.gop,
+                &mut pixel,
+                graphics_output::BltOperation::BltVideoFill,
+                0,
+                0,
+                x,
+                y,
+                width,
+                height,
+                0,
+            )
+        };
+    }
+
+    fn draw_glyph(&mut self, x: usize, y: usize, glyph: &[u8; 8], fg: Rgb, bg: Rgb) {
+        let fg = blt_pixel(fg);
+        let bg = blt_pixel(bg);
+        let mut cell = [bg; CELL_SIZE * CELL_SIZE];
+        for row in 0..CELL_SIZE {
+            let bits = glyph[row / GLYPH_SCALE];
+            for column in 0..CELL_SIZE {
+                if (bits >> (column / GLYPH_SCALE)) & 1 != 0 {
+                    cell[row * CELL_SIZE + column] = fg;
+                }
+            }
+        }
+        unsafe {
+            ((*self.gop).blt)(
+                self.gop,
+                cell.as_mut_ptr(),
+                graphics_output::BltOperation::BltBufferToVideo,
+                0,
+                0,
+                x,
+                y,
+                CELL_SIZE,
+                CELL_SIZE,
+                0,
+            )
+        };
+    }
+}
+
+/**
+ * @brief Draws the banner with the text, truncated to the screen width.
+ */
+fn draw_banner(surface: &mut dyn Surface, text: &str) {
+    let width = surface.width();
+    surface.fill(0, 0, width, BANNER_HEIGHT, BANNER_BACKGROUND);
+
+    let columns = width.saturating_sub(2 * BANNER_PADDING) / CELL_SIZE;
+    for (i, c) in text.chars().take(columns).enumerate() {
+        surface.draw_glyph(
+            BANNER_PADDING + i * CELL_SIZE,
+            BANNER_PADDING,
+            glyph(c),
+            BANNER_FOREGROUND,
+            BANNER_BACKGROUND,
+        );
+    }
+}
+
+struct TextBuffer {
+    text: [u8; MAX_TEXT_LENGTH],
+    length: usize,
+}
+
+impl fmt::Write for TextBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let slot = match self.text.get_mut(self.length) {
+                Some(slot) => slot,
+                None => break,
+            };
+            // The font is ASCII only.
+            *slot = if c.is_ascii() && !c.is_ascii_control() {
+                c as u8
+            } else {
+                b'?'
+            };
+            self.length += 1;
+        }
+        Ok(())
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUSY: AtomicBool = AtomicBool::new(false);
+static BOOT_SERVICES: AtomicPtr<efi::BootServices> = AtomicPtr::new(core::ptr::null_mut());
+
+/**
+ * @brief Enables the banner. GOP itself is located when the first alert is
+ *        shown, as it usually does not exist yet when the driver is loaded.
+ */
+pub fn initialize(boot_services: &mut efi::BootServices) {
+    BOOT_SERVICES.store(boot_services, Ordering::Release);
+    ENABLED.store(true, Ordering::Release);
+}
+
+/**
+ * @brief Permanently disables the banner. Called at ExitBootServices.
+ */
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+    BOOT_SERVICES.store(core::ptr::null_mut(), Ordering::Release);
+}
+
+/**
+ * @brief Shows the alert text on the banner, replacing any previous one.
+ */
+// Only alert! calls this, and not in tests (see macros.rs).
+#[cfg_attr(test, allow(dead_code))]
+pub fn show_alert(args: fmt::Arguments) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    if BUSY.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let boot_services = BOOT_SERVICES.load(Ordering::Acquire);
+    if !boot_services.is_null() {
+        let mut text = TextBuffer {
+            text: [0; MAX_TEXT_LENGTH],
+            length: 0,
+        };
+        let _ = fmt::Write::write_fmt(&mut text, args);
+        // TextBuffer only ever holds ASCII.
+        let text = core::str::from_utf8(text.text.get(..text.length).unwrap_or(&[])).unwrap_or("");
+        render(unsafe { &*boot_services }, text);
+    }
+    BUSY.store(false, Ordering::Release);
+}
+
+/**
+ * @brief Locates GOP and draws the banner on whatever surface the current
+ *        mode offers.
+ */
+fn render(boot_services: &efi::BootServices, text: &str) {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.locate_protocol)(
+        &graphics_output::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        return;
+    }
+    let gop = interface as *mut graphics_output::Protocol;
+
+    let mode = unsafe { (*gop).mode };
+    if mode.is_null() {
+        return;
+    }
+    let mode = unsafe { *mode };
+    if mode.info.is_null()
+        || mode.size_of_info < core::mem::size_of::<graphics_output::ModeInformation>()
+    {
+        return;
+    }
+
+    // Read the pixel format as a raw integer; firmware may report a value
+    // outside of the GraphicsPixelFormat enum.
+    let info = mode.info;
+    let (width, height, stride, pixel_format, masks) = unsafe {
+        (
+            (*info).horizontal_resolution as usize,
+            (*info).vertical_resolution as usize,
+            (*info).pixels_per_scan_line as usize,
+            core::ptr::read_unaligned(core::ptr::addr_of!((*info).pixel_format) as *const u32),
+            (*info).pixel_information,
+        )
+    };
+    if width < CELL_SIZE || height < BANNER_HEIGHT {
+        return;
+    }
+
+    if let Some(layout) = PixelLayout::from_mode(pixel_format, &masks) {
+        if let Some(mut frame_buffer) = FrameBuffer::new(
+            mode.frame_buffer_base as usize as *mut u32,
+            mode.frame_buffer_size,
+            width,
+            height,
+            stride,
+            layout,
+        ) {
+            draw_banner(&mut frame_buffer, text);
+        }
+        return;
+    }
+
+    // Blt() may only be used up to TPL_NOTIFY.
+    if pixel_format == PIXEL_FORMAT_BLT_ONLY {
+        let tpl = (boot_services.raise_tpl)(efi::TPL_HIGH_LEVEL);
+        (boot_services.restore_tpl)(tpl);
+        if tpl <= efi::TPL_NOTIFY {
+            draw_banner(&mut BltSurface { gop, width }, text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 32;
+    // Wider than the mode, as framebuffers often are.
+    const STRIDE: usize = 72;
+
+    fn masks(red_mask: u32, green_mask: u32, blue_mask: u32) -> graphics_output::PixelBitmask {
+        graphics_output::PixelBitmask {
+            red_mask,
+            green_mask,
+            blue_mask,
+            reserved_mask: 0,
+        }
+    }
+
+    // Draws `text` into a framebuffer in memory, and returns its pixels.
+    fn draw(layout: PixelLayout, text: &str) -> Vec<u32> {
+        let mut pixels = vec![0u32; STRIDE * HEIGHT];
+        let mut frame_buffer = FrameBuffer::new(
+            pixels.as_mut_ptr(),
+            pixels.len() * 4,
+            WIDTH,
+            HEIGHT,
+            STRIDE,
+            layout,
+        )
+        .unwrap();
+        draw_banner(&mut frame_buffer, text);
+        pixels
+    }
+
+    // Returns the character cell the pixel is in, and its row and column
+    // within the cell, if it is in one.
+    fn cell_of(x: usize, y: usize) -> Option<(usize, usize, usize)> {
+        let (x, y) = (
+            x.checked_sub(BANNER_PADDING)?,
+            y.checked_sub(BANNER_PADDING)?,
+        );
+        if y >= CELL_SIZE {
+            return None;
+        }
+        Some((x / CELL_SIZE, y, x % CELL_SIZE))
+    }
+
+    #[test]
+    fn modes_map_to_layouts() {
+        let none = masks(0, 0, 0);
+        assert_eq!(
+            PixelLayout::from_mode(PIXEL_FORMAT_RGBX, &none),
+            Some(PixelLayout::Rgbx)
+        );
+        assert_eq!(
+            PixelLayout::from_mode(PIXEL_FORMAT_BGRX, &none),
+            Some(PixelLayout::Bgrx)
+        );
+        assert_eq!(
+            PixelLayout::from_mode(PIXEL_FORMAT_BIT_MASK, &masks(0xf800, 0x07e0, 0x001f)),
+            Some(PixelLayout::BitMask {
+                red: 0xf800,
+                green: 0x07e0,
+                blue: 0x001f,
+            })
+        );
+        // Gaps, overlaps and missing channels cannot be drawn into.
+        for rejected in [
+            masks(0xf0f0, 0x0f00, 0x000f),
+            masks(0xff00, 0x0ff0, 0x000f),
+            masks(0xff00, 0x00f0, 0),
+        ] {
+            assert_eq!(
+                PixelLayout::from_mode(PIXEL_FORMAT_BIT_MASK, &rejected),
+                None
+            );
+        }
+        assert_eq!(PixelLayout::from_mode(PIXEL_FORMAT_BLT_ONLY, &none), None);
+        assert_eq!(PixelLayout::from_mode(4, &none), None);
+    }
+
+    #[test]
+    fn channels_are_scaled_to_their_masks() {
+        assert_eq!(scale_to_mask(0xff, 0x0000_f800), 0x0000_f800);
+        assert_eq!(scale_to_mask(0xc0, 0x0000_f800), 0x0000_c000);
+        assert_eq!(scale_to_mask(0xc0, 0x0000_07e0), 0x0000_0600);
+        assert_eq!(scale_to_mask(0xff, 0x3ff0_0000), 0x3fc0_0000);
+        assert_eq!(scale_to_mask(0x00, 0x00ff_0000), 0);
+    }
+
+    #[test]
+    fn characters_without_a_glyph_are_drawn_as_question_marks() {
+        assert_eq!(glyph(' '), &FONT_8X8[0]);
+        assert_eq!(glyph('A'), &FONT_8X8[usize::from(b'A' - b' ')]);
+        assert_eq!(glyph('~'), &FONT_8X8[94]);
+        for c in ['\n', '\u{7f}', 'é'] {
+            assert_eq!(glyph(c), glyph('?'));
+        }
+    }
+
+    #[test]
+    fn banner_is_drawn_in_each_pixel_layout() {
+        let layouts = [
+            (PixelLayout::Rgbx, 0x0000_00c0, 0x00ff_ffff),
+            (PixelLayout::Bgrx, 0x00c0_0000, 0x00ff_ffff),
+            (
+                PixelLayout::BitMask {
+                    red: 0xf800,
+                    green: 0x07e0,
+                    blue: 0x001f,
+                },
+                0x0000_c000,
+                0x0000_ffff,
+            ),
+        ];
+        // Room for three cells; the fourth character is cut off.
+        let text = "H!x?";
+        for (layout, background, foreground) in layouts {
+            assert_eq!(layout.encode(BANNER_BACKGROUND), background);
+            assert_eq!(layout.encode(BANNER_FOREGROUND), foreground);
+            let pixels = draw(layout, text);
+            for y in 0..HEIGHT {
+                for x in 0..STRIDE {
+                    let expected = match cell_of(x, y) {
+                        _ if x >= WIDTH || y >= BANNER_HEIGHT => 0,
+                        Some((cell, row, column)) if cell < 3 => {
+                            let c = text.chars().nth(cell).unwrap();
+                            let bits = glyph(c)[row / GLYPH_SCALE];
+                            if (bits >> (column / GLYPH_SCALE)) & 1 != 0 {
+                                foreground
+                            } else {
+                                background
+                            }
+                        }
+                        _ => background,
+                    };
+                    assert_eq!(
+                        pixels[y * STRIDE + x],
+                        expected,
+                        "{:?} at {},{}",
+                        layout,
+                        x,
+                        y
+                    );
+                }
+            }
+            // The top left of the 'H': two scaled pixels on, then two off.
+            let row = BANNER_PADDING * STRIDE;
+            assert_eq!(
+                pixels[row + BANNER_PADDING..row + BANNER_PADDING + 6],
+                [foreground, foreground, foreground, foreground, background, background]
+            );
+        }
+    }
+
+    #[test]
+    fn framebuffers_too_small_for_the_mode_are_refused() {
+        let mut pixels = vec![0u32; STRIDE * HEIGHT];
+        let base = pixels.as_mut_ptr();
+        let size = pixels.len() * 4;
+        let layout = PixelLayout::Rgbx;
+        assert!(FrameBuffer::new(base, size, WIDTH, HEIGHT, STRIDE, layout).is_some());
+        assert!(FrameBuffer::new(base, size - 4, WIDTH, HEIGHT, STRIDE, layout).is_none());
+        assert!(FrameBuffer::new(base, size, WIDTH, HEIGHT, WIDTH - 1, layout).is_none());
+        assert!(FrameBuffer::new(base, size, 0, HEIGHT, STRIDE, layout).is_none());
+        assert!(
+            FrameBuffer::new(core::ptr::null_mut(), size, WIDTH, HEIGHT, STRIDE, layout).is_none()
+        );
+        assert!(FrameBuffer::new(base, usize::MAX, usize::MAX, 2, usize::MAX, layout).is_none());
+    }
+
+    #[test]
+    fn alert_text_is_ascii_truncated_to_max_text_length() {
+        let mut text = TextBuffer {
+            text: [0; MAX_TEXT_LENGTH],
+            length: 0,
+        };
+        let long = "0123456789".repeat(20);
+        fmt::Write::write_fmt(&mut text, format_args!("é\t{}", long)).unwrap();
+        assert_eq!(text.length, MAX_TEXT_LENGTH);
+        assert_eq!(&text.text[..2], b"??");
+        assert_eq!(&text.text[2..], &long.as_bytes()[..MAX_TEXT_LENGTH - 2]);
+    }
+}
@@ -0,0 +1,173 @@
+// uefi-var-monitor-rust/src/hide.rs
+//
+// Deception mode, only built with the `enforce` feature: selected variables
+// are made to look absent. A GetVariable call for a hidden variable returns
+// NOT_FOUND without reaching firmware, and GetNextVariableName enumeration
+// steps over hidden names (see get_next_variable_name.rs). This is meant for
+// analysis rigs, to pretend SecureBoot does not exist to a sample or to keep
+// honeypot variables out of sight.
+//
+// The hidden set is the UVM_HIDDEN list given at build time, in the format of
+// pattern.rs:
+//
+//   UVM_HIDDEN="8be4df61-93ca-11d2-aa0d-00e098032b8c:SecureBoot;6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90:Uvm*"
+//
+// Callers inside one of the images named in the ';'-separated
+// UVM_HIDE_EXEMPT list still see everything:
+//
+//   UVM_HIDE_EXEMPT="Shell;UvmTool"
+//
+// Images are named as in images.rs, so exemption lasts as long as the image
+// is in the table; a caller that cannot be resolved is not exempt. There is
+// no variable counterpart of either list, as one would be readable by the
+// very software being deceived. If the set is being loaded, nothing is
+// hidden.
+
+use crate::images;
+use crate::pattern::PatternTable;
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::ENFORCE;
+
+pub const MAX_HIDDEN: usize = 16;
+
+static TABLE: AtomicRefCell<PatternTable<MAX_HIDDEN>> = AtomicRefCell::new(PatternTable::new());
+static EXEMPT: AtomicRefCell<&'static str> = AtomicRefCell::new("");
+static HIDDEN: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Loads the hidden set and the exempt images from the build.
+ */
+pub fn load() {
+    let mut table = match TABLE.try_borrow_mut() {
+        Ok(table) => table,
+        Err(_) => return,
+    };
+    if let Some(list) = option_env!("UVM_HIDDEN") {
+        let (added, rejected) = table.add_list(list);
+        info!(
+            TAG,
+            "Hidden from build: {} entries, {} rejected", added, rejected
+        );
+    }
+    if let Some(list) = option_env!("UVM_HIDE_EXEMPT") {
+        if let Ok(mut exempt) = EXEMPT.try_borrow_mut() {
+            *exempt = list;
+        }
+        info!(TAG, "Hiding exempts: {}", list);
+    }
+}
+
+/**
+ * @brief Returns whether anything is hidden at all.
+ */
+pub fn is_active() -> bool {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => !table.is_empty(),
+        Err(_) => false,
+    }
+}
+
+/**
+ * @brief Returns whether the variable must look absent to `caller`.
+ */
+pub fn is_hidden(name: &str, guid: &efi::Guid, caller: Option<usize>) -> bool {
+    let matched = match TABLE.try_borrow_mut() {
+        Ok(table) => table.matches(name, guid),
+        Err(_) => false,
+    };
+    matched && !is_exempt(caller)
+}
+
+/**
+ * @brief Returns whether `caller` lies inside an exempt image.
+ */
+fn is_exempt(caller: Option<usize>) -> bool {
+    let image = match caller.and_then(images::find) {
+        Some(image) => image,
+        None => return false,
+    };
+    match EXEMPT.try_borrow_mut() {
+        Ok(exempt) => exempt
+            .split(|c| c == ';')
+            .map(str::trim)
+            .any(|exempt| !exempt.is_empty() && exempt == image.name()),
+        Err(_) => false,
+    }
+}
+
+/**
+ * @brief Counts a variable hidden from a caller and returns how many there
+ *        were so far.
+ */
+pub fn count_hidden() -> u64 {
+    HIDDEN.fetch_add(1, Ordering::AcqRel) + 1
+}
+
+/**
+ * @brief Returns the number of times a variable was hidden so far.
+ */
+pub fn hidden() -> u64 {
+    HIDDEN.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Zeroes the count of hidden accesses.
+ */
+pub fn reset_hidden() {
+    HIDDEN.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *TABLE.borrow_mut() = PatternTable::new();
+    *EXEMPT.borrow_mut() = "";
+    HIDDEN.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn hide(list: &str) {
+    TABLE.borrow_mut().add_list(list);
+}
+
+#[cfg(test)]
+pub fn exempt(list: &'static str) {
+    *EXEMPT.borrow_mut() = list;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+
+    #[test]
+    fn exempt_images_see_hidden_variables() {
+        let _lock = crate::mock::lock();
+        reset();
+        images::reset();
+        assert!(!is_active());
+        hide("8be4df61-93ca-11d2-aa0d-00e098032b8c:Secure*");
+        exempt("Shell; UvmTool");
+        assert!(is_active());
+        images::record(0x10_0000, 0x1_0000, &["\\EFI\\Tools\\UvmTool.efi"]);
+        images::record(0x20_0000, 0x1_0000, &["\\EFI\\BOOT\\BOOTX64.EFI"]);
+
+        let guid = &GLOBAL_VARIABLE_GUID;
+        assert!(is_hidden("SecureBoot", guid, Some(0x20_0100)));
+        assert!(is_hidden("SecureBoot", guid, Some(0x30_0000)));
+        assert!(is_hidden("SecureBoot", guid, None));
+        assert!(!is_hidden("SecureBoot", guid, Some(0x10_0100)));
+        assert!(!is_hidden("SetupMode", guid, Some(0x20_0100)));
+        assert!(!is_hidden(
+            "SecureBoot",
+            &crate::config::UVM_VENDOR_GUID,
+            Some(0x20_0100)
+        ));
+
+        images::reset();
+        reset();
+    }
+}
@@ -0,0 +1,324 @@
+// uefi-var-monitor-rust/src/hint.rs
+//
+// A guess at what variable data holds, shown after the data where a record
+// gives it in hex, e.g. the New= field of D: records (see diff.rs):
+//
+//   New=0100 (u16 = 0x0001)
+//   New=65006e00... (looks like: UCS-2 string)
+//   New=02010c00... (device path, 3 nodes)
+//
+// The checks run in this order, and the first that fits wins:
+//
+//   size 1, 2, 4 or 8   a little-endian integer of that size
+//   UCS-2               printable ASCII characters in UCS-2, ending with the
+//                       only NUL
+//   device path         nodes of the types of the UEFI specification, each of
+//                       at least 4 bytes, ending exactly with the end of the
+//                       data on an end-of-path node; the nodes counted are
+//                       those before the end nodes, of every instance
+//   ASCII               at least 3 printable ASCII characters, and an
+//                       optional trailing NUL
+//
+// Anything else is "binary", and so is anything longer than MAX_CLASSIFIED
+// bytes: the guess is meant to be right when made, and a wrong one is worse
+// than none. fixtures/variables holds the payloads the checks are tested on.
+
+use uvm_interface::hex::{self, Case};
+
+// Largest data classified, which bounds the device path walk.
+pub const MAX_CLASSIFIED: usize = 4096;
+// Fewest characters of ASCII text.
+const MIN_ASCII_LENGTH: usize = 3;
+
+// Device path node types, and the subtypes of the end node.
+const HARDWARE_DEVICE_PATH: u8 = 0x01;
+const BBS_DEVICE_PATH: u8 = 0x05;
+const END_DEVICE_PATH: u8 = 0x7f;
+const END_INSTANCE: u8 = 0x01;
+const END_ENTIRE: u8 = 0xff;
+const DEVICE_PATH_HEADER_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hint {
+    Integer { size: usize, value: u64 },
+    Ucs2,
+    DevicePath { nodes: usize },
+    Ascii,
+    Binary,
+}
+
+fn is_printable(c: u16) -> bool {
+    (0x20..=0x7e).contains(&c)
+}
+
+fn integer(data: &[u8]) -> Option<Hint> {
+    if !matches!(data.len(), 1 | 2 | 4 | 8) {
+        return None;
+    }
+    let value = data
+        .iter()
+        .rev()
+        .fold(0u64, |value, &byte| value << 8 | u64::from(byte));
+    Some(Hint::Integer {
+        size: data.len(),
+        value,
+    })
+}
+
+fn is_ucs2(data: &[u8]) -> bool {
+    let (text, nul) = match data.len() {
+        length if length >= 4 && length % 2 == 0 => data.split_at(length - 2),
+        _ => return false,
+    };
+    nul == [0, 0]
+        && text
+            .chunks_exact(2)
+            .all(|unit| is_printable(u16::from_le_bytes([unit[0], unit[1]])))
+}
+
+/**
+ * @brief Returns the number of nodes before the end nodes if `data` is a
+ *        whole device path. The walk is bounded by the data, each node
+ *        taking at least its header.
+ */
+pub fn device_path_nodes(data: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    let mut nodes = 0;
+    loop {
+        let header = data.get(offset..offset + DEVICE_PATH_HEADER_SIZE)?;
+        let length = usize::from(u16::from_le_bytes([header[2], header[3]]));
+        if length < DEVICE_PATH_HEADER_SIZE {
+            return None;
+        }
+        let end = offset
+            .checked_add(length)
+            .filter(|&end| end <= data.len())?;
+        match (header[0], header[1]) {
+            (END_DEVICE_PATH, END_ENTIRE) if length == DEVICE_PATH_HEADER_SIZE => {
+                return Some(nodes).filter(|&nodes| nodes != 0 && end == data.len());
+            }
+            (END_DEVICE_PATH, END_INSTANCE) if length == DEVICE_PATH_HEADER_SIZE => {}
+            (HARDWARE_DEVICE_PATH..=BBS_DEVICE_PATH, _) => nodes += 1,
+            _ => return None,
+        }
+        offset = end;
+    }
+}
+
+fn is_ascii(data: &[u8]) -> bool {
+    let text = data.strip_suffix(&[0]).unwrap_or(data);
+    text.len() >= MIN_ASCII_LENGTH && text.iter().all(|&c| is_printable(u16::from(c)))
+}
+
+/**
+ * @brief Guesses what `data` holds.
+ */
+pub fn classify(data: &[u8]) -> Hint {
+    if data.len() > MAX_CLASSIFIED {
+        return Hint::Binary;
+    }
+    if let Some(hint) = integer(data) {
+        return hint;
+    }
+    if is_ucs2(data) {
+        return Hint::Ucs2;
+    }
+    if let Some(nodes) = device_path_nodes(data) {
+        return Hint::DevicePath { nodes };
+    }
+    if is_ascii(data) {
+        return Hint::Ascii;
+    }
+    Hint::Binary
+}
+
+/**
+ * @brief Appends the guess for `data`, in parentheses.
+ */
+pub fn push_hint<const N: usize>(line: &mut hex::Line<N>, data: &[u8]) {
+    match classify(data) {
+        Hint::Integer { size, value } => {
+            let name = match size {
+                1 => "(u8 = 0x",
+                2 => "(u16 = 0x",
+                4 => "(u32 = 0x",
+                _ => "(u64 = 0x",
+            };
+            line.push_str(name)
+                .push_hex(value, size * 2, Case::Lower)
+                .push_str(")");
+        }
+        Hint::Ucs2 => {
+            line.push_str("(looks like: UCS-2 string)");
+        }
+        Hint::DevicePath { nodes } => {
+            line.push_str("(device path, ");
+            push_decimal(line, nodes);
+            line.push_str(if nodes == 1 { " node)" } else { " nodes)" });
+        }
+        Hint::Ascii => {
+            line.push_str("(looks like: ASCII string)");
+        }
+        Hint::Binary => {
+            line.push_str("(binary)");
+        }
+    }
+}
+
+fn push_decimal<const N: usize>(line: &mut hex::Line<N>, value: usize) {
+    let mut digits = [0u8; 20];
+    let mut start = digits.len();
+    let mut value = value;
+    loop {
+        start -= 1;
+        if let Some(digit) = digits.get_mut(start) {
+            *digit = b'0' + (value % 10) as u8;
+        }
+        value /= 10;
+        if value == 0 || start == 0 {
+            break;
+        }
+    }
+    for &digit in digits.get(start..).unwrap_or(&[]) {
+        line.push_char(char::from(digit));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Payloads in the layouts OVMF and the UEFI specification give them.
+    const CORPUS: [(&str, &[u8], &str); 14] = [
+        (
+            "SecureBoot",
+            include_bytes!("../fixtures/variables/SecureBoot.bin"),
+            "(u8 = 0x00)",
+        ),
+        (
+            "Timeout",
+            include_bytes!("../fixtures/variables/Timeout.bin"),
+            "(u16 = 0x0000)",
+        ),
+        (
+            "BootCurrent",
+            include_bytes!("../fixtures/variables/BootCurrent.bin"),
+            "(u16 = 0x0001)",
+        ),
+        (
+            "OsIndicationsSupported",
+            include_bytes!("../fixtures/variables/OsIndicationsSupported.bin"),
+            "(u64 = 0x0000000000000041)",
+        ),
+        (
+            "BootOrder",
+            include_bytes!("../fixtures/variables/BootOrder.bin"),
+            "(binary)",
+        ),
+        (
+            "PlatformLang",
+            include_bytes!("../fixtures/variables/PlatformLang.bin"),
+            "(looks like: ASCII string)",
+        ),
+        (
+            "Lang",
+            include_bytes!("../fixtures/variables/Lang.bin"),
+            "(looks like: ASCII string)",
+        ),
+        (
+            "PlatformLangCodes",
+            include_bytes!("../fixtures/variables/PlatformLangCodes.bin"),
+            "(looks like: ASCII string)",
+        ),
+        (
+            "ConOut",
+            include_bytes!("../fixtures/variables/ConOut.bin"),
+            "(device path, 3 nodes)",
+        ),
+        (
+            "ConIn",
+            include_bytes!("../fixtures/variables/ConIn.bin"),
+            "(device path, 4 nodes)",
+        ),
+        (
+            "Boot0001",
+            include_bytes!("../fixtures/variables/Boot0001.bin"),
+            "(binary)",
+        ),
+        (
+            "ShellPath",
+            include_bytes!("../fixtures/variables/ShellPath.bin"),
+            "(looks like: UCS-2 string)",
+        ),
+        (
+            "dbx",
+            include_bytes!("../fixtures/variables/dbx.bin"),
+            "(binary)",
+        ),
+        (
+            "MemoryTypeInformation",
+            include_bytes!("../fixtures/variables/MemoryTypeInformation.bin"),
+            "(binary)",
+        ),
+    ];
+
+    fn shown(data: &[u8]) -> std::string::String {
+        let mut line = hex::Line::<64>::new();
+        push_hint(&mut line, data);
+        line.as_str().into()
+    }
+
+    #[test]
+    fn the_corpus_is_classified() {
+        for (name, data, expected) in CORPUS.iter() {
+            assert_eq!(shown(data), *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn near_misses_are_binary() {
+        let con_out: &[u8] = include_bytes!("../fixtures/variables/ConOut.bin");
+        // Cut short, with bytes past the end node, or without any node
+        // before it.
+        assert_eq!(classify(&con_out[..con_out.len() - 1]), Hint::Binary);
+        let mut longer = con_out.to_vec();
+        longer.extend_from_slice(&[0; 3]);
+        assert_eq!(classify(&longer), Hint::Binary);
+        assert_eq!(
+            classify(&[0x7f, 0xff, 0x04, 0x00, 0x7f, 0xff]),
+            Hint::Binary
+        );
+        // A node of an unknown type, or of a length below its header.
+        let one_node = [0x02, 0x01, 0x05, 0x00, 0x00, 0x7f, 0xff, 0x04, 0x00];
+        assert_eq!(shown(&one_node), "(device path, 1 node)");
+        let mut unknown = one_node;
+        unknown[0] = 0x06;
+        assert_eq!(classify(&unknown), Hint::Binary);
+        let mut short = one_node;
+        short[2] = 0x02;
+        assert_eq!(classify(&short), Hint::Binary);
+
+        // UCS-2 with a NUL inside, or a character outside printable ASCII.
+        assert_eq!(classify(b"a\0\0\0b\0c\0\0\0"), Hint::Binary);
+        assert_eq!(classify(b"a\0\xe9\0\0\0"), Hint::Binary);
+        // Too short for text, or not text.
+        assert_eq!(classify(b"ab\0"), Hint::Binary);
+        assert_eq!(classify(b"abc\x01\0"), Hint::Binary);
+        assert_eq!(classify(&[]), Hint::Binary);
+        assert_eq!(classify(&[b'a'; MAX_CLASSIFIED + 1]), Hint::Binary);
+    }
+
+    // The fuzz target (see fuzz/) in short, on the device paths of the
+    // corpus, for the runs that do not have cargo-fuzz.
+    #[test]
+    fn mangled_device_paths_are_walked_without_panicking() {
+        let con_out: &[u8] = include_bytes!("../fixtures/variables/ConOut.bin");
+        let con_in: &[u8] = include_bytes!("../fixtures/variables/ConIn.bin");
+        crate::mangle::run(&[con_out, con_in], 20_000, |data| {
+            if let Some(nodes) = device_path_nodes(data) {
+                assert!(nodes >= 1 && nodes <= data.len() / DEVICE_PATH_HEADER_SIZE);
+            }
+            classify(data);
+        });
+    }
+}
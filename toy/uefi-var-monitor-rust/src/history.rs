@@ -0,0 +1,448 @@
+// uefi-var-monitor-rust/src/history.rs
+//
+// Write histories of chosen variables. BootOrder, BootNext, PK and KEK have
+// one by default, plus the UVM_HISTORY list given at build time, in the
+// format of pattern.rs but exact names only. Each history keeps the last
+// UVM_HISTORY_DEPTH writes the SetVariable hook saw, 8 by default and at most
+// MAX_HISTORY_DEPTH, accepted or not, the oldest dropped first:
+//
+//   sequence   the order of the write among those of every history
+//   timestamp  the cycle counter at the write, 0 without one
+//   caller     the 4 KiB page of the return address, as rate.rs buckets it
+//   size, attributes and the status firmware returned
+//   CRC32 and the first HISTORY_DATA_SIZE bytes of the data
+//
+// The data is only read where safety.rs allows it, so at OS runtime the CRC
+// and bytes are usually missing; HISTORY_FLAG_INSPECTED tells. Writes the
+// hook could not record, at the lowest step of the latency guard (see
+// budget.rs) or while the histories were in use, set HISTORY_FLAG_GAP on the
+// next entry of every history.
+//
+// The histories are read through the control protocol (see protocol.rs), and
+// the newest entries go into the boot report (see report.rs). Everything is
+// in static arrays: MAX_HISTORIES variables of MAX_HISTORY_DEPTH writes.
+
+use crate::arch::{self, Arch};
+use crate::classify::GLOBAL_VARIABLE_GUID;
+use crate::pattern::Pattern;
+use crate::safety::{self, Inspection};
+use crate::tag::{self, Tag};
+use crate::{crc32, rate, Phase};
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use r_efi::efi;
+use uvm_interface::protocol::{
+    HistoryEntry, HISTORY_DATA_SIZE, HISTORY_FLAG_GAP, HISTORY_FLAG_INSPECTED,
+    HISTORY_FLAG_RUNTIME, TOP_NAME_SIZE,
+};
+
+const TAG: Tag = tag::CONFIG;
+
+pub const MAX_HISTORIES: usize = 8;
+pub const MAX_HISTORY_DEPTH: usize = 16;
+pub const DEFAULT_HISTORY_DEPTH: usize = 8;
+
+const DEFAULT_HISTORIES: [&str; 4] = ["BootOrder", "BootNext", "PK", "KEK"];
+
+#[derive(Clone, Copy)]
+struct Write {
+    sequence: u64,
+    timestamp: u64,
+    caller_page: u64,
+    status: u64,
+    size: u64,
+    attributes: u32,
+    crc32: u32,
+    flags: u32,
+    data_length: u32,
+    data: [u8; HISTORY_DATA_SIZE],
+}
+
+impl Write {
+    const EMPTY: Write = Write {
+        sequence: 0,
+        timestamp: 0,
+        caller_page: 0,
+        status: 0,
+        size: 0,
+        attributes: 0,
+        crc32: 0,
+        flags: 0,
+        data_length: 0,
+        data: [0; HISTORY_DATA_SIZE],
+    };
+}
+
+#[derive(Clone, Copy)]
+struct History {
+    pattern: Pattern,
+    writes: [Write; MAX_HISTORY_DEPTH],
+    // Writes recorded since load; the newest is at (written - 1) % depth.
+    written: u64,
+    // Whether writes went by unrecorded since the newest.
+    gap: bool,
+}
+
+impl History {
+    const fn new(pattern: Pattern) -> Self {
+        History {
+            pattern,
+            writes: [Write::EMPTY; MAX_HISTORY_DEPTH],
+            written: 0,
+            gap: false,
+        }
+    }
+
+    fn record(&mut self, depth: usize, mut write: Write) {
+        if self.gap {
+            write.flags |= HISTORY_FLAG_GAP;
+            self.gap = false;
+        }
+        let index = (self.written % depth as u64) as usize;
+        if let Some(slot) = self.writes.get_mut(index) {
+            *slot = write;
+        }
+        self.written += 1;
+    }
+
+    /**
+     * @brief Returns the writes kept, oldest first.
+     */
+    fn kept(&self, depth: usize) -> impl Iterator<Item = &Write> {
+        let count = core::cmp::min(self.written, depth as u64);
+        (self.written - count..self.written)
+            .filter_map(move |written| self.writes.get((written % depth as u64) as usize))
+    }
+
+    fn entry(&self, write: &Write) -> HistoryEntry {
+        let mut entry = HistoryEntry {
+            guid: self.pattern.guid(),
+            sequence: write.sequence,
+            timestamp: write.timestamp,
+            caller_page: write.caller_page,
+            status: write.status,
+            size: write.size,
+            attributes: write.attributes,
+            crc32: write.crc32,
+            flags: write.flags,
+            data_length: write.data_length,
+            data: write.data,
+            ..HistoryEntry::EMPTY
+        };
+        let name = self.pattern.name().as_bytes();
+        let length = core::cmp::min(name.len(), TOP_NAME_SIZE);
+        entry.name[..length].copy_from_slice(&name[..length]);
+        entry.name_length = length as u32;
+        entry
+    }
+}
+
+struct Histories {
+    histories: [Option<History>; MAX_HISTORIES],
+    // Sequence number of the last write recorded.
+    sequence: u64,
+}
+
+impl Histories {
+    const fn new() -> Self {
+        Histories {
+            histories: [None; MAX_HISTORIES],
+            sequence: 0,
+        }
+    }
+
+    fn add(&mut self, pattern: Pattern) -> bool {
+        if self
+            .histories
+            .iter()
+            .flatten()
+            .any(|history| history.pattern.matches(pattern.name(), &pattern.guid()))
+        {
+            return true;
+        }
+        match self.histories.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(History::new(pattern));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn count(&self, depth: usize) -> usize {
+        self.histories
+            .iter()
+            .flatten()
+            .map(|history| core::cmp::min(history.written, depth as u64) as usize)
+            .sum()
+    }
+
+    /**
+     * @brief Fills `entries` with the newest writes kept, in sequence order.
+     *        Returns the number filled.
+     */
+    fn newest(&self, depth: usize, entries: &mut [HistoryEntry]) -> usize {
+        let skipped = self.count(depth).saturating_sub(entries.len());
+        let mut filled = 0;
+        // Walks the writes in sequence order, by taking the oldest write
+        // after the last one taken each time.
+        let mut after = 0;
+        let mut position = 0;
+        loop {
+            let next = self
+                .histories
+                .iter()
+                .flatten()
+                .flat_map(|history| history.kept(depth).map(move |write| (history, write)))
+                .filter(|(_, write)| write.sequence > after)
+                .min_by_key(|(_, write)| write.sequence);
+            let (history, write) = match next {
+                Some(next) => next,
+                None => return filled,
+            };
+            after = write.sequence;
+            position += 1;
+            if position <= skipped {
+                continue;
+            }
+            match entries.get_mut(filled) {
+                Some(entry) => *entry = history.entry(write),
+                None => return filled,
+            }
+            filled += 1;
+        }
+    }
+}
+
+static HISTORIES: AtomicRefCell<Histories> = AtomicRefCell::new(Histories::new());
+static DEPTH: AtomicUsize = AtomicUsize::new(DEFAULT_HISTORY_DEPTH);
+// Set when writes went by unrecorded.
+static MISSED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Parses a history depth, 1 to MAX_HISTORY_DEPTH writes.
+ */
+pub fn parse_depth(text: &str) -> Option<usize> {
+    text.trim()
+        .parse()
+        .ok()
+        .filter(|depth| (1..=MAX_HISTORY_DEPTH).contains(depth))
+}
+
+fn depth() -> usize {
+    DEPTH.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Sets up the histories of the default variables and those of the
+ *        UVM_HISTORY list.
+ */
+pub fn load() {
+    let mut histories = match HISTORIES.try_borrow_mut() {
+        Ok(histories) => histories,
+        Err(_) => return,
+    };
+    for name in DEFAULT_HISTORIES.iter() {
+        if let Some(pattern) = Pattern::exact(name, &GLOBAL_VARIABLE_GUID) {
+            histories.add(pattern);
+        }
+    }
+    if let Some(list) = option_env!("UVM_HISTORY") {
+        let (mut added, mut rejected) = (0, 0);
+        for text in list
+            .split(|c| c == ';')
+            .filter(|text| !text.trim().is_empty())
+        {
+            match Pattern::parse(text).filter(|pattern| !pattern.is_prefix()) {
+                Some(pattern) if histories.add(pattern) => added += 1,
+                _ => rejected += 1,
+            }
+        }
+        info!(
+            TAG,
+            "Write histories from build: {} entries, {} rejected", added, rejected
+        );
+    }
+    if let Some(text) = option_env!("UVM_HISTORY_DEPTH") {
+        match parse_depth(text) {
+            Some(depth) => DEPTH.store(depth, Ordering::Release),
+            None => warn!(TAG, "UVM_HISTORY_DEPTH ignored: {}", text),
+        }
+    }
+}
+
+/**
+ * @brief Adds a write the SetVariable hook saw to the history of the
+ *        variable, if it has one.
+ */
+pub fn record_write(
+    name: &str,
+    guid: &efi::Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *const core::ffi::c_void,
+    efi_status: efi::Status,
+    caller: Option<usize>,
+) {
+    let mut histories = match HISTORIES.try_borrow_mut() {
+        Ok(histories) => histories,
+        Err(_) => return missed(),
+    };
+    if MISSED.swap(false, Ordering::AcqRel) {
+        for history in histories.histories.iter_mut().flatten() {
+            history.gap = true;
+        }
+    }
+    if !histories
+        .histories
+        .iter()
+        .flatten()
+        .any(|history| history.pattern.matches(name, guid))
+    {
+        return;
+    }
+
+    histories.sequence += 1;
+    let mut write = Write {
+        sequence: histories.sequence,
+        timestamp: arch::Current::read_cycle_counter().unwrap_or(0),
+        caller_page: caller.map_or(0, |caller| (caller & rate::BUCKET_MASK) as u64),
+        status: efi_status.as_usize() as u64,
+        size: data_size as u64,
+        attributes,
+        ..Write::EMPTY
+    };
+    if crate::phase() == Phase::Runtime {
+        write.flags |= HISTORY_FLAG_RUNTIME;
+    }
+    if let Inspection::Data(data) = safety::inspect(name, guid, data, data_size, data_size) {
+        let length = core::cmp::min(data.len(), HISTORY_DATA_SIZE);
+        write.data[..length].copy_from_slice(&data[..length]);
+        write.data_length = length as u32;
+        write.crc32 = crc32::crc32(data);
+        write.flags |= HISTORY_FLAG_INSPECTED;
+    }
+
+    let depth = depth();
+    if let Some(history) = histories
+        .histories
+        .iter_mut()
+        .flatten()
+        .find(|history| history.pattern.matches(name, guid))
+    {
+        history.record(depth, write);
+    }
+}
+
+/**
+ * @brief Notes that writes went by without their names being looked at.
+ */
+pub fn missed() {
+    MISSED.store(true, Ordering::Release);
+}
+
+/**
+ * @brief Returns the number of writes kept in every history.
+ */
+pub fn count() -> usize {
+    match HISTORIES.try_borrow_mut() {
+        Ok(histories) => histories.count(depth()),
+        Err(_) => 0,
+    }
+}
+
+/**
+ * @brief Fills `entries` with the newest writes kept in every history,
+ *        oldest first, and returns the number filled. None if the histories
+ *        are in use.
+ */
+pub fn read(entries: &mut [HistoryEntry]) -> Option<usize> {
+    let histories = HISTORIES.try_borrow_mut().ok()?;
+    Some(histories.newest(depth(), entries))
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *HISTORIES.borrow_mut() = Histories::new();
+    DEPTH.store(DEFAULT_HISTORY_DEPTH, Ordering::Release);
+    MISSED.store(false, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(sequence: u64) -> Write {
+        Write {
+            sequence,
+            ..Write::EMPTY
+        }
+    }
+
+    fn history(name: &str) -> History {
+        History::new(Pattern::exact(name, &GLOBAL_VARIABLE_GUID).unwrap())
+    }
+
+    #[test]
+    fn the_oldest_writes_are_dropped_first() {
+        let mut boot_order = history("BootOrder");
+        for sequence in 1..=5 {
+            boot_order.record(3, write(sequence));
+        }
+        let kept: std::vec::Vec<u64> = boot_order.kept(3).map(|write| write.sequence).collect();
+        assert_eq!(kept, [3, 4, 5]);
+
+        boot_order.gap = true;
+        boot_order.record(3, write(6));
+        boot_order.record(3, write(7));
+        let flags: std::vec::Vec<u32> = boot_order.kept(3).map(|write| write.flags).collect();
+        assert_eq!(flags, [0, HISTORY_FLAG_GAP, 0]);
+
+        let entry = boot_order.entry(&write(7));
+        assert_eq!(
+            (entry.name(), entry.guid),
+            ("BootOrder", GLOBAL_VARIABLE_GUID)
+        );
+    }
+
+    #[test]
+    fn histories_are_read_in_sequence_order() {
+        let mut histories = Histories::new();
+        assert!(histories.add(Pattern::exact("BootOrder", &GLOBAL_VARIABLE_GUID).unwrap()));
+        assert!(histories.add(Pattern::exact("BootNext", &GLOBAL_VARIABLE_GUID).unwrap()));
+        // BootOrder is written 1, 3, 4 and BootNext 2, 5, 6, 7, 8 of which
+        // 2 is dropped.
+        for (index, sequence) in [
+            (0, 1),
+            (1, 2),
+            (0, 3),
+            (0, 4),
+            (1, 5),
+            (1, 6),
+            (1, 7),
+            (1, 8),
+        ] {
+            if let Some(history) = histories.histories[index].as_mut() {
+                history.record(4, write(sequence));
+            }
+        }
+        assert_eq!(histories.count(4), 7);
+
+        let mut entries = [HistoryEntry::EMPTY; 8];
+        assert_eq!(histories.newest(4, &mut entries), 7);
+        let sequences: std::vec::Vec<u64> =
+            entries[..7].iter().map(|entry| entry.sequence).collect();
+        assert_eq!(sequences, [1, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(entries[0].name(), "BootOrder");
+        assert_eq!(entries[6].name(), "BootNext");
+
+        // Too few entries: the newest ones.
+        let mut entries = [HistoryEntry::EMPTY; 3];
+        assert_eq!(histories.newest(4, &mut entries), 3);
+        assert_eq!(entries.map(|entry| entry.sequence), [6, 7, 8]);
+
+        assert_eq!(parse_depth("8"), Some(8));
+        assert_eq!(parse_depth("0"), None);
+        assert_eq!(parse_depth(&(MAX_HISTORY_DEPTH + 1).to_string()), None);
+    }
+}
@@ -0,0 +1,238 @@
+// uefi-var-monitor-rust/src/hook.rs
+//
+// Storage for the service pointers a hook forwards to. The hook reads them on
+// any CPU at OS runtime while they are replaced at load, when re-hooking and
+// at SetVirtualAddressMap, so they are atomics rather than static muts.
+
+#[cfg(feature = "enforce")]
+use crate::GetNextVariableNameType;
+use crate::{GetVariableType, SetVariableType};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use r_efi::efi;
+
+pub struct HookSlot<F> {
+    target: AtomicPtr<core::ffi::c_void>,
+    _signature: PhantomData<F>,
+}
+
+impl<F: Copy> HookSlot<F> {
+    /**
+     * @brief Creates an empty slot. F must be a function pointer type.
+     */
+    pub const fn new() -> Self {
+        assert!(core::mem::size_of::<F>() == core::mem::size_of::<*mut core::ffi::c_void>());
+        HookSlot {
+            target: AtomicPtr::new(core::ptr::null_mut()),
+            _signature: PhantomData,
+        }
+    }
+
+    /**
+     * @brief Returns the saved function, if any.
+     */
+    pub fn get(&self) -> Option<F> {
+        let target = self.target.load(Ordering::Acquire);
+        if target.is_null() {
+            return None;
+        }
+        Some(unsafe { core::mem::transmute_copy(&target) })
+    }
+
+    pub fn set(&self, target: F) {
+        self.target.store(
+            unsafe { core::mem::transmute_copy(&target) },
+            Ordering::Release,
+        );
+    }
+
+    /**
+     * @brief Saves a function and returns the previous one.
+     */
+    pub fn swap(&self, target: F) -> Option<F> {
+        let previous = self.target.swap(
+            unsafe { core::mem::transmute_copy(&target) },
+            Ordering::AcqRel,
+        );
+        if previous.is_null() {
+            return None;
+        }
+        Some(unsafe { core::mem::transmute_copy(&previous) })
+    }
+
+    /**
+     * @brief Copies the saved pointer from another slot.
+     */
+    pub fn copy_from(&self, other: &HookSlot<F>) {
+        self.target
+            .store(other.target.load(Ordering::Acquire), Ordering::Release);
+    }
+
+    pub fn as_raw(&self) -> *mut core::ffi::c_void {
+        self.target.load(Ordering::Acquire)
+    }
+
+    /**
+     * @brief Returns the atomic itself, for registering it for conversion at
+     *        SetVirtualAddressMap.
+     */
+    pub fn storage(&self) -> &AtomicPtr<core::ffi::c_void> {
+        &self.target
+    }
+
+    /**
+     * @brief Returns the address of the pointer, for code that writes it in
+     *        place. Only valid while nothing else can run, such as at
+     *        TPL_HIGH_LEVEL during the boot-services phase.
+     */
+    pub fn as_mut_ptr(&self) -> *mut *mut core::ffi::c_void {
+        self.target.as_ptr()
+    }
+}
+
+impl<F: Copy> Default for HookSlot<F> {
+    fn default() -> Self {
+        HookSlot::new()
+    }
+}
+
+impl HookSlot<GetVariableType> {
+    /**
+     * @brief Forwards a GetVariable call to the saved function.
+     */
+    pub fn call(
+        &self,
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        match self.get() {
+            Some(get_variable) => {
+                get_variable(variable_name, vendor_guid, attributes, data_size, data)
+            }
+            None => efi::Status::NOT_READY,
+        }
+    }
+}
+
+impl HookSlot<SetVariableType> {
+    /**
+     * @brief Forwards a SetVariable call to the saved function.
+     */
+    pub fn call(
+        &self,
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        match self.get() {
+            Some(set_variable) => {
+                set_variable(variable_name, vendor_guid, attributes, data_size, data)
+            }
+            None => efi::Status::NOT_READY,
+        }
+    }
+}
+
+#[cfg(feature = "enforce")]
+impl HookSlot<GetNextVariableNameType> {
+    /**
+     * @brief Forwards a GetNextVariableName call to the saved function.
+     */
+    pub fn call(
+        &self,
+        variable_name_size: *mut usize,
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+    ) -> efi::Status {
+        match self.get() {
+            Some(get_next_variable_name) => {
+                get_next_variable_name(variable_name_size, variable_name, vendor_guid)
+            }
+            None => efi::Status::NOT_READY,
+        }
+    }
+}
+
+// These do not touch any hardware and can also be run under Miri.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    efiapi! {
+        fn first(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            data_size: *mut usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            unsafe { *data_size = 1 };
+            efi::Status::SUCCESS
+        }
+    }
+
+    efiapi! {
+        fn second(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            data_size: *mut usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            unsafe { *data_size = 2 };
+            efi::Status::BUFFER_TOO_SMALL
+        }
+    }
+
+    fn call(slot: &HookSlot<GetVariableType>) -> (efi::Status, usize) {
+        let mut data_size = 0usize;
+        let efi_status = slot.call(
+            core::ptr::null_mut(),
+            core::ptr::null_mut(),
+            core::ptr::null_mut(),
+            &mut data_size,
+            core::ptr::null_mut(),
+        );
+        (efi_status, data_size)
+    }
+
+    #[test]
+    fn empty_slot_is_not_ready() {
+        let slot = HookSlot::<GetVariableType>::new();
+        assert!(slot.get().is_none());
+        assert!(slot.as_raw().is_null());
+        assert_eq!(call(&slot), (efi::Status::NOT_READY, 0));
+    }
+
+    #[test]
+    fn call_through_and_swap() {
+        let slot = HookSlot::<GetVariableType>::new();
+        assert!(slot.swap(first).is_none());
+        assert_eq!(call(&slot), (efi::Status::SUCCESS, 1));
+
+        let previous = slot.swap(second).unwrap();
+        assert_eq!(previous as usize, first as GetVariableType as usize);
+        assert_eq!(call(&slot), (efi::Status::BUFFER_TOO_SMALL, 2));
+
+        let other = HookSlot::<GetVariableType>::new();
+        other.copy_from(&slot);
+        assert_eq!(other.as_raw(), slot.as_raw());
+    }
+
+    #[test]
+    fn in_place_update_is_seen_by_get() {
+        static SLOT: HookSlot<GetVariableType> = HookSlot::new();
+        SLOT.set(first);
+        unsafe { *SLOT.as_mut_ptr() = second as GetVariableType as *mut core::ffi::c_void };
+        assert_eq!(call(&SLOT), (efi::Status::BUFFER_TOO_SMALL, 2));
+        assert_eq!(
+            SLOT.storage().load(Ordering::Acquire),
+            second as GetVariableType as *mut core::ffi::c_void
+        );
+    }
+}
@@ -0,0 +1,412 @@
+// uefi-var-monitor-rust/src/images.rs
+//
+// Table of the loaded images, so that caller return addresses can be logged
+// as "DriverName+0x1234" instead of a raw address (see ReturnAddress in
+// set_variable.rs).
+//
+// The table is built at load from every handle carrying the LoadedImage
+// protocol, and rebuilt at ReadyToBoot, when the drivers and applications
+// that matter have been loaded. Each image gets a short name from its file
+// path: the last component of a File() node without its extension, such as
+// "Shell", or the GUID of an FvFile() node for images loaded from a firmware
+// volume.
+//
+// The table is a static of this runtime driver, so it lives in runtime
+// services data and stays readable at OS runtime. Lookups only ever
+// try-borrow it. At SetVirtualAddressMap the base of each image is converted
+// along with the other pointers; images firmware does not map, such as every
+// boot services driver, are dropped, as their code is gone by then.
+
+use crate::signature_list;
+use crate::tag::{self, Tag};
+use crate::GuidFmt;
+use atomic_refcell::AtomicRefCell;
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+use r_efi::efi;
+use r_efi::protocols::{device_path, loaded_image};
+
+const TAG: Tag = tag::HOOK;
+
+pub const MAX_IMAGES: usize = 64;
+pub const MAX_IMAGE_NAME: usize = 36;
+// Nodes of a file path looked at before giving up on naming the image.
+const MAX_PATH_NODES: usize = 32;
+
+const MEDIA_FILE_PATH: u8 = 0x04;
+const MEDIA_FV_FILE: u8 = 0x06;
+
+#[derive(Clone, Copy)]
+pub struct Image {
+    pub base: usize,
+    pub size: usize,
+    name: [u8; MAX_IMAGE_NAME],
+    name_length: usize,
+}
+
+impl Image {
+    pub fn name(&self) -> &str {
+        // Only printable ASCII is stored.
+        core::str::from_utf8(self.name.get(..self.name_length).unwrap_or(&[])).unwrap_or("?")
+    }
+
+    fn contains(&self, address: usize) -> bool {
+        address >= self.base && address - self.base < self.size
+    }
+}
+
+// Writes a name into an Image, truncating it.
+struct NameWriter<'a>(&'a mut Image);
+
+impl fmt::Write for NameWriter<'_> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for byte in text.bytes() {
+            if let Some(slot) = self.0.name.get_mut(self.0.name_length) {
+                *slot = if (0x20..0x7f).contains(&byte) {
+                    byte
+                } else {
+                    b'?'
+                };
+                self.0.name_length += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Table<const N: usize> {
+    entries: [Option<Image>; N],
+}
+
+impl<const N: usize> Table<N> {
+    pub const fn new() -> Self {
+        Table { entries: [None; N] }
+    }
+
+    /**
+     * @brief Adds an image. Returns false if the table is full.
+     */
+    pub fn insert(&mut self, image: Image) -> bool {
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(slot) => {
+                *slot = Some(image);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /**
+     * @brief Returns the image `address` falls inside of, if any.
+     */
+    pub fn find(&self, address: usize) -> Option<&Image> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|image| image.contains(address))
+    }
+
+    /**
+     * @brief Converts the base of each image, dropping the ones that cannot
+     *        be. Returns how many were kept.
+     */
+    pub fn relocate(
+        &mut self,
+        convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status,
+    ) -> usize {
+        let mut kept = 0;
+        for entry in self.entries.iter_mut() {
+            if let Some(image) = entry {
+                let mut address = image.base as *mut core::ffi::c_void;
+                if convert(&mut address).is_error() {
+                    *entry = None;
+                } else {
+                    image.base = address as usize;
+                    kept += 1;
+                }
+            }
+        }
+        kept
+    }
+
+    fn count(&self) -> usize {
+        self.entries.iter().flatten().count()
+    }
+}
+
+/**
+ * @brief Names an image after its file path, "?" if no node of it names a
+ *        file. `file_path` must be null or a valid device path.
+ */
+fn image_named(base: usize, size: usize, file_path: *const device_path::Protocol) -> Image {
+    let mut image = Image {
+        base,
+        size,
+        name: [0; MAX_IMAGE_NAME],
+        name_length: 0,
+    };
+    let mut node = file_path as *const u8;
+    for _ in 0..MAX_PATH_NODES {
+        if node.is_null() {
+            break;
+        }
+        let header = unsafe { (node as *const device_path::Protocol).read_unaligned() };
+        let length = usize::from(u16::from_le_bytes(header.length));
+        if header.r#type == device_path::TYPE_END || length < 4 {
+            break;
+        }
+        let data = unsafe { core::slice::from_raw_parts(node.add(4), length - 4) };
+        match (header.r#type, header.sub_type) {
+            (device_path::TYPE_MEDIA, MEDIA_FILE_PATH) => {
+                image.name_length = 0;
+                write_file_name(&mut image, data);
+            }
+            (device_path::TYPE_MEDIA, MEDIA_FV_FILE) => {
+                if let Some(guid) = signature_list::read_guid(data, 0) {
+                    image.name_length = 0;
+                    let _ = write!(NameWriter(&mut image), "{}", GuidFmt(&guid));
+                }
+            }
+            _ => {}
+        }
+        node = unsafe { node.add(length) };
+    }
+    if image.name_length == 0 {
+        let _ = NameWriter(&mut image).write_str("?");
+    }
+    image
+}
+
+/**
+ * @brief Writes the last component of a File() node's UCS-2 path, without
+ *        its extension.
+ */
+fn write_file_name(image: &mut Image, path: &[u8]) {
+    let chars = || {
+        path.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|c| *c != 0)
+    };
+    let mut start = 0;
+    let mut end = 0;
+    let mut dot = None;
+    for (index, c) in chars().enumerate() {
+        if c == u16::from(b'\\') || c == u16::from(b'/') {
+            start = index + 1;
+            dot = None;
+        } else if c == u16::from(b'.') {
+            dot = Some(index);
+        }
+        end = index + 1;
+    }
+    let end = dot.unwrap_or(end);
+    for c in chars().skip(start).take(end - start) {
+        let byte = u8::try_from(c).unwrap_or(b'?');
+        let _ = NameWriter(image).write_str(core::str::from_utf8(&[byte]).unwrap_or("?"));
+    }
+}
+
+static TABLE: AtomicRefCell<Table<MAX_IMAGES>> = AtomicRefCell::new(Table::new());
+
+/**
+ * @brief Rebuilds the table from the handles carrying LoadedImage. Returns
+ *        the number of images recorded.
+ */
+pub fn refresh(boot_services: &efi::BootServices) -> Result<usize, efi::Status> {
+    let mut count = 0usize;
+    let mut handles: *mut efi::Handle = core::ptr::null_mut();
+    let efi_status = (boot_services.locate_handle_buffer)(
+        efi::LocateSearchType::ByProtocol,
+        &loaded_image::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut count,
+        &mut handles,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+
+    let mut table = Table::new();
+    let mut dropped = 0;
+    for index in 0..count {
+        let handle = unsafe { handles.add(index).read() };
+        let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+        let efi_status = (boot_services.handle_protocol)(
+            handle,
+            &loaded_image::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+            &mut interface,
+        );
+        if efi_status.is_error() || interface.is_null() {
+            continue;
+        }
+        let loaded_image = unsafe { &*(interface as *const loaded_image::Protocol) };
+        let image = image_named(
+            loaded_image.image_base as usize,
+            loaded_image.image_size as usize,
+            loaded_image.file_path,
+        );
+        if !table.insert(image) {
+            dropped += 1;
+        }
+    }
+    let _ = (boot_services.free_pool)(handles as *mut core::ffi::c_void);
+
+    let recorded = table.count();
+    match TABLE.try_borrow_mut() {
+        Ok(mut current) => *current = table,
+        Err(_) => return Err(efi::Status::ACCESS_DENIED),
+    }
+    info!(
+        TAG,
+        "Images recorded: {}, {} not recorded", recorded, dropped
+    );
+    Ok(recorded)
+}
+
+/**
+ * @brief Builds the table and registers a notification rebuilding it at
+ *        ReadyToBoot. Returns the event, which the caller must close on
+ *        unload.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> Result<r_efi::base::Event, efi::Status> {
+    refresh(boot_services)?;
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_ready_to_boot,
+        boot_services as *mut efi::BootServices as *mut core::ffi::c_void,
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(event)
+}
+
+efiapi! {
+    /**
+     * @brief Rebuilds the table before control goes to a boot option.
+     */
+    fn handle_ready_to_boot(_event: r_efi::base::Event, context: *mut core::ffi::c_void) {
+        let boot_services = unsafe { &*(context as *const efi::BootServices) };
+        if let Err(efi_status) = refresh(boot_services) {
+            error!(TAG, "Images not refreshed : {:#x}", efi_status.as_usize());
+        }
+    }
+}
+
+/**
+ * @brief Converts the image bases at SetVirtualAddressMap, keeping the
+ *        images firmware mapped.
+ */
+pub fn relocate(convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status) {
+    if let Ok(mut table) = TABLE.try_borrow_mut() {
+        let kept = table.relocate(convert);
+        debug!(TAG, "Images relocated: {} kept", kept);
+    }
+}
+
+/**
+ * @brief Returns the recorded image `address` falls inside of, if any and
+ *        the table is not in use.
+ */
+pub fn find(address: usize) -> Option<Image> {
+    TABLE
+        .try_borrow_mut()
+        .ok()
+        .and_then(|table| table.find(address).copied())
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *TABLE.borrow_mut() = Table::new();
+}
+
+/**
+ * @brief Records an image loaded from the file path of `components`.
+ */
+#[cfg(all(test, feature = "enforce"))]
+pub fn record(base: usize, size: usize, components: &[&str]) {
+    let path = file_path(components);
+    let image = image_named(base, size, path.as_ptr() as *const device_path::Protocol);
+    TABLE.borrow_mut().insert(image);
+}
+
+/**
+ * @brief Returns a device path of File() nodes for `components`.
+ */
+#[cfg(test)]
+pub fn file_path(components: &[&str]) -> std::vec::Vec<u8> {
+    let mut path = std::vec::Vec::new();
+    for component in components {
+        let text: std::vec::Vec<u16> = component.encode_utf16().chain([0]).collect();
+        path.extend_from_slice(&[device_path::TYPE_MEDIA, MEDIA_FILE_PATH]);
+        path.extend_from_slice(&(4 + 2 * text.len() as u16).to_le_bytes());
+        for c in text {
+            path.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+    path.extend_from_slice(&[device_path::TYPE_END, 0xff, 4, 0]);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(path: &[u8]) -> std::string::String {
+        image_named(0, 0, path.as_ptr() as *const device_path::Protocol)
+            .name()
+            .into()
+    }
+
+    #[test]
+    fn images_are_named_after_their_file() {
+        assert_eq!(named(&file_path(&["\\EFI\\BOOT\\BOOTX64.EFI"])), "BOOTX64");
+        assert_eq!(named(&file_path(&["\\EFI\\Tools", "Shell.efi"])), "Shell");
+        assert_eq!(named(&file_path(&["a.b.c"])), "a.b");
+        assert_eq!(named(&file_path(&["\\EFI\\"])), "?");
+        assert_eq!(named(&[device_path::TYPE_END, 0xff, 4, 0]), "?");
+        assert_eq!(image_named(0, 0, core::ptr::null()).name(), "?");
+
+        let mut fv_file = std::vec![device_path::TYPE_MEDIA, MEDIA_FV_FILE, 20, 0];
+        fv_file.extend_from_slice(crate::classify::GLOBAL_VARIABLE_GUID.as_bytes());
+        fv_file.extend_from_slice(&[device_path::TYPE_END, 0xff, 4, 0]);
+        assert_eq!(named(&fv_file), "8BE4DF61-93CA-11D2-AA0D-00E098032B8C");
+
+        let long = "x".repeat(100);
+        assert_eq!(named(&file_path(&[&long])).len(), MAX_IMAGE_NAME);
+    }
+
+    #[test]
+    fn addresses_resolve_within_images_kept_at_relocation() {
+        let mut table = Table::<2>::new();
+        let image = |base, size| Image {
+            base,
+            size,
+            ..image_named(0, 0, core::ptr::null())
+        };
+        assert!(table.insert(image(0x1000, 0x2000)));
+        assert!(table.insert(image(0x8000, 0x1000)));
+        assert!(!table.insert(image(0x10000, 0x1000)));
+        assert_eq!(table.find(0x2fff).map(|image| image.base), Some(0x1000));
+        assert!(table.find(0x3000).is_none());
+        assert!(table.find(0xfff).is_none());
+
+        let kept = table.relocate(&mut |address| unsafe {
+            if *address as usize == 0x8000 {
+                return efi::Status::NOT_FOUND;
+            }
+            *address = (*address as usize + 0x4000_0000) as *mut core::ffi::c_void;
+            efi::Status::SUCCESS
+        });
+        assert_eq!(kept, 1);
+        assert_eq!(
+            table.find(0x4000_1800).map(|image| image.base),
+            Some(0x4000_1000)
+        );
+        assert!(table.find(0x8000).is_none());
+    }
+}
@@ -0,0 +1,260 @@
+// uefi-var-monitor-rust/src/integrity.rs
+//
+// Verification that the GetVariable slot still points at our hook. Another
+// driver can overwrite it after we hooked it and silently cut us out, so the
+// slot is checked periodically and once more at ReadyToBoot, the last point
+// before the OS loader runs. Both checks stop at ExitBootServices. The
+// SetVariable hook, which is still installed when GetVariable's is not, also
+// checks at the top of every call, in either phase. Each check first follows
+// the firmware if it republished the Runtime Services Table.
+//
+// When the slot changed, an alert is raised once per interloper, and depending
+// on the policy the hook is re-installed on top of it. The interloper then
+// becomes the chain target that GET_VARIABLE forwards to. Another driver doing
+// the same would have both re-installing forever, so there are at most
+// max_reinstalls re-installs per boot (UVM_HOOK_REINSTALLS, see settings.rs
+// in the library).
+//
+// The system table is registered for conversion at SetVirtualAddressMap, as
+// the checks from SetVariable go on at OS runtime. The table is modified
+// through exchange_pointer_in_service_table, which leaves boot services alone
+// in that phase.
+
+use crate::alerts::Rule;
+use crate::rules;
+use crate::tag::{self, Tag};
+use crate::GetVariableType;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use r_efi::efi;
+
+pub use uefi_var_monitor::settings::{IntegrityPolicy, DEFAULT_MAX_REINSTALLS};
+
+const TAG: Tag = tag::INTEGRITY;
+
+// Interval of the check timer in 100ns units (1s).
+const CHECK_INTERVAL: u64 = 10_000_000;
+
+static POLICY: AtomicU32 = AtomicU32::new(IntegrityPolicy::Record as u32);
+static SYSTEM_TABLE: AtomicPtr<efi::SystemTable> = AtomicPtr::new(core::ptr::null_mut());
+static TIMER_EVENT: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+static READY_TO_BOOT_EVENT: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+// Slot value last reported, so that an unchanged interloper is alerted on once.
+static LAST_INTERLOPER: AtomicUsize = AtomicUsize::new(0);
+static REPLACEMENTS: AtomicU64 = AtomicU64::new(0);
+// Set once the hook was re-installed on top of another one.
+static REHOOKED: AtomicBool = AtomicBool::new(false);
+static REINSTALLS: AtomicU32 = AtomicU32::new(0);
+static MAX_REINSTALLS: AtomicU32 = AtomicU32::new(DEFAULT_MAX_REINSTALLS);
+
+/**
+ * @brief Changes the policy applied when the hook was replaced.
+ */
+pub fn set_policy(policy: IntegrityPolicy) {
+    POLICY.store(policy as u32, Ordering::Release);
+}
+
+pub fn policy() -> IntegrityPolicy {
+    IntegrityPolicy::from_u32(POLICY.load(Ordering::Acquire)).unwrap_or(IntegrityPolicy::Record)
+}
+
+/**
+ * @brief Changes how many times per boot the hook may be re-installed.
+ */
+pub fn set_max_reinstalls(max_reinstalls: u32) {
+    MAX_REINSTALLS.store(max_reinstalls, Ordering::Release);
+}
+
+/**
+ * @brief Returns how many times the hook was re-installed so far.
+ */
+pub fn reinstalls() -> u32 {
+    REINSTALLS.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Returns whether GET_VARIABLE now leads to a hook installed after ours,
+ *        which may forward back to us.
+ */
+pub fn is_rehooked() -> bool {
+    REHOOKED.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Starts the check timer and registers the ReadyToBoot check. Must be
+ *        called after the GetVariable hook is installed.
+ */
+pub fn start(system_table: &mut efi::SystemTable) -> efi::Status {
+    let boot_services = unsafe { &mut *system_table.boot_services };
+
+    let mut timer_event: r_efi::base::Event = core::ptr::null_mut();
+    let mut efi_status = (boot_services.create_event)(
+        efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_timer,
+        core::ptr::null_mut(),
+        &mut timer_event,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+
+    let mut ready_to_boot_event: r_efi::base::Event = core::ptr::null_mut();
+    efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_ready_to_boot,
+        core::ptr::null_mut(),
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut ready_to_boot_event,
+    );
+    if efi_status.is_error() {
+        (boot_services.close_event)(timer_event);
+        return efi_status;
+    }
+
+    efi_status =
+        (boot_services.set_timer)(timer_event, efi::TimerDelay::TimerPeriodic, CHECK_INTERVAL);
+    if efi_status.is_error() {
+        (boot_services.close_event)(ready_to_boot_event);
+        (boot_services.close_event)(timer_event);
+        return efi_status;
+    }
+
+    efi_status = crate::relocate::register("IntegritySystemTable", &SYSTEM_TABLE);
+    if efi_status.is_error() {
+        (boot_services.close_event)(ready_to_boot_event);
+        (boot_services.close_event)(timer_event);
+        return efi_status;
+    }
+
+    SYSTEM_TABLE.store(system_table, Ordering::Release);
+    TIMER_EVENT.store(timer_event, Ordering::Release);
+    READY_TO_BOOT_EVENT.store(ready_to_boot_event, Ordering::Release);
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Closes the check events. Called at ExitBootServices, after which
+ *        only the checks from SetVariable remain.
+ */
+pub fn stop() {
+    let system_table = SYSTEM_TABLE.load(Ordering::Acquire);
+    if system_table.is_null() {
+        return;
+    }
+    let boot_services = unsafe { &mut *(*system_table).boot_services };
+
+    for event in [&TIMER_EVENT, &READY_TO_BOOT_EVENT] {
+        let event = event.swap(core::ptr::null_mut(), Ordering::AcqRel);
+        if !event.is_null() {
+            (boot_services.close_event)(event);
+        }
+    }
+}
+
+/**
+ * @brief Stops every check. Called at unload.
+ */
+pub fn shutdown() {
+    stop();
+    SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::Release);
+}
+
+efiapi! {
+    fn handle_timer(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        check("timer");
+    }
+}
+
+efiapi! {
+    fn handle_ready_to_boot(
+        _event: r_efi::base::Event,
+        _context: *mut core::ffi::c_void,
+    ) {
+        check("ReadyToBoot");
+    }
+}
+
+/**
+ * @brief Compares the GetVariable slot against our hook and applies the
+ *        policy if it was replaced.
+ */
+pub fn check(reason: &str) {
+    let system_table = SYSTEM_TABLE.load(Ordering::Acquire);
+    if system_table.is_null() {
+        return;
+    }
+    crate::follow_runtime_services_table(system_table, crate::phase());
+    let runtime_services = unsafe { &mut *(*system_table).runtime_services };
+
+    let hook = crate::handle_get_variable as GetVariableType as usize;
+    let current = runtime_services.get_variable as usize;
+    if current == hook || LAST_INTERLOPER.swap(current, Ordering::AcqRel) == current {
+        return;
+    }
+    REPLACEMENTS.fetch_add(1, Ordering::AcqRel);
+    rules::emit(
+        Rule::HookIntegrity,
+        rules::Severity::Critical,
+        format_args!(
+            "GetVariable hook {:#x} replaced ({}): slot is now {:#x}",
+            hook, reason, current
+        ),
+    );
+
+    if policy() != IntegrityPolicy::Reinstall {
+        return;
+    }
+    let max_reinstalls = MAX_REINSTALLS.load(Ordering::Acquire);
+    let reinstalls = REINSTALLS.load(Ordering::Acquire);
+    if reinstalls >= max_reinstalls {
+        rules::emit(
+            Rule::HookIntegrity,
+            rules::Severity::Critical,
+            format_args!(
+                "GetVariable hook not re-installed on top of {:#x}: {} re-install(s) done, at most {} per boot",
+                current,
+                reinstalls,
+                max_reinstalls
+        ),
+        );
+        return;
+    }
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        &mut runtime_services.get_variable as *mut _ as *mut *mut core::ffi::c_void,
+        hook as *mut core::ffi::c_void,
+        crate::GET_VARIABLE.as_mut_ptr(),
+        crate::phase(),
+    );
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return;
+    }
+    REHOOKED.store(true, Ordering::Release);
+    LAST_INTERLOPER.store(0, Ordering::Release);
+    let reinstalls = REINSTALLS.fetch_add(1, Ordering::AcqRel) + 1;
+    rules::emit(
+        Rule::HookIntegrity,
+        rules::Severity::Critical,
+        format_args!(
+            "GetVariable hook {:#x} re-installed on top of {:#x} ({} of {})",
+            hook, current, reinstalls, max_reinstalls
+        ),
+    );
+}
+
+#[cfg(test)]
+pub fn reset() {
+    SYSTEM_TABLE.store(core::ptr::null_mut(), Ordering::Release);
+    LAST_INTERLOPER.store(0, Ordering::Release);
+    REPLACEMENTS.store(0, Ordering::Release);
+    REHOOKED.store(false, Ordering::Release);
+    REINSTALLS.store(0, Ordering::Release);
+    set_policy(IntegrityPolicy::Record);
+    set_max_reinstalls(DEFAULT_MAX_REINSTALLS);
+}
@@ -0,0 +1,1028 @@
+// uefi-var-monitor-rust/src/inventory.rs
+//
+// The variables in the store when the driver loaded, the baseline later
+// passes over the store are compared against. Each entry gives the vendor
+// GUID, the name as the hooks render it (see convert_name() in main.rs), the
+// attributes and size, and the CRC32 of the data if it is at most
+// MAX_HASHED_SIZE bytes and may be read (see safety.rs): redacted variables
+// are never hashed. Entries are sorted by GUID, then name.
+//
+// The store is walked once at load, before GetNextVariableName and
+// SetVariable are hooked: names come from the firmware's GetNextVariableName
+// in the runtime services table, and values from the firmware's GetVariable
+// the hook saved, under the reentrancy guard, so that the walk is neither
+// logged nor counted. It follows the GetVariable hook only because the
+// redaction list is read through it.
+//
+// The walk is bounded, with no heap:
+//
+//   names       read into NAME_BUFFER_SIZE bytes. A name the firmware says
+//               is larger cannot be stepped past without reading it, so it
+//               ends the walk, with a warning
+//   data        read into MAX_HASHED_SIZE bytes; a larger variable only has
+//               its size kept
+//   variables   MAX_INVENTORY are kept; those found past it are counted
+//   calls       at most MAX_WALK_STEPS names are asked for, so that a store
+//               handing the same names round forever ends too
+//
+// A walk that ended early says so in its summary line, and what it kept
+// stays the baseline. With log-inventory, every entry is also written as an
+// I: record after the summary:
+//
+//   I: <guid> <name> Attributes=00000007 Size=00000004 CRC32=1c291ca3
+//
+// with CRC32=-------- where the data was not hashed, and Size=-------- where
+// GetVariable failed on a name GetNextVariableName gave.
+//
+// At the first ReadyToBoot, the store is walked again the same way into a
+// second table, and the two sorted tables are merged to find what DXE and BDS
+// changed. Each change is a V: record in three columns, the change, the
+// variable and what changed, after a summary line:
+//
+//   V: created <guid>:<name> Size=00000010
+//   V: deleted <guid>:<name> Size=00000004
+//   V: resized <guid>:<name> Size=00000004->00000006
+//   V: changed <guid>:<name> CRC32=1c291ca3->8a9136aa (modified outside monitored path)
+//
+// A variable is changed when its size is the same and the CRC32 of its data
+// is not, so one that was not hashed both times can only be found resized.
+// Created and deleted variables are only listed when neither walk ended
+// early nor dropped any.
+//
+// A change is flagged as outside the monitored path when the SetVariable hook
+// accepted no write to the variable since load, as when a setup driver or an
+// option ROM writes the store directly, or SMM does. The hook marks the
+// baseline entry of each variable it sees written, and keeps up to
+// MAX_WRITTEN_NEW variables that have none; beyond that, or once the hook had
+// to skip writes (see budget.rs), an unmarked change is only "maybe" outside.
+// The monitor's own variables are written around the hooks, and never
+// flagged.
+//
+// The baseline also tells the hooks which variables were created since load
+// (see newcomer.rs), as long as the walk at load listed them all.
+
+use crate::config::UVM_VENDOR_GUID;
+use crate::hook::HookSlot;
+use crate::level::Level;
+use crate::safety::{self, Inspection};
+use crate::tag::{self, Tag};
+use crate::{crc32, GetNextVariableNameType, GetVariableType, GuidFmt};
+use atomic_refcell::AtomicRefCell;
+use core::cmp::Ordering as Order;
+use core::convert::TryFrom;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::INTEGRITY;
+
+pub const MAX_INVENTORY: usize = 512;
+// Largest name read, in bytes with its terminator.
+pub const NAME_BUFFER_SIZE: usize = 1024;
+pub const MAX_HASHED_SIZE: usize = 4096;
+pub const MAX_WALK_STEPS: usize = 2 * MAX_INVENTORY;
+pub const MAX_WRITTEN_NEW: usize = 32;
+// As many characters as convert_name() reads.
+const NAME_LENGTH: usize = 64;
+
+const ZERO_GUID: efi::Guid = efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub guid: efi::Guid,
+    name: [u8; NAME_LENGTH],
+    name_length: u8,
+    pub attributes: u32,
+    // None if GetVariable failed on the name.
+    pub size: Option<u32>,
+    // None if the data was not hashed.
+    pub crc32: Option<u32>,
+    // Whether the SetVariable hook accepted a write to it since load.
+    written: bool,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        guid: ZERO_GUID,
+        name: [0; NAME_LENGTH],
+        name_length: 0,
+        attributes: 0,
+        size: None,
+        crc32: None,
+        written: false,
+    };
+
+    pub fn name(&self) -> &str {
+        let name = self
+            .name
+            .get(..usize::from(self.name_length))
+            .unwrap_or(&[]);
+        // convert_name() gave only printable ASCII.
+        unsafe { core::str::from_utf8_unchecked(name) }
+    }
+
+    fn order(&self, name: &str, guid: &efi::Guid) -> Order {
+        self.guid
+            .as_bytes()
+            .cmp(guid.as_bytes())
+            .then_with(|| self.name().cmp(name))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    // GetNextVariableName found no more names.
+    Complete,
+    // A name of this many bytes did not fit the buffer.
+    NameTooLarge(usize),
+    // MAX_WALK_STEPS names were asked for.
+    TooManySteps,
+    // GetNextVariableName failed, or the walk could not start.
+    Failed(efi::Status),
+}
+
+impl fmt::Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            End::Complete => f.write_str("complete"),
+            End::NameTooLarge(size) => write!(f, "ended at a name of {} bytes", size),
+            End::TooManySteps => write!(f, "ended after {} names", MAX_WALK_STEPS),
+            End::Failed(efi_status) => write!(f, "ended by {:#x}", efi_status.as_usize()),
+        }
+    }
+}
+
+pub struct Inventory {
+    entries: [Entry; MAX_INVENTORY],
+    count: usize,
+    // Variables found once the table was full.
+    dropped: usize,
+    // None until the store was walked.
+    end: Option<End>,
+}
+
+impl Inventory {
+    const EMPTY: Inventory = Inventory {
+        entries: [Entry::EMPTY; MAX_INVENTORY],
+        count: 0,
+        dropped: 0,
+        end: None,
+    };
+}
+
+// The store at load, and at the first ReadyToBoot.
+static INVENTORY: AtomicRefCell<Inventory> = AtomicRefCell::new(Inventory::EMPTY);
+static AT_READY_TO_BOOT: AtomicRefCell<Inventory> = AtomicRefCell::new(Inventory::EMPTY);
+// The firmware's service, as the table held it at load.
+static FIRMWARE_GET_NEXT_VARIABLE_NAME: HookSlot<GetNextVariableNameType> = HookSlot::new();
+static COMPARED: AtomicBool = AtomicBool::new(false);
+// Set when the SetVariable hook did not look at an accepted write.
+static MISSED: AtomicBool = AtomicBool::new(false);
+
+// Variables outside the baseline the SetVariable hook saw written, by GUID
+// and the CRC32 of their name.
+struct WrittenNew {
+    keys: [(efi::Guid, u32); MAX_WRITTEN_NEW],
+    count: usize,
+    // Whether one did not fit.
+    overflowed: bool,
+}
+
+impl WrittenNew {
+    fn add(&mut self, guid: &efi::Guid, name_crc32: u32) {
+        if self.contains(guid, name_crc32) {
+            return;
+        }
+        match self.keys.get_mut(self.count) {
+            Some(key) => {
+                *key = (*guid, name_crc32);
+                self.count += 1;
+            }
+            None => self.overflowed = true,
+        }
+    }
+
+    fn contains(&self, guid: &efi::Guid, name_crc32: u32) -> bool {
+        self.keys
+            .get(..self.count)
+            .unwrap_or(&[])
+            .contains(&(*guid, name_crc32))
+    }
+}
+
+static WRITTEN_NEW: AtomicRefCell<WrittenNew> = AtomicRefCell::new(WrittenNew {
+    keys: [(ZERO_GUID, 0); MAX_WRITTEN_NEW],
+    count: 0,
+    overflowed: false,
+});
+
+impl Inventory {
+    pub fn entries(&self) -> &[Entry] {
+        self.entries.get(..self.count).unwrap_or(&[])
+    }
+
+    fn add(&mut self, entry: Entry) {
+        match self.entries.get_mut(self.count) {
+            Some(slot) => {
+                *slot = entry;
+                self.count += 1;
+            }
+            None => self.dropped += 1,
+        }
+    }
+
+    /**
+     * @brief Adds every variable the store holds, as far as the bounds allow,
+     *        and returns how the walk ended.
+     */
+    fn walk(
+        &mut self,
+        get_next_variable_name: GetNextVariableNameType,
+        get_variable: GetVariableType,
+    ) -> End {
+        self.count = 0;
+        self.dropped = 0;
+        let mut name = [0u16; NAME_BUFFER_SIZE / 2];
+        let mut guid = ZERO_GUID;
+        for _ in 0..MAX_WALK_STEPS {
+            let mut name_size = NAME_BUFFER_SIZE;
+            let efi_status = get_next_variable_name(&mut name_size, name.as_mut_ptr(), &mut guid);
+            match efi_status {
+                efi::Status::SUCCESS => {}
+                efi::Status::NOT_FOUND => return End::Complete,
+                efi::Status::BUFFER_TOO_SMALL => return End::NameTooLarge(name_size),
+                _ => return End::Failed(efi_status),
+            }
+            // A name filling the buffer ends at its last character.
+            if let Some(last) = name.last_mut() {
+                *last = 0;
+            }
+            self.add(read(&mut name, guid, get_variable));
+        }
+        End::TooManySteps
+    }
+
+    fn sort(&mut self) {
+        let count = self.count;
+        if let Some(entries) = self.entries.get_mut(..count) {
+            entries.sort_unstable_by(|a, b| a.order(b.name(), &b.guid));
+        }
+    }
+
+    fn find_mut(&mut self, name: &str, guid: &efi::Guid) -> Option<&mut Entry> {
+        let index = self
+            .entries()
+            .binary_search_by(|entry| entry.order(name, guid))
+            .ok()?;
+        self.entries.get_mut(index)
+    }
+
+    /**
+     * @brief Returns whether the walk listed every variable the store held.
+     */
+    fn is_whole(&self) -> bool {
+        self.end == Some(End::Complete) && self.dropped == 0
+    }
+
+    /**
+     * @brief Walks the store through the firmware's services, under the
+     *        reentrancy guard, and sorts what it found.
+     */
+    fn take(&mut self, get_next_variable_name: GetNextVariableNameType) -> End {
+        let end = match crate::FIRMWARE_GET_VARIABLE.get() {
+            Some(_) if crate::IN_GET_VARIABLE.swap(true, Ordering::Acquire) => {
+                End::Failed(efi::Status::NOT_READY)
+            }
+            Some(get_variable) => {
+                let end = self.walk(get_next_variable_name, get_variable);
+                crate::IN_GET_VARIABLE.store(false, Ordering::Release);
+                end
+            }
+            None => End::Failed(efi::Status::NOT_READY),
+        };
+        self.sort();
+        self.end = Some(end);
+        end
+    }
+}
+
+/**
+ * @brief Reads the variable `name` under `guid` into an entry.
+ */
+fn read(name: &mut [u16], guid: efi::Guid, get_variable: GetVariableType) -> Entry {
+    let mut entry = Entry {
+        guid,
+        ..Entry::EMPTY
+    };
+    entry.name_length = crate::convert_name(name.as_ptr(), &mut entry.name).len() as u8;
+    let rendered = entry.name();
+
+    let mut data = [0u8; MAX_HASHED_SIZE];
+    let mut guid_buffer = guid;
+    let mut attributes = 0u32;
+    let mut data_size = data.len();
+    let efi_status = get_variable(
+        name.as_mut_ptr(),
+        &mut guid_buffer,
+        &mut attributes,
+        &mut data_size,
+        data.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    let crc32 = match efi_status {
+        efi::Status::SUCCESS => {
+            let data = data.as_ptr() as *const core::ffi::c_void;
+            match safety::inspect(rendered, &guid, data, data_size, MAX_HASHED_SIZE) {
+                Inspection::Data(data) if data.len() == data_size => Some(crc32::crc32(data)),
+                _ => None,
+            }
+        }
+        efi::Status::BUFFER_TOO_SMALL => None,
+        _ => return entry,
+    };
+    Entry {
+        attributes,
+        size: Some(u32::try_from(data_size).unwrap_or(u32::MAX)),
+        crc32,
+        ..entry
+    }
+}
+
+/**
+ * @brief Walks the store and keeps what it holds as the baseline. Must be
+ *        called after the GetVariable hook is installed and the redaction
+ *        list is loaded, and before GetNextVariableName is hooked.
+ */
+pub fn load(runtime_services: &efi::RuntimeServices) {
+    let mut inventory = match INVENTORY.try_borrow_mut() {
+        Ok(inventory) => inventory,
+        Err(_) => return,
+    };
+    FIRMWARE_GET_NEXT_VARIABLE_NAME.set(runtime_services.get_next_variable_name);
+    let end = inventory.take(runtime_services.get_next_variable_name);
+
+    let (mut non_volatile, mut bytes, mut hashed) = (0, 0u64, 0);
+    for entry in inventory.entries() {
+        if entry.attributes & efi::VARIABLE_NON_VOLATILE != 0 {
+            non_volatile += 1;
+        }
+        bytes += u64::from(entry.size.unwrap_or(0));
+        if entry.crc32.is_some() {
+            hashed += 1;
+        }
+    }
+    log_at!(
+        if end == End::Complete {
+            Level::Trace
+        } else {
+            Level::Warning
+        },
+        TAG,
+        "Inventory at load: {} variables, {} not kept, {} non-volatile, {} bytes, {} hashed; {}",
+        inventory.count + inventory.dropped,
+        inventory.dropped,
+        non_volatile,
+        bytes,
+        hashed,
+        end
+    );
+    #[cfg(feature = "log-inventory")]
+    for entry in inventory.entries() {
+        log_record!("I: {}", Record(entry));
+    }
+}
+
+/**
+ * @brief Registers the notification comparing the store at ReadyToBoot with
+ *        the baseline. Returns the event, which the caller must close on
+ *        unload.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> Result<r_efi::base::Event, efi::Status> {
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_ready_to_boot,
+        core::ptr::null_mut(),
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(event)
+}
+
+efiapi! {
+    fn handle_ready_to_boot(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        compare();
+    }
+}
+
+/**
+ * @brief Marks the variable as written, after the SetVariable hook saw a
+ *        write to it accepted.
+ */
+pub fn observe_write(name: &str, guid: &efi::Guid) {
+    if COMPARED.load(Ordering::Acquire) {
+        return;
+    }
+    match INVENTORY.try_borrow_mut() {
+        Ok(mut inventory) => {
+            if let Some(entry) = inventory.find_mut(name, guid) {
+                entry.written = true;
+                return;
+            }
+        }
+        Err(_) => return missed(),
+    }
+    match WRITTEN_NEW.try_borrow_mut() {
+        Ok(mut written_new) => written_new.add(guid, crc32::crc32(name.as_bytes())),
+        Err(_) => missed(),
+    }
+}
+
+/**
+ * @brief Notes that the SetVariable hook did not look at a write.
+ */
+pub fn missed() {
+    MISSED.store(true, Ordering::Release);
+}
+
+/**
+ * @brief Returns whether the walk at load listed every variable.
+ */
+pub fn is_whole_at_load() -> bool {
+    INVENTORY
+        .try_borrow_mut()
+        .map(|inventory| inventory.is_whole())
+        .unwrap_or(false)
+}
+
+/**
+ * @brief Returns whether the variable was in the store at load, or None if
+ *        the walk at load did not list every variable or the table is busy.
+ */
+pub fn was_at_load(name: &str, guid: &efi::Guid) -> Option<bool> {
+    let inventory = INVENTORY.try_borrow_mut().ok()?;
+    if !inventory.is_whole() {
+        return None;
+    }
+    Some(
+        inventory
+            .entries()
+            .binary_search_by(|entry| entry.order(name, guid))
+            .is_ok(),
+    )
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Created,
+    Deleted,
+    Resized { before: u32, after: u32 },
+    Changed { before: u32, after: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Observed {
+    Written,
+    NotWritten,
+    // Not marked, but writes may have gone unmarked.
+    NotKnown,
+}
+
+/**
+ * @brief A change found between the two walks, as a V: record without the
+ *        prefix.
+ */
+pub struct Change<'a> {
+    pub kind: Kind,
+    // The entry at ReadyToBoot, or at load for a deleted variable.
+    pub entry: &'a Entry,
+    pub observed: Observed,
+}
+
+impl fmt::Display for Change<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let word = match self.kind {
+            Kind::Created => "created",
+            Kind::Deleted => "deleted",
+            Kind::Resized { .. } => "resized",
+            Kind::Changed { .. } => "changed",
+        };
+        write!(
+            f,
+            "{} {}:{} ",
+            word,
+            GuidFmt(&self.entry.guid),
+            self.entry.name()
+        )?;
+        match self.kind {
+            Kind::Created | Kind::Deleted => match self.entry.size {
+                Some(size) => write!(f, "Size={:08x}", size)?,
+                None => f.write_str("Size=--------")?,
+            },
+            Kind::Resized { before, after } => write!(f, "Size={:08x}->{:08x}", before, after)?,
+            Kind::Changed { before, after } => write!(f, "CRC32={:08x}->{:08x}", before, after)?,
+        }
+        match self.observed {
+            Observed::Written => Ok(()),
+            Observed::NotWritten => f.write_str(" (modified outside monitored path)"),
+            Observed::NotKnown => f.write_str(" (maybe outside monitored path)"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub created: usize,
+    pub deleted: usize,
+    pub resized: usize,
+    pub changed: usize,
+    // Of those, the changes flagged as outside the monitored path.
+    pub outside: usize,
+    // Whether created and deleted variables were looked for.
+    pub listed_all: bool,
+}
+
+/**
+ * @brief Merges the sorted tables of the store `before` and `after`, and
+ *        calls `emit` on each change, in the order of the tables.
+ */
+fn diff(
+    before: &Inventory,
+    after: &Inventory,
+    written_new: &WrittenNew,
+    missed: bool,
+    emit: &mut dyn FnMut(&Change),
+) -> Totals {
+    let mut totals = Totals {
+        listed_all: before.is_whole() && after.is_whole(),
+        ..Totals::default()
+    };
+    let observed = |written: bool, guid: &efi::Guid, overflowed: bool| {
+        if written || *guid == UVM_VENDOR_GUID {
+            Observed::Written
+        } else if missed || overflowed {
+            Observed::NotKnown
+        } else {
+            Observed::NotWritten
+        }
+    };
+    let (mut old, mut new) = (
+        before.entries().iter().peekable(),
+        after.entries().iter().peekable(),
+    );
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (Some(a), Some(b)) => a.order(b.name(), &b.guid),
+            (Some(_), None) => Order::Less,
+            (None, Some(_)) => Order::Greater,
+            (None, None) => break,
+        };
+        let change = match order {
+            Order::Less => {
+                let a = old.next();
+                a.filter(|_| totals.listed_all).map(|a| Change {
+                    kind: Kind::Deleted,
+                    entry: a,
+                    observed: observed(a.written, &a.guid, false),
+                })
+            }
+            Order::Greater => {
+                let b = new.next();
+                b.filter(|_| totals.listed_all).map(|b| {
+                    let written = written_new.contains(&b.guid, crc32::crc32(b.name().as_bytes()));
+                    Change {
+                        kind: Kind::Created,
+                        entry: b,
+                        observed: observed(written, &b.guid, written_new.overflowed),
+                    }
+                })
+            }
+            Order::Equal => match (old.next(), new.next()) {
+                (Some(a), Some(b)) => {
+                    let kind = match (a.size, b.size, a.crc32, b.crc32) {
+                        (Some(before), Some(after), _, _) if before != after => {
+                            Some(Kind::Resized { before, after })
+                        }
+                        (_, _, Some(before), Some(after)) if before != after => {
+                            Some(Kind::Changed { before, after })
+                        }
+                        _ => None,
+                    };
+                    kind.map(|kind| Change {
+                        kind,
+                        entry: b,
+                        observed: observed(a.written, &a.guid, false),
+                    })
+                }
+                _ => None,
+            },
+        };
+        if let Some(change) = change {
+            match change.kind {
+                Kind::Created => totals.created += 1,
+                Kind::Deleted => totals.deleted += 1,
+                Kind::Resized { .. } => totals.resized += 1,
+                Kind::Changed { .. } => totals.changed += 1,
+            }
+            if change.observed == Observed::NotWritten {
+                totals.outside += 1;
+            }
+            emit(&change);
+        }
+    }
+    totals
+}
+
+/**
+ * @brief Walks the store again and logs what changed since load. Only the
+ *        first call does anything, as ReadyToBoot is signalled again for
+ *        each boot attempt.
+ */
+pub fn compare() {
+    if COMPARED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let get_next_variable_name = match FIRMWARE_GET_NEXT_VARIABLE_NAME.get() {
+        Some(get_next_variable_name) => get_next_variable_name,
+        None => return,
+    };
+    let (before, mut after, written_new) = match (
+        INVENTORY.try_borrow_mut(),
+        AT_READY_TO_BOOT.try_borrow_mut(),
+        WRITTEN_NEW.try_borrow_mut(),
+    ) {
+        (Ok(before), Ok(after), Ok(written_new)) => (before, after, written_new),
+        _ => return,
+    };
+    let end = after.take(get_next_variable_name);
+    let totals = diff(
+        &before,
+        &after,
+        &written_new,
+        MISSED.load(Ordering::Acquire),
+        &mut |change| {
+            log_record_at!(
+                if change.observed == Observed::NotWritten {
+                    Level::Warning
+                } else {
+                    Level::Trace
+                },
+                "V: {}",
+                change
+            );
+        },
+    );
+    log_at!(
+        if totals.outside != 0 || !totals.listed_all {
+            Level::Warning
+        } else {
+            Level::Trace
+        }, TAG,
+        "Store diff at ReadyToBoot: {} created, {} deleted, {} resized, {} changed, {} outside monitored path; {}{}",
+        totals.created,
+        totals.deleted,
+        totals.resized,
+        totals.changed,
+        totals.outside,
+        end,
+        if totals.listed_all {
+            ""
+        } else {
+            ", created and deleted not listed as a walk was not whole"
+        }
+    );
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *INVENTORY.borrow_mut() = Inventory::EMPTY;
+    COMPARED.store(false, Ordering::Release);
+    MISSED.store(false, Ordering::Release);
+    let mut written_new = WRITTEN_NEW.borrow_mut();
+    written_new.count = 0;
+    written_new.overflowed = false;
+}
+
+/**
+ * @brief Runs `f` on the inventory, if the store was walked and the
+ *        inventory is not in use.
+ */
+#[cfg(test)]
+pub fn with_inventory<R>(f: impl FnOnce(&Inventory, End) -> R) -> Option<R> {
+    let inventory = INVENTORY.try_borrow_mut().ok()?;
+    let end = inventory.end?;
+    Some(f(&inventory, end))
+}
+
+// An entry as an I: record, without the prefix.
+#[cfg(feature = "log-inventory")]
+struct Record<'a>(&'a Entry);
+
+#[cfg(feature = "log-inventory")]
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entry = self.0;
+        write!(
+            f,
+            "{} {} Attributes={:08x} Size=",
+            GuidFmt(&entry.guid),
+            entry.name(),
+            entry.attributes
+        )?;
+        match entry.size {
+            Some(size) => write!(f, "{:08x}", size)?,
+            None => f.write_str("--------")?,
+        }
+        match entry.crc32 {
+            Some(crc32) => write!(f, " CRC32={:08x}", crc32),
+            None => f.write_str(" CRC32=--------"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+    use std::boxed::Box;
+    use std::string::String;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    const GLOBAL: efi::Guid = GLOBAL_VARIABLE_GUID;
+
+    // The variables the fake store hands out, in order, and whether it hands
+    // them round forever. GetVariable fails on names starting with X.
+    static STORE: Mutex<Vec<(efi::Guid, String, Vec<u8>)>> = Mutex::new(Vec::new());
+    static LOOPING: Mutex<bool> = Mutex::new(false);
+
+    fn fill(variables: &[(efi::Guid, &str, &[u8])], looping: bool) {
+        *STORE.lock().unwrap() = variables
+            .iter()
+            .map(|(guid, name, data)| (*guid, String::from(*name), data.to_vec()))
+            .collect();
+        *LOOPING.lock().unwrap() = looping;
+    }
+
+    fn name_of(variable_name: *const u16) -> String {
+        let name: Vec<u16> = (0..)
+            .map(|index| unsafe { *variable_name.add(index) })
+            .take_while(|c| *c != 0)
+            .collect();
+        String::from_utf16(&name).unwrap()
+    }
+
+    efiapi! {
+        fn fake_get_next_variable_name(
+            variable_name_size: *mut usize,
+            variable_name: *mut r_efi::base::Char16,
+            vendor_guid: *mut r_efi::base::Guid,
+        ) -> efi::Status {
+            let store = STORE.lock().unwrap();
+            let current = (unsafe { *vendor_guid }, name_of(variable_name));
+            let mut next = match store
+                .iter()
+                .position(|(guid, name, _)| (*guid, name.clone()) == current)
+            {
+                Some(position) => position + 1,
+                None => 0,
+            };
+            if next == store.len() && *LOOPING.lock().unwrap() {
+                next = 0;
+            }
+            let (guid, name) = match store.get(next) {
+                Some((guid, name, _)) => (*guid, name.encode_utf16().chain([0]).collect::<Vec<_>>()),
+                None => return efi::Status::NOT_FOUND,
+            };
+            let needed = 2 * name.len();
+            if unsafe { *variable_name_size } < needed {
+                unsafe { *variable_name_size = needed };
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            for (index, c) in name.iter().enumerate() {
+                unsafe { *variable_name.add(index) = *c };
+            }
+            unsafe {
+                *variable_name_size = needed;
+                *vendor_guid = guid;
+            }
+            efi::Status::SUCCESS
+        }
+    }
+
+    efiapi! {
+        fn fake_get_variable(
+            variable_name: *mut r_efi::base::Char16,
+            vendor_guid: *mut r_efi::base::Guid,
+            attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let store = STORE.lock().unwrap();
+            let key = (unsafe { *vendor_guid }, name_of(variable_name));
+            let value = match store.iter().find(|(guid, name, _)| (*guid, name.clone()) == key) {
+                Some(_) if key.1.starts_with('X') => return efi::Status::DEVICE_ERROR,
+                Some((_, _, value)) => value,
+                None => return efi::Status::NOT_FOUND,
+            };
+            unsafe {
+                *attributes = efi::VARIABLE_BOOTSERVICE_ACCESS;
+                let available = *data_size;
+                *data_size = value.len();
+                if available < value.len() {
+                    return efi::Status::BUFFER_TOO_SMALL;
+                }
+                core::ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len());
+            }
+            efi::Status::SUCCESS
+        }
+    }
+
+    fn walk(inventory: &mut Inventory) -> End {
+        let end = inventory.walk(fake_get_next_variable_name, fake_get_variable);
+        inventory.sort();
+        inventory.end = Some(end);
+        end
+    }
+
+    #[test]
+    fn the_store_is_listed_sorted() {
+        let _lock = crate::mock::lock();
+        let large = [0x55; MAX_HASHED_SIZE + 1];
+        fill(
+            &[
+                (GLOBAL, "Timeout", &[5, 0]),
+                (GLOBAL, "Boot0001", b"Bo"),
+                (GLOBAL, "Lang", &large),
+                (GLOBAL, "Xfail", &[]),
+            ],
+            false,
+        );
+        let mut inventory = Box::new(Inventory::EMPTY);
+        assert_eq!(walk(&mut inventory), End::Complete);
+        assert!(inventory.is_whole());
+        let listed: Vec<_> = inventory
+            .entries()
+            .iter()
+            .map(|entry| (entry.name(), entry.size, entry.crc32))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("Boot0001", Some(2), Some(crc32::crc32(b"Bo"))),
+                ("Lang", Some(MAX_HASHED_SIZE as u32 + 1), None),
+                ("Timeout", Some(2), Some(crc32::crc32(&[5, 0]))),
+                ("Xfail", None, None),
+            ]
+        );
+        assert_eq!(
+            inventory.entries()[0].attributes,
+            efi::VARIABLE_BOOTSERVICE_ACCESS
+        );
+        assert_eq!(inventory.entries()[3].attributes, 0);
+        assert!(inventory.find_mut("Timeout", &GLOBAL).is_some());
+        assert!(inventory.find_mut("Timeout", &UVM_VENDOR_GUID).is_none());
+    }
+
+    #[test]
+    fn the_walk_is_bounded() {
+        let _lock = crate::mock::lock();
+        let mut inventory = Box::new(Inventory::EMPTY);
+        // A name larger than the buffer ends the walk where it is.
+        let giant = "G".repeat(NAME_BUFFER_SIZE);
+        fill(
+            &[
+                (GLOBAL, "Timeout", &[5, 0]),
+                (GLOBAL, &giant, &[1]),
+                (GLOBAL, "Lang", b"eng"),
+            ],
+            false,
+        );
+        assert_eq!(
+            walk(&mut inventory),
+            End::NameTooLarge(2 * NAME_BUFFER_SIZE + 2)
+        );
+        assert_eq!(inventory.entries().len(), 1);
+        assert!(!inventory.is_whole());
+
+        // A store handing its names round ends after MAX_WALK_STEPS, keeping
+        // MAX_INVENTORY of them.
+        fill(
+            &[(GLOBAL, "Timeout", &[5, 0]), (GLOBAL, "Lang", b"eng")],
+            true,
+        );
+        assert_eq!(walk(&mut inventory), End::TooManySteps);
+        assert_eq!(inventory.entries().len(), MAX_INVENTORY);
+        assert_eq!(inventory.dropped, MAX_WALK_STEPS - MAX_INVENTORY);
+    }
+
+    fn changes(
+        before: &Inventory,
+        after: &Inventory,
+        written_new: &WrittenNew,
+        missed: bool,
+    ) -> (Vec<String>, Totals) {
+        let mut lines = Vec::new();
+        let totals = diff(before, after, written_new, missed, &mut |change| {
+            lines.push(std::format!("{}", change))
+        });
+        (lines, totals)
+    }
+
+    #[test]
+    fn changes_are_found_by_merging_the_walks() {
+        let _lock = crate::mock::lock();
+        let mut before = Box::new(Inventory::EMPTY);
+        let mut after = Box::new(Inventory::EMPTY);
+        fill(
+            &[
+                (GLOBAL, "BootOrder", &[1, 0, 2, 0]),
+                (GLOBAL, "Lang", b"eng"),
+                (GLOBAL, "PlatformLang", b"en-US\0"),
+                (GLOBAL, "Timeout", &[5, 0]),
+                (UVM_VENDOR_GUID, "UvmBootReport", &[1]),
+            ],
+            false,
+        );
+        walk(&mut before);
+        fill(
+            &[
+                (GLOBAL, "Boot0002", &[9]),
+                (GLOBAL, "BootOrder", &[1, 0]),
+                (GLOBAL, "Lang", b"fra"),
+                (GLOBAL, "Timeout", &[5, 0]),
+                (UVM_VENDOR_GUID, "UvmBootReport", &[2]),
+                (UVM_VENDOR_GUID, "UvmLevel", &[3]),
+            ],
+            false,
+        );
+        walk(&mut after);
+        before.find_mut("BootOrder", &GLOBAL).unwrap().written = true;
+        let mut written_new = WrittenNew {
+            keys: [(ZERO_GUID, 0); MAX_WRITTEN_NEW],
+            count: 0,
+            overflowed: false,
+        };
+
+        let (lines, totals) = changes(&before, &after, &written_new, false);
+        let global = "8BE4DF61-93CA-11D2-AA0D-00E098032B8C";
+        let vendor = std::format!("{}", GuidFmt(&UVM_VENDOR_GUID));
+        let outside = " (modified outside monitored path)";
+        let mut expected = std::vec![
+            std::format!("created {}:Boot0002 Size=00000001{}", global, outside),
+            std::format!("resized {}:BootOrder Size=00000004->00000002", global),
+            std::format!(
+                "changed {}:Lang CRC32={:08x}->{:08x}{}",
+                global,
+                crc32::crc32(b"eng"),
+                crc32::crc32(b"fra"),
+                outside
+            ),
+            std::format!("deleted {}:PlatformLang Size=00000006{}", global, outside),
+            std::format!(
+                "changed {}:UvmBootReport CRC32={:08x}->{:08x}",
+                vendor,
+                crc32::crc32(&[1]),
+                crc32::crc32(&[2])
+            ),
+            std::format!("created {}:UvmLevel Size=00000001", vendor),
+        ];
+        // The vendor GUID sorts first, by its bytes.
+        expected.sort_by_key(|line| !line.contains(&vendor));
+        assert_eq!(lines, expected);
+        assert_eq!(
+            totals,
+            Totals {
+                created: 2,
+                deleted: 1,
+                resized: 1,
+                changed: 2,
+                outside: 3,
+                listed_all: true,
+            }
+        );
+
+        // A new variable seen written is not flagged; with writes missed,
+        // the rest only may have been changed outside.
+        written_new.add(&GLOBAL, crc32::crc32(b"Boot0002"));
+        let (lines, totals) = changes(&before, &after, &written_new, true);
+        assert!(lines.contains(&std::format!("created {}:Boot0002 Size=00000001", global)));
+        assert!(lines.iter().any(
+            |line| line.contains(":Lang ") && line.ends_with(" (maybe outside monitored path)")
+        ));
+        assert_eq!(totals.outside, 0);
+
+        // Without the whole store both times, only what both walks found is
+        // compared.
+        before.end = Some(End::TooManySteps);
+        let (lines, totals) = changes(&before, &after, &written_new, false);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            (totals.created, totals.deleted, totals.listed_all),
+            (0, 0, false)
+        );
+    }
+}
@@ -0,0 +1,223 @@
+// uefi-var-monitor-rust/src/last_value.rs
+//
+// Last value observed per variable, so that a write can be reported against
+// what it replaced. Values are copied from successful GetVariable and
+// SetVariable calls, and only while the caller's data buffer may be read (see
+// safety.rs).
+//
+// Memory is fixed at build time so that the cache cannot grow at runtime:
+// MAX_VALUES entries of at most MAX_VALUE_SIZE bytes. A longer value keeps
+// its first bytes along with its full size, and once the table is full the
+// oldest entry is replaced. Like seen.rs, the table is only ever
+// try-borrowed.
+
+use crate::crc32;
+use atomic_refcell::AtomicRefCell;
+use r_efi::efi;
+
+pub const MAX_VALUES: usize = 16;
+pub const MAX_VALUE_SIZE: usize = 256;
+
+#[derive(Clone, Copy)]
+pub struct Value {
+    guid: efi::Guid,
+    name_crc32: u32,
+    // Size of the variable, which may exceed what was kept.
+    size: usize,
+    // CRC32 of the whole value, if it was all given.
+    crc32: Option<u32>,
+    length: usize,
+    data: [u8; MAX_VALUE_SIZE],
+}
+
+impl Value {
+    /**
+     * @brief Copies the value of a variable of `size` bytes, of which `data`
+     *        holds the first ones.
+     */
+    fn new(name: &str, guid: &efi::Guid, data: &[u8], size: usize) -> Self {
+        let mut value = Value {
+            guid: *guid,
+            name_crc32: crc32::crc32(name.as_bytes()),
+            size,
+            crc32: if data.len() == size {
+                Some(crc32::crc32(data))
+            } else {
+                None
+            },
+            length: 0,
+            data: [0; MAX_VALUE_SIZE],
+        };
+        for (slot, byte) in value.data.iter_mut().zip(data) {
+            *slot = *byte;
+            value.length += 1;
+        }
+        value
+    }
+
+    /**
+     * @brief Returns the bytes kept, the first MAX_VALUE_SIZE at most.
+     */
+    pub fn data(&self) -> &[u8] {
+        self.data.get(..self.length).unwrap_or(&[])
+    }
+
+    /**
+     * @brief Returns the full size of the value.
+     */
+    #[allow(dead_code)]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /**
+     * @brief Returns the CRC32 of the whole value, if it was given whole,
+     *        even if only its first bytes were kept.
+     */
+    pub fn crc32(&self) -> Option<u32> {
+        self.crc32
+    }
+
+    /**
+     * @brief Returns whether the whole value was kept, not only its first
+     *        bytes.
+     */
+    #[allow(dead_code)]
+    pub fn is_complete(&self) -> bool {
+        self.length == self.size
+    }
+}
+
+pub struct Table<const N: usize> {
+    entries: [Option<Value>; N],
+    // Entry replaced next once the table is full.
+    next: usize,
+}
+
+impl<const N: usize> Table<N> {
+    pub const fn new() -> Self {
+        Table {
+            entries: [None; N],
+            next: 0,
+        }
+    }
+
+    fn position(&self, guid: &efi::Guid, name_crc32: u32) -> Option<usize> {
+        self.entries.iter().position(|entry| match entry {
+            Some(value) => value.name_crc32 == name_crc32 && value.guid == *guid,
+            None => false,
+        })
+    }
+
+    /**
+     * @brief Records the value of a variable of `size` bytes, of which `data`
+     *        holds the first ones.
+     */
+    pub fn record(&mut self, name: &str, guid: &efi::Guid, data: &[u8], size: usize) {
+        let value = Value::new(name, guid, data, size);
+        let index = match self.position(guid, value.name_crc32) {
+            Some(index) => index,
+            None => match self.entries.iter().position(|entry| entry.is_none()) {
+                Some(index) => index,
+                None => {
+                    let index = self.next;
+                    self.next = (self.next + 1) % N;
+                    index
+                }
+            },
+        };
+        if let Some(entry) = self.entries.get_mut(index) {
+            *entry = Some(value);
+        }
+    }
+
+    /**
+     * @brief Forgets the value of a deleted variable.
+     */
+    pub fn remove(&mut self, name: &str, guid: &efi::Guid) {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        if let Some(entry) = self
+            .position(guid, name_crc32)
+            .and_then(|index| self.entries.get_mut(index))
+        {
+            *entry = None;
+        }
+    }
+
+    /**
+     * @brief Returns the value last recorded for a variable.
+     */
+    pub fn get(&self, name: &str, guid: &efi::Guid) -> Option<&Value> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        self.position(guid, name_crc32)
+            .and_then(|index| self.entries.get(index)?.as_ref())
+    }
+}
+
+static TABLE: AtomicRefCell<Table<MAX_VALUES>> = AtomicRefCell::new(Table::new());
+
+/**
+ * @brief Records the value of a variable, unless the table is in use.
+ */
+pub fn record(name: &str, guid: &efi::Guid, data: &[u8], size: usize) {
+    if let Ok(mut table) = TABLE.try_borrow_mut() {
+        table.record(name, guid, data, size);
+    }
+}
+
+/**
+ * @brief Forgets the value of a variable, unless the table is in use.
+ */
+pub fn remove(name: &str, guid: &efi::Guid) {
+    if let Ok(mut table) = TABLE.try_borrow_mut() {
+        table.remove(name, guid);
+    }
+}
+
+/**
+ * @brief Calls `f` with the value last recorded for a variable, or None if
+ *        it is not known or the table is in use.
+ */
+pub fn with<R>(name: &str, guid: &efi::Guid, f: impl FnOnce(Option<&Value>) -> R) -> R {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => f(table.get(name, guid)),
+        Err(_) => f(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+
+    #[test]
+    fn bounded_in_entries_and_size() {
+        let mut table = Table::<2>::new();
+        table.record("BootOrder", &GLOBAL_VARIABLE_GUID, &[1, 0, 2, 0], 4);
+        let long = [0xaa; MAX_VALUE_SIZE + 8];
+        table.record("Boot0001", &GLOBAL_VARIABLE_GUID, &long, long.len());
+
+        let value = table.get("Boot0001", &GLOBAL_VARIABLE_GUID).unwrap();
+        assert_eq!(value.data().len(), MAX_VALUE_SIZE);
+        assert_eq!(value.size(), MAX_VALUE_SIZE + 8);
+        assert_eq!(value.crc32(), Some(crc32::crc32(&long)));
+        table.record("Boot0001", &GLOBAL_VARIABLE_GUID, &long[..8], long.len());
+        let value = table.get("Boot0001", &GLOBAL_VARIABLE_GUID).unwrap();
+        assert_eq!((value.data().len(), value.crc32()), (8, None));
+
+        // Updating an entry does not take a new one.
+        table.record("BootOrder", &GLOBAL_VARIABLE_GUID, &[2, 0], 2);
+        let value = table.get("BootOrder", &GLOBAL_VARIABLE_GUID).unwrap();
+        assert_eq!(value.data(), [2, 0]);
+        assert!(table.get("Boot0001", &GLOBAL_VARIABLE_GUID).is_some());
+
+        // A third variable replaces the oldest.
+        table.record("Boot0002", &GLOBAL_VARIABLE_GUID, &[], 0);
+        assert!(table.get("BootOrder", &GLOBAL_VARIABLE_GUID).is_none());
+        assert!(table.get("Boot0002", &GLOBAL_VARIABLE_GUID).is_some());
+
+        table.remove("Boot0002", &GLOBAL_VARIABLE_GUID);
+        assert!(table.get("Boot0002", &GLOBAL_VARIABLE_GUID).is_none());
+        assert!(table.get("Boot0001", &GLOBAL_VARIABLE_GUID).is_some());
+    }
+}
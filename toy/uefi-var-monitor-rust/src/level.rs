@@ -0,0 +1,219 @@
+// uefi-var-monitor-rust/src/level.rs
+//
+// Which records are written. Every record has a level, most severe first:
+//
+//   critical   critical alerts, through alert!
+//   warning    warning alerts, and error! and warn!
+//   info       info alerts, and info!
+//   trace      everything else: accesses, through record! and log_record!,
+//              and debug! and trace!
+//
+// Records below the current level are dropped before they are formatted.
+// The build profile picks the initial level (see profile.rs); trace writes
+// everything. Logging can also be paused, which drops everything but critical
+// alerts until it is resumed, while the hooks stay installed and keep
+// counting. The records skipped meanwhile are counted, and marker records
+// bracket the gap:
+//
+//   ---- Logging paused ----
+//   ---- Logging resumed, 42 records skipped ----
+//
+// Both are changed through the monitor's protocol (see protocol.rs) and the
+// control variable (see control.rs); with ring-dump, the F11 hotkey toggles
+// the pause (see dump.rs). Setting the level also returns the latency guard
+// to full detail (see budget.rs).
+//
+// What the driver says about itself goes through error!, warn!, info!,
+// debug! and trace!, which are also compiled out per module below a
+// verbosity given at build time (see logging.rs).
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+pub use uefi_var_monitor::settings::Level;
+
+static LEVEL: AtomicU8 = AtomicU8::new(crate::profile::DEFAULT_LEVEL as u8);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+// Records dropped while paused that the level would have written, in this
+// pause and in the pauses before it.
+static SKIPPED: AtomicU64 = AtomicU64::new(0);
+static SKIPPED_BEFORE: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Release);
+    crate::budget::restart();
+}
+
+pub fn level() -> Level {
+    Level::from_u32(LEVEL.load(Ordering::Acquire) as u32).unwrap_or(Level::Trace)
+}
+
+/**
+ * @brief Pauses or resumes logging. Returns whether it was paused before.
+ */
+pub fn set_paused(paused: bool) -> bool {
+    PAUSED.swap(paused, Ordering::AcqRel)
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Pauses logging, after a marker record. Does nothing if it already
+ *        is.
+ */
+pub fn pause() {
+    if is_paused() {
+        return;
+    }
+    log_record_at!(Level::Critical, "---- Logging paused ----");
+    SKIPPED.store(0, Ordering::Release);
+    set_paused(true);
+}
+
+/**
+ * @brief Resumes logging, with a marker record giving the records skipped.
+ *        Does nothing if it was not paused.
+ */
+pub fn resume() {
+    if set_paused(false) {
+        let skipped = SKIPPED.swap(0, Ordering::AcqRel);
+        SKIPPED_BEFORE.fetch_add(skipped, Ordering::AcqRel);
+        log_record_at!(
+            Level::Critical,
+            "---- Logging resumed, {} records skipped ----",
+            skipped
+        );
+    }
+}
+
+/**
+ * @brief Returns the number of records skipped while paused since load or
+ *        the last reset_skipped().
+ */
+pub fn skipped() -> u64 {
+    SKIPPED_BEFORE.load(Ordering::Acquire) + SKIPPED.load(Ordering::Acquire)
+}
+
+pub fn reset_skipped() {
+    SKIPPED_BEFORE.store(0, Ordering::Release);
+}
+
+/**
+ * @brief Returns whether records of `level` are written, counting those
+ *        skipped only for the pause.
+ */
+// The builds without a sink drop records unasked (see logging.rs).
+#[cfg_attr(
+    all(not(test), any(feature = "profile-minimal", feature = "no-log")),
+    allow(dead_code)
+)]
+pub fn is_enabled(level: Level) -> bool {
+    if level == Level::Critical {
+        return true;
+    }
+    if level as u8 > LEVEL.load(Ordering::Acquire) {
+        return false;
+    }
+    if is_paused() {
+        SKIPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+pub fn reset() {
+    set_level(Level::Trace);
+    set_paused(false);
+    SKIPPED.store(0, Ordering::Release);
+    reset_skipped();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::{self, Tag};
+
+    const TAG: Tag = tag::CONFIG;
+
+    #[test]
+    fn records_below_the_level_or_while_paused_are_dropped() {
+        let _lock = crate::mock::lock();
+        reset();
+        assert!(is_enabled(Level::Trace));
+        assert_eq!(Level::from_u32(4), None);
+
+        set_level(Level::Warning);
+        assert_eq!(level(), Level::Warning);
+        assert!(is_enabled(Level::Critical));
+        assert!(is_enabled(Level::Warning));
+        assert!(!is_enabled(Level::Info));
+        assert!(!is_enabled(Level::Trace));
+
+        assert!(!set_paused(true));
+        assert!(is_enabled(Level::Critical));
+        assert!(!is_enabled(Level::Warning));
+        assert!(set_paused(false));
+        assert!(is_enabled(Level::Warning));
+
+        reset();
+    }
+
+    #[test]
+    fn the_pause_is_bracketed_with_the_records_skipped() {
+        let _lock = crate::mock::lock();
+        reset();
+        set_level(Level::Info);
+        crate::serial::start_capture();
+        pause();
+        pause();
+        trace!(TAG, "not at this level");
+        info!(TAG, "skipped");
+        warn!(TAG, "skipped");
+        alert!("still written");
+        resume();
+        resume();
+        info!(TAG, "written");
+        assert_eq!(
+            crate::serial::without_locations(&crate::serial::take_capture()),
+            "---- Logging paused ----\n\
+             ALERT: still written\n\
+             ---- Logging resumed, 2 records skipped ----\n\
+             [cfg] written\n"
+        );
+        assert_eq!(skipped(), 2);
+        reset();
+    }
+
+    // Built with trace compiled out (see MODULES in logging.rs).
+    mod quiet {
+        pub fn say() {
+            debug!(super::TAG, "uvm-quiet-debug-line");
+            trace!(super::TAG, "uvm-quiet-trace-line");
+        }
+    }
+
+    #[test]
+    fn a_module_built_without_trace_holds_none_of_its_trace_strings() {
+        let _lock = crate::mock::lock();
+        reset();
+        crate::serial::start_capture();
+        quiet::say();
+        assert_eq!(
+            crate::serial::without_locations(&crate::serial::take_capture()),
+            "[cfg] uvm-quiet-debug-line\n"
+        );
+
+        // Reversed, so that this test does not add them itself.
+        let binary = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let holds = |reversed: &str| {
+            let text: std::string::String = reversed.chars().rev().collect();
+            binary
+                .windows(text.len())
+                .any(|window| window == text.as_bytes())
+        };
+        assert!(holds("enil-gubed-teiuq-mvu"));
+        assert!(!holds("enil-ecart-teiuq-mvu"));
+    }
+}
@@ -0,0 +1,142 @@
+// uefi-var-monitor-rust/src/lib.rs
+//
+// What the driver does that does not need firmware: decoding variable names,
+// the G: and S: records and the fields they are built from, the trace filter
+// and the lists it is given in, the call counters and the table of the most
+// accessed variables, the classes of variables and the alerts they raise,
+// the slots the hooks forward through, the settings (the build environment,
+// the image's load options, and the configuration and level blobs they are
+// saved in), and the parsers of what callers write: signature lists, load
+// options and device paths, and the configuration blob. Those, and the name decoder, are what the fuzz
+// targets of fuzz/ feed arbitrary bytes to; the decisions are what uvmlog
+// replay runs captured dumps through (see replay.rs). It takes names, GUIDs
+// and data as slices and values, but for caller.rs, through which the hooks
+// read what their callers pass by pointer; the binary (main.rs and the
+// modules it declares) holds efi_main, the hooks, and everything else that
+// reads caller or firmware memory through raw pointers, and hands the library
+// what it read.
+//
+// The library is no_std like the driver, and its tests run on the host with
+// std, as the driver's do. So does the mock system table, which the driver's
+// tests take from here with the mock feature.
+
+#![cfg_attr(not(any(test, feature = "mock")), no_std)]
+// Strings are split with closures rather than chars: core searches for a
+// char with code that indexes, and so can panic (see the panic handler in
+// main.rs).
+#![allow(clippy::manual_pattern_char_comparison)]
+
+// efiapi!, for the function types of the hooks (see interface/src/lib.rs).
+#[macro_use]
+extern crate uvm_interface;
+
+pub mod alert;
+pub mod caller;
+pub mod classify;
+pub mod config_format;
+pub mod counter;
+pub mod crc32;
+pub mod filter;
+pub mod hint;
+pub mod hook;
+pub mod load_option;
+// The boot and runtime services the hooks are tested against.
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod pattern;
+pub mod record;
+pub mod replay;
+pub mod settings;
+pub mod signature_list;
+pub mod top;
+
+pub type GetVariableType = efiapi! {fn(
+    *mut r_efi::base::Char16,
+    *mut r_efi::base::Guid,
+    *mut u32,
+    *mut usize,
+    *mut core::ffi::c_void,
+) -> r_efi::base::Status};
+
+pub type SetVariableType = efiapi! {fn(
+    *mut r_efi::base::Char16,
+    *mut r_efi::base::Guid,
+    u32,
+    usize,
+    *mut core::ffi::c_void,
+) -> r_efi::base::Status};
+
+pub type GetNextVariableNameType = efiapi! {fn(
+    *mut usize,
+    *mut r_efi::base::Char16,
+    *mut r_efi::base::Guid,
+) -> r_efi::base::Status};
+
+// Vendor GUIDs for the tests.
+#[cfg(test)]
+mod guids {
+    pub use crate::classify::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID};
+    pub use crate::settings::UVM_VENDOR_GUID;
+}
+
+// The records of a table of accesses, against the golden files of
+// fixtures/records.
+#[cfg(test)]
+mod golden;
+
+// Properties of the GUID and name conversions, over random inputs.
+#[cfg(test)]
+mod properties;
+
+// Reproducible mutations of seed inputs, for the tests that the parsers fed
+// caller data never panic: the fuzz targets (see fuzz/) in short, for the
+// runs that do not have cargo-fuzz.
+#[cfg(test)]
+mod mangle {
+    // xorshift, for reproducible runs.
+    struct Rng(u64);
+
+    impl Rng {
+        fn new() -> Self {
+            Rng(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    /**
+     * @brief Calls `check` on `rounds` inputs, each a seed with up to 8 bytes
+     *        overwritten, then one in four cut short and one in four grown.
+     */
+    pub fn run(seeds: &[&[u8]], rounds: usize, mut check: impl FnMut(&[u8])) {
+        let mut rng = Rng::new();
+        let mut next = move || rng.next();
+        for round in 0..rounds {
+            let mut mangled = seeds[round % seeds.len()].to_vec();
+            if !mangled.is_empty() {
+                for _ in 0..1 + next() % 8 {
+                    let index = (next() as usize) % mangled.len();
+                    mangled[index] = next() as u8;
+                }
+            }
+            match next() % 4 {
+                0 => {
+                    let length = (next() as usize) % (mangled.len() + 1);
+                    mangled.truncate(length);
+                }
+                1 => {
+                    for _ in 0..1 + next() % 16 {
+                        mangled.push(next() as u8);
+                    }
+                }
+                _ => {}
+            }
+            check(&mangled);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// uefi-var-monitor-rust/src/load_option.rs
+//
+// The values of the boot manager variables, as far as an alert line shows
+// them (see boot_option.rs): the UINT16 option numbers of BootOrder and
+// BootNext, and the description and first device path node of a Boot####
+// EFI_LOAD_OPTION. The values are whatever the caller wrote, or what the
+// last-value cache kept of them, so they may be truncated or malformed: they
+// are taken as slices, and decoding stops where the data ends.
+
+use core::fmt::{self, Write};
+
+// EFI_LOAD_OPTION: Attributes, FilePathListLength, then the description.
+const LOAD_OPTION_HEADER_SIZE: usize = 6;
+const LOAD_OPTION_ACTIVE: u32 = 0x0000_0001;
+// Characters of a description shown before it is cut off.
+const MAX_DESCRIPTION: usize = 32;
+
+/**
+ * @brief Returns whether `name` is Boot#### with four uppercase hex digits.
+ */
+pub fn is_load_option(name: &str) -> bool {
+    match name.strip_prefix("Boot") {
+        Some(number) => {
+            number.len() == 4
+                && number
+                    .bytes()
+                    .all(|b| b.is_ascii_digit() || (b'A'..=b'F').contains(&b))
+        }
+        None => false,
+    }
+}
+
+// The option numbers of BootOrder or BootNext, e.g. [0001,0010]; an odd
+// trailing byte is left out.
+pub struct OptionNumbers<'a>(pub &'a [u8]);
+
+impl fmt::Display for OptionNumbers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("[")?;
+        for (index, number) in self.0.chunks_exact(2).enumerate() {
+            if index != 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{:04X}", u16::from_le_bytes([number[0], number[1]]))?;
+        }
+        f.write_str("]")
+    }
+}
+
+// A Boot#### load option, e.g. "Windows Boot Manager" HD(04/01).
+pub struct LoadOption<'a>(pub &'a [u8]);
+
+impl fmt::Display for LoadOption<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = self.0;
+        let header = match data.get(..LOAD_OPTION_HEADER_SIZE) {
+            Some(header) => header,
+            None => return f.write_str("<malformed load option>"),
+        };
+        let attributes = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+
+        // The description is NUL-terminated UCS-2; the device path follows it.
+        f.write_char('"')?;
+        let mut offset = LOAD_OPTION_HEADER_SIZE;
+        let mut shown = 0;
+        let mut terminated = false;
+        while let Some(c) = data.get(offset..offset + 2) {
+            offset += 2;
+            let c = u16::from_le_bytes([c[0], c[1]]);
+            if c == 0 {
+                terminated = true;
+                break;
+            }
+            if shown < MAX_DESCRIPTION {
+                f.write_char(if (0x20..0x7f).contains(&c) {
+                    c as u8 as char
+                } else {
+                    '?'
+                })?;
+            }
+            shown += 1;
+        }
+        f.write_char('"')?;
+        if shown > MAX_DESCRIPTION {
+            f.write_str("...")?;
+        }
+
+        let path = if terminated {
+            data.get(offset..).unwrap_or(&[])
+        } else {
+            &[]
+        };
+        match path.get(..2) {
+            Some(node) => write!(f, " {}", DevicePathNode(node[0], node[1]))?,
+            None => f.write_str(" <no device path>")?,
+        }
+        if attributes & LOAD_OPTION_ACTIVE == 0 {
+            f.write_str(" inactive")?;
+        }
+        Ok(())
+    }
+}
+
+// The type and subtype of a device path node, named where well-known.
+struct DevicePathNode(u8, u8);
+
+impl fmt::Display for DevicePathNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match (self.0, self.1) {
+            (0x01, 0x01) => "Pci",
+            (0x01, 0x04) => "VenHw",
+            (0x02, 0x01) => "Acpi",
+            (0x03, 0x05) => "Usb",
+            (0x03, 0x0b) => "MAC",
+            (0x03, 0x12) => "Sata",
+            (0x03, 0x17) => "NVMe",
+            (0x03, 0x18) => "Uri",
+            (0x04, 0x01) => "HD",
+            (0x04, 0x02) => "CDROM",
+            (0x04, 0x04) => "File",
+            (0x04, 0x06) => "FvFile",
+            (0x04, 0x07) => "Fv",
+            (0x05, 0x01) => "BBS",
+            _ => "DevicePath",
+        };
+        write!(f, "{}({:02x}/{:02x})", name, self.0, self.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    fn load_option(attributes: u32, description: &str, path: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&attributes.to_le_bytes());
+        data.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        for c in description.encode_utf16().chain([0]) {
+            data.extend_from_slice(&c.to_le_bytes());
+        }
+        data.extend_from_slice(path);
+        data
+    }
+
+    #[test]
+    fn boot_manager_values_are_decoded() {
+        assert!(is_load_option("Boot0001"));
+        assert!(is_load_option("Boot00AF"));
+        assert!(!is_load_option("Boot00af"));
+        assert!(!is_load_option("BootOrder"));
+        assert!(!is_load_option("Boot00010"));
+
+        assert_eq!(
+            OptionNumbers(&[1, 0, 0x10, 0, 3]).to_string(),
+            "[0001,0010]"
+        );
+        assert_eq!(OptionNumbers(&[]).to_string(), "[]");
+
+        // HD(1,GPT,...) followed by the end node.
+        let path = [4, 1, 42, 0, 1, 0, 0, 0, 0x7f, 0xff, 4, 0];
+        let option = load_option(LOAD_OPTION_ACTIVE, "Windows Boot Manager", &path);
+        assert_eq!(
+            LoadOption(&option).to_string(),
+            "\"Windows Boot Manager\" HD(04/01)"
+        );
+        let option = load_option(0, "shim\u{e9}", &[1, 4]);
+        assert_eq!(
+            LoadOption(&option).to_string(),
+            "\"shim?\" VenHw(01/04) inactive"
+        );
+
+        // Truncated in the description or right after it.
+        let option = load_option(LOAD_OPTION_ACTIVE, "Windows Boot Manager", &path);
+        assert_eq!(
+            LoadOption(&option[..12]).to_string(),
+            "\"Win\" <no device path>"
+        );
+        let option = load_option(LOAD_OPTION_ACTIVE, "x", &[]);
+        assert_eq!(LoadOption(&option).to_string(), "\"x\" <no device path>");
+        assert_eq!(LoadOption(&[1, 0]).to_string(), "<malformed load option>");
+    }
+
+    // The fuzz target (see fuzz/) in short, on the boot manager values of
+    // the corpus, for the runs that do not have cargo-fuzz.
+    #[test]
+    fn mangled_values_are_shown_without_panicking() {
+        let boot0001: &[u8] = include_bytes!("../fixtures/variables/Boot0001.bin");
+        let boot_order: &[u8] = include_bytes!("../fixtures/variables/BootOrder.bin");
+        crate::mangle::run(&[boot0001, boot_order], 20_000, |data| {
+            let option = LoadOption(data).to_string();
+            // The description is cut off, whatever its length.
+            assert!(option.len() <= MAX_DESCRIPTION + 40, "{}", option);
+            let numbers = OptionNumbers(data).to_string();
+            assert_eq!(
+                numbers.len(),
+                2 + (data.len() / 2) * 5 - (data.len() >= 2) as usize
+            );
+        });
+    }
+}
@@ -0,0 +1,419 @@
+// uefi-var-monitor-rust/src/lock.rs
+//
+// Write-lock after ReadyToBoot, the analogue of EDK2's VariableLock for
+// variables the platform does not lock itself. Only built with the `enforce`
+// feature. At the first ReadyToBoot, the current value of every listed
+// variable is snapshot; from then on SetVariable calls that would modify or
+// delete one are failed with WRITE_PROTECTED without reaching firmware, while
+// rewriting the same attributes and data is let through.
+//
+// The list is the UVM_LOCKED list given at build time plus the entries of the
+// "UvmLock" variable under UVM_VENDOR_GUID read at load, in the format of
+// pattern.rs but with exact names only: a prefix cannot be snapshot without
+// enumerating the store. UvmLock itself is always on it. UVM_LOCK_ABSENT
+// decides what happens to a listed variable that does not exist at lock time
+// (see settings.rs in the library):
+//
+//   lock     it must stay absent; creating it is rejected  (default)
+//   ignore   it is not locked
+//
+// Deciding that a write is identical means reading the caller's buffer, so at
+// OS runtime it follows safety.rs: unless the buffer may be read, every write
+// to a locked variable is rejected. So is every write to a variable whose
+// value could not be kept whole: one larger than last_value::MAX_VALUE_SIZE, a
+// redacted one, or one that could not be read at lock time.
+
+use crate::config::UVM_VENDOR_GUID;
+use crate::last_value::{self, Value};
+use crate::pattern::{self, Pattern, MAX_PATTERN_NAME};
+use crate::rules;
+use crate::safety::{self, Inspection};
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use r_efi::efi;
+
+pub use uefi_var_monitor::settings::AbsentPolicy;
+
+const TAG: Tag = tag::ENFORCE;
+
+pub const MAX_LOCKED: usize = 16;
+
+const LOCK_VARIABLE: &str = "UvmLock";
+
+// "UvmLock"
+const LOCK_VARIABLE_NAME: [u16; 8] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'L' as u16,
+    b'o' as u16,
+    b'c' as u16,
+    b'k' as u16,
+    0,
+];
+
+// What a listed variable was at lock time.
+#[derive(Clone, Copy)]
+enum Snapshot {
+    // Not locked: before ReadyToBoot, or absent and ignored.
+    Open,
+    Absent,
+    // Its value is in VALUES.
+    Present { attributes: u32 },
+    // Its value could not be read or kept; no write is let through.
+    Frozen,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    pattern: Pattern,
+    snapshot: Snapshot,
+}
+
+static ENTRIES: AtomicRefCell<[Option<Entry>; MAX_LOCKED]> = AtomicRefCell::new([None; MAX_LOCKED]);
+// Values of the locked variables, filled once at lock time.
+static VALUES: AtomicRefCell<last_value::Table<MAX_LOCKED>> =
+    AtomicRefCell::new(last_value::Table::new());
+static ABSENT_POLICY: AtomicU32 = AtomicU32::new(AbsentPolicy::Lock as u32);
+static ENGAGED: AtomicBool = AtomicBool::new(false);
+static REJECTED: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Changes what happens to listed variables absent at lock time. Only
+ *        has an effect before the lock is engaged.
+ */
+pub fn set_absent_policy(policy: AbsentPolicy) {
+    ABSENT_POLICY.store(policy as u32, Ordering::Release);
+}
+
+fn absent_policy() -> AbsentPolicy {
+    AbsentPolicy::from_u32(ABSENT_POLICY.load(Ordering::Acquire)).unwrap_or(AbsentPolicy::Lock)
+}
+
+/**
+ * @brief Adds the entries of a list to the lock list. Returns how many were
+ *        added and how many were malformed, prefixes or did not fit.
+ */
+fn add_list(entries: &mut [Option<Entry>; MAX_LOCKED], list: &str) -> (usize, usize) {
+    let (mut added, mut rejected) = (0, 0);
+    for text in list
+        .split(|c| c == ';')
+        .filter(|text| !text.trim().is_empty())
+    {
+        let pattern = Pattern::parse(text).filter(|pattern| !pattern.is_prefix());
+        match (pattern, entries.iter_mut().find(|slot| slot.is_none())) {
+            (Some(pattern), Some(slot)) => {
+                *slot = Some(Entry {
+                    pattern,
+                    snapshot: Snapshot::Open,
+                });
+                added += 1;
+            }
+            _ => rejected += 1,
+        }
+    }
+    (added, rejected)
+}
+
+/**
+ * @brief Loads the lock list. Must be called after the GetVariable hook is
+ *        installed, as UvmLock is read through the saved original pointer.
+ */
+pub fn load() {
+    let mut entries = match ENTRIES.try_borrow_mut() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    if let (Some(pattern), Some(slot)) = (
+        Pattern::exact(LOCK_VARIABLE, &UVM_VENDOR_GUID),
+        entries.iter_mut().find(|slot| slot.is_none()),
+    ) {
+        *slot = Some(Entry {
+            pattern,
+            snapshot: Snapshot::Open,
+        });
+    }
+
+    if let Some(list) = option_env!("UVM_LOCKED") {
+        let (added, rejected) = add_list(&mut entries, list);
+        info!(
+            TAG,
+            "Locked from build: {} entries, {} rejected", added, rejected
+        );
+    }
+
+    let mut buffer = [0u8; pattern::MAX_LIST_SIZE];
+    match crate::read_list_variable(&LOCK_VARIABLE_NAME, &mut buffer) {
+        Ok(list) => {
+            let (added, rejected) = add_list(&mut entries, list);
+            info!(
+                TAG,
+                "Locked from {}: {} entries, {} rejected", LOCK_VARIABLE, added, rejected
+            );
+        }
+        Err(efi::Status::NOT_FOUND) => {}
+        Err(efi_status) => error!(
+            TAG,
+            "{} not read : {:#x}",
+            LOCK_VARIABLE,
+            efi_status.as_usize()
+        ),
+    }
+}
+
+/**
+ * @brief Reads the current value of a listed variable through the saved
+ *        GetVariable, so that the read is not logged, and keeps it in
+ *        `values`.
+ */
+fn take_snapshot(pattern: &Pattern, values: &mut last_value::Table<MAX_LOCKED>) -> Snapshot {
+    let name = pattern.name();
+    let guid = pattern.guid();
+    if crate::redact::is_redacted(name, &guid) {
+        return Snapshot::Frozen;
+    }
+    let mut name_buffer = [0u16; MAX_PATTERN_NAME + 1];
+    for (slot, c) in name_buffer.iter_mut().zip(name.bytes()) {
+        *slot = c as u16;
+    }
+    let mut guid_buffer = guid;
+    let mut attributes = 0u32;
+    let mut data = [0u8; last_value::MAX_VALUE_SIZE];
+    let mut data_size = data.len();
+    let efi_status = crate::GET_VARIABLE.call(
+        name_buffer.as_mut_ptr(),
+        &mut guid_buffer,
+        &mut attributes,
+        &mut data_size,
+        data.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    match efi_status {
+        efi::Status::SUCCESS => {
+            let kept = data.get(..data_size).unwrap_or(&[]);
+            values.record(name, &guid, kept, data_size);
+            Snapshot::Present { attributes }
+        }
+        // Only the size is known, so no rewrite can be told identical.
+        efi::Status::BUFFER_TOO_SMALL => {
+            values.record(name, &guid, &[], data_size);
+            Snapshot::Present { attributes }
+        }
+        efi::Status::NOT_FOUND => match absent_policy() {
+            AbsentPolicy::Lock => Snapshot::Absent,
+            AbsentPolicy::Ignore => Snapshot::Open,
+        },
+        _ => Snapshot::Frozen,
+    }
+}
+
+/**
+ * @brief Snapshots the listed variables and engages the lock. Only the first
+ *        call does anything, as ReadyToBoot is signalled again for each boot
+ *        attempt.
+ */
+pub fn engage() {
+    if ENGAGED.load(Ordering::Acquire) {
+        return;
+    }
+    let (mut entries, mut values) = match (ENTRIES.try_borrow_mut(), VALUES.try_borrow_mut()) {
+        (Ok(entries), Ok(values)) => (entries, values),
+        _ => return,
+    };
+    let mut locked = 0;
+    for entry in entries.iter_mut().flatten() {
+        entry.snapshot = take_snapshot(&entry.pattern, &mut values);
+        match entry.snapshot {
+            Snapshot::Open => {}
+            Snapshot::Frozen => {
+                locked += 1;
+                warn!(
+                    TAG,
+                    "{} {} locked against every write, its value was not kept",
+                    crate::GuidFmt(&entry.pattern.guid()),
+                    entry.pattern.name()
+                );
+            }
+            _ => locked += 1,
+        }
+    }
+    drop(entries);
+    drop(values);
+    ENGAGED.store(true, Ordering::Release);
+    info!(TAG, "Variable lock engaged on {} variable(s)", locked);
+}
+
+/**
+ * @brief Registers the ReadyToBoot notification that engages the lock.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> Result<r_efi::base::Event, efi::Status> {
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_ready_to_boot,
+        core::ptr::null_mut(),
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(event)
+}
+
+efiapi! {
+    /**
+     * @brief Engages the lock before control goes to a boot option.
+     */
+    fn handle_ready_to_boot(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        engage();
+    }
+}
+
+/**
+ * @brief Returns why a write leaves a variable snapshot as `snapshot`, with
+ *        `value` if it was present, in a different state, or None if it
+ *        leaves it as it was.
+ */
+fn modification(
+    snapshot: &Snapshot,
+    value: Option<&Value>,
+    attributes: u32,
+    data_size: usize,
+    inspection: Inspection,
+) -> Option<&'static str> {
+    let deletion = rules::is_deletion(attributes, data_size);
+    match *snapshot {
+        Snapshot::Open => None,
+        Snapshot::Absent if deletion => None,
+        Snapshot::Absent => Some("creates it"),
+        Snapshot::Frozen => Some("value not kept"),
+        Snapshot::Present { .. } if deletion => Some("deletes it"),
+        Snapshot::Present {
+            attributes: kept, ..
+        } if kept != attributes => Some("changes its attributes"),
+        Snapshot::Present { .. } => {
+            let value = match value {
+                Some(value) if value.is_complete() => value,
+                _ => return Some("value not kept"),
+            };
+            match inspection {
+                Inspection::Data(data) if data_size == value.size() && data == value.data() => None,
+                Inspection::Data(_) => Some("modifies it"),
+                _ => Some("data not inspected"),
+            }
+        }
+    }
+}
+
+/**
+ * @brief Returns why a write to the variable `name` under `guid` must be
+ *        rejected, or None to let it through. Fails closed if the list is
+ *        being changed once the lock is engaged.
+ */
+pub fn check(
+    name: &str,
+    guid: &efi::Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *const core::ffi::c_void,
+) -> Option<&'static str> {
+    if !ENGAGED.load(Ordering::Acquire) {
+        return None;
+    }
+    let (entries, values) = match (ENTRIES.try_borrow_mut(), VALUES.try_borrow_mut()) {
+        (Ok(entries), Ok(values)) => (entries, values),
+        _ => return Some("lock list busy"),
+    };
+    let entry = entries
+        .iter()
+        .flatten()
+        .find(|entry| entry.pattern.matches(name, guid))?;
+    let limit = last_value::MAX_VALUE_SIZE;
+    let inspection = safety::inspect(name, guid, data, data_size, limit);
+    let value = values.get(name, guid);
+    modification(&entry.snapshot, value, attributes, data_size, inspection)
+}
+
+/**
+ * @brief Counts a rejected write and returns how many there were so far.
+ */
+pub fn count_rejected() -> u64 {
+    REJECTED.fetch_add(1, Ordering::AcqRel) + 1
+}
+
+/**
+ * @brief Returns the number of writes rejected so far.
+ */
+pub fn rejected() -> u64 {
+    REJECTED.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Zeroes the count of rejected writes.
+ */
+pub fn reset_rejected() {
+    REJECTED.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *ENTRIES.borrow_mut() = [None; MAX_LOCKED];
+    *VALUES.borrow_mut() = last_value::Table::new();
+    ENGAGED.store(false, Ordering::Release);
+    REJECTED.store(0, Ordering::Release);
+    set_absent_policy(AbsentPolicy::Lock);
+}
+
+#[cfg(test)]
+pub fn lock(list: &str) {
+    add_list(&mut ENTRIES.borrow_mut(), list);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+
+    #[test]
+    fn only_identical_rewrites_leave_a_locked_variable() {
+        let guid = GLOBAL_VARIABLE_GUID;
+        let mut values = last_value::Table::<2>::new();
+        values.record("BootOrder", &guid, &[1, 0, 2, 0], 4);
+        // Only the size of a long value is kept.
+        values.record("db", &guid, &[], 4096);
+        let value = values.get("BootOrder", &guid);
+        let present = Snapshot::Present { attributes: 0x07 };
+        let data = |data| Inspection::Data(data);
+        let check = |value, attributes, data_size, inspection| {
+            modification(&present, value, attributes, data_size, inspection)
+        };
+        assert_eq!(check(value, 0x07, 4, data(&[1, 0, 2, 0])), None);
+        assert!(check(value, 0x07, 4, data(&[2, 0, 1, 0])).is_some());
+        assert!(check(value, 0x07, 2, data(&[1, 0])).is_some());
+        assert!(check(value, 0x03, 4, data(&[1, 0, 2, 0])).is_some());
+        assert!(check(value, 0x07, 0, data(&[])).is_some());
+        assert!(check(value, 0x07, 4, Inspection::NotInspected).is_some());
+        assert!(check(value, 0x07, 4, Inspection::Redacted).is_some());
+        let long = values.get("db", &guid);
+        assert!(check(long, 0x07, 4096, data(&[0; 256])).is_some());
+        assert!(check(None, 0x07, 4, data(&[1, 0, 2, 0])).is_some());
+
+        let other = |snapshot, attributes, data_size, inspection| {
+            modification(&snapshot, None, attributes, data_size, inspection)
+        };
+        assert_eq!(other(Snapshot::Absent, 0x07, 0, data(&[])), None);
+        assert!(other(Snapshot::Absent, 0x07, 1, data(&[1])).is_some());
+        assert!(other(Snapshot::Frozen, 0x07, 0, data(&[])).is_some());
+        assert_eq!(other(Snapshot::Open, 0x07, 1, data(&[1])), None);
+    }
+
+    #[test]
+    fn prefixes_cannot_be_locked() {
+        let mut entries = [None; MAX_LOCKED];
+        let list = "8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*;\
+                    8be4df61-93ca-11d2-aa0d-00e098032b8c:BootOrder";
+        assert_eq!(add_list(&mut entries, list), (1, 1));
+    }
+}
@@ -0,0 +1,66 @@
+// uefi-var-monitor-rust/src/logging.rs
+//
+// Where the logging macros write the driver's records. The macros, and the
+// line writer under the serial sink, are shared with uvmctl (see
+// support/src/macros.rs); this is what they call into. A record of a level
+// the current one lets through (see level.rs) goes to every sink registered
+// with sink.rs. Host tests print records instead, and capture them for the
+// test to check (see serial.rs).
+//
+// profile-minimal has no sink to log to, and no-log none at all: no level is
+// enabled there, so that records are type-checked, then dropped, and nothing
+// is formatted and no sink code is referenced.
+//
+// What the driver says about itself through error!, warn!, info!, debug! and
+// trace! is also compiled out per module below a verbosity given at build
+// time in UVM_LOG_MODULES, a list of module=verbosity entries separated by
+// ';', the first matching a module applying:
+//
+//   UVM_LOG_MODULES="filter=trace;*=info"
+//
+// A module is named as in log-src-loc lines, without the crate ("filter",
+// "report", "arch::x86_64", "main" for the crate root), and names its
+// submodules too; "*" names every module. The verbosities are error, warn,
+// info, debug and trace, and off, which compiles out all five. Without an
+// entry, a module builds every one. The runtime level then applies to what
+// is built: debug and trace are both written at the trace level, and only
+// the build tells them apart.
+
+use crate::level::Level;
+use core::fmt;
+
+#[cfg(not(test))]
+pub const MODULES: &str = match option_env!("UVM_LOG_MODULES") {
+    Some(list) => list,
+    None => "",
+};
+// Host tests build one module with trace compiled out (see the tests of
+// level.rs).
+#[cfg(test)]
+pub const MODULES: &str = "level::tests::quiet=debug";
+
+// Whether lines about the driver start with where they were logged.
+pub const LOCATIONS: bool = cfg!(feature = "log-src-loc");
+
+#[cfg(any(test, not(any(feature = "profile-minimal", feature = "no-log"))))]
+pub fn enabled(level: Level) -> bool {
+    crate::level::is_enabled(level)
+}
+
+#[cfg(all(not(test), any(feature = "profile-minimal", feature = "no-log")))]
+#[inline(always)]
+pub fn enabled(_level: Level) -> bool {
+    false
+}
+
+#[cfg(not(test))]
+pub fn write(args: fmt::Arguments) {
+    crate::sink::write(args);
+}
+
+#[cfg(test)]
+pub fn write(args: fmt::Arguments) {
+    #[cfg(feature = "log-serial")]
+    println!("{}", args);
+    crate::serial::capture(args);
+}
@@ -0,0 +1,42 @@
+// uefi-var-monitor-rust/src/macros.rs
+//
+// The macros only the driver has. The logging macros it shares with uvmctl,
+// error!, warn!, info!, debug!, trace!, log_at!, log_record! and
+// log_record_at!, are in the support crate (see support/src/macros.rs and
+// logging.rs); these write through them, the records of accesses from their
+// fields and the alerts.
+
+// Writes the record of an access from its fields, in the form the build
+// writes records in (see fields.rs), at the trace level unless given one:
+//
+//   record!(Get { variable, size, efi_status }, Extra::Content(content));
+//   record!(level, Set { variable, size, efi_status }, Extra::New);
+#[macro_export]
+macro_rules! record {
+    ($op:ident { $($field:ident $(: $value:expr)?),* $(,)? } $(, $extra:expr)* $(,)?) => {
+        record!($crate::level::Level::Trace, $op { $($field $(: $value)?),* } $(, $extra)*)
+    };
+    ($level:expr, $op:ident { $($field:ident $(: $value:expr)?),* $(,)? } $(, $extra:expr)* $(,)?) => {
+        $crate::fields::emit(
+            $level,
+            &$crate::fields::Fields {
+                op: $crate::fields::Op::$op,
+                $($field $(: $value)?,)*
+                extras: &[$($extra),*],
+            },
+        )
+    };
+}
+
+// Logs an alert-class record. Besides the usual log sinks, alerts are shown on
+// the GOP banner and measured into the TPM when those features are enabled.
+#[macro_export]
+macro_rules! alert {
+    ($($arg:tt)*) => {{
+        log_record_at!($crate::level::Level::Critical, "ALERT: {}", format_args!($($arg)*));
+        #[cfg(all(feature = "gop-alert", not(test)))]
+        $crate::gop::show_alert(format_args!($($arg)*));
+        #[cfg(all(feature = "tpm-measure", not(test)))]
+        $crate::tpm::measure_alert(format_args!($($arg)*));
+    }};
+}
@@ -1,205 +1,3988 @@
 // uefi-var-monitor-rust/src/main.rs
 
-#![no_main]
-#![no_std]
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
+// Strings are split with closures rather than chars: core searches for a
+// char with code that indexes, and so can panic (see the panic handler
+// below).
+#![allow(clippy::manual_pattern_char_comparison)]
 
+// What runs without firmware is in the library (see lib.rs). Its modules are
+// imported at the root, so that crate::filter and the like still resolve.
+// efiapi! comes from the definitions shared with the MM module, the logging
+// macros from those shared with uvmctl (see logging.rs).
+#[macro_use]
+extern crate uvm_interface;
+#[macro_use]
+extern crate uvm_support;
+
+use arch::Arch;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering};
+use fields::Extra;
+use hook::HookSlot;
 use r_efi::efi;
+use uefi_var_monitor::record::{self, GuidFmt};
+use uefi_var_monitor::{
+    caller, classify, config_format, crc32, filter, hint, hook, load_option, pattern,
+    signature_list, top,
+};
+use uefi_var_monitor::{GetNextVariableNameType, GetVariableType, SetVariableType};
+use uvm_interface::build;
+use uvm_interface::protocol::{HOOK_ACTIVE, HOOK_PASS_THROUGH, HOOK_UNUSABLE};
+use uvm_interface::sha256;
 
 #[macro_use]
+mod macros;
+mod alerts;
+mod arch;
+mod banner;
+mod benchmark;
+mod boot_option;
+mod budget;
+mod capacity;
+mod config;
+mod config_store;
+mod control;
+mod correlate;
+mod counters;
+mod decide;
+#[cfg(feature = "log-deferred")]
+mod deferred;
+mod diff;
+#[cfg(feature = "ring-dump")]
+mod dump;
+#[cfg(feature = "enforce")]
+mod enforce;
+mod fields;
+mod footprint;
+#[cfg(feature = "enforce")]
+mod get_next_variable_name;
+#[cfg(feature = "gop-alert")]
+mod gop;
+#[cfg(feature = "enforce")]
+mod hide;
+mod history;
+mod images;
+mod integrity;
+mod inventory;
+mod last_value;
+mod level;
+#[cfg(feature = "enforce")]
+mod lock;
+mod logging;
+#[cfg(feature = "mm-events")]
+mod mm;
+mod mode;
+mod mor;
+mod names;
+#[cfg(feature = "log-net")]
+mod net;
+mod newcomer;
+mod options;
+mod overhead;
+#[cfg(feature = "per-cpu")]
+mod percpu;
+mod persist;
+mod pin;
+mod profile;
+mod protocol;
+#[cfg(feature = "serial-queue")]
+mod queue;
+mod rate;
+mod redact;
+mod relocate;
+mod report;
+#[cfg(feature = "log-ring")]
+mod ring;
+mod rules;
+mod safety;
+mod seen;
+mod self_test;
+#[cfg(any(test, not(feature = "no-log")))]
 mod serial;
+mod set_variable;
+mod shadow;
+mod signature;
+mod sink;
+mod snapshot;
+mod stats;
+mod tag;
+mod teardown;
+#[cfg(feature = "tpm-measure")]
+mod tpm;
+
+// The mock system table of the library (see mock.rs there). Its lock also
+// forgets the store at load, which the driver keeps.
+#[cfg(test)]
+mod mock {
+    pub use uefi_var_monitor::mock::*;
+
+    pub fn lock() -> std::sync::MutexGuard<'static, ()> {
+        let guard = uefi_var_monitor::mock::lock();
+        crate::inventory::reset();
+        crate::newcomer::reset();
+        guard
+    }
+}
+
+static GET_VARIABLE: HookSlot<GetVariableType> = HookSlot::new();
+
+// The service provided by the firmware. GET_VARIABLE only differs from it once
+// the hook was re-installed on top of another one (see integrity.rs).
+static FIRMWARE_GET_VARIABLE: HookSlot<GetVariableType> = HookSlot::new();
+static IN_GET_VARIABLE: AtomicBool = AtomicBool::new(false);
+
+// The runtime services table for use at OS runtime, converted along with the
+// other registered pointers.
+static RUNTIME_SERVICES: AtomicPtr<efi::RuntimeServices> = AtomicPtr::new(core::ptr::null_mut());
+
+// What handle_get_variable may still do after a failed relocation. Faulting
+// inside a runtime service call kills the OS without diagnostics, so the hook
+// degrades instead: with HOOK_PASS_THROUGH, as logging may touch unconverted
+// pointers, it only forwards the call; with HOOK_UNUSABLE, nothing converted
+// is left to forward to and it fails the call. The states are reported through
+// the statistics protocol (see stats.rs).
+static HOOK_STATE: AtomicU8 = AtomicU8::new(HOOK_ACTIVE);
+
+/**
+ * @brief Returns the state of the GetVariable hook.
+ */
+fn hook_state() -> u8 {
+    HOOK_STATE.load(Ordering::Acquire)
+}
+
+// Whether ExitBootServices was signaled. Boot services must not be called
+// anymore from then on, and the caller's data buffer is off limits unless
+// configured otherwise (see safety.rs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    BootServices,
+    Runtime,
+}
+static BOOT_SERVICES_EXITED: AtomicBool = AtomicBool::new(false);
+
+fn phase() -> Phase {
+    if BOOT_SERVICES_EXITED.load(Ordering::Acquire) {
+        Phase::Runtime
+    } else {
+        Phase::BootServices
+    }
+}
+
+// The system table the Unload handler releases resources through.
+static mut SYSTEM_TABLE: *mut efi::SystemTable = core::ptr::null_mut();
+
+efiapi! {
+    /**
+     * @brief Handles GetVariable runtime service calls.
+     */
+    fn handle_get_variable(
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        match HOOK_STATE.load(Ordering::Acquire) {
+            HOOK_ACTIVE => {}
+            HOOK_PASS_THROUGH => {
+                return GET_VARIABLE.call(variable_name, vendor_guid, attributes, data_size, data)
+            }
+            _ => return efi::Status::DEVICE_ERROR,
+        }
+        let mut measure = overhead::Measure::start();
+        // profile-minimal counts by GUID and stops there, before the name, and
+        // so does the latency guard at its lowest step; at the critical level
+        // or paused, so does a read no critical check watches (see decide.rs).
+        let plan = decide::plan(
+            cfg!(feature = "profile-minimal"),
+            budget::step(),
+            level::level(),
+            level::is_paused(),
+        );
+
+        // A hook we re-installed on top of forwards to its saved pointer, which is
+        // us. Send that nested call to the firmware instead of recursing forever.
+        let nested = IN_GET_VARIABLE.swap(true, Ordering::Acquire);
+        if nested && integrity::is_rehooked() {
+            return measure.forward(|| {
+                FIRMWARE_GET_VARIABLE.call(variable_name, vendor_guid, attributes, data_size, data)
+            });
+        }
+
+        // Invoke the original GetVariable service and log the invocation.
+        let caller = arch::Current::return_address();
+        if let Some(efi_status) = control::read_status(variable_name, vendor_guid, attributes, data_size, data) {
+            if !nested {
+                IN_GET_VARIABLE.store(false, Ordering::Release);
+            }
+            return efi_status;
+        }
+        #[cfg(feature = "enforce")]
+        if let Some(efi_status) = conceal(variable_name, vendor_guid, caller) {
+            if !nested {
+                IN_GET_VARIABLE.store(false, Ordering::Release);
+            }
+            return efi_status;
+        }
+        // Only the G: record shows the size asked for.
+        let size_before = if plan.quiet {
+            None
+        } else {
+            unsafe { caller::value(data_size) }
+        };
+        let efi_status = measure
+            .forward(|| GET_VARIABLE.call(variable_name, vendor_guid, attributes, data_size, data));
+        if !nested {
+            IN_GET_VARIABLE.store(false, Ordering::Release);
+        }
+
+        // The firmware rejects these with INVALID_PARAMETER; there is nothing to
+        // log beyond that.
+        let guid = match unsafe { caller::value(vendor_guid) } {
+            Some(guid) if !variable_name.is_null() => guid,
+            _ => {
+                record!(Get {
+                    variable: None,
+                    size: fields::Size::Read {
+                        before: size_before,
+                        after: None,
+                    },
+                    efi_status,
+                });
+                return efi_status;
+            }
+        };
 
-type GetVariableType = extern "win64" fn(
-    *mut r_efi::base::Char16,
-    *mut r_efi::base::Guid,
-    *mut u32,
-    *mut usize,
-    *mut core::ffi::c_void,
-) -> r_efi::base::Status;
+        // Decide on the G: record before decoding the name: the level rules it
+        // out with one load, and the filter by GUID alone, before is_traced()
+        // matches the name against its entries.
+        let guid = &guid;
+        let size_after = data_size_after(efi_status, data_size);
+        counters::count(top::Access::Read, efi_status, size_after);
+        // Without an Attributes buffer, the storage of the variable is unknown.
+        let attributes_after = match (efi_status, unsafe { caller::value(attributes) }) {
+            (efi::Status::SUCCESS, Some(attributes)) => attributes,
+            _ => 0,
+        };
+        if let (efi::Status::SUCCESS, Some(size)) = (efi_status, size_after) {
+            counters::count_storage(top::Access::Read, attributes_after, size);
+        }
+        if !plan.names_read(watched(guid, attributes_after)) {
+            top::count_guid(guid, top::Access::Read);
+            return efi_status;
+        }
+        let traced = plan.may_trace && filter::may_trace(guid);
+
+        let rendered = names::render(variable_name, guid);
+        let name = rendered.name();
+        rate::observe(name, guid, caller);
+        top::count(name, guid, top::Access::Read);
+        if let (efi::Status::SUCCESS, Some(size)) = (efi_status, size_after) {
+            counters::note_storage(name, guid, top::Access::Read, attributes_after, size);
+        }
+        let traced = traced && filter::is_traced(name, guid);
+        let sighting = match efi_status {
+            efi::Status::SUCCESS => newcomer::observe(name, guid),
+            _ => None,
+        };
+        // In changes mode, how the data compares with the last read decides
+        // whether it is recorded (see seen.rs).
+        let content = match (traced, efi_status, size_after) {
+            (true, efi::Status::SUCCESS, Some(size)) => {
+                diff::observe_read(name, guid, data, size);
+                seen::observe_read(name, guid, data, size)
+            }
+            _ => None,
+        };
+        let variable = Some(fields::Variable {
+            variable_name,
+            guid,
+            rendered: &rendered,
+        });
+        let size = fields::Size::Read {
+            before: size_before,
+            after: size_after,
+        };
+        match decide::record(true, traced, sighting, content) {
+            decide::Record::New(level) => record!(
+                level,
+                Get {
+                    variable,
+                    size,
+                    efi_status,
+                },
+                Extra::Content(content),
+                Extra::New,
+            ),
+            decide::Record::Access => record!(
+                Get {
+                    variable,
+                    size,
+                    efi_status,
+                },
+                Extra::Content(content),
+            ),
+            decide::Record::None => {}
+        }
+        rules::check(name, guid, rules::Access::Get, efi_status);
+        if efi_status == efi::Status::SUCCESS && !attributes.is_null() {
+            seen::record(name, guid, attributes_after);
+        }
+        if let (efi::Status::SUCCESS, Some(size)) = (efi_status, size_after) {
+            if plan.decode_values {
+                boot_option::observe(name, guid, data, size);
+            }
+            if let Some(change) = seen::observe_size(name, guid, size) {
+                rules::emit(
+                    alerts::Rule::SizeChange,
+                    rules::Severity::Warning,
+                    format_args!(
+                        "Size of {} {} changed from {} to {} bytes",
+                        GuidFmt(guid),
+                        name,
+                        change.previous,
+                        change.size
+                    ),
+                );
+            }
+        }
+        mor::check(
+            name,
+            guid,
+            rules::Access::Get,
+            efi_status,
+            data,
+            size_after,
+            None,
+        );
+        if let (efi::Status::SUCCESS, Some(size)) = (efi_status, size_after) {
+            mode::observe(name, guid, "GetVariable", data, size, caller);
+        }
+        shadow::check_read(name, guid, efi_status, data, size_after);
+        pin::check_read(name, guid, efi_status, data, size_after);
+
+        // New feature: Log variable name and size, and the access time as a
+        // cycle count: core has no clock, and calling GetTime from the hook is
+        // not ours to do.
+        record!(
+            Accessed {
+                variable,
+                size,
+                efi_status,
+            },
+            Extra::Ticks(arch::Current::read_cycle_counter()),
+        );
+
+        efi_status
+    }
+}
 
-static mut GET_VARIABLE: GetVariableType = handle_get_variable;
+/**
+ * @brief Returns whether a critical check looks at reads of a variable under
+ *        `guid` that a read returned `attributes` for: those on Secure Boot
+ *        and MOR state, the shadows and pins, and the deletion check on
+ *        time-based authenticated variables, which reads keep informed.
+ */
+fn watched(guid: &efi::Guid, attributes: u32) -> bool {
+    *guid == classify::GLOBAL_VARIABLE_GUID
+        || *guid == classify::IMAGE_SECURITY_DATABASE_GUID
+        || mor::is_mor_guid(guid)
+        || attributes & efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0
+        || shadow::watches(guid)
+        || pin::watches(guid)
+}
 
 /**
- * @brief Handles GetVariable runtime service calls.
+ * @brief Answers a read of a variable hidden from the caller with NOT_FOUND
+ *        instead of forwarding it (see hide.rs). Returns None to forward it.
  */
-extern "win64" fn handle_get_variable(
+#[cfg(feature = "enforce")]
+fn conceal(
     variable_name: *mut r_efi::base::Char16,
     vendor_guid: *mut r_efi::base::Guid,
-    attributes: *mut u32,
-    data_size: *mut usize,
-    data: *mut core::ffi::c_void,
-) -> efi::Status {
-    // Invoke the original GetVariable service and log the invocation.
-    let efi_status =
-        unsafe { GET_VARIABLE(variable_name, vendor_guid, attributes, data_size, data) };
-
-    // Convert to UTF-8 from USC-2 up to 64 characters.
-    let mut name;
-    let name = unsafe {
-        let variable_name = core::slice::from_raw_parts(variable_name, 64);
-        name = core::mem::MaybeUninit::<[u8; 64]>::uninit();
-        let name_ptr = name.as_mut_ptr() as *mut u8;
-        let mut offset = 0;
-        for c in variable_name
-            .iter()
-            .take_while(|&&c| c != 0)
-            .map(|&c| c as u8)
-        {
-            name_ptr.add(offset).write(c);
-            offset += 1;
+    caller: Option<usize>,
+) -> Option<efi::Status> {
+    if variable_name.is_null() || vendor_guid.is_null() || !hide::is_active() {
+        return None;
+    }
+    let mut name = [0u8; 64];
+    let name = convert_name(variable_name, &mut name);
+    let guid = &unsafe { caller::value(vendor_guid) }?;
+    if !hide::is_hidden(name, guid, caller) {
+        return None;
+    }
+    let hidden = hide::count_hidden();
+    log_record!(
+        "G: {} {} hidden from {} (#{})",
+        GuidFmt(guid),
+        name,
+        set_variable::ReturnAddress(caller),
+        hidden
+    );
+    Some(efi::Status::NOT_FOUND)
+}
+
+/**
+ * @brief Returns DataSize as left by GetVariable. It is only defined for
+ *        SUCCESS and BUFFER_TOO_SMALL; on other errors firmware may leave it
+ *        untouched or write garbage.
+ */
+fn data_size_after(efi_status: efi::Status, data_size: *const usize) -> Option<usize> {
+    match efi_status {
+        efi::Status::SUCCESS | efi::Status::BUFFER_TOO_SMALL => unsafe { caller::value(data_size) },
+        _ => None,
+    }
+}
+
+/**
+ * @brief Asks the firmware whether a variable exists, on behalf of the other
+ *        hooks. The call goes to the firmware's GetVariable under the
+ *        reentrancy guard, so it is neither logged nor seen by a hook on top
+ *        of ours. Returns None if a GetVariable call is already in progress
+ *        or the firmware did not say.
+ */
+fn variable_exists(
+    variable_name: *mut r_efi::base::Char16,
+    vendor_guid: *mut r_efi::base::Guid,
+) -> Option<bool> {
+    if IN_GET_VARIABLE.swap(true, Ordering::Acquire) {
+        return None;
+    }
+    let mut attributes = 0u32;
+    let mut data_size = 0usize;
+    let efi_status = FIRMWARE_GET_VARIABLE.call(
+        variable_name,
+        vendor_guid,
+        &mut attributes,
+        &mut data_size,
+        core::ptr::null_mut(),
+    );
+    IN_GET_VARIABLE.store(false, Ordering::Release);
+    match efi_status {
+        efi::Status::SUCCESS | efi::Status::BUFFER_TOO_SMALL => Some(true),
+        efi::Status::NOT_FOUND => Some(false),
+        _ => None,
+    }
+}
+
+/**
+ * @brief Reads a list from the variable `name` (NUL-terminated UCS-2) under
+ *        UVM_VENDOR_GUID into `buffer`, through the saved GetVariable so
+ *        that the read is not logged (see pattern.rs). Must be called after
+ *        the GetVariable hook is installed.
+ */
+fn read_list_variable<'a>(
+    name: &[u16],
+    buffer: &'a mut [u8; pattern::MAX_LIST_SIZE],
+) -> Result<&'a str, efi::Status> {
+    let mut name_buffer = [0u16; pattern::MAX_PATTERN_NAME];
+    for (slot, c) in name_buffer.iter_mut().zip(name) {
+        *slot = *c;
+    }
+    let mut guid = config::UVM_VENDOR_GUID;
+    let mut attributes = 0u32;
+    let mut data_size = buffer.len();
+    let efi_status = GET_VARIABLE.call(
+        name_buffer.as_mut_ptr(),
+        &mut guid,
+        &mut attributes,
+        &mut data_size,
+        buffer.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    core::str::from_utf8(buffer.get(..data_size).unwrap_or(&[]))
+        .map(|list| list.trim_end_matches('\0'))
+        .map_err(|_| efi::Status::COMPROMISED_DATA)
+}
+
+/**
+ * @brief Converts a variable name passed by a caller as
+ *        record::convert_name() does, or to "" if it cannot be read (see
+ *        caller.rs). Reads no further than the terminator, nor past the
+ *        characters converted. Always inlined: the runtime_paths_cannot_panic
+ *        test only links if this, caller::name() and record::convert_name()
+ *        are compiled into the test's own code.
+ */
+#[inline(always)]
+fn convert_name(
+    variable_name: *const r_efi::base::Char16,
+    buffer: &mut [u8; record::NAME_LENGTH],
+) -> &str {
+    let characters = unsafe { caller::name(variable_name, buffer.len()) };
+    record::convert_name(characters.unwrap_or(&[]), buffer)
+}
+
+efiapi! {
+    /**
+     * @brief Converts global pointers from physical-mode ones to virtual-mode ones.
+     */
+    fn handle_set_virtual_address_map(
+        _event: r_efi::base::Event,
+        context: *mut core::ffi::c_void,
+    ) {
+        // The table hooked at load, which the context points at, or the one the
+        // hook followed to since. The physical pointer is only valid until this
+        // handler returns; later code goes through the converted
+        // RUNTIME_SERVICES.
+        let mut runtime_services = RUNTIME_SERVICES.load(Ordering::Acquire);
+        if runtime_services.is_null() {
+            runtime_services = context as *mut efi::RuntimeServices;
         }
-        core::str::from_utf8_unchecked(core::slice::from_raw_parts(name_ptr, offset))
-    };
+        if runtime_services.is_null() {
+            error!(
+                tag::BOOT,
+                "SetVirtualAddressMap: no runtime services, GetVariable hook disabled"
+            );
+            HOOK_STATE.store(HOOK_UNUSABLE, Ordering::Release);
+            set_variable::disable();
+            #[cfg(feature = "enforce")]
+            get_next_variable_name::disable();
+            arch::Current::relocate(&mut |_| efi::Status::NOT_READY);
+            return;
+        }
+        let runtime_services = unsafe { &mut *runtime_services };
+        let convert_pointer = runtime_services.convert_pointer;
+        let mut convert = move |address| convert_pointer(0, address);
+        if !relocate_hook(runtime_services.get_variable as usize, &mut convert) {
+            degrade_hook(runtime_services);
+            set_variable::degrade(runtime_services);
+            #[cfg(feature = "enforce")]
+            get_next_variable_name::degrade(runtime_services);
+        }
+
+        images::relocate(&mut convert);
 
-    let effective_size = if data_size.is_null() {
-        0
+        // Last, as logging goes through the serial port until the switch.
+        arch::Current::relocate(&mut convert);
+    }
+}
+
+/**
+ * @brief Picks what the hook may still do after a failed relocation.
+ */
+fn degrade_hook(runtime_services: &mut efi::RuntimeServices) {
+    // Forward through whichever saved pointer was converted, without logging.
+    let state = if !relocate::failed(GET_VARIABLE.storage()) {
+        HOOK_PASS_THROUGH
+    } else if !relocate::failed(FIRMWARE_GET_VARIABLE.storage()) {
+        GET_VARIABLE.copy_from(&FIRMWARE_GET_VARIABLE);
+        HOOK_PASS_THROUGH
     } else {
-        unsafe { *data_size }
+        HOOK_UNUSABLE
     };
-    let data = unsafe { (*vendor_guid).as_fields() };
-    log!(
-        "G: {:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X} Size={:08x} {}: {:#x}",
-        data.0,
-        data.1,
-        data.2,
-        data.3,
-        data.4,
-        data.5[0],
-        data.5[1],
-        data.5[2],
-        data.5[3],
-        data.5[4],
-        data.5[5],
-        effective_size,
-        name,
-        efi_status.as_usize(),
+    HOOK_STATE.store(state, Ordering::Release);
+    error!(
+        tag::BOOT,
+        "SetVirtualAddressMap: relocation incomplete, GetVariable hook degraded"
+    );
+    if state == HOOK_UNUSABLE {
+        restore_firmware_get_variable(runtime_services);
+    }
+}
+
+/**
+ * @brief Puts the firmware's GetVariable back into the table, so that calls
+ *        do not fail for good. The firmware converts the pointers in the
+ *        table itself after signaling the SetVirtualAddressMap event, so the
+ *        physical address it gave us is the one to store. Only done while
+ *        our hook is the head of the chain; a hook on top of ours keeps
+ *        forwarding to us regardless.
+ */
+fn restore_firmware_get_variable(runtime_services: &mut efi::RuntimeServices) -> bool {
+    if let ChainPosition::Behind { head } = chain_position(runtime_services.get_variable as usize) {
+        warn!(
+            tag::HOOK,
+            "GetVariable not restored, {:#08x} is hooked after this driver", head
+        );
+        return false;
+    }
+    let firmware = FIRMWARE_GET_VARIABLE.as_raw();
+    if firmware.is_null() {
+        return false;
+    }
+    let slot = unsafe {
+        &*(&mut runtime_services.get_variable as *mut _ as *const AtomicPtr<core::ffi::c_void>)
+    };
+    slot.store(firmware, Ordering::Release);
+    let _ = update_table_crc32(None, &mut runtime_services.hdr);
+    info!(
+        tag::HOOK,
+        "GetVariable restored to the firmware's {:#08x}", firmware as u64
     );
+    true
+}
 
-    // New feature: Log the variable access time
-    let access_time = unsafe { core::time::SystemTime::now() };
-    log!("Variable accessed at: {:?}", access_time);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ChainPosition {
+    // The GetVariable slot points at our hook.
+    Head,
+    // Another driver hooked GetVariable after us; its hook is at the head.
+    Behind { head: usize },
+}
 
-    // New feature: Log variable name and size
-    let variable_size = if data_size.is_null() {
-        0
+fn chain_position(slot: usize) -> ChainPosition {
+    if slot == handle_get_variable as GetVariableType as usize {
+        ChainPosition::Head
     } else {
-        unsafe { *data_size }
-    };
-    log!("Accessed variable: {}, Size: {}", name, variable_size);
+        ChainPosition::Behind { head: slot }
+    }
+}
+
+/**
+ * @brief Converts the registered pointers, including the ones our hook
+ *        forwards through. `slot` is the current GetVariable slot, which the
+ *        firmware converts itself; when another hook sits on top of ours it
+ *        still forwards to us, so our saved pointers need converting either
+ *        way. Returns false if any conversion failed.
+ */
+fn relocate_hook(
+    slot: usize,
+    convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status,
+) -> bool {
+    if let ChainPosition::Behind { head } = chain_position(slot) {
+        warn!(
+            tag::HOOK,
+            "GetVariable chain head is {:#08x}, hooked after this driver", head
+        );
+    }
+
+    relocate::convert_all(convert)
+}
+
+efiapi! {
+    /**
+     * @brief Shuts down boot-phase-only facilities at ExitBootServices.
+     */
+    fn handle_exit_boot_services(
+        _event: r_efi::base::Event,
+        _context: *mut core::ffi::c_void,
+    ) {
+        BOOT_SERVICES_EXITED.store(true, Ordering::Release);
+        follow_runtime_services_table(unsafe { SYSTEM_TABLE }, Phase::Runtime);
+        integrity::stop();
+
+        #[cfg(feature = "ring-dump")]
+        dump::stop();
+
+        #[cfg(feature = "gop-alert")]
+        gop::disable();
+
+        #[cfg(feature = "tpm-measure")]
+        {
+            tpm::stop();
+            let (dropped, failures) = tpm::losses();
+            warn!(
+                tag::SINK,
+                "TPM alert measurements lost: dropped={} failed={}",
+                dropped,
+                failures
+            );
+        }
+
+        #[cfg(feature = "log-ring")]
+        if let Some(header) = ring::header() {
+            info!(
+                tag::SINK,
+                "Ring buffer holds #{}..#{}, dropped={} overwritten={}",
+                header.first_sequence,
+                header.next_sequence,
+                header.dropped,
+                header.overwritten,
+            );
+        }
+        #[cfg(not(feature = "profile-minimal"))]
+        overhead::log_summary();
+        newcomer::log_summary();
+        counters::log_flash_pressure();
+        // ReadyToBoot wrote the report before the store was measured.
+        capacity::exit_boot_services();
+        let report_status = report::write();
+        if report_status.is_error() {
+            error!(tag::BOOT, "Boot report not written : {:#x}", report_status.as_usize());
+        }
+        #[cfg(feature = "name-cache")]
+        {
+            let (hits, lookups) = names::hits();
+            debug!(tag::HOOK, "Name cache hits: {} of {} lookups", hits, lookups);
+        }
+        #[cfg(not(feature = "no-log"))]
+        info!(tag::SINK, "Serial log records lost: {}", serial::failures());
+        #[cfg(feature = "serial-queue")]
+        info!(tag::SINK, "Serial queue records dropped: {}", queue::dropped());
+
+        // Last, so that the records above still reach the boot-only sinks.
+        sink::exit_boot_services();
+        #[cfg(feature = "per-cpu")]
+        percpu::start();
+    }
+}
+
+/**
+ * @brief Registers the log sinks enabled at build time.
+ */
+fn register_sinks() {
+    #[cfg(all(feature = "log-serial", not(feature = "serial-queue")))]
+    sink::register(sink::Sink {
+        name: "serial",
+        phase: sink::SinkPhase::Both,
+        write: serial::write_record,
+        flush: None,
+        disable: None,
+    });
+    #[cfg(feature = "serial-queue")]
+    sink::register(sink::Sink {
+        name: "serial",
+        phase: sink::SinkPhase::Both,
+        write: queue::write_record,
+        flush: Some(queue::stop),
+        disable: None,
+    });
+    #[cfg(feature = "log-ring")]
+    sink::register(sink::Sink {
+        name: "ring",
+        phase: sink::SinkPhase::Both,
+        write: ring::push_record,
+        flush: None,
+        disable: None,
+    });
+    #[cfg(feature = "log-net")]
+    sink::register(sink::Sink {
+        name: "net",
+        phase: sink::SinkPhase::BootServices,
+        write: net::send_record,
+        flush: None,
+        disable: Some(net::disable),
+    });
+}
+
+/**
+ * @brief Registers the global pointers used at OS runtime for conversion at
+ *        SetVirtualAddressMap.
+ */
+fn register_relocations() -> efi::Status {
+    let mut efi_status = relocate::register("GetVariable", GET_VARIABLE.storage());
+    if !efi_status.is_error() {
+        efi_status = relocate::register("FirmwareGetVariable", FIRMWARE_GET_VARIABLE.storage());
+    }
+    if !efi_status.is_error() {
+        efi_status = set_variable::register_relocations();
+    }
+    #[cfg(feature = "enforce")]
+    if !efi_status.is_error() {
+        efi_status = get_next_variable_name::register_relocations();
+    }
+    if !efi_status.is_error() {
+        efi_status = relocate::register("RuntimeServices", &RUNTIME_SERVICES);
+    }
+    efi_status
+}
+
+/**
+ * @brief Raises the TPL to TPL_HIGH_LEVEL until dropped. After
+ *        ExitBootServices there is no TPL to raise and this does nothing.
+ */
+struct TplGuard {
+    restore: Option<(efiapi! {fn(efi::Tpl)}, efi::Tpl)>,
+}
+
+impl TplGuard {
+    fn raise(boot_services: Option<&mut efi::BootServices>) -> Self {
+        TplGuard {
+            restore: boot_services.map(|boot_services| {
+                (
+                    boot_services.restore_tpl,
+                    (boot_services.raise_tpl)(efi::TPL_HIGH_LEVEL),
+                )
+            }),
+        }
+    }
+}
+
+impl Drop for TplGuard {
+    fn drop(&mut self) {
+        if let Some((restore_tpl, tpl)) = self.restore {
+            restore_tpl(tpl);
+        }
+    }
+}
 
-    return efi_status;
+/**
+ * @brief Computes the CRC32 of a table as it would be stored in its header,
+ *        that is with the crc32 field taken as zero. Without boot services,
+ *        it is computed by crc32.rs.
+ */
+fn calculate_table_crc32(
+    boot_services: Option<&mut efi::BootServices>,
+    hdr: &mut efi::TableHeader,
+) -> Result<u32, efi::Status> {
+    let stored = hdr.crc32;
+    let mut crc32 = 0u32;
+    hdr.crc32 = 0;
+    let efi_status = match boot_services {
+        Some(boot_services) => (boot_services.calculate_crc32)(
+            hdr as *mut _ as *mut core::ffi::c_void,
+            hdr.header_size as usize,
+            &mut crc32,
+        ),
+        None => {
+            let table = unsafe {
+                core::slice::from_raw_parts(hdr as *const _ as *const u8, hdr.header_size as usize)
+            };
+            crc32 = crc32::crc32(table);
+            efi::Status::SUCCESS
+        }
+    };
+    hdr.crc32 = stored;
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(crc32)
 }
 
 /**
- * @brief Converts global pointers from physical-mode ones to virtual-mode ones.
+ * @brief Warns if a table's stored CRC32 is already wrong, so that updating
+ *        it does not paper over corruption done by someone else.
  */
-extern "win64" fn handle_set_virtual_address_map(
-    _event: r_efi::base::Event,
-    context: *mut core::ffi::c_void,
+fn verify_table_crc32(
+    boot_services: Option<&mut efi::BootServices>,
+    hdr: &mut efi::TableHeader,
+    name: &str,
 ) {
-    assert!(!context.is_null());
+    match calculate_table_crc32(boot_services, hdr) {
+        Ok(crc32) if crc32 != hdr.crc32 => {
+            rules::emit(
+                alerts::Rule::TableCrc,
+                rules::Severity::Critical,
+                format_args!(
+                    "{} CRC32 mismatch before update: stored={:#010x} computed={:#010x}",
+                    name, hdr.crc32, crc32
+                ),
+            );
+        }
+        Ok(_) => {}
+        Err(efi_status) => error!(
+            tag::HOOK,
+            "calculate_crc32 failed : {:#x}",
+            efi_status.as_usize()
+        ),
+    }
+}
 
-    let runtime_services = unsafe { &mut *(context as *mut r_efi::efi::RuntimeServices) };
-    let curr_addr = unsafe { GET_VARIABLE as u64 };
-    let efi_status = (runtime_services.convert_pointer)(0, unsafe {
-        &mut GET_VARIABLE as *mut _ as *mut *mut core::ffi::c_void
-    });
-    log!(
-        "GetVariable relocated from {:#08x} to {:#08x}",
-        curr_addr,
-        unsafe { GET_VARIABLE as u64 },
-    );
+/**
+ * @brief Recomputes the CRC32 in a table header after modifying the table.
+ */
+fn update_table_crc32(
+    boot_services: Option<&mut efi::BootServices>,
+    hdr: &mut efi::TableHeader,
+) -> efi::Status {
+    match calculate_table_crc32(boot_services, hdr) {
+        Ok(crc32) => {
+            hdr.crc32 = crc32;
+            efi::Status::SUCCESS
+        }
+        Err(efi_status) => efi_status,
+    }
+}
 
-    assert!(!efi_status.is_error());
+// Tables are copied to the stack to compute their CRC32 ahead of an update;
+// the Runtime Services Table is 136 bytes as of UEFI 2.10.
+const MAX_TABLE_SIZE: usize = 1024;
+
+// Exchanges retried because a table changed while its CRC32 was computed.
+const MAX_EXCHANGE_ATTEMPTS: usize = 3;
+
+/**
+ * @brief Computes the CRC32 of a table as it will be once the pointer at
+ *        `slot` in it is `value`, on a copy of the table.
+ */
+fn calculate_exchanged_crc32(
+    boot_services: Option<&mut efi::BootServices>,
+    hdr: &efi::TableHeader,
+    slot: *mut *mut core::ffi::c_void,
+    value: *mut core::ffi::c_void,
+) -> Result<u32, efi::Status> {
+    let size = hdr.header_size as usize;
+    let offset = (slot as usize).wrapping_sub(hdr as *const _ as usize);
+    let end = offset.checked_add(core::mem::size_of::<usize>());
+    if size > MAX_TABLE_SIZE || end.is_none_or(|end| end > size) {
+        return Err(efi::Status::BAD_BUFFER_SIZE);
+    }
+    // In u64s, for the alignment of the header.
+    let mut copy = [0u64; MAX_TABLE_SIZE / 8];
+    let bytes = unsafe { core::slice::from_raw_parts_mut(copy.as_mut_ptr() as *mut u8, size) };
+    bytes.copy_from_slice(unsafe {
+        core::slice::from_raw_parts(hdr as *const _ as *const u8, size)
+    });
+    match bytes.get_mut(offset..offset + core::mem::size_of::<usize>()) {
+        Some(slot) => slot.copy_from_slice(&(value as usize).to_ne_bytes()),
+        None => return Err(efi::Status::BAD_BUFFER_SIZE),
+    }
+    calculate_table_crc32(boot_services, unsafe {
+        &mut *(copy.as_mut_ptr() as *mut efi::TableHeader)
+    })
 }
 
 /**
- * @brief Exchanges a pointer in the EFI System Table.
+ * @brief Exchanges a pointer in the EFI Runtime Services Table. In the
+ *        runtime phase, boot services are not touched: the TPL is left alone
+ *        and the CRC32s are computed by the driver.
+ *
+ * The CRC32s are computed beforehand, on a copy of the table, so that only
+ * the exchange and the two stores of the CRC32s happen at TPL_HIGH_LEVEL.
+ * Should either table have changed in between, as seen from its CRC32, or
+ * the slot, nothing is stored and the CRC32s are computed again.
  */
 fn exchange_pointer_in_service_table(
     system_table: *mut efi::SystemTable,
     address_to_update: *mut *mut core::ffi::c_void,
     new_function_pointer: *mut core::ffi::c_void,
     original_function_pointer: *mut *mut core::ffi::c_void,
+    phase: Phase,
 ) -> efi::Status {
-    unsafe {
-        assert!(!system_table.is_null());
-        assert!(*address_to_update != new_function_pointer);
-    };
+    if system_table.is_null() || address_to_update.is_null() || original_function_pointer.is_null()
+    {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // The slot may be read by other CPUs at OS runtime, and the saved pointer
+    // by the hook.
+    let slot = unsafe { &*(address_to_update as *const AtomicPtr<core::ffi::c_void>) };
+    let original = unsafe { &*(original_function_pointer as *const AtomicPtr<core::ffi::c_void>) };
+    if slot.load(Ordering::Acquire) == new_function_pointer {
+        return efi::Status::ALREADY_STARTED;
+    }
     let system_table = unsafe { &mut *system_table };
-    let boot_services = unsafe { &mut *system_table.boot_services };
-
-    // Disable interrupt.
-    let tpl = (boot_services.raise_tpl)(efi::TPL_HIGH_LEVEL);
-
-    unsafe {
-        *original_function_pointer = *address_to_update;
-        *address_to_update = new_function_pointer;
+    let mut boot_services = match phase {
+        Phase::BootServices => Some(unsafe { &mut *system_table.boot_services }),
+        Phase::Runtime => None,
     };
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
 
-    // Update the CRC32 in the EFI System Table header.
-    system_table.hdr.crc32 = 0;
-    let efi_status = (boot_services.calculate_crc32)(
-        &mut system_table.hdr as *mut _ as *mut core::ffi::c_void,
-        system_table.hdr.header_size as usize,
-        &mut system_table.hdr.crc32,
+    verify_table_crc32(
+        boot_services.as_deref_mut(),
+        &mut system_table.hdr,
+        "System Table",
+    );
+    verify_table_crc32(
+        boot_services.as_deref_mut(),
+        &mut runtime_services.hdr,
+        "Runtime Services Table",
     );
-    assert!(!efi_status.is_error());
 
-    (boot_services.restore_tpl)(tpl);
-    return efi_status;
+    for _ in 0..MAX_EXCHANGE_ATTEMPTS {
+        let current = slot.load(Ordering::Acquire);
+        let stored = (runtime_services.hdr.crc32, system_table.hdr.crc32);
+        let crc32 = calculate_exchanged_crc32(
+            boot_services.as_deref_mut(),
+            &runtime_services.hdr,
+            address_to_update,
+            new_function_pointer,
+        )
+        .and_then(|runtime_crc32| {
+            calculate_table_crc32(boot_services.as_deref_mut(), &mut system_table.hdr)
+                .map(|system_crc32| (runtime_crc32, system_crc32))
+        });
+        let (runtime_crc32, system_crc32) = match crc32 {
+            Ok(crc32) => crc32,
+            Err(efi_status) => {
+                error!(
+                    tag::HOOK,
+                    "calculate_crc32 failed : {:#x}",
+                    efi_status.as_usize()
+                );
+                return efi_status;
+            }
+        };
+
+        // Disable interrupt.
+        let tpl = TplGuard::raise(boot_services.as_deref_mut());
+        let started = arch::Current::read_cycle_counter();
+        // The hook may run on another CPU as soon as the slot points to it, so
+        // the pointer it forwards to is saved first, and restored if the
+        // exchange fails.
+        let saved = original.swap(current, Ordering::AcqRel);
+        let exchanged = (runtime_services.hdr.crc32, system_table.hdr.crc32) == stored
+            && slot
+                .compare_exchange(
+                    current,
+                    new_function_pointer,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok();
+        if exchanged {
+            runtime_services.hdr.crc32 = runtime_crc32;
+            system_table.hdr.crc32 = system_crc32;
+        } else {
+            original.store(saved, Ordering::Release);
+        }
+        let cycles = started.and_then(|started| {
+            arch::Current::read_cycle_counter().map(|now| now.wrapping_sub(started))
+        });
+        drop(tpl);
+
+        if exchanged {
+            if let Some(cycles) = cycles {
+                debug!(
+                    tag::HOOK,
+                    "Service table updated in {} cycles at high TPL", cycles
+                );
+            }
+            return efi::Status::SUCCESS;
+        }
+    }
+    error!(
+        tag::HOOK,
+        "Service table kept changing; pointer not exchanged"
+    );
+    efi::Status::NOT_READY
 }
 
 /**
- * @brief The module entry point.
+ * @brief Moves the hook over if the system table no longer points at the
+ *        Runtime Services Table hooked at load, as firmware republishing the
+ *        table leaves us in a stale copy. The slot of the new table becomes
+ *        what GET_VARIABLE forwards to, which may well be the firmware's
+ *        original function again.
  */
-#[no_mangle]
-fn efi_main(_image_handle: efi::Handle, system_table: *mut efi::SystemTable) -> efi::Status {
-    assert!(!system_table.is_null());
-    let system_table = unsafe { &mut *system_table };
-
-    assert!(!system_table.boot_services.is_null());
-    let boot_services = unsafe { &mut *system_table.boot_services };
-
-    log!("Driver being loaded");
+fn follow_runtime_services_table(system_table: *mut efi::SystemTable, phase: Phase) {
+    if system_table.is_null() {
+        return;
+    }
+    let hooked = RUNTIME_SERVICES.load(Ordering::Acquire);
+    let live = unsafe { (*system_table).runtime_services };
+    if live.is_null() || live == hooked {
+        return;
+    }
 
-    // Register a notification for SetVirtualAddressMap call.
-    let mut event: r_efi::base::Event = core::ptr::null_mut();
-    let mut efi_status = (boot_services.create_event_ex)(
-        r_efi::efi::EVT_NOTIFY_SIGNAL,
-        r_efi::efi::TPL_CALLBACK,
-        handle_set_virtual_address_map,
-        system_table.runtime_services as *mut core::ffi::c_void,
-        &mut r_efi::efi::EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE,
-        &mut event,
+    let slot = unsafe { &mut (*live).get_variable as *mut _ as *mut *mut core::ffi::c_void };
+    // A failed exchange leaves GET_VARIABLE pointing into the old chain,
+    // which the stale table still leads to.
+    let mut found = unsafe { *slot };
+    let efi_status = exchange_pointer_in_service_table(
+        system_table,
+        slot,
+        handle_get_variable as GetVariableType as *mut core::ffi::c_void,
+        &mut found,
+        phase,
     );
-    if efi_status.is_error() {
-        log!("create_event_ex failed : {:#x}", efi_status.as_usize());
-        return efi_status;
+    if efi_status.is_error() && efi_status != efi::Status::ALREADY_STARTED {
+        error!(
+            tag::HOOK,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return;
+    }
+    if efi_status != efi::Status::ALREADY_STARTED {
+        GET_VARIABLE.storage().store(found, Ordering::Release);
     }
+    set_variable::follow(system_table, live, phase);
+    #[cfg(feature = "enforce")]
+    get_next_variable_name::follow(system_table, live, phase);
+    RUNTIME_SERVICES.store(live, Ordering::Release);
+    info!(
+        tag::HOOK,
+        "Runtime Services Table moved from {:#08x} to {:#08x}", hooked as u64, live as u64
+    );
+    if efi_status == efi::Status::ALREADY_STARTED {
+        info!(tag::HOOK, "GetVariable is already hooked in the new table");
+    } else if found == FIRMWARE_GET_VARIABLE.as_raw() {
+        info!(
+            tag::HOOK,
+            "GetVariable hook moved, forwarding to the firmware's original"
+        );
+    } else {
+        info!(
+            tag::HOOK,
+            "GetVariable hook moved on top of {:#08x}", found as u64
+        );
+    }
+}
 
-    // Install hooks.
-    efi_status = unsafe {
-        exchange_pointer_in_service_table(
-            system_table,
-            &mut (*system_table.runtime_services).get_variable as *mut _
-                as *mut *mut core::ffi::c_void,
-            handle_get_variable as *mut core::ffi::c_void,
-            &mut GET_VARIABLE as *mut _ as *mut *mut core::ffi::c_void,
-        )
-    };
+/**
+ * @brief Puts the saved GetVariable back into the Runtime Services Table.
+ */
+fn unhook(system_table: &mut efi::SystemTable) -> efi::Status {
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
+    let mut hook: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = exchange_pointer_in_service_table(
+        system_table,
+        &mut runtime_services.get_variable as *mut _ as *mut *mut core::ffi::c_void,
+        GET_VARIABLE.as_raw(),
+        &mut hook,
+        phase(),
+    );
     if efi_status.is_error() {
-        log!(
+        error!(
+            tag::HOOK,
             "exchange_table_pointer failed : {:#x}",
             efi_status.as_usize()
         );
-        (boot_services.close_event)(event);
-        return efi_status;
     }
+    efi_status
+}
+
+efiapi! {
+    /**
+     * @brief Unloads the driver, restoring the original GetVariable and
+     *        SetVariable, and GetNextVariableName if hooked.
+     */
+    fn handle_unload(_image_handle: efi::Handle) -> efi::Status {
+        let system_table = unsafe { &mut *SYSTEM_TABLE };
+        let runtime_services = unsafe { &mut *system_table.runtime_services };
+
+        // If another driver hooked on top of us, restoring our saved pointer would
+        // cut it out of the chain, and unloading would leave it calling into
+        // freed memory.
+        let current = runtime_services.get_variable as usize;
+        if current != handle_get_variable as GetVariableType as usize {
+            warn!(tag::BOOT, "Unload refused: GetVariable is now {:#x}", current);
+            return efi::Status::ACCESS_DENIED;
+        }
+        if !set_variable::is_head(runtime_services) {
+            warn!(
+                tag::BOOT,
+                "Unload refused: SetVariable is now {:#x}",
+                runtime_services.set_variable as usize
+            );
+            return efi::Status::ACCESS_DENIED;
+        }
+        #[cfg(feature = "enforce")]
+        if !get_next_variable_name::is_head(runtime_services) {
+            warn!(
+                tag::BOOT,
+                "Unload refused: GetNextVariableName is now {:#x}",
+                runtime_services.get_next_variable_name as usize
+            );
+            return efi::Status::ACCESS_DENIED;
+        }
+        // Likewise if we re-hooked on top of another driver: it still holds a
+        // pointer to us.
+        if integrity::is_rehooked() {
+            warn!(tag::BOOT, "Unload refused: GetVariable hook was re-installed");
+            return efi::Status::ACCESS_DENIED;
+        }
+
+        info!(tag::BOOT, "Driver being unloaded");
+        let efi_status = teardown::unwind(system_table);
+        if efi_status.is_error() {
+            return efi_status;
+        }
+        info!(tag::BOOT, "Driver unloaded");
+        efi::Status::SUCCESS
+    }
+}
 
-    return efi_status;
+/**
+ * @brief Registers the Unload handler in the LoadedImage protocol.
+ */
+fn install_unload_handler(
+    boot_services: &mut efi::BootServices,
+    image_handle: efi::Handle,
+) -> efi::Status {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.handle_protocol)(
+        image_handle,
+        &r_efi::protocols::loaded_image::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        &mut interface,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    let loaded_image = interface as *mut r_efi::protocols::loaded_image::Protocol;
+    unsafe { (*loaded_image).unload = handle_unload };
+    efi::Status::SUCCESS
 }
 
-#[panic_handler]
-fn panic_handler(_info: &core::panic::PanicInfo) -> ! {
-    loop {}
+efiapi! {
+    /**
+     * @brief The module entry point.
+     */
+    #[no_mangle]
+    fn efi_main(image_handle: efi::Handle, system_table: *mut efi::SystemTable) -> efi::Status {
+        if system_table.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        unsafe { SYSTEM_TABLE = system_table };
+        let system_table = unsafe { &mut *system_table };
+        register_sinks();
+
+        if system_table.boot_services.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let boot_services = unsafe { &mut *system_table.boot_services };
+
+        // Refuse to stack a second hook on top of an already loaded monitor.
+        let get_variable = unsafe { (*system_table.runtime_services).get_variable } as usize;
+        if protocol::is_installed(boot_services)
+            || get_variable == handle_get_variable as GetVariableType as usize
+        {
+            warn!(tag::BOOT, "Driver already loaded, refusing to hook GetVariable again");
+            return efi::Status::ALREADY_STARTED;
+        }
+
+        config::apply(&config::RuntimeConfig::from_build_env());
+
+        let efi_status = load(image_handle, system_table);
+        if efi_status.is_error() {
+            teardown::unwind(system_table);
+        }
+        return efi_status;
+    }
+}
+
+/**
+ * @brief Acquires the driver's resources and installs the hook, recording
+ *        each resource in the teardown list as it goes. On failure, the
+ *        caller unwinds whatever was recorded.
+ */
+fn load(image_handle: efi::Handle, system_table: &mut efi::SystemTable) -> efi::Status {
+    let boot_services = unsafe { &mut *system_table.boot_services };
+
+    // Without the drain timer, serial output stays inline.
+    #[cfg(feature = "serial-queue")]
+    {
+        let queue_status = queue::start(boot_services);
+        if queue_status.is_error() {
+            error!(
+                tag::SINK,
+                "queue::start failed : {:#x}",
+                queue_status.as_usize()
+            );
+        } else {
+            let efi_status = teardown::record(teardown::Cleanup::StopQueue, system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+    }
+
+    #[cfg(feature = "log-net")]
+    {
+        net::initialize(boot_services);
+        let efi_status = teardown::record(teardown::Cleanup::DisableNet, system_table);
+        if efi_status.is_error() {
+            return efi_status;
+        }
+    }
+
+    #[cfg(feature = "gop-alert")]
+    {
+        gop::initialize(boot_services);
+        let efi_status = teardown::record(teardown::Cleanup::DisableGop, system_table);
+        if efi_status.is_error() {
+            return efi_status;
+        }
+    }
+
+    // Absence of a TPM is not worth a log line.
+    #[cfg(feature = "tpm-measure")]
+    {
+        let tpm_status = tpm::start(system_table);
+        if tpm_status.is_error() && tpm_status != efi::Status::NOT_FOUND {
+            error!(
+                tag::SINK,
+                "tpm::start failed : {:#x}",
+                tpm_status.as_usize()
+            );
+        } else if !tpm_status.is_error() {
+            let efi_status = teardown::record(teardown::Cleanup::StopTpm, system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+    }
+
+    // Written whatever the level, so that every log says what was built.
+    banner::announce();
+    filter::start(profile::TRACE_FILTER);
+    let mut sources = 0;
+    if config_store::restore(unsafe { &*system_table.runtime_services }) {
+        sources |= build::CONFIG_FROM_VARIABLE;
+    }
+    if persist::restore(unsafe { &*system_table.runtime_services }) {
+        sources |= build::CONFIG_FROM_LEVEL_VARIABLE;
+    }
+    if options::apply(boot_services, image_handle) {
+        sources |= build::CONFIG_FROM_LOAD_OPTIONS;
+    }
+    banner::configured(sources);
+
+    // Register before the SetVirtualAddressMap notification can fire; a
+    // pointer left unconverted faults at OS runtime.
+    RUNTIME_SERVICES.store(system_table.runtime_services, Ordering::Release);
+    let mut efi_status = register_relocations();
+    if efi_status.is_error() {
+        error!(
+            tag::BOOT,
+            "register_relocations failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return efi_status;
+    }
+
+    // Register a notification for SetVirtualAddressMap call.
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    efi_status = (boot_services.create_event_ex)(
+        r_efi::efi::EVT_NOTIFY_SIGNAL,
+        r_efi::efi::TPL_CALLBACK,
+        handle_set_virtual_address_map,
+        system_table.runtime_services as *mut core::ffi::c_void,
+        &r_efi::efi::EVENT_GROUP_VIRTUAL_ADDRESS_CHANGE,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        error!(
+            tag::BOOT,
+            "create_event_ex failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return efi_status;
+    }
+    efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+    if efi_status.is_error() {
+        return efi_status;
+    }
+
+    // Register a notification for ExitBootServices call. At TPL_NOTIFY, it
+    // runs before the TPL_CALLBACK handlers of the drivers the boot-only
+    // sinks depend on, such as the network stack, tear them down.
+    let mut exit_boot_services_event: r_efi::base::Event = core::ptr::null_mut();
+    efi_status = (boot_services.create_event_ex)(
+        r_efi::efi::EVT_NOTIFY_SIGNAL,
+        r_efi::efi::TPL_NOTIFY,
+        handle_exit_boot_services,
+        core::ptr::null_mut(),
+        &r_efi::efi::EVENT_GROUP_EXIT_BOOT_SERVICES,
+        &mut exit_boot_services_event,
+    );
+    if efi_status.is_error() {
+        error!(
+            tag::BOOT,
+            "create_event_ex failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return efi_status;
+    }
+    efi_status = teardown::record(
+        teardown::Cleanup::CloseEvent(exit_boot_services_event),
+        system_table,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+
+    // Install hooks.
+    efi_status = unsafe {
+        exchange_pointer_in_service_table(
+            system_table,
+            &mut (*system_table.runtime_services).get_variable as *mut _
+                as *mut *mut core::ffi::c_void,
+            handle_get_variable as *mut core::ffi::c_void,
+            GET_VARIABLE.as_mut_ptr(),
+            Phase::BootServices,
+        )
+    };
+    if efi_status.is_error() {
+        if efi_status == efi::Status::ALREADY_STARTED {
+            warn!(tag::HOOK, "GetVariable is already hooked by this driver");
+        } else {
+            error!(
+                tag::HOOK,
+                "exchange_table_pointer failed : {:#x}",
+                efi_status.as_usize()
+            );
+        }
+        return efi_status;
+    }
+    FIRMWARE_GET_VARIABLE.copy_from(&GET_VARIABLE);
+    efi_status = teardown::record(teardown::Cleanup::Unhook, system_table);
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    redact::load();
+    // Before GetNextVariableName and SetVariable are hooked.
+    inventory::load(unsafe { &*system_table.runtime_services });
+    capacity::load(unsafe { &*system_table.runtime_services });
+    mode::sample();
+    shadow::load();
+    history::load();
+    pin::load();
+    // Loaded before writes can reach the SetVariable hook.
+    #[cfg(feature = "enforce")]
+    {
+        enforce::load();
+        lock::load();
+        hide::load();
+    }
+    efi_status = set_variable::install(system_table);
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    efi_status = teardown::record(teardown::Cleanup::UnhookSetVariable, system_table);
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    #[cfg(feature = "enforce")]
+    {
+        efi_status = get_next_variable_name::install(system_table);
+        if efi_status.is_error() {
+            return efi_status;
+        }
+        efi_status = teardown::record(teardown::Cleanup::UnhookGetNextVariableName, system_table);
+        if efi_status.is_error() {
+            return efi_status;
+        }
+    }
+
+    // The remaining facilities are optional; failing to set one up is logged
+    // and loading goes on without it.
+    let integrity_status = integrity::start(system_table);
+    if integrity_status.is_error() {
+        error!(
+            tag::INTEGRITY,
+            "integrity::start failed : {:#x}",
+            integrity_status.as_usize()
+        );
+    } else {
+        efi_status = teardown::record(teardown::Cleanup::StopIntegrity, system_table);
+        if efi_status.is_error() {
+            return efi_status;
+        }
+    }
+
+    let rate_status = rate::calibrate(boot_services);
+    if rate_status.is_error() {
+        error!(
+            tag::HOOK,
+            "rate::calibrate failed : {:#x}",
+            rate_status.as_usize()
+        );
+    }
+
+    match report::start(boot_services) {
+        Ok(event) => {
+            efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+        Err(report_status) => {
+            error!(
+                tag::BOOT,
+                "report::start failed : {:#x}",
+                report_status.as_usize()
+            );
+        }
+    }
+
+    match inventory::start(boot_services) {
+        Ok(event) => {
+            efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+        Err(inventory_status) => {
+            error!(
+                tag::INTEGRITY,
+                "inventory::start failed : {:#x}",
+                inventory_status.as_usize()
+            );
+        }
+    }
+
+    match newcomer::start(boot_services) {
+        Ok(event) => {
+            efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+        Err(newcomer_status) => {
+            error!(
+                tag::INTEGRITY,
+                "newcomer::start failed : {:#x}",
+                newcomer_status.as_usize()
+            );
+        }
+    }
+
+    #[cfg(feature = "mm-events")]
+    match mm::start(boot_services) {
+        Ok(event) => {
+            efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+        Err(efi::Status::NOT_FOUND) => {}
+        Err(mm_status) => {
+            error!(tag::MM, "mm::start failed : {:#x}", mm_status.as_usize());
+        }
+    }
+
+    match images::start(boot_services) {
+        Ok(event) => {
+            efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+        Err(images_status) => {
+            error!(
+                tag::HOOK,
+                "images::start failed : {:#x}",
+                images_status.as_usize()
+            );
+        }
+    }
+
+    #[cfg(feature = "enforce")]
+    match lock::start(boot_services) {
+        Ok(event) => {
+            efi_status = teardown::record(teardown::Cleanup::CloseEvent(event), system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+        Err(lock_status) => {
+            error!(
+                tag::ENFORCE,
+                "lock::start failed : {:#x}",
+                lock_status.as_usize()
+            );
+        }
+    }
+
+    // The marker is what lets a second copy of the driver detect this one.
+    let protocol_status = protocol::install(boot_services, image_handle);
+    if protocol_status.is_error() {
+        error!(
+            tag::CONFIG,
+            "protocol::install failed : {:#x}",
+            protocol_status.as_usize()
+        );
+    } else {
+        efi_status = teardown::record(
+            teardown::Cleanup::UninstallProtocol(image_handle),
+            system_table,
+        );
+        if efi_status.is_error() {
+            return efi_status;
+        }
+    }
+
+    let stats_status = stats::install(boot_services, image_handle);
+    if stats_status.is_error() {
+        error!(
+            tag::CONFIG,
+            "stats::install failed : {:#x}",
+            stats_status.as_usize()
+        );
+    } else {
+        efi_status = teardown::record(
+            teardown::Cleanup::UninstallStatsProtocol(image_handle),
+            system_table,
+        );
+        if efi_status.is_error() {
+            return efi_status;
+        }
+    }
+
+    // Without the Unload handler the driver simply stays resident.
+    let unload_status = install_unload_handler(boot_services, image_handle);
+    if unload_status.is_error() {
+        error!(
+            tag::BOOT,
+            "install_unload_handler failed : {:#x}",
+            unload_status.as_usize()
+        );
+    }
+
+    // The on-demand dump is a debugging aid.
+    #[cfg(feature = "ring-dump")]
+    {
+        let dump_status = dump::start(system_table);
+        if dump_status.is_error() {
+            error!(
+                tag::SINK,
+                "dump::start failed : {:#x}",
+                dump_status.as_usize()
+            );
+        } else {
+            efi_status = teardown::record(teardown::Cleanup::StopDump, system_table);
+            if efi_status.is_error() {
+                return efi_status;
+            }
+        }
+    }
+
+    efi_status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FAKE_HOOK_NEXT: HookSlot<GetVariableType> = HookSlot::new();
+
+    efiapi! {
+        fn fake_firmware(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            data_size: *mut usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            unsafe { *data_size = 4 };
+            efi::Status::SUCCESS
+        }
+    }
+
+    efiapi! {
+        // Returns the data the test passed in, all of it.
+        #[cfg(all(not(feature = "profile-minimal"), not(feature = "log-deferred")))]
+        fn fake_firmware_get_language(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            _data_size: *mut usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            efi::Status::SUCCESS
+        }
+    }
+
+    efiapi! {
+        // A platform filter driver that hooked GetVariable after us, forwarding to
+        // the pointer it found in the table.
+        fn fake_hook(
+            variable_name: *mut r_efi::base::Char16,
+            vendor_guid: *mut r_efi::base::Guid,
+            attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            FAKE_HOOK_NEXT.call(variable_name, vendor_guid, attributes, data_size, data)
+        }
+    }
+
+    // Links only if the compiler proves that nothing between creating and
+    // forgetting the guard can unwind, i.e. no panic is reachable. It relies on
+    // optimizations, so it is only checked by `cargo test --release`. Code
+    // behind core::fmt is opaque to the check and not covered.
+    #[cfg(not(debug_assertions))]
+    struct NoPanic;
+
+    #[cfg(not(debug_assertions))]
+    impl Drop for NoPanic {
+        fn drop(&mut self) {
+            extern "C" {
+                fn uvm_panic_is_reachable() -> !;
+            }
+            unsafe { uvm_panic_is_reachable() }
+        }
+    }
+
+    #[test]
+    #[cfg(not(debug_assertions))]
+    fn runtime_paths_cannot_panic() {
+        let guard = NoPanic;
+        let mut buffer = [0u8; 64];
+        let variable_name = [b'B' as u16, b'o' as u16, 0xe9, b't' as u16, 0];
+        let name = convert_name(std::hint::black_box(variable_name.as_ptr()), &mut buffer);
+        let crc32 = crc32::crc32(std::hint::black_box(name.as_bytes()));
+        // Port I/O faults in user mode, so this is linked but never run.
+        if std::hint::black_box(false) {
+            let _ = serial::write_text(name);
+        }
+        core::mem::forget(guard);
+        assert_eq!(name, "Bo?t");
+        assert_ne!(crc32, 0);
+    }
+
+    #[test]
+    fn relocate_with_hook_layered_on_top() {
+        let _lock = mock::lock();
+
+        // The mock table slot: our hook installed over the firmware, then the
+        // fake hook over ours.
+        let mut slot: GetVariableType = handle_get_variable;
+        GET_VARIABLE.set(fake_firmware);
+        FIRMWARE_GET_VARIABLE.set(fake_firmware);
+        FAKE_HOOK_NEXT.set(slot);
+        slot = fake_hook;
+        assert_eq!(
+            chain_position(slot as usize),
+            ChainPosition::Behind {
+                head: fake_hook as GetVariableType as usize
+            }
+        );
+
+        // Relocate by a fixed offset, then undo it so that the chain stays
+        // callable.
+        assert!(!register_relocations().is_error());
+        const OFFSET: usize = 0x1000_0000;
+        assert!(relocate_hook(slot as usize, &mut |address| {
+            unsafe { *address = (*address as usize + OFFSET) as *mut core::ffi::c_void };
+            efi::Status::SUCCESS
+        }));
+        let relocated = fake_firmware as GetVariableType as usize + OFFSET;
+        assert_eq!(GET_VARIABLE.as_raw() as usize, relocated);
+        assert_eq!(FIRMWARE_GET_VARIABLE.as_raw() as usize, relocated);
+        GET_VARIABLE.set(fake_firmware);
+        FIRMWARE_GET_VARIABLE.set(fake_firmware);
+
+        // A call entering at the head still reaches the firmware through us.
+        let mut name = [0u16; 64];
+        name[..4].copy_from_slice(&[b'T' as u16, b'e' as u16, b's' as u16, b't' as u16]);
+        let mut guid = efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]);
+        let mut attributes = 0u32;
+        let mut data_size = 0usize;
+        let efi_status = slot(
+            name.as_mut_ptr(),
+            &mut guid,
+            &mut attributes,
+            &mut data_size,
+            core::ptr::null_mut(),
+        );
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        assert_eq!(data_size, 4);
+    }
+
+    #[test]
+    fn data_size_is_only_trusted_where_defined() {
+        let mut data_size = 0x20usize;
+        assert_eq!(
+            data_size_after(efi::Status::SUCCESS, &data_size),
+            Some(0x20)
+        );
+        data_size = 0x80;
+        assert_eq!(
+            data_size_after(efi::Status::BUFFER_TOO_SMALL, &data_size),
+            Some(0x80)
+        );
+        for efi_status in [
+            efi::Status::NOT_FOUND,
+            efi::Status::INVALID_PARAMETER,
+            efi::Status::DEVICE_ERROR,
+            efi::Status::SECURITY_VIOLATION,
+        ] {
+            assert_eq!(data_size_after(efi_status, &data_size), None);
+        }
+        assert_eq!(
+            data_size_after(efi::Status::SUCCESS, core::ptr::null()),
+            None
+        );
+    }
+
+    fn table_crc32(hdr: &mut efi::TableHeader) -> u32 {
+        calculate_table_crc32(None, hdr).unwrap()
+    }
+
+    fn exchange_get_variable(
+        firmware: &mut mock::MockFirmware,
+        phase: Phase,
+    ) -> (efi::Status, *mut core::ffi::c_void) {
+        let mut original: *mut core::ffi::c_void = core::ptr::null_mut();
+        let efi_status = exchange_pointer_in_service_table(
+            firmware.system_table(),
+            &mut firmware.runtime_services.get_variable as *mut _ as *mut *mut core::ffi::c_void,
+            fake_hook as GetVariableType as *mut core::ffi::c_void,
+            &mut original,
+            phase,
+        );
+        (efi_status, original)
+    }
+
+    #[test]
+    fn exchange_in_boot_services_phase() {
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(fake_firmware);
+
+        let (efi_status, original) = exchange_get_variable(&mut firmware, Phase::BootServices);
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        assert_eq!(original, fake_firmware as GetVariableType as *mut _);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_hook as GetVariableType as usize
+        );
+        assert_eq!(mock::tpl_raises(), 1);
+        assert_eq!(mock::tpl(), efi::TPL_APPLICATION);
+
+        // A failed CRC32 computation leaves the pointer alone, and the TPL
+        // is not even raised.
+        firmware.runtime_services.get_variable = fake_firmware;
+        mock::fail_at(Some(2));
+        let (efi_status, _) = exchange_get_variable(&mut firmware, Phase::BootServices);
+        assert_eq!(efi_status, efi::Status::DEVICE_ERROR);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_firmware as GetVariableType as usize
+        );
+        assert_eq!(mock::tpl_raises(), 1);
+        assert_eq!(mock::tpl(), efi::TPL_APPLICATION);
+    }
+
+    #[test]
+    fn exchange_in_runtime_phase() {
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(fake_firmware);
+        firmware.system_table.hdr.crc32 = table_crc32(&mut firmware.system_table.hdr);
+        firmware.runtime_services.hdr.crc32 = table_crc32(&mut firmware.runtime_services.hdr);
+        // Boot services are gone after ExitBootServices.
+        firmware.system_table.boot_services = core::ptr::null_mut();
+
+        let (efi_status, original) = exchange_get_variable(&mut firmware, Phase::Runtime);
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        assert_eq!(original, fake_firmware as GetVariableType as *mut _);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_hook as GetVariableType as usize
+        );
+        assert_eq!(mock::tpl_raises(), 0);
+
+        let crc32 = table_crc32(&mut firmware.runtime_services.hdr);
+        assert_eq!(firmware.runtime_services.hdr.crc32, crc32);
+        let crc32 = table_crc32(&mut firmware.system_table.hdr);
+        assert_eq!(firmware.system_table.hdr.crc32, crc32);
+
+        let (efi_status, _) = exchange_get_variable(&mut firmware, Phase::Runtime);
+        assert_eq!(efi_status, efi::Status::ALREADY_STARTED);
+    }
+
+    fn reset_hook(get_variable: GetVariableType) {
+        HOOK_STATE.store(HOOK_ACTIVE, Ordering::Release);
+        GET_VARIABLE.set(get_variable);
+        FIRMWARE_GET_VARIABLE.set(fake_firmware);
+    }
+
+    fn virtual_address_change(firmware: &mut mock::MockFirmware) {
+        RUNTIME_SERVICES.store(&mut *firmware.runtime_services, Ordering::Release);
+        assert!(!register_relocations().is_error());
+        handle_set_virtual_address_map(
+            core::ptr::null_mut(),
+            &mut *firmware.runtime_services as *mut _ as *mut core::ffi::c_void,
+        );
+    }
+
+    #[test]
+    fn failed_hook_conversion_passes_through_firmware() {
+        let _lock = mock::lock();
+        // Re-hooked on top of fake_hook, whose address cannot be converted.
+        reset_hook(fake_hook);
+        let mut firmware = mock::MockFirmware::new(handle_get_variable);
+        mock::refuse_conversion(fake_hook as GetVariableType as usize);
+
+        virtual_address_change(&mut firmware);
+        let state = HOOK_STATE.load(Ordering::Acquire);
+        let get_variable = GET_VARIABLE.as_raw() as usize;
+        reset_hook(fake_firmware);
+
+        assert_eq!(state, HOOK_PASS_THROUGH);
+        assert_eq!(
+            get_variable,
+            fake_firmware as GetVariableType as usize + mock::VIRTUAL_OFFSET
+        );
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            handle_get_variable as GetVariableType as usize
+        );
+    }
+
+    #[test]
+    fn failed_conversion_restores_firmware_pointer() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        let mut firmware = mock::MockFirmware::new(handle_get_variable);
+        mock::refuse_conversion(fake_firmware as GetVariableType as usize);
+
+        virtual_address_change(&mut firmware);
+        let state = HOOK_STATE.load(Ordering::Acquire);
+        reset_hook(fake_firmware);
+
+        // The slot holds the physical address for the firmware to convert.
+        assert_eq!(state, HOOK_UNUSABLE);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_firmware as GetVariableType as usize
+        );
+        let crc32 = table_crc32(&mut firmware.runtime_services.hdr);
+        assert_eq!(firmware.runtime_services.hdr.crc32, crc32);
+    }
+
+    #[test]
+    fn failed_conversion_behind_another_hook_leaves_table() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        let mut firmware = mock::MockFirmware::new(fake_hook);
+        mock::refuse_conversion(fake_firmware as GetVariableType as usize);
+
+        virtual_address_change(&mut firmware);
+        let state = HOOK_STATE.load(Ordering::Acquire);
+        reset_hook(fake_firmware);
+
+        assert_eq!(state, HOOK_UNUSABLE);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_hook as GetVariableType as usize
+        );
+    }
+
+    #[test]
+    fn hook_follows_republished_table() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        let mut firmware = mock::MockFirmware::new(handle_get_variable);
+        RUNTIME_SERVICES.store(&mut *firmware.runtime_services, Ordering::Release);
+        let system_table = firmware.system_table();
+
+        // Nothing moved.
+        follow_runtime_services_table(system_table, Phase::BootServices);
+        assert_eq!(mock::tpl_raises(), 0);
+
+        // The new table holds the firmware's original function.
+        let mut republished = mock::MockFirmware::new(fake_firmware);
+        firmware.system_table.runtime_services = &mut *republished.runtime_services;
+        follow_runtime_services_table(system_table, Phase::BootServices);
+        assert_eq!(
+            republished.runtime_services.get_variable as usize,
+            handle_get_variable as GetVariableType as usize
+        );
+        assert_eq!(
+            GET_VARIABLE.as_raw(),
+            fake_firmware as GetVariableType as *mut _
+        );
+        assert_eq!(
+            RUNTIME_SERVICES.load(Ordering::Acquire),
+            &mut *republished.runtime_services as *mut _
+        );
+
+        // Once more at runtime, with another hook already in the new table.
+        let mut hooked = mock::MockFirmware::new(fake_hook);
+        firmware.system_table.runtime_services = &mut *hooked.runtime_services;
+        firmware.system_table.boot_services = core::ptr::null_mut();
+        follow_runtime_services_table(system_table, Phase::Runtime);
+        let get_variable = GET_VARIABLE.as_raw();
+        reset_hook(fake_firmware);
+        assert_eq!(
+            hooked.runtime_services.get_variable as usize,
+            handle_get_variable as GetVariableType as usize
+        );
+        assert_eq!(get_variable, fake_hook as GetVariableType as *mut _);
+        let crc32 = table_crc32(&mut hooked.runtime_services.hdr);
+        assert_eq!(hooked.runtime_services.hdr.crc32, crc32);
+    }
+
+    // Installs the GetVariable hook in the mock's table as load() does.
+    fn install_get_variable_hook(firmware: &mut mock::MockFirmware) -> efi::Status {
+        HOOK_STATE.store(HOOK_ACTIVE, Ordering::Release);
+        let efi_status = exchange_pointer_in_service_table(
+            firmware.system_table(),
+            &mut firmware.runtime_services.get_variable as *mut _ as *mut *mut core::ffi::c_void,
+            handle_get_variable as *mut core::ffi::c_void,
+            GET_VARIABLE.as_mut_ptr(),
+            Phase::BootServices,
+        );
+        if !efi_status.is_error() {
+            FIRMWARE_GET_VARIABLE.copy_from(&GET_VARIABLE);
+        }
+        efi_status
+    }
+
+    // Reads a global variable through the mock's table, as a caller would,
+    // into a buffer of `buffer_size` bytes. None passes a null name. Returns
+    // the status, DataSize, the data returned and the attributes.
+    fn read_through_table(
+        firmware: &mock::MockFirmware,
+        name: Option<&str>,
+        buffer_size: usize,
+    ) -> (efi::Status, usize, std::vec::Vec<u8>, u32) {
+        let mut name: Option<std::vec::Vec<u16>> =
+            name.map(|name| name.encode_utf16().chain([0]).collect());
+        let name = name
+            .as_mut()
+            .map_or(core::ptr::null_mut(), |name| name.as_mut_ptr());
+        let mut guid = classify::GLOBAL_VARIABLE_GUID;
+        let mut attributes = 0u32;
+        let mut data = std::vec![0u8; buffer_size];
+        let mut data_size = buffer_size;
+        let efi_status = (firmware.runtime_services.get_variable)(
+            name,
+            &mut guid,
+            &mut attributes,
+            &mut data_size,
+            data.as_mut_ptr() as *mut core::ffi::c_void,
+        );
+        if efi_status != efi::Status::SUCCESS {
+            data.clear();
+        }
+        data.truncate(data_size);
+        (efi_status, data_size, data, attributes)
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn reads_through_the_hooked_table_are_recorded() {
+        let _lock = mock::lock();
+        level::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        let mut firmware = mock::MockFirmware::new(mock::variables::get_variable);
+        mock::variables::put("Timeout", classify::GLOBAL_VARIABLE_GUID, 0x07, &[5, 0]);
+        assert_eq!(
+            install_get_variable_hook(&mut firmware),
+            efi::Status::SUCCESS
+        );
+
+        serial::start_capture();
+        let found = read_through_table(&firmware, Some("Timeout"), 16);
+        let missing = read_through_table(&firmware, Some("Missing"), 16);
+        let too_small = read_through_table(&firmware, Some("Timeout"), 1);
+        let unnamed = read_through_table(&firmware, None, 16);
+        let records = serial::without_locations(&serial::take_capture());
+        reset_hook(fake_firmware);
+
+        assert_eq!(found, (efi::Status::SUCCESS, 2, std::vec![5, 0], 0x07));
+        assert_eq!(missing.0, efi::Status::NOT_FOUND);
+        assert_eq!(
+            (too_small.0, too_small.1),
+            (efi::Status::BUFFER_TOO_SMALL, 2)
+        );
+        assert_eq!(unnamed.0, efi::Status::INVALID_PARAMETER);
+        assert_eq!(mock::variables::calls(), 4);
+        let guid = GuidFmt(&classify::GLOBAL_VARIABLE_GUID);
+        for record in [
+            std::format!("G: {} Size=00000010->00000002 Timeout: 0x0", guid),
+            std::format!("G: {} Size=00000010->n/a Missing: 0x800000000000000e", guid),
+            std::format!(
+                "G: {} Size=00000001->00000002 Timeout: 0x8000000000000005",
+                guid
+            ),
+            std::string::String::from(
+                "GetVariable called with a null name or GUID: 0x8000000000000002",
+            ),
+        ] {
+            assert!(
+                records.lines().any(|line| line == record),
+                "{} not in:\n{}",
+                record,
+                records
+            );
+        }
+    }
+
+    // With an empty filter at level trace, every call counted has its record,
+    // as tools/smoke checks under OVMF.
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn ready_to_boot_summary_counts_the_records() {
+        let _lock = mock::lock();
+        level::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        counters::reset();
+        let mut firmware = mock::MockFirmware::new(mock::variables::get_variable);
+        mock::variables::put("Timeout", classify::GLOBAL_VARIABLE_GUID, 0x07, &[5, 0]);
+        assert_eq!(
+            install_get_variable_hook(&mut firmware),
+            efi::Status::SUCCESS
+        );
+
+        serial::start_capture();
+        read_through_table(&firmware, Some("Timeout"), 16);
+        read_through_table(&firmware, Some("Missing"), 16);
+        read_through_table(&firmware, Some("Timeout"), 1);
+        counters::log_calls();
+        let records = serial::take_capture();
+        reset_hook(fake_firmware);
+
+        assert_eq!(
+            records
+                .lines()
+                .filter(|line| line.starts_with("G: "))
+                .count(),
+            3
+        );
+        assert!(
+            records.contains("Calls at ReadyToBoot: GetVariable=3 (1 ok) SetVariable=0 (0 ok)"),
+            "{}",
+            records
+        );
+    }
+
+    // Loaded twice, the driver finds itself in the slot and leaves it; under
+    // another driver's hook, each read still reaches the firmware, and is
+    // recorded, once.
+    #[test]
+    fn double_hooking_records_each_read_once() {
+        let _lock = mock::lock();
+        level::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        let mut firmware = mock::MockFirmware::new(mock::variables::get_variable);
+        mock::variables::put("Timeout", classify::GLOBAL_VARIABLE_GUID, 0x07, &[5, 0]);
+        assert_eq!(
+            install_get_variable_hook(&mut firmware),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            install_get_variable_hook(&mut firmware),
+            efi::Status::ALREADY_STARTED
+        );
+        let saved = GET_VARIABLE.as_raw() as usize;
+        FAKE_HOOK_NEXT.set(firmware.runtime_services.get_variable);
+        firmware.runtime_services.get_variable = fake_hook;
+
+        serial::start_capture();
+        let read = read_through_table(&firmware, Some("Timeout"), 16);
+        let records = serial::take_capture();
+        reset_hook(fake_firmware);
+
+        assert_eq!(
+            saved,
+            mock::variables::get_variable as GetVariableType as usize
+        );
+        assert_eq!(read, (efi::Status::SUCCESS, 2, std::vec![5, 0], 0x07));
+        assert_eq!(mock::variables::calls(), 1);
+        let recorded = !cfg!(feature = "profile-minimal");
+        assert_eq!(records.matches("G: ").count(), recorded as usize);
+    }
+
+    #[test]
+    fn set_variable_reinstalls_a_removed_hook_a_bounded_number_of_times() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        integrity::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        let mut firmware = mock::MockFirmware::new(handle_get_variable);
+        RUNTIME_SERVICES.store(&mut *firmware.runtime_services, Ordering::Release);
+        assert!(!integrity::start(&mut firmware.system_table).is_error());
+        integrity::set_policy(integrity::IntegrityPolicy::Reinstall);
+        integrity::set_max_reinstalls(2);
+        FAKE_HOOK_NEXT.set(handle_get_variable);
+
+        let write = || {
+            let mut name = [b'T' as u16, 0];
+            let mut data = [0u8];
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+        for phase_exited in [false, true, true] {
+            BOOT_SERVICES_EXITED.store(phase_exited, Ordering::Release);
+            firmware.runtime_services.get_variable = fake_hook;
+            let crc32 = table_crc32(&mut firmware.runtime_services.hdr);
+            firmware.runtime_services.hdr.crc32 = crc32;
+            assert_eq!(write(), efi::Status::SUCCESS);
+        }
+        BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+        let (get_variable, rehooked) = (GET_VARIABLE.as_raw(), integrity::is_rehooked());
+        let reinstalls = integrity::reinstalls();
+        integrity::shutdown();
+        integrity::reset();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+
+        // Re-installed twice, in front of the interloper, then left behind it.
+        assert_eq!(reinstalls, 2);
+        assert!(rehooked);
+        assert_eq!(get_variable, fake_hook as GetVariableType as *mut _);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_hook as GetVariableType as usize
+        );
+        assert_eq!(mock::open_events(), 0);
+    }
+
+    efiapi! {
+        #[cfg(not(feature = "profile-minimal"))]
+        fn fake_firmware_set_variable(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: u32,
+            _data_size: usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            efi::Status::SECURITY_VIOLATION
+        }
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn rejected_authenticated_deletion_is_flagged() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_firmware_set_variable);
+
+        let mut name = [b'd' as u16, b'b' as u16, 0];
+        let mut guid = classify::IMAGE_SECURITY_DATABASE_GUID;
+        seen::record("db", &guid, 0x27);
+        let efi_status = set_variable::handle_set_variable(
+            name.as_mut_ptr(),
+            &mut guid,
+            0x27,
+            0,
+            core::ptr::null_mut(),
+        );
+        assert_eq!(efi_status, efi::Status::SECURITY_VIOLATION);
+        assert_eq!(set_variable::deletion_attempts(), 1);
+        // The existence check released the reentrancy guard.
+        assert!(!IN_GET_VARIABLE.load(Ordering::Acquire));
+
+        // A write is not a deletion.
+        let mut data = [0u8; 4];
+        set_variable::handle_set_variable(
+            name.as_mut_ptr(),
+            &mut guid,
+            0x27,
+            data.len(),
+            data.as_mut_ptr() as *mut core::ffi::c_void,
+        );
+        assert_eq!(set_variable::deletion_attempts(), 1);
+        set_variable::reset();
+    }
+
+    static ACCEPTED_WRITES: core::sync::atomic::AtomicUsize =
+        core::sync::atomic::AtomicUsize::new(0);
+
+    efiapi! {
+        fn fake_accepting_set_variable(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: u32,
+            _data_size: usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            ACCEPTED_WRITES.fetch_add(1, Ordering::AcqRel);
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn accepted_runtime_write_to_boot_critical_variable_is_persisted() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        correlate::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        ACCEPTED_WRITES.store(0, Ordering::Release);
+
+        let mut name: std::vec::Vec<u16> = "BootOrder\0".encode_utf16().collect();
+        let mut guid = classify::GLOBAL_VARIABLE_GUID;
+        let mut data = [1u8, 0];
+        let mut write = || {
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut guid,
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+
+        // Before ExitBootServices, the write is only logged.
+        assert_eq!(write(), efi::Status::SUCCESS);
+        assert_eq!(ACCEPTED_WRITES.load(Ordering::Acquire), 1);
+        assert_eq!(
+            correlate::matches(correlate::RUNTIME_BOOT_CRITICAL_WRITE),
+            0
+        );
+
+        // At runtime, it is counted and the boot report rewritten.
+        BOOT_SERVICES_EXITED.store(true, Ordering::Release);
+        assert_eq!(write(), efi::Status::SUCCESS);
+        BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+        assert_eq!(ACCEPTED_WRITES.load(Ordering::Acquire), 3);
+        assert_eq!(
+            correlate::matches(correlate::RUNTIME_BOOT_CRITICAL_WRITE),
+            1
+        );
+        assert_eq!(report::collect().runtime_boot_critical_writes, 1);
+
+        correlate::reset();
+        set_variable::reset();
+    }
+
+    #[test]
+    fn control_writes_apply_without_reaching_firmware() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(mock::set_variable);
+        control::reset();
+        level::reset();
+        config::apply(&config::RuntimeConfig::from_build_env());
+
+        let write = |name: &str, data: &[u8]| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = data.to_vec();
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut config::UVM_VENDOR_GUID.clone(),
+                0x06,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+        let read_status = |data_size: usize| {
+            let mut name: std::vec::Vec<u16> = "UvmCtlStatus\0".encode_utf16().collect();
+            let mut attributes = 0u32;
+            let mut data = [0u8; control::STATUS_SIZE];
+            let mut size = data_size;
+            let efi_status = handle_get_variable(
+                name.as_mut_ptr(),
+                &mut config::UVM_VENDOR_GUID.clone(),
+                &mut attributes,
+                &mut size,
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            );
+            (efi_status, size, data)
+        };
+
+        // Info level, paused with runtime data access.
+        let command = [1, 0x01, 1, 2, 0x02, 8, 3, 0, 0, 0, 3, 0, 0, 0];
+        assert_eq!(write("UvmCtl", &command), efi::Status::SUCCESS);
+        assert_eq!(level::level(), level::Level::Info);
+        assert!(level::is_paused());
+        assert_eq!(
+            config::current().unwrap().runtime_data_access,
+            safety::RuntimeDataAccess::Allow
+        );
+        assert_eq!(write("UvmCtlStatus", &[0]), efi::Status::SUCCESS);
+        // Only the new level reached firmware, to be kept for the next boots.
+        let writes = mock::take_writes();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].name, persist::LEVEL_VARIABLE);
+        assert_eq!(writes[0].data, [config_format::LEVEL_VERSION, 2]);
+
+        assert_eq!(
+            read_status(0),
+            (efi::Status::BUFFER_TOO_SMALL, control::STATUS_SIZE, [0; 16])
+        );
+        assert_eq!(
+            read_status(control::STATUS_SIZE),
+            (
+                efi::Status::SUCCESS,
+                control::STATUS_SIZE,
+                [1, 1, 0xff, 2, 3, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]
+            )
+        );
+
+        // Nothing of a malformed write is applied.
+        assert_eq!(
+            write("UvmCtl", &[1, 0x01, 1, 3, 0x02, 8, 0]),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(level::level(), level::Level::Info);
+        let (_, _, status) = read_status(control::STATUS_SIZE);
+        assert_eq!((status[1], status[2], status[8]), (3, 1, 2));
+
+        // A snapshot, whose ID the status gives, diffed into UvmCtlDiff.
+        let read_diff = || {
+            let mut name: std::vec::Vec<u16> = "UvmCtlDiff\0".encode_utf16().collect();
+            let mut data = std::vec![0u8; 1024];
+            let mut size = data.len();
+            let efi_status = handle_get_variable(
+                name.as_mut_ptr(),
+                &mut config::UVM_VENDOR_GUID.clone(),
+                core::ptr::null_mut(),
+                &mut size,
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            );
+            (
+                efi_status,
+                uvm_interface::protocol::Statistics::parse(&data[..size]),
+            )
+        };
+        snapshot::reset();
+        assert_eq!(read_diff().0, efi::Status::NOT_FOUND);
+        assert_eq!(write("UvmCtl", &[1, 0x04, 0]), efi::Status::SUCCESS);
+        let (_, _, status) = read_status(control::STATUS_SIZE);
+        let id = u32::from_le_bytes([status[12], status[13], status[14], status[15]]);
+        assert_ne!(id, 0);
+        let [i0, i1, i2, i3] = id.to_le_bytes();
+        counters::count(top::Access::Read, efi::Status::NOT_FOUND, None);
+        assert_eq!(
+            write("UvmCtl", &[1, 0x05, 4, i0, i1, i2, i3]),
+            efi::Status::SUCCESS
+        );
+        let (efi_status, diff) = read_diff();
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        assert_eq!(diff.unwrap().get_variable.by_phase.iter().sum::<u64>(), 1);
+        // Stale once the counters are reset.
+        assert_eq!(
+            write("UvmCtl", &[1, 0x03, 0, 0x05, 4, i0, i1, i2, i3]),
+            efi::Status::INVALID_PARAMETER
+        );
+        let (_, _, status) = read_status(control::STATUS_SIZE);
+        assert_eq!((status[1], status[2]), (8, 1));
+
+        // Forwarded when so configured.
+        config::update(|config| config.control_forward_policy = control::ForwardPolicy::Forward);
+        assert_eq!(write("UvmCtl", &[1, 0x03, 0]), efi::Status::SUCCESS);
+        let writes = mock::take_writes();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].name, "UvmCtl");
+
+        config::apply(&config::RuntimeConfig::from_build_env());
+        control::reset();
+        level::reset();
+        set_variable::reset();
+        reset_hook(fake_firmware);
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn redacted_values_are_never_logged() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        redact::reset();
+        redact::add(
+            "8be4df61-93ca-11d2-aa0d-00e098032b8c:BootOrder;\
+             e20939be-32d4-41be-a150-897f85d49829:MemoryOverwriteRequestControl",
+        );
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        last_value::remove("BootOrder", &classify::GLOBAL_VARIABLE_GUID);
+        // Redaction takes precedence over allowing data access at runtime.
+        safety::set_runtime_data_access(safety::RuntimeDataAccess::Allow);
+        serial::start_capture();
+
+        let accesses = [
+            ("BootOrder", classify::GLOBAL_VARIABLE_GUID),
+            (
+                "MemoryOverwriteRequestControl",
+                classify::MEMORY_ONLY_RESET_CONTROL_GUID,
+            ),
+        ];
+        for phase_exited in [false, true] {
+            BOOT_SERVICES_EXITED.store(phase_exited, Ordering::Release);
+            for (name, mut guid) in accesses {
+                let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+                let mut secret = [0xa5u8, 0x5a, 0xa5, 0x5a];
+                let mut attributes = 0u32;
+                let mut data_size = secret.len();
+                handle_get_variable(
+                    name.as_mut_ptr(),
+                    &mut guid,
+                    &mut attributes,
+                    &mut data_size,
+                    secret.as_mut_ptr() as *mut core::ffi::c_void,
+                );
+                set_variable::handle_set_variable(
+                    name.as_mut_ptr(),
+                    &mut guid,
+                    0x07,
+                    1,
+                    secret.as_mut_ptr() as *mut core::ffi::c_void,
+                );
+            }
+        }
+        BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+        safety::set_runtime_data_access(safety::RuntimeDataAccess::Deny);
+
+        let records = serial::take_capture();
+        assert!(records.contains("SetVariable of MOR variable"));
+        assert!(records.contains("now <redacted>"));
+        // Caller addresses and the timestamps of accesses may contain
+        // anything.
+        let words = records.split_whitespace().collect::<std::vec::Vec<_>>();
+        let records = words
+            .iter()
+            .zip(words.iter().skip(1).chain([&""]))
+            .filter(|(word, next)| !word.starts_with("0x") && **next != "ticks")
+            .map(|(word, _)| *word)
+            .collect::<std::vec::Vec<_>>()
+            .join(" ");
+        for shown in ["a5", "A5", "5a", "5A", "165", "ClearMemory"] {
+            assert!(!records.contains(shown), "{} in:\n{}", shown, records);
+        }
+        assert!(last_value::with(
+            "BootOrder",
+            &classify::GLOBAL_VARIABLE_GUID,
+            |value| { value.is_none() }
+        ));
+
+        redact::reset();
+        set_variable::reset();
+    }
+
+    #[cfg(feature = "enforce")]
+    #[test]
+    fn writes_to_protected_variables_never_reach_firmware() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        enforce::reset();
+        enforce::protect("8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*");
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        ACCEPTED_WRITES.store(0, Ordering::Release);
+
+        let write = |name: &str, mut guid: efi::Guid| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = [1u8, 0];
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut guid,
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+
+        // Listed, and the configuration variable, deletions included.
+        let blocked = [
+            ("BootOrder", classify::GLOBAL_VARIABLE_GUID),
+            ("Boot0001", classify::GLOBAL_VARIABLE_GUID),
+            ("UvmProtect", config::UVM_VENDOR_GUID),
+        ];
+        for (name, guid) in blocked {
+            assert_eq!(write(name, guid), efi::Status::SECURITY_VIOLATION);
+        }
+        let mut name: std::vec::Vec<u16> = "UvmProtect\0".encode_utf16().collect();
+        let efi_status = set_variable::handle_set_variable(
+            name.as_mut_ptr(),
+            &mut config::UVM_VENDOR_GUID.clone(),
+            0,
+            0,
+            core::ptr::null_mut(),
+        );
+        assert_eq!(efi_status, efi::Status::SECURITY_VIOLATION);
+        assert_eq!(ACCEPTED_WRITES.load(Ordering::Acquire), 0);
+        assert_eq!(enforce::blocked(), 4);
+
+        // Everything else is forwarded.
+        assert_eq!(
+            write("BootOrder", classify::IMAGE_SECURITY_DATABASE_GUID),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            write("Timeout", classify::GLOBAL_VARIABLE_GUID),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(ACCEPTED_WRITES.load(Ordering::Acquire), 2);
+
+        enforce::reset();
+        set_variable::reset();
+    }
+
+    #[cfg(feature = "enforce")]
+    static FIRMWARE_READS: core::sync::atomic::AtomicUsize =
+        core::sync::atomic::AtomicUsize::new(0);
+
+    #[cfg(feature = "enforce")]
+    efiapi! {
+        // Firmware without any variable, counting the reads reaching it.
+        fn fake_empty_get_variable(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            _data_size: *mut usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            FIRMWARE_READS.fetch_add(1, Ordering::AcqRel);
+            efi::Status::NOT_FOUND
+        }
+    }
+
+    #[cfg(feature = "enforce")]
+    #[test]
+    fn hidden_variables_look_absent_through_the_table() {
+        use mock::store;
+
+        let _lock = mock::lock();
+        hide::reset();
+        get_next_variable_name::reset();
+        hide::hide("8be4df61-93ca-11d2-aa0d-00e098032b8c:SecureBoot");
+        let mut firmware = mock::MockFirmware::new(fake_empty_get_variable);
+        store::fill(&[
+            (classify::GLOBAL_VARIABLE_GUID, "SecureBoot"),
+            (classify::GLOBAL_VARIABLE_GUID, "Timeout"),
+        ]);
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+        let get_variable = firmware.runtime_services.get_variable;
+        let get_next_variable_name = firmware.runtime_services.get_next_variable_name;
+
+        let read = |name: &str| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut guid = classify::GLOBAL_VARIABLE_GUID;
+            let mut data = [0u8; 4];
+            let mut data_size = data.len();
+            get_variable(
+                name.as_mut_ptr(),
+                &mut guid,
+                core::ptr::null_mut(),
+                &mut data_size,
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+        FIRMWARE_READS.store(0, Ordering::Release);
+        assert_eq!(read("SecureBoot"), efi::Status::NOT_FOUND);
+        assert_eq!(FIRMWARE_READS.load(Ordering::Acquire), 0);
+        assert_eq!(read("Timeout"), efi::Status::NOT_FOUND);
+        assert_eq!(FIRMWARE_READS.load(Ordering::Acquire), 1);
+
+        let mut name = [0u16; 32];
+        let mut guid = classify::GLOBAL_VARIABLE_GUID;
+        let mut size = 64;
+        assert_eq!(
+            get_next_variable_name(&mut size, name.as_mut_ptr(), &mut guid),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            &name[..8],
+            "Timeout\0".encode_utf16().collect::<std::vec::Vec<_>>()
+        );
+        assert_eq!(hide::hidden(), 2);
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        assert_eq!(
+            firmware.runtime_services.get_next_variable_name as usize,
+            store::get_next_variable_name as GetNextVariableNameType as usize
+        );
+        images::reset();
+        hide::reset();
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn appends_to_signature_databases_are_summarized() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        serial::start_capture();
+
+        // One EFI_SIGNATURE_LIST holding a single 32-byte entry owned by the
+        // monitor's vendor GUID, of a type that has no name.
+        let mut list = std::vec::Vec::new();
+        list.extend_from_slice(classify::GLOBAL_VARIABLE_GUID.as_bytes());
+        list.extend_from_slice(&(28u32 + 48).to_le_bytes());
+        list.extend_from_slice(&0u32.to_le_bytes());
+        list.extend_from_slice(&48u32.to_le_bytes());
+        list.extend_from_slice(config::UVM_VENDOR_GUID.as_bytes());
+        list.extend_from_slice(&[0x11; 32]);
+        let append = |data: &mut [u8]| {
+            let mut name = [b'd' as u16, b'b' as u16, 0];
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut classify::IMAGE_SECURITY_DATABASE_GUID.clone(),
+                0x47,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+        append(&mut list.clone());
+        append(&mut list[..40]);
+
+        let records = serial::take_capture();
+        set_variable::reset();
+        assert!(records.contains(&std::format!(
+            "type={} entries=1 owner={} lists=1",
+            GuidFmt(&classify::GLOBAL_VARIABLE_GUID),
+            GuidFmt(&config::UVM_VENDOR_GUID)
+        )));
+        assert!(records.contains("Malformed signature list appended to db"));
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    static SETUP_MODE: AtomicU8 = AtomicU8::new(1);
+
+    efiapi! {
+        // A store holding only SetupMode, of value SETUP_MODE.
+        #[cfg(not(feature = "profile-minimal"))]
+        fn fake_mode_get_variable(
+            variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let mut name = [0u8; 64];
+            if convert_name(variable_name, &mut name) != "SetupMode" {
+                return efi::Status::NOT_FOUND;
+            }
+            if unsafe { core::mem::replace(&mut *data_size, 1) } < 1 {
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            unsafe { *(data as *mut u8) = SETUP_MODE.load(Ordering::Acquire) };
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn mode_transitions_are_reported_from_the_first_access() {
+        let _lock = mock::lock();
+        reset_hook(fake_mode_get_variable);
+        FIRMWARE_GET_VARIABLE.set(fake_mode_get_variable);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        SETUP_MODE.store(1, Ordering::Release);
+
+        serial::start_capture();
+        mode::sample();
+        SETUP_MODE.store(0, Ordering::Release);
+        let read = || {
+            let mut name: std::vec::Vec<u16> = "SetupMode".encode_utf16().chain([0]).collect();
+            let mut attributes = 0u32;
+            let mut value = 0xffu8;
+            let mut data_size = 1usize;
+            handle_get_variable(
+                name.as_mut_ptr(),
+                &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+                &mut attributes,
+                &mut data_size,
+                &mut value as *mut u8 as *mut core::ffi::c_void,
+            )
+        };
+        assert_eq!(read(), efi::Status::SUCCESS);
+        assert_eq!(read(), efi::Status::SUCCESS);
+        let mut name: std::vec::Vec<u16> = "SetupMode".encode_utf16().chain([0]).collect();
+        let mut value = 1u8;
+        set_variable::handle_set_variable(
+            name.as_mut_ptr(),
+            &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+            0x07,
+            1,
+            &mut value as *mut u8 as *mut core::ffi::c_void,
+        );
+
+        let records = serial::take_capture();
+        set_variable::reset();
+        reset_hook(fake_firmware);
+        assert!(records.contains("Secure Boot modes at load: SetupMode=1 AuditMode=n/a"));
+        assert_eq!(records.matches("SetupMode 1\u{2192}0").count(), 1);
+        assert!(records.contains("SetupMode 1\u{2192}0 on GetVariable in BootServices from "));
+        assert!(records.contains("SetupMode 0\u{2192}1 on SetVariable in BootServices from "));
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    static PK_VALUE: std::sync::Mutex<std::vec::Vec<u8>> =
+        std::sync::Mutex::new(std::vec::Vec::new());
+
+    efiapi! {
+        // A store holding only PK, of value PK_VALUE.
+        #[cfg(not(feature = "profile-minimal"))]
+        fn fake_pk_get_variable(
+            variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let mut name = [0u8; 64];
+            if convert_name(variable_name, &mut name) != "PK" {
+                return efi::Status::NOT_FOUND;
+            }
+            let value = PK_VALUE.lock().unwrap();
+            let size = unsafe { core::mem::replace(&mut *data_size, value.len()) };
+            if size < value.len() {
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            unsafe { core::ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len()) };
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn reads_diverging_from_a_shadow_are_flagged() {
+        let _lock = mock::lock();
+        reset_hook(fake_pk_get_variable);
+        FIRMWARE_GET_VARIABLE.set(fake_pk_get_variable);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        shadow::reset();
+
+        // One signature list holding one 16-byte signature.
+        let list = |fill: u8| {
+            let mut list = std::vec::Vec::new();
+            list.extend_from_slice(classify::GLOBAL_VARIABLE_GUID.as_bytes());
+            list.extend_from_slice(&(28u32 + 32).to_le_bytes());
+            list.extend_from_slice(&0u32.to_le_bytes());
+            list.extend_from_slice(&32u32.to_le_bytes());
+            list.extend_from_slice(config::UVM_VENDOR_GUID.as_bytes());
+            list.extend_from_slice(&[fill; 16]);
+            list
+        };
+        let read = || {
+            let mut name = [b'P' as u16, b'K' as u16, 0];
+            let mut attributes = 0u32;
+            let mut value = [0u8; 64];
+            let mut data_size = value.len();
+            handle_get_variable(
+                name.as_mut_ptr(),
+                &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+                &mut attributes,
+                &mut data_size,
+                value.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+        *PK_VALUE.lock().unwrap() = list(0x11);
+        serial::start_capture();
+        shadow::load();
+        assert_eq!(read(), efi::Status::SUCCESS);
+
+        // Changed behind the hooks, then no longer signature lists.
+        *PK_VALUE.lock().unwrap() = list(0x22);
+        assert_eq!(read(), efi::Status::SUCCESS);
+        assert_eq!(read(), efi::Status::SUCCESS);
+        *PK_VALUE.lock().unwrap() = std::vec![0xff; 40];
+        assert_eq!(read(), efi::Status::SUCCESS);
+
+        // A write the hook saw is followed, past its authentication descriptor.
+        let mut write = std::vec![0u8; 16];
+        write.extend_from_slice(&8u32.to_le_bytes());
+        write.extend_from_slice(&[0; 4]);
+        write.extend_from_slice(&list(0x33));
+        let mut name = [b'P' as u16, b'K' as u16, 0];
+        set_variable::handle_set_variable(
+            name.as_mut_ptr(),
+            &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+            0x27,
+            write.len(),
+            write.as_mut_ptr() as *mut core::ffi::c_void,
+        );
+        *PK_VALUE.lock().unwrap() = list(0x33);
+        assert_eq!(read(), efi::Status::SUCCESS);
+
+        let records = serial::take_capture();
+        set_variable::reset();
+        shadow::reset();
+        reset_hook(fake_firmware);
+        let guid = GuidFmt(&classify::GLOBAL_VARIABLE_GUID);
+        assert!(records.contains(&std::format!("Shadow of {} PK: 60 bytes", guid)));
+        assert!(records.contains(&std::format!(
+            "Unobserved write to {} PK in BootServices: shadow 60 bytes, now 60 bytes",
+            guid
+        )));
+        assert!(records.contains(&std::format!(
+            "Store corruption of {} PK in BootServices: shadow 60 bytes, now 40 bytes",
+            guid
+        )));
+        assert_eq!(records.matches("Unobserved write").count(), 1);
+        assert_eq!(records.matches("Store corruption").count(), 1);
+    }
+
+    #[cfg(feature = "enforce")]
+    efiapi! {
+        // A store holding only BootOrder.
+        fn fake_store_get_variable(
+            variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let mut name = [0u8; 64];
+            if convert_name(variable_name, &mut name) != "BootOrder" {
+                return efi::Status::NOT_FOUND;
+            }
+            let value = [1u8, 0, 2, 0];
+            let size = unsafe { core::mem::replace(&mut *data_size, value.len()) };
+            if size < value.len() {
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            unsafe {
+                *attributes = 0x07;
+                core::ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len());
+            }
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[cfg(feature = "enforce")]
+    #[test]
+    fn locked_variables_only_take_identical_rewrites() {
+        let _lock = mock::lock();
+        reset_hook(fake_store_get_variable);
+        set_variable::reset();
+        enforce::reset();
+        lock::reset();
+        lock::lock(
+            "8be4df61-93ca-11d2-aa0d-00e098032b8c:BootOrder;\
+             8be4df61-93ca-11d2-aa0d-00e098032b8c:BootNext",
+        );
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        ACCEPTED_WRITES.store(0, Ordering::Release);
+
+        let write = |name: &str, data: &[u8]| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = data.to_vec();
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+                if data.is_empty() { 0 } else { 0x07 },
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+
+        // Nothing is locked before ReadyToBoot.
+        assert_eq!(write("BootNext", &[1, 0]), efi::Status::SUCCESS);
+        lock::engage();
+
+        assert_eq!(write("BootOrder", &[1, 0, 2, 0]), efi::Status::SUCCESS);
+        assert_eq!(
+            write("BootOrder", &[2, 0, 1, 0]),
+            efi::Status::WRITE_PROTECTED
+        );
+        assert_eq!(write("BootOrder", &[]), efi::Status::WRITE_PROTECTED);
+        // BootNext did not exist at lock time.
+        assert_eq!(write("BootNext", &[1, 0]), efi::Status::WRITE_PROTECTED);
+        assert_eq!(write("BootNext", &[]), efi::Status::SUCCESS);
+        assert_eq!(write("Timeout", &[5, 0]), efi::Status::SUCCESS);
+        assert_eq!(ACCEPTED_WRITES.load(Ordering::Acquire), 4);
+
+        // At OS runtime the data cannot be compared by default.
+        BOOT_SERVICES_EXITED.store(true, Ordering::Release);
+        let efi_status = write("BootOrder", &[1, 0, 2, 0]);
+        BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+        assert_eq!(efi_status, efi::Status::WRITE_PROTECTED);
+        assert_eq!(lock::rejected(), 4);
+
+        // Ignored when absent, BootNext is left open.
+        lock::reset();
+        lock::set_absent_policy(lock::AbsentPolicy::Ignore);
+        lock::lock("8be4df61-93ca-11d2-aa0d-00e098032b8c:BootNext");
+        lock::engage();
+        assert_eq!(write("BootNext", &[1, 0]), efi::Status::SUCCESS);
+
+        lock::reset();
+        set_variable::reset();
+        reset_hook(fake_firmware);
+    }
+
+    fn assert_released(firmware: &mock::MockFirmware) {
+        assert_eq!(mock::open_events(), 0);
+        assert!(!mock::protocol_installed());
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_firmware as GetVariableType as usize
+        );
+        assert!(!set_variable::is_head(&firmware.runtime_services));
+        #[cfg(feature = "enforce")]
+        assert!(!get_next_variable_name::is_head(&firmware.runtime_services));
+    }
+
+    #[test]
+    fn callers_resolve_to_loaded_images() {
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(fake_firmware);
+        let mut file_path = images::file_path(&["\\EFI\\Tools\\UefiVarMonitor.efi"]);
+        firmware.loaded_image.file_path = file_path.as_mut_ptr() as *mut _;
+        firmware.loaded_image.image_base = 0x4000_0000 as *mut core::ffi::c_void;
+        firmware.loaded_image.image_size = 0x1_0000;
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+
+        let caller = |address| std::format!("{}", set_variable::ReturnAddress(Some(address)));
+        assert_eq!(caller(0x4000_0123), "UefiVarMonitor+0x123");
+        assert_eq!(caller(0x4001_0000), "0x40010000");
+        images::relocate(&mut |address| unsafe {
+            *address = (*address as usize + mock::VIRTUAL_OFFSET) as *mut core::ffi::c_void;
+            efi::Status::SUCCESS
+        });
+        assert_eq!(caller(0x5000_0123), "UefiVarMonitor+0x123");
+        assert_eq!(caller(0x4000_0123), "0x40000123");
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        assert_released(&firmware);
+        images::reset();
+        level::reset();
+    }
+
+    #[test]
+    fn self_test_finds_the_hooks_cut_out() {
+        use uvm_interface::protocol::{
+            SELF_TEST_CALL_OBSERVED, SELF_TEST_GET_VARIABLE_SLOT, SELF_TEST_SET_VARIABLE_SLOT,
+        };
+
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(fake_firmware);
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+        serial::start_capture();
+        let result = self_test::run();
+        assert_eq!(result.failed(), 0);
+        let hooks =
+            SELF_TEST_GET_VARIABLE_SLOT | SELF_TEST_SET_VARIABLE_SLOT | SELF_TEST_CALL_OBSERVED;
+        assert_eq!(result.checked & hooks, hooks);
+        assert!(serial::take_capture().contains(self_test::SELF_TEST_RECORD));
+        // Again, as often as asked.
+        assert_eq!(self_test::run().failed(), 0);
+
+        // Another driver took the slot, without forwarding to us.
+        FAKE_HOOK_NEXT.set(fake_firmware);
+        firmware.runtime_services.get_variable = fake_hook;
+        let result = self_test::run();
+        assert_eq!(
+            result.failed(),
+            SELF_TEST_GET_VARIABLE_SLOT | SELF_TEST_CALL_OBSERVED
+        );
+        firmware.runtime_services.get_variable = handle_get_variable;
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        assert_released(&firmware);
+        level::reset();
+    }
+
+    // Unloads the driver if the test fails before it does, while the mock
+    // firmware is still there: the tests after it would find it loaded.
+    struct UnloadOnPanic;
+
+    impl Drop for UnloadOnPanic {
+        fn drop(&mut self) {
+            if std::thread::panicking() {
+                let _ = handle_unload(mock::IMAGE_HANDLE);
+            }
+        }
+    }
+
+    #[test]
+    fn benchmark_calls_through_the_hook_and_around_it() {
+        use uvm_interface::protocol::MAX_BENCHMARK_CALLS;
+
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(fake_firmware);
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+        let _unload = UnloadOnPanic;
+        serial::start_capture();
+        let (reads, _) = top::totals();
+        let result = benchmark::run(8).unwrap();
+        assert_eq!(result.calls, 8);
+        assert!(result.hooked_ticks > 0 && result.bypassed_ticks > 0);
+        // Only the calls through the table reach the hook.
+        assert_eq!(top::totals().0, reads + 8);
+        let logged = serial::take_capture().contains("Benchmark: 8 GetVariable calls");
+        assert_eq!(logged, !cfg!(feature = "profile-minimal"));
+        for calls in [0, MAX_BENCHMARK_CALLS + 1] {
+            assert_eq!(benchmark::run(calls), Err(efi::Status::INVALID_PARAMETER));
+        }
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        assert_released(&firmware);
+        level::reset();
+    }
+
+    #[test]
+    fn every_load_failure_is_unwound() {
+        let _lock = mock::lock();
+
+        // Fail each boot service call of the load in turn, until one load
+        // goes through without hitting the injected failure.
+        for step in 0.. {
+            let mut firmware = mock::MockFirmware::new(fake_firmware);
+            mock::fail_at(Some(step));
+            let efi_status = efi_main(mock::IMAGE_HANDLE, firmware.system_table());
+            let injected = mock::failure_injected();
+            mock::fail_at(None);
+
+            // Optional facilities failing does not fail the load; unloading
+            // has to release what was set up either way. The Unload handler is
+            // one of them, so it is called directly.
+            if !efi_status.is_error() {
+                assert_eq!(
+                    firmware.runtime_services.get_variable as usize,
+                    handle_get_variable as GetVariableType as usize
+                );
+                assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+            }
+            assert_released(&firmware);
+            if !injected {
+                assert!(!efi_status.is_error());
+                assert_eq!(
+                    firmware.loaded_image.unload as usize,
+                    handle_unload as efiapi! {fn(efi::Handle) -> efi::Status} as usize
+                );
+                break;
+            }
+        }
+        level::reset();
+    }
+
+    fn read_timeout() -> efi::Status {
+        let mut name: std::vec::Vec<u16> = "Timeout".encode_utf16().chain([0]).collect();
+        let mut data = [0u8; 4];
+        let mut data_size = data.len();
+        handle_get_variable(
+            name.as_mut_ptr(),
+            &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+            core::ptr::null_mut(),
+            &mut data_size,
+            data.as_mut_ptr() as *mut core::ffi::c_void,
+        )
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn suppressed_reads_are_counted_but_not_recorded() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        level::reset();
+        let (reads, _) = top::totals();
+
+        serial::start_capture();
+        level::set_level(level::Level::Warning);
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        level::set_level(level::Level::Trace);
+        // Only db and dbx are traced, both under another GUID.
+        assert_eq!(
+            filter::replace(
+                "d719b2cb-3d3a-4596-a3bc-dad00e67656f:db;d719b2cb-3d3a-4596-a3bc-dad00e67656f:dbx"
+            ),
+            Ok(2)
+        );
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert_eq!(filter::replace(""), Ok(0));
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        let records = serial::take_capture();
+
+        assert_eq!(records.matches("G: ").count(), 1);
+        assert_eq!(top::totals().0, reads + 3);
+        level::reset();
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn reads_through_the_hook_are_measured() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        overhead::reset();
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        let measured = overhead::statistics();
+        assert_eq!(measured.calls, 2);
+        assert!(measured.max_ticks <= measured.total_ticks);
+        overhead::reset();
+    }
+
+    // profile-minimal counts a read under its GUID, and logs nothing.
+    #[cfg(feature = "profile-minimal")]
+    #[test]
+    fn minimal_reads_are_counted_by_guid_only() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        top::reset();
+
+        serial::start_capture();
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        let records = serial::take_capture();
+
+        assert!(!records.contains("G: "));
+        let mut entries = [uvm_interface::protocol::TopEntry::EMPTY; 4];
+        assert_eq!(top::top(&mut entries), 1);
+        assert_eq!(entries[0].guid, classify::GLOBAL_VARIABLE_GUID);
+        assert_eq!((entries[0].name_length, entries[0].reads), (0, 2));
+        assert_eq!(top::totals().0, 2);
+    }
+
+    // Reads without an Attributes buffer count as of unknown storage until a
+    // write shows that the variable is non-volatile.
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn reads_move_to_the_storage_a_later_write_shows() {
+        use uvm_interface::protocol::{
+            FlashWrites, StorageCounts, STORAGE_NON_VOLATILE, STORAGE_UNKNOWN,
+        };
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        top::reset();
+        counters::reset();
+        counters::reset_flash_writes();
+
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        let by_storage = counters::statistics().by_storage;
+        assert_eq!(by_storage[STORAGE_UNKNOWN as usize].bytes_read, 8);
+
+        let mut name: std::vec::Vec<u16> = "Timeout".encode_utf16().chain([0]).collect();
+        let mut data = [5u8, 0];
+        set_variable::handle_set_variable(
+            name.as_mut_ptr(),
+            &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+            0x7,
+            data.len(),
+            data.as_mut_ptr() as *mut core::ffi::c_void,
+        );
+        let statistics = counters::statistics();
+        assert_eq!(
+            statistics.by_storage[STORAGE_UNKNOWN as usize],
+            StorageCounts::default()
+        );
+        assert_eq!(
+            statistics.by_storage[STORAGE_NON_VOLATILE as usize],
+            StorageCounts {
+                reads: 2,
+                writes: 1,
+                bytes_read: 8,
+                bytes_written: 2,
+            }
+        );
+        assert_eq!(
+            statistics.flash_writes,
+            FlashWrites {
+                writes: 1,
+                bytes: 2
+            }
+        );
+        let mut entries = [uvm_interface::protocol::TopEntry::EMPTY; 1];
+        assert_eq!(top::top(&mut entries), 1);
+        assert_eq!(entries[0].storage, STORAGE_NON_VOLATILE);
+        set_variable::reset();
+    }
+
+    // The latency guard drops the G: record before it drops the name, and
+    // the name before the count.
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn latency_guard_steps_shed_records_then_names() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        level::reset();
+        top::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        let mut entries = [uvm_interface::protocol::TopEntry::EMPTY; 4];
+
+        budget::set_step(budget::Step::Reduced);
+        serial::start_capture();
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert!(!serial::take_capture().contains("G: "));
+        assert_eq!(top::top(&mut entries), 1);
+        assert_eq!(entries[0].name_length, "Timeout".len() as u32);
+
+        budget::set_step(budget::Step::CountersOnly);
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert_eq!(top::top(&mut entries), 2);
+        assert!(entries
+            .iter()
+            .any(|entry| entry.name_length == 0 && entry.reads == 1));
+
+        budget::restart();
+        serial::start_capture();
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert!(serial::take_capture().contains("G: "));
+    }
+
+    #[cfg(all(not(feature = "profile-minimal"), not(feature = "log-deferred")))]
+    fn read_language(language: &[u8]) -> efi::Status {
+        let mut name: std::vec::Vec<u16> = "PlatformLang".encode_utf16().chain([0]).collect();
+        let mut data = language.to_vec();
+        let mut data_size = data.len();
+        handle_get_variable(
+            name.as_mut_ptr(),
+            &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+            core::ptr::null_mut(),
+            &mut data_size,
+            data.as_mut_ptr() as *mut core::ffi::c_void,
+        )
+    }
+
+    #[cfg(all(not(feature = "profile-minimal"), not(feature = "log-deferred")))]
+    #[test]
+    fn changes_mode_records_reads_of_new_data_only() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware_get_language);
+        level::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        seen::set_read_records(seen::ReadRecords::Changes);
+
+        serial::start_capture();
+        for language in [b"en-US", b"en-US", b"en-US", b"fr-FR", b"fr-FR"].iter() {
+            assert_eq!(read_language(*language), efi::Status::SUCCESS);
+        }
+        // Not hashed at runtime unless allowed: every read is recorded.
+        BOOT_SERVICES_EXITED.store(true, Ordering::Release);
+        assert_eq!(read_language(b"fr-FR"), efi::Status::SUCCESS);
+        BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+        let records = serial::take_capture();
+
+        seen::set_read_records(seen::ReadRecords::Every);
+        reset_hook(fake_firmware);
+        let records: std::vec::Vec<&str> = records
+            .lines()
+            .filter(|record| record.starts_with("G: ") && record.contains("PlatformLang"))
+            .collect();
+        assert_eq!(records.len(), 3, "{:?}", records);
+        assert!(records[0].ends_with(": 0x0 first read"));
+        assert!(records[1].ends_with(": 0x0 changed after 2 unchanged reads"));
+        assert!(records[2].ends_with(": 0x0"));
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn accepted_writes_are_diffed_against_the_kept_value() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        level::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+        diff::reset();
+        let write = |order: &[u8]| {
+            let mut name: std::vec::Vec<u16> = "BootOrder".encode_utf16().chain([0]).collect();
+            let mut data = order.to_vec();
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            );
+        };
+
+        serial::start_capture();
+        write(&[1, 0, 2, 0]);
+        write(&[2, 0, 1, 0]);
+        // Unseen at the lowest step: the kept value may be stale.
+        budget::set_step(budget::Step::CountersOnly);
+        write(&[1, 0]);
+        budget::restart();
+        write(&[1, 0, 2, 0]);
+        let records = serial::take_capture();
+        set_variable::reset();
+
+        let diffs: std::vec::Vec<&str> = records
+            .lines()
+            .filter(|record| record.starts_with("D: "))
+            .collect();
+        assert_eq!(diffs.len(), 3, "{:?}", diffs);
+        assert!(diffs[0].contains(" BootOrder Size=00000004 CRC32="));
+        assert!(diffs[0].ends_with(" New=01000200 (u32 = 0x00020001)"));
+        assert!(diffs[1].contains(" Size=00000004->00000004 CRC32="));
+        assert!(diffs[1].ends_with(" Changed=+0000:01>02,+0002:02>01"));
+        assert!(diffs[2].ends_with(" New=01000200 (u32 = 0x00020001)"));
+    }
+
+    // The size of the last write fake_small_store_set_variable accepted.
+    #[cfg(not(feature = "profile-minimal"))]
+    static SMALL_STORE_WRITTEN: core::sync::atomic::AtomicUsize =
+        core::sync::atomic::AtomicUsize::new(0);
+
+    efiapi! {
+        #[cfg(not(feature = "profile-minimal"))]
+        fn fake_small_store_set_variable(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: u32,
+            data_size: usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            if data_size > uvm_interface::report::REPORT_MINOR_0_SIZE {
+                return efi::Status::OUT_OF_RESOURCES;
+            }
+            SMALL_STORE_WRITTEN.store(data_size, Ordering::Release);
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn writes_to_histories_are_kept_and_reported() {
+        use uvm_interface::protocol::{HistoryEntry, HISTORY_FLAG_GAP, HISTORY_FLAG_INSPECTED};
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_small_store_set_variable);
+        history::reset();
+        history::load();
+        let write = |name: &str, value: &[u8]| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = value.to_vec();
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut classify::GLOBAL_VARIABLE_GUID.clone(),
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            );
+        };
+
+        write("BootNext", &[1, 0]);
+        // Unseen at the lowest step.
+        budget::set_step(budget::Step::CountersOnly);
+        write("BootNext", &[2, 0]);
+        budget::restart();
+        write("Timeout", &[5, 0]);
+        write("BootNext", &[3, 0]);
+
+        let mut entries = [HistoryEntry::EMPTY; 4];
+        assert_eq!(history::read(&mut entries), Some(2));
+        assert_eq!(entries[0].name(), "BootNext");
+        assert_eq!(entries[0].data(), [1, 0]);
+        assert_eq!(entries[0].crc32, crc32::crc32(&[1, 0]));
+        assert_eq!(entries[0].flags, HISTORY_FLAG_INSPECTED);
+        assert_eq!(entries[1].data(), [3, 0]);
+        assert_eq!(entries[1].flags, HISTORY_FLAG_INSPECTED | HISTORY_FLAG_GAP);
+        assert_eq!(report::collect().history(), &entries[..2]);
+
+        // Without room for the history, the report is written without it.
+        assert_eq!(report::write(), efi::Status::SUCCESS);
+        assert_eq!(
+            SMALL_STORE_WRITTEN.load(Ordering::Acquire),
+            uvm_interface::report::REPORT_MINOR_0_SIZE
+        );
+        set_variable::reset();
+        history::reset();
+    }
+
+    efiapi! {
+        // Firmware whose every variable is non-volatile and holds its name.
+        fn fake_named_get_variable(
+            variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let mut buffer = [0u8; 64];
+            let name = convert_name(variable_name, &mut buffer);
+            unsafe {
+                if !attributes.is_null() {
+                    *attributes = 0x07;
+                }
+                let available = *data_size;
+                *data_size = name.len();
+                if available < name.len() {
+                    return efi::Status::BUFFER_TOO_SMALL;
+                }
+                core::ptr::copy_nonoverlapping(name.as_ptr(), data as *mut u8, name.len());
+            }
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[test]
+    fn the_store_is_inventoried_at_load() {
+        let _lock = mock::lock();
+        let setup = efi::Guid::from_fields(
+            0xc811fa38,
+            0x42c8,
+            0x4579,
+            0xa9,
+            0xbb,
+            &[0x60, 0xe9, 0x4e, 0xdd, 0xfb, 0x34],
+        );
+        let mut firmware = mock::MockFirmware::new(fake_named_get_variable);
+        mock::store::fill(&[
+            (classify::GLOBAL_VARIABLE_GUID, "Timeout"),
+            (setup, "AMITSESetup"),
+            (classify::GLOBAL_VARIABLE_GUID, "BootOrder"),
+        ]);
+        counters::reset();
+        level::set_level(level::Level::Trace);
+        serial::start_capture();
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+
+        let (listed, end) = inventory::with_inventory(|inventory, end| {
+            let listed: std::vec::Vec<_> = inventory
+                .entries()
+                .iter()
+                .map(|entry| (entry.guid, entry.name().to_owned(), entry.size, entry.crc32))
+                .collect();
+            (listed, end)
+        })
+        .unwrap();
+        assert_eq!(end, inventory::End::Complete);
+        // Sorted by the bytes of the GUID, then by name, and the redacted
+        // variable not hashed.
+        assert_eq!(
+            listed,
+            [
+                (setup, "AMITSESetup".to_owned(), Some(11), None),
+                (
+                    classify::GLOBAL_VARIABLE_GUID,
+                    "BootOrder".to_owned(),
+                    Some(9),
+                    Some(crc32::crc32(b"BootOrder"))
+                ),
+                (
+                    classify::GLOBAL_VARIABLE_GUID,
+                    "Timeout".to_owned(),
+                    Some(7),
+                    Some(crc32::crc32(b"Timeout"))
+                ),
+            ]
+        );
+        // The profiles that start above trace leave the summary out.
+        #[cfg(not(any(feature = "profile-production", feature = "profile-minimal")))]
+        {
+            let log = serial::take_capture();
+            assert!(log.contains(
+                "Inventory at load: 3 variables, 0 not kept, 3 non-volatile, 27 bytes, 2 hashed; complete"
+            ));
+            #[cfg(feature = "log-inventory")]
+            assert!(log.contains(&std::format!(
+                "I: {} Timeout Attributes=00000007 Size=00000007 CRC32={:08x}",
+                GuidFmt(&classify::GLOBAL_VARIABLE_GUID),
+                crc32::crc32(b"Timeout")
+            )));
+        }
+        // Not seen by the hooks.
+        let statistics = counters::statistics();
+        assert_eq!(statistics.get_variable.by_status.iter().sum::<u64>(), 0);
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        assert_eq!(
+            firmware.runtime_services.get_variable as usize,
+            fake_named_get_variable as GetVariableType as usize
+        );
+        level::reset();
+    }
+
+    #[cfg(not(any(feature = "profile-production", feature = "profile-minimal")))]
+    #[test]
+    fn the_store_is_compared_at_ready_to_boot() {
+        let _lock = mock::lock();
+        inventory::reset();
+        let mut firmware = mock::MockFirmware::new(fake_named_get_variable);
+        let global = classify::GLOBAL_VARIABLE_GUID;
+        mock::store::fill(&[(global, "BootOrder"), (global, "Lang"), (global, "Timeout")]);
+        level::set_level(level::Level::Trace);
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+        let set_variable = firmware.runtime_services.set_variable;
+        for name in ["Timeout", "BootNext"] {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = [0u8; 2];
+            set_variable(
+                name.as_mut_ptr(),
+                &mut global.clone(),
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            );
+        }
+        mock::take_writes();
+
+        // Timeout and BootNext were written through the hook, Lang and
+        // Boot0003 around it.
+        mock::store::fill(&[
+            (global, "Boot0003"),
+            (global, "BootNext"),
+            (global, "BootOrder"),
+        ]);
+        serial::start_capture();
+        inventory::compare();
+        let log = serial::take_capture();
+        let guid = std::format!("{}", GuidFmt(&global));
+        for line in [
+            std::format!("V: created {}:Boot0003 Size=00000008 (modified outside monitored path)", guid),
+            std::format!("V: created {}:BootNext Size=00000008\n", guid),
+            std::format!("V: deleted {}:Lang Size=00000004 (modified outside monitored path)", guid),
+            std::format!("V: deleted {}:Timeout Size=00000007\n", guid),
+            "Store diff at ReadyToBoot: 2 created, 2 deleted, 0 resized, 0 changed, 2 outside monitored path; complete".into(),
+        ] {
+            assert!(log.contains(&line), "{}", line);
+        }
+        // Once per boot.
+        inventory::compare();
+        assert_eq!(serial::take_capture(), "");
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        inventory::reset();
+        level::reset();
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn variables_new_since_load_are_tagged() {
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(fake_named_get_variable);
+        let global = classify::GLOBAL_VARIABLE_GUID;
+        mock::store::fill(&[(global, "BootOrder")]);
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+        level::set_level(level::Level::Trace);
+        assert_eq!(
+            filter::replace("8be4df61-93ca-11d2-aa0d-00e098032b8c:BootOrder"),
+            Ok(1)
+        );
+        let set_variable = firmware.runtime_services.set_variable;
+        let get_variable = firmware.runtime_services.get_variable;
+        let write = |name: &str| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = [0u8; 2];
+            set_variable(
+                name.as_mut_ptr(),
+                &mut global.clone(),
+                0x07,
+                data.len(),
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+        let read = |name: &str| {
+            let mut name: std::vec::Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let mut data = [0u8; 16];
+            let mut data_size = data.len();
+            get_variable(
+                name.as_mut_ptr(),
+                &mut global.clone(),
+                core::ptr::null_mut(),
+                &mut data_size,
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            )
+        };
+
+        // Only BootOrder is traced, but the first sight of a new variable is
+        // recorded all the same.
+        serial::start_capture();
+        for name in ["Foo", "Foo", "BootOrder"] {
+            assert_eq!(write(name), efi::Status::SUCCESS);
+        }
+        for name in ["Bar", "Bar", "Foo", "BootOrder"] {
+            assert_eq!(read(name), efi::Status::SUCCESS);
+        }
+        newcomer::end_of_dxe();
+        assert_eq!(write("Baz"), efi::Status::SUCCESS);
+        mock::take_writes();
+        BOOT_SERVICES_EXITED.store(true, Ordering::Release);
+        assert_eq!(write("Qux"), efi::Status::SUCCESS);
+        BOOT_SERVICES_EXITED.store(false, Ordering::Release);
+        let log = serial::take_capture();
+
+        let guid = std::format!("{}", GuidFmt(&global));
+        let tagged: std::vec::Vec<&str> =
+            log.lines().filter(|line| line.ends_with(" NEW")).collect();
+        assert_eq!(
+            tagged,
+            [
+                std::format!("S: {} Attributes=00000007 Size=00000002 Foo: 0x0 NEW", guid),
+                std::format!("G: {} Size=00000010->00000003 Bar: 0x0 NEW", guid),
+                std::format!("S: {} Attributes=00000007 Size=00000002 Baz: 0x0 NEW", guid),
+                std::format!("S: {} Attributes=00000007 Size=00000002 Qux: 0x0 NEW", guid),
+            ]
+        );
+        #[cfg(not(feature = "log-deferred"))]
+        assert!(log.contains(&std::format!(
+            "S: {} Attributes=00000007 Size=00000002 BootOrder: 0x0\n",
+            guid
+        )));
+        // Found at OS runtime, so the report was written again.
+        let writes = mock::take_writes();
+        assert!(writes.iter().any(|write| write.name == "UvmBootReport"));
+
+        let report = report::collect();
+        assert_eq!(report.new_counts, [2, 1, 1]);
+        let names: std::vec::Vec<&str> = report
+            .new_variables()
+            .iter()
+            .map(|variable| variable.name())
+            .collect();
+        assert_eq!(names, ["Foo", "Bar", "Baz", "Qux"]);
+        serial::start_capture();
+        newcomer::log_summary();
+        assert!(serial::take_capture().contains(
+            "New variables since load: 2 before EndOfDxe, 1 before ExitBootServices, 0 accesses to others not kept"
+        ));
+
+        assert_eq!(filter::replace(""), Ok(0));
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        level::reset();
+    }
+
+    #[test]
+    fn the_store_space_is_measured_at_load_and_exit() {
+        let _lock = mock::lock();
+        capacity::reset();
+        let mut firmware = mock::MockFirmware::new(fake_firmware);
+        serial::start_capture();
+        assert_eq!(
+            efi_main(mock::IMAGE_HANDLE, firmware.system_table()),
+            efi::Status::SUCCESS
+        );
+        let at_load = report::collect().store_at_load;
+        assert_eq!(
+            (at_load.attributes, at_load.remaining),
+            (mock::space::SUPPORTED, 0x7_0000)
+        );
+        assert!(!report::collect().store_at_exit.is_measured());
+
+        mock::space::set_remaining(0x7_0000 - 37 * 1024);
+        capacity::exit_boot_services();
+        let at_exit = report::collect().store_at_exit;
+        assert_eq!(
+            (
+                at_exit.maximum,
+                at_exit.remaining,
+                at_exit.max_variable_size
+            ),
+            (0x8_0000, 0x7_0000 - 37 * 1024, 0x8000)
+        );
+        // Less room than the largest variable needs.
+        mock::space::set_remaining(0x7_0000 + 0x1000);
+        capacity::exit_boot_services();
+        mock::space::set_remaining(0x4000);
+        capacity::exit_boot_services();
+        mock::space::set_answered(false);
+        capacity::exit_boot_services();
+        assert_eq!(report::collect().store_at_exit.remaining, 0x4000);
+
+        let log = serial::take_capture();
+        #[cfg(not(any(feature = "profile-production", feature = "profile-minimal")))]
+        for line in [
+            "NV store at load: 512KiB total, 448KiB remaining, 32KiB largest variable, attributes 0x7",
+            "NV store: 512KiB total, 37KiB consumed this boot, 411KiB remaining, 32KiB largest variable",
+            "NV store: 512KiB total, 4KiB freed this boot, 452KiB remaining, 32KiB largest variable",
+        ] {
+            assert!(log.contains(line), "{}", line);
+        }
+        #[cfg(not(feature = "profile-minimal"))]
+        for line in [
+            "NV store: 512KiB total, 432KiB consumed this boot, 16KiB remaining, 32KiB largest variable",
+            "NV store not measured at ExitBootServices : 0x8000000000000003",
+        ] {
+            assert!(log.contains(line), "{}", line);
+        }
+        let _ = log;
+
+        assert_eq!(handle_unload(mock::IMAGE_HANDLE), efi::Status::SUCCESS);
+        assert_released(&firmware);
+        capacity::reset();
+    }
+
+    // How deep the hooks go into their caller's stack (see footprint.rs).
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn hooks_stay_shallow() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        level::reset();
+        assert_eq!(filter::replace(""), Ok(0));
+        set_variable::reset();
+        set_variable::SET_VARIABLE.set(fake_accepting_set_variable);
+
+        serial::start_capture();
+        let read = footprint::high_water(|| assert_eq!(read_timeout(), efi::Status::SUCCESS));
+        let mut name = [b'd' as u16, b'b' as u16, 0];
+        let mut write = std::vec![0u8; 16];
+        write.extend_from_slice(&8u32.to_le_bytes());
+        write.extend_from_slice(&[0; 4]);
+        // One signature list holding one 16-byte signature.
+        write.extend_from_slice(classify::GLOBAL_VARIABLE_GUID.as_bytes());
+        write.extend_from_slice(&(28u32 + 32).to_le_bytes());
+        write.extend_from_slice(&0u32.to_le_bytes());
+        write.extend_from_slice(&32u32.to_le_bytes());
+        write.extend_from_slice(config::UVM_VENDOR_GUID.as_bytes());
+        write.extend_from_slice(&[0x33; 16]);
+        let append = footprint::high_water(|| {
+            set_variable::handle_set_variable(
+                name.as_mut_ptr(),
+                &mut classify::IMAGE_SECURITY_DATABASE_GUID.clone(),
+                0x67,
+                write.len(),
+                write.as_mut_ptr() as *mut core::ffi::c_void,
+            );
+        });
+        let records = serial::take_capture();
+        assert!(records.contains("G: ") && records.contains("APPEND_WRITE to db"));
+        assert!(
+            read < footprint::HOOK_STACK_LIMIT,
+            "GetVariable: {} bytes",
+            read
+        );
+        assert!(
+            append < footprint::HOOK_STACK_LIMIT,
+            "SetVariable: {} bytes",
+            append
+        );
+        set_variable::reset();
+        shadow::reset();
+    }
+
+    #[cfg(not(feature = "profile-minimal"))]
+    #[test]
+    fn quiet_reads_skip_the_unwatched_names() {
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        level::reset();
+        top::reset();
+        let vendor_guid = efi::Guid::from_fields(
+            0xc811fa38,
+            0x42c8,
+            0x4579,
+            0xa9,
+            0xbb,
+            &[0x60, 0xe9, 0x4e, 0xdd, 0xfb, 0x34],
+        );
+        let mut name: std::vec::Vec<u16> = "Setup".encode_utf16().chain([0]).collect();
+        let mut data = [0u8; 4];
+        let mut data_size = data.len();
+
+        level::set_level(level::Level::Critical);
+        assert_eq!(read_timeout(), efi::Status::SUCCESS);
+        assert_eq!(
+            handle_get_variable(
+                name.as_mut_ptr(),
+                &mut vendor_guid.clone(),
+                core::ptr::null_mut(),
+                &mut data_size,
+                data.as_mut_ptr() as *mut core::ffi::c_void,
+            ),
+            efi::Status::SUCCESS
+        );
+
+        // Timeout is under the global GUID, which the critical checks watch;
+        // Setup is counted under an empty name.
+        let mut entries = [uvm_interface::protocol::TopEntry::EMPTY; 2];
+        assert_eq!(top::top(&mut entries), 2);
+        let mut names = entries
+            .iter()
+            .map(|entry| (entry.guid, &entry.name[..entry.name_length as usize]))
+            .collect::<std::vec::Vec<_>>();
+        names.sort_by_key(|(_, name)| name.len());
+        assert_eq!(
+            names,
+            [
+                (vendor_guid, &b""[..]),
+                (classify::GLOBAL_VARIABLE_GUID, &b"Timeout"[..])
+            ]
+        );
+        assert_eq!(top::totals().0, 2);
+        level::reset();
+        top::reset();
+    }
+
+    // The cost of a read whose record is dropped, next to the call it wraps,
+    // for a variable under the global GUID, which the critical checks watch,
+    // and for one under a vendor GUID, which they do not:
+    //
+    //   cargo test --release -- --ignored --nocapture suppressed_read_overhead
+    #[test]
+    #[ignore]
+    fn suppressed_read_overhead() {
+        const CALLS: u32 = 1_000_000;
+        let _lock = mock::lock();
+        reset_hook(fake_firmware);
+        level::reset();
+        let vendor_guid = efi::Guid::from_fields(
+            0xc811fa38,
+            0x42c8,
+            0x4579,
+            0xa9,
+            0xbb,
+            &[0x60, 0xe9, 0x4e, 0xdd, 0xfb, 0x34],
+        );
+
+        let time = |call: &dyn Fn(GetVariableType) -> efi::Status, service| {
+            let start = std::time::Instant::now();
+            for _ in 0..CALLS {
+                std::hint::black_box(call(service));
+            }
+            start.elapsed().as_nanos() as f64 / CALLS as f64
+        };
+        for (variable, guid) in [
+            ("Timeout", classify::GLOBAL_VARIABLE_GUID),
+            ("Setup", vendor_guid),
+        ] {
+            let read = |service: GetVariableType| {
+                let mut name: std::vec::Vec<u16> = variable.encode_utf16().chain([0]).collect();
+                let mut data = [0u8; 4];
+                let mut data_size = data.len();
+                service(
+                    name.as_mut_ptr(),
+                    &mut guid.clone(),
+                    core::ptr::null_mut(),
+                    &mut data_size,
+                    data.as_mut_ptr() as *mut core::ffi::c_void,
+                )
+            };
+            let firmware = time(&read, fake_firmware);
+            for (setting, level, paused) in [
+                ("warning", level::Level::Warning, false),
+                ("critical", level::Level::Critical, false),
+                ("paused", level::Level::Trace, true),
+            ] {
+                level::set_level(level);
+                level::set_paused(paused);
+                let hooked = time(&read, handle_get_variable);
+                std::println!(
+                    "GetVariable of {} at {}: {:.1} ns direct, {:.1} ns hooked, {:.1} ns added",
+                    variable,
+                    setting,
+                    firmware,
+                    hooked,
+                    hooked - firmware
+                );
+            }
+        }
+        level::reset();
+    }
+}
+
+// In release builds without allow-panic, the handler ends in a call to a
+// symbol nothing defines, so the driver only links if the optimizer removed
+// every path to it (see tools/no-panic-check.sh). The report below is then
+// only linked in builds that are not checked. What keeps the paths clear:
+// slices are taken with get() rather than indexed, tables are locked with
+// try_borrow_mut() only, as a shared borrow panics when its count overflows
+// (runtime services are not reentrant, so readers never had to share), and
+// strings are split with closures. Firmware halts on a panic all the same.
+// With the mock feature, which only the host tests turn on, the library is
+// built with std, which brings its own handler.
+#[cfg(not(any(test, feature = "mock")))]
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    #[cfg(not(feature = "log-panic"))]
+    let _ = info;
+
+    #[cfg(feature = "log-ring")]
+    ring::mark_panicked();
+
+    #[cfg(feature = "log-panic")]
+    {
+        use core::fmt::Write;
+
+        let mut serial = serial::PanicSerial;
+        serial.write_bytes(b"\r\nPANIC");
+        if let Some(location) = info.location() {
+            serial.write_bytes(b" at ");
+            serial.write_bytes(location.file().as_bytes());
+            serial.write_bytes(b":");
+            serial.write_decimal(location.line());
+        }
+        serial.write_bytes(b": ");
+        let _ = write!(serial, "{}", info.message());
+        serial.write_bytes(b"\r\n");
+    }
+
+    #[cfg(any(debug_assertions, feature = "allow-panic"))]
+    arch::Current::halt();
+    #[cfg(not(any(debug_assertions, feature = "allow-panic")))]
+    {
+        extern "C" {
+            fn uvm_panic_is_reachable() -> !;
+        }
+        unsafe { uvm_panic_is_reachable() }
+    }
 }
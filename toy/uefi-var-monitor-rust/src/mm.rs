@@ -0,0 +1,349 @@
+// uefi-var-monitor-rust/src/mm.rs
+//
+// The DXE half of the pairing with the monitor's Standalone MM module (see
+// mm/), only built with the `mm-events` feature. Writes the OS sends straight
+// to an MM variable store never reach our hooks; that module sees them. They
+// are read from it through the MM Communication protocol at load and at
+// ReadyToBoot, and relayed into the log, one record each:
+//
+//   M: <guid> Attributes=<attributes> Size=<size> <name>: <status> (<source>)
+//
+// status being "?" for requests seen before the variable driver handled
+// them. Records go through the trace filter and to every sink, like the
+// accesses the hooks see. On such platforms, writes through the runtime
+// services reach MM too and show up twice, as an S: then an M: record; an M:
+// record alone is a write that bypassed the services table. Events the MM
+// module overwrote before they were read are counted in a record of their
+// own.
+//
+// Messages are those of interface/src/mm.rs, built in a buffer in the
+// driver's image, which the MM core copies in and out of MMRAM. Nothing is
+// read after ReadyToBoot: MM Communicate is a boot service here, and the MM
+// module keeps what it sees afterwards in MMRAM.
+
+use crate::filter;
+use crate::tag::{self, Tag};
+use crate::GuidFmt;
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+use uvm_interface::mm::{
+    MmEvent, MmInfo, MmRead, MmRequest, MM_EVENT_SIZE, MM_FUNCTION_INFO, MM_FUNCTION_READ,
+    MM_MESSAGE_SIZE, MM_READ_MAX, MM_SOURCE_COMMUNICATE, MM_SOURCE_PROTOCOL, MM_STATUS_UNKNOWN,
+    MM_VERSION, UVM_MM_COMMUNICATE_GUID,
+};
+
+const TAG: Tag = tag::MM;
+
+// {c68ed8e2-9dc6-4cbd-9d94-db65acc5c332}
+const MM_COMMUNICATION_PROTOCOL_GUID: efi::Guid = efi::Guid::from_fields(
+    0xc68ed8e2,
+    0x9dc6,
+    0x4cbd,
+    0x9d,
+    0x94,
+    &[0xdb, 0x65, 0xac, 0xc5, 0xc3, 0x32],
+);
+
+type CommunicateType = efiapi! {fn(
+    *mut MmCommunicationProtocol,
+    *mut core::ffi::c_void,
+    *mut usize,
+) -> efi::Status};
+
+#[repr(C)]
+pub struct MmCommunicationProtocol {
+    pub communicate: CommunicateType,
+}
+
+// EFI_MM_COMMUNICATE_HEADER, followed by the message.
+#[repr(C)]
+struct CommunicateHeader {
+    header_guid: efi::Guid,
+    message_length: usize,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<CommunicateHeader>();
+const REQUEST_SIZE: usize = core::mem::size_of::<MmRequest>();
+
+#[repr(C, align(8))]
+struct Buffer([u8; HEADER_SIZE + MM_MESSAGE_SIZE]);
+
+static BUFFER: AtomicRefCell<Buffer> =
+    AtomicRefCell::new(Buffer([0; HEADER_SIZE + MM_MESSAGE_SIZE]));
+// Sequence number of the next event to read.
+static NEXT_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Sends a message with `payload` and returns the payload of the reply.
+ */
+fn send<'a>(
+    protocol: *mut MmCommunicationProtocol,
+    buffer: &'a mut Buffer,
+    function: u32,
+    payload: &[u8],
+) -> Result<&'a [u8], efi::Status> {
+    let request = MmRequest {
+        function,
+        version: MM_VERSION,
+        status: 0,
+    };
+    let header = CommunicateHeader {
+        header_guid: UVM_MM_COMMUNICATE_GUID,
+        message_length: MM_MESSAGE_SIZE,
+    };
+    buffer.0 = [0; HEADER_SIZE + MM_MESSAGE_SIZE];
+    unsafe { (buffer.0.as_mut_ptr() as *mut CommunicateHeader).write(header) };
+    let message = &mut buffer.0[HEADER_SIZE..];
+    message[..REQUEST_SIZE].copy_from_slice(request.as_bytes());
+    message
+        .get_mut(REQUEST_SIZE..REQUEST_SIZE + payload.len())
+        .ok_or(efi::Status::BAD_BUFFER_SIZE)?
+        .copy_from_slice(payload);
+
+    let mut size = buffer.0.len();
+    let efi_status = (unsafe { &*protocol }.communicate)(
+        protocol,
+        buffer.0.as_mut_ptr() as *mut core::ffi::c_void,
+        &mut size,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    let message = &buffer.0[HEADER_SIZE..];
+    let reply = MmRequest::parse(message).map_err(|_| efi::Status::PROTOCOL_ERROR)?;
+    let efi_status = efi::Status::from_usize(reply.status as usize);
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(&message[REQUEST_SIZE..])
+}
+
+struct Status(u64);
+
+impl core::fmt::Display for Status {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            MM_STATUS_UNKNOWN => f.write_str("?"),
+            status => write!(f, "{:#x}", status),
+        }
+    }
+}
+
+fn source(event: &MmEvent) -> &'static str {
+    match event.source {
+        MM_SOURCE_COMMUNICATE => "SMI",
+        MM_SOURCE_PROTOCOL => "MM",
+        _ => "?",
+    }
+}
+
+fn relay(event: &MmEvent) {
+    let name = event.name();
+    if filter::is_traced(name, &event.guid) {
+        log_record!(
+            "M: {} Attributes={:08x} Size={:08x} {}: {} ({})",
+            GuidFmt(&event.guid),
+            event.attributes,
+            event.data_size,
+            name,
+            Status(event.status),
+            source(event),
+        );
+    }
+}
+
+/**
+ * @brief Reads the events not read yet and relays them. Returns how many
+ *        there were.
+ */
+pub fn drain(protocol: *mut MmCommunicationProtocol) -> Result<u64, efi::Status> {
+    let mut buffer = BUFFER
+        .try_borrow_mut()
+        .map_err(|_| efi::Status::NOT_READY)?;
+    let mut relayed = 0;
+    loop {
+        let first = NEXT_SEQUENCE.load(Ordering::Acquire);
+        let request = MmRead {
+            first_sequence: first,
+            ..MmRead::default()
+        };
+        let reply = send(protocol, &mut buffer, MM_FUNCTION_READ, request.as_bytes())?;
+        let read = MmRead::parse(reply).map_err(|_| efi::Status::PROTOCOL_ERROR)?;
+        let count = core::cmp::min(read.count as usize, MM_READ_MAX);
+        if read.first_sequence > first {
+            warn!(TAG, "MM events lost: {}", read.first_sequence - first);
+        }
+        let events = reply.get(core::mem::size_of::<MmRead>()..).unwrap_or(&[]);
+        for bytes in events.chunks_exact(MM_EVENT_SIZE).take(count) {
+            if let Ok(event) = MmEvent::parse(bytes) {
+                relay(&event);
+            }
+        }
+        relayed += count as u64;
+        NEXT_SEQUENCE.store(read.first_sequence + count as u64, Ordering::Release);
+        if count < MM_READ_MAX {
+            return Ok(relayed);
+        }
+    }
+}
+
+/**
+ * @brief Asks the MM module how it watches writes.
+ */
+fn info(protocol: *mut MmCommunicationProtocol) -> Result<MmInfo, efi::Status> {
+    let mut buffer = BUFFER
+        .try_borrow_mut()
+        .map_err(|_| efi::Status::NOT_READY)?;
+    let reply = send(protocol, &mut buffer, MM_FUNCTION_INFO, &[])?;
+    MmInfo::parse(reply).map_err(|_| efi::Status::PROTOCOL_ERROR)
+}
+
+/**
+ * @brief Reads what the MM module saw so far and registers the ReadyToBoot
+ *        notification reading the rest. Returns the event for the caller to
+ *        record in the teardown list; NOT_FOUND without an MM module.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> Result<r_efi::base::Event, efi::Status> {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.locate_protocol)(
+        &MM_COMMUNICATION_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut interface,
+    );
+    if efi_status.is_error() {
+        info!(TAG, "No MM Communication protocol, MM events not read");
+        return Err(efi::Status::NOT_FOUND);
+    }
+    let protocol = interface as *mut MmCommunicationProtocol;
+    match info(protocol) {
+        Ok(info) => info!(
+            TAG,
+            "MM module: handler {}, protocol hook {}, {} events seen",
+            info.handler_registered != 0,
+            info.protocol_hooked != 0,
+            info.next_sequence
+        ),
+        Err(efi_status) => {
+            warn!(TAG, "No MM module answered : {:#x}", efi_status.as_usize());
+            return Err(efi::Status::NOT_FOUND);
+        }
+    }
+    if let Err(efi_status) = drain(protocol) {
+        error!(TAG, "MM events not read : {:#x}", efi_status.as_usize());
+    }
+
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_ready_to_boot,
+        protocol as *mut core::ffi::c_void,
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(event)
+}
+
+efiapi! {
+    /**
+     * @brief Reads the events seen since load before control goes to a boot
+     *        option.
+     */
+    fn handle_ready_to_boot(_event: r_efi::base::Event, context: *mut core::ffi::c_void) {
+        if let Err(efi_status) = drain(context as *mut MmCommunicationProtocol) {
+            error!(TAG, "MM events not read : {:#x}", efi_status.as_usize());
+        }
+    }
+}
+
+#[cfg(test)]
+pub fn reset() {
+    NEXT_SEQUENCE.store(0, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::IMAGE_SECURITY_DATABASE_GUID;
+    use crate::{mock, serial};
+
+    // Sequence number of the first event the fake MM module still holds, and
+    // of the next one.
+    static HELD: (AtomicU64, AtomicU64) = (AtomicU64::new(0), AtomicU64::new(0));
+
+    efiapi! {
+        // An MM module that saw HELD events, all writes to db.
+        fn fake_communicate(
+            _this: *mut MmCommunicationProtocol,
+            comm_buffer: *mut core::ffi::c_void,
+            comm_size: *mut usize,
+        ) -> efi::Status {
+            let buffer =
+                unsafe { core::slice::from_raw_parts_mut(comm_buffer as *mut u8, *comm_size) };
+            let header = unsafe { &*(comm_buffer as *const CommunicateHeader) };
+            assert_eq!(header.header_guid, UVM_MM_COMMUNICATE_GUID);
+            let message = &mut buffer[HEADER_SIZE..];
+            let request = MmRequest::parse(message).unwrap();
+            assert_eq!(request.function, MM_FUNCTION_READ);
+            let read = MmRead::parse(&message[REQUEST_SIZE..]).unwrap();
+            let first = core::cmp::max(read.first_sequence, HELD.0.load(Ordering::Acquire));
+            let count = core::cmp::min(
+                HELD.1.load(Ordering::Acquire).saturating_sub(first),
+                MM_READ_MAX as u64,
+            );
+            let reply = MmRead {
+                first_sequence: first,
+                count: count as u32,
+                reserved: 0,
+            };
+            let mut offset = REQUEST_SIZE;
+            message[offset..offset + 16].copy_from_slice(reply.as_bytes());
+            offset += 16;
+            for sequence in first..first + count {
+                let mut event = MmEvent::EMPTY;
+                event.sequence = sequence;
+                event.guid = IMAGE_SECURITY_DATABASE_GUID;
+                event.attributes = 0x27;
+                event.source = MM_SOURCE_COMMUNICATE;
+                event.status = MM_STATUS_UNKNOWN;
+                event.name[..2].copy_from_slice(b"db");
+                event.name_length = 2;
+                message[offset..offset + MM_EVENT_SIZE].copy_from_slice(event.as_bytes());
+                offset += MM_EVENT_SIZE;
+            }
+            efi::Status::SUCCESS
+        }
+    }
+
+    #[test]
+    fn events_are_relayed_once_and_losses_counted() {
+        let _lock = mock::lock();
+        reset();
+        let mut protocol = MmCommunicationProtocol {
+            communicate: fake_communicate,
+        };
+        HELD.0.store(0, Ordering::Release);
+        HELD.1.store(MM_READ_MAX as u64 + 2, Ordering::Release);
+
+        serial::start_capture();
+        assert_eq!(drain(&mut protocol), Ok(MM_READ_MAX as u64 + 2));
+        assert_eq!(drain(&mut protocol), Ok(0));
+        // Three more seen, of which the first was overwritten.
+        HELD.0.store(MM_READ_MAX as u64 + 3, Ordering::Release);
+        HELD.1.store(MM_READ_MAX as u64 + 5, Ordering::Release);
+        assert_eq!(drain(&mut protocol), Ok(2));
+        let records = serial::take_capture();
+
+        let line = std::format!(
+            "M: {} Attributes=00000027 Size=00000000 db: ? (SMI)",
+            GuidFmt(&IMAGE_SECURITY_DATABASE_GUID)
+        );
+        assert_eq!(records.matches(&line).count(), MM_READ_MAX + 4);
+        assert!(records.contains("MM events lost: 1"));
+        reset();
+    }
+}
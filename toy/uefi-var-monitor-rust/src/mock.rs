@@ -0,0 +1,804 @@
+// uefi-var-monitor-rust/src/mock.rs
+//
+// Mock system table for host tests. Only the boot services the driver uses
+// are implemented; they track the events and protocols the driver holds, and
+// can be made to fail at a chosen step. ConIn has Simple Text Input Ex, whose
+// key notifications keys::press() calls. GetVariable may be given by the test,
+// or be variables::get_variable over an in-memory store. SetVariable accepts
+// every write and records it for the test to check; QueryVariableInfo answers
+// for the attributes of space::SUPPORTED alone. Calling any other service
+// aborts the test.
+//
+// The mock is part of the library, built for its tests and, with the mock
+// feature, for the driver's, which drive the hooks through it (see lib.rs
+// and Cargo.toml).
+//
+// The driver keeps its state in globals, so tests using the mock must hold the
+// lock returned by lock() for their whole duration.
+
+// The services take pointers as firmware's do, and are called through the
+// tables as those are.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use crate::GetVariableType;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use r_efi::efi;
+use r_efi::protocols::{loaded_image, simple_text_input_ex};
+use std::sync::{Mutex, MutexGuard};
+
+const IMAGE_HANDLE_ADDRESS: usize = 0x1000;
+pub const IMAGE_HANDLE: efi::Handle = IMAGE_HANDLE_ADDRESS as efi::Handle;
+// What ConvertPointer adds to an address.
+pub const VIRTUAL_OFFSET: usize = 0x1000_0000;
+
+static LOCK: Mutex<()> = Mutex::new(());
+static STEPS: AtomicUsize = AtomicUsize::new(0);
+static FAIL_AT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static FAILED: AtomicBool = AtomicBool::new(false);
+static OPEN_EVENTS: AtomicUsize = AtomicUsize::new(0);
+static NEXT_EVENT: AtomicUsize = AtomicUsize::new(0x2000);
+// Protocols installed on the image handle.
+static PROTOCOLS_INSTALLED: AtomicUsize = AtomicUsize::new(0);
+static TPL: AtomicUsize = AtomicUsize::new(efi::TPL_APPLICATION);
+static TPL_RAISES: AtomicUsize = AtomicUsize::new(0);
+// Addresses ConvertPointer fails for.
+static UNCONVERTIBLE: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+static LOADED_IMAGE: AtomicPtr<loaded_image::Protocol> = AtomicPtr::new(core::ptr::null_mut());
+static WRITES: Mutex<Vec<Write>> = Mutex::new(Vec::new());
+
+// A write that reached the mock's SetVariable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Write {
+    pub name: String,
+    pub guid: efi::Guid,
+    pub attributes: u32,
+    pub data: Vec<u8>,
+}
+
+/**
+ * @brief Serializes the tests that use the driver's globals, and resets the
+ *        mock's bookkeeping. The driver's lock forgets the store at load
+ *        too, so that which variables are new does not depend on the test
+ *        run before (see main.rs).
+ */
+pub fn lock() -> MutexGuard<'static, ()> {
+    let guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    fail_at(None);
+    OPEN_EVENTS.store(0, Ordering::SeqCst);
+    PROTOCOLS_INSTALLED.store(0, Ordering::SeqCst);
+    TPL.store(efi::TPL_APPLICATION, Ordering::SeqCst);
+    TPL_RAISES.store(0, Ordering::SeqCst);
+    unconvertible().clear();
+    take_writes();
+    guard
+}
+
+/**
+ * @brief Makes the fallible boot service call number `step`, counted from
+ *        now, fail. None disables failure injection.
+ */
+pub fn fail_at(step: Option<usize>) {
+    STEPS.store(0, Ordering::SeqCst);
+    FAILED.store(false, Ordering::SeqCst);
+    FAIL_AT.store(step.unwrap_or(usize::MAX), Ordering::SeqCst);
+}
+
+/**
+ * @brief Returns whether a failure was injected since the last fail_at().
+ */
+pub fn failure_injected() -> bool {
+    FAILED.load(Ordering::SeqCst)
+}
+
+pub fn open_events() -> usize {
+    OPEN_EVENTS.load(Ordering::SeqCst)
+}
+
+pub fn protocol_installed() -> bool {
+    PROTOCOLS_INSTALLED.load(Ordering::SeqCst) != 0
+}
+
+/**
+ * @brief Returns the writes recorded by the mock's SetVariable since the last
+ *        call, and forgets them.
+ */
+pub fn take_writes() -> Vec<Write> {
+    let mut writes = WRITES
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    core::mem::take(&mut *writes)
+}
+
+fn unconvertible() -> MutexGuard<'static, Vec<usize>> {
+    UNCONVERTIBLE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/**
+ * @brief Makes ConvertPointer fail with NOT_FOUND for `address`.
+ */
+pub fn refuse_conversion(address: usize) {
+    unconvertible().push(address);
+}
+
+pub fn tpl() -> efi::Tpl {
+    TPL.load(Ordering::SeqCst)
+}
+
+pub fn tpl_raises() -> usize {
+    TPL_RAISES.load(Ordering::SeqCst)
+}
+
+fn step_fails() -> bool {
+    if STEPS.fetch_add(1, Ordering::SeqCst) == FAIL_AT.load(Ordering::SeqCst) {
+        FAILED.store(true, Ordering::SeqCst);
+        return true;
+    }
+    false
+}
+
+efiapi! {
+    fn unexpected_call() {
+        panic!("unexpected firmware service call");
+    }
+}
+
+/**
+ * @brief Returns a table whose every function pointer is unexpected_call.
+ */
+fn table_of_unexpected<T>() -> T {
+    let mut table = core::mem::MaybeUninit::<T>::uninit();
+    let words = table.as_mut_ptr() as *mut usize;
+    for index in 0..core::mem::size_of::<T>() / core::mem::size_of::<usize>() {
+        unsafe {
+            words
+                .add(index)
+                .write(unexpected_call as efiapi! {fn()} as usize)
+        };
+    }
+    unsafe { table.assume_init() }
+}
+
+fn table_header(signature: u64, header_size: usize) -> efi::TableHeader {
+    efi::TableHeader {
+        signature,
+        revision: efi::SYSTEM_TABLE_REVISION_2_70,
+        header_size: header_size as u32,
+        crc32: 0,
+        reserved: 0,
+    }
+}
+
+efiapi! {
+    fn raise_tpl(new_tpl: efi::Tpl) -> efi::Tpl {
+        TPL_RAISES.fetch_add(1, Ordering::SeqCst);
+        TPL.swap(new_tpl, Ordering::SeqCst)
+    }
+}
+
+efiapi! {
+    fn restore_tpl(old_tpl: efi::Tpl) {
+        TPL.store(old_tpl, Ordering::SeqCst);
+    }
+}
+
+fn new_event(event: *mut r_efi::base::Event) -> efi::Status {
+    if step_fails() {
+        return efi::Status::OUT_OF_RESOURCES;
+    }
+    OPEN_EVENTS.fetch_add(1, Ordering::SeqCst);
+    unsafe { *event = NEXT_EVENT.fetch_add(1, Ordering::SeqCst) as r_efi::base::Event };
+    efi::Status::SUCCESS
+}
+
+efiapi! {
+    fn create_event(
+        _type: u32,
+        _notify_tpl: efi::Tpl,
+        _notify_function: efi::EventNotify,
+        _notify_context: *mut core::ffi::c_void,
+        event: *mut r_efi::base::Event,
+    ) -> efi::Status {
+        new_event(event)
+    }
+}
+
+efiapi! {
+    fn create_event_ex(
+        _type: u32,
+        _notify_tpl: efi::Tpl,
+        _notify_function: efi::EventNotify,
+        _notify_context: *const core::ffi::c_void,
+        _event_group: *const efi::Guid,
+        event: *mut r_efi::base::Event,
+    ) -> efi::Status {
+        new_event(event)
+    }
+}
+
+efiapi! {
+    fn set_timer(
+        _event: r_efi::base::Event,
+        _type: efi::TimerDelay,
+        _trigger_time: u64,
+    ) -> efi::Status {
+        if step_fails() {
+            return efi::Status::DEVICE_ERROR;
+        }
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn close_event(_event: r_efi::base::Event) -> efi::Status {
+        assert!(OPEN_EVENTS.fetch_sub(1, Ordering::SeqCst) > 0);
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn install_protocol_interface(
+        _handle: *mut efi::Handle,
+        _protocol: *mut efi::Guid,
+        _interface_type: efi::InterfaceType,
+        _interface: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        if step_fails() {
+            return efi::Status::OUT_OF_RESOURCES;
+        }
+        PROTOCOLS_INSTALLED.fetch_add(1, Ordering::SeqCst);
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn uninstall_protocol_interface(
+        _handle: efi::Handle,
+        _protocol: *mut efi::Guid,
+        _interface: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        assert!(PROTOCOLS_INSTALLED.fetch_sub(1, Ordering::SeqCst) != 0);
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn handle_protocol(
+        _handle: efi::Handle,
+        protocol: *mut efi::Guid,
+        interface: *mut *mut core::ffi::c_void,
+    ) -> efi::Status {
+        if step_fails() {
+            return efi::Status::UNSUPPORTED;
+        }
+        let found = if unsafe { *protocol } == simple_text_input_ex::PROTOCOL_GUID {
+            keys::TEXT_INPUT_EX.load(Ordering::SeqCst) as *mut core::ffi::c_void
+        } else {
+            LOADED_IMAGE.load(Ordering::SeqCst) as *mut core::ffi::c_void
+        };
+        unsafe { *interface = found };
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn locate_protocol(
+        _protocol: *mut efi::Guid,
+        _registration: *mut core::ffi::c_void,
+        interface: *mut *mut core::ffi::c_void,
+    ) -> efi::Status {
+        if PROTOCOLS_INSTALLED.load(Ordering::SeqCst) == 0 {
+            return efi::Status::NOT_FOUND;
+        }
+        unsafe { *interface = IMAGE_HANDLE };
+        efi::Status::SUCCESS
+    }
+}
+
+// The handles LocateHandleBuffer returns; only the image's.
+static HANDLES: [usize; 1] = [IMAGE_HANDLE_ADDRESS];
+
+efiapi! {
+    fn locate_handle_buffer(
+        _search_type: efi::LocateSearchType,
+        _protocol: *mut efi::Guid,
+        _search_key: *mut core::ffi::c_void,
+        no_handles: *mut usize,
+        buffer: *mut *mut efi::Handle,
+    ) -> efi::Status {
+        unsafe {
+            *no_handles = HANDLES.len();
+            *buffer = HANDLES.as_ptr() as *mut efi::Handle;
+        }
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn free_pool(buffer: *mut core::ffi::c_void) -> efi::Status {
+        assert_eq!(buffer as *const usize, HANDLES.as_ptr());
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn calculate_crc32(
+        _data: *mut core::ffi::c_void,
+        _data_size: usize,
+        crc32: *mut u32,
+    ) -> efi::Status {
+        if step_fails() {
+            return efi::Status::DEVICE_ERROR;
+        }
+        unsafe { *crc32 = 0 };
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn stall(microseconds: usize) -> efi::Status {
+        std::thread::sleep(std::time::Duration::from_micros(microseconds as u64));
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn convert_pointer(
+        _debug_disposition: usize,
+        address: *mut *mut core::ffi::c_void,
+    ) -> efi::Status {
+        let physical = unsafe { *address } as usize;
+        if unconvertible().contains(&physical) {
+            return efi::Status::NOT_FOUND;
+        }
+        unsafe { *address = (physical + VIRTUAL_OFFSET) as *mut core::ffi::c_void };
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    pub fn set_variable(
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        let name = (0..)
+            .map(|index| unsafe { *variable_name.add(index) })
+            .take_while(|c16| *c16 != 0)
+            .collect::<Vec<u16>>();
+        let data = match data_size {
+            0 => Vec::new(),
+            _ => unsafe { core::slice::from_raw_parts(data as *const u8, data_size) }.to_vec(),
+        };
+        WRITES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(Write {
+                name: String::from_utf16_lossy(&name),
+                guid: unsafe { *vendor_guid },
+                attributes,
+                data,
+            });
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    fn unload(_image_handle: efi::Handle) -> efi::Status {
+        efi::Status::UNSUPPORTED
+    }
+}
+
+/**
+ * @brief A variable store holding `names` in enumeration order, empty in
+ *        each new MockFirmware. Its GetNextVariableName follows the UEFI
+ *        specification, and counts its calls.
+ */
+pub mod store {
+    use r_efi::efi;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static NAMES: Mutex<Vec<(efi::Guid, &'static str)>> = Mutex::new(Vec::new());
+    static CALLS: Mutex<usize> = Mutex::new(0);
+
+    pub fn fill(names: &[(efi::Guid, &'static str)]) {
+        *NAMES.lock().unwrap() = names.to_vec();
+        *CALLS.lock().unwrap() = 0;
+    }
+
+    #[cfg(feature = "enforce")]
+    pub fn calls() -> usize {
+        *CALLS.lock().unwrap()
+    }
+
+    efiapi! {
+        pub fn get_next_variable_name(
+            variable_name_size: *mut usize,
+            variable_name: *mut r_efi::base::Char16,
+            vendor_guid: *mut r_efi::base::Guid,
+        ) -> efi::Status {
+            *CALLS.lock().unwrap() += 1;
+            let names = NAMES.lock().unwrap();
+            let mut input = Vec::new();
+            let mut index = 0;
+            loop {
+                let c = unsafe { *variable_name.add(index) };
+                if c == 0 {
+                    break;
+                }
+                input.push(c);
+                index += 1;
+            }
+            let input = std::string::String::from_utf16(&input).unwrap();
+            let next = if input.is_empty() {
+                0
+            } else {
+                let guid = unsafe { *vendor_guid };
+                match names.iter().position(|entry| *entry == (guid, input.as_str())) {
+                    Some(position) => position + 1,
+                    None => return efi::Status::INVALID_PARAMETER,
+                }
+            };
+            let (guid, name) = match names.get(next) {
+                Some(entry) => *entry,
+                None => return efi::Status::NOT_FOUND,
+            };
+            let name: Vec<u16> = name.encode_utf16().chain([0]).collect();
+            let needed = 2 * name.len();
+            if unsafe { *variable_name_size } < needed {
+                unsafe { *variable_name_size = needed };
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            for (index, c) in name.iter().enumerate() {
+                unsafe { *variable_name.add(index) = *c };
+            }
+            unsafe {
+                *variable_name_size = needed;
+                *vendor_guid = guid;
+            }
+            efi::Status::SUCCESS
+        }
+    }
+}
+
+/**
+ * @brief An in-memory variable store, empty in each new MockFirmware. Its
+ *        GetVariable follows the UEFI specification: INVALID_PARAMETER for a
+ *        null name, GUID or DataSize, or a null Data to be written to,
+ *        NOT_FOUND, and BUFFER_TOO_SMALL with the size needed. It counts its
+ *        calls, so that a test can tell how many reached the firmware.
+ */
+pub mod variables {
+    use r_efi::efi;
+    use std::string::String;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    struct Variable {
+        name: String,
+        guid: efi::Guid,
+        attributes: u32,
+        data: Vec<u8>,
+    }
+
+    static VARIABLES: Mutex<Vec<Variable>> = Mutex::new(Vec::new());
+    static CALLS: Mutex<usize> = Mutex::new(0);
+
+    pub fn clear() {
+        VARIABLES.lock().unwrap().clear();
+        *CALLS.lock().unwrap() = 0;
+    }
+
+    /**
+     * @brief Creates a variable, or replaces the one of that name and GUID.
+     */
+    pub fn put(name: &str, guid: efi::Guid, attributes: u32, data: &[u8]) {
+        let mut variables = VARIABLES.lock().unwrap();
+        variables.retain(|variable| (variable.name.as_str(), variable.guid) != (name, guid));
+        variables.push(Variable {
+            name: String::from(name),
+            guid,
+            attributes,
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn calls() -> usize {
+        *CALLS.lock().unwrap()
+    }
+
+    efiapi! {
+        pub fn get_variable(
+            variable_name: *mut r_efi::base::Char16,
+            vendor_guid: *mut r_efi::base::Guid,
+            attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            *CALLS.lock().unwrap() += 1;
+            if variable_name.is_null() || vendor_guid.is_null() || data_size.is_null() {
+                return efi::Status::INVALID_PARAMETER;
+            }
+            let name = (0..)
+                .map(|index| unsafe { *variable_name.add(index) })
+                .take_while(|c16| *c16 != 0)
+                .collect::<Vec<u16>>();
+            let name = String::from_utf16_lossy(&name);
+            let guid = unsafe { *vendor_guid };
+            let variables = VARIABLES.lock().unwrap();
+            let variable = match variables
+                .iter()
+                .find(|variable| (variable.name.as_str(), variable.guid) == (name.as_str(), guid))
+            {
+                Some(variable) => variable,
+                None => return efi::Status::NOT_FOUND,
+            };
+            if unsafe { *data_size } < variable.data.len() {
+                unsafe { *data_size = variable.data.len() };
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            if !variable.data.is_empty() {
+                if data.is_null() {
+                    return efi::Status::INVALID_PARAMETER;
+                }
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        variable.data.as_ptr(),
+                        data as *mut u8,
+                        variable.data.len(),
+                    )
+                };
+            }
+            unsafe {
+                *data_size = variable.data.len();
+                if !attributes.is_null() {
+                    *attributes = variable.attributes;
+                }
+            }
+            efi::Status::SUCCESS
+        }
+    }
+}
+
+/**
+ * @brief The space QueryVariableInfo reports, reset in each new MockFirmware:
+ *        a store of 512 KiB with `remaining` left, answered for SUPPORTED
+ *        alone and UNSUPPORTED for any other attributes.
+ */
+pub mod space {
+    use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+    use r_efi::efi;
+
+    pub const MAXIMUM: u64 = 0x8_0000;
+    pub const MAX_VARIABLE_SIZE: u64 = 0x8000;
+    pub const SUPPORTED: u32 = efi::VARIABLE_NON_VOLATILE
+        | efi::VARIABLE_BOOTSERVICE_ACCESS
+        | efi::VARIABLE_RUNTIME_ACCESS;
+
+    static REMAINING: AtomicU64 = AtomicU64::new(MAXIMUM);
+    static ANSWERED: AtomicU32 = AtomicU32::new(SUPPORTED);
+
+    pub fn set_remaining(remaining: u64) {
+        REMAINING.store(remaining, Ordering::SeqCst);
+    }
+
+    /**
+     * @brief Makes every QueryVariableInfo call fail if `answered` is false.
+     */
+    pub fn set_answered(answered: bool) {
+        ANSWERED.store(if answered { SUPPORTED } else { 0 }, Ordering::SeqCst);
+    }
+
+    efiapi! {
+        pub fn query_variable_info(
+            attributes: u32,
+            maximum: *mut u64,
+            remaining: *mut u64,
+            max_variable_size: *mut u64,
+        ) -> efi::Status {
+            if attributes != ANSWERED.load(Ordering::SeqCst) {
+                return efi::Status::UNSUPPORTED;
+            }
+            unsafe {
+                *maximum = MAXIMUM;
+                *remaining = REMAINING.load(Ordering::SeqCst);
+                *max_variable_size = MAX_VARIABLE_SIZE;
+            }
+            efi::Status::SUCCESS
+        }
+    }
+}
+
+/**
+ * @brief The Simple Text Input Ex protocol HandleProtocol returns for ConIn,
+ *        with no key notifications in each new MockFirmware. Only
+ *        RegisterKeyNotify and UnregisterKeyNotify are implemented; press()
+ *        calls the notifications for a key as a keystroke would.
+ */
+pub mod keys {
+    use core::sync::atomic::AtomicPtr;
+    use r_efi::efi;
+    use r_efi::protocols::simple_text_input_ex;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    pub(super) static TEXT_INPUT_EX: AtomicPtr<simple_text_input_ex::Protocol> =
+        AtomicPtr::new(core::ptr::null_mut());
+    // The scan code and function of each notification; its handle is its
+    // index plus one, and unregistering it empties the slot.
+    static NOTIFICATIONS: Mutex<Vec<Option<(u16, simple_text_input_ex::KeyNotifyFunction)>>> =
+        Mutex::new(Vec::new());
+
+    pub fn clear() {
+        NOTIFICATIONS.lock().unwrap().clear();
+    }
+
+    /**
+     * @brief Returns the number of key notifications registered.
+     */
+    #[cfg(feature = "ring-dump")]
+    pub fn registered() -> usize {
+        NOTIFICATIONS.lock().unwrap().iter().flatten().count()
+    }
+
+    /**
+     * @brief Calls the notifications registered for `scan_code`, and returns
+     *        how many there were.
+     */
+    #[cfg(feature = "ring-dump")]
+    pub fn press(scan_code: u16) -> usize {
+        let notify: Vec<_> = NOTIFICATIONS
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .filter(|(registered, _)| *registered == scan_code)
+            .map(|(_, function)| *function)
+            .collect();
+        for function in notify.iter() {
+            let mut key_data = simple_text_input_ex::KeyData::default();
+            key_data.key.scan_code = scan_code;
+            assert_eq!(function(&mut key_data), efi::Status::SUCCESS);
+        }
+        notify.len()
+    }
+
+    efiapi! {
+        pub fn register_key_notify(
+            _this: *mut simple_text_input_ex::Protocol,
+            key_data: *mut simple_text_input_ex::KeyData,
+            function: simple_text_input_ex::KeyNotifyFunction,
+            handle: *mut *mut core::ffi::c_void,
+        ) -> efi::Status {
+            if key_data.is_null() || handle.is_null() {
+                return efi::Status::INVALID_PARAMETER;
+            }
+            let mut notifications = NOTIFICATIONS.lock().unwrap();
+            notifications.push(Some((unsafe { (*key_data).key.scan_code }, function)));
+            unsafe { *handle = notifications.len() as *mut core::ffi::c_void };
+            efi::Status::SUCCESS
+        }
+    }
+
+    efiapi! {
+        pub fn unregister_key_notify(
+            _this: *mut simple_text_input_ex::Protocol,
+            handle: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let mut notifications = NOTIFICATIONS.lock().unwrap();
+            match (handle as usize)
+                .checked_sub(1)
+                .and_then(|index| notifications.get_mut(index))
+            {
+                Some(slot @ Some(_)) => {
+                    *slot = None;
+                    efi::Status::SUCCESS
+                }
+                _ => efi::Status::INVALID_PARAMETER,
+            }
+        }
+    }
+}
+
+pub struct MockFirmware {
+    pub system_table: Box<efi::SystemTable>,
+    // Only referenced through the system table.
+    #[allow(dead_code)]
+    pub boot_services: Box<efi::BootServices>,
+    pub runtime_services: Box<efi::RuntimeServices>,
+    pub loaded_image: Box<loaded_image::Protocol>,
+    // Only referenced through HandleProtocol.
+    #[allow(dead_code)]
+    pub text_input_ex: Box<simple_text_input_ex::Protocol>,
+}
+
+impl MockFirmware {
+    /**
+     * @brief Creates a system table whose GetVariable slot holds
+     *        `get_variable`.
+     */
+    pub fn new(get_variable: GetVariableType) -> Self {
+        let mut boot_services: Box<efi::BootServices> = Box::new(table_of_unexpected());
+        boot_services.hdr = table_header(
+            efi::BOOT_SERVICES_SIGNATURE,
+            core::mem::size_of::<efi::BootServices>(),
+        );
+        boot_services.raise_tpl = raise_tpl;
+        boot_services.restore_tpl = restore_tpl;
+        boot_services.create_event = create_event;
+        boot_services.create_event_ex = create_event_ex;
+        boot_services.set_timer = set_timer;
+        boot_services.close_event = close_event;
+        boot_services.install_protocol_interface = install_protocol_interface;
+        boot_services.uninstall_protocol_interface = uninstall_protocol_interface;
+        boot_services.handle_protocol = handle_protocol;
+        boot_services.locate_protocol = locate_protocol;
+        boot_services.locate_handle_buffer = locate_handle_buffer;
+        boot_services.free_pool = free_pool;
+        boot_services.calculate_crc32 = calculate_crc32;
+        boot_services.stall = stall;
+
+        let mut runtime_services: Box<efi::RuntimeServices> = Box::new(table_of_unexpected());
+        runtime_services.hdr = table_header(
+            efi::RUNTIME_SERVICES_SIGNATURE,
+            core::mem::size_of::<efi::RuntimeServices>(),
+        );
+        runtime_services.get_variable = get_variable;
+        runtime_services.set_variable = set_variable;
+        runtime_services.get_next_variable_name = store::get_next_variable_name;
+        store::fill(&[]);
+        variables::clear();
+        runtime_services.query_variable_info = space::query_variable_info;
+        space::set_remaining(space::MAXIMUM - 0x1_0000);
+        space::set_answered(true);
+        runtime_services.convert_pointer = convert_pointer;
+
+        let mut system_table: Box<efi::SystemTable> = Box::new(unsafe { core::mem::zeroed() });
+        system_table.hdr = table_header(
+            efi::SYSTEM_TABLE_SIGNATURE,
+            core::mem::size_of::<efi::SystemTable>(),
+        );
+        system_table.boot_services = &mut *boot_services;
+        system_table.runtime_services = &mut *runtime_services;
+
+        let mut loaded_image = Box::new(loaded_image::Protocol {
+            revision: loaded_image::REVISION,
+            parent_handle: core::ptr::null_mut(),
+            system_table: &mut *system_table,
+            device_handle: core::ptr::null_mut(),
+            file_path: core::ptr::null_mut(),
+            reserved: core::ptr::null_mut(),
+            load_options_size: 0,
+            load_options: core::ptr::null_mut(),
+            image_base: core::ptr::null_mut(),
+            image_size: 0,
+            image_code_type: efi::MemoryType::RuntimeServicesCode,
+            image_data_type: efi::MemoryType::RuntimeServicesData,
+            unload,
+        });
+        LOADED_IMAGE.store(&mut *loaded_image, Ordering::SeqCst);
+
+        let mut text_input_ex: Box<simple_text_input_ex::Protocol> =
+            Box::new(table_of_unexpected());
+        text_input_ex.register_key_notify = keys::register_key_notify;
+        text_input_ex.unregister_key_notify = keys::unregister_key_notify;
+        keys::TEXT_INPUT_EX.store(&mut *text_input_ex, Ordering::SeqCst);
+        keys::clear();
+
+        MockFirmware {
+            system_table,
+            boot_services,
+            runtime_services,
+            loaded_image,
+            text_input_ex,
+        }
+    }
+
+    pub fn system_table(&mut self) -> *mut efi::SystemTable {
+        &mut *self.system_table
+    }
+}
@@ -0,0 +1,159 @@
+// uefi-var-monitor-rust/src/mode.rs
+//
+// Transitions of the Secure Boot mode variables: SetupMode, AuditMode,
+// DeployedMode and SecureBoot under the EFI global variable GUID. Each holds
+// one byte, 0 or 1, and only changes on key enrollment or an explicit mode
+// switch; one changing behind the platform owner's back is how Secure Boot
+// ends up off without anybody turning it off.
+//
+// The value last observed is kept in the seen-variables table (see seen.rs).
+// A successful read or write showing another value is a critical alert:
+//
+//   SetupMode 0→1 on GetVariable in BootServices from 0x7e6a1234
+//
+// The initial values are sampled at load through the saved GetVariable, under
+// the reentrancy guard, so that a transition is caught from the very first
+// access made from outside the driver. Reads at runtime only show a value if
+// the data buffer may be inspected then (see safety.rs).
+
+use crate::alerts::Rule;
+use crate::boot_option;
+use crate::classify::GLOBAL_VARIABLE_GUID;
+use crate::rules::{self, Severity};
+use crate::safety::Inspection;
+use crate::seen;
+use crate::set_variable::ReturnAddress;
+use crate::tag::{self, Tag};
+use core::sync::atomic::Ordering;
+use r_efi::efi;
+
+const TAG: Tag = tag::INTEGRITY;
+
+pub const MODE_VARIABLES: [&str; 4] = ["SetupMode", "AuditMode", "DeployedMode", "SecureBoot"];
+
+// Longest name in MODE_VARIABLES plus the terminator.
+const MAX_NAME: usize = 13;
+
+/**
+ * @brief Returns whether `name` under `guid` is a Secure Boot mode variable.
+ */
+pub fn is_mode_variable(name: &str, guid: &efi::Guid) -> bool {
+    *guid == GLOBAL_VARIABLE_GUID && MODE_VARIABLES.contains(&name)
+}
+
+/**
+ * @brief Records the value a successful `access` to a mode variable showed,
+ *        and alerts if it differs from the last one observed. Anything but a
+ *        single inspectable byte is ignored.
+ */
+pub fn observe(
+    name: &str,
+    guid: &efi::Guid,
+    access: &str,
+    data: *const core::ffi::c_void,
+    data_size: usize,
+    caller: Option<usize>,
+) {
+    if data_size != 1 || !is_mode_variable(name, guid) {
+        return;
+    }
+    let value = match boot_option::inspect(name, guid, data, data_size) {
+        Inspection::Data(&[value]) => value,
+        _ => return,
+    };
+    if let Some(previous) = seen::observe_mode(name, guid, value) {
+        rules::emit(
+            Rule::ModeTransition,
+            Severity::Critical,
+            format_args!(
+                "{} {}\u{2192}{} on {} in {:?} from {}",
+                name,
+                previous,
+                value,
+                access,
+                crate::phase(),
+                ReturnAddress(caller)
+            ),
+        );
+    }
+}
+
+/**
+ * @brief Reads the mode variables through the saved GetVariable and records
+ *        their values. Must be called after the GetVariable hook is
+ *        installed and the redaction list is loaded. Nothing is sampled if a
+ *        GetVariable call is already in progress.
+ */
+pub fn sample() {
+    if crate::IN_GET_VARIABLE.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let mut values = [None; MODE_VARIABLES.len()];
+    for (name, slot) in MODE_VARIABLES.iter().zip(values.iter_mut()) {
+        if crate::redact::is_redacted(name, &GLOBAL_VARIABLE_GUID) {
+            continue;
+        }
+        let mut name_buffer = [0u16; MAX_NAME];
+        for (c16, c) in name_buffer.iter_mut().zip(name.bytes()) {
+            *c16 = c as u16;
+        }
+        let mut guid = GLOBAL_VARIABLE_GUID;
+        let mut attributes = 0u32;
+        let mut value = 0u8;
+        let mut data_size = 1usize;
+        let efi_status = crate::FIRMWARE_GET_VARIABLE.call(
+            name_buffer.as_mut_ptr(),
+            &mut guid,
+            &mut attributes,
+            &mut data_size,
+            &mut value as *mut u8 as *mut core::ffi::c_void,
+        );
+        if efi_status == efi::Status::SUCCESS && data_size == 1 {
+            seen::observe_mode(name, &GLOBAL_VARIABLE_GUID, value);
+            *slot = Some(value);
+        }
+    }
+    crate::IN_GET_VARIABLE.store(false, Ordering::Release);
+
+    info!(
+        TAG,
+        "Secure Boot modes at load: {}={} {}={} {}={} {}={}",
+        MODE_VARIABLES[0],
+        Sampled(values[0]),
+        MODE_VARIABLES[1],
+        Sampled(values[1]),
+        MODE_VARIABLES[2],
+        Sampled(values[2]),
+        MODE_VARIABLES[3],
+        Sampled(values[3]),
+    );
+}
+
+// A sampled mode value for logging, "n/a" if it was not read.
+struct Sampled(Option<u8>);
+
+impl core::fmt::Display for Sampled {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}", value),
+            None => f.write_str("n/a"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_global_mode_variables() {
+        assert!(is_mode_variable("SetupMode", &GLOBAL_VARIABLE_GUID));
+        assert!(is_mode_variable("DeployedMode", &GLOBAL_VARIABLE_GUID));
+        assert!(!is_mode_variable("PK", &GLOBAL_VARIABLE_GUID));
+        assert!(!is_mode_variable(
+            "SetupMode",
+            &crate::classify::IMAGE_SECURITY_DATABASE_GUID
+        ));
+        assert!(MODE_VARIABLES.iter().all(|name| name.len() < MAX_NAME));
+    }
+}
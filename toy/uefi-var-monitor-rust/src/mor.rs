@@ -0,0 +1,313 @@
+// uefi-var-monitor-rust/src/mor.rs
+//
+// The TCG Platform Reset Attack Mitigation variables. MemoryOverwriteRequest-
+// Control asks firmware to clear memory on the next boot after an unclean
+// reset, which is what keeps secrets in RAM from surviving a cold boot
+// attack. MemoryOverwriteRequestControlLock locks it against changes until
+// the next boot, optionally with a key needed to unlock it again.
+//
+// Every access to a variable under either vendor GUID is logged at info level
+// with the value decoded. Writes that turn memory clearing off, or that
+// unlock the lock, are critical alerts whether or not firmware accepts them.
+//
+// MemoryOverwriteRequestControl, one byte:
+//   bit 0    ClearMemory
+//   bit 4    DisableAutoDetect
+//   others   reserved
+//
+// MemoryOverwriteRequestControlLock, one byte when read:
+//   0x00     unlocked
+//   0x01     locked without key; only a reset unlocks it
+//   0x02     locked with key; writing the same 8-byte key unlocks it
+// Written with one byte to lock without key, or with an 8-byte key.
+
+use crate::alerts::Rule;
+use crate::boot_option;
+pub use crate::classify::{mor_variable as variable, MorVariable as Variable};
+use crate::classify::{MEMORY_ONLY_RESET_CONTROL_GUID, MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID};
+use crate::last_value;
+use crate::rules::{self, Access, Severity};
+use crate::safety::Inspection;
+use crate::set_variable::ReturnAddress;
+use core::fmt;
+use r_efi::efi;
+
+pub const MOR_CLEAR_MEMORY: u8 = 0x01;
+pub const MOR_DISABLE_AUTO_DETECT: u8 = 0x10;
+const MOR_RESERVED: u8 = !(MOR_CLEAR_MEMORY | MOR_DISABLE_AUTO_DETECT);
+
+pub const MOR_LOCK_UNLOCKED: u8 = 0x00;
+pub const MOR_LOCK_LOCKED_WITHOUT_KEY: u8 = 0x01;
+pub const MOR_LOCK_LOCKED_WITH_KEY: u8 = 0x02;
+pub const MOR_LOCK_KEY_SIZE: usize = 8;
+
+pub fn is_mor_guid(guid: &efi::Guid) -> bool {
+    *guid == MEMORY_ONLY_RESET_CONTROL_GUID || *guid == MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID
+}
+
+// A MOR variable value, decoded where the variable is known.
+pub enum Shown<'a> {
+    Value {
+        variable: Option<Variable>,
+        data: &'a [u8],
+    },
+    Deleted,
+    // The data buffer may not be read in this phase.
+    NotInspected,
+    Redacted,
+}
+
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Shown::Value {
+                variable: Some(Variable::Control),
+                data: &[value],
+            } => {
+                write!(
+                    f,
+                    "ClearMemory={} DisableAutoDetect={}",
+                    (value & MOR_CLEAR_MEMORY != 0) as u8,
+                    (value & MOR_DISABLE_AUTO_DETECT != 0) as u8
+                )?;
+                if value & MOR_RESERVED != 0 {
+                    write!(f, " Reserved={:#04x}", value & MOR_RESERVED)?;
+                }
+                Ok(())
+            }
+            Shown::Value {
+                variable: Some(Variable::Lock),
+                data: &[value],
+            } => match value {
+                MOR_LOCK_UNLOCKED => f.write_str("unlocked"),
+                MOR_LOCK_LOCKED_WITHOUT_KEY => f.write_str("locked without key"),
+                MOR_LOCK_LOCKED_WITH_KEY => f.write_str("locked with key"),
+                _ => write!(f, "invalid lock state {:#04x}", value),
+            },
+            Shown::Value {
+                variable: Some(Variable::Lock),
+                data,
+            } if data.len() == MOR_LOCK_KEY_SIZE => f.write_str("key"),
+            Shown::Value { data, .. } => {
+                write!(f, "{} byte(s)", data.len())?;
+                for byte in data.iter().take(MOR_LOCK_KEY_SIZE) {
+                    write!(f, " {:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Shown::Deleted => f.write_str("deleted"),
+            Shown::NotInspected => f.write_str("not inspected"),
+            Shown::Redacted => f.write_str("<redacted>"),
+        }
+    }
+}
+
+/**
+ * @brief Returns how a write of `new` to a MOR variable last seen holding
+ *        `previous` weakens the mitigation, if it does. Without the new
+ *        value, only a deletion can be told apart.
+ */
+pub fn weakening(variable: Variable, previous: Option<&[u8]>, new: &Shown) -> Option<&'static str> {
+    match (variable, new) {
+        (Variable::Control, Shown::Deleted) => Some("clears MOR"),
+        (Variable::Control, Shown::Value { data: &[value], .. })
+            if value & MOR_CLEAR_MEMORY == 0 =>
+        {
+            Some("clears MOR")
+        }
+        (Variable::Lock, Shown::Deleted) => Some("unlocks MORLock"),
+        (
+            Variable::Lock,
+            Shown::Value {
+                data: &[MOR_LOCK_UNLOCKED],
+                ..
+            },
+        ) => Some("unlocks MORLock"),
+        // The key that locked it is also the one that unlocks it.
+        (Variable::Lock, Shown::Value { data, .. })
+            if data.len() == MOR_LOCK_KEY_SIZE
+                && previous == Some(&[MOR_LOCK_LOCKED_WITH_KEY][..]) =>
+        {
+            Some("may unlock MORLock with a key")
+        }
+        _ => None,
+    }
+}
+
+/**
+ * @brief Logs an access to a variable under a MOR vendor GUID with its
+ *        value, and alerts on writes that weaken the mitigation. For
+ *        GetVariable, `data_size` is the size firmware returned.
+ */
+pub fn check(
+    name: &str,
+    guid: &efi::Guid,
+    access: Access,
+    efi_status: efi::Status,
+    data: *const core::ffi::c_void,
+    data_size: Option<usize>,
+    caller: Option<usize>,
+) {
+    if !is_mor_guid(guid) {
+        return;
+    }
+    let variable = variable(name, guid);
+    let shown = |size: usize| match boot_option::inspect(name, guid, data, size) {
+        Inspection::Data(data) => Shown::Value { variable, data },
+        Inspection::NotInspected => Shown::NotInspected,
+        Inspection::Redacted => Shown::Redacted,
+    };
+
+    match (access, data_size) {
+        (Access::Get, Some(size)) if efi_status == efi::Status::SUCCESS => {
+            let value = shown(size);
+            rules::emit(
+                Rule::MorAccess,
+                Severity::Info,
+                format_args!("GetVariable of MOR variable {}: {}", name, value),
+            );
+            if let Shown::Value { data, .. } = value {
+                last_value::record(name, guid, data, size);
+            }
+        }
+        (Access::Get, _) => rules::emit(
+            Rule::MorAccess,
+            Severity::Info,
+            format_args!(
+                "GetVariable of MOR variable {}: {:#x}",
+                name,
+                efi_status.as_usize()
+            ),
+        ),
+        (
+            Access::Set {
+                attributes,
+                data_size,
+            },
+            _,
+        ) => {
+            let new = if rules::is_deletion(attributes, data_size) {
+                Shown::Deleted
+            } else {
+                shown(data_size)
+            };
+            rules::emit(
+                Rule::MorAccess,
+                Severity::Info,
+                format_args!(
+                    "SetVariable of MOR variable {} from {}: {:#x}, {}",
+                    name,
+                    ReturnAddress(caller),
+                    efi_status.as_usize(),
+                    new
+                ),
+            );
+            if let Some(variable) = variable {
+                last_value::with(name, guid, |previous| {
+                    let previous = previous.map(|value| value.data());
+                    if let Some(effect) = weakening(variable, previous, &new) {
+                        rules::emit(
+                            Rule::MorWeakening,
+                            Severity::Critical,
+                            format_args!(
+                                "SetVariable {} {} from {}: {:#x}",
+                                effect,
+                                name,
+                                ReturnAddress(caller),
+                                efi_status.as_usize()
+                            ),
+                        );
+                    }
+                });
+            }
+            // A key written to the lock is not its state; leave that to the
+            // next read.
+            if efi_status == efi::Status::SUCCESS {
+                match new {
+                    Shown::Deleted => last_value::remove(name, guid),
+                    Shown::Value { data, .. } if data.len() == 1 => {
+                        last_value::record(name, guid, data, data_size)
+                    }
+                    _ => last_value::remove(name, guid),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    fn control(value: u8) -> std::string::String {
+        Shown::Value {
+            variable: Some(Variable::Control),
+            data: &[value],
+        }
+        .to_string()
+    }
+
+    fn lock(data: &[u8]) -> std::string::String {
+        Shown::Value {
+            variable: Some(Variable::Lock),
+            data,
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn values_are_decoded_by_bit() {
+        assert_eq!(control(0x00), "ClearMemory=0 DisableAutoDetect=0");
+        assert_eq!(control(0x01), "ClearMemory=1 DisableAutoDetect=0");
+        assert_eq!(control(0x11), "ClearMemory=1 DisableAutoDetect=1");
+        assert_eq!(
+            control(0x83),
+            "ClearMemory=1 DisableAutoDetect=0 Reserved=0x82"
+        );
+        assert_eq!(lock(&[0]), "unlocked");
+        assert_eq!(lock(&[1]), "locked without key");
+        assert_eq!(lock(&[2]), "locked with key");
+        assert_eq!(lock(&[3]), "invalid lock state 0x03");
+        assert_eq!(lock(&[0xaa; MOR_LOCK_KEY_SIZE]), "key");
+        assert_eq!(lock(&[1, 2]), "2 byte(s) 01 02");
+
+        assert_eq!(
+            variable(
+                "MemoryOverwriteRequestControl",
+                &MEMORY_ONLY_RESET_CONTROL_GUID
+            ),
+            Some(Variable::Control)
+        );
+        assert_eq!(
+            variable(
+                "MemoryOverwriteRequestControl",
+                &MEMORY_OVERWRITE_REQUEST_CONTROL_LOCK_GUID
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn clearing_mor_and_unlocking_are_weakening() {
+        let value = |variable, data| Shown::Value {
+            variable: Some(variable),
+            data,
+        };
+        let control = Variable::Control;
+        assert!(weakening(control, None, &value(control, &[0x00])).is_some());
+        assert!(weakening(control, None, &value(control, &[0x10])).is_some());
+        assert!(weakening(control, None, &Shown::Deleted).is_some());
+        assert!(weakening(control, None, &value(control, &[0x01])).is_none());
+        assert!(weakening(control, None, &Shown::NotInspected).is_none());
+
+        let lock = Variable::Lock;
+        let key = [0x5a; MOR_LOCK_KEY_SIZE];
+        assert!(weakening(lock, None, &value(lock, &[0x00])).is_some());
+        assert!(weakening(lock, None, &Shown::Deleted).is_some());
+        assert!(weakening(lock, None, &value(lock, &[0x01])).is_none());
+        // A key locks an unlocked variable, and unlocks one locked with it.
+        assert!(weakening(lock, Some(&[0x00]), &value(lock, &key)).is_none());
+        assert!(weakening(lock, Some(&[0x02]), &value(lock, &key)).is_some());
+    }
+}
@@ -0,0 +1,323 @@
+// uefi-var-monitor-rust/src/names.rs
+//
+// Cache of the strings the hooks render for each access: the name as
+// convert_name() gives it and the GUID in registry format. A handful of
+// variables account for most accesses, so the hooks look the pair up here
+// before decoding anything.
+//
+// The cache is direct-mapped: NAME_CACHE_SIZE entries, the slot chosen by a
+// hash of the GUID and of the name as passed, and a miss overwrites whatever
+// held the slot. A hit takes the same GUID and the same characters, compared
+// in full; the hash only picks the slot. Names are keyed on the characters
+// convert_name() reads, so longer names that share them render the same.
+//
+// The cache is guarded by a try-lock like the ring buffer (see ring.rs): a
+// lookup finding it busy renders without it, and is counted as a miss.
+//
+// It is only used with name-cache. On the host, over a trace where two
+// variables make up nine accesses in ten (name_cache_speed below), 93% of
+// lookups hit and still take longer than rendering: about 21 ns against
+// 16 ns, as hashing and comparing the name reads it as often as converting
+// it does. Not measured on firmware.
+
+use crate::caller;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use r_efi::efi;
+use uvm_interface::hex::{self, GUID_TEXT_LENGTH};
+
+pub const NAME_CACHE_SIZE: usize = 16;
+// As many characters as convert_name() reads.
+const NAME_LENGTH: usize = 64;
+
+/**
+ * @brief The strings the hooks log for an access.
+ */
+pub struct Rendered {
+    name: [u8; NAME_LENGTH],
+    name_length: usize,
+    guid: [u8; GUID_TEXT_LENGTH],
+}
+
+impl Rendered {
+    /**
+     * @brief Renders the strings without the cache.
+     */
+    fn new(variable_name: *const r_efi::base::Char16, guid: &efi::Guid) -> Self {
+        let mut name = [0u8; NAME_LENGTH];
+        let name_length = crate::convert_name(variable_name, &mut name).len();
+        Rendered {
+            name,
+            name_length,
+            guid: hex::guid_text(guid),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        // convert_name() stored only printable ASCII.
+        unsafe { core::str::from_utf8_unchecked(self.name.get(..self.name_length).unwrap_or(&[])) }
+    }
+
+    pub fn guid(&self) -> &str {
+        unsafe { core::str::from_utf8_unchecked(&self.guid) }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    guid: efi::Guid,
+    hash: u32,
+    // The name as passed, up to NAME_LENGTH characters; None while unused.
+    length: Option<usize>,
+    characters: [u16; NAME_LENGTH],
+    name: [u8; NAME_LENGTH],
+    guid_text: [u8; GUID_TEXT_LENGTH],
+}
+
+const EMPTY_ENTRY: Entry = Entry {
+    guid: efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+    hash: 0,
+    length: None,
+    characters: [0; NAME_LENGTH],
+    name: [0; NAME_LENGTH],
+    guid_text: [0; GUID_TEXT_LENGTH],
+};
+
+pub struct NameCache<const N: usize> {
+    entries: [Entry; N],
+    hits: u64,
+    lookups: u64,
+}
+
+impl<const N: usize> NameCache<N> {
+    pub const fn new() -> Self {
+        NameCache {
+            entries: [EMPTY_ENTRY; N],
+            hits: 0,
+            lookups: 0,
+        }
+    }
+
+    /**
+     * @brief Returns the strings of the access, from the cache if they are
+     *        held, rendering and storing them otherwise.
+     */
+    pub fn render(
+        &mut self,
+        variable_name: *const r_efi::base::Char16,
+        guid: &efi::Guid,
+    ) -> Rendered {
+        self.lookups += 1;
+        let characters = unsafe { caller::name(variable_name, NAME_LENGTH) }.unwrap_or(&[]);
+        let (hash, length) = (hash(characters, guid), characters.len());
+        let entry = &mut self.entries[hash as usize % N];
+        if entry.length == Some(length)
+            && entry.hash == hash
+            && entry.guid == *guid
+            && entry.characters.get(..length) == Some(characters)
+        {
+            self.hits += 1;
+            return Rendered {
+                name: entry.name,
+                name_length: length,
+                guid: entry.guid_text,
+            };
+        }
+
+        let rendered = Rendered::new(variable_name, guid);
+        *entry = Entry {
+            guid: *guid,
+            hash,
+            length: Some(length),
+            characters: [0; NAME_LENGTH],
+            name: rendered.name,
+            guid_text: rendered.guid,
+        };
+        if let Some(slot) = entry.characters.get_mut(..length) {
+            slot.copy_from_slice(characters);
+        }
+        rendered
+    }
+}
+
+/**
+ * @brief Returns the FNV-1a hash of the GUID and of the characters of the
+ *        name as passed.
+ */
+fn hash(characters: &[u16], guid: &efi::Guid) -> u32 {
+    let mut hash = 0x811c_9dc5u32;
+    for &byte in guid.as_bytes().iter() {
+        hash = (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193);
+    }
+    for &c in characters {
+        hash = (hash ^ u32::from(c)).wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+static BUSY: AtomicBool = AtomicBool::new(false);
+static CONTENDED: AtomicU64 = AtomicU64::new(0);
+static mut CACHE: NameCache<NAME_CACHE_SIZE> = NameCache::new();
+
+/**
+ * @brief Returns the strings the hooks log for an access.
+ */
+pub fn render(variable_name: *const r_efi::base::Char16, guid: &efi::Guid) -> Rendered {
+    if !cfg!(feature = "name-cache") {
+        return Rendered::new(variable_name, guid);
+    }
+    if BUSY.swap(true, Ordering::Acquire) {
+        CONTENDED.fetch_add(1, Ordering::Relaxed);
+        return Rendered::new(variable_name, guid);
+    }
+    let rendered = unsafe { &mut *core::ptr::addr_of_mut!(CACHE) }.render(variable_name, guid);
+    BUSY.store(false, Ordering::Release);
+    rendered
+}
+
+/**
+ * @brief Returns how many lookups the cache answered, and how many there
+ *        were, for the summary at ExitBootServices.
+ */
+#[cfg_attr(not(feature = "name-cache"), allow(dead_code))]
+pub fn hits() -> (u64, u64) {
+    let contended = CONTENDED.load(Ordering::Relaxed);
+    if BUSY.swap(true, Ordering::Acquire) {
+        return (0, contended);
+    }
+    let cache = unsafe { &*core::ptr::addr_of!(CACHE) };
+    let hits = (cache.hits, cache.lookups + contended);
+    BUSY.store(false, Ordering::Release);
+    hits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+    use crate::config::UVM_VENDOR_GUID;
+    use std::vec::Vec;
+
+    fn utf16(name: &str) -> Vec<u16> {
+        name.encode_utf16().chain([0]).collect()
+    }
+
+    #[test]
+    fn hits_match_the_name_in_full() {
+        let mut cache = NameCache::<16>::new();
+        let boot_order = utf16("BootOrder");
+        let rendered = cache.render(boot_order.as_ptr(), &GLOBAL_VARIABLE_GUID);
+        assert_eq!(rendered.name(), "BootOrder");
+        assert_eq!(rendered.guid(), "8BE4DF61-93CA-11D2-AA0D-00E098032B8C");
+        assert_eq!((cache.hits, cache.lookups), (0, 1));
+
+        let rendered = cache.render(boot_order.as_ptr(), &GLOBAL_VARIABLE_GUID);
+        assert_eq!(rendered.name(), "BootOrder");
+        assert_eq!(rendered.guid(), "8BE4DF61-93CA-11D2-AA0D-00E098032B8C");
+        assert_eq!((cache.hits, cache.lookups), (1, 2));
+
+        // Same name under another GUID.
+        let rendered = cache.render(boot_order.as_ptr(), &UVM_VENDOR_GUID);
+        assert_eq!(rendered.guid(), "6C8A7F3E-2D4B-4F1A-9C5E-8B2D1F7A3C90");
+        assert_eq!(cache.hits, 1);
+
+        // An entry with the same hash and length but other characters is
+        // not a hit.
+        let boot_next = utf16("BootNext");
+        let length = boot_next.len() - 1;
+        let hash = hash(&boot_next[..length], &GLOBAL_VARIABLE_GUID);
+        let entry = &mut cache.entries[hash as usize % 16];
+        entry.guid = GLOBAL_VARIABLE_GUID;
+        entry.hash = hash;
+        entry.length = Some(length);
+        entry.characters[..length].copy_from_slice(&utf16("BootNexT")[..length]);
+        entry.name[..length].copy_from_slice(b"BootNexT");
+        assert_eq!(
+            cache
+                .render(boot_next.as_ptr(), &GLOBAL_VARIABLE_GUID)
+                .name(),
+            "BootNext"
+        );
+        assert_eq!(cache.hits, 1);
+        assert_eq!(
+            cache
+                .render(boot_next.as_ptr(), &GLOBAL_VARIABLE_GUID)
+                .name(),
+            "BootNext"
+        );
+        assert_eq!(cache.hits, 2);
+    }
+
+    #[test]
+    fn collisions_overwrite_the_slot() {
+        let mut cache = NameCache::<1>::new();
+        let (pk, kek) = (utf16("PK"), utf16("KEK"));
+        cache.render(pk.as_ptr(), &GLOBAL_VARIABLE_GUID);
+        assert_eq!(
+            cache.render(kek.as_ptr(), &GLOBAL_VARIABLE_GUID).name(),
+            "KEK"
+        );
+        assert_eq!(
+            cache.render(pk.as_ptr(), &GLOBAL_VARIABLE_GUID).name(),
+            "PK"
+        );
+        assert_eq!(cache.hits, 0);
+
+        // Non-ASCII, and longer than what is rendered.
+        let long: std::string::String = "é".repeat(70);
+        let long = utf16(&long);
+        cache.render(long.as_ptr(), &GLOBAL_VARIABLE_GUID);
+        assert_eq!(
+            cache.render(long.as_ptr(), &GLOBAL_VARIABLE_GUID).name(),
+            "?".repeat(64)
+        );
+        assert_eq!(cache.hits, 1);
+    }
+
+    // Rendering through the cache against without it, over a trace where
+    // BootOrder and Boot0001 make up nine accesses in ten:
+    //
+    //   cargo test --release -- --ignored --nocapture name_cache
+    #[test]
+    #[ignore]
+    fn name_cache_speed() {
+        let others: Vec<Vec<u16>> = (0..30)
+            .map(|index| utf16(&format!("Vendor{:04}Setting", index)))
+            .collect();
+        let (boot_order, boot_0001) = (utf16("BootOrder"), utf16("Boot0001"));
+        let trace: Vec<*const u16> = (0..1000usize)
+            .map(|index| match index % 10 {
+                0..=4 => boot_order.as_ptr(),
+                5..=8 => boot_0001.as_ptr(),
+                _ => others[index * 7 % others.len()].as_ptr(),
+            })
+            .collect();
+        const ROUNDS: usize = 1000;
+        let calls = (ROUNDS * trace.len()) as f64;
+        let guid = GLOBAL_VARIABLE_GUID;
+
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            for &name in trace.iter() {
+                let rendered = Rendered::new(core::hint::black_box(name), &guid);
+                core::hint::black_box(rendered.name().len());
+            }
+        }
+        let uncached = start.elapsed().as_nanos() as f64 / calls;
+
+        let mut cache = NameCache::<NAME_CACHE_SIZE>::new();
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            for &name in trace.iter() {
+                let rendered = cache.render(core::hint::black_box(name), &guid);
+                core::hint::black_box(rendered.name().len());
+            }
+        }
+        let cached = start.elapsed().as_nanos() as f64 / calls;
+        std::println!(
+            "name and GUID: {:.1} ns rendered, {:.1} ns through the cache, {}% hits",
+            uncached,
+            cached,
+            cache.hits * 100 / cache.lookups
+        );
+    }
+}
@@ -0,0 +1,562 @@
+// uefi-var-monitor-rust/src/net.rs
+//
+// Optional log sink that ships each log record off-box as a UDP syslog
+// datagram through the Simple Network Protocol (SNP). There is no ARP, DHCP
+// or IP stack involved: frames are built by hand and sent to a statically
+// configured MAC/IP/port. The sink only exists during the boot-services phase
+// and is disabled at ExitBootServices.
+//
+// The target is configured at build time with the following environment
+// variables.
+//
+//   UVM_SYSLOG_DST_MAC   aa:bb:cc:dd:ee:ff   (default: broadcast)
+//   UVM_SYSLOG_DST_IP    a.b.c.d             (default: 255.255.255.255)
+//   UVM_SYSLOG_SRC_IP    a.b.c.d             (default: 0.0.0.0)
+//   UVM_SYSLOG_PORT      n                   (default: 514)
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use r_efi::efi;
+use r_efi::protocols::simple_network;
+
+pub const ETHERNET_HEADER_SIZE: usize = 14;
+pub const IPV4_HEADER_SIZE: usize = 20;
+pub const UDP_HEADER_SIZE: usize = 8;
+pub const HEADERS_SIZE: usize = ETHERNET_HEADER_SIZE + IPV4_HEADER_SIZE + UDP_HEADER_SIZE;
+
+// Standard Ethernet MTU payload; larger records are truncated.
+pub const MAX_FRAME_SIZE: usize = ETHERNET_HEADER_SIZE + 1500;
+pub const MAX_PAYLOAD_SIZE: usize = MAX_FRAME_SIZE - HEADERS_SIZE;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const IP_PROTOCOL_UDP: u8 = 17;
+const IP_DEFAULT_TTL: u8 = 64;
+const SYSLOG_DEFAULT_PORT: u16 = 514;
+
+// Facility local0 (16), severity informational (6).
+const SYSLOG_PRIORITY: u8 = 16 * 8 + 6;
+const SYSLOG_APP_NAME: &str = "uefi-var-monitor";
+
+// Upper bound of GetStatus polls while waiting for SNP to hand back the
+// transmit buffer.
+const TX_RECYCLE_POLLS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NetConfig {
+    pub src_mac: [u8; 6],
+    pub dst_mac: [u8; 6],
+    pub src_ip: [u8; 4],
+    pub dst_ip: [u8; 4],
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+impl NetConfig {
+    pub const fn new() -> Self {
+        NetConfig {
+            src_mac: [0; 6],
+            dst_mac: [0xff; 6],
+            src_ip: [0; 4],
+            dst_ip: [0xff; 4],
+            src_port: SYSLOG_DEFAULT_PORT,
+            dst_port: SYSLOG_DEFAULT_PORT,
+        }
+    }
+
+    /**
+     * @brief Builds the configuration from the UVM_SYSLOG_* build-time
+     *        variables, falling back to the defaults for unset or malformed
+     *        values.
+     */
+    pub fn from_build_env() -> Self {
+        let mut config = NetConfig::new();
+        if let Some(mac) = option_env!("UVM_SYSLOG_DST_MAC").and_then(parse_mac) {
+            config.dst_mac = mac;
+        }
+        if let Some(ip) = option_env!("UVM_SYSLOG_DST_IP").and_then(parse_ipv4) {
+            config.dst_ip = ip;
+        }
+        if let Some(ip) = option_env!("UVM_SYSLOG_SRC_IP").and_then(parse_ipv4) {
+            config.src_ip = ip;
+        }
+        if let Some(port) = option_env!("UVM_SYSLOG_PORT").and_then(|s| s.parse::<u16>().ok()) {
+            config.dst_port = port;
+        }
+        config
+    }
+}
+
+/**
+ * @brief Parses a MAC address in the "aa:bb:cc:dd:ee:ff" form.
+ */
+pub fn parse_mac(text: &str) -> Option<[u8; 6]> {
+    let mut mac = [0u8; 6];
+    let mut parts = text.split(|c| c == ':' || c == '-');
+    for byte in mac.iter_mut() {
+        let part = parts.next()?;
+        if part.len() != 2 {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(mac)
+}
+
+/**
+ * @brief Parses an IPv4 address in the dotted-decimal form.
+ */
+pub fn parse_ipv4(text: &str) -> Option<[u8; 4]> {
+    let mut ip = [0u8; 4];
+    let mut parts = text.split(|c| c == '.');
+    for octet in ip.iter_mut() {
+        *octet = parts.next()?.parse::<u8>().ok()?;
+    }
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(ip)
+}
+
+/**
+ * @brief Adds data to a running one's complement sum (RFC 1071).
+ */
+fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        sum += u32::from(u16::from_be_bytes([*last, 0]));
+    }
+    sum
+}
+
+/**
+ * @brief Folds a running sum into the final 16-bit internet checksum.
+ */
+fn checksum_finish(mut sum: u32) -> u16 {
+    while (sum >> 16) != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/**
+ * @brief Computes the internet checksum of the data.
+ */
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    checksum_finish(checksum_add(0, data))
+}
+
+/**
+ * @brief Computes the UDP checksum of a datagram (header and payload) with
+ *        the IPv4 pseudo header.
+ */
+pub fn udp_checksum(src_ip: &[u8; 4], dst_ip: &[u8; 4], datagram: &[u8]) -> u16 {
+    let mut sum = checksum_add(0, src_ip);
+    sum = checksum_add(sum, dst_ip);
+    sum += u32::from(IP_PROTOCOL_UDP);
+    sum += datagram.len() as u32;
+    sum = checksum_add(sum, datagram);
+    match checksum_finish(sum) {
+        // Zero means "no checksum" for UDP over IPv4; send all ones instead.
+        0 => 0xffff,
+        checksum => checksum,
+    }
+}
+
+/**
+ * @brief Builds an Ethernet/IPv4/UDP frame carrying the payload into the
+ *        buffer and returns the frame length, or None if the payload does not
+ *        fit.
+ */
+pub fn build_frame(
+    buffer: &mut [u8],
+    config: &NetConfig,
+    identification: u16,
+    payload: &[u8],
+) -> Option<usize> {
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        return None;
+    }
+    let frame_size = HEADERS_SIZE + payload.len();
+    let frame = buffer.get_mut(..frame_size)?;
+    let (ethernet, rest) = frame.split_at_mut(ETHERNET_HEADER_SIZE);
+    let (ipv4, rest) = rest.split_at_mut(IPV4_HEADER_SIZE);
+    let (udp, data) = rest.split_at_mut(UDP_HEADER_SIZE);
+
+    ethernet[0..6].copy_from_slice(&config.dst_mac);
+    ethernet[6..12].copy_from_slice(&config.src_mac);
+    ethernet[12..14].copy_from_slice(&ETHER_TYPE_IPV4.to_be_bytes());
+
+    let ip_total_size = (IPV4_HEADER_SIZE + UDP_HEADER_SIZE + payload.len()) as u16;
+    ipv4[0] = 0x45; // Version 4, IHL 5 (no options).
+    ipv4[1] = 0; // DSCP/ECN.
+    ipv4[2..4].copy_from_slice(&ip_total_size.to_be_bytes());
+    ipv4[4..6].copy_from_slice(&identification.to_be_bytes());
+    ipv4[6..8].copy_from_slice(&0x4000u16.to_be_bytes()); // Don't fragment.
+    ipv4[8] = IP_DEFAULT_TTL;
+    ipv4[9] = IP_PROTOCOL_UDP;
+    ipv4[10..12].copy_from_slice(&[0, 0]);
+    ipv4[12..16].copy_from_slice(&config.src_ip);
+    ipv4[16..20].copy_from_slice(&config.dst_ip);
+    let ip_checksum = internet_checksum(ipv4);
+    ipv4[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+    let udp_size = (UDP_HEADER_SIZE + payload.len()) as u16;
+    udp[0..2].copy_from_slice(&config.src_port.to_be_bytes());
+    udp[2..4].copy_from_slice(&config.dst_port.to_be_bytes());
+    udp[4..6].copy_from_slice(&udp_size.to_be_bytes());
+    udp[6..8].copy_from_slice(&[0, 0]);
+    data.copy_from_slice(payload);
+
+    let datagram = &mut frame[ETHERNET_HEADER_SIZE + IPV4_HEADER_SIZE..];
+    let checksum = udp_checksum(&config.src_ip, &config.dst_ip, datagram);
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+    Some(frame_size)
+}
+
+/**
+ * @brief fmt::Write adapter over a fixed buffer that silently truncates.
+ */
+pub struct PayloadWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl<'a> PayloadWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        PayloadWriter { buffer, length: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+}
+
+impl<'a> fmt::Write for PayloadWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = self.buffer.len().saturating_sub(self.length);
+        let bytes = s.as_bytes().get(..available).unwrap_or(s.as_bytes());
+        if let Some(slot) = self.buffer.get_mut(self.length..self.length + bytes.len()) {
+            slot.copy_from_slice(bytes);
+            self.length += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+/**
+ * @brief Formats a log record as an RFC 5424 syslog message into the buffer
+ *        and returns its length. Trailing line breaks are dropped.
+ */
+pub fn format_syslog(buffer: &mut [u8], args: fmt::Arguments) -> usize {
+    use core::fmt::Write;
+
+    let mut writer = PayloadWriter::new(buffer);
+    // <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG
+    let _ = write!(
+        writer,
+        "<{}>1 - - {} - - - ",
+        SYSLOG_PRIORITY, SYSLOG_APP_NAME
+    );
+    let _ = writer.write_fmt(args);
+    let mut length = writer.len();
+    while let Some(b'\n' | b'\r') = length.checked_sub(1).and_then(|last| buffer.get(last)) {
+        length -= 1;
+    }
+    length
+}
+
+// Sink state. ENABLED is only set once an SNP instance has been found and
+// brought up, and is cleared for good at ExitBootServices. BUSY guards STATE
+// against re-entrance (e.g. a GetVariable call made by the NIC driver).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUSY: AtomicBool = AtomicBool::new(false);
+static SNP: AtomicPtr<simple_network::Protocol> = AtomicPtr::new(core::ptr::null_mut());
+static BOOT_SERVICES: AtomicPtr<efi::BootServices> = AtomicPtr::new(core::ptr::null_mut());
+
+struct NetState {
+    config: NetConfig,
+    identification: u16,
+    payload: [u8; MAX_PAYLOAD_SIZE],
+    frame: [u8; MAX_FRAME_SIZE],
+}
+
+static mut STATE: NetState = NetState {
+    config: NetConfig::new(),
+    identification: 0,
+    payload: [0; MAX_PAYLOAD_SIZE],
+    frame: [0; MAX_FRAME_SIZE],
+};
+
+/**
+ * @brief Locates an SNP instance and brings it up. Silently leaves the sink
+ *        disabled if no usable NIC is present.
+ */
+pub fn initialize(boot_services: &mut efi::BootServices) {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.locate_protocol)(
+        &simple_network::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        return;
+    }
+
+    let snp = interface as *mut simple_network::Protocol;
+    let mode = unsafe { (*snp).mode };
+    if mode.is_null() {
+        return;
+    }
+
+    // Bring the interface up to the initialized state if nobody has yet.
+    unsafe {
+        if (*mode).state == simple_network::NetworkState::NetworkStopped as u32
+            && ((*snp).start)(snp).is_error()
+        {
+            return;
+        }
+        if (*mode).state == simple_network::NetworkState::NetworkStarted as u32
+            && ((*snp).initialize)(snp, 0, 0).is_error()
+        {
+            return;
+        }
+        if (*mode).state != simple_network::NetworkState::NetworkInitialized as u32 {
+            return;
+        }
+    }
+
+    let current_address = unsafe { (*mode).current_address };
+    let state = unsafe { &mut *core::ptr::addr_of_mut!(STATE) };
+    state.config = NetConfig::from_build_env();
+    state
+        .config
+        .src_mac
+        .copy_from_slice(&current_address.addr[..6]);
+
+    BOOT_SERVICES.store(boot_services, Ordering::Release);
+    SNP.store(snp, Ordering::Release);
+    ENABLED.store(true, Ordering::Release);
+}
+
+/**
+ * @brief Permanently disables the sink. Called at ExitBootServices.
+ */
+pub fn disable() {
+    ENABLED.store(false, Ordering::Release);
+    SNP.store(core::ptr::null_mut(), Ordering::Release);
+    BOOT_SERVICES.store(core::ptr::null_mut(), Ordering::Release);
+}
+
+/**
+ * @brief Sends one log record as a syslog datagram. No-op if the sink is
+ *        disabled, busy (re-entered), or called above TPL_CALLBACK where SNP
+ *        must not be used.
+ */
+pub fn send_record(args: fmt::Arguments) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    if BUSY.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    let snp = SNP.load(Ordering::Acquire);
+    let boot_services = BOOT_SERVICES.load(Ordering::Acquire);
+    if !snp.is_null() && !boot_services.is_null() {
+        let boot_services = unsafe { &*boot_services };
+        let tpl = (boot_services.raise_tpl)(efi::TPL_HIGH_LEVEL);
+        (boot_services.restore_tpl)(tpl);
+        if tpl <= efi::TPL_CALLBACK {
+            // BUSY is held, so nobody else references STATE.
+            let state = unsafe { &mut *core::ptr::addr_of_mut!(STATE) };
+            unsafe { transmit(snp, state, args) };
+        }
+    }
+
+    BUSY.store(false, Ordering::Release);
+}
+
+/**
+ * @brief Builds and transmits the frame, then waits for SNP to recycle the
+ *        transmit buffer so that it can be reused for the next record.
+ */
+unsafe fn transmit(snp: *mut simple_network::Protocol, state: &mut NetState, args: fmt::Arguments) {
+    let payload_size = format_syslog(&mut state.payload, args);
+    state.identification = state.identification.wrapping_add(1);
+    let frame_size = match build_frame(
+        &mut state.frame,
+        &state.config,
+        state.identification,
+        state.payload.get(..payload_size).unwrap_or(&[]),
+    ) {
+        Some(size) => size,
+        None => return,
+    };
+
+    let frame_ptr = state.frame.as_mut_ptr() as *mut core::ffi::c_void;
+    let efi_status = ((*snp).transmit)(
+        snp,
+        0,
+        frame_size,
+        frame_ptr,
+        core::ptr::null_mut(),
+        core::ptr::null_mut(),
+        core::ptr::null_mut(),
+    );
+    if efi_status.is_error() {
+        return;
+    }
+
+    for _ in 0..TX_RECYCLE_POLLS {
+        let mut tx_buffer: *mut core::ffi::c_void = core::ptr::null_mut();
+        if ((*snp).get_status)(snp, core::ptr::null_mut(), &mut tx_buffer).is_error() {
+            break;
+        }
+        if tx_buffer == frame_ptr {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> NetConfig {
+        NetConfig {
+            src_mac: [0x52, 0x54, 0x00, 0x12, 0x34, 0x56],
+            dst_mac: [0x00, 0x11, 0x22, 0x33, 0x44, 0x55],
+            src_ip: [192, 168, 0, 10],
+            dst_ip: [192, 168, 0, 1],
+            src_port: 514,
+            dst_port: 5514,
+        }
+    }
+
+    #[test]
+    fn parses_mac() {
+        assert_eq!(
+            parse_mac("00:11:22:aa:BB:ff"),
+            Some([0x00, 0x11, 0x22, 0xaa, 0xbb, 0xff])
+        );
+        assert_eq!(
+            parse_mac("00-11-22-33-44-55"),
+            Some([0x00, 0x11, 0x22, 0x33, 0x44, 0x55])
+        );
+        assert_eq!(parse_mac("00:11:22:33:44"), None);
+        assert_eq!(parse_mac("00:11:22:33:44:55:66"), None);
+        assert_eq!(parse_mac("0:11:22:33:44:55"), None);
+        assert_eq!(parse_mac("zz:11:22:33:44:55"), None);
+    }
+
+    #[test]
+    fn parses_ipv4() {
+        assert_eq!(parse_ipv4("10.0.2.2"), Some([10, 0, 2, 2]));
+        assert_eq!(parse_ipv4("255.255.255.255"), Some([255; 4]));
+        assert_eq!(parse_ipv4("10.0.2"), None);
+        assert_eq!(parse_ipv4("10.0.2.2.1"), None);
+        assert_eq!(parse_ipv4("10.0.2.256"), None);
+    }
+
+    #[test]
+    fn checksum_matches_known_ipv4_header() {
+        // Example header with a known checksum of 0xb861.
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(internet_checksum(&header), 0xb861);
+    }
+
+    #[test]
+    fn checksum_handles_odd_length() {
+        assert_eq!(internet_checksum(&[0x01]), !0x0100);
+        assert_eq!(
+            internet_checksum(&[0x00, 0x01, 0xf2]),
+            !(0x0001u16 + 0xf200)
+        );
+    }
+
+    #[test]
+    fn builds_valid_frame() {
+        let config = test_config();
+        let payload = b"<134>1 - - uefi-var-monitor - - - hello";
+        let mut buffer = [0u8; MAX_FRAME_SIZE];
+        let size = build_frame(&mut buffer, &config, 0x1234, payload).unwrap();
+        assert_eq!(size, HEADERS_SIZE + payload.len());
+
+        let frame = &buffer[..size];
+        assert_eq!(&frame[0..6], &config.dst_mac);
+        assert_eq!(&frame[6..12], &config.src_mac);
+        assert_eq!(&frame[12..14], &[0x08, 0x00]);
+
+        let ipv4 = &frame[14..34];
+        assert_eq!(ipv4[0], 0x45);
+        assert_eq!(u16::from_be_bytes([ipv4[2], ipv4[3]]) as usize, size - 14);
+        assert_eq!(u16::from_be_bytes([ipv4[4], ipv4[5]]), 0x1234);
+        assert_eq!(ipv4[9], 17);
+        assert_eq!(&ipv4[12..16], &config.src_ip);
+        assert_eq!(&ipv4[16..20], &config.dst_ip);
+        // A header including its checksum sums to zero.
+        assert_eq!(internet_checksum(ipv4), 0);
+
+        let datagram = &frame[34..];
+        assert_eq!(u16::from_be_bytes([datagram[0], datagram[1]]), 514);
+        assert_eq!(u16::from_be_bytes([datagram[2], datagram[3]]), 5514);
+        assert_eq!(
+            u16::from_be_bytes([datagram[4], datagram[5]]) as usize,
+            UDP_HEADER_SIZE + payload.len()
+        );
+        assert_eq!(&datagram[8..], &payload[..]);
+
+        // Verifying over the pseudo header and the datagram yields zero.
+        let mut sum = checksum_add(0, &config.src_ip);
+        sum = checksum_add(sum, &config.dst_ip);
+        sum += 17 + datagram.len() as u32;
+        sum = checksum_add(sum, datagram);
+        assert_eq!(checksum_finish(sum), 0);
+    }
+
+    #[test]
+    fn rejects_oversized_payload() {
+        let config = test_config();
+        let payload = [b'x'; MAX_PAYLOAD_SIZE + 1];
+        let mut buffer = [0u8; MAX_FRAME_SIZE + 1];
+        assert_eq!(build_frame(&mut buffer, &config, 0, &payload), None);
+
+        let mut small = [0u8; HEADERS_SIZE + 3];
+        assert_eq!(build_frame(&mut small, &config, 0, b"four"), None);
+        assert_eq!(
+            build_frame(&mut small, &config, 0, b"abc"),
+            Some(HEADERS_SIZE + 3)
+        );
+    }
+
+    #[test]
+    fn formats_syslog_payload() {
+        let mut buffer = [0u8; 128];
+        let size = format_syslog(&mut buffer, format_args!("G: {} Size={:08x}\n", "Boot", 4));
+        assert_eq!(
+            &buffer[..size],
+            &b"<134>1 - - uefi-var-monitor - - - G: Boot Size=00000004"[..]
+        );
+    }
+
+    #[test]
+    fn truncates_syslog_payload() {
+        let mut buffer = [0u8; 40];
+        let size = format_syslog(
+            &mut buffer,
+            format_args!("{}", "a long message that does not fit"),
+        );
+        assert_eq!(size, 40);
+        assert_eq!(&buffer[..34], &b"<134>1 - - uefi-var-monitor - - - "[..]);
+    }
+}
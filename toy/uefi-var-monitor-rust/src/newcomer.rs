@@ -0,0 +1,311 @@
+// uefi-var-monitor-rust/src/newcomer.rs
+//
+// Variables that were not in the store at load. Most of the store is written
+// on the firmware's first boots; a variable that first comes into existence
+// late, after EndOfDxe when third-party code runs, or at OS runtime, belongs
+// to a small set worth a look. A variable is new when the inventory taken at
+// load does not list it (see inventory.rs), which is only decided if that
+// walk listed the whole store.
+//
+// The hooks look up every successful access. Every write of a new variable
+// is tagged, and the first read of one the driver had not seen before; the
+// G: or S: record ends with NEW:
+//
+//   S: <guid> Attributes=00000007 Size=00000004 <name>: 0x0 NEW
+//
+// The first observation of each is always logged, at the info level: the
+// trace filter (see filter.rs), changes mode (see seen.rs) and the reduced
+// step of the latency guard (see budget.rs) do not hold it back, and with
+// log-deferred it is formatted at once so that the tag is not lost. Only the
+// lowest step of the guard and profile-minimal, which stop before the name
+// is decoded, miss new variables.
+//
+// The first MAX_NEWCOMERS are kept with the stage of the boot they were first
+// seen in: before EndOfDxe, between EndOfDxe and ExitBootServices, or at OS
+// runtime. A driver loaded after EndOfDxe, from the shell for instance, never
+// sees the event and counts all of boot services as the first stage. The
+// counts by stage are logged at ExitBootServices and carried in the boot
+// report with the first MAX_REPORTED_NEW names (see report.rs); the report is
+// written again for each new variable found at OS runtime. Past
+// MAX_NEWCOMERS, accesses to new variables are counted and tagged as writes,
+// but it is no longer known which one is the first.
+//
+// The monitor's own variables are written around the hooks and are never
+// new. The table is only ever try-borrowed: an access finding it busy is
+// skipped.
+
+use crate::config::UVM_VENDOR_GUID;
+use crate::level::Level;
+use crate::tag::{self, Tag};
+use crate::{crc32, inventory, Phase};
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use r_efi::efi;
+use uvm_interface::protocol::TOP_NAME_SIZE;
+use uvm_interface::report::{
+    NewVariable, MAX_REPORTED_NEW, NEW_AT_RUNTIME, NEW_BEFORE_END_OF_DXE,
+    NEW_BEFORE_EXIT_BOOT_SERVICES, NEW_STAGES,
+};
+
+const TAG: Tag = tag::INTEGRITY;
+
+pub const MAX_NEWCOMERS: usize = 32;
+
+// EFI_END_OF_DXE_EVENT_GROUP_GUID
+const END_OF_DXE_EVENT_GROUP: efi::Guid = efi::Guid::from_fields(
+    0x02ce967a,
+    0xdd7e,
+    0x4ffc,
+    0x9e,
+    0xe7,
+    &[0x81, 0x0c, 0xf0, 0x47, 0x08, 0x80],
+);
+
+static END_OF_DXE: AtomicBool = AtomicBool::new(false);
+// Accesses to new variables found past the table.
+static NOT_KEPT: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sighting {
+    // The first access the driver sees to the variable.
+    First,
+    Again,
+}
+
+impl Sighting {
+    /**
+     * @brief Returns the level of the tagged record.
+     */
+    pub fn level(self) -> Level {
+        match self {
+            Sighting::First => Level::Info,
+            Sighting::Again => Level::Trace,
+        }
+    }
+}
+
+struct Table {
+    variables: [NewVariable; MAX_NEWCOMERS],
+    name_crc32s: [u32; MAX_NEWCOMERS],
+    count: usize,
+}
+
+impl Table {
+    fn kept(&self) -> &[NewVariable] {
+        self.variables.get(..self.count).unwrap_or(&[])
+    }
+
+    fn contains(&self, guid: &efi::Guid, name_crc32: u32) -> bool {
+        self.kept()
+            .iter()
+            .zip(self.name_crc32s.iter())
+            .any(|(variable, crc32)| variable.guid == *guid && *crc32 == name_crc32)
+    }
+
+    /**
+     * @brief Keeps the variable, if there is room. Returns whether it was
+     *        kept.
+     */
+    fn add(&mut self, name: &str, guid: &efi::Guid, name_crc32: u32, stage: u32) -> bool {
+        let (variable, crc32) = match (
+            self.variables.get_mut(self.count),
+            self.name_crc32s.get_mut(self.count),
+        ) {
+            (Some(variable), Some(crc32)) => (variable, crc32),
+            _ => return false,
+        };
+        let length = core::cmp::min(name.len(), TOP_NAME_SIZE);
+        *variable = NewVariable {
+            guid: *guid,
+            stage,
+            name_length: length as u32,
+            ..NewVariable::EMPTY
+        };
+        variable.name[..length].copy_from_slice(&name.as_bytes()[..length]);
+        *crc32 = name_crc32;
+        self.count += 1;
+        true
+    }
+
+    fn counts(&self) -> [u32; NEW_STAGES] {
+        let mut counts = [0; NEW_STAGES];
+        for variable in self.kept() {
+            if let Some(count) = counts.get_mut(variable.stage as usize) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+}
+
+static TABLE: AtomicRefCell<Table> = AtomicRefCell::new(Table {
+    variables: [NewVariable::EMPTY; MAX_NEWCOMERS],
+    name_crc32s: [0; MAX_NEWCOMERS],
+    count: 0,
+});
+
+/**
+ * @brief Returns the stage of the boot now, a NEW_* value.
+ */
+fn stage() -> u32 {
+    if crate::phase() == Phase::Runtime {
+        NEW_AT_RUNTIME
+    } else if END_OF_DXE.load(Ordering::Acquire) {
+        NEW_BEFORE_EXIT_BOOT_SERVICES
+    } else {
+        NEW_BEFORE_END_OF_DXE
+    }
+}
+
+/**
+ * @brief Looks up a variable a hook saw accessed successfully. Returns None
+ *        if it was in the store at load, or that is not known.
+ */
+pub fn observe(name: &str, guid: &efi::Guid) -> Option<Sighting> {
+    if *guid == UVM_VENDOR_GUID || inventory::was_at_load(name, guid) != Some(false) {
+        return None;
+    }
+    let name_crc32 = crc32::crc32(name.as_bytes());
+    let stage = stage();
+    {
+        let mut table = TABLE.try_borrow_mut().ok()?;
+        if table.contains(guid, name_crc32) {
+            return Some(Sighting::Again);
+        }
+        if !table.add(name, guid, name_crc32, stage) {
+            NOT_KEPT.fetch_add(1, Ordering::Relaxed);
+            return Some(Sighting::Again);
+        }
+    }
+    if stage == NEW_AT_RUNTIME {
+        let efi_status = crate::report::write();
+        if efi_status.is_error() {
+            error!(
+                TAG,
+                "Boot report not updated : {:#x}",
+                efi_status.as_usize()
+            );
+        }
+    }
+    Some(Sighting::First)
+}
+
+/**
+ * @brief Fills in the counts by stage and the first new variables for the
+ *        boot report. Returns how many variables were written.
+ */
+pub fn read(
+    counts: &mut [u32; NEW_STAGES],
+    variables: &mut [NewVariable; MAX_REPORTED_NEW],
+) -> usize {
+    let table = match TABLE.try_borrow_mut() {
+        Ok(table) => table,
+        Err(_) => return 0,
+    };
+    *counts = table.counts();
+    let mut written = 0;
+    for (slot, variable) in variables.iter_mut().zip(table.kept()) {
+        *slot = *variable;
+        written += 1;
+    }
+    written
+}
+
+/**
+ * @brief Registers the notification of EndOfDxe. Returns the event, which
+ *        the caller must close on unload.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> Result<r_efi::base::Event, efi::Status> {
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_end_of_dxe,
+        core::ptr::null_mut(),
+        &END_OF_DXE_EVENT_GROUP,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(event)
+}
+
+efiapi! {
+    fn handle_end_of_dxe(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        end_of_dxe();
+    }
+}
+
+/**
+ * @brief Starts the stage between EndOfDxe and ExitBootServices.
+ */
+pub fn end_of_dxe() {
+    END_OF_DXE.store(true, Ordering::Release);
+}
+
+/**
+ * @brief Logs the new variables found during boot, for the ExitBootServices
+ *        summary.
+ */
+pub fn log_summary() {
+    if !inventory::is_whole_at_load() {
+        info!(
+            TAG,
+            "New variables not looked for, the inventory at load is not whole"
+        );
+        return;
+    }
+    let counts = TABLE
+        .try_borrow_mut()
+        .map(|table| table.counts())
+        .unwrap_or([0; NEW_STAGES]);
+    info!(
+        TAG,
+        "New variables since load: {} before EndOfDxe, {} before ExitBootServices, {} accesses to others not kept",
+        counts[NEW_BEFORE_END_OF_DXE as usize],
+        counts[NEW_BEFORE_EXIT_BOOT_SERVICES as usize],
+        NOT_KEPT.load(Ordering::Relaxed)
+    );
+}
+
+#[cfg(test)]
+pub fn reset() {
+    END_OF_DXE.store(false, Ordering::Release);
+    NOT_KEPT.store(0, Ordering::Relaxed);
+    TABLE.borrow_mut().count = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_first_variables_are_kept_by_stage() {
+        let mut table = Table {
+            variables: [NewVariable::EMPTY; MAX_NEWCOMERS],
+            name_crc32s: [0; MAX_NEWCOMERS],
+            count: 0,
+        };
+        let guid = crate::classify::GLOBAL_VARIABLE_GUID;
+        assert!(table.add("Foo", &guid, 1, NEW_BEFORE_END_OF_DXE));
+        assert!(table.add("Bar", &guid, 2, NEW_AT_RUNTIME));
+        assert!(table.contains(&guid, 1));
+        assert!(!table.contains(&UVM_VENDOR_GUID, 1));
+        assert!(!table.contains(&guid, 3));
+        assert_eq!(table.kept()[1].name(), "Bar");
+        assert_eq!(table.counts(), [1, 0, 1]);
+
+        for crc32 in 3..=MAX_NEWCOMERS as u32 {
+            assert!(table.add("Baz", &guid, crc32, NEW_BEFORE_EXIT_BOOT_SERVICES));
+        }
+        assert!(!table.add("Full", &guid, 0, NEW_AT_RUNTIME));
+        assert_eq!(table.counts(), [1, MAX_NEWCOMERS as u32 - 2, 1]);
+
+        // Names are cut where the hooks cut them.
+        let long = "L".repeat(TOP_NAME_SIZE + 8);
+        table.count = 0;
+        assert!(table.add(&long, &guid, 1, NEW_BEFORE_END_OF_DXE));
+        assert_eq!(table.kept()[0].name(), &long[..TOP_NAME_SIZE]);
+    }
+}
@@ -0,0 +1,120 @@
+// uefi-var-monitor-rust/src/options.rs
+//
+// Settings for one load, given in the image's load options as key=value
+// words, e.g.
+//
+//   load UefiVarMonitor.efi level=info rate-limit=20 trace=8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*
+//
+// The keys and their values are those of settings.rs in the library, which
+// decodes and parses the options; the level they set is for this boot only:
+// the one kept in UvmLevel is not changed (see persist.rs). Options are
+// applied after the build-time configuration and the kept level, and win
+// over both. A word skipped is logged as a warning; the rest still applies
+// and the load goes on.
+
+use crate::config;
+use crate::filter;
+use crate::level;
+use crate::tag::{self, Tag};
+use r_efi::efi;
+use r_efi::protocols::loaded_image;
+use uefi_var_monitor::settings::{self, MAX_OPTIONS_SIZE};
+
+const TAG: Tag = tag::CONFIG;
+
+/**
+ * @brief Applies the settings in the load options of the image, if any.
+ *        Never fails the load. Returns whether any was taken.
+ */
+pub fn apply(boot_services: &mut efi::BootServices, image_handle: efi::Handle) -> bool {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.handle_protocol)(
+        image_handle,
+        &loaded_image::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        error!(TAG, "Load options not read : {:#x}", efi_status.as_usize());
+        return false;
+    }
+    let loaded_image = unsafe { &*(interface as *const loaded_image::Protocol) };
+    if loaded_image.load_options.is_null() || loaded_image.load_options_size == 0 {
+        return false;
+    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            loaded_image.load_options as *const u8,
+            core::cmp::min(
+                loaded_image.load_options_size as usize,
+                2 * MAX_OPTIONS_SIZE,
+            ),
+        )
+    };
+    let mut buffer = [0u8; MAX_OPTIONS_SIZE];
+    let text = settings::decode_options(bytes, &mut buffer);
+    info!(TAG, "Load options: {}", text);
+
+    let mut options = settings::Options::default();
+    config::update(|config| {
+        options = settings::parse_options(text, config, |word, problem| {
+            warn!(TAG, "Load option {} skipped: {:?}", word, problem);
+        });
+    });
+    if let Some(new) = options.level {
+        level::set_level(new);
+    }
+    if let Some(list) = options.trace_filter {
+        if let Err(efi_status) = filter::replace(list) {
+            warn!(
+                TAG,
+                "Load option trace={} skipped : {:#x}",
+                list,
+                efi_status.as_usize()
+            );
+        }
+    }
+    options.taken != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RuntimeConfig;
+    use crate::level::Level;
+    use crate::{mock, serial};
+
+    efiapi! {
+        fn no_variables(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            _data_size: *mut usize,
+            _data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            efi::Status::NOT_FOUND
+        }
+    }
+
+    #[test]
+    fn options_of_the_image_are_applied() {
+        let _lock = mock::lock();
+        let mut firmware = mock::MockFirmware::new(no_variables);
+        let mut options: std::vec::Vec<u16> = "uvm.efi level=warning rate-sustain=9 nope"
+            .encode_utf16()
+            .chain([0])
+            .collect();
+        firmware.loaded_image.load_options = options.as_mut_ptr() as *mut core::ffi::c_void;
+        firmware.loaded_image.load_options_size = (2 * options.len()) as u32;
+        let boot_services = unsafe { &mut *firmware.system_table.boot_services };
+
+        serial::start_capture();
+        assert!(apply(boot_services, mock::IMAGE_HANDLE));
+        let records = serial::take_capture();
+        assert_eq!(level::level(), Level::Warning);
+        assert_eq!(config::current().unwrap().rate_policy.sustain, 9);
+        assert!(records.contains("Load option nope skipped: Malformed"));
+
+        level::reset();
+        config::apply(&RuntimeConfig::from_build_env());
+    }
+}
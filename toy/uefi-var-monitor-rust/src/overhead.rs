@@ -0,0 +1,188 @@
+// uefi-var-monitor-rust/src/overhead.rs
+//
+// What the monitor costs: the time each GetVariable call spends in the hook
+// beyond the service it forwards to, from the cycle counter. A Measure is
+// started when the hook takes a call and times the forwarded call; when it is
+// dropped, whichever way the hook returns, the difference is added to the
+// total and the worst call kept.
+//
+// The measurement is part of what it measures: four reads of the counter and
+// two atomic additions per call. In a VM whose RDTSC takes 16 ns, it added
+// about 80 ns to a suppressed read through the hook (suppressed_read_overhead
+// in main.rs, profile-production, from 145 to 230 ns); RDTSC is several
+// times cheaper on bare metal, which was not measured.
+//
+// Ticks are turned into microseconds with the rate calibrated at load (see
+// rate.rs); without it the summary states ticks. The totals go to the
+// statistics (see interface/src/protocol.rs) and the ExitBootServices
+// summary, and are zeroed with the other counters. Each call is also handed
+// to the latency guard (see budget.rs).
+//
+// profile-minimal measures nothing: Measure is then empty and the statistics
+// read NOT_COUNTED.
+
+#[cfg(not(feature = "profile-minimal"))]
+use crate::arch::{self, Arch};
+#[cfg(not(feature = "profile-minimal"))]
+use crate::tag::{self, Tag};
+#[cfg(not(feature = "profile-minimal"))]
+use core::sync::atomic::{AtomicU64, Ordering};
+use uvm_interface::protocol::Overhead;
+
+#[cfg(not(feature = "profile-minimal"))]
+const TAG: Tag = tag::HOOK;
+
+#[cfg(not(feature = "profile-minimal"))]
+static CALLS: AtomicU64 = AtomicU64::new(0);
+#[cfg(not(feature = "profile-minimal"))]
+static TOTAL_TICKS: AtomicU64 = AtomicU64::new(0);
+#[cfg(not(feature = "profile-minimal"))]
+static MAX_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Times a call through the hook, and what it forwarded.
+ */
+#[cfg(not(feature = "profile-minimal"))]
+pub struct Measure {
+    // None without a cycle counter.
+    entered: Option<u64>,
+    forwarded: u64,
+}
+
+#[cfg(not(feature = "profile-minimal"))]
+impl Measure {
+    pub fn start() -> Self {
+        Measure {
+            entered: arch::Current::read_cycle_counter(),
+            forwarded: 0,
+        }
+    }
+
+    /**
+     * @brief Runs the call to the service, to be left out of the overhead.
+     */
+    pub fn forward<T>(&mut self, call: impl FnOnce() -> T) -> T {
+        let started = arch::Current::read_cycle_counter();
+        let result = call();
+        if let (Some(started), Some(now)) = (started, arch::Current::read_cycle_counter()) {
+            self.forwarded = self.forwarded.wrapping_add(now.wrapping_sub(started));
+        }
+        result
+    }
+}
+
+#[cfg(not(feature = "profile-minimal"))]
+impl Drop for Measure {
+    fn drop(&mut self) {
+        if let (Some(entered), Some(now)) = (self.entered, arch::Current::read_cycle_counter()) {
+            let ticks = now.wrapping_sub(entered).saturating_sub(self.forwarded);
+            record(ticks);
+            crate::budget::observe(ticks);
+        }
+    }
+}
+
+#[cfg(feature = "profile-minimal")]
+pub struct Measure;
+
+#[cfg(feature = "profile-minimal")]
+impl Measure {
+    #[inline(always)]
+    pub fn start() -> Self {
+        Measure
+    }
+
+    #[inline(always)]
+    pub fn forward<T>(&mut self, call: impl FnOnce() -> T) -> T {
+        call()
+    }
+}
+
+/**
+ * @brief Adds a call that cost the hook `ticks`.
+ */
+#[cfg(not(feature = "profile-minimal"))]
+fn record(ticks: u64) {
+    CALLS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_TICKS.fetch_add(ticks, Ordering::Relaxed);
+    // Few calls are the worst yet; the others only load.
+    if ticks > MAX_TICKS.load(Ordering::Relaxed) {
+        MAX_TICKS.fetch_max(ticks, Ordering::Relaxed);
+    }
+}
+
+/**
+ * @brief Returns the overhead measured so far, as the statistics carry it.
+ */
+#[cfg(not(feature = "profile-minimal"))]
+pub fn statistics() -> Overhead {
+    Overhead {
+        calls: CALLS.load(Ordering::Relaxed),
+        total_ticks: TOTAL_TICKS.load(Ordering::Relaxed),
+        max_ticks: MAX_TICKS.load(Ordering::Relaxed),
+        ticks_per_second: crate::rate::ticks_per_second(),
+    }
+}
+
+#[cfg(feature = "profile-minimal")]
+pub fn statistics() -> Overhead {
+    Overhead::NOT_COUNTED
+}
+
+pub fn reset() {
+    #[cfg(not(feature = "profile-minimal"))]
+    for counter in [&CALLS, &TOTAL_TICKS, &MAX_TICKS] {
+        counter.store(0, Ordering::Relaxed);
+    }
+}
+
+/**
+ * @brief Logs the overhead for the ExitBootServices summary.
+ */
+#[cfg(not(feature = "profile-minimal"))]
+pub fn log_summary() {
+    let overhead = statistics();
+    match (
+        overhead.microseconds(overhead.total_ticks),
+        overhead.microseconds(overhead.max_ticks),
+    ) {
+        (Some(total), Some(max)) => info!(
+            TAG,
+            "Monitor overhead: {} \u{b5}s total across {} calls, worst case {} \u{b5}s",
+            total,
+            overhead.calls,
+            max
+        ),
+        _ => info!(
+            TAG,
+            "Monitor overhead: {} ticks total across {} calls, worst case {} ticks",
+            overhead.total_ticks,
+            overhead.calls,
+            overhead.max_ticks
+        ),
+    }
+}
+
+#[cfg(all(test, not(feature = "profile-minimal")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_time_is_left_out() {
+        let _lock = crate::mock::lock();
+        reset();
+        {
+            let mut measure = Measure::start();
+            measure.forward(|| std::thread::sleep(std::time::Duration::from_millis(20)));
+        }
+        record(7);
+        let overhead = statistics();
+        assert_eq!(overhead.calls, 2);
+        assert!(overhead.max_ticks >= 7);
+        // The sleep alone is tens of millions of ticks.
+        assert!(overhead.total_ticks < 10_000_000);
+
+        reset();
+        assert_eq!((statistics().calls, statistics().total_ticks), (0, 0));
+    }
+}
@@ -0,0 +1,216 @@
+// uefi-var-monitor-rust/src/pattern.rs
+//
+// Lists of variables given as text, at build time or in one of the
+// monitor's own variables: entries are <guid>:<name> separated by ';', and a
+// name ending in '*' matches every name starting with what precedes it:
+//
+//   8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*;d719b2cb-3d3a-4596-a3bc-dad00e67656f:dbx
+//
+// Names are compared as the hooks log them (see convert_name): non-ASCII
+// characters become '?' and only the first 64 are kept. The monitor's
+// variables holding lists are read by read_list_variable() in main.rs.
+
+use crate::record::GuidFmt;
+use core::fmt;
+use r_efi::efi;
+
+pub const MAX_PATTERN_NAME: usize = 64;
+// Bytes of a list variable read.
+pub const MAX_LIST_SIZE: usize = 1024;
+
+#[derive(Clone, Copy)]
+pub struct Pattern {
+    guid: efi::Guid,
+    name: [u8; MAX_PATTERN_NAME],
+    length: usize,
+    // Whether the name is a prefix, written with a trailing '*'.
+    prefix: bool,
+}
+
+impl Pattern {
+    /**
+     * @brief Parses an entry in the <guid>:<name> form.
+     */
+    pub fn parse(text: &str) -> Option<Self> {
+        let (guid, name) = text.trim().split_once(|c| c == ':')?;
+        let (name, prefix) = match name.strip_suffix('*') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        if name.is_empty() && !prefix {
+            return None;
+        }
+        Self::new(name, &GuidFmt::parse(guid)?, prefix)
+    }
+
+    /**
+     * @brief Builds an entry matching exactly `name` under `guid`.
+     */
+    pub fn exact(name: &str, guid: &efi::Guid) -> Option<Self> {
+        if name.is_empty() {
+            return None;
+        }
+        Self::new(name, guid, false)
+    }
+
+    fn new(name: &str, guid: &efi::Guid, prefix: bool) -> Option<Self> {
+        if name.len() > MAX_PATTERN_NAME {
+            return None;
+        }
+        let mut pattern = Pattern {
+            guid: *guid,
+            name: [0; MAX_PATTERN_NAME],
+            length: name.len(),
+            prefix,
+        };
+        pattern.name[..name.len()].copy_from_slice(name.as_bytes());
+        Some(pattern)
+    }
+
+    /**
+     * @brief Returns the name, or the prefix without its '*'.
+     */
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(self.pattern()).unwrap_or("")
+    }
+
+    pub fn guid(&self) -> efi::Guid {
+        self.guid
+    }
+
+    pub fn is_prefix(&self) -> bool {
+        self.prefix
+    }
+
+    // Without indexing: the hooks match names on the paths that must not
+    // panic.
+    fn pattern(&self) -> &[u8] {
+        self.name.get(..self.length).unwrap_or(&[])
+    }
+
+    pub fn matches(&self, name: &str, guid: &efi::Guid) -> bool {
+        let pattern = self.pattern();
+        *guid == self.guid
+            && if self.prefix {
+                name.as_bytes().starts_with(pattern)
+            } else {
+                name.as_bytes() == pattern
+            }
+    }
+}
+
+impl fmt::Display for Pattern {
+    /**
+     * @brief Writes the entry back in the <guid>:<name> form it parses from.
+     */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let suffix = if self.prefix { "*" } else { "" };
+        write!(f, "{}:{}{}", GuidFmt(&self.guid), self.name(), suffix)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PatternTable<const N: usize> {
+    entries: [Option<Pattern>; N],
+}
+
+impl<const N: usize> PatternTable<N> {
+    pub const fn new() -> Self {
+        PatternTable { entries: [None; N] }
+    }
+
+    /**
+     * @brief Adds the entries of a ';'-separated list. Returns how many were
+     *        added and how many were malformed or did not fit.
+     */
+    pub fn add_list(&mut self, list: &str) -> (usize, usize) {
+        let (mut added, mut rejected) = (0, 0);
+        for text in list
+            .split(|c| c == ';')
+            .filter(|text| !text.trim().is_empty())
+        {
+            let pattern = Pattern::parse(text);
+            match (pattern, self.entries.iter_mut().find(|slot| slot.is_none())) {
+                (Some(pattern), Some(slot)) => {
+                    *slot = Some(pattern);
+                    added += 1;
+                }
+                _ => rejected += 1,
+            }
+        }
+        (added, rejected)
+    }
+
+    pub fn matches(&self, name: &str, guid: &efi::Guid) -> bool {
+        self.entries
+            .iter()
+            .flatten()
+            .any(|pattern| pattern.matches(name, guid))
+    }
+
+    /**
+     * @brief Returns whether any entry is under `guid`, which is all matches()
+     *        can tell without the name.
+     */
+    pub fn has_guid(&self, guid: &efi::Guid) -> bool {
+        self.entries
+            .iter()
+            .flatten()
+            .any(|pattern| pattern.guid == *guid)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|entry| entry.is_none())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.iter().flatten().count()
+    }
+
+    /**
+     * @brief Writes the entries as a ';'-separated list add_list takes back.
+     */
+    pub fn write_list(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        for (index, pattern) in self.entries.iter().flatten().enumerate() {
+            if index != 0 {
+                out.write_char(';')?;
+            }
+            write!(out, "{}", pattern)?;
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Default for PatternTable<N> {
+    fn default() -> Self {
+        PatternTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guids::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID};
+
+    #[test]
+    fn matches_exact_names_and_prefixes() {
+        let mut table = PatternTable::<3>::new();
+        let list = "8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot* ; \
+                    d719b2cb-3d3a-4596-a3bc-dad00e67656f:dbx;;bogus;\
+                    d719b2cb-3d3a-4596-a3bc-dad00e67656f:";
+        assert_eq!(table.add_list(list), (2, 2));
+
+        assert!(table.matches("BootOrder", &GLOBAL_VARIABLE_GUID));
+        assert!(table.matches("Boot0001", &GLOBAL_VARIABLE_GUID));
+        assert!(!table.matches("BootOrder", &IMAGE_SECURITY_DATABASE_GUID));
+        assert!(table.matches("dbx", &IMAGE_SECURITY_DATABASE_GUID));
+        assert!(!table.matches("dbxx", &IMAGE_SECURITY_DATABASE_GUID));
+        assert!(!table.matches("db", &IMAGE_SECURITY_DATABASE_GUID));
+
+        // Entries that do not fit are rejected.
+        let list = "8be4df61-93ca-11d2-aa0d-00e098032b8c:*;\
+                    8be4df61-93ca-11d2-aa0d-00e098032b8c:PK";
+        assert_eq!(table.add_list(list), (1, 1));
+        assert!(table.matches("Timeout", &GLOBAL_VARIABLE_GUID));
+    }
+}
@@ -0,0 +1,202 @@
+// uefi-var-monitor-rust/src/percpu.rs
+//
+// Per-CPU serial queues at OS runtime, with per-cpu. The OS may call
+// GetVariable on several CPUs at once, and they would all contend on the one
+// serial port. Instead, from ExitBootServices on, each record goes into the
+// queue of the CPU logging it (see queue.rs), indexed by its APIC ID, and
+// whoever logs next writes out every queue it can to serial.
+//
+// Memory is bounded to PER_CPU_SLOTS queues of PER_CPU_QUEUE_SIZE bytes. IDs
+// from PER_CPU_SLOTS up are hashed into the slots, so some CPUs share a
+// queue; their records are then dropped rather than waited for when they
+// log at the same time. Where the CPU ID cannot be read (see arch/), every
+// CPU shares slot 0.
+//
+// The guarantee on the order of records is weaker than inline serial output:
+//
+//   - serial receives the records queue by queue, not in the order they
+//     were logged; each is tagged with the CPU and a sequence number from a
+//     shared counter (see interface/src/percpu.rs), which uvmlog interleave
+//     orders them by again;
+//   - records of one CPU keep their order, but the sequence numbers of two
+//     CPUs logging at the same time follow the order they were taken in,
+//     which need not be the order of the accesses;
+//   - a record waits in its queue until the next record on any CPU finds
+//     serial free, so the last ones before a hang may never be written.
+//
+// A record dropped by a full or busy queue leaves a gap in the sequence
+// numbers.
+
+use crate::arch::{Arch, Current};
+use crate::queue::SpscQueue;
+use crate::serial;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use uvm_interface::percpu::CpuTag;
+
+pub const PER_CPU_SLOTS: usize = 8;
+pub const PER_CPU_QUEUE_SIZE: usize = 2048;
+
+pub struct PerCpu<const SLOTS: usize, const SIZE: usize> {
+    queues: [SpscQueue<SIZE>; SLOTS],
+    sequence: AtomicU64,
+    // Held by the one CPU writing the queues to serial.
+    draining: AtomicBool,
+}
+
+impl<const SLOTS: usize, const SIZE: usize> PerCpu<SLOTS, SIZE> {
+    pub const fn new() -> Self {
+        PerCpu {
+            queues: [const { SpscQueue::new() }; SLOTS],
+            sequence: AtomicU64::new(0),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    /**
+     * @brief Returns the slot of the CPU with `cpu_id`: its own below SLOTS,
+     *        a hashed one above.
+     */
+    pub fn slot(cpu_id: u32) -> usize {
+        if (cpu_id as usize) < SLOTS {
+            return cpu_id as usize;
+        }
+        // Fibonacci hashing spreads the IDs of the packages and cores above.
+        (cpu_id.wrapping_mul(0x9e37_79b9) as usize >> 16) % SLOTS
+    }
+
+    /**
+     * @brief Tags a record and queues it for the CPU with `cpu_id`. Returns
+     *        false if it was dropped.
+     */
+    pub fn push(&self, cpu_id: u32, args: fmt::Arguments) -> bool {
+        let tag = CpuTag {
+            cpu: cpu_id,
+            sequence: self.sequence.fetch_add(1, Ordering::Relaxed),
+        };
+        self.queues[Self::slot(cpu_id)].push_fmt(format_args!("{} {}", tag, args))
+    }
+
+    /**
+     * @brief Hands the records of every queue to `write`, queue by queue.
+     *        Returns false if another CPU is doing so.
+     */
+    pub fn drain(&self, mut write: impl FnMut(&[u8])) -> bool {
+        if self.draining.swap(true, Ordering::Acquire) {
+            return false;
+        }
+        for queue in self.queues.iter() {
+            queue.drain(&mut write);
+        }
+        self.draining.store(false, Ordering::Release);
+        true
+    }
+}
+
+static PER_CPU: PerCpu<PER_CPU_SLOTS, PER_CPU_QUEUE_SIZE> = PerCpu::new();
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Switches serial output over to the per-CPU queues. Called at
+ *        ExitBootServices, after the handoff marker.
+ */
+pub fn start() {
+    ACTIVE.store(true, Ordering::Release);
+}
+
+/**
+ * @brief Returns whether serial output goes through the per-CPU queues.
+ */
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Queues a record for the calling CPU, then writes out the queues
+ *        unless another CPU is doing so.
+ */
+#[cfg_attr(test, allow(dead_code))]
+pub fn write_record(args: fmt::Arguments) {
+    PER_CPU.push(Current::cpu_id().unwrap_or(0), args);
+    PER_CPU.drain(|record| {
+        // Truncated at a character boundary when queued.
+        let text = core::str::from_utf8(record).unwrap_or_default();
+        if serial::write_text(text).is_err() {
+            serial::record_failure();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    #[test]
+    fn ids_map_into_the_slots() {
+        type Small = PerCpu<4, 64>;
+        assert_eq!((0..4).map(Small::slot).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        let hashed: Vec<_> = (4..1024).map(Small::slot).collect();
+        assert!(hashed.iter().all(|&slot| slot < 4));
+        for slot in 0..4 {
+            assert!(hashed.contains(&slot));
+        }
+    }
+
+    // Four threads standing in for CPUs, one of them with an ID above the
+    // slots, logging and draining at the same time. Every record must reach
+    // the output whole, the records of each CPU in order, and those missing
+    // must have been counted as dropped.
+    #[test]
+    fn cpus_log_concurrently() {
+        const RECORDS: u64 = 20_000;
+        const CPUS: [u32; 4] = [0, 1, 2, 300];
+        let per_cpu = PerCpu::<4, 512>::new();
+        let output = Mutex::new(Vec::new());
+        let collect = |record: &[u8]| {
+            output
+                .lock()
+                .unwrap()
+                .push(String::from_utf8(record.to_vec()).unwrap())
+        };
+
+        std::thread::scope(|scope| {
+            for &cpu in CPUS.iter() {
+                let (per_cpu, collect) = (&per_cpu, &collect);
+                scope.spawn(move || {
+                    for index in 0..RECORDS {
+                        per_cpu.push(cpu, format_args!("cpu {} record {}", cpu, index));
+                        per_cpu.drain(collect);
+                    }
+                });
+            }
+        });
+        assert!(per_cpu.drain(collect));
+
+        let output = output.into_inner().unwrap();
+        let mut last = [None; 4];
+        let mut sequences = Vec::new();
+        for line in output.iter() {
+            let (tag, text) = CpuTag::parse(line).unwrap();
+            let cpu = CPUS.iter().position(|&cpu| cpu == tag.cpu).unwrap();
+            let index: u64 = text
+                .strip_prefix(&format!("cpu {} record ", tag.cpu))
+                .unwrap()
+                .parse()
+                .unwrap();
+            if let Some((last_index, last_sequence)) = last[cpu] {
+                assert!(index > last_index && tag.sequence > last_sequence);
+            }
+            last[cpu] = Some((index, tag.sequence));
+            sequences.push(tag.sequence);
+        }
+        sequences.sort_unstable();
+        sequences.dedup();
+        assert_eq!(sequences.len(), output.len());
+
+        let dropped: u64 = per_cpu.queues.iter().map(|queue| queue.dropped()).sum();
+        assert_eq!(output.len() as u64 + dropped, RECORDS * CPUS.len() as u64);
+    }
+}
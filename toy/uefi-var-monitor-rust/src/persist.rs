@@ -0,0 +1,292 @@
+// uefi-var-monitor-rust/src/persist.rs
+//
+// The log level, kept across boots in "UvmLevel" under UVM_VENDOR_GUID. Each
+// change through the protocol (see protocol.rs) or the control variable (see
+// control.rs) is written there, and the driver reads it back at load, before
+// the hooks are installed, so that the first access logged already uses it.
+// The data is a version and the level (see config_format.rs in the library,
+// which reads and writes it). A corrupt variable gets the build profile's
+// level instead (see profile.rs), written over it. A missing variable leaves
+// the profile's level, and is only created by the first change.
+//
+// At load the variable is read and repaired through the firmware's services,
+// as the hooks are not installed yet; afterwards it is written through the
+// saved SetVariable. Neither access is seen by our own hooks. Writes are not
+// nested: one started while another is in progress, on another CPU or from a
+// notification, is skipped and the level it carries is only kept in memory.
+//
+// Writes by others only take effect at the next load, after the same checks.
+// With enforce, they are blocked like writes to protected variables (see
+// enforce.rs), unless built with UVM_LEVEL_WRITES=allow (see settings.rs in
+// the library), so that the OS cannot lower the level of the next boot behind
+// the monitor's back. The same goes for the saved configuration in UvmConfig
+// (see config_store.rs). Our own writes bypass the hook and are not blocked
+// either way.
+// Without enforce, anyone able to write variables can set the level of the
+// next boot, as they can set the current one through UvmCtl.
+
+use crate::config::UVM_VENDOR_GUID;
+use crate::config_format::{level_data, parse_level, LEVEL_DATA_SIZE};
+use crate::level::{self, Level};
+use crate::profile;
+use crate::set_variable::SET_VARIABLE;
+use crate::tag::{self, Tag};
+use crate::SetVariableType;
+#[cfg(feature = "enforce")]
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::{AtomicBool, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::CONFIG;
+
+pub const LEVEL_VARIABLE: &str = "UvmLevel";
+
+// "UvmLevel"
+const LEVEL_VARIABLE_NAME: [u16; 9] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'L' as u16,
+    b'e' as u16,
+    b'v' as u16,
+    b'e' as u16,
+    b'l' as u16,
+    0,
+];
+
+const LEVEL_ATTRIBUTES: u32 =
+    efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+
+static WRITING: AtomicBool = AtomicBool::new(false);
+
+/**
+ * @brief Writes `level` through `set_variable`, unless a write is already in
+ *        progress.
+ */
+fn write(set_variable: SetVariableType, level: Level) -> efi::Status {
+    if WRITING.swap(true, Ordering::Acquire) {
+        return efi::Status::NOT_READY;
+    }
+    let mut name = LEVEL_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    let mut data = level_data(level);
+    let efi_status = set_variable(
+        name.as_mut_ptr(),
+        &mut guid,
+        LEVEL_ATTRIBUTES,
+        data.len(),
+        data.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    WRITING.store(false, Ordering::Release);
+    efi_status
+}
+
+/**
+ * @brief Reads the stored level through the firmware's GetVariable and
+ *        applies it, rewriting the variable if it is corrupt. Must be called
+ *        before the hooks are installed. Returns whether a stored level was
+ *        applied.
+ */
+pub fn restore(runtime_services: &efi::RuntimeServices) -> bool {
+    let mut name = LEVEL_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    let mut attributes = 0u32;
+    let mut data = [0u8; LEVEL_DATA_SIZE];
+    let mut data_size = data.len();
+    let efi_status = (runtime_services.get_variable)(
+        name.as_mut_ptr(),
+        &mut guid,
+        &mut attributes,
+        &mut data_size,
+        data.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    let stored = match efi_status {
+        efi::Status::NOT_FOUND => return false,
+        efi::Status::SUCCESS => data.get(..data_size).and_then(parse_level),
+        efi::Status::BUFFER_TOO_SMALL => None,
+        _ => {
+            error!(
+                TAG,
+                "{} not read : {:#x}",
+                LEVEL_VARIABLE,
+                efi_status.as_usize()
+            );
+            return false;
+        }
+    };
+
+    // Written whatever the level, as the load line is.
+    match stored {
+        Some(stored) => {
+            level::set_level(stored);
+            log_at!(
+                Level::Critical,
+                TAG,
+                "Log level {:?} restored from {}",
+                stored,
+                LEVEL_VARIABLE
+            );
+            true
+        }
+        None => {
+            level::set_level(profile::DEFAULT_LEVEL);
+            let efi_status = write(runtime_services.set_variable, profile::DEFAULT_LEVEL);
+            log_at!(
+                Level::Critical,
+                TAG,
+                "{} corrupt, log level reset to {:?} : {:#x}",
+                LEVEL_VARIABLE,
+                profile::DEFAULT_LEVEL,
+                efi_status.as_usize()
+            );
+            false
+        }
+    }
+}
+
+/**
+ * @brief Stores `level` for the next boots, through the saved SetVariable.
+ */
+pub fn save(level: Level) -> efi::Status {
+    let efi_status = match SET_VARIABLE.get() {
+        Some(set_variable) => write(set_variable, level),
+        None => efi::Status::NOT_READY,
+    };
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "Log level not saved to {} : {:#x}",
+            LEVEL_VARIABLE,
+            efi_status.as_usize()
+        );
+    }
+    efi_status
+}
+
+#[cfg(feature = "enforce")]
+pub use uefi_var_monitor::settings::WritePolicy;
+
+#[cfg(feature = "enforce")]
+static WRITE_POLICY: AtomicU32 = AtomicU32::new(WritePolicy::Protect as u32);
+
+#[cfg(feature = "enforce")]
+pub fn set_write_policy(policy: WritePolicy) {
+    WRITE_POLICY.store(policy as u32, Ordering::Release);
+}
+
+/**
+ * @brief Returns whether writes to the variable by others must be blocked:
+ *        UvmLevel, and UvmConfig, which sets the level too (see
+ *        config_store.rs).
+ */
+#[cfg(feature = "enforce")]
+pub fn is_protected(name: &str, guid: &efi::Guid) -> bool {
+    *guid == UVM_VENDOR_GUID
+        && (name == LEVEL_VARIABLE || name == crate::config_store::CONFIG_VARIABLE)
+        && WRITE_POLICY.load(Ordering::Acquire) == WritePolicy::Protect as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_format::LEVEL_VERSION;
+    use crate::mock;
+    use std::sync::Mutex;
+
+    // What the fake firmware holds in UvmLevel, None if it is absent.
+    static STORED: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+    efiapi! {
+        fn fake_get_variable(
+            _variable_name: *mut r_efi::base::Char16,
+            _vendor_guid: *mut r_efi::base::Guid,
+            _attributes: *mut u32,
+            data_size: *mut usize,
+            data: *mut core::ffi::c_void,
+        ) -> efi::Status {
+            let stored = STORED.lock().unwrap().clone();
+            let stored = match stored {
+                Some(stored) => stored,
+                None => return efi::Status::NOT_FOUND,
+            };
+            let capacity = unsafe { *data_size };
+            unsafe { *data_size = stored.len() };
+            if stored.len() > capacity {
+                return efi::Status::BUFFER_TOO_SMALL;
+            }
+            unsafe { core::ptr::copy_nonoverlapping(stored.as_ptr(), data as *mut u8, stored.len()) };
+            efi::Status::SUCCESS
+        }
+    }
+
+    fn restore_from(stored: Option<&[u8]>) -> Vec<mock::Write> {
+        *STORED.lock().unwrap() = stored.map(<[u8]>::to_vec);
+        let firmware = mock::MockFirmware::new(fake_get_variable);
+        level::set_level(Level::Warning);
+        let restored = restore(&firmware.runtime_services);
+        assert_eq!(restored, stored.and_then(parse_level).is_some());
+        mock::take_writes()
+    }
+
+    #[test]
+    fn stored_level_is_restored_and_corrupt_ones_rewritten() {
+        let _lock = mock::lock();
+
+        // Nothing stored: the level is left alone.
+        assert!(restore_from(None).is_empty());
+        assert_eq!(level::level(), Level::Warning);
+
+        assert!(restore_from(Some(&[LEVEL_VERSION, Level::Info as u8])).is_empty());
+        assert_eq!(level::level(), Level::Info);
+
+        for corrupt in [
+            &[LEVEL_VERSION, 4][..],
+            &[2, 0],
+            &[LEVEL_VERSION],
+            &[1, 0, 0],
+        ] {
+            let writes = restore_from(Some(corrupt));
+            assert_eq!(level::level(), profile::DEFAULT_LEVEL, "{:?}", corrupt);
+            assert_eq!(writes.len(), 1);
+            assert_eq!(writes[0].name, LEVEL_VARIABLE);
+            assert_eq!(writes[0].guid, UVM_VENDOR_GUID);
+            assert_eq!(writes[0].attributes, LEVEL_ATTRIBUTES);
+            assert_eq!(
+                writes[0].data,
+                [LEVEL_VERSION, profile::DEFAULT_LEVEL as u8]
+            );
+        }
+        level::reset();
+    }
+
+    #[test]
+    fn changes_are_saved_unless_a_write_is_in_progress() {
+        let _lock = mock::lock();
+        SET_VARIABLE.set(mock::set_variable);
+
+        assert_eq!(save(Level::Trace), efi::Status::SUCCESS);
+        let writes = mock::take_writes();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].data, [LEVEL_VERSION, Level::Trace as u8]);
+        assert_eq!(parse_level(&writes[0].data), Some(Level::Trace));
+
+        WRITING.store(true, Ordering::Release);
+        assert_eq!(save(Level::Critical), efi::Status::NOT_READY);
+        WRITING.store(false, Ordering::Release);
+        assert!(mock::take_writes().is_empty());
+    }
+
+    #[cfg(feature = "enforce")]
+    #[test]
+    fn writes_by_others_are_blocked_by_policy() {
+        let _lock = mock::lock();
+        assert!(is_protected(LEVEL_VARIABLE, &UVM_VENDOR_GUID));
+        assert!(!is_protected(
+            LEVEL_VARIABLE,
+            &crate::classify::GLOBAL_VARIABLE_GUID
+        ));
+        set_write_policy(WritePolicy::Allow);
+        assert!(!is_protected(LEVEL_VARIABLE, &UVM_VENDOR_GUID));
+        set_write_policy(WritePolicy::Protect);
+    }
+}
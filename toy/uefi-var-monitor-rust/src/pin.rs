@@ -0,0 +1,284 @@
+// uefi-var-monitor-rust/src/pin.rs
+//
+// Expected-value pinning. The UVM_PINNED list given at build time names
+// variables with the SHA-256 digest their data must have, as
+// <guid>:<name>=<digest> entries separated by ';', exact names only:
+//
+//   UVM_PINNED="8be4df61-93ca-11d2-aa0d-00e098032b8c:PK=3f2a...c901"
+//
+// The first successful read of each pinned variable is hashed and compared.
+// A match is logged once; a mismatch raises a critical alert with both
+// digests:
+//
+//   [integrity] Pin of <guid> PK verified: 3f2a...c901
+//   Pinned <guid> PK mismatch: expected 3f2a...c901, got 77e0...04bd
+//
+// Pins are only checked during boot services, where the data may always be
+// inspected (see safety.rs); a pinned variable first read at OS runtime is
+// never checked. Neither is a redacted one. There is no variable
+// counterpart of the list, as it would be writable by whoever changes the
+// pinned variables.
+
+use crate::alerts::Rule;
+use crate::pattern::Pattern;
+use crate::rules::{self, Severity};
+use crate::safety::{self, Inspection};
+use crate::sha256::{self, DIGEST_SIZE};
+use crate::tag::{self, Tag};
+use crate::{GuidFmt, Phase};
+use atomic_refcell::AtomicRefCell;
+use core::fmt;
+use r_efi::efi;
+
+const TAG: Tag = tag::INTEGRITY;
+
+pub const MAX_PINNED: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Pin {
+    pattern: Pattern,
+    digest: [u8; DIGEST_SIZE],
+    // Whether a read was compared already.
+    checked: bool,
+}
+
+static PINS: AtomicRefCell<[Option<Pin>; MAX_PINNED]> = AtomicRefCell::new([None; MAX_PINNED]);
+
+// A digest as lowercase hex.
+struct Digest<'a>(&'a [u8; DIGEST_SIZE]);
+
+impl fmt::Display for Digest<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * @brief Parses an entry in the <guid>:<name>=<digest> form.
+ */
+fn parse(text: &str) -> Option<Pin> {
+    let (variable, digest) = text.trim().rsplit_once(|c| c == '=')?;
+    let pattern = Pattern::parse(variable).filter(|pattern| !pattern.is_prefix())?;
+    Some(Pin {
+        pattern,
+        digest: parse_digest(digest.trim())?,
+        checked: false,
+    })
+}
+
+/**
+ * @brief Parses a digest written as 64 hex digits.
+ */
+fn parse_digest(text: &str) -> Option<[u8; DIGEST_SIZE]> {
+    if text.len() != 2 * DIGEST_SIZE || !text.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; DIGEST_SIZE];
+    for (index, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[2 * index..2 * index + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+/**
+ * @brief Adds the entries of a ';'-separated list. Returns how many were
+ *        added and how many rejected.
+ */
+fn add_list(pins: &mut [Option<Pin>; MAX_PINNED], list: &str) -> (usize, usize) {
+    let (mut added, mut rejected) = (0, 0);
+    for text in list
+        .split(|c| c == ';')
+        .filter(|text| !text.trim().is_empty())
+    {
+        match (parse(text), pins.iter_mut().find(|pin| pin.is_none())) {
+            (Some(pin), Some(slot)) => {
+                *slot = Some(pin);
+                added += 1;
+            }
+            _ => rejected += 1,
+        }
+    }
+    (added, rejected)
+}
+
+/**
+ * @brief Loads the pinned variables from the build.
+ */
+pub fn load() {
+    let list = match option_env!("UVM_PINNED") {
+        Some(list) => list,
+        None => return,
+    };
+    if let Ok(mut pins) = PINS.try_borrow_mut() {
+        let (added, rejected) = add_list(&mut pins, list);
+        info!(
+            TAG,
+            "Pinned from build: {} entries, {} rejected", added, rejected
+        );
+    }
+}
+
+/**
+ * @brief Returns whether a pin under `guid` is still to be checked, which is
+ *        all check_read() needs to know to rule out a read.
+ */
+pub fn watches(guid: &efi::Guid) -> bool {
+    match PINS.try_borrow_mut() {
+        Ok(pins) => pins
+            .iter()
+            .flatten()
+            .any(|pin| !pin.checked && pin.pattern.guid() == *guid),
+        Err(_) => false,
+    }
+}
+
+/**
+ * @brief Compares the first successful read of a pinned variable against
+ *        its digest. `size` is the size firmware returned.
+ */
+pub fn check_read(
+    name: &str,
+    guid: &efi::Guid,
+    efi_status: efi::Status,
+    data: *const core::ffi::c_void,
+    size: Option<usize>,
+) {
+    let size = match (efi_status, size) {
+        (efi::Status::SUCCESS, Some(size)) => size,
+        _ => return,
+    };
+    if crate::phase() != Phase::BootServices {
+        return;
+    }
+    let mut pins = match PINS.try_borrow_mut() {
+        Ok(pins) => pins,
+        Err(_) => return,
+    };
+    let pin = match pins
+        .iter_mut()
+        .flatten()
+        .find(|pin| !pin.checked && pin.pattern.matches(name, guid))
+    {
+        Some(pin) => pin,
+        None => return,
+    };
+    let data = match safety::inspect(name, guid, data, size, size) {
+        Inspection::Data(data) if data.len() == size => data,
+        _ => return,
+    };
+    pin.checked = true;
+    let digest = sha256::sha256(data);
+    if digest == pin.digest {
+        info!(
+            TAG,
+            "Pin of {} {} verified: {}",
+            GuidFmt(guid),
+            name,
+            Digest(&digest)
+        );
+        return;
+    }
+    rules::emit(
+        Rule::PinMismatch,
+        Severity::Critical,
+        format_args!(
+            "Pinned {} {} mismatch: expected {}, got {}",
+            GuidFmt(guid),
+            name,
+            Digest(&pin.digest),
+            Digest(&digest)
+        ),
+    );
+}
+
+#[cfg(test)]
+pub fn reset() {
+    *PINS.borrow_mut() = [None; MAX_PINNED];
+}
+
+#[cfg(test)]
+pub fn pin(list: &str) {
+    add_list(&mut PINS.borrow_mut(), list);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+    use crate::serial;
+
+    // SHA-256 of "abc".
+    const ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn entries_from_the_build_list() {
+        let mut pins = [None; MAX_PINNED];
+        let list = std::format!(
+            "8be4df61-93ca-11d2-aa0d-00e098032b8c:PK={};\
+             8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*={};\
+             8be4df61-93ca-11d2-aa0d-00e098032b8c:KEK=ba78;\
+             8be4df61-93ca-11d2-aa0d-00e098032b8c:db",
+            ABC,
+            ABC
+        );
+        assert_eq!(add_list(&mut pins, &list), (1, 3));
+        let pk = pins[0].unwrap();
+        assert!(pk.pattern.matches("PK", &GLOBAL_VARIABLE_GUID));
+        assert_eq!(pk.digest, sha256::sha256(b"abc"));
+        assert_eq!(std::format!("{}", Digest(&pk.digest)), ABC);
+        assert_eq!(parse_digest(&ABC.replace('a', "g")), None);
+    }
+
+    #[test]
+    fn only_the_first_read_is_compared() {
+        let _lock = crate::mock::lock();
+        reset();
+        crate::alerts::reset();
+        pin(&std::format!(
+            "8be4df61-93ca-11d2-aa0d-00e098032b8c:PK={};8be4df61-93ca-11d2-aa0d-00e098032b8c:KEK={}",
+            ABC,
+            ABC
+        ));
+        let read = |name: &str, value: &[u8]| {
+            check_read(
+                name,
+                &GLOBAL_VARIABLE_GUID,
+                efi::Status::SUCCESS,
+                value.as_ptr() as *const core::ffi::c_void,
+                Some(value.len()),
+            )
+        };
+        serial::start_capture();
+        read("PK", b"abc");
+        read("PK", b"abd");
+        check_read(
+            "KEK",
+            &GLOBAL_VARIABLE_GUID,
+            efi::Status::BUFFER_TOO_SMALL,
+            core::ptr::null(),
+            Some(3),
+        );
+        read("KEK", b"abd");
+        read("KEK", b"abc");
+        let records = serial::without_locations(&serial::take_capture());
+        reset();
+
+        let guid = GuidFmt(&GLOBAL_VARIABLE_GUID);
+        let mismatched = Digest(&sha256::sha256(b"abd"));
+        assert_eq!(
+            records,
+            std::format!(
+                "[integrity] Pin of {} PK verified: {}\n\
+                 ALERT: [critical] Pinned {} KEK mismatch: expected {}, got {}\n",
+                guid,
+                ABC,
+                guid,
+                ABC,
+                mismatched
+            )
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// uefi-var-monitor-rust/src/profile.rs
+//
+// The build profiles: umbrella features for the flavors the driver ships in,
+// each picking its features and the defaults below.
+//
+//   profile-forensics    every log sink and piece of evidence: serial, syslog,
+//                        the ring buffer and its dump, the GOP banner, the
+//                        TPM and the inventory of the store at load. Every
+//                        access is traced, and the ring buffer holds
+//                        RING_CAPACITY records.
+//   profile-production   alerts on serial, and the counters of the statistics
+//                        protocol. Records below info are dropped; raising
+//                        the level at runtime traces the boot-critical
+//                        variables only.
+//   profile-minimal      the counters only, no log sink at all. The hooks
+//                        count each call by outcome and by vendor GUID, and
+//                        return before decoding the name; records are
+//                        compiled out (see logging.rs). What it
+//                        reports goes through the statistics protocol and
+//                        the boot-report variable.
+//
+// None of them builds enforce, which turns the monitor from an observer into
+// a gate and is always a choice of its own. Selecting no profile builds the
+// features picked one by one ("custom"), with the defaults the driver always
+// had: every access traced, and a ring buffer of 256 records.
+//
+// The load record states which profile was built. Build them with
+// --no-default-features, as profile-minimal refuses the default serial sink:
+//
+//   cargo build --no-default-features --features profile-production
+
+use crate::level::Level;
+
+#[cfg(any(
+    all(feature = "profile-forensics", feature = "profile-production"),
+    all(feature = "profile-forensics", feature = "profile-minimal"),
+    all(feature = "profile-production", feature = "profile-minimal"),
+))]
+compile_error!("Select at most one of the profile-* features");
+
+#[cfg(all(
+    feature = "profile-minimal",
+    any(
+        feature = "log-serial",
+        feature = "log-net",
+        feature = "log-ring",
+        feature = "gop-alert",
+        feature = "tpm-measure",
+    )
+))]
+compile_error!(
+    "profile-minimal builds no log sink; build it with --no-default-features and no log-* feature"
+);
+
+#[cfg(all(
+    feature = "no-log",
+    any(
+        feature = "log-serial",
+        feature = "log-src-loc",
+        feature = "log-net",
+        feature = "log-ring",
+        feature = "log-inventory",
+        feature = "gop-alert",
+        feature = "tpm-measure",
+    )
+))]
+compile_error!(
+    "no-log writes nothing out; build it with --no-default-features and no log-* feature"
+);
+
+// The boot-critical variables, as a trace filter (see filter.rs).
+#[cfg(any(test, feature = "profile-production", feature = "profile-minimal"))]
+const BOOT_CRITICAL: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*;\
+                             8be4df61-93ca-11d2-aa0d-00e098032b8c:SecureBoot;\
+                             8be4df61-93ca-11d2-aa0d-00e098032b8c:PK;\
+                             8be4df61-93ca-11d2-aa0d-00e098032b8c:KEK;\
+                             d719b2cb-3d3a-4596-a3bc-dad00e67656f:db;\
+                             d719b2cb-3d3a-4596-a3bc-dad00e67656f:dbx";
+
+#[cfg(feature = "profile-forensics")]
+mod selected {
+    use super::*;
+
+    pub const NAME: &str = "forensics";
+    pub const DEFAULT_LEVEL: Level = Level::Trace;
+    pub const TRACE_FILTER: &str = "";
+    #[cfg(feature = "log-ring")]
+    pub const RING_CAPACITY: usize = 1024;
+}
+
+#[cfg(feature = "profile-production")]
+mod selected {
+    use super::*;
+
+    pub const NAME: &str = "production";
+    pub const DEFAULT_LEVEL: Level = Level::Info;
+    pub const TRACE_FILTER: &str = BOOT_CRITICAL;
+    #[cfg(feature = "log-ring")]
+    pub const RING_CAPACITY: usize = 256;
+}
+
+#[cfg(feature = "profile-minimal")]
+mod selected {
+    use super::*;
+
+    pub const NAME: &str = "minimal";
+    pub const DEFAULT_LEVEL: Level = Level::Critical;
+    pub const TRACE_FILTER: &str = BOOT_CRITICAL;
+    #[cfg(feature = "log-ring")]
+    pub const RING_CAPACITY: usize = 256;
+}
+
+#[cfg(not(any(
+    feature = "profile-forensics",
+    feature = "profile-production",
+    feature = "profile-minimal",
+)))]
+mod selected {
+    use super::*;
+
+    pub const NAME: &str = "custom";
+    pub const DEFAULT_LEVEL: Level = Level::Trace;
+    pub const TRACE_FILTER: &str = "";
+    #[cfg(feature = "log-ring")]
+    pub const RING_CAPACITY: usize = 256;
+}
+
+pub use selected::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::PatternTable;
+
+    #[test]
+    fn trace_filters_parse() {
+        assert_eq!(PatternTable::<8>::new().add_list(BOOT_CRITICAL), (6, 0));
+        assert_eq!(PatternTable::<8>::new().add_list(TRACE_FILTER).1, 0);
+    }
+}
@@ -0,0 +1,160 @@
+// uefi-var-monitor-rust/src/properties.rs
+//
+// Properties of the conversions byte order and length bugs hide in, checked
+// with proptest over random inputs:
+//
+//   - any 16 bytes, taken as an EFI_GUID, are shown by GuidFmt and
+//     hex::guid_text alike and parsed back by GuidFmt::parse to the same
+//     bytes, in either case;
+//   - any UCS-2 name decodes to what std makes of it: convert_name keeps the
+//     printable ASCII and shows a '?' for any other UTF-16 unit, and the
+//     raw records (RecordText of interface/src/ring.rs) decode the name
+//     whole, surrogate pairs included;
+//   - a line cut short (hex::Line) keeps the longest prefix of whole
+//     characters, and never splits a multi-byte sequence.
+//
+// A failing case is shrunk and printed with its input; the seed is fixed, so
+// the run is the same every time.
+
+use crate::record::{convert_name, GuidFmt, NAME_LENGTH};
+use proptest::prelude::*;
+use proptest::test_runner::{Config, RngSeed, TestCaseError};
+use r_efi::efi;
+use uvm_interface::hex::{self, Line};
+use uvm_interface::ring::{
+    AccessRecord, RecordText, RingRecord, ACCESS_NAME_LENGTH, RECORD_GET_VARIABLE,
+};
+
+const CASES: u32 = 10_000;
+
+fn config() -> Config {
+    Config {
+        cases: CASES,
+        rng_seed: RngSeed::Fixed(0x2545_f491_4f6c_dd1d),
+        // No regression files in the source tree.
+        failure_persistence: None,
+        ..Config::default()
+    }
+}
+
+/**
+ * @brief Returns a GUID with the in-memory layout `bytes`: the first three
+ *        fields little-endian, as EFI_GUID keeps them.
+ */
+fn guid_of(bytes: &[u8; 16]) -> efi::Guid {
+    efi::Guid::from_fields(
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8],
+        bytes[9],
+        &[
+            bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15],
+        ],
+    )
+}
+
+/**
+ * @brief Returns characters for a name, most often ASCII, else anywhere in
+ *        Unicode but NUL.
+ */
+fn character() -> impl Strategy<Value = char> {
+    prop_oneof![
+        2 => 1u32..0x80,
+        1 => 1u32..0x1_0000,
+        1 => 1u32..0x11_0000,
+    ]
+    .prop_filter_map("not a character", char::from_u32)
+}
+
+/**
+ * @brief Returns names of up to `length` characters.
+ */
+fn name(length: usize) -> impl Strategy<Value = String> {
+    prop::collection::vec(character(), 0..=length).prop_map(|name| name.into_iter().collect())
+}
+
+proptest! {
+    #![proptest_config(config())]
+
+    #[test]
+    fn guids_round_trip_through_their_text(bytes in any::<[u8; 16]>()) {
+        let guid = guid_of(&bytes);
+        prop_assert_eq!(guid.as_bytes(), &bytes);
+
+        let text = GuidFmt(&guid).to_string();
+        prop_assert_eq!(text.as_bytes(), &hex::guid_text(&guid)[..]);
+        let parsed = GuidFmt::parse(&text);
+        prop_assert_eq!(parsed.as_ref().map(efi::Guid::as_bytes), Some(&bytes));
+        let parsed = GuidFmt::parse(&text.to_lowercase());
+        prop_assert_eq!(parsed.as_ref().map(efi::Guid::as_bytes), Some(&bytes));
+    }
+
+    #[test]
+    fn names_decode_as_std_decodes_them(name in name(NAME_LENGTH + 8)) {
+        let units: Vec<u16> = name.encode_utf16().collect();
+
+        // One '?' per UTF-16 unit that is not printable ASCII: a character
+        // outside the BMP shows as two.
+        let mut buffer = [0u8; NAME_LENGTH];
+        let shown = convert_name(&units, &mut buffer);
+        let expected: String = char::decode_utf16(units.iter().copied())
+            .flat_map(|c| match c {
+                Ok(c) if (' '..='~').contains(&c) => vec![c],
+                Ok(c) => vec!['?'; c.len_utf16()],
+                Err(_) => vec!['?'],
+            })
+            .take(NAME_LENGTH)
+            .collect();
+        prop_assert_eq!(shown, expected);
+
+        // The raw record keeps ACCESS_NAME_LENGTH units, and may end in the
+        // first half of a pair.
+        let mut access = AccessRecord {
+            guid: guid_of(&[0; 16]),
+            status: 0,
+            cycles: 0,
+            argument: 0,
+            size: 0,
+            name: [0; ACCESS_NAME_LENGTH],
+        };
+        let kept = units.len().min(ACCESS_NAME_LENGTH);
+        access.name[..kept].copy_from_slice(&units[..kept]);
+        let record = RingRecord::raw(RECORD_GET_VARIABLE, &access);
+        let text = RecordText(&record).to_string();
+        let decoded = text
+            .strip_prefix("G: 00000000-0000-0000-0000-000000000000 Size=00000000->00000000 ")
+            .and_then(|text| text.strip_suffix(": 0x0"));
+        prop_assert_eq!(decoded, Some(&*String::from_utf16_lossy(&units[..kept])));
+    }
+
+    #[test]
+    fn cut_lines_keep_whole_characters(text in name(80)) {
+        check_cut::<1>(&text)?;
+        check_cut::<3>(&text)?;
+        check_cut::<7>(&text)?;
+        check_cut::<64>(&text)?;
+        check_cut::<224>(&text)?;
+    }
+}
+
+/**
+ * @brief Checks that `text` pushed onto an empty Line<N> keeps its longest
+ *        prefix of whole characters that fits.
+ */
+fn check_cut<const N: usize>(text: &str) -> Result<(), TestCaseError> {
+    let mut line = Line::<N>::new();
+    line.push_str(text);
+    let mut expected = text.len().min(N);
+    while !text.is_char_boundary(expected) {
+        expected -= 1;
+    }
+    prop_assert_eq!(line.as_str(), &text[..expected], "into {}", N);
+
+    // Nothing is added once a line was cut.
+    line.push_char('x');
+    if expected < text.len() {
+        prop_assert_eq!(line.as_str(), &text[..expected], "into {}", N);
+    }
+    Ok(())
+}
@@ -0,0 +1,603 @@
+// uefi-var-monitor-rust/src/protocol.rs
+//
+// The monitor's own protocol, installed on the image handle once the driver
+// has loaded successfully and uninstalled with everything else on unload
+// (see teardown.rs). It lets a second copy of the driver detect the first
+// one and refuse to load, and lets a shell application control the running
+// monitor (see tools/uvmctl):
+//
+//   pause / resume   stop and restart logging, except for critical alerts
+//   set_level        set the level of the records written (see level.rs),
+//                    kept for the next boots (see persist.rs)
+//   set_filter       replace the set of variables whose accesses are traced
+//                    with an ASCII list in the format of pattern.rs (see
+//                    filter.rs)
+//   dump             replay the ring buffer to serial (see dump.rs)
+//   get_config       read the current configuration
+//   save_config      keep the current configuration for the next boots
+//                    (see config_store.rs)
+//   get_statistics   copy everything counted so far (see counters.rs)
+//   reset_statistics zero the counters, if UVM_STATS_RESET allows it
+//   snapshot_statistics
+//                    keep a copy of the statistics, to diff against later
+//                    (see snapshot.rs)
+//   diff_statistics  copy the statistics minus a snapshot
+//   self_test        check the hooks and the sinks (see self_test.rs)
+//   read_history     copy the write histories (see history.rs)
+//   benchmark        time GetVariable calls through the hook and around it
+//                    (see benchmark.rs)
+//
+// Invalid input is rejected with INVALID_PARAMETER and changes nothing. The
+// layout is shared with the applications calling it (see
+// interface/src/protocol.rs).
+
+use crate::benchmark;
+use crate::config::{self, RuntimeConfig};
+use crate::config_store;
+use crate::control;
+use crate::counters::{self, ResetPolicy};
+#[cfg(feature = "ring-dump")]
+use crate::dump;
+use crate::filter;
+use crate::history;
+use crate::level::{self, Level};
+use crate::pattern::MAX_LIST_SIZE;
+use crate::persist;
+use crate::self_test;
+use crate::snapshot;
+use r_efi::efi;
+// Unused when every feature is built.
+use crate::tag::{self, Tag};
+#[cfg(not(all(feature = "log-ring", feature = "tpm-measure", feature = "enforce")))]
+use uvm_interface::protocol::NOT_BUILT;
+use uvm_interface::protocol::{
+    BenchmarkResult, ControlConfig, HistoryEntry, Protocol, SelfTestResult, Statistics,
+    UVM_PROTOCOL_GUID, UVM_PROTOCOL_REVISION_BENCHMARK,
+};
+
+const TAG: Tag = tag::CONFIG;
+
+/**
+ * @brief Returns the configuration get_config reports.
+ */
+fn control_config(runtime: &RuntimeConfig) -> ControlConfig {
+    ControlConfig {
+        level: level::level() as u32,
+        paused: level::is_paused() as u32,
+        filter_entries: filter::len() as u32,
+        #[cfg(feature = "log-ring")]
+        ring_overflow_policy: runtime.ring_overflow_policy as u32,
+        #[cfg(not(feature = "log-ring"))]
+        ring_overflow_policy: NOT_BUILT,
+        hook_integrity_policy: runtime.hook_integrity_policy as u32,
+        max_reinstalls: runtime.max_reinstalls,
+        runtime_data_access: runtime.runtime_data_access as u32,
+        #[cfg(feature = "tpm-measure")]
+        tpm_pcr: runtime.tpm_pcr,
+        #[cfg(not(feature = "tpm-measure"))]
+        tpm_pcr: NOT_BUILT,
+        size_factor: runtime.size_policy.factor,
+        size_limit: runtime.size_policy.limit,
+        rate_limit: runtime.rate_policy.limit,
+        rate_sustain: runtime.rate_policy.sustain,
+        #[cfg(feature = "enforce")]
+        lock_absent_policy: runtime.lock_absent_policy as u32,
+        #[cfg(not(feature = "enforce"))]
+        lock_absent_policy: NOT_BUILT,
+    }
+}
+
+static mut PROTOCOL: Protocol = Protocol {
+    revision: UVM_PROTOCOL_REVISION_BENCHMARK,
+    image_handle: core::ptr::null_mut(),
+    pause,
+    resume,
+    set_level,
+    set_filter,
+    dump,
+    get_config,
+    save_config,
+    get_statistics,
+    reset_statistics,
+    snapshot_statistics,
+    diff_statistics,
+    self_test,
+    read_history,
+    benchmark,
+};
+
+efiapi! {
+    /**
+     * @brief Pauses logging. Critical alerts are still written.
+     */
+    fn pause(_this: *mut Protocol) -> efi::Status {
+        level::pause();
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Resumes logging.
+     */
+    fn resume(_this: *mut Protocol) -> efi::Status {
+        level::resume();
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Sets the level of the records written, numbered as in
+     *        level::Level.
+     */
+    fn set_level(_this: *mut Protocol, value: u32) -> efi::Status {
+        let new = match Level::from_u32(value) {
+            Some(new) => new,
+            None => return efi::Status::INVALID_PARAMETER,
+        };
+        // Logged before the change, so that lowering the level shows too.
+        info!(TAG, "Log level: {:?} -> {:?}", level::level(), new);
+        level::set_level(new);
+        persist::save(new);
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Replaces the set of traced variables with the `size`-byte ASCII
+     *        list at `list`. An empty list traces every variable.
+     */
+    fn set_filter(_this: *mut Protocol, list: *const u8, size: usize) -> efi::Status {
+        if size > MAX_LIST_SIZE {
+            return efi::Status::BAD_BUFFER_SIZE;
+        }
+        if list.is_null() && size != 0 {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let bytes = match size {
+            0 => &[][..],
+            _ => unsafe { core::slice::from_raw_parts(list, size) },
+        };
+        let list = match core::str::from_utf8(bytes) {
+            Ok(list) => list.trim_end_matches('\0'),
+            Err(_) => return efi::Status::INVALID_PARAMETER,
+        };
+        match filter::replace(list) {
+            Ok(entries) => {
+                info!(TAG, "Trace filter: {} entries", entries);
+                efi::Status::SUCCESS
+            }
+            Err(efi_status) => efi_status,
+        }
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Requests a replay of the ring buffer to serial. It runs from the
+     *        dump timer, within half a second.
+     */
+    fn dump(_this: *mut Protocol) -> efi::Status {
+        #[cfg(feature = "ring-dump")]
+        return dump::request();
+        #[cfg(not(feature = "ring-dump"))]
+        return efi::Status::UNSUPPORTED;
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Fills `config` with the current configuration.
+     */
+    fn get_config(_this: *mut Protocol, config: *mut ControlConfig) -> efi::Status {
+        if config.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        match config::current() {
+            Some(runtime) => {
+                unsafe { config.write(control_config(&runtime)) };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_READY,
+        }
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Saves the current configuration in UvmConfig, read back at the
+     *        next load.
+     */
+    fn save_config(_this: *mut Protocol) -> efi::Status {
+        config_store::save()
+    }
+}
+
+/**
+ * @brief Copies `statistics` into the `*size` bytes at `buffer`, and sets
+ *        `*size` to the bytes copied. Fails with BUFFER_TOO_SMALL, setting
+ *        `*size` to the size needed, if they do not fit.
+ */
+fn copy_statistics(
+    statistics: &Statistics,
+    buffer: *mut core::ffi::c_void,
+    size: *mut usize,
+) -> efi::Status {
+    if size.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    let needed = core::mem::size_of::<Statistics>();
+    if unsafe { *size } < needed {
+        unsafe { *size = needed };
+        return efi::Status::BUFFER_TOO_SMALL;
+    }
+    if buffer.is_null() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    // The caller's buffer need not be aligned.
+    let bytes = statistics.as_bytes();
+    unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer as *mut u8, needed);
+        *size = needed;
+    }
+    efi::Status::SUCCESS
+}
+
+efiapi! {
+    /**
+     * @brief Copies the statistics into the `*size` bytes at `buffer`, as
+     *        copy_statistics does.
+     */
+    fn get_statistics(
+        _this: *mut Protocol,
+        buffer: *mut core::ffi::c_void,
+        size: *mut usize,
+    ) -> efi::Status {
+        copy_statistics(&counters::statistics(), buffer, size)
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Zeroes the counters, those of the statistics protocol included.
+     *        Fails with ACCESS_DENIED unless UVM_STATS_RESET allows it.
+     */
+    fn reset_statistics(_this: *mut Protocol) -> efi::Status {
+        if counters::reset_policy() != ResetPolicy::Allow {
+            return efi::Status::ACCESS_DENIED;
+        }
+        info!(TAG, "Statistics reset");
+        control::reset_counters();
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Keeps a copy of the statistics, and sets `*id` to the ID to
+     *        diff against.
+     */
+    fn snapshot_statistics(_this: *mut Protocol, id: *mut u32) -> efi::Status {
+        if id.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        match snapshot::take() {
+            Ok(taken) => {
+                unsafe { *id = taken };
+                efi::Status::SUCCESS
+            }
+            Err(error) => error.status(),
+        }
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Copies the statistics minus the snapshot `id`, as
+     *        copy_statistics does. Fails with NOT_FOUND if the snapshot is
+     *        no longer kept, and INVALID_PARAMETER if there never was one.
+     */
+    fn diff_statistics(
+        _this: *mut Protocol,
+        id: u32,
+        buffer: *mut core::ffi::c_void,
+        size: *mut usize,
+    ) -> efi::Status {
+        match snapshot::diff(id) {
+            Ok(diff) => copy_statistics(&diff, buffer, size),
+            Err(error) => error.status(),
+        }
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Runs the self-test and fills `result` with the checks run and
+     *        passed. Succeeds whatever the checks found.
+     */
+    fn self_test(_this: *mut Protocol, result: *mut SelfTestResult) -> efi::Status {
+        if result.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        unsafe { result.write(self_test::run()) };
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Fills the `*count` entries at `entries` with the writes kept in
+     *        the write histories, oldest first, and sets `*count` to the
+     *        number filled. Fails with BUFFER_TOO_SMALL, setting `*count` to
+     *        the number kept, if they do not fit.
+     */
+    fn read_history(
+        _this: *mut Protocol,
+        entries: *mut HistoryEntry,
+        count: *mut usize,
+    ) -> efi::Status {
+        if count.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let capacity = unsafe { *count };
+        let needed = history::count();
+        if capacity < needed {
+            unsafe { *count = needed };
+            return efi::Status::BUFFER_TOO_SMALL;
+        }
+        if entries.is_null() && capacity != 0 {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let entries = match capacity {
+            0 => &mut [][..],
+            _ => unsafe { core::slice::from_raw_parts_mut(entries, capacity) },
+        };
+        match history::read(entries) {
+            Some(filled) => {
+                unsafe { *count = filled };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_READY,
+        }
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Times `calls` GetVariable calls through the hook and as many
+     *        around it, and fills `result` with both times.
+     */
+    fn benchmark(_this: *mut Protocol, calls: u32, result: *mut BenchmarkResult) -> efi::Status {
+        if result.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        match benchmark::run(calls) {
+            Ok(measured) => {
+                unsafe { result.write(measured) };
+                efi::Status::SUCCESS
+            }
+            Err(efi_status) => efi_status,
+        }
+    }
+}
+
+/**
+ * @brief Returns whether an instance of the monitor has already installed
+ *        the protocol.
+ */
+pub fn is_installed(boot_services: &mut efi::BootServices) -> bool {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.locate_protocol)(
+        &UVM_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut interface,
+    );
+    !efi_status.is_error() && !interface.is_null()
+}
+
+/**
+ * @brief Installs the protocol on the image handle.
+ */
+pub fn install(boot_services: &mut efi::BootServices, image_handle: efi::Handle) -> efi::Status {
+    let mut handle = image_handle;
+    unsafe {
+        let protocol = &mut *core::ptr::addr_of_mut!(PROTOCOL);
+        protocol.image_handle = image_handle;
+        (boot_services.install_protocol_interface)(
+            &mut handle,
+            &UVM_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+            efi::InterfaceType::NativeInterface,
+            protocol as *mut _ as *mut core::ffi::c_void,
+        )
+    }
+}
+
+/**
+ * @brief Uninstalls the protocol from the image handle.
+ */
+pub fn uninstall(boot_services: &mut efi::BootServices, image_handle: efi::Handle) -> efi::Status {
+    (boot_services.uninstall_protocol_interface)(
+        image_handle,
+        &UVM_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        unsafe { &mut *core::ptr::addr_of_mut!(PROTOCOL) } as *mut _ as *mut core::ffi::c_void,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uvm_interface::protocol::STATISTICS_FLAG_DIFF;
+
+    #[test]
+    fn control_entries_validate_their_input() {
+        let _lock = crate::mock::lock();
+        level::reset();
+        let this = core::ptr::null_mut();
+        config::apply(&RuntimeConfig::from_build_env());
+
+        assert_eq!(set_level(this, 4), efi::Status::INVALID_PARAMETER);
+        assert_eq!(set_level(this, Level::Warning as u32), efi::Status::SUCCESS);
+        assert_eq!(pause(this), efi::Status::SUCCESS);
+
+        let list =
+            b"8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*;d719b2cb-3d3a-4596-a3bc-dad00e67656f:db\0";
+        assert_eq!(
+            set_filter(this, list.as_ptr(), list.len()),
+            efi::Status::SUCCESS
+        );
+        let malformed = b"8be4df61-93ca-11d2-aa0d:Boot*";
+        assert_eq!(
+            set_filter(this, malformed.as_ptr(), malformed.len()),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(
+            set_filter(this, [0xffu8].as_ptr(), 1),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(
+            set_filter(this, core::ptr::null(), 1),
+            efi::Status::INVALID_PARAMETER
+        );
+
+        let mut config = ControlConfig::default();
+        assert_eq!(
+            get_config(this, core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(get_config(this, &mut config), efi::Status::SUCCESS);
+        assert_eq!(config.level, Level::Warning as u32);
+        assert_eq!(config.paused, 1);
+        assert_eq!(config.filter_entries, 2);
+        assert_eq!(config.rate_limit, crate::rate::DEFAULT_RATE_LIMIT);
+
+        assert_eq!(resume(this), efi::Status::SUCCESS);
+        assert_eq!(set_filter(this, core::ptr::null(), 0), efi::Status::SUCCESS);
+        assert_eq!(get_config(this, &mut config), efi::Status::SUCCESS);
+        assert_eq!((config.paused, config.filter_entries), (0, 0));
+        level::reset();
+    }
+
+    #[test]
+    fn statistics_are_copied_and_reset_if_allowed() {
+        let _lock = crate::mock::lock();
+        let this = core::ptr::null_mut();
+        config::apply(&RuntimeConfig::from_build_env());
+        counters::reset();
+        counters::count(crate::top::Access::Write, efi::Status::SUCCESS, Some(8));
+
+        // Asked for the size first, then copied unaligned.
+        let mut size = 0;
+        assert_eq!(
+            get_statistics(this, core::ptr::null_mut(), &mut size),
+            efi::Status::BUFFER_TOO_SMALL
+        );
+        assert_eq!(size, core::mem::size_of::<Statistics>());
+        let mut buffer = std::vec![0u8; size + 1];
+        assert_eq!(
+            get_statistics(this, buffer[1..].as_mut_ptr() as *mut _, &mut size),
+            efi::Status::SUCCESS
+        );
+        let statistics = Statistics::parse(&buffer[1..]).unwrap();
+        assert_eq!(statistics.set_variable.by_phase.iter().sum::<u64>(), 1);
+        assert_eq!(
+            get_statistics(this, core::ptr::null_mut(), core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER
+        );
+
+        assert_eq!(reset_statistics(this), efi::Status::ACCESS_DENIED);
+        assert_ne!(counters::statistics().set_variable, Default::default());
+        config::update(|config| config.stats_reset_policy = ResetPolicy::Allow);
+        assert_eq!(reset_statistics(this), efi::Status::SUCCESS);
+        assert_eq!(counters::statistics().set_variable, Default::default());
+        config::apply(&RuntimeConfig::from_build_env());
+    }
+
+    #[test]
+    fn snapshots_are_diffed_until_overwritten() {
+        let _lock = crate::mock::lock();
+        let this = core::ptr::null_mut();
+        snapshot::reset();
+        counters::reset();
+
+        let mut id = 0;
+        assert_eq!(
+            snapshot_statistics(this, core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(snapshot_statistics(this, &mut id), efi::Status::SUCCESS);
+        counters::count(crate::top::Access::Read, efi::Status::SUCCESS, Some(4));
+
+        let mut size = 0;
+        assert_eq!(
+            diff_statistics(this, id, core::ptr::null_mut(), &mut size),
+            efi::Status::BUFFER_TOO_SMALL
+        );
+        let mut buffer = std::vec![0u8; size];
+        assert_eq!(
+            diff_statistics(this, id, buffer.as_mut_ptr() as *mut _, &mut size),
+            efi::Status::SUCCESS
+        );
+        let diff = Statistics::parse(&buffer).unwrap();
+        assert_eq!(diff.format.flags, STATISTICS_FLAG_DIFF);
+        assert_eq!(diff.get_variable.by_size.iter().sum::<u64>(), 1);
+
+        assert_eq!(
+            diff_statistics(this, id + 1, buffer.as_mut_ptr() as *mut _, &mut size),
+            efi::Status::INVALID_PARAMETER
+        );
+        let mut later = 0;
+        for _ in 0..snapshot::SNAPSHOTS {
+            assert_eq!(snapshot_statistics(this, &mut later), efi::Status::SUCCESS);
+        }
+        assert_eq!(
+            diff_statistics(this, id, buffer.as_mut_ptr() as *mut _, &mut size),
+            efi::Status::NOT_FOUND
+        );
+        counters::reset();
+    }
+
+    #[test]
+    fn write_histories_are_read_whole() {
+        let _lock = crate::mock::lock();
+        let this = core::ptr::null_mut();
+        history::reset();
+        history::load();
+        let guid = crate::classify::GLOBAL_VARIABLE_GUID;
+        for data in [[0x01u8, 0x00], [0x02, 0x00]] {
+            history::record_write(
+                "BootNext",
+                &guid,
+                efi::VARIABLE_NON_VOLATILE,
+                data.len(),
+                data.as_ptr() as *const _,
+                efi::Status::SUCCESS,
+                Some(0x1234_5678),
+            );
+        }
+
+        let mut count = 1;
+        assert_eq!(
+            read_history(this, core::ptr::null_mut(), &mut count),
+            efi::Status::BUFFER_TOO_SMALL
+        );
+        assert_eq!(count, 2);
+        let mut entries = [HistoryEntry::EMPTY; 3];
+        count = entries.len();
+        assert_eq!(
+            read_history(this, entries.as_mut_ptr(), &mut count),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(count, 2);
+        assert_eq!(entries[1].name(), "BootNext");
+        assert_eq!(entries[1].data(), [0x02, 0x00]);
+        assert_eq!(entries[1].caller_page, 0x1234_5000);
+        assert!(entries[0].sequence < entries[1].sequence);
+        assert_eq!(
+            read_history(this, entries.as_mut_ptr(), core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER
+        );
+        history::reset();
+    }
+}
@@ -0,0 +1,393 @@
+// uefi-var-monitor-rust/src/queue.rs
+//
+// Queued serial output, with serial-queue. Writing a record to the UART takes
+// about 87us per byte at 115200 baud, which log-serial otherwise spends inside
+// the hook, on every GetVariable. Instead, the serial sink formats the record
+// into a lock-free single-producer single-consumer byte queue, and a periodic
+// boot-services timer at TPL_CALLBACK writes what is queued to the UART.
+//
+// There are no timers after ExitBootServices. The serial sink's flush drains
+// the queue and switches to writing each record inline, before the handoff
+// marker; the Unload handler does the same. A record arriving while the
+// switch is under way may overtake records still queued. With per-cpu, the
+// records after the handoff marker go to the per-CPU queues instead (see
+// percpu.rs).
+//
+// Neither side ever waits. A record finding the queue full, or another record
+// being queued (a hook interrupted by one at a higher TPL), is dropped and
+// counted; so is a drain finding another drain under way, which leaves the
+// records for it.
+
+use crate::serial;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use r_efi::efi;
+
+// Bytes held by the queue, framing included.
+pub const QUEUE_SIZE: usize = 16384;
+// A queued record is truncated to this many bytes.
+pub const QUEUE_RECORD_SIZE: usize = 256;
+// Each record is preceded by its length, as a little-endian u16.
+const FRAME_SIZE: usize = 2;
+
+// Drain interval of the timer in 100ns units (10ms).
+const DRAIN_INTERVAL: u64 = 100_000;
+
+pub struct SpscQueue<const N: usize> {
+    bytes: UnsafeCell<[u8; N]>,
+    // Bytes ever queued and ever drained; positions in `bytes` are taken
+    // modulo N. Only the producer stores `tail`, only the consumer `head`.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    // Held by the one producer and the one consumer, which is what keeps the
+    // queue single-producer single-consumer.
+    producing: AtomicBool,
+    consuming: AtomicBool,
+    dropped: AtomicU64,
+}
+
+// The bytes between head and tail belong to the consumer, the others to the
+// producer, and each role is held by one caller at a time.
+unsafe impl<const N: usize> Sync for SpscQueue<N> {}
+
+impl<const N: usize> SpscQueue<N> {
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two() && N > FRAME_SIZE);
+        SpscQueue {
+            bytes: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            producing: AtomicBool::new(false),
+            consuming: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /**
+     * @brief Returns the number of records dropped so far.
+     */
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /**
+     * @brief Queues a record of at most QUEUE_RECORD_SIZE bytes. Returns
+     *        false, counting it as dropped, if the queue is full or another
+     *        record is being queued.
+     */
+    pub fn push(&self, record: &[u8]) -> bool {
+        if self.producing.swap(true, Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let tail = self.tail.load(Ordering::Relaxed);
+        // Acquire: the consumer is done reading the bytes it released.
+        let head = self.head.load(Ordering::Acquire);
+        let needed = FRAME_SIZE + record.len();
+        let queued = needed <= N - tail.wrapping_sub(head) && record.len() <= QUEUE_RECORD_SIZE;
+        if queued {
+            let length = (record.len() as u16).to_le_bytes();
+            self.copy_in(tail, &length);
+            self.copy_in(tail.wrapping_add(FRAME_SIZE), record);
+            // Release: the bytes are written before the consumer sees them.
+            self.tail
+                .store(tail.wrapping_add(needed), Ordering::Release);
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        self.producing.store(false, Ordering::Release);
+        queued
+    }
+
+    /**
+     * @brief Formats and queues a record, truncated to QUEUE_RECORD_SIZE at
+     *        a character boundary. The record is formatted on the caller's
+     *        stack, the largest array log! puts there (see footprint.rs).
+     */
+    pub fn push_fmt(&self, args: fmt::Arguments) -> bool {
+        let mut writer = RecordWriter {
+            bytes: [0; QUEUE_RECORD_SIZE],
+            length: 0,
+        };
+        let _ = fmt::Write::write_fmt(&mut writer, args);
+        self.push(writer.bytes.get(..writer.length).unwrap_or(&[]))
+    }
+
+    /**
+     * @brief Removes the oldest record into `out` and returns its length.
+     *        None if the queue is empty.
+     */
+    fn pop(&self, out: &mut [u8; QUEUE_RECORD_SIZE]) -> Option<usize> {
+        let head = self.head.load(Ordering::Relaxed);
+        // Acquire: the producer is done writing the bytes it published.
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let mut length = [0u8; FRAME_SIZE];
+        self.copy_out(head, &mut length);
+        // Never more than push() queued; the bound only spares the panic.
+        let length = core::cmp::min(u16::from_le_bytes(length) as usize, QUEUE_RECORD_SIZE);
+        self.copy_out(
+            head.wrapping_add(FRAME_SIZE),
+            out.get_mut(..length).unwrap_or(&mut []),
+        );
+        // Release: the bytes are read before the producer reuses them.
+        self.head
+            .store(head.wrapping_add(FRAME_SIZE + length), Ordering::Release);
+        Some(length)
+    }
+
+    /**
+     * @brief Hands each queued record, oldest first, to `write`. Returns
+     *        false if another drain is under way.
+     */
+    pub fn drain(&self, mut write: impl FnMut(&[u8])) -> bool {
+        if self.consuming.swap(true, Ordering::Acquire) {
+            return false;
+        }
+        let mut record = [0u8; QUEUE_RECORD_SIZE];
+        while let Some(length) = self.pop(&mut record) {
+            write(record.get(..length).unwrap_or(&[]));
+        }
+        self.consuming.store(false, Ordering::Release);
+        true
+    }
+
+    fn copy_in(&self, position: usize, data: &[u8]) {
+        let bytes = self.bytes.get() as *mut u8;
+        for (offset, &byte) in data.iter().enumerate() {
+            let index = position.wrapping_add(offset) % N;
+            unsafe { bytes.add(index).write(byte) };
+        }
+    }
+
+    fn copy_out(&self, position: usize, data: &mut [u8]) {
+        let bytes = self.bytes.get() as *const u8;
+        for (offset, byte) in data.iter_mut().enumerate() {
+            let index = position.wrapping_add(offset) % N;
+            *byte = unsafe { bytes.add(index).read() };
+        }
+    }
+}
+
+struct RecordWriter {
+    bytes: [u8; QUEUE_RECORD_SIZE],
+    length: usize,
+}
+
+impl fmt::Write for RecordWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut count = core::cmp::min(QUEUE_RECORD_SIZE.saturating_sub(self.length), s.len());
+        while !s.is_char_boundary(count) {
+            count -= 1;
+        }
+        let bytes = s.as_bytes().get(..count).unwrap_or(&[]);
+        if let Some(slot) = self.bytes.get_mut(self.length..self.length + bytes.len()) {
+            slot.copy_from_slice(bytes);
+            self.length += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+static QUEUE: SpscQueue<QUEUE_SIZE> = SpscQueue::new();
+// Set while the timer drains the queue; records are written inline
+// otherwise.
+static QUEUED: AtomicBool = AtomicBool::new(false);
+static BOOT_SERVICES: AtomicPtr<efi::BootServices> = AtomicPtr::new(core::ptr::null_mut());
+static TIMER_EVENT: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+
+/**
+ * @brief Starts the drain timer and switches serial output to the queue.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> efi::Status {
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let mut efi_status = (boot_services.create_event)(
+        efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_timer,
+        core::ptr::null_mut(),
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    efi_status = (boot_services.set_timer)(event, efi::TimerDelay::TimerPeriodic, DRAIN_INTERVAL);
+    if efi_status.is_error() {
+        (boot_services.close_event)(event);
+        return efi_status;
+    }
+    BOOT_SERVICES.store(boot_services, Ordering::Release);
+    TIMER_EVENT.store(event, Ordering::Release);
+    QUEUED.store(true, Ordering::Release);
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Switches serial output back to inline writes, drains the queue and
+ *        stops the timer. Registered as the serial sink's flush, so it runs
+ *        at ExitBootServices while boot services are still usable.
+ */
+pub fn stop() {
+    if !QUEUED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    drain();
+    let event = TIMER_EVENT.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    let boot_services = BOOT_SERVICES.load(Ordering::Acquire);
+    if !event.is_null() && !boot_services.is_null() {
+        unsafe { ((*boot_services).close_event)(event) };
+    }
+}
+
+/**
+ * @brief Returns the number of records dropped by the queue so far.
+ */
+pub fn dropped() -> u64 {
+    QUEUE.dropped()
+}
+
+/**
+ * @brief Queues one record for serial output, or writes it inline once the
+ *        queue is stopped. Registered as the serial log sink.
+ */
+#[cfg_attr(test, allow(dead_code))]
+pub fn write_record(args: fmt::Arguments) {
+    if !QUEUED.load(Ordering::Acquire) {
+        #[cfg(feature = "per-cpu")]
+        if crate::percpu::is_active() {
+            crate::percpu::write_record(args);
+            return;
+        }
+        serial::write_record(args);
+        return;
+    }
+    QUEUE.push_fmt(args);
+    // Stopped while the record was being queued; no timer is left to write it.
+    if !QUEUED.load(Ordering::Acquire) {
+        drain();
+    }
+}
+
+/**
+ * @brief Writes the queued records to serial.
+ */
+fn drain() {
+    QUEUE.drain(|record| {
+        // Truncated at a character boundary when queued.
+        let text = core::str::from_utf8(record).unwrap_or_default();
+        if serial::write_text(text).is_err() {
+            serial::record_failure();
+        }
+    });
+}
+
+efiapi! {
+    /**
+     * @brief Writes the records queued since the last tick to serial.
+     */
+    fn handle_timer(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        drain();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryInto;
+    use std::vec::Vec;
+
+    fn drain_all<const N: usize>(queue: &SpscQueue<N>) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        assert!(queue.drain(|record| records.push(record.to_vec())));
+        records
+    }
+
+    #[test]
+    fn full_or_busy_queue_drops_and_counts() {
+        let queue = SpscQueue::<16>::new();
+        assert!(queue.push(b"0123456789"));
+        // 12 of 16 bytes used; another frame of 6 does not fit.
+        assert!(!queue.push(b"abcd"));
+        assert!(queue.push(b"ab"));
+        assert_eq!(queue.dropped(), 1);
+
+        queue.producing.store(true, Ordering::Release);
+        assert!(!queue.push(b""));
+        queue.producing.store(false, Ordering::Release);
+        assert_eq!(queue.dropped(), 2);
+
+        queue.consuming.store(true, Ordering::Release);
+        assert!(!queue.drain(|_| panic!("drained while busy")));
+        queue.consuming.store(false, Ordering::Release);
+
+        assert_eq!(drain_all(&queue), [b"0123456789".to_vec(), b"ab".to_vec()]);
+        // Wraps around the end of the buffer.
+        assert!(queue.push(b"wrapped record"));
+        assert_eq!(drain_all(&queue), [b"wrapped record".to_vec()]);
+    }
+
+    #[test]
+    fn long_records_are_truncated_at_a_character_boundary() {
+        let queue = SpscQueue::<1024>::new();
+        let text = "é".repeat(QUEUE_RECORD_SIZE);
+        assert!(queue.push_fmt(format_args!("x{}", text)));
+        let records = drain_all(&queue);
+        assert_eq!(records[0].len(), QUEUE_RECORD_SIZE - 1);
+        assert!(core::str::from_utf8(&records[0]).is_ok());
+    }
+
+    // One thread queuing and one draining, on a queue small enough to wrap
+    // and fill all the time. Every record drained must be whole and in order,
+    // and every record queued must be drained or counted.
+    #[test]
+    fn producer_and_consumer_threads_agree() {
+        const RECORDS: u32 = 200_000;
+        let queue = SpscQueue::<256>::new();
+        let done = AtomicBool::new(false);
+        let record = |index: u32| -> Vec<u8> {
+            let length = (index % 40) as usize;
+            let mut bytes = index.to_le_bytes().to_vec();
+            bytes.extend((0..length).map(|offset| (index as usize + offset) as u8));
+            bytes
+        };
+
+        let (queued, drained) = std::thread::scope(|scope| {
+            let consumer = scope.spawn(|| {
+                let mut drained = 0u32;
+                let mut last = None;
+                loop {
+                    // Read before draining, so that nothing pushed before it
+                    // was set is left behind.
+                    let finished = done.load(Ordering::Acquire);
+                    queue.drain(|bytes| {
+                        let index = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+                        assert_eq!(bytes, record(index).as_slice());
+                        if let Some(last) = last {
+                            assert!(index > last);
+                        }
+                        last = Some(index);
+                        drained += 1;
+                    });
+                    if finished {
+                        return drained;
+                    }
+                }
+            });
+            let mut queued = 0u32;
+            for index in 0..RECORDS {
+                if queue.push(&record(index)) {
+                    queued += 1;
+                }
+            }
+            done.store(true, Ordering::Release);
+            (queued, consumer.join().unwrap())
+        });
+
+        assert_eq!(queued, drained);
+        assert_eq!(queue.dropped(), u64::from(RECORDS - queued));
+        assert!(drained > 0);
+    }
+}
@@ -0,0 +1,396 @@
+// uefi-var-monitor-rust/src/rate.rs
+//
+// Excessive access rate alarm. A component polling a variable thousands of
+// times a second is either broken or probing for a race; either way it is
+// worth one warning, naming the caller responsible:
+//
+//   Access rate of 8BE4DF61-... Timeout at 2400 calls/s for 3 s: 7180 of 7204
+//   calls from 0x7e6a1000
+//
+// GetVariable and SetVariable calls are counted per variable over one-second
+// windows of the cycle counter, calibrated against the Stall boot service at
+// load. A variable whose rate stays at or above RatePolicy::limit for
+// RatePolicy::sustain consecutive windows raises the warning once. It is only
+// raised again after a window below the limit rearmed it, so a steady poller
+// is reported once rather than every second. Without a cycle counter, or if
+// calibration failed, nothing is counted. The counter is assumed to keep
+// running at the same rate at OS runtime, as an invariant TSC does.
+//
+// Callers are bucketed by the 4 KiB page of their return address, which
+// keeps calls from one function together, and the first MAX_BUCKETS buckets
+// seen in a sustained period are counted; any later one only counts towards
+// the total.
+//
+// Variables are keyed by vendor GUID and name CRC32 in a bounded table that,
+// like the one in seen.rs, is only ever try-borrowed and replaces its oldest
+// entry once full.
+
+use crate::alerts::Rule;
+use crate::arch::{self, Arch};
+use crate::crc32;
+use crate::rules::{self, Severity};
+use crate::set_variable::ReturnAddress;
+use crate::tag::{self, Tag};
+use crate::GuidFmt;
+use atomic_refcell::AtomicRefCell;
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use r_efi::efi;
+
+pub use uefi_var_monitor::settings::{RatePolicy, DEFAULT_RATE_LIMIT, DEFAULT_RATE_SUSTAIN};
+
+const TAG: Tag = tag::HOOK;
+
+pub const MAX_TRACKED: usize = 32;
+pub const MAX_BUCKETS: usize = 4;
+// Callers are bucketed by page.
+pub const BUCKET_MASK: usize = !0xfff;
+// How long calibration stalls for, in microseconds.
+const CALIBRATION_STALL: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bucket {
+    // Page of the return address, None if it is not known.
+    pub page: Option<usize>,
+    pub calls: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Alarm {
+    Raised {
+        // Calls per second in the last window.
+        rate: u32,
+        seconds: u32,
+        // The bucket with the most calls in the sustained period.
+        top: Bucket,
+        total: u32,
+    },
+    Rearmed {
+        rate: u32,
+    },
+}
+
+#[derive(Clone, Copy)]
+struct Tracked {
+    guid: efi::Guid,
+    name_crc32: u32,
+    window_start: u64,
+    // Calls in the current window.
+    calls: u32,
+    // Consecutive excessive windows, and the calls made in them.
+    excessive: u32,
+    period_calls: u32,
+    buckets: [Option<Bucket>; MAX_BUCKETS],
+    raised: bool,
+}
+
+impl Tracked {
+    fn count_caller(&mut self, caller: Option<usize>) {
+        let page = caller.map(|caller| caller & BUCKET_MASK);
+        for slot in self.buckets.iter_mut() {
+            match slot {
+                Some(bucket) if bucket.page == page => {
+                    bucket.calls = bucket.calls.saturating_add(1);
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    *slot = Some(Bucket { page, calls: 1 });
+                    return;
+                }
+            }
+        }
+    }
+
+    fn top(&self) -> Bucket {
+        self.buckets
+            .iter()
+            .flatten()
+            .copied()
+            .max_by_key(|bucket| bucket.calls)
+            .unwrap_or(Bucket {
+                page: None,
+                calls: 0,
+            })
+    }
+}
+
+pub struct Table<const N: usize> {
+    entries: [Option<Tracked>; N],
+    // Entry replaced next once the table is full.
+    next: usize,
+}
+
+impl<const N: usize> Table<N> {
+    pub const fn new() -> Self {
+        Table {
+            entries: [None; N],
+            next: 0,
+        }
+    }
+
+    /**
+     * @brief Returns the entry of a variable, taking a free or the oldest
+     *        one, with a window starting at `now`, if it has none.
+     */
+    fn entry(&mut self, guid: &efi::Guid, name_crc32: u32, now: u64) -> Option<&mut Tracked> {
+        let position = self.entries.iter().position(|entry| match entry {
+            Some(tracked) => tracked.name_crc32 == name_crc32 && tracked.guid == *guid,
+            None => false,
+        });
+        let index = match position {
+            Some(index) => index,
+            None => {
+                let index = match self.entries.iter().position(|entry| entry.is_none()) {
+                    Some(index) => index,
+                    None => {
+                        let index = self.next;
+                        self.next = (self.next + 1) % N;
+                        index
+                    }
+                };
+                *self.entries.get_mut(index)? = Some(Tracked {
+                    guid: *guid,
+                    name_crc32,
+                    window_start: now,
+                    calls: 0,
+                    excessive: 0,
+                    period_calls: 0,
+                    buckets: [None; MAX_BUCKETS],
+                    raised: false,
+                });
+                index
+            }
+        };
+        self.entries.get_mut(index)?.as_mut()
+    }
+
+    /**
+     * @brief Counts a call to a variable from `caller` at `now`, in cycle
+     *        counter ticks. Returns the alarm raised or rearmed by the window
+     *        it closes, if any.
+     */
+    pub fn observe(
+        &mut self,
+        name: &str,
+        guid: &efi::Guid,
+        caller: Option<usize>,
+        now: u64,
+        ticks_per_second: u64,
+        policy: &RatePolicy,
+    ) -> Option<Alarm> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        let tracked = self.entry(guid, name_crc32, now)?;
+        let mut alarm = None;
+        let elapsed = now.wrapping_sub(tracked.window_start);
+        if elapsed >= ticks_per_second {
+            let rate = u64::from(tracked.calls).saturating_mul(ticks_per_second) / elapsed;
+            let rate = u32::try_from(rate).unwrap_or(u32::MAX);
+            if rate >= policy.limit {
+                tracked.excessive = tracked.excessive.saturating_add(1);
+                if tracked.excessive >= policy.sustain && !tracked.raised {
+                    tracked.raised = true;
+                    alarm = Some(Alarm::Raised {
+                        rate,
+                        seconds: tracked.excessive,
+                        top: tracked.top(),
+                        total: tracked.period_calls,
+                    });
+                }
+            } else {
+                if tracked.raised {
+                    tracked.raised = false;
+                    alarm = Some(Alarm::Rearmed { rate });
+                }
+                tracked.excessive = 0;
+                tracked.period_calls = 0;
+                tracked.buckets = [None; MAX_BUCKETS];
+            }
+            tracked.window_start = now;
+            tracked.calls = 0;
+        }
+        tracked.calls = tracked.calls.saturating_add(1);
+        tracked.period_calls = tracked.period_calls.saturating_add(1);
+        tracked.count_caller(caller);
+        alarm
+    }
+}
+
+static TABLE: AtomicRefCell<Table<MAX_TRACKED>> = AtomicRefCell::new(Table::new());
+// Cycle counter ticks per second, 0 until calibrated.
+static TICKS_PER_SECOND: AtomicU64 = AtomicU64::new(0);
+static LIMIT: AtomicU32 = AtomicU32::new(DEFAULT_RATE_LIMIT);
+static SUSTAIN: AtomicU32 = AtomicU32::new(DEFAULT_RATE_SUSTAIN);
+
+/**
+ * @brief Sets what makes an access rate excessive. A sustain below 1 is
+ *        raised to 1.
+ */
+pub fn set_policy(policy: RatePolicy) {
+    LIMIT.store(policy.limit, Ordering::Release);
+    SUSTAIN.store(core::cmp::max(policy.sustain, 1), Ordering::Release);
+}
+
+/**
+ * @brief Measures the cycle counter against the Stall boot service. Returns
+ *        UNSUPPORTED if there is no cycle counter or it did not advance.
+ */
+pub fn calibrate(boot_services: &efi::BootServices) -> efi::Status {
+    let start = match arch::Current::read_cycle_counter() {
+        Some(start) => start,
+        None => return efi::Status::UNSUPPORTED,
+    };
+    let efi_status = (boot_services.stall)(CALIBRATION_STALL);
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    let end = arch::Current::read_cycle_counter().unwrap_or(start);
+    let ticks = end.wrapping_sub(start);
+    if ticks == 0 {
+        return efi::Status::UNSUPPORTED;
+    }
+    let ticks_per_second = ticks.saturating_mul(1_000_000 / CALIBRATION_STALL as u64);
+    TICKS_PER_SECOND.store(ticks_per_second, Ordering::Release);
+    info!(
+        TAG,
+        "Access rate alarm at {} calls/s over {} s, {} ticks/s",
+        LIMIT.load(Ordering::Acquire),
+        SUSTAIN.load(Ordering::Acquire),
+        ticks_per_second
+    );
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Returns the ticks per second of the cycle counter, 0 until
+ *        calibrated.
+ */
+#[cfg_attr(feature = "profile-minimal", allow(dead_code))]
+pub fn ticks_per_second() -> u64 {
+    TICKS_PER_SECOND.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Returns the cycle counter and its ticks per second, None until
+ *        calibrated.
+ */
+pub fn clock() -> Option<(u64, u64)> {
+    let ticks_per_second = TICKS_PER_SECOND.load(Ordering::Acquire);
+    if ticks_per_second == 0 {
+        return None;
+    }
+    arch::Current::read_cycle_counter().map(|now| (now, ticks_per_second))
+}
+
+/**
+ * @brief Counts a call to a variable, unless the table is in use, and warns
+ *        once its rate stays excessive.
+ */
+pub fn observe(name: &str, guid: &efi::Guid, caller: Option<usize>) {
+    let policy = RatePolicy {
+        limit: LIMIT.load(Ordering::Acquire),
+        sustain: SUSTAIN.load(Ordering::Acquire),
+    };
+    let ticks_per_second = TICKS_PER_SECOND.load(Ordering::Acquire);
+    if policy.limit == 0 || ticks_per_second == 0 {
+        return;
+    }
+    let now = match arch::Current::read_cycle_counter() {
+        Some(now) => now,
+        None => return,
+    };
+    let alarm = match TABLE.try_borrow_mut() {
+        Ok(mut table) => table.observe(name, guid, caller, now, ticks_per_second, &policy),
+        Err(_) => return,
+    };
+    match alarm {
+        Some(Alarm::Raised {
+            rate,
+            seconds,
+            top,
+            total,
+        }) => rules::emit(
+            Rule::AccessRate,
+            Severity::Warning,
+            format_args!(
+                "Access rate of {} {} at {} calls/s for {} s: {} of {} calls from {}",
+                GuidFmt(guid),
+                name,
+                rate,
+                seconds,
+                top.calls,
+                total,
+                ReturnAddress(top.page)
+            ),
+        ),
+        Some(Alarm::Rearmed { rate }) => info!(
+            TAG,
+            "Access rate of {} {} down to {} calls/s, alarm rearmed",
+            GuidFmt(guid),
+            name,
+            rate
+        ),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+
+    #[test]
+    fn sustained_rates_raise_one_alarm_until_rearmed() {
+        let policy = RatePolicy {
+            limit: 100,
+            sustain: 3,
+        };
+        let guid = &GLOBAL_VARIABLE_GUID;
+        let mut table = Table::<2>::new();
+        let mut now = 0u64;
+        // Runs `calls` calls spread over one 1000-tick second, mostly from
+        // one page. Returns the alarms they raised.
+        let mut second = |table: &mut Table<2>, calls: u64| {
+            let mut alarms = std::vec::Vec::new();
+            for call in 0..calls {
+                let caller = if call % 10 == 0 { 0x5000 } else { 0x7123 };
+                let at = now + call * 1000 / calls;
+                alarms.extend(table.observe("Timeout", guid, Some(caller), at, 1000, &policy));
+            }
+            now += 1000;
+            alarms
+        };
+
+        assert!(second(&mut table, 150).is_empty());
+        assert!(second(&mut table, 150).is_empty());
+        assert!(second(&mut table, 150).is_empty());
+        // The third excessive second closes in the fourth.
+        let alarms = second(&mut table, 50);
+        assert_eq!(
+            alarms,
+            [Alarm::Raised {
+                rate: 150,
+                seconds: 3,
+                top: Bucket {
+                    page: Some(0x7000),
+                    calls: 405
+                },
+                total: 450
+            }]
+        );
+        // A slower second rearms it.
+        assert_eq!(second(&mut table, 150), [Alarm::Rearmed { rate: 50 }]);
+        assert!(second(&mut table, 150).is_empty());
+
+        // A busy second after an idle gap does not continue the period.
+        let mut table = Table::<2>::new();
+        for at in [0, 10, 5000] {
+            for call in 0..150 {
+                assert_eq!(
+                    table.observe("Timeout", guid, None, at * 1000 + call, 1000, &policy),
+                    None
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,553 @@
+// uefi-var-monitor-rust/src/record.rs
+//
+// The G: and S: records the hooks write for each access, and the pieces they
+// are made of: the variable name as the hooks render it, the vendor GUID in
+// registry format and the data sizes. uvmlog and any other parser read these
+// records, so they are pinned against the core::fmt strings they replaced:
+//
+//   G: <guid> Size=<before>-><after> <name>: <status>
+//   S: <guid> Attributes=<attributes> Size=<size> <name>: <status>
+//
+// The records are built by hand rather than through core::fmt, as one is
+// logged on every traced access. What a hook knows of an access is passed
+// as fields (see fields.rs in the driver), and line() writes them: the record
+// of the service, then the extras in the order given, such as how the data
+// changed and the NEW tag (see seen.rs and newcomer.rs). A call with a null
+// name or GUID, and the note of a read (Op::Accessed), are written as the
+// lines they replaced.
+
+use core::fmt::{self, Write};
+use r_efi::efi;
+use uvm_interface::hex::{self, Case};
+
+// Characters of a variable name convert_name() reads.
+pub const NAME_LENGTH: usize = 64;
+
+// Longest G: or S: record built by hand: a name of 64 bytes (see
+// convert_name), 64-bit sizes and status, 166 bytes, then up to 41 bytes on
+// how the data changed and the NEW tag (see newcomer.rs), 211 bytes.
+pub const RECORD_LINE_SIZE: usize = 224;
+
+pub type RecordLine = hex::Line<RECORD_LINE_SIZE>;
+
+/**
+ * @brief Converts a variable name to ASCII up to NAME_LENGTH characters,
+ *        replacing anything else with '?'. Stops at the first NUL or the end
+ *        of `characters`, whichever comes first. Inline, so that the
+ *        driver's no-panic test (see main.rs) still sees into it.
+ */
+#[inline]
+pub fn convert_name<'a>(characters: &[u16], buffer: &'a mut [u8; NAME_LENGTH]) -> &'a str {
+    let mut length = 0;
+    let characters = characters.iter().take_while(|c| **c != 0);
+    for (slot, &c) in buffer.iter_mut().zip(characters) {
+        *slot = if (0x20..0x7f).contains(&c) {
+            c as u8
+        } else {
+            b'?'
+        };
+        length += 1;
+    }
+    // Only printable ASCII was stored.
+    unsafe { core::str::from_utf8_unchecked(buffer.get(..length).unwrap_or(&[])) }
+}
+
+// A vendor GUID for logging, in registry format.
+pub struct GuidFmt<'a>(pub &'a efi::Guid);
+
+impl fmt::Display for GuidFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data = self.0.as_fields();
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            data.0,
+            data.1,
+            data.2,
+            data.3,
+            data.4,
+            data.5[0],
+            data.5[1],
+            data.5[2],
+            data.5[3],
+            data.5[4],
+            data.5[5],
+        )
+    }
+}
+
+impl GuidFmt<'_> {
+    /**
+     * @brief Parses a GUID in registry format, as GuidFmt displays it, in
+     *        either case and without braces; whitespace around it is ignored.
+     */
+    pub fn parse(text: &str) -> Option<efi::Guid> {
+        let mut parts = text.trim().split(|c| c == '-');
+        // Hex digits only: from_str_radix would take a leading '+'.
+        let mut field = |length: usize| {
+            parts
+                .next()
+                .filter(|part| part.len() == length && part.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|part| u64::from_str_radix(part, 16).ok())
+        };
+        let data1 = field(8)? as u32;
+        let data2 = field(4)? as u16;
+        let data3 = field(4)? as u16;
+        let data4 = field(4)? as u16;
+        let node = field(12)?.to_be_bytes();
+        if parts.next().is_some() {
+            return None;
+        }
+        let [clock_seq_high, clock_seq_low] = data4.to_be_bytes();
+        Some(efi::Guid::from_fields(
+            data1,
+            data2,
+            data3,
+            clock_seq_high,
+            clock_seq_low,
+            &[node[2], node[3], node[4], node[5], node[6], node[7]],
+        ))
+    }
+}
+
+// A DataSize value for logging, "n/a" if it is not known.
+pub struct DataSize(pub Option<usize>);
+
+impl fmt::Display for DataSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(size) => write!(f, "{:08x}", size),
+            None => f.write_str("n/a"),
+        }
+    }
+}
+
+/**
+ * @brief Appends a size as DataSize displays it.
+ */
+fn push_data_size(line: &mut RecordLine, size: Option<usize>) {
+    match size {
+        Some(size) => line.push_hex(size as u64, 8, Case::Lower),
+        None => line.push_str("n/a"),
+    };
+}
+
+/**
+ * @brief Returns the G: record of an access, the same bytes as
+ *        "G: {} Size={}->{} {}: {:#x}" with GuidFmt and DataSize, written
+ *        without core::fmt as it is logged on every traced read. The GUID
+ *        comes as text, from the name cache (see names.rs).
+ */
+pub fn get_record(
+    guid: &str,
+    size_before: Option<usize>,
+    size_after: Option<usize>,
+    name: &str,
+    efi_status: efi::Status,
+) -> RecordLine {
+    let mut line = RecordLine::new();
+    line.push_str("G: ").push_str(guid).push_str(" Size=");
+    push_data_size(&mut line, size_before);
+    line.push_str("->");
+    push_data_size(&mut line, size_after);
+    line.push_str(" ")
+        .push_str(name)
+        .push_str(": ")
+        .push_prefixed_hex(efi_status.as_usize() as u64);
+    line
+}
+
+/**
+ * @brief Returns the S: record of an access, the same bytes as
+ *        "S: {} Attributes={:08x} Size={:08x} {}: {:#x}" with GuidFmt,
+ *        written without core::fmt like the G: record (see get_record()).
+ */
+pub fn set_record(
+    guid: &str,
+    attributes: u32,
+    data_size: usize,
+    name: &str,
+    efi_status: efi::Status,
+) -> RecordLine {
+    let mut line = RecordLine::new();
+    line.push_str("S: ")
+        .push_str(guid)
+        .push_str(" Attributes=")
+        .push_hex(u64::from(attributes), 8, Case::Lower)
+        .push_str(" Size=")
+        .push_hex(data_size as u64, 8, Case::Lower)
+        .push_str(" ")
+        .push_str(name)
+        .push_str(": ")
+        .push_prefixed_hex(efi_status.as_usize() as u64);
+    line
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    // A G: record.
+    Get,
+    // An S: record.
+    Set,
+    // The note of a read the GetVariable hook logs after its checks, with
+    // the name and the size returned.
+    Accessed,
+}
+
+impl Op {
+    fn service(self) -> &'static str {
+        match self {
+            Op::Get | Op::Accessed => "GetVariable",
+            Op::Set => "SetVariable",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Size {
+    // DataSize on entry and as returned, for a read.
+    Read {
+        before: Option<usize>,
+        after: Option<usize>,
+    },
+    // Attributes and DataSize, for a write.
+    Write {
+        attributes: u32,
+        data_size: usize,
+    },
+}
+
+// What a read returned against the digest kept for the variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Content {
+    // No digest was kept: never read, or replaced since.
+    First,
+    Unchanged,
+    Changed { unchanged_reads: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extra {
+    // How the data compares with the last read, in changes mode (see
+    // seen.rs).
+    Content(Option<Content>),
+    // A variable created since load (see newcomer.rs).
+    New,
+    // The cycle counter when the call returned, if there is one.
+    Ticks(Option<u64>),
+}
+
+impl Extra {
+    fn push(self, line: &mut RecordLine) {
+        match self {
+            Extra::Content(Some(Content::First)) => {
+                line.push_str(" first read");
+            }
+            Extra::Content(Some(Content::Changed { unchanged_reads })) => {
+                let _ = write!(line, " changed after {} unchanged reads", unchanged_reads);
+            }
+            Extra::Content(Some(Content::Unchanged) | None) | Extra::Ticks(None) => {}
+            Extra::New => {
+                line.push_str(" NEW");
+            }
+            Extra::Ticks(Some(ticks)) => {
+                let _ = write!(line, " at {} ticks", ticks);
+            }
+        }
+    }
+
+    /**
+     * @brief Returns whether the extra writes nothing, without formatting.
+     */
+    pub fn is_empty(self) -> bool {
+        matches!(
+            self,
+            Extra::Content(Some(Content::Unchanged) | None) | Extra::Ticks(None)
+        )
+    }
+}
+
+/**
+ * @brief Returns the record of an access: `op` on the variable whose GUID
+ *        and name are `variable` as rendered, None for a call with a null
+ *        name or GUID, then `extras` in order.
+ */
+pub fn line(
+    op: Op,
+    variable: Option<(&str, &str)>,
+    size: Size,
+    efi_status: efi::Status,
+    extras: &[Extra],
+) -> RecordLine {
+    let (guid, name) = match variable {
+        Some(variable) => variable,
+        None => {
+            let mut line = RecordLine::new();
+            line.push_str(op.service())
+                .push_str(" called with a null name or GUID: ")
+                .push_prefixed_hex(efi_status.as_usize() as u64);
+            return line;
+        }
+    };
+    let mut line = match (op, size) {
+        (Op::Accessed, Size::Read { after, .. }) => {
+            let mut line = RecordLine::new();
+            let _ = write!(
+                line,
+                "Accessed variable: {}, Size: {}",
+                name,
+                DataSize(after)
+            );
+            line
+        }
+        (_, Size::Read { before, after }) => get_record(guid, before, after, name, efi_status),
+        (
+            _,
+            Size::Write {
+                attributes,
+                data_size,
+            },
+        ) => set_record(guid, attributes, data_size, name, efi_status),
+    };
+    for extra in extras {
+        extra.push(&mut line);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guids::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID, UVM_VENDOR_GUID};
+
+    #[test]
+    fn parses_guids() {
+        assert_eq!(
+            GuidFmt::parse("8be4df61-93ca-11d2-aa0d-00e098032b8c"),
+            Some(GLOBAL_VARIABLE_GUID)
+        );
+        assert_eq!(
+            GuidFmt::parse("D719B2CB-3D3A-4596-A3BC-DAD00E67656F"),
+            Some(IMAGE_SECURITY_DATABASE_GUID)
+        );
+        assert_eq!(GuidFmt::parse("8be4df61-93ca-11d2-aa0d"), None);
+        assert_eq!(
+            GuidFmt::parse("8be4df61-93ca-11d2-aa0d-00e098032b8c-00"),
+            None
+        );
+        assert_eq!(GuidFmt::parse("8be4df6-193ca-11d2-aa0d-00e098032b8c"), None);
+        assert_eq!(GuidFmt::parse("8be4df61-93ca-11d2-aa0d-00e098032bxx"), None);
+        assert_eq!(GuidFmt::parse("+be4df61-93ca-11d2-aa0d-00e098032b8c"), None);
+    }
+
+    #[test]
+    fn names_are_cut_at_the_terminator_or_the_buffer() {
+        let mut buffer = [0u8; NAME_LENGTH];
+        let name: Vec<u16> = "Bo\u{e9}t\0Order".encode_utf16().collect();
+        assert_eq!(convert_name(&name, &mut buffer), "Bo?t");
+        assert_eq!(convert_name(&name[..2], &mut buffer), "Bo");
+        assert_eq!(convert_name(&[], &mut buffer), "");
+
+        let long = vec![u16::from(b'L'); NAME_LENGTH + 8];
+        assert_eq!(convert_name(&long, &mut buffer).len(), NAME_LENGTH);
+    }
+
+    #[test]
+    fn data_sizes_are_shown_in_hex() {
+        assert_eq!(DataSize(Some(0x80)).to_string(), "00000080");
+        assert_eq!(DataSize(None).to_string(), "n/a");
+    }
+
+    // The G: record built by hand must not differ by a byte from the one
+    // core::fmt made, which uvmlog and any other parser read.
+    #[test]
+    fn get_records_match_core_fmt() {
+        let guids = [
+            GLOBAL_VARIABLE_GUID,
+            UVM_VENDOR_GUID,
+            efi::Guid::from_fields(0, 0, 0, 0, 0, &[0; 6]),
+        ];
+        let sizes = [None, Some(0), Some(8), Some(0x1234_5678), Some(usize::MAX)];
+        let statuses = [
+            efi::Status::SUCCESS,
+            efi::Status::BUFFER_TOO_SMALL,
+            efi::Status::NOT_FOUND,
+        ];
+        for guid in guids.iter() {
+            for &size_before in sizes.iter() {
+                for &size_after in sizes.iter() {
+                    for &efi_status in statuses.iter() {
+                        let text = GuidFmt(guid).to_string();
+                        let record =
+                            get_record(&text, size_before, size_after, "Boot0001", efi_status);
+                        assert_eq!(
+                            record.as_str(),
+                            format!(
+                                "G: {} Size={}->{} {}: {:#x}",
+                                GuidFmt(guid),
+                                DataSize(size_before),
+                                DataSize(size_after),
+                                "Boot0001",
+                                efi_status.as_usize(),
+                            )
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_records_match_core_fmt() {
+        let guid = GLOBAL_VARIABLE_GUID;
+        for &attributes in [0, 7, u32::MAX].iter() {
+            for &data_size in [0, 4, usize::MAX].iter() {
+                let efi_status = efi::Status::WRITE_PROTECTED;
+                let text = GuidFmt(&guid).to_string();
+                let record = set_record(&text, attributes, data_size, "db", efi_status);
+                assert_eq!(
+                    record.as_str(),
+                    format!(
+                        "S: {} Attributes={:08x} Size={:08x} {}: {:#x}",
+                        GuidFmt(&guid),
+                        attributes,
+                        data_size,
+                        "db",
+                        efi_status.as_usize(),
+                    )
+                );
+            }
+        }
+    }
+
+    // The G: record through core::fmt against built by hand, each into a
+    // buffer on the stack:
+    //
+    //   cargo test --release --lib -- --ignored --nocapture get_record_formatting
+    #[test]
+    #[ignore]
+    fn get_record_formatting_speed() {
+        use core::fmt::Write;
+        const CALLS: u32 = 1_000_000;
+        let guid = GLOBAL_VARIABLE_GUID;
+        let time = |format: &mut dyn FnMut() -> usize| {
+            let start = std::time::Instant::now();
+            let mut length = 0;
+            for _ in 0..CALLS {
+                length += core::hint::black_box(format());
+            }
+            assert!(length > 0);
+            start.elapsed().as_nanos() as f64 / CALLS as f64
+        };
+        let status = core::hint::black_box(efi::Status::BUFFER_TOO_SMALL);
+        let name = core::hint::black_box("BootOrder");
+        let formatted = time(&mut || {
+            let mut line = RecordLine::new();
+            let _ = write!(
+                line,
+                "G: {} Size={}->{} {}: {:#x}",
+                GuidFmt(&guid),
+                DataSize(Some(0)),
+                DataSize(Some(8)),
+                name,
+                status.as_usize(),
+            );
+            line.as_str().len()
+        });
+        let by_hand = time(&mut || {
+            let text = hex::guid_text(&guid);
+            let text = core::str::from_utf8(&text).unwrap();
+            get_record(text, Some(0), Some(8), name, status)
+                .as_str()
+                .len()
+        });
+        std::println!(
+            "G: record: {:.1} ns with core::fmt, {:.1} ns by hand",
+            formatted,
+            by_hand
+        );
+    }
+
+    // The fuzz target (see fuzz/) in short, on its seeds, for the runs that
+    // do not have cargo-fuzz.
+    #[test]
+    fn mangled_names_are_cut_and_printable() {
+        let boot0001: &[u8] = include_bytes!("../fuzz/corpus/name/Boot0001");
+        let non_ascii: &[u8] = include_bytes!("../fuzz/corpus/name/non-ascii");
+        crate::mangle::run(&[boot0001, non_ascii], 20_000, |data| {
+            let characters: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            let mut buffer = [0u8; NAME_LENGTH];
+            let name = convert_name(&characters, &mut buffer);
+            assert!(name.len() <= NAME_LENGTH.min(characters.len()));
+            assert!(name.bytes().all(|c| (0x20..0x7f).contains(&c)));
+        });
+    }
+
+    #[test]
+    fn every_op_is_written_with_its_extras_in_order() {
+        let guid = GuidFmt(&GLOBAL_VARIABLE_GUID).to_string();
+        let variable = Some((guid.as_str(), "Boot0001"));
+        let read = Size::Read {
+            before: Some(0x100),
+            after: Some(0x3e),
+        };
+        for (op, size, extras, expected) in [
+            (
+                Op::Get,
+                read,
+                &[
+                    Extra::Content(Some(Content::Changed { unchanged_reads: 3 })),
+                    Extra::New,
+                ][..],
+                "G: 8BE4DF61-93CA-11D2-AA0D-00E098032B8C Size=00000100->0000003e Boot0001: 0x0 \
+                 changed after 3 unchanged reads NEW",
+            ),
+            (
+                Op::Set,
+                Size::Write {
+                    attributes: 7,
+                    data_size: 0x3e,
+                },
+                &[Extra::Content(None)][..],
+                "S: 8BE4DF61-93CA-11D2-AA0D-00E098032B8C Attributes=00000007 Size=0000003e \
+                 Boot0001: 0x0",
+            ),
+            (
+                Op::Accessed,
+                read,
+                &[Extra::Ticks(Some(1234))][..],
+                "Accessed variable: Boot0001, Size: 0000003e at 1234 ticks",
+            ),
+        ] {
+            let record = line(op, variable, size, efi::Status::SUCCESS, extras);
+            assert_eq!(record.as_str(), expected);
+        }
+
+        let rejected = line(
+            Op::Set,
+            None,
+            Size::Write {
+                attributes: 0,
+                data_size: 0,
+            },
+            efi::Status::INVALID_PARAMETER,
+            &[],
+        );
+        assert_eq!(
+            rejected.as_str(),
+            "SetVariable called with a null name or GUID: 0x8000000000000002"
+        );
+    }
+
+    #[test]
+    fn only_extras_writing_something_hold_a_record_back_from_the_raw_form() {
+        assert!(Extra::Content(None).is_empty());
+        assert!(Extra::Content(Some(Content::Unchanged)).is_empty());
+        assert!(Extra::Ticks(None).is_empty());
+        assert!(!Extra::Content(Some(Content::First)).is_empty());
+        assert!(!Extra::New.is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+// uefi-var-monitor-rust/src/redact.rs
+//
+// Variables whose values are secrets, such as setup password hashes or asset
+// tokens. Their data is never inspected: size and status are still logged,
+// but values show as "<redacted>", nothing is cached or hashed, and no
+// decoder sees them. safety::inspect consults the list before anything else,
+// so no per-variable setting can override it.
+//
+// The list is the built-in DEFAULT_REDACTED, the UVM_REDACT list given at
+// build time, and the entries of the "UvmRedact" variable under
+// UVM_VENDOR_GUID read at load; all in the format of pattern.rs. Entries can
+// also be added while the driver runs, but never removed.
+
+use crate::pattern::{self, PatternTable};
+use crate::tag::{self, Tag};
+use atomic_refcell::AtomicRefCell;
+use r_efi::efi;
+
+const TAG: Tag = tag::CONFIG;
+
+pub const MAX_REDACTED: usize = 32;
+
+// AMI setup password hashes.
+const DEFAULT_REDACTED: &str = "c811fa38-42c8-4579-a9bb-60e94eddfb34:AMITSESetup";
+
+const REDACT_VARIABLE: &str = "UvmRedact";
+
+// "UvmRedact"
+const REDACT_VARIABLE_NAME: [u16; 10] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'R' as u16,
+    b'e' as u16,
+    b'd' as u16,
+    b'a' as u16,
+    b'c' as u16,
+    b't' as u16,
+    0,
+];
+
+static TABLE: AtomicRefCell<PatternTable<MAX_REDACTED>> = AtomicRefCell::new(PatternTable::new());
+
+/**
+ * @brief Loads the redaction list. Must be called after the GetVariable hook
+ *        is installed, as UvmRedact is read through the saved original
+ *        pointer.
+ */
+pub fn load() {
+    let mut table = match TABLE.try_borrow_mut() {
+        Ok(table) => table,
+        Err(_) => return,
+    };
+    table.add_list(DEFAULT_REDACTED);
+    if let Some(list) = option_env!("UVM_REDACT") {
+        let (added, rejected) = table.add_list(list);
+        info!(
+            TAG,
+            "Redacted from build: {} entries, {} rejected", added, rejected
+        );
+    }
+
+    let mut buffer = [0u8; pattern::MAX_LIST_SIZE];
+    match crate::read_list_variable(&REDACT_VARIABLE_NAME, &mut buffer) {
+        Ok(list) => {
+            let (added, rejected) = table.add_list(list);
+            info!(
+                TAG,
+                "Redacted from {}: {} entries, {} rejected", REDACT_VARIABLE, added, rejected
+            );
+        }
+        Err(efi::Status::NOT_FOUND) => {}
+        Err(efi_status) => error!(
+            TAG,
+            "{} not read : {:#x}",
+            REDACT_VARIABLE,
+            efi_status.as_usize()
+        ),
+    }
+}
+
+/**
+ * @brief Adds entries to the redaction list. Returns how many were added and
+ *        how many were rejected.
+ */
+#[allow(dead_code)]
+pub fn add(list: &str) -> (usize, usize) {
+    match TABLE.try_borrow_mut() {
+        Ok(mut table) => table.add_list(list),
+        Err(_) => (0, list.split(';').count()),
+    }
+}
+
+/**
+ * @brief Returns whether the value of the variable must not be inspected.
+ *        Fails closed if the list is being changed.
+ */
+pub fn is_redacted(name: &str, guid: &efi::Guid) -> bool {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.matches(name, guid),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "profile-minimal", allow(dead_code))]
+pub fn reset() {
+    *TABLE.borrow_mut() = PatternTable::new();
+}
@@ -0,0 +1,202 @@
+// uefi-var-monitor-rust/src/relocate.rs
+//
+// Registry of the global pointers that are still dereferenced at OS runtime,
+// and so have to be converted to virtual addresses at SetVirtualAddressMap.
+// A pointer missing here faults on its first use after the switch, so every
+// module that keeps one registers it at load, next to where it is set up.
+// Pointers only used during the boot-services phase must not be registered.
+//
+// The pointers are atomics, as they may be read on other CPUs while being
+// converted. Each is converted in a local copy and then stored back.
+
+use crate::tag::{self, Tag};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::BOOT;
+
+pub const MAX_RELOCATIONS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Relocation {
+    name: &'static str,
+    pointer: Option<&'static AtomicPtr<core::ffi::c_void>>,
+    failed: bool,
+}
+
+pub struct Registry<const N: usize> {
+    entries: [Relocation; N],
+    count: usize,
+}
+
+/**
+ * @brief Erases the pointee type; AtomicPtr<T> has the same layout for any T.
+ */
+fn erase<T>(pointer: &AtomicPtr<T>) -> &AtomicPtr<core::ffi::c_void> {
+    unsafe { &*(pointer as *const AtomicPtr<T> as *const AtomicPtr<core::ffi::c_void>) }
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new() -> Self {
+        Registry {
+            entries: [Relocation {
+                name: "",
+                pointer: None,
+                failed: false,
+            }; N],
+            count: 0,
+        }
+    }
+
+    fn find(&self, pointer: &AtomicPtr<core::ffi::c_void>) -> Option<&Relocation> {
+        self.entries
+            .get(..self.count)
+            .unwrap_or(&[])
+            .iter()
+            .find(|entry| match entry.pointer {
+                Some(registered) => core::ptr::eq(registered, pointer),
+                None => false,
+            })
+    }
+
+    /**
+     * @brief Adds a pointer to convert. Registering the same pointer twice is
+     *        a no-op.
+     */
+    pub fn register<T>(
+        &mut self,
+        name: &'static str,
+        pointer: &'static AtomicPtr<T>,
+    ) -> efi::Status {
+        let pointer = erase(pointer);
+        if self.find(pointer).is_some() {
+            return efi::Status::SUCCESS;
+        }
+        let entry = match self.entries.get_mut(self.count) {
+            Some(entry) => entry,
+            None => return efi::Status::OUT_OF_RESOURCES,
+        };
+        *entry = Relocation {
+            name,
+            pointer: Some(pointer),
+            failed: false,
+        };
+        self.count += 1;
+        efi::Status::SUCCESS
+    }
+
+    /**
+     * @brief Converts every registered pointer, logging each one. A failed
+     *        conversion is logged and skipped; returns the number of failures.
+     *        Null pointers, of facilities not set up, are left alone.
+     */
+    pub fn convert_all(
+        &mut self,
+        convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status,
+    ) -> usize {
+        let mut failures = 0;
+        for entry in self.entries.get_mut(..self.count).unwrap_or(&mut []) {
+            let pointer = match entry.pointer {
+                Some(pointer) => pointer,
+                None => continue,
+            };
+            let curr_addr = pointer.load(Ordering::Acquire);
+            if curr_addr.is_null() {
+                continue;
+            }
+            let mut address = curr_addr;
+            let efi_status = convert(&mut address);
+            entry.failed = efi_status.is_error();
+            if entry.failed {
+                error!(
+                    TAG,
+                    "{} at {:#08x} could not be relocated : {:#x}",
+                    entry.name,
+                    curr_addr as u64,
+                    efi_status.as_usize()
+                );
+                failures += 1;
+                continue;
+            }
+            pointer.store(address, Ordering::Release);
+            debug!(
+                TAG,
+                "{} relocated from {:#08x} to {:#08x}",
+                entry.name,
+                curr_addr as u64,
+                address as u64,
+            );
+        }
+        failures
+    }
+
+    /**
+     * @brief Returns whether the pointer failed to convert.
+     */
+    pub fn failed<T>(&self, pointer: &AtomicPtr<T>) -> bool {
+        self.find(erase(pointer)).is_some_and(|entry| entry.failed)
+    }
+}
+
+// Only written from efi_main, before the SetVirtualAddressMap event can fire.
+static mut REGISTRY: Registry<MAX_RELOCATIONS> = Registry::new();
+
+/**
+ * @brief Registers a global pointer for conversion at SetVirtualAddressMap.
+ */
+pub fn register<T>(name: &'static str, pointer: &'static AtomicPtr<T>) -> efi::Status {
+    unsafe { &mut *core::ptr::addr_of_mut!(REGISTRY) }.register(name, pointer)
+}
+
+/**
+ * @brief Converts every registered pointer. Returns false if any failed.
+ */
+pub fn convert_all(convert: &mut dyn FnMut(*mut *mut core::ffi::c_void) -> efi::Status) -> bool {
+    let failures = unsafe { &mut *core::ptr::addr_of_mut!(REGISTRY) }.convert_all(convert);
+    if failures != 0 {
+        error!(TAG, "{} pointer(s) could not be relocated", failures);
+    }
+    failures == 0
+}
+
+/**
+ * @brief Returns whether the pointer failed to convert.
+ */
+pub fn failed<T>(pointer: &AtomicPtr<T>) -> bool {
+    unsafe { &*core::ptr::addr_of!(REGISTRY) }.failed(pointer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FIRST: AtomicPtr<u8> = AtomicPtr::new(0x1000 as *mut u8);
+    static SECOND: AtomicPtr<u8> = AtomicPtr::new(0x2000 as *mut u8);
+    static THIRD: AtomicPtr<u8> = AtomicPtr::new(0x3000 as *mut u8);
+
+    #[test]
+    fn convert_all_continues_past_failures() {
+        let mut registry = Registry::<2>::new();
+        assert_eq!(registry.register("first", &FIRST), efi::Status::SUCCESS);
+        assert_eq!(registry.register("second", &SECOND), efi::Status::SUCCESS);
+        assert_eq!(registry.register("first", &FIRST), efi::Status::SUCCESS);
+        assert_eq!(
+            registry.register("third", &THIRD),
+            efi::Status::OUT_OF_RESOURCES
+        );
+
+        // Fail the first conversion; the second one still has to happen.
+        let failures = registry.convert_all(&mut |address| unsafe {
+            if *address as usize == 0x1000 {
+                return efi::Status::NOT_FOUND;
+            }
+            *address = (*address as usize + 0x8000_0000) as *mut core::ffi::c_void;
+            efi::Status::SUCCESS
+        });
+        assert_eq!(failures, 1);
+        assert!(registry.failed(&FIRST));
+        assert!(!registry.failed(&SECOND));
+        assert_eq!(FIRST.load(Ordering::Acquire) as usize, 0x1000);
+        assert_eq!(SECOND.load(Ordering::Acquire) as usize, 0x8000_2000);
+    }
+}
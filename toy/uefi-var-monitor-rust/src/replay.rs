@@ -0,0 +1,257 @@
+// uefi-var-monitor-rust/src/replay.rs
+//
+// The driver's decisions on a stream of accesses, taken again away from the
+// firmware: whether each is traced under a filter (filter.rs), how it is
+// counted (CallCounts of interface/src/protocol.rs, and top.rs), and whether
+// it raises an alert and the alert manager shows or suppresses it
+// (alert.rs). uvmlog replay feeds it the raw records of a captured dump and
+// compares what it decides with what the unit logged (see
+// tools/uvmlog/src/replay.rs).
+//
+// A replay depends on its input alone. The clock of the cooldowns in seconds
+// is the cycle count each access carries, with the ticks per second the unit
+// calibrated, given with calibrate(); until then they suppress nothing, as
+// in the driver. The phase is boot services until exit_boot_services().
+//
+// Only what the driver decides from the name, GUID, status and size of an
+// access is replayed. Alerts on the data, or on earlier boots (size changes,
+// correlations, MOR values and the like), need more than a record holds.
+
+use crate::alert::{self, Access, LimitTable, Manager, Rule, Severity};
+use crate::classify;
+use crate::filter::{self, MAX_FILTERED};
+use crate::pattern::PatternTable;
+use crate::top::{self, MAX_COUNTED};
+use r_efi::efi;
+use uvm_interface::protocol::{CallCounts, TopEntry, PHASE_BOOT_SERVICES, PHASE_RUNTIME};
+
+// A GetVariable or SetVariable call, as a raw record keeps it.
+#[derive(Clone, Copy, Debug)]
+pub struct Call<'a> {
+    pub name: &'a str,
+    pub guid: &'a efi::Guid,
+    pub access: Access,
+    pub efi_status: efi::Status,
+    // The data size read or written, if any.
+    pub size: Option<usize>,
+    // The cycle counter when the call returned.
+    pub cycles: u64,
+}
+
+// An alert raised by a call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Alert {
+    pub rule: Rule,
+    pub severity: Severity,
+    // The occurrences suppressed before it if it fires, None if it is
+    // suppressed itself.
+    pub shown: Option<u32>,
+}
+
+// What the driver decides on a call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub traced: bool,
+    pub alert: Option<Alert>,
+}
+
+pub struct Replay {
+    filter: PatternTable<MAX_FILTERED>,
+    alerts: Manager,
+    ticks_per_second: u64,
+    phase: usize,
+    top: top::Table<MAX_COUNTED>,
+    pub get_variable: CallCounts,
+    pub set_variable: CallCounts,
+    pub traced: u64,
+    // Alerts fired by rule, indexed by rule ID.
+    pub fired: [u32; alert::RULE_COUNT],
+}
+
+impl Replay {
+    pub fn new(filter: PatternTable<MAX_FILTERED>, limits: &LimitTable) -> Self {
+        let mut alerts = Manager::new();
+        alerts.set_limits(limits);
+        Replay {
+            filter,
+            alerts,
+            ticks_per_second: 0,
+            phase: PHASE_BOOT_SERVICES,
+            top: top::Table::new(),
+            get_variable: CallCounts::default(),
+            set_variable: CallCounts::default(),
+            traced: 0,
+            fired: [0; alert::RULE_COUNT],
+        }
+    }
+
+    /**
+     * @brief Sets the ticks per second of the cycle counter, as the unit
+     *        calibrated it; 0 leaves it uncalibrated.
+     */
+    pub fn calibrate(&mut self, ticks_per_second: u64) {
+        self.ticks_per_second = ticks_per_second;
+    }
+
+    /**
+     * @brief Counts the calls from now on as made at OS runtime.
+     */
+    pub fn exit_boot_services(&mut self) {
+        self.phase = PHASE_RUNTIME;
+    }
+
+    /**
+     * @brief Decides on `call` as the driver would have, and counts it.
+     */
+    pub fn feed(&mut self, call: &Call) -> Decision {
+        let (counts, access) = match call.access {
+            Access::Get => (&mut self.get_variable, top::Access::Read),
+            Access::Set { .. } => (&mut self.set_variable, top::Access::Write),
+        };
+        counts.count(call.efi_status, call.size, self.phase);
+        self.top.count(call.name, call.guid, access);
+        let traced = filter::traces(&self.filter, call.name, call.guid);
+        if traced {
+            self.traced += 1;
+        }
+        let clock = match self.ticks_per_second {
+            0 => None,
+            ticks_per_second => Some((call.cycles, ticks_per_second)),
+        };
+        let alert = alert::severity(classify::classify(call.name, call.guid), call.access).map(
+            |(rule, severity)| {
+                let shown = self.alerts.admit(rule, clock);
+                if shown.is_some() {
+                    self.fired[rule as usize] += 1;
+                }
+                Alert {
+                    rule,
+                    severity,
+                    shown,
+                }
+            },
+        );
+        Decision { traced, alert }
+    }
+
+    pub fn suppressed(&self, rule: Rule) -> u32 {
+        self.alerts.total_suppressed(rule)
+    }
+
+    /**
+     * @brief Fills `entries` with the most accessed variables, most first, as
+     *        the statistics protocol hands them out. Returns how many.
+     */
+    pub fn top(&self, entries: &mut [TopEntry]) -> usize {
+        self.top.top(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alert::{parse_limits, DEFAULT_LIMITS};
+    use crate::guids::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID, UVM_VENDOR_GUID};
+    use uvm_interface::protocol::{OUTCOME_NOT_FOUND, OUTCOME_SUCCESS};
+
+    fn get<'a>(name: &'a str, guid: &'a efi::Guid, seconds: u64) -> Call<'a> {
+        Call {
+            name,
+            guid,
+            access: Access::Get,
+            efi_status: efi::Status::SUCCESS,
+            size: Some(4),
+            // 1000 ticks per second.
+            cycles: seconds * 1000,
+        }
+    }
+
+    // The suppressed counts of the key-read alerts of `calls`, None for
+    // those suppressed.
+    fn key_reads(replay: &mut Replay, calls: &[Call]) -> std::vec::Vec<Option<u32>> {
+        calls
+            .iter()
+            .filter_map(|call| replay.feed(call).alert)
+            .map(|alert| alert.shown)
+            .collect()
+    }
+
+    #[test]
+    fn cooldowns_run_on_the_cycles_of_the_records() {
+        let pk = |seconds| get("PK", &GLOBAL_VARIABLE_GUID, seconds);
+        let calls = [pk(0), pk(30), pk(59), pk(60), pk(200)];
+
+        // Uncalibrated, as the unit is before rate.rs measured the counter.
+        let mut replay = Replay::new(PatternTable::new(), &DEFAULT_LIMITS);
+        assert_eq!(key_reads(&mut replay, &calls), [Some(0); 5]);
+
+        let mut replay = Replay::new(PatternTable::new(), &DEFAULT_LIMITS);
+        replay.calibrate(1000);
+        assert_eq!(
+            key_reads(&mut replay, &calls),
+            [Some(0), None, None, Some(2), Some(0)]
+        );
+        assert_eq!(replay.suppressed(Rule::SecureBootKeyRead), 2);
+        assert_eq!(replay.fired[Rule::SecureBootKeyRead as usize], 3);
+
+        // The same stream under other limits.
+        let mut replay = Replay::new(PatternTable::new(), &parse_limits("key-read=2"));
+        assert_eq!(
+            key_reads(&mut replay, &calls),
+            [None, Some(1), None, Some(1), None]
+        );
+    }
+
+    #[test]
+    fn calls_are_filtered_and_counted_by_phase() {
+        let mut filter = PatternTable::new();
+        assert_eq!(
+            filter.add_list("d719b2cb-3d3a-4596-a3bc-dad00e67656f:db*"),
+            (1, 0)
+        );
+        let mut replay = Replay::new(filter, &DEFAULT_LIMITS);
+        let dbx = get("dbx", &IMAGE_SECURITY_DATABASE_GUID, 0);
+        let decision = replay.feed(&dbx);
+        assert!(decision.traced);
+        assert_eq!(
+            decision.alert,
+            Some(Alert {
+                rule: Rule::SecureBootKeyRead,
+                severity: Severity::Info,
+                shown: Some(0),
+            })
+        );
+        assert_eq!(
+            replay.feed(&get("UvmLevel", &UVM_VENDOR_GUID, 0)),
+            Decision {
+                traced: false,
+                alert: None
+            }
+        );
+
+        replay.exit_boot_services();
+        let write = Call {
+            access: Access::Set {
+                attributes: 0x27,
+                data_size: 0x400,
+            },
+            efi_status: efi::Status::NOT_FOUND,
+            size: Some(0x400),
+            ..dbx
+        };
+        assert_eq!(
+            replay.feed(&write).alert.map(|alert| alert.severity),
+            Some(Severity::Critical)
+        );
+
+        assert_eq!(replay.traced, 2);
+        assert_eq!(replay.get_variable.by_status[OUTCOME_SUCCESS], 2);
+        assert_eq!(replay.get_variable.by_phase, [2, 0]);
+        assert_eq!(replay.set_variable.by_status[OUTCOME_NOT_FOUND], 1);
+        assert_eq!(replay.set_variable.by_phase, [0, 1]);
+        assert_eq!(replay.set_variable.by_size.iter().sum::<u64>(), 0);
+        let mut top = [TopEntry::EMPTY; 4];
+        assert_eq!(replay.top(&mut top), 2);
+        assert_eq!((top[0].name(), top[0].reads, top[0].writes), ("dbx", 1, 1));
+    }
+}
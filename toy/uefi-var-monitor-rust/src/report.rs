@@ -0,0 +1,268 @@
+// uefi-var-monitor-rust/src/report.rs
+//
+// The boot-report variable: a small binary summary of the monitoring done
+// during this boot, written to "UvmBootReport" under UVM_VENDOR_GUID at
+// ReadyToBoot so that the OS, or the driver on the next boot, can read it
+// back.
+//
+// It holds the head of the log chain (see ring.rs) at the time it was written.
+// A ring buffer dumped later must chain through that value, which the firmware
+// committed to before any OS loader ran. With tpm-measure, the head is also
+// measured, which makes it part of the quote.
+//
+// It also counts the persisted correlations (see correlate.rs). Those are
+// mostly raised at OS runtime, after ReadyToBoot, so each match rewrites the
+// variable; the next boot reads the counts back at load, alerts on them and
+// carries them in its own report.
+//
+// And it carries the variable sizes learned so far (see seen.rs), which the
+// next boot takes back at load so that size anomalies can span boots, and the
+// number of alerts suppressed by each rule (see alerts.rs), indexed by rule
+// ID.
+//
+// Since minor version 1 it carries the newest writes of the write
+// histories (see history.rs). Firmware may refuse a variable that large for
+// want of space; the report is then written again with the fields of minor
+// version 0 alone.
+//
+// Since minor version 2 it carries the space in the variable store at load
+// and at ExitBootServices (see capacity.rs), and is written again at
+// ExitBootServices for the second. Since minor version 3 it ends with the
+// variables created since load (see newcomer.rs), and is written again for
+// each one found at OS runtime. Since minor version 4 it says which build of
+// the driver wrote it (see banner.rs).
+//
+// The variable is written through the saved SetVariable and read through the
+// saved GetVariable, so that neither access is reported by our own hooks. Its
+// layout, behind the common header of interface/src/format.rs, is shared with
+// the tools reading it (see interface/src/report.rs).
+
+use crate::alerts::{self, Rule};
+use crate::banner;
+use crate::capacity;
+use crate::config::UVM_VENDOR_GUID;
+use crate::correlate;
+use crate::counters;
+use crate::history;
+use crate::newcomer;
+use crate::rules;
+use crate::seen;
+use crate::set_variable::SET_VARIABLE;
+use crate::tag::{self, Tag};
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+use uvm_interface::report::{BootReport, MAX_REPORTED_RULES};
+
+const TAG: Tag = tag::BOOT;
+
+const _: () = assert!(alerts::RULE_COUNT <= MAX_REPORTED_RULES);
+
+// "UvmBootReport"
+const REPORT_VARIABLE_NAME: [u16; 14] = [
+    b'U' as u16,
+    b'v' as u16,
+    b'm' as u16,
+    b'B' as u16,
+    b'o' as u16,
+    b'o' as u16,
+    b't' as u16,
+    b'R' as u16,
+    b'e' as u16,
+    b'p' as u16,
+    b'o' as u16,
+    b'r' as u16,
+    b't' as u16,
+    0,
+];
+
+const REPORT_ATTRIBUTES: u32 =
+    efi::VARIABLE_NON_VOLATILE | efi::VARIABLE_BOOTSERVICE_ACCESS | efi::VARIABLE_RUNTIME_ACCESS;
+
+// Counts read back from the previous boot's report.
+static PREVIOUS_RUNTIME_BOOT_CRITICAL_WRITES: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Returns the report for the current state of the driver.
+ */
+pub fn collect() -> BootReport {
+    let mut report = BootReport {
+        format: BootReport::FORMAT,
+        runtime_boot_critical_writes: correlate::matches(correlate::RUNTIME_BOOT_CRITICAL_WRITE),
+        previous_runtime_boot_critical_writes: PREVIOUS_RUNTIME_BOOT_CRITICAL_WRITES
+            .load(Ordering::Acquire),
+        store_at_load: capacity::at_load(),
+        store_at_exit: capacity::at_exit(),
+        build: banner::info(),
+        ..BootReport::EMPTY
+    };
+    report.learned_size_count = seen::learned_sizes(&mut report.learned_sizes) as u32;
+    alerts::suppressed(&mut report.suppressed_alerts);
+    report.history_count = history::read(&mut report.history).unwrap_or(0) as u32;
+    report.new_variable_count =
+        newcomer::read(&mut report.new_counts, &mut report.new_variables) as u32;
+    #[cfg(feature = "log-ring")]
+    if let Some(header) = crate::ring::header() {
+        report.next_sequence = header.next_sequence;
+        report.chain_head = header.chain_head;
+    }
+    report
+}
+
+/**
+ * @brief Returns the report left by the previous boot. Fields it is too old
+ *        to have are zero.
+ */
+fn read_previous() -> Option<BootReport> {
+    let mut name = REPORT_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    let mut attributes = 0u32;
+    let mut report = BootReport::EMPTY;
+    let mut data_size = core::mem::size_of_val(&report);
+    let efi_status = crate::GET_VARIABLE.call(
+        name.as_mut_ptr(),
+        &mut guid,
+        &mut attributes,
+        &mut data_size,
+        &mut report as *mut _ as *mut core::ffi::c_void,
+    );
+    if efi_status.is_error() {
+        return None;
+    }
+    // Reports of another major version, or of before the common header, are
+    // ignored.
+    BootReport::parse(report.as_bytes().get(..data_size)?).ok()
+}
+
+/**
+ * @brief Reads the previous boot's report, then registers the ReadyToBoot
+ *        notification that writes this one. Must be called after the hooks
+ *        are installed. Returns the event for the caller to record in the
+ *        teardown list.
+ */
+pub fn start(boot_services: &mut efi::BootServices) -> Result<r_efi::base::Event, efi::Status> {
+    if let Some(previous) = read_previous() {
+        let writes = previous.runtime_boot_critical_writes;
+        PREVIOUS_RUNTIME_BOOT_CRITICAL_WRITES.store(writes, Ordering::Release);
+        if writes != 0 {
+            rules::emit(
+                Rule::PreviousBoot,
+                rules::Severity::Critical,
+                format_args!(
+                    "Previous boot: firmware accepted {} write(s) to boot-critical variables at OS runtime",
+                    writes
+            ),
+            );
+        }
+        seen::learn(previous.learned_sizes());
+    }
+
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_ready_to_boot,
+        core::ptr::null_mut(),
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(event)
+}
+
+/**
+ * @brief Writes the report variable, without the write history if firmware
+ *        has no room for it.
+ */
+pub fn write() -> efi::Status {
+    let mut report = collect();
+    let efi_status = write_report(&mut report);
+    if efi_status != efi::Status::OUT_OF_RESOURCES {
+        return efi_status;
+    }
+    report.truncate_to_minor_0();
+    let efi_status = write_report(&mut report);
+    if !efi_status.is_error() {
+        warn!(
+            TAG,
+            "Boot report written without the write history, for want of space"
+        );
+    }
+    efi_status
+}
+
+fn write_report(report: &mut BootReport) -> efi::Status {
+    let mut name = REPORT_VARIABLE_NAME;
+    let mut guid = UVM_VENDOR_GUID;
+    SET_VARIABLE.call(
+        name.as_mut_ptr(),
+        &mut guid,
+        REPORT_ATTRIBUTES,
+        report.format.size as usize,
+        report as *mut _ as *mut core::ffi::c_void,
+    )
+}
+
+efiapi! {
+    /**
+     * @brief Commits to the log chain before control goes to a boot option.
+     *        ReadyToBoot is signalled again for each boot attempt.
+     */
+    fn handle_ready_to_boot(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        alerts::log_summary();
+        counters::log_calls();
+        let efi_status = write();
+        if efi_status.is_error() {
+            error!(TAG, "Boot report not written : {:#x}", efi_status.as_usize());
+        }
+
+        #[cfg(feature = "tpm-measure")]
+        {
+            let report = collect();
+            crate::tpm::measure_alert(format_args!(
+                "UVM log chain #{} {:016x}",
+                report.next_sequence,
+                u64::from_be_bytes(report.chain_head)
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uvm_interface::build::BuildInfo;
+    use uvm_interface::protocol::HistoryEntry;
+    use uvm_interface::report::{
+        LearnedSize, NewVariable, StoreSpace, MAX_LEARNED_SIZES, MAX_REPORTED_HISTORY,
+        MAX_REPORTED_NEW,
+    };
+
+    #[test]
+    fn layout_is_stable() {
+        // Readers outside this driver parse the variable by offset.
+        assert_eq!(core::mem::size_of::<LearnedSize>(), 32);
+        assert_eq!(core::mem::size_of::<HistoryEntry>(), 160);
+        assert_eq!(core::mem::size_of::<StoreSpace>(), 32);
+        assert_eq!(core::mem::size_of::<NewVariable>(), 88);
+        assert_eq!(core::mem::size_of::<BuildInfo>(), 104);
+        assert_eq!(
+            core::mem::size_of::<BootReport>(),
+            64 + 32 * MAX_LEARNED_SIZES
+                + 4 * MAX_REPORTED_RULES
+                + 8
+                + 160 * MAX_REPORTED_HISTORY
+                + 2 * 32
+                + 16
+                + 88 * MAX_REPORTED_NEW
+                + 104
+        );
+        let report = collect();
+        assert_eq!(BootReport::parse(report.as_bytes()), Ok(report));
+        assert_eq!(
+            report.format.size as usize,
+            core::mem::size_of::<BootReport>()
+        );
+    }
+}
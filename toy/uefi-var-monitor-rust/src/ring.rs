@@ -0,0 +1,291 @@
+// uefi-var-monitor-rust/src/ring.rs
+//
+// In-memory ring buffer of log records. The buffer lives in the driver image
+// (runtime services data), so it keeps working after ExitBootServices and can
+// be inspected post-mortem.
+//
+// Every stored record gets a sequence number. The header tracks the range of
+// sequence numbers currently held, and how many records were lost either by
+// being overwritten (OverwriteOldest) or by being refused (DropNewest, or the
+// buffer being busy when a record arrived).
+//
+// Stored records are chained: each carries the first RING_CHAIN_SIZE bytes of
+// SHA-256(link of the previous record || sequence || data), so that scrubbing
+// or editing a record breaks every link after it. Overwriting the oldest
+// record moves its link into the header as the anchor the oldest record held
+// chains from, which keeps what is left verifiable across wraparound.
+//
+// From the load banner on, the header also says which build of the driver
+// wrote the buffer (see banner.rs).
+//
+// The layout of the header and records, and the links, are shared with the
+// tools reading the buffer (see interface/src/ring.rs), and the header starts
+// with the common one of the formats the driver leaves behind (see
+// interface/src/format.rs).
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use uvm_interface::build::BuildInfo;
+
+pub use uefi_var_monitor::settings::OverflowPolicy;
+
+#[cfg(any(feature = "ring-dump", all(test, feature = "log-deferred")))]
+pub use uvm_interface::ring::RecordText;
+#[cfg(feature = "log-deferred")]
+pub use uvm_interface::ring::{access_size, RECORD_GET_VARIABLE, RECORD_SET_VARIABLE};
+pub use uvm_interface::ring::{
+    link, RingHeader, RingRecord, RECORD_TEXT, RING_CHAIN_SIZE, RING_DATA_SIZE, RING_FLAG_PANICKED,
+};
+
+// Set by the build profile (see profile.rs).
+pub const RING_CAPACITY: usize = crate::profile::RING_CAPACITY;
+
+#[repr(C)]
+pub struct RingBuffer<const N: usize> {
+    pub header: RingHeader,
+    records: [RingRecord; N],
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        RingBuffer {
+            header: RingHeader {
+                format: RingHeader::FORMAT,
+                capacity: N as u32,
+                policy: policy as u32,
+                first_sequence: 0,
+                next_sequence: 0,
+                dropped: 0,
+                overwritten: 0,
+                chain_anchor: [0; RING_CHAIN_SIZE],
+                chain_head: [0; RING_CHAIN_SIZE],
+                build: BuildInfo::EMPTY,
+            },
+            records: [RingRecord::EMPTY; N],
+        }
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        OverflowPolicy::from_u32(self.header.policy).unwrap_or(OverflowPolicy::OverwriteOldest)
+    }
+
+    pub fn set_policy(&mut self, policy: OverflowPolicy) {
+        self.header.policy = policy as u32;
+    }
+
+    pub fn len(&self) -> usize {
+        (self.header.next_sequence - self.header.first_sequence) as usize
+    }
+
+    /**
+     * @brief Reserves the slot for the next record according to the overflow
+     *        policy, or returns None if the record has to be dropped.
+     */
+    fn reserve(&mut self) -> Option<&mut RingRecord> {
+        if self.len() >= N {
+            match self.policy() {
+                OverflowPolicy::OverwriteOldest => {
+                    let oldest = self.header.first_sequence;
+                    self.header.chain_anchor = self.records[(oldest % N as u64) as usize].chain;
+                    self.header.first_sequence += 1;
+                    self.header.overwritten += 1;
+                }
+                OverflowPolicy::DropNewest => {
+                    self.header.dropped += 1;
+                    return None;
+                }
+            }
+        }
+        let sequence = self.header.next_sequence;
+        self.header.next_sequence += 1;
+        let record = &mut self.records[(sequence % N as u64) as usize];
+        record.sequence = sequence;
+        record.length = 0;
+        record.kind = RECORD_TEXT;
+        Some(record)
+    }
+
+    /**
+     * @brief Formats and stores a record, truncated to RING_DATA_SIZE, and
+     *        chains it. Returns false if it was dropped.
+     */
+    pub fn push_fmt(&mut self, args: fmt::Arguments) -> bool {
+        let head = self.header.chain_head;
+        let record = match self.reserve() {
+            Some(record) => record,
+            None => return false,
+        };
+        let mut writer = RecordWriter { record };
+        let _ = fmt::Write::write_fmt(&mut writer, args);
+        record.chain = link(record, &head);
+        self.header.chain_head = record.chain;
+        true
+    }
+
+    /**
+     * @brief Stores a raw record (see deferred.rs) and chains it. Returns
+     *        false if it was dropped.
+     */
+    #[cfg(feature = "log-deferred")]
+    pub fn push_raw(&mut self, raw: &RingRecord) -> bool {
+        let head = self.header.chain_head;
+        let record = match self.reserve() {
+            Some(record) => record,
+            None => return false,
+        };
+        record.kind = raw.kind;
+        record.length = raw.length;
+        record.data = raw.data;
+        record.chain = link(record, &head);
+        self.header.chain_head = record.chain;
+        true
+    }
+
+    /**
+     * @brief Returns the record with the sequence number if still held.
+     */
+    pub fn get(&self, sequence: u64) -> Option<&RingRecord> {
+        if sequence < self.header.first_sequence || sequence >= self.header.next_sequence {
+            return None;
+        }
+        Some(&self.records[(sequence % N as u64) as usize])
+    }
+}
+
+struct RecordWriter<'a> {
+    record: &'a mut RingRecord,
+}
+
+impl<'a> fmt::Write for RecordWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let offset = self.record.length as usize;
+        let count = core::cmp::min(RING_DATA_SIZE.saturating_sub(offset), s.len());
+        let bytes = s.as_bytes().get(..count).unwrap_or(&[]);
+        if let Some(slot) = self.record.data.get_mut(offset..offset + bytes.len()) {
+            slot.copy_from_slice(bytes);
+            self.record.length += bytes.len() as u16;
+        }
+        Ok(())
+    }
+}
+
+// The buffer is guarded by a try-lock rather than a spin lock: the writer may
+// be interrupted by a higher TPL or run concurrently on another CPU at OS
+// runtime, and must never wait. A record that finds the buffer busy is
+// counted in CONTENDED and folded into the dropped count by the next owner.
+static BUSY: AtomicBool = AtomicBool::new(false);
+static CONTENDED: AtomicU64 = AtomicU64::new(0);
+static mut RING: RingBuffer<RING_CAPACITY> = RingBuffer::new(OverflowPolicy::OverwriteOldest);
+
+/**
+ * @brief Runs the closure with exclusive access to the ring buffer, or
+ *        returns None if it is busy.
+ */
+fn with_ring<R>(f: impl FnOnce(&mut RingBuffer<RING_CAPACITY>) -> R) -> Option<R> {
+    if BUSY.swap(true, Ordering::Acquire) {
+        return None;
+    }
+    let ring = unsafe { &mut *core::ptr::addr_of_mut!(RING) };
+    ring.header.dropped += CONTENDED.swap(0, Ordering::Relaxed);
+    let result = f(ring);
+    BUSY.store(false, Ordering::Release);
+    Some(result)
+}
+
+/**
+ * @brief Stores a log record into the ring buffer.
+ */
+pub fn push_record(args: fmt::Arguments) {
+    if with_ring(|ring| ring.push_fmt(args)).is_none() {
+        CONTENDED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/**
+ * @brief Stores a raw record into the ring buffer.
+ */
+#[cfg(feature = "log-deferred")]
+pub fn push_raw(record: &RingRecord) {
+    if with_ring(|ring| ring.push_raw(record)).is_none() {
+        CONTENDED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/**
+ * @brief Flags the buffer for post-mortem tooling as left by a driver that
+ *        panicked. Called from the panic handler, so it ignores the lock: the
+ *        panicking code may hold it, and nothing runs afterwards anyway.
+ */
+#[cfg_attr(any(test, feature = "mock"), allow(dead_code))]
+pub fn mark_panicked() {
+    unsafe {
+        let flags = core::ptr::addr_of_mut!(RING.header.format.flags);
+        flags.write_volatile(flags.read_volatile() | RING_FLAG_PANICKED);
+    }
+}
+
+/**
+ * @brief Changes the overflow policy. Takes effect from the next record.
+ */
+pub fn set_overflow_policy(policy: OverflowPolicy) -> bool {
+    with_ring(|ring| ring.set_policy(policy)).is_some()
+}
+
+/**
+ * @brief Records the build of the driver in the header.
+ */
+pub fn set_build(build: &BuildInfo) -> bool {
+    with_ring(|ring| ring.header.build = *build).is_some()
+}
+
+/**
+ * @brief Returns a copy of the buffer header.
+ */
+pub fn header() -> Option<RingHeader> {
+    with_ring(|ring| ring.header)
+}
+
+/**
+ * @brief Returns a copy of the record with the sequence number if still held.
+ */
+pub fn copy_record(sequence: u64) -> Option<RingRecord> {
+    with_ring(|ring| ring.get(sequence).copied()).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uvm_interface::ring::verify_chain;
+
+    fn verify<const N: usize>(ring: &RingBuffer<N>) -> Result<(), u64> {
+        let range = ring.header.first_sequence..ring.header.next_sequence;
+        verify_chain(
+            ring.header.chain_anchor,
+            range.filter_map(|sequence| ring.get(sequence)),
+        )
+    }
+
+    #[test]
+    fn chain_survives_wraparound_and_catches_edits() {
+        let mut ring = RingBuffer::<4>::new(OverflowPolicy::OverwriteOldest);
+        for index in 0..10 {
+            assert!(ring.push_fmt(format_args!("record {}", index)));
+        }
+        assert_eq!(ring.header.first_sequence, 6);
+        assert_ne!(ring.header.chain_anchor, [0; RING_CHAIN_SIZE]);
+        assert_eq!(verify(&ring), Ok(()));
+        assert_eq!(ring.get(9).unwrap().chain, ring.header.chain_head);
+
+        // Scrubbing a record breaks its own link.
+        ring.records[7 % 4].data[..8].copy_from_slice(b"RECORD 7");
+        assert_eq!(verify(&ring), Err(7));
+
+        // So does dropping one from the middle, at the record after it.
+        let mut ring = RingBuffer::<4>::new(OverflowPolicy::DropNewest);
+        for index in 0..3 {
+            ring.push_fmt(format_args!("record {}", index));
+        }
+        let records = [ring.get(0).unwrap(), ring.get(2).unwrap()];
+        assert_eq!(verify_chain(ring.header.chain_anchor, records), Err(2));
+    }
+}
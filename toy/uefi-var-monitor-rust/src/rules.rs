@@ -0,0 +1,139 @@
+// uefi-var-monitor-rust/src/rules.rs
+//
+// Alert rules applied to each variable access after it was logged. A rule
+// matches on the class of the variable (see classify.rs) and the kind of
+// access, and decides the severity of the alert line it produces (see
+// alert.rs in the library, which decides). Critical alerts go through
+// alert!, so they also reach the GOP banner; informational ones only go to
+// the log sinks, but carry the same "ALERT:" prefix so that they stand out
+// from the per-access records. Every alert is raised under a
+// rule ID and may be suppressed by the alert manager (see alerts.rs).
+
+use crate::alerts::{self, Rule, Suppressed};
+use crate::classify;
+use crate::level::Level;
+use core::fmt;
+use r_efi::efi;
+use uefi_var_monitor::alert;
+pub use uefi_var_monitor::alert::{Access, Severity};
+
+/**
+ * @brief Applies the rules to an access to the variable `name` under `guid`
+ *        that returned `efi_status`.
+ */
+pub fn check(name: &str, guid: &efi::Guid, access: Access, efi_status: efi::Status) {
+    let (rule, severity) = match alert::severity(classify::classify(name, guid), access) {
+        Some(raised) => raised,
+        None => return,
+    };
+    match access {
+        Access::Get => emit(
+            rule,
+            severity,
+            format_args!(
+                "GetVariable of Secure Boot key {}: {:#x}",
+                name,
+                efi_status.as_usize()
+            ),
+        ),
+        Access::Set {
+            attributes,
+            data_size,
+        } => emit(
+            rule,
+            severity,
+            format_args!(
+                "SetVariable of Secure Boot key {} Attributes={:#010x} Size={:08x}: {:#x}",
+                name,
+                attributes,
+                data_size,
+                efi_status.as_usize()
+            ),
+        ),
+    }
+}
+
+/**
+ * @brief Returns whether a SetVariable call asks for the variable to be
+ *        deleted, which zero DataSize or zero Attributes do.
+ */
+pub fn is_deletion(attributes: u32, data_size: usize) -> bool {
+    data_size == 0 || attributes == 0
+}
+
+/**
+ * @brief Returns whether a SetVariable call is a deletion attempt against a
+ *        variable last seen with `previous` attributes as time-based
+ *        authenticated. Firmware rejecting it does not make it less of one.
+ */
+pub fn is_authenticated_deletion(previous: Option<u32>, attributes: u32, data_size: usize) -> bool {
+    let authenticated = match previous {
+        Some(previous) => previous & efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS != 0,
+        None => false,
+    };
+    authenticated && is_deletion(attributes, data_size)
+}
+
+/**
+ * @brief Writes an alert line of `severity` raised by `rule`, unless the
+ *        alert manager suppresses it.
+ */
+pub fn emit(rule: Rule, severity: Severity, args: fmt::Arguments) {
+    let suppressed = match alerts::admit(rule) {
+        Some(suppressed) => Suppressed(suppressed),
+        None => return,
+    };
+    match severity {
+        Severity::Info => log_record_at!(Level::Info, "ALERT: [info] {}{}", args, suppressed),
+        Severity::Warning => {
+            log_record_at!(Level::Warning, "ALERT: [warning] {}{}", args, suppressed)
+        }
+        Severity::Critical => alert!("[critical] {}{}", args, suppressed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uefi_var_monitor::alert::{Cooldown, Limits, DEFAULT_LIMITS};
+
+    #[test]
+    fn alerts_following_suppressed_ones_carry_their_count() {
+        let _lock = crate::mock::lock();
+        alerts::reset();
+        let mut limits = DEFAULT_LIMITS;
+        limits[Rule::SizeChange as usize] = Limits {
+            threshold: 1,
+            cooldown: Cooldown::Occurrences(2),
+        };
+        alerts::set_limits(&limits);
+        crate::serial::start_capture();
+        for count in 0..5 {
+            emit(
+                Rule::SizeChange,
+                Severity::Warning,
+                format_args!("Size #{}", count),
+            );
+        }
+        alerts::log_summary();
+        let records = crate::serial::without_locations(&crate::serial::take_capture());
+        assert_eq!(
+            records,
+            "ALERT: [warning] Size #0\n\
+             ALERT: [warning] Size #3 (2 suppressed)\n\
+             [hook] Alerts suppressed: size-change=3\n"
+        );
+        alerts::reset();
+    }
+
+    #[test]
+    fn deletions_of_authenticated_variables() {
+        let authenticated = Some(0x27);
+        assert!(is_authenticated_deletion(authenticated, 0x27, 0));
+        assert!(is_authenticated_deletion(authenticated, 0, 0x40));
+        assert!(!is_authenticated_deletion(authenticated, 0x27, 0x40));
+        // Only what the variable was counts, not what the call claims.
+        assert!(!is_authenticated_deletion(Some(0x07), 0x27, 0));
+        assert!(!is_authenticated_deletion(None, 0x27, 0));
+    }
+}
@@ -0,0 +1,100 @@
+// uefi-var-monitor-rust/src/safety.rs
+//
+// Whether the caller's data buffer may be read. After ExitBootServices the OS
+// may pass buffers that are only mapped in its own page tables for the
+// duration of the call, so by default nothing past the name, GUID, size and
+// status is looked at in the runtime phase; those are pointers the call
+// itself already dereferenced. Any feature reading the data itself (hexdump,
+// hashing, decoders, caches) must get it from inspect() on every call.
+//
+// inspect() also keeps the values of redacted variables (see redact.rs) from
+// every such feature. The redaction list is consulted first, so nothing that
+// allows reading more, whether phase or per-variable settings, overrides it.
+//
+// The answer is derived from the phase flag rather than stored separately, so
+// it flips with the same atomic store that marks ExitBootServices.
+
+use crate::caller;
+use crate::redact;
+use crate::Phase;
+use core::sync::atomic::{AtomicU32, Ordering};
+use r_efi::efi;
+
+pub use uefi_var_monitor::settings::RuntimeDataAccess;
+
+static RUNTIME_DATA_ACCESS: AtomicU32 = AtomicU32::new(RuntimeDataAccess::Deny as u32);
+
+/**
+ * @brief Changes whether the data buffer may be read at OS runtime.
+ */
+pub fn set_runtime_data_access(access: RuntimeDataAccess) {
+    RUNTIME_DATA_ACCESS.store(access as u32, Ordering::Release);
+}
+
+pub fn runtime_data_access() -> RuntimeDataAccess {
+    RuntimeDataAccess::from_u32(RUNTIME_DATA_ACCESS.load(Ordering::Acquire))
+        .unwrap_or(RuntimeDataAccess::Deny)
+}
+
+/**
+ * @brief Returns whether the caller's data buffer may be read in the current
+ *        phase.
+ */
+pub fn data_access_allowed() -> bool {
+    access_allowed(crate::phase(), runtime_data_access())
+}
+
+// The caller's data buffer as far as it may be looked at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Inspection<'a> {
+    Data(&'a [u8]),
+    // There is no buffer, or it may not be read in this phase.
+    NotInspected,
+    // The variable is on the redaction list.
+    Redacted,
+}
+
+/**
+ * @brief Returns the data buffer of an access to the variable `name` under
+ *        `guid`, limited to its first `limit` bytes, unless the variable is
+ *        redacted or the buffer may not be read.
+ */
+pub fn inspect<'a>(
+    name: &str,
+    guid: &efi::Guid,
+    data: *const core::ffi::c_void,
+    data_size: usize,
+    limit: usize,
+) -> Inspection<'a> {
+    if redact::is_redacted(name, guid) {
+        return Inspection::Redacted;
+    }
+    if !data_access_allowed() {
+        return Inspection::NotInspected;
+    }
+    match unsafe { caller::bytes(data, core::cmp::min(data_size, limit)) } {
+        Some(data) => Inspection::Data(data),
+        None => Inspection::NotInspected,
+    }
+}
+
+fn access_allowed(phase: Phase, runtime_data_access: RuntimeDataAccess) -> bool {
+    phase == Phase::BootServices || runtime_data_access == RuntimeDataAccess::Allow
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_access_is_denied_at_runtime_by_default() {
+        assert!(access_allowed(Phase::BootServices, RuntimeDataAccess::Deny));
+        assert!(access_allowed(
+            Phase::BootServices,
+            RuntimeDataAccess::Allow
+        ));
+        assert!(!access_allowed(Phase::Runtime, RuntimeDataAccess::Deny));
+        assert!(access_allowed(Phase::Runtime, RuntimeDataAccess::Allow));
+        assert_eq!(RuntimeDataAccess::from_str("lax"), None);
+    }
+}
@@ -0,0 +1,499 @@
+// uefi-var-monitor-rust/src/seen.rs
+//
+// Attributes last observed per variable, from successful GetVariable calls
+// and SetVariable writes. Rules that depend on what a variable was, rather
+// than on what a call claims, look them up here; a delete request carries no
+// meaningful attributes of its own.
+//
+// The table also learns the size each variable reads back with, across boots
+// through the boot-report variable (see report.rs). A successful read whose
+// size is more than SizePolicy::factor times off the learned one, or that
+// crosses SizePolicy::limit, is an anomaly. A size is only compared against
+// once it was confirmed on MIN_CONFIRMATIONS boots, so that a variable seen
+// for the first time, or just rewritten, does not raise one.
+//
+// Variables are keyed by vendor GUID and the CRC32 of their name. The table
+// is bounded; once full, the oldest entry is replaced. It is used at OS
+// runtime, possibly on several CPUs, so it is only ever try-borrowed: an
+// access that finds it busy is skipped rather than waited for.
+//
+// For the single-byte Secure Boot mode variables, the value last observed is
+// kept as well, so that a read or write showing another one can be reported
+// as a transition (see mode.rs).
+//
+// With read records set to changes (UVM_READ_RECORDS, see settings.rs in the library), the
+// CRC32 of the data each traced read returned is kept too, and the G: record
+// of a read is only written when it differs from the one kept, noting how
+// many reads returned the same data before. The data is only hashed where
+// safety.rs allows reading it; a read that may not be hashed, or that finds
+// the table busy, is recorded as with every read. An entry that was replaced
+// takes its digest with it: the next read of that variable is a first read
+// again, recorded as such and never as a change, since what it replaced is
+// not known.
+
+use crate::crc32;
+use crate::safety::{self, Inspection};
+use atomic_refcell::AtomicRefCell;
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicU32, Ordering};
+use r_efi::efi;
+use uvm_interface::report::LearnedSize;
+
+pub use uefi_var_monitor::record::Content;
+pub use uefi_var_monitor::settings::{
+    ReadRecords, SizePolicy, DEFAULT_SIZE_FACTOR, DEFAULT_SIZE_LIMIT,
+};
+
+pub const MAX_SEEN: usize = 64;
+// Boots a size must have been read back on before it is compared against.
+pub const MIN_CONFIRMATIONS: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeChange {
+    pub previous: u32,
+    pub size: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Seen {
+    guid: efi::Guid,
+    name_crc32: u32,
+    attributes: Option<u32>,
+    size: Option<u32>,
+    confirmations: u32,
+    // Whether this boot already counted as a confirmation.
+    confirmed: bool,
+    // Value of a single-byte mode variable.
+    mode: Option<u8>,
+    // CRC32 of the data last read, and how many reads returned it since.
+    digest: Option<u32>,
+    unchanged: u32,
+}
+
+pub struct Table<const N: usize> {
+    entries: [Option<Seen>; N],
+    // Entry replaced next once the table is full.
+    next: usize,
+}
+
+impl<const N: usize> Table<N> {
+    pub const fn new() -> Self {
+        Table {
+            entries: [None; N],
+            next: 0,
+        }
+    }
+
+    fn find(&mut self, guid: &efi::Guid, name_crc32: u32) -> Option<&mut Seen> {
+        self.entries
+            .iter_mut()
+            .flatten()
+            .find(|seen| seen.name_crc32 == name_crc32 && seen.guid == *guid)
+    }
+
+    /**
+     * @brief Returns the entry of a variable, taking a free or the oldest
+     *        one if it has none.
+     */
+    fn entry(&mut self, guid: &efi::Guid, name_crc32: u32) -> Option<&mut Seen> {
+        let position = self.entries.iter().position(|entry| match entry {
+            Some(seen) => seen.name_crc32 == name_crc32 && seen.guid == *guid,
+            None => false,
+        });
+        let index = match position {
+            Some(index) => index,
+            None => {
+                let index = match self.entries.iter().position(|entry| entry.is_none()) {
+                    Some(index) => index,
+                    None => {
+                        let index = self.next;
+                        self.next = (self.next + 1) % N;
+                        index
+                    }
+                };
+                *self.entries.get_mut(index)? = Some(Seen {
+                    guid: *guid,
+                    name_crc32,
+                    attributes: None,
+                    size: None,
+                    confirmations: 0,
+                    confirmed: false,
+                    mode: None,
+                    digest: None,
+                    unchanged: 0,
+                });
+                index
+            }
+        };
+        self.entries.get_mut(index)?.as_mut()
+    }
+
+    /**
+     * @brief Records the attributes of a variable.
+     */
+    pub fn record(&mut self, name: &str, guid: &efi::Guid, attributes: u32) {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        if let Some(seen) = self.entry(guid, name_crc32) {
+            seen.attributes = Some(attributes);
+        }
+    }
+
+    /**
+     * @brief Returns the attributes last recorded for a variable.
+     */
+    pub fn attributes(&mut self, name: &str, guid: &efi::Guid) -> Option<u32> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        self.find(guid, name_crc32).and_then(|seen| seen.attributes)
+    }
+
+    /**
+     * @brief Records the size a variable read back with. Returns the change
+     *        if it is anomalous against a confirmed size.
+     */
+    pub fn observe_size(
+        &mut self,
+        name: &str,
+        guid: &efi::Guid,
+        size: u32,
+        policy: &SizePolicy,
+    ) -> Option<SizeChange> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        let seen = self.entry(guid, name_crc32)?;
+        match seen.size {
+            Some(previous) if previous == size => {
+                if !seen.confirmed {
+                    seen.confirmed = true;
+                    seen.confirmations = seen.confirmations.saturating_add(1);
+                }
+                None
+            }
+            previous => {
+                let confirmed = seen.confirmations >= MIN_CONFIRMATIONS;
+                seen.size = Some(size);
+                seen.confirmations = 1;
+                seen.confirmed = true;
+                match previous {
+                    Some(previous) if confirmed && policy.is_anomalous(previous, size) => {
+                        Some(SizeChange { previous, size })
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /**
+     * @brief Records the value a mode variable was observed with. Returns
+     *        the one it replaces if it differs.
+     */
+    pub fn observe_mode(&mut self, name: &str, guid: &efi::Guid, value: u8) -> Option<u8> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        let seen = self.entry(guid, name_crc32)?;
+        let previous = seen.mode.replace(value);
+        previous.filter(|previous| *previous != value)
+    }
+
+    /**
+     * @brief Records the CRC32 of the data a variable was read with, and
+     *        returns how it compares with the one kept.
+     */
+    pub fn observe_content(
+        &mut self,
+        name: &str,
+        guid: &efi::Guid,
+        digest: u32,
+    ) -> Option<Content> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        let seen = self.entry(guid, name_crc32)?;
+        let content = match seen.digest.replace(digest) {
+            None => Content::First,
+            Some(previous) if previous == digest => {
+                seen.unchanged = seen.unchanged.saturating_add(1);
+                return Some(Content::Unchanged);
+            }
+            Some(_) => Content::Changed {
+                unchanged_reads: seen.unchanged,
+            },
+        };
+        seen.unchanged = 0;
+        Some(content)
+    }
+
+    /**
+     * @brief Takes a size learned on a previous boot, unless the variable was
+     *        already read on this one.
+     */
+    pub fn learn(&mut self, learned: &LearnedSize) {
+        if let Some(seen) = self.entry(&learned.guid, learned.name_crc32) {
+            if seen.size.is_none() {
+                seen.size = Some(learned.size);
+                seen.confirmations = learned.confirmations;
+            }
+        }
+    }
+
+    /**
+     * @brief Fills `sizes` with the learned sizes, as many as fit. Returns how
+     *        many were written.
+     */
+    pub fn learned_sizes(&self, sizes: &mut [LearnedSize]) -> usize {
+        let learned = self.entries.iter().flatten().filter_map(|seen| {
+            Some(LearnedSize {
+                guid: seen.guid,
+                name_crc32: seen.name_crc32,
+                size: seen.size?,
+                confirmations: seen.confirmations,
+                reserved: 0,
+            })
+        });
+        let mut count = 0;
+        for (slot, learned) in sizes.iter_mut().zip(learned) {
+            *slot = learned;
+            count += 1;
+        }
+        count
+    }
+}
+
+static TABLE: AtomicRefCell<Table<MAX_SEEN>> = AtomicRefCell::new(Table::new());
+static SIZE_FACTOR: AtomicU32 = AtomicU32::new(DEFAULT_SIZE_FACTOR);
+static SIZE_LIMIT: AtomicU32 = AtomicU32::new(DEFAULT_SIZE_LIMIT);
+static READ_RECORDS: AtomicU32 = AtomicU32::new(ReadRecords::Every as u32);
+
+/**
+ * @brief Sets what makes a size change anomalous. A factor below 2 is
+ *        raised to 2.
+ */
+pub fn set_size_policy(policy: SizePolicy) {
+    SIZE_FACTOR.store(core::cmp::max(policy.factor, 2), Ordering::Release);
+    SIZE_LIMIT.store(policy.limit, Ordering::Release);
+}
+
+/**
+ * @brief Records the attributes of a variable, unless the table is in use.
+ */
+pub fn record(name: &str, guid: &efi::Guid, attributes: u32) {
+    if let Ok(mut table) = TABLE.try_borrow_mut() {
+        table.record(name, guid, attributes);
+    }
+}
+
+/**
+ * @brief Returns the attributes last recorded for a variable, if known and
+ *        the table is not in use.
+ */
+pub fn attributes(name: &str, guid: &efi::Guid) -> Option<u32> {
+    TABLE
+        .try_borrow_mut()
+        .ok()
+        .and_then(|mut table| table.attributes(name, guid))
+}
+
+/**
+ * @brief Records the size a variable read back with, unless the table is in
+ *        use, and returns the change if it is anomalous.
+ */
+pub fn observe_size(name: &str, guid: &efi::Guid, size: usize) -> Option<SizeChange> {
+    let policy = SizePolicy {
+        factor: SIZE_FACTOR.load(Ordering::Acquire),
+        limit: SIZE_LIMIT.load(Ordering::Acquire),
+    };
+    let size = u32::try_from(size).unwrap_or(u32::MAX);
+    TABLE
+        .try_borrow_mut()
+        .ok()
+        .and_then(|mut table| table.observe_size(name, guid, size, &policy))
+}
+
+/**
+ * @brief Records the value a mode variable was observed with, unless the
+ *        table is in use, and returns the one it replaces if it differs.
+ */
+pub fn observe_mode(name: &str, guid: &efi::Guid, value: u8) -> Option<u8> {
+    TABLE
+        .try_borrow_mut()
+        .ok()
+        .and_then(|mut table| table.observe_mode(name, guid, value))
+}
+
+/**
+ * @brief Sets which traced reads get a G: record.
+ */
+pub fn set_read_records(records: ReadRecords) {
+    READ_RECORDS.store(records as u32, Ordering::Release);
+}
+
+pub fn read_records() -> ReadRecords {
+    ReadRecords::from_u32(READ_RECORDS.load(Ordering::Acquire)).unwrap_or(ReadRecords::Every)
+}
+
+/**
+ * @brief Returns how the data of a successful read of `size` bytes compares
+ *        with the last one, in changes mode. None if every read is recorded,
+ *        the data may not be read (see safety.rs) or the table is in use.
+ */
+pub fn observe_read(
+    name: &str,
+    guid: &efi::Guid,
+    data: *const core::ffi::c_void,
+    size: usize,
+) -> Option<Content> {
+    if read_records() != ReadRecords::Changes {
+        return None;
+    }
+    let data = match safety::inspect(name, guid, data, size, size) {
+        Inspection::Data(data) => data,
+        _ => return None,
+    };
+    let digest = crc32::crc32(data);
+    TABLE
+        .try_borrow_mut()
+        .ok()
+        .and_then(|mut table| table.observe_content(name, guid, digest))
+}
+
+/**
+ * @brief Takes the sizes learned on the previous boot.
+ */
+pub fn learn(sizes: &[LearnedSize]) {
+    if let Ok(mut table) = TABLE.try_borrow_mut() {
+        for learned in sizes {
+            table.learn(learned);
+        }
+    }
+}
+
+/**
+ * @brief Fills `sizes` with the learned sizes. Returns how many were
+ *        written, none if the table is in use.
+ */
+pub fn learned_sizes(sizes: &mut [LearnedSize]) -> usize {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.learned_sizes(sizes),
+        Err(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID};
+
+    #[test]
+    fn bounded_and_keyed_by_name_and_guid() {
+        let mut table = Table::<2>::new();
+        table.record("db", &IMAGE_SECURITY_DATABASE_GUID, 0x27);
+        table.record("db", &GLOBAL_VARIABLE_GUID, 0x07);
+        assert_eq!(
+            table.attributes("db", &IMAGE_SECURITY_DATABASE_GUID),
+            Some(0x27)
+        );
+        assert_eq!(table.attributes("db", &GLOBAL_VARIABLE_GUID), Some(0x07));
+
+        // Updating an entry does not take a new one.
+        table.record("db", &GLOBAL_VARIABLE_GUID, 0x03);
+        assert_eq!(table.attributes("db", &GLOBAL_VARIABLE_GUID), Some(0x03));
+        assert_eq!(
+            table.attributes("db", &IMAGE_SECURITY_DATABASE_GUID),
+            Some(0x27)
+        );
+
+        // A third variable replaces the oldest.
+        table.record("dbx", &IMAGE_SECURITY_DATABASE_GUID, 0x27);
+        assert_eq!(table.attributes("db", &IMAGE_SECURITY_DATABASE_GUID), None);
+        assert_eq!(
+            table.attributes("dbx", &IMAGE_SECURITY_DATABASE_GUID),
+            Some(0x27)
+        );
+        assert_eq!(table.attributes("db", &GLOBAL_VARIABLE_GUID), Some(0x03));
+    }
+
+    #[test]
+    fn size_anomalies_need_a_confirmed_size() {
+        let policy = SizePolicy {
+            factor: DEFAULT_SIZE_FACTOR,
+            limit: 0x1000,
+        };
+        assert!(!policy.is_anomalous(68, 68 * 4));
+        assert!(policy.is_anomalous(68, 68 * 4 + 1));
+        assert!(policy.is_anomalous(400, 99));
+        assert!(policy.is_anomalous(0x0f00, 0x1001));
+        assert!(!policy.is_anomalous(0x1001, 0x1100));
+
+        let guid = &GLOBAL_VARIABLE_GUID;
+        let mut table = Table::<2>::new();
+        // First seen on this boot: not confirmed yet, however often read.
+        assert_eq!(table.observe_size("Lang", guid, 68, &policy), None);
+        assert_eq!(table.observe_size("Lang", guid, 68, &policy), None);
+        assert_eq!(table.observe_size("Lang", guid, 20000, &policy), None);
+
+        // Learned on previous boots, and confirmed by this one.
+        let mut learned = [LearnedSize::EMPTY; 2];
+        assert_eq!(table.learned_sizes(&mut learned), 1);
+        assert_eq!((learned[0].size, learned[0].confirmations), (20000, 1));
+        let mut table = Table::<2>::new();
+        table.learn(&LearnedSize {
+            size: 68,
+            ..learned[0]
+        });
+        assert_eq!(table.observe_size("Lang", guid, 68, &policy), None);
+        assert_eq!(
+            table.observe_size("Lang", guid, 20000, &policy),
+            Some(SizeChange {
+                previous: 68,
+                size: 20000
+            })
+        );
+        // The new size is learned from scratch.
+        assert_eq!(table.observe_size("Lang", guid, 68, &policy), None);
+
+        // Attributes and sizes share entries.
+        table.record("Lang", guid, 0x07);
+        table.record("PK", guid, 0x27);
+        table.record("KEK", guid, 0x27);
+        assert_eq!(table.learned_sizes(&mut learned), 0);
+        assert_eq!(table.attributes("Lang", guid), None);
+        assert_eq!(table.attributes("PK", guid), Some(0x27));
+    }
+
+    #[test]
+    fn mode_values_report_changes_only() {
+        let guid = &GLOBAL_VARIABLE_GUID;
+        let mut table = Table::<2>::new();
+        assert_eq!(table.observe_mode("SetupMode", guid, 1), None);
+        assert_eq!(table.observe_mode("SetupMode", guid, 1), None);
+        assert_eq!(table.observe_mode("SecureBoot", guid, 0), None);
+        assert_eq!(table.observe_mode("SetupMode", guid, 0), Some(1));
+        assert_eq!(table.observe_mode("SetupMode", guid, 0), None);
+        assert_eq!(table.observe_mode("SecureBoot", guid, 1), Some(0));
+    }
+
+    #[test]
+    fn content_changes_count_the_reads_between() {
+        let guid = &GLOBAL_VARIABLE_GUID;
+        let mut table = Table::<2>::new();
+        assert_eq!(table.observe_content("Lang", guid, 1), Some(Content::First));
+        assert_eq!(
+            table.observe_content("Lang", guid, 1),
+            Some(Content::Unchanged)
+        );
+        assert_eq!(
+            table.observe_content("Lang", guid, 1),
+            Some(Content::Unchanged)
+        );
+        assert_eq!(
+            table.observe_content("Lang", guid, 2),
+            Some(Content::Changed { unchanged_reads: 2 })
+        );
+        assert_eq!(
+            table.observe_content("Lang", guid, 1),
+            Some(Content::Changed { unchanged_reads: 0 })
+        );
+
+        // Replaced and read again with other data: a first read, not a change.
+        table.record("PK", guid, 0x27);
+        table.record("KEK", guid, 0x27);
+        assert_eq!(table.observe_content("Lang", guid, 3), Some(Content::First));
+        assert_eq!(ReadRecords::from_str("some"), None);
+    }
+}
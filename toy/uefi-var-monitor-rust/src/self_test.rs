@@ -0,0 +1,147 @@
+// uefi-var-monitor-rust/src/self_test.rs
+//
+// A health check of the running monitor, run through the monitor's protocol
+// (see protocol.rs and tools/uvmctl). Each check sets a SELF_TEST_* bit (see
+// interface/src/protocol.rs) once run, and another once passed:
+//
+//   slots      the GetVariable and SetVariable slots of the runtime services
+//              table, as last followed, point at the hooks
+//   call       a GetVariable of PlatformLang through the table is counted by
+//              the hook; what firmware answers does not matter
+//   sinks      a test record is written to every active sink: the serial
+//              port must not have failed on it, and the ring buffer must
+//              hold one more record
+//
+// It only reads a variable and writes a record, so it can run any number of
+// times, in either phase; boot services are not used.
+
+#[cfg(feature = "log-ring")]
+use crate::ring;
+#[cfg(feature = "log-serial")]
+use crate::serial;
+#[cfg(not(test))]
+use crate::sink;
+use crate::tag::{self, Tag};
+use crate::{set_variable, top, GetVariableType};
+use core::sync::atomic::Ordering;
+use r_efi::efi;
+#[cfg(feature = "log-ring")]
+use uvm_interface::protocol::SELF_TEST_RING;
+#[cfg(feature = "log-serial")]
+use uvm_interface::protocol::SELF_TEST_SERIAL;
+use uvm_interface::protocol::{
+    SelfTestResult, SELF_TEST_CALL_OBSERVED, SELF_TEST_GET_VARIABLE_SLOT,
+    SELF_TEST_SET_VARIABLE_SLOT,
+};
+
+const TAG: Tag = tag::HOOK;
+
+pub const SELF_TEST_RECORD: &str = "---- Self-test record ----";
+
+// "PlatformLang", which every platform defines; benchmark.rs reads it too.
+pub const PROBE_NAME: [u16; 13] = [
+    b'P' as u16,
+    b'l' as u16,
+    b'a' as u16,
+    b't' as u16,
+    b'f' as u16,
+    b'o' as u16,
+    b'r' as u16,
+    b'm' as u16,
+    b'L' as u16,
+    b'a' as u16,
+    b'n' as u16,
+    b'g' as u16,
+    0,
+];
+
+struct Checks(SelfTestResult);
+
+impl Checks {
+    fn record(&mut self, check: u32, passed: bool) {
+        self.0.checked |= check;
+        if passed {
+            self.0.passed |= check;
+        }
+    }
+}
+
+/**
+ * @brief Runs every check that applies.
+ */
+pub fn run() -> SelfTestResult {
+    let mut checks = Checks(SelfTestResult::default());
+    let runtime_services = crate::RUNTIME_SERVICES.load(Ordering::Acquire);
+    if !runtime_services.is_null() {
+        let runtime_services = unsafe { &mut *runtime_services };
+        let hook = crate::handle_get_variable as GetVariableType as usize;
+        checks.record(
+            SELF_TEST_GET_VARIABLE_SLOT,
+            runtime_services.get_variable as usize == hook,
+        );
+        checks.record(
+            SELF_TEST_SET_VARIABLE_SLOT,
+            set_variable::is_head(runtime_services),
+        );
+        checks.record(SELF_TEST_CALL_OBSERVED, call_observed(runtime_services));
+    }
+
+    #[cfg(feature = "log-serial")]
+    let failures = serial::failures();
+    #[cfg(feature = "log-ring")]
+    let next_sequence = ring::header().map(|header| header.next_sequence);
+    write_record();
+    #[cfg(feature = "log-serial")]
+    checks.record(SELF_TEST_SERIAL, serial::failures() == failures);
+    #[cfg(feature = "log-ring")]
+    {
+        let now = ring::header().map(|header| header.next_sequence);
+        let stored = matches!((next_sequence, now), (Some(before), Some(now)) if now > before);
+        checks.record(SELF_TEST_RING, stored);
+    }
+    let result = checks.0;
+    info!(
+        TAG,
+        "Self-test: checks {:#x}, failed {:#x}",
+        result.checked,
+        result.failed()
+    );
+    result
+}
+
+/**
+ * @brief Writes the test record to every active sink.
+ */
+#[cfg(not(test))]
+fn write_record() {
+    sink::write(format_args!("{}", SELF_TEST_RECORD));
+}
+
+// Tests have no sinks, and log! only captures (see logging.rs); the ring
+// buffer stands in for them.
+#[cfg(test)]
+fn write_record() {
+    crate::serial::capture(format_args!("{}", SELF_TEST_RECORD));
+    #[cfg(feature = "log-ring")]
+    ring::push_record(format_args!("{}", SELF_TEST_RECORD));
+}
+
+/**
+ * @brief Calls GetVariable through the table, and returns whether the hook
+ *        counted the call.
+ */
+fn call_observed(runtime_services: &mut efi::RuntimeServices) -> bool {
+    let mut name = PROBE_NAME;
+    let mut guid = crate::classify::GLOBAL_VARIABLE_GUID;
+    let mut data = [0u8; 16];
+    let mut size = data.len();
+    let (reads, _) = top::totals();
+    let _ = (runtime_services.get_variable)(
+        name.as_mut_ptr(),
+        &mut guid,
+        core::ptr::null_mut(),
+        &mut size,
+        data.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    top::totals().0 > reads
+}
@@ -16,42 +16,186 @@
 // Inspired by https://github.com/phil-opp/blog_os/blob/post-03/src/vga_buffer.rs
 // from Philipp Oppermann
 
+// The serial port the serial sink writes to, through the line writer shared
+// with uvmctl (see support/src/line.rs): a record goes out to the UART in one
+// slice, and one that finds the port busy, or that the UART drops part of,
+// is lost and counted rather than panicking.
+
+// Host tests print records instead, and builds without log-serial only
+// count; the serial writers are unused there.
+#![cfg_attr(any(test, not(feature = "log-serial")), allow(dead_code))]
+
+use crate::arch::{Arch, Current};
 use core::fmt;
-use atomic_refcell::AtomicRefCell;
-use x86_64::instructions::port::PortWriteOnly;
+use uvm_support::line::LineWriter;
 
-// We use COM1 as it is the standard first serial port.
-static PORT: AtomicRefCell<PortWriteOnly<u8>> = AtomicRefCell::new(PortWriteOnly::new(0x3f8));
+static PORT: LineWriter = LineWriter::new(Current::write_bytes);
 
-pub struct Serial;
+/**
+ * @brief Counts a record log! could not write to serial output.
+ */
+pub fn record_failure() {
+    PORT.record_failure();
+}
 
-impl fmt::Write for Serial {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        let mut port = PORT.borrow_mut();
-        for b in s.bytes() {
-            unsafe { port.write(b) }
+/**
+ * @brief Returns the number of records lost on serial output so far.
+ */
+pub fn failures() -> u64 {
+    PORT.failures()
+}
+
+/**
+ * @brief Zeroes the count of lost records.
+ */
+pub fn reset_failures() {
+    PORT.reset_failures();
+}
+
+#[cfg(test)]
+std::thread_local! {
+    static CAPTURED: core::cell::RefCell<Option<std::string::String>> =
+        const { core::cell::RefCell::new(None) };
+}
+
+/**
+ * @brief Starts collecting the records logged by the calling test thread.
+ */
+#[cfg(test)]
+pub fn start_capture() {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(std::string::String::new()));
+}
+
+/**
+ * @brief Stops collecting records and returns those logged since
+ *        start_capture().
+ */
+#[cfg(test)]
+pub fn take_capture() -> std::string::String {
+    CAPTURED.with(|captured| captured.borrow_mut().take().unwrap_or_default())
+}
+
+/**
+ * @brief Returns `records` without the locations log-src-loc starts lines
+ *        with, for tests comparing what was logged.
+ */
+#[cfg(test)]
+pub fn without_locations(records: &str) -> std::string::String {
+    let mut text = std::string::String::with_capacity(records.len());
+    for line in records.lines() {
+        let line = match (cfg!(feature = "log-src-loc"), line.split_once("] ")) {
+            (true, Some((location, rest))) if location.starts_with('[') => rest,
+            _ => line,
+        };
+        text.push_str(line);
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+pub fn capture(args: fmt::Arguments) {
+    use core::fmt::Write;
+    CAPTURED.with(|captured| {
+        if let Some(records) = captured.borrow_mut().as_mut() {
+            let _ = writeln!(records, "{}", args);
         }
-        Ok(())
+    });
+}
+
+/**
+ * @brief Writes one record to serial output. Registered as the serial log
+ *        sink.
+ */
+pub fn write_record(args: fmt::Arguments) {
+    if write_line(args).is_err() {
+        record_failure();
     }
 }
 
-// New function to log error messages with a specific prefix
-pub fn log_error(message: &str) {
-    let _ = writeln!(Serial, "[ERROR] {}", message);
+/**
+ * @brief Writes `args` and a line break to serial output, in one slice if
+ *        it fits the line.
+ */
+pub fn write_line(args: fmt::Arguments) -> fmt::Result {
+    PORT.write_line(args)
+}
+
+/**
+ * @brief Writes a record formatted already, and a line break, as write_line
+ *        does but without core::fmt.
+ */
+#[cfg(any(test, feature = "serial-queue"))]
+pub fn write_text(text: &str) -> fmt::Result {
+    PORT.write_text(text)
+}
+
+// Writer for the panic handler only. It does not borrow PORT, which the
+// panicking code may be holding, and formats numbers itself rather than
+// going back through the logging stack. With the mock feature there is no
+// panic handler of ours (see main.rs).
+#[cfg(feature = "log-panic")]
+#[cfg_attr(feature = "mock", allow(dead_code))]
+pub struct PanicSerial;
+
+#[cfg(feature = "log-panic")]
+#[cfg_attr(feature = "mock", allow(dead_code))]
+impl PanicSerial {
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let _ = Current::write_bytes(bytes);
+    }
+
+    pub fn write_decimal(&mut self, mut value: u32) {
+        let mut digits = [0u8; 10];
+        let mut start = digits.len();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        self.write_bytes(&digits[start..]);
+    }
 }
 
-// New function to log info messages with a specific prefix
-pub fn log_info(message: &str) {
-    let _ = writeln!(Serial, "[INFO] {}", message);
+#[cfg(feature = "log-panic")]
+impl fmt::Write for PanicSerial {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
 }
 
-#[macro_export]
-macro_rules! log {
-    ($($arg:tt)*) => {{
-        use core::fmt::Write;
-        #[cfg(all(feature = "log-serial", not(test)))]
-        writeln!(crate::serial::Serial, $($arg)*).unwrap();
-        #[cfg(all(feature = "log-serial", test))]
-        println!($($arg)*);
-    }};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tag::{self, Tag};
+    use std::format;
+
+    const TAG: Tag = tag::SINK;
+
+    #[test]
+    fn lines_about_the_driver_carry_their_location_if_built_with_it() {
+        let _lock = crate::mock::lock();
+        crate::level::reset();
+        start_capture();
+        let line = line!() + 1;
+        info!(TAG, "Loaded");
+        log_record!("G: record");
+        let records = take_capture();
+
+        if cfg!(feature = "log-src-loc") {
+            assert_eq!(
+                records,
+                format!(
+                    "[serial::tests::lines_about_the_driver_carry_their_location_if_built_with_it:{}] [sink] Loaded\nG: record\n",
+                    line
+                )
+            );
+        } else {
+            assert_eq!(records, "[sink] Loaded\nG: record\n");
+        }
+    }
 }
@@ -0,0 +1,529 @@
+// uefi-var-monitor-rust/src/set_variable.rs
+//
+// The SetVariable hook. It is installed, relocated, followed and removed
+// along with the GetVariable one in main.rs, but is simpler: nothing re-hooks
+// it, so SET_VARIABLE always holds what the table held at load.
+//
+// Each call is logged and run through the alert rules. A deletion attempt
+// against a variable last seen as time-based authenticated is flagged
+// whether or not firmware accepts it, with whether the variable still exists
+// afterwards. So is any write to the boot manager variables after
+// ExitBootServices, with the value it replaced (see boot_option.rs). Calls are
+// also run through the correlation rules (see correlate.rs), writes to the
+// MOR variables are decoded (see mor.rs), and so are appends to the image
+// security databases (see signature.rs). Writes changing a Secure Boot mode
+// variable are reported as transitions (see mode.rs), and each call counts
+// towards the access rate of its variable (see rate.rs). Accepted writes to
+// shadowed variables update their shadow (see shadow.rs), and accepted writes
+// to traced variables are followed by what they changed (see diff.rs). Writes
+// to variables with a write history, accepted or not, are added to it (see
+// history.rs), and accepted writes are marked for the comparison of the store
+// at ReadyToBoot (see inventory.rs). Accepted writes to variables created
+// since load are tagged NEW (see newcomer.rs). Built with `enforce`, writes to
+// protected variables are failed without reaching firmware (see enforce.rs),
+// and so are writes changing a variable locked at ReadyToBoot (see lock.rs).
+
+use crate::alerts::Rule;
+use crate::arch::{self, Arch};
+use crate::boot_option::{self, Shown};
+use crate::budget;
+use crate::caller;
+use crate::classify::{self, VariableClass};
+use crate::control;
+use crate::decide::{self, Record};
+use crate::fields::{self, Extra};
+use crate::filter;
+use crate::hook::HookSlot;
+use crate::images;
+use crate::integrity;
+use crate::level;
+use crate::tag::{self, Tag};
+use crate::{
+    correlate, counters, diff, history, inventory, last_value, mode, mor, newcomer, rate, rules,
+    seen, shadow, signature, top, Phase, SetVariableType, HOOK_ACTIVE, HOOK_PASS_THROUGH,
+    HOOK_UNUSABLE,
+};
+#[cfg(feature = "enforce")]
+use crate::{enforce, lock};
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicU8, Ordering};
+use r_efi::efi;
+
+const TAG: Tag = tag::HOOK;
+
+pub static SET_VARIABLE: HookSlot<SetVariableType> = HookSlot::new();
+
+// What handle_set_variable may still do after a failed relocation, as
+// HOOK_STATE is for GetVariable.
+static STATE: AtomicU8 = AtomicU8::new(HOOK_ACTIVE);
+
+/**
+ * @brief Returns the state of the hook.
+ */
+pub fn state() -> u8 {
+    STATE.load(Ordering::Acquire)
+}
+
+static DELETION_ATTEMPTS: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Returns the number of deletion attempts against authenticated
+ *        variables seen so far.
+ */
+pub fn deletion_attempts() -> u64 {
+    DELETION_ATTEMPTS.load(Ordering::Acquire)
+}
+
+/**
+ * @brief Zeroes the count of deletion attempts.
+ */
+pub fn reset_deletion_attempts() {
+    DELETION_ATTEMPTS.store(0, Ordering::Release);
+}
+
+efiapi! {
+    /**
+     * @brief Handles SetVariable runtime service calls.
+     */
+    pub fn handle_set_variable(
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: u32,
+        data_size: usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        match STATE.load(Ordering::Acquire) {
+            HOOK_ACTIVE => {}
+            HOOK_PASS_THROUGH => {
+                return SET_VARIABLE.call(variable_name, vendor_guid, attributes, data_size, data)
+            }
+            _ => return efi::Status::DEVICE_ERROR,
+        }
+
+        // Whoever cut out the GetVariable hook may have missed this one.
+        integrity::check("SetVariable");
+        let caller = arch::Current::return_address();
+        if let Some(efi_status) = control::intercept(variable_name, vendor_guid, data_size, data) {
+            return efi_status;
+        }
+        #[cfg(feature = "enforce")]
+        if let Some(efi_status) = gate(variable_name, vendor_guid, attributes, data_size, data, caller) {
+            return efi_status;
+        }
+        let efi_status = SET_VARIABLE.call(variable_name, vendor_guid, attributes, data_size, data);
+
+        let guid = match unsafe { caller::value(vendor_guid) } {
+            Some(guid) if !variable_name.is_null() => guid,
+            _ => {
+                record!(Set {
+                    variable: None,
+                    size: fields::Size::Write {
+                        attributes,
+                        data_size,
+                    },
+                    efi_status,
+                });
+                return efi_status;
+            }
+        };
+
+        // As for GetVariable, decide on the S: record before decoding the name.
+        let guid = &guid;
+        counters::count(top::Access::Write, efi_status, Some(data_size));
+        if efi_status == efi::Status::SUCCESS {
+            counters::count_storage(top::Access::Write, attributes, data_size);
+        }
+        let plan = decide::plan(
+            cfg!(feature = "profile-minimal"),
+            budget::step(),
+            level::level(),
+            level::is_paused(),
+        );
+        if !plan.named {
+            top::count_guid(guid, top::Access::Write);
+            diff::missed();
+            history::missed();
+            inventory::missed();
+            return efi_status;
+        }
+        let traced = plan.may_trace && filter::may_trace(guid);
+
+        let rendered = crate::names::render(variable_name, guid);
+        let name = rendered.name();
+        rate::observe(name, guid, caller);
+        top::count(name, guid, top::Access::Write);
+        if efi_status == efi::Status::SUCCESS {
+            counters::note_storage(name, guid, top::Access::Write, attributes, data_size);
+        }
+        let traced = traced && filter::is_traced(name, guid);
+        let sighting = match efi_status {
+            efi::Status::SUCCESS => newcomer::observe(name, guid),
+            _ => None,
+        };
+        let variable = Some(fields::Variable {
+            variable_name,
+            guid,
+            rendered: &rendered,
+        });
+        let size = fields::Size::Write {
+            attributes,
+            data_size,
+        };
+        match decide::record(false, traced, sighting, None) {
+            // Written as text even with log-deferred, so that the tag is kept
+            // (see fields.rs and newcomer.rs).
+            Record::New(level) => record!(
+                level,
+                Set {
+                    variable,
+                    size,
+                    efi_status,
+                },
+                Extra::New,
+            ),
+            Record::Access => record!(Set {
+                variable,
+                size,
+                efi_status,
+            }),
+            Record::None => {}
+        }
+        if efi_status == efi::Status::SUCCESS {
+            if traced {
+                diff::observe_write(rendered.guid(), name, guid, attributes, data, data_size);
+            } else {
+                diff::forget(name, guid);
+            }
+            inventory::observe_write(name, guid);
+        }
+        history::record_write(name, guid, attributes, data_size, data, efi_status, caller);
+        let access = rules::Access::Set {
+            attributes,
+            data_size,
+        };
+        rules::check(name, guid, access, efi_status);
+        mor::check(
+            name,
+            guid,
+            access,
+            efi_status,
+            data,
+            Some(data_size),
+            caller,
+        );
+        signature::check(name, guid, attributes, data_size, data, efi_status, caller);
+        correlate::check(&correlate::Event {
+            name,
+            guid,
+            phase: crate::phase(),
+            access,
+            efi_status,
+            caller,
+        });
+
+        let previous = seen::attributes(name, guid);
+        if rules::is_authenticated_deletion(previous, attributes, data_size) {
+            DELETION_ATTEMPTS.fetch_add(1, Ordering::AcqRel);
+            rules::emit(
+                Rule::AuthenticatedDeletion,
+                rules::Severity::Critical,
+                format_args!(
+                    "Deletion attempt of authenticated variable {} from {}: {:#x}, {}",
+                    name,
+                    ReturnAddress(caller),
+                    efi_status.as_usize(),
+                    Existence(crate::variable_exists(variable_name, vendor_guid)),
+                ),
+            );
+        }
+        let boot_manager = classify::classify(name, guid) == VariableClass::BootManager;
+        if boot_manager && crate::phase() == Phase::Runtime {
+            alert_boot_manager_write(name, guid, caller, attributes, data_size, data, efi_status);
+        }
+        if efi_status == efi::Status::SUCCESS {
+            shadow::record_write(name, guid, attributes, data_size, data);
+        }
+        if efi_status == efi::Status::SUCCESS && !rules::is_deletion(attributes, data_size) {
+            seen::record(name, guid, attributes);
+            if plan.decode_values {
+                boot_option::observe(name, guid, data, data_size);
+            }
+            mode::observe(name, guid, "SetVariable", data, data_size, caller);
+        } else if efi_status == efi::Status::SUCCESS && boot_manager {
+            last_value::remove(name, guid);
+        }
+
+        return efi_status;
+    }
+}
+
+/**
+ * @brief Blocks a write to a protected variable, or one changing a locked
+ *        variable, instead of forwarding it. Returns the status to fail the
+ *        call with, or None to forward it.
+ */
+#[cfg(feature = "enforce")]
+fn gate(
+    variable_name: *mut r_efi::base::Char16,
+    vendor_guid: *mut r_efi::base::Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *mut core::ffi::c_void,
+    caller: Option<usize>,
+) -> Option<efi::Status> {
+    if variable_name.is_null() {
+        return None;
+    }
+    let guid = &unsafe { caller::value(vendor_guid) }?;
+    let mut name = [0u8; 64];
+    let name = crate::convert_name(variable_name, &mut name);
+    if !enforce::is_protected(name, guid) {
+        let reason = lock::check(name, guid, attributes, data_size, data)?;
+        let rejected = lock::count_rejected();
+        rules::emit(
+            Rule::BlockedWrite,
+            rules::Severity::Critical,
+            format_args!(
+                "Rejected SetVariable of locked {} {} Attributes={:08x} Size={:08x} from {}, {} (#{})",
+                crate::GuidFmt(guid),
+                name,
+                attributes,
+                data_size,
+                ReturnAddress(caller),
+                reason,
+                rejected,
+            ),
+        );
+        return Some(efi::Status::WRITE_PROTECTED);
+    }
+    // The alert may be formatted more than once.
+    let blocked = enforce::count_blocked();
+    rules::emit(
+        Rule::BlockedWrite,
+        rules::Severity::Critical,
+        format_args!(
+            "Blocked SetVariable of protected {} {} Attributes={:08x} Size={:08x} from {} (#{})",
+            crate::GuidFmt(guid),
+            name,
+            attributes,
+            data_size,
+            ReturnAddress(caller),
+            blocked,
+        ),
+    );
+    Some(efi::Status::SECURITY_VIOLATION)
+}
+
+/**
+ * @brief Alerts on a write to a boot manager variable, successful or not,
+ *        showing the value it replaces next to the new one.
+ */
+fn alert_boot_manager_write(
+    name: &str,
+    guid: &efi::Guid,
+    caller: Option<usize>,
+    attributes: u32,
+    data_size: usize,
+    data: *mut core::ffi::c_void,
+    efi_status: efi::Status,
+) {
+    let new = if rules::is_deletion(attributes, data_size) {
+        Shown::Deleted
+    } else {
+        boot_option::shown(name, guid, data, data_size)
+    };
+    last_value::with(name, guid, |previous| {
+        let previous = match previous {
+            Some(value) => Shown::Value {
+                name,
+                data: value.data(),
+            },
+            None => Shown::Unknown,
+        };
+        rules::emit(
+            Rule::BootManagerWrite,
+            rules::Severity::Critical,
+            format_args!(
+                "SetVariable of {} at OS runtime from {}: {:#x}, was {} now {}",
+                name,
+                ReturnAddress(caller),
+                efi_status.as_usize(),
+                previous,
+                new,
+            ),
+        );
+    });
+}
+
+// A caller return address for logging, as an offset into the loaded image
+// it falls inside of if known (see images.rs), "unknown" without frame
+// pointers.
+pub struct ReturnAddress(pub Option<usize>);
+
+impl core::fmt::Display for ReturnAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Some(address) => match images::find(address) {
+                Some(image) => write!(f, "{}+{:#x}", image.name(), address - image.base),
+                None => write!(f, "{:#08x}", address),
+            },
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+// Whether a variable still exists, as told by the firmware.
+struct Existence(Option<bool>);
+
+impl core::fmt::Display for Existence {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 {
+            Some(true) => f.write_str("still exists"),
+            Some(false) => f.write_str("deleted"),
+            None => f.write_str("existence unknown"),
+        }
+    }
+}
+
+fn slot(runtime_services: &mut efi::RuntimeServices) -> *mut *mut core::ffi::c_void {
+    &mut runtime_services.set_variable as *mut _ as *mut *mut core::ffi::c_void
+}
+
+/**
+ * @brief Hooks SetVariable in the system table's Runtime Services Table.
+ */
+pub fn install(system_table: &mut efi::SystemTable) -> efi::Status {
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        slot(runtime_services),
+        handle_set_variable as SetVariableType as *mut core::ffi::c_void,
+        SET_VARIABLE.as_mut_ptr(),
+        Phase::BootServices,
+    );
+    if efi_status == efi::Status::ALREADY_STARTED {
+        warn!(TAG, "SetVariable is already hooked by this driver");
+    } else if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+    }
+    efi_status
+}
+
+/**
+ * @brief Puts the saved SetVariable back into the Runtime Services Table.
+ */
+pub fn unhook(system_table: &mut efi::SystemTable) -> efi::Status {
+    let runtime_services = unsafe { &mut *system_table.runtime_services };
+    let mut hook: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        slot(runtime_services),
+        SET_VARIABLE.as_raw(),
+        &mut hook,
+        crate::phase(),
+    );
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+    }
+    efi_status
+}
+
+/**
+ * @brief Returns whether the SetVariable slot of a table points at our hook.
+ */
+pub fn is_head(runtime_services: &efi::RuntimeServices) -> bool {
+    runtime_services.set_variable as usize == handle_set_variable as SetVariableType as usize
+}
+
+/**
+ * @brief Moves the hook into the republished table `live`. Called by
+ *        follow_runtime_services_table once GetVariable was moved.
+ */
+pub fn follow(system_table: *mut efi::SystemTable, live: *mut efi::RuntimeServices, phase: Phase) {
+    let slot = slot(unsafe { &mut *live });
+    let mut found = unsafe { *slot };
+    let efi_status = crate::exchange_pointer_in_service_table(
+        system_table,
+        slot,
+        handle_set_variable as SetVariableType as *mut core::ffi::c_void,
+        &mut found,
+        phase,
+    );
+    if efi_status == efi::Status::ALREADY_STARTED {
+        return;
+    }
+    if efi_status.is_error() {
+        error!(
+            TAG,
+            "exchange_table_pointer failed : {:#x}",
+            efi_status.as_usize()
+        );
+        return;
+    }
+    SET_VARIABLE.storage().store(found, Ordering::Release);
+    info!(
+        TAG,
+        "SetVariable hook moved on top of {:#08x}", found as u64
+    );
+}
+
+/**
+ * @brief Registers SET_VARIABLE for conversion at SetVirtualAddressMap.
+ */
+pub fn register_relocations() -> efi::Status {
+    crate::relocate::register("SetVariable", SET_VARIABLE.storage())
+}
+
+/**
+ * @brief Picks what the hook may still do after a failed relocation. If
+ *        SET_VARIABLE itself was not converted, the saved physical pointer
+ *        is put back into the table for the firmware to convert, as done
+ *        for GetVariable.
+ */
+pub fn degrade(runtime_services: &mut efi::RuntimeServices) {
+    if !crate::relocate::failed(SET_VARIABLE.storage()) {
+        STATE.store(HOOK_PASS_THROUGH, Ordering::Release);
+        return;
+    }
+    STATE.store(HOOK_UNUSABLE, Ordering::Release);
+    if !is_head(runtime_services) {
+        warn!(
+            TAG,
+            "SetVariable not restored, {:#08x} is hooked after this driver",
+            runtime_services.set_variable as usize
+        );
+        return;
+    }
+    let firmware = SET_VARIABLE.as_raw();
+    if firmware.is_null() {
+        return;
+    }
+    let slot = unsafe { &*(slot(runtime_services) as *const AtomicPtr<core::ffi::c_void>) };
+    slot.store(firmware, Ordering::Release);
+    let _ = crate::update_table_crc32(None, &mut runtime_services.hdr);
+    info!(
+        TAG,
+        "SetVariable restored to the firmware's {:#08x}", firmware as u64
+    );
+}
+
+/**
+ * @brief Makes the hook fail every call. Used when SetVirtualAddressMap
+ *        found nothing to convert SET_VARIABLE with.
+ */
+pub fn disable() {
+    STATE.store(HOOK_UNUSABLE, Ordering::Release);
+}
+
+#[cfg(test)]
+pub fn reset() {
+    STATE.store(HOOK_ACTIVE, Ordering::Release);
+    DELETION_ATTEMPTS.store(0, Ordering::Release);
+}
@@ -0,0 +1,620 @@
+// uefi-var-monitor-rust/src/settings.rs
+//
+// The runtime configuration of the driver as values: the settings and what
+// each can be set to, the log level, and the two texts they are given in at
+// build time and at load. The driver applies a configuration to its modules,
+// keeps the one last applied and reads the load options of its image (see
+// config.rs and options.rs in the driver); what is parsed, and into what, is
+// decided here. The settings are given at build time in the environment:
+//
+//   UVM_RING_OVERFLOW    overwrite | drop     (default: overwrite)
+//   UVM_HOOK_INTEGRITY   record | reinstall   (default: record)
+//   UVM_HOOK_REINSTALLS  0..                  (default: 3)
+//   UVM_RUNTIME_DATA     deny | allow         (default: deny)
+//   UVM_TPM_PCR          0..23                (default: 7)
+//   UVM_SIZE_FACTOR      2..                  (default: 4)
+//   UVM_SIZE_LIMIT       bytes                (default: 32768)
+//   UVM_RATE_LIMIT       calls/s, 0 disables  (default: 100)
+//   UVM_RATE_SUSTAIN     1.. seconds          (default: 3)
+//   UVM_LOCK_ABSENT      lock | ignore        (default: lock, enforce only)
+//   UVM_LEVEL_WRITES     protect | allow      (default: protect, enforce only)
+//   UVM_ALERT_LIMITS     rule=n[/m[s]];...    (see alert.rs)
+//   UVM_CTL_FORWARD      drop | forward       (default: drop)
+//   UVM_STATS_RESET      deny | allow         (default: deny)
+//   UVM_LATENCY_BUDGET   µs/call, 0 disables  (default: 50)
+//   UVM_READ_RECORDS     every | changes      (default: every)
+//
+// and at load in the image's load options: after the image path on the
+// shell's command line, or as the optional data of a Driver#### option, e.g.
+//
+//   load UefiVarMonitor.efi level=info rate-limit=20 trace=8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*
+//
+// The options are UCS-2 text of key=value words separated by spaces. A first
+// word without '=' is the image path and is skipped. The keys are those of
+// the environment without the UVM_ prefix, in lowercase with '-' for '_'
+// (ring-overflow, rate-limit, ctl-forward, ...) and take the same values,
+// plus:
+//
+//   level   critical | warning | info | trace, for this boot only
+//   trace   the trace filter, as a list of pattern.rs (see filter.rs)
+//
+// An unknown key, a malformed word or a value that does not parse is handed
+// back and skipped; the rest still applies. Only the first MAX_OPTIONS_SIZE
+// characters are read, and non-ASCII characters become '?'.
+
+use crate::alert::{self, LimitTable};
+use r_efi::efi;
+
+// The levels of records are those of the logging shared with uvmctl.
+pub use uvm_support::level::Level;
+
+// Vendor GUID of the variables owned by the monitor.
+// {6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90}
+pub const UVM_VENDOR_GUID: efi::Guid = efi::Guid::from_fields(
+    0x6c8a7f3e,
+    0x2d4b,
+    0x4f1a,
+    0x9c,
+    0x5e,
+    &[0x8b, 0x2d, 0x1f, 0x7a, 0x3c, 0x90],
+);
+
+pub const DEFAULT_MAX_REINSTALLS: u32 = 3;
+pub const DEFAULT_PCR: u32 = 7;
+// PCRs of a PC Client TPM.
+pub const MAX_PCR: u32 = 23;
+pub const DEFAULT_SIZE_FACTOR: u32 = 4;
+pub const DEFAULT_SIZE_LIMIT: u32 = 32 * 1024;
+pub const DEFAULT_RATE_LIMIT: u32 = 100;
+pub const DEFAULT_RATE_SUSTAIN: u32 = 3;
+// Microseconds per call.
+pub const DEFAULT_LATENCY_BUDGET: u32 = 50;
+
+// Characters of the load options read.
+pub const MAX_OPTIONS_SIZE: usize = 512;
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Wrap around and overwrite the oldest record (keeps the latest evidence).
+    OverwriteOldest = 0,
+    // Stop recording when full (keeps the earliest evidence).
+    DropNewest = 1,
+}
+
+impl OverflowPolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(OverflowPolicy::OverwriteOldest),
+            1 => Some(OverflowPolicy::DropNewest),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "overwrite" | "overwrite-oldest" => Some(OverflowPolicy::OverwriteOldest),
+            "drop" | "drop-newest" => Some(OverflowPolicy::DropNewest),
+            _ => None,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegrityPolicy {
+    // Only raise the alert and count the replacement.
+    Record = 0,
+    // Also hook again on top of the interloper.
+    Reinstall = 1,
+}
+
+impl IntegrityPolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(IntegrityPolicy::Record),
+            1 => Some(IntegrityPolicy::Reinstall),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "record" => Some(IntegrityPolicy::Record),
+            "reinstall" => Some(IntegrityPolicy::Reinstall),
+            _ => None,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuntimeDataAccess {
+    // Never read the data buffer at OS runtime.
+    Deny = 0,
+    // Keep reading it; for lab machines only.
+    Allow = 1,
+}
+
+impl RuntimeDataAccess {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(RuntimeDataAccess::Deny),
+            1 => Some(RuntimeDataAccess::Allow),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "deny" => Some(RuntimeDataAccess::Deny),
+            "allow" => Some(RuntimeDataAccess::Allow),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizePolicy {
+    // Ratio between the learned and the new size above which it is anomalous.
+    pub factor: u32,
+    // Size in bytes a variable may not grow across.
+    pub limit: u32,
+}
+
+impl SizePolicy {
+    /**
+     * @brief Returns whether a variable learned as `previous` bytes reading
+     *        back as `size` bytes is anomalous.
+     */
+    pub fn is_anomalous(&self, previous: u32, size: u32) -> bool {
+        let factor = u64::from(self.factor);
+        u64::from(size) > u64::from(previous) * factor
+            || u64::from(size) * factor < u64::from(previous)
+            || (previous <= self.limit && size > self.limit)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RatePolicy {
+    // Calls per second at or above which a window counts as excessive; 0
+    // disables the alarm.
+    pub limit: u32,
+    // Consecutive excessive windows needed to raise it.
+    pub sustain: u32,
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadRecords {
+    // A G: record for every traced read.
+    Every = 0,
+    // Only for reads that return other data than the last one.
+    Changes = 1,
+}
+
+impl ReadRecords {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ReadRecords::Every),
+            1 => Some(ReadRecords::Changes),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "every" => Some(ReadRecords::Every),
+            "changes" => Some(ReadRecords::Changes),
+            _ => None,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbsentPolicy {
+    // A variable absent at lock time must stay absent.
+    Lock = 0,
+    // A variable absent at lock time is not locked.
+    Ignore = 1,
+}
+
+impl AbsentPolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(AbsentPolicy::Lock),
+            1 => Some(AbsentPolicy::Ignore),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "lock" => Some(AbsentPolicy::Lock),
+            "ignore" => Some(AbsentPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+// Whether others may write UvmLevel and UvmConfig, by UVM_LEVEL_WRITES.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    // Writes are blocked as writes to protected variables are.
+    Protect = 0,
+    // Writes go through, and take effect at the next load.
+    Allow = 1,
+}
+
+impl WritePolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(WritePolicy::Protect),
+            1 => Some(WritePolicy::Allow),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "protect" => Some(WritePolicy::Protect),
+            "allow" => Some(WritePolicy::Allow),
+            _ => None,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardPolicy {
+    // Answer accepted writes with SUCCESS without reaching firmware.
+    Drop = 0,
+    // Also forward them.
+    Forward = 1,
+}
+
+impl ForwardPolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ForwardPolicy::Drop),
+            1 => Some(ForwardPolicy::Forward),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "drop" => Some(ForwardPolicy::Drop),
+            "forward" => Some(ForwardPolicy::Forward),
+            _ => None,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetPolicy {
+    // reset_statistics fails with ACCESS_DENIED.
+    Deny = 0,
+    Allow = 1,
+}
+
+impl ResetPolicy {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(ResetPolicy::Deny),
+            1 => Some(ResetPolicy::Allow),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "deny" => Some(ResetPolicy::Deny),
+            "allow" => Some(ResetPolicy::Allow),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * @brief Parses a PCR index for UVM_TPM_PCR.
+ */
+pub fn parse_pcr(text: &str) -> Option<u32> {
+    text.parse().ok().filter(|pcr| *pcr <= MAX_PCR)
+}
+
+/**
+ * @brief Parses a size factor from the build environment.
+ */
+pub fn parse_size_factor(text: &str) -> Option<u32> {
+    text.parse().ok().filter(|factor| *factor >= 2)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RuntimeConfig {
+    #[cfg(feature = "log-ring")]
+    pub ring_overflow_policy: OverflowPolicy,
+    pub hook_integrity_policy: IntegrityPolicy,
+    pub max_reinstalls: u32,
+    pub runtime_data_access: RuntimeDataAccess,
+    #[cfg(feature = "tpm-measure")]
+    pub tpm_pcr: u32,
+    pub size_policy: SizePolicy,
+    pub rate_policy: RatePolicy,
+    #[cfg(feature = "enforce")]
+    pub lock_absent_policy: AbsentPolicy,
+    #[cfg(feature = "enforce")]
+    pub level_write_policy: WritePolicy,
+    pub alert_limits: LimitTable,
+    pub control_forward_policy: ForwardPolicy,
+    pub stats_reset_policy: ResetPolicy,
+    pub latency_budget: u32,
+    pub read_records: ReadRecords,
+}
+
+impl RuntimeConfig {
+    /**
+     * @brief Builds the configuration from the build-time defaults.
+     */
+    pub fn from_build_env() -> Self {
+        RuntimeConfig {
+            #[cfg(feature = "log-ring")]
+            ring_overflow_policy: option_env!("UVM_RING_OVERFLOW")
+                .and_then(OverflowPolicy::from_str)
+                .unwrap_or(OverflowPolicy::OverwriteOldest),
+            hook_integrity_policy: option_env!("UVM_HOOK_INTEGRITY")
+                .and_then(IntegrityPolicy::from_str)
+                .unwrap_or(IntegrityPolicy::Record),
+            max_reinstalls: option_env!("UVM_HOOK_REINSTALLS")
+                .and_then(|text| text.parse().ok())
+                .unwrap_or(DEFAULT_MAX_REINSTALLS),
+            runtime_data_access: option_env!("UVM_RUNTIME_DATA")
+                .and_then(RuntimeDataAccess::from_str)
+                .unwrap_or(RuntimeDataAccess::Deny),
+            #[cfg(feature = "tpm-measure")]
+            tpm_pcr: option_env!("UVM_TPM_PCR")
+                .and_then(parse_pcr)
+                .unwrap_or(DEFAULT_PCR),
+            size_policy: SizePolicy {
+                factor: option_env!("UVM_SIZE_FACTOR")
+                    .and_then(parse_size_factor)
+                    .unwrap_or(DEFAULT_SIZE_FACTOR),
+                limit: option_env!("UVM_SIZE_LIMIT")
+                    .and_then(|text| text.parse().ok())
+                    .unwrap_or(DEFAULT_SIZE_LIMIT),
+            },
+            rate_policy: RatePolicy {
+                limit: option_env!("UVM_RATE_LIMIT")
+                    .and_then(|text| text.parse().ok())
+                    .unwrap_or(DEFAULT_RATE_LIMIT),
+                sustain: option_env!("UVM_RATE_SUSTAIN")
+                    .and_then(|text| text.parse().ok())
+                    .unwrap_or(DEFAULT_RATE_SUSTAIN),
+            },
+            #[cfg(feature = "enforce")]
+            lock_absent_policy: option_env!("UVM_LOCK_ABSENT")
+                .and_then(AbsentPolicy::from_str)
+                .unwrap_or(AbsentPolicy::Lock),
+            #[cfg(feature = "enforce")]
+            level_write_policy: option_env!("UVM_LEVEL_WRITES")
+                .and_then(WritePolicy::from_str)
+                .unwrap_or(WritePolicy::Protect),
+            alert_limits: option_env!("UVM_ALERT_LIMITS")
+                .map(alert::parse_limits)
+                .unwrap_or(alert::DEFAULT_LIMITS),
+            control_forward_policy: option_env!("UVM_CTL_FORWARD")
+                .and_then(ForwardPolicy::from_str)
+                .unwrap_or(ForwardPolicy::Drop),
+            stats_reset_policy: option_env!("UVM_STATS_RESET")
+                .and_then(ResetPolicy::from_str)
+                .unwrap_or(ResetPolicy::Deny),
+            latency_budget: option_env!("UVM_LATENCY_BUDGET")
+                .and_then(|text| text.parse().ok())
+                .unwrap_or(DEFAULT_LATENCY_BUDGET),
+            read_records: option_env!("UVM_READ_RECORDS")
+                .and_then(ReadRecords::from_str)
+                .unwrap_or(ReadRecords::Every),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Problem {
+    // A word that is not key=value.
+    Malformed,
+    UnknownKey,
+    BadValue,
+}
+
+/**
+ * @brief The settings of the load options kept outside the configuration.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options<'a> {
+    pub level: Option<Level>,
+    pub trace_filter: Option<&'a str>,
+    // Words taken, those setting the configuration included.
+    pub taken: u32,
+}
+
+/**
+ * @brief Sets the setting `key` to `value`, in `config` or `options`.
+ */
+fn set<'a>(
+    key: &str,
+    value: &'a str,
+    config: &mut RuntimeConfig,
+    options: &mut Options<'a>,
+) -> Result<(), Problem> {
+    fn parsed<T>(value: Option<T>) -> Result<T, Problem> {
+        value.ok_or(Problem::BadValue)
+    }
+    match key {
+        "level" => options.level = Some(parsed(Level::from_str(value))?),
+        "trace" => options.trace_filter = Some(value),
+        #[cfg(feature = "log-ring")]
+        "ring-overflow" => config.ring_overflow_policy = parsed(OverflowPolicy::from_str(value))?,
+        "hook-integrity" => {
+            config.hook_integrity_policy = parsed(IntegrityPolicy::from_str(value))?
+        }
+        "hook-reinstalls" => config.max_reinstalls = parsed(value.parse().ok())?,
+        "runtime-data" => config.runtime_data_access = parsed(RuntimeDataAccess::from_str(value))?,
+        #[cfg(feature = "tpm-measure")]
+        "tpm-pcr" => config.tpm_pcr = parsed(parse_pcr(value))?,
+        "size-factor" => config.size_policy.factor = parsed(parse_size_factor(value))?,
+        "size-limit" => config.size_policy.limit = parsed(value.parse().ok())?,
+        "rate-limit" => config.rate_policy.limit = parsed(value.parse().ok())?,
+        "rate-sustain" => config.rate_policy.sustain = parsed(value.parse().ok())?,
+        #[cfg(feature = "enforce")]
+        "lock-absent" => config.lock_absent_policy = parsed(AbsentPolicy::from_str(value))?,
+        #[cfg(feature = "enforce")]
+        "level-writes" => config.level_write_policy = parsed(WritePolicy::from_str(value))?,
+        "alert-limits" => config.alert_limits = alert::parse_limits(value),
+        "ctl-forward" => config.control_forward_policy = parsed(ForwardPolicy::from_str(value))?,
+        "stats-reset" => config.stats_reset_policy = parsed(ResetPolicy::from_str(value))?,
+        "latency-budget" => config.latency_budget = parsed(value.parse().ok())?,
+        "read-records" => config.read_records = parsed(ReadRecords::from_str(value))?,
+        _ => return Err(Problem::UnknownKey),
+    }
+    Ok(())
+}
+
+/**
+ * @brief Parses the load options `text` into `config` and the returned
+ *        settings, passing each word skipped and why to `skipped`.
+ */
+pub fn parse_options<'a>(
+    text: &'a str,
+    config: &mut RuntimeConfig,
+    mut skipped: impl FnMut(&'a str, Problem),
+) -> Options<'a> {
+    let mut options = Options::default();
+    for (index, word) in text.split_ascii_whitespace().enumerate() {
+        let result = match word.split_once(|c| c == '=') {
+            Some((key, value)) if !key.is_empty() => set(key, value, config, &mut options),
+            None if index == 0 => continue,
+            _ => Err(Problem::Malformed),
+        };
+        match result {
+            Ok(()) => options.taken += 1,
+            Err(problem) => skipped(word, problem),
+        }
+    }
+    options
+}
+
+/**
+ * @brief Decodes the UCS-2 load options `bytes` into `buffer`, up to the
+ *        first NUL.
+ */
+pub fn decode_options<'a>(bytes: &[u8], buffer: &'a mut [u8; MAX_OPTIONS_SIZE]) -> &'a str {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|c16| *c16 != 0);
+    let mut length = 0;
+    for (slot, c) in buffer.iter_mut().zip(core::char::decode_utf16(units)) {
+        *slot = match c {
+            Ok(c) if c == ' ' || c.is_ascii_graphic() => c as u8,
+            Ok(c) if c.is_whitespace() => b' ',
+            _ => b'?',
+        };
+        length += 1;
+    }
+    // Only ASCII was stored.
+    core::str::from_utf8(buffer.get(..length).unwrap_or(&[])).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(text: &str) -> (Options<'_>, RuntimeConfig, Vec<(&str, Problem)>) {
+        let mut config = RuntimeConfig::from_build_env();
+        let mut skipped = Vec::new();
+        let options = parse_options(text, &mut config, |word, problem| {
+            skipped.push((word, problem))
+        });
+        (options, config, skipped)
+    }
+
+    #[test]
+    fn key_value_words_are_parsed_and_the_rest_skipped() {
+        let (options, config, problems) = parse_all(
+            "UefiVarMonitor.efi  level=info rate-limit=20 \
+             trace=8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot* runtime-data=allow latency-budget=0 \
+             read-records=changes",
+        );
+        assert_eq!(options.level, Some(Level::Info));
+        assert_eq!(
+            options.trace_filter,
+            Some("8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*")
+        );
+        assert_eq!(config.rate_policy.limit, 20);
+        assert_eq!(config.latency_budget, 0);
+        assert_eq!(config.read_records, ReadRecords::Changes);
+        assert_eq!(config.runtime_data_access, RuntimeDataAccess::Allow);
+        assert!(problems.is_empty());
+        assert_eq!(options.taken, 6);
+
+        // Later words win; bad ones leave the setting as it was.
+        let (options, config, problems) = parse_all(
+            "level=warning level=loud bogus=1 stray =x size-factor=1 rate-limit=5 rate-limit=x",
+        );
+        assert_eq!(options.level, Some(Level::Warning));
+        assert_eq!(options.taken, 2);
+        assert_eq!(config.rate_policy.limit, 5);
+        assert_eq!(config.size_policy.factor, DEFAULT_SIZE_FACTOR);
+        assert_eq!(
+            problems,
+            vec![
+                ("level=loud", Problem::BadValue),
+                ("bogus=1", Problem::UnknownKey),
+                ("stray", Problem::Malformed),
+                ("=x", Problem::Malformed),
+                ("size-factor=1", Problem::BadValue),
+                ("rate-limit=x", Problem::BadValue),
+            ]
+        );
+
+        // Nothing at all, or only the image path.
+        assert_eq!(parse_all("").0, Options::default());
+        assert!(parse_all("  \\EFI\\UefiVarMonitor.efi ").2.is_empty());
+    }
+
+    #[test]
+    fn numbers_are_parsed_within_their_bounds() {
+        assert_eq!(parse_pcr("7"), Some(7));
+        assert_eq!(parse_pcr("23"), Some(23));
+        assert_eq!(parse_pcr("24"), None);
+        assert_eq!(parse_pcr("seven"), None);
+        assert_eq!(parse_size_factor("2"), Some(2));
+        assert_eq!(parse_size_factor("1"), None);
+    }
+
+    #[test]
+    fn options_are_decoded_up_to_the_terminator() {
+        let mut buffer = [0u8; MAX_OPTIONS_SIZE];
+        let bytes: Vec<u8> = "a\tb=\u{263a}\0level=info"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .chain([0x41])
+            .collect();
+        assert_eq!(decode_options(&bytes, &mut buffer), "a b=?");
+        // Unpaired surrogates, and more than fits.
+        assert_eq!(decode_options(&[0x00, 0xd8, 0x41, 0x00], &mut buffer), "?A");
+        let long = [b'x', 0].repeat(2 * MAX_OPTIONS_SIZE);
+        assert_eq!(decode_options(&long, &mut buffer).len(), MAX_OPTIONS_SIZE);
+    }
+}
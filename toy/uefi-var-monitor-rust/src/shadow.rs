@@ -0,0 +1,499 @@
+// uefi-var-monitor-rust/src/shadow.rs
+//
+// Private shadow copies of critical variables. PK, KEK and SecureBoot are
+// shadowed by default, plus the UVM_SHADOW list given at build time, in the
+// format of pattern.rs but exact names only.
+//
+// Each shadow is captured at load through the saved GetVariable, under the
+// reentrancy guard, and follows the successful writes the SetVariable hook
+// sees. Every later read is compared against it. A read that differs means
+// the variable changed without passing through the hooks: either a write
+// path we do not see, such as SMM modifying the store directly, or a store
+// corrupted underneath. The two get different critical alerts:
+//
+//   Unobserved write to <guid> PK in BootServices: shadow 1262 bytes, now 1300 bytes
+//   Store corruption of <guid> PK in BootServices: shadow 1262 bytes, now 1262 bytes
+//
+// A value that still has the shape of the variable, such as well-formed
+// signature lists for PK or KEK, is taken as written; one that does not, as
+// corrupted. After the alert the shadow follows the new value.
+//
+// Values up to MAX_SHADOW_SIZE bytes are kept; a larger one only has its
+// size compared. So does any value whose data may not be inspected, at OS
+// runtime by default (see safety.rs). Redacted variables are never shadowed,
+// even when redacted after load. An appended write leaves the value unknown
+// until the next read.
+
+use crate::alerts::Rule;
+use crate::classify::GLOBAL_VARIABLE_GUID;
+use crate::pattern::{Pattern, MAX_PATTERN_NAME};
+use crate::rules::{self, Severity};
+use crate::safety::{self, Inspection};
+use crate::tag::{self, Tag};
+use crate::{mode, redact, signature, signature_list, GuidFmt};
+use atomic_refcell::AtomicRefCell;
+use core::fmt;
+use core::sync::atomic::Ordering;
+use r_efi::efi;
+
+const TAG: Tag = tag::INTEGRITY;
+
+pub const MAX_SHADOWS: usize = 8;
+pub const MAX_SHADOW_SIZE: usize = 4096;
+
+const DEFAULT_SHADOWED: [&str; 3] = ["PK", "KEK", "SecureBoot"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    // Not captured, or changed by a write whose result is not known.
+    Unknown,
+    Absent,
+    // `kept` if the data is in the shadow, not only its size.
+    Present { size: usize, kept: bool },
+}
+
+// A variable as a read or write showed it. The data is None if it may not
+// be inspected, or is larger than a shadow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Observed<'a> {
+    Absent,
+    Present { size: usize, data: Option<&'a [u8]> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    UnobservedWrite,
+    Corruption,
+}
+
+#[derive(Clone, Copy)]
+struct Shadow {
+    pattern: Pattern,
+    state: State,
+    data: [u8; MAX_SHADOW_SIZE],
+}
+
+impl Shadow {
+    const fn empty(pattern: Pattern) -> Self {
+        Shadow {
+            pattern,
+            state: State::Unknown,
+            data: [0; MAX_SHADOW_SIZE],
+        }
+    }
+
+    fn adopt(&mut self, observed: &Observed) {
+        self.state = match *observed {
+            Observed::Absent => State::Absent,
+            Observed::Present {
+                size,
+                data: Some(data),
+            } if size == data.len() && size <= MAX_SHADOW_SIZE => {
+                self.data[..size].copy_from_slice(data);
+                State::Present { size, kept: true }
+            }
+            Observed::Present { size, .. } => State::Present { size, kept: false },
+        };
+    }
+
+    /**
+     * @brief Returns whether `observed` differs from the shadow, as far as
+     *        both are known.
+     */
+    fn differs(&self, observed: &Observed) -> bool {
+        match (self.state, observed) {
+            (State::Unknown, _) => false,
+            (State::Absent, Observed::Absent) => false,
+            (State::Absent, Observed::Present { .. }) => true,
+            (State::Present { .. }, Observed::Absent) => true,
+            (State::Present { size, kept }, Observed::Present { size: now, data }) => {
+                size != *now
+                    || match (kept, data) {
+                        (true, Some(data)) => self.data.get(..size) != Some(*data),
+                        _ => false,
+                    }
+            }
+        }
+    }
+
+    /**
+     * @brief Compares a read against the shadow, which then follows it.
+     *        Returns how it diverged, if it did.
+     */
+    fn observe_read(&mut self, observed: &Observed) -> Option<Divergence> {
+        let divergence = if self.differs(observed) {
+            let name = self.pattern.name();
+            let guid = self.pattern.guid();
+            Some(match observed {
+                Observed::Present {
+                    data: Some(data), ..
+                } if !well_formed(name, &guid, data) => Divergence::Corruption,
+                _ => Divergence::UnobservedWrite,
+            })
+        } else {
+            None
+        };
+        // A read showing data the shadow only has the size of fills it in.
+        let fills = matches!(
+            (self.state, observed),
+            (
+                State::Present { kept: false, .. },
+                Observed::Present { data: Some(_), .. }
+            )
+        );
+        if divergence.is_some() || self.state == State::Unknown || fills {
+            self.adopt(observed);
+        }
+        divergence
+    }
+}
+
+/**
+ * @brief Returns whether `data` has the shape of the variable's value, as
+ *        far as that is known.
+ */
+fn well_formed(name: &str, guid: &efi::Guid, data: &[u8]) -> bool {
+    if mode::is_mode_variable(name, guid) {
+        return matches!(data, [0] | [1]);
+    }
+    let signature_lists = (*guid == GLOBAL_VARIABLE_GUID && (name == "PK" || name == "KEK"))
+        || signature::is_signature_database(name, guid);
+    if signature_lists {
+        // An empty PK is Setup Mode.
+        return data.is_empty() || signature_list::parse(0, data).is_ok();
+    }
+    true
+}
+
+// A shadow state or observation for logging.
+struct Shown<'a>(&'a Observed<'a>);
+
+impl fmt::Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Observed::Absent => f.write_str("absent"),
+            Observed::Present { size, .. } => write!(f, "{} bytes", size),
+        }
+    }
+}
+
+struct StateFmt(State);
+
+impl fmt::Display for StateFmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            State::Unknown => f.write_str("unknown"),
+            State::Absent => f.write_str("absent"),
+            State::Present { size, .. } => write!(f, "{} bytes", size),
+        }
+    }
+}
+
+static SHADOWS: AtomicRefCell<[Option<Shadow>; MAX_SHADOWS]> =
+    AtomicRefCell::new([None; MAX_SHADOWS]);
+
+fn add(shadows: &mut [Option<Shadow>; MAX_SHADOWS], pattern: Pattern) -> bool {
+    if shadows
+        .iter()
+        .flatten()
+        .any(|shadow| shadow.pattern.matches(pattern.name(), &pattern.guid()))
+    {
+        return true;
+    }
+    match shadows.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(Shadow::empty(pattern));
+            true
+        }
+        None => false,
+    }
+}
+
+/**
+ * @brief Captures the value of a shadowed variable through the saved
+ *        GetVariable.
+ */
+fn capture(shadow: &mut Shadow) {
+    let name = shadow.pattern.name();
+    let guid = shadow.pattern.guid();
+    if redact::is_redacted(name, &guid) {
+        shadow.state = State::Unknown;
+        return;
+    }
+    let mut name_buffer = [0u16; MAX_PATTERN_NAME + 1];
+    for (c16, c) in name_buffer.iter_mut().zip(name.bytes()) {
+        *c16 = c as u16;
+    }
+    let mut guid_buffer = guid;
+    let mut attributes = 0u32;
+    let mut data_size = MAX_SHADOW_SIZE;
+    let efi_status = crate::FIRMWARE_GET_VARIABLE.call(
+        name_buffer.as_mut_ptr(),
+        &mut guid_buffer,
+        &mut attributes,
+        &mut data_size,
+        shadow.data.as_mut_ptr() as *mut core::ffi::c_void,
+    );
+    shadow.state = match efi_status {
+        efi::Status::SUCCESS => State::Present {
+            size: data_size,
+            kept: true,
+        },
+        efi::Status::BUFFER_TOO_SMALL => State::Present {
+            size: data_size,
+            kept: false,
+        },
+        efi::Status::NOT_FOUND => State::Absent,
+        _ => State::Unknown,
+    };
+}
+
+/**
+ * @brief Sets up the shadows and captures their values. Must be called after
+ *        the GetVariable hook is installed and the redaction list is loaded.
+ *        Nothing is captured if a GetVariable call is already in progress.
+ */
+pub fn load() {
+    let mut shadows = match SHADOWS.try_borrow_mut() {
+        Ok(shadows) => shadows,
+        Err(_) => return,
+    };
+    for name in DEFAULT_SHADOWED.iter() {
+        if let Some(pattern) = Pattern::exact(name, &GLOBAL_VARIABLE_GUID) {
+            add(&mut shadows, pattern);
+        }
+    }
+    if let Some(list) = option_env!("UVM_SHADOW") {
+        let (mut added, mut rejected) = (0, 0);
+        for text in list
+            .split(|c| c == ';')
+            .filter(|text| !text.trim().is_empty())
+        {
+            match Pattern::parse(text).filter(|pattern| !pattern.is_prefix()) {
+                Some(pattern) if add(&mut shadows, pattern) => added += 1,
+                _ => rejected += 1,
+            }
+        }
+        info!(
+            TAG,
+            "Shadowed from build: {} entries, {} rejected", added, rejected
+        );
+    }
+
+    if crate::IN_GET_VARIABLE.swap(true, Ordering::Acquire) {
+        return;
+    }
+    for shadow in shadows.iter_mut().flatten() {
+        capture(shadow);
+        info!(
+            TAG,
+            "Shadow of {} {}: {}",
+            GuidFmt(&shadow.pattern.guid()),
+            shadow.pattern.name(),
+            StateFmt(shadow.state)
+        );
+    }
+    crate::IN_GET_VARIABLE.store(false, Ordering::Release);
+}
+
+/**
+ * @brief Returns what the data buffer of an access shows of a variable
+ *        `size` bytes long.
+ */
+fn observed<'a>(
+    name: &str,
+    guid: &efi::Guid,
+    data: *const core::ffi::c_void,
+    size: usize,
+) -> Option<Observed<'a>> {
+    let data = match safety::inspect(name, guid, data, size, MAX_SHADOW_SIZE) {
+        Inspection::Data(data) if data.len() == size => Some(data),
+        Inspection::Data(_) | Inspection::NotInspected => None,
+        Inspection::Redacted => return None,
+    };
+    Some(Observed::Present { size, data })
+}
+
+/**
+ * @brief Runs `f` on the shadow of the variable, if it has one, the shadows
+ *        are not in use and it is not redacted.
+ */
+fn with_shadow(name: &str, guid: &efi::Guid, f: impl FnOnce(&mut Shadow)) {
+    let mut shadows = match SHADOWS.try_borrow_mut() {
+        Ok(shadows) => shadows,
+        Err(_) => return,
+    };
+    let shadow = match shadows
+        .iter_mut()
+        .flatten()
+        .find(|shadow| shadow.pattern.matches(name, guid))
+    {
+        Some(shadow) => shadow,
+        None => return,
+    };
+    if redact::is_redacted(name, guid) {
+        shadow.state = State::Unknown;
+        shadow.data = [0; MAX_SHADOW_SIZE];
+        return;
+    }
+    f(shadow);
+}
+
+/**
+ * @brief Returns whether a shadow is under `guid`, which is all check_read()
+ *        needs to know to rule out a read.
+ */
+pub fn watches(guid: &efi::Guid) -> bool {
+    match SHADOWS.try_borrow_mut() {
+        Ok(shadows) => shadows
+            .iter()
+            .flatten()
+            .any(|shadow| shadow.pattern.guid() == *guid),
+        Err(_) => false,
+    }
+}
+
+/**
+ * @brief Compares a GetVariable result against the shadow of the variable,
+ *        and alerts if it diverged. `size` is the size firmware returned.
+ */
+pub fn check_read(
+    name: &str,
+    guid: &efi::Guid,
+    efi_status: efi::Status,
+    data: *const core::ffi::c_void,
+    size: Option<usize>,
+) {
+    with_shadow(name, guid, |shadow| {
+        let observed = match (efi_status, size) {
+            (efi::Status::SUCCESS, Some(size)) => match observed(name, guid, data, size) {
+                Some(observed) => observed,
+                None => return,
+            },
+            (efi::Status::NOT_FOUND, _) => Observed::Absent,
+            _ => return,
+        };
+        let previous = shadow.state;
+        let prefix = match shadow.observe_read(&observed) {
+            Some(Divergence::UnobservedWrite) => "Unobserved write to",
+            Some(Divergence::Corruption) => "Store corruption of",
+            None => return,
+        };
+        rules::emit(
+            Rule::ShadowDivergence,
+            Severity::Critical,
+            format_args!(
+                "{} {} {} in {:?}: shadow {}, now {}",
+                prefix,
+                GuidFmt(guid),
+                name,
+                crate::phase(),
+                StateFmt(previous),
+                Shown(&observed)
+            ),
+        );
+    });
+}
+
+/**
+ * @brief Makes the shadow of a variable follow a write firmware accepted.
+ */
+pub fn record_write(
+    name: &str,
+    guid: &efi::Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *const core::ffi::c_void,
+) {
+    with_shadow(name, guid, |shadow| {
+        if rules::is_deletion(attributes, data_size) {
+            shadow.adopt(&Observed::Absent);
+            return;
+        }
+        if attributes & (efi::VARIABLE_APPEND_WRITE | efi::VARIABLE_AUTHENTICATED_WRITE_ACCESS) != 0
+        {
+            shadow.state = State::Unknown;
+            return;
+        }
+        // What is stored is the payload, without the authentication
+        // descriptor.
+        let inspection = safety::inspect(name, guid, data, data_size, data_size);
+        let payload = match inspection {
+            Inspection::Data(data) => signature_list::lists_offset(attributes, data)
+                .ok()
+                .and_then(|offset| data.get(offset..)),
+            _ => None,
+        };
+        match payload {
+            Some(payload) => shadow.adopt(&Observed::Present {
+                size: payload.len(),
+                data: Some(payload).filter(|payload| payload.len() <= MAX_SHADOW_SIZE),
+            }),
+            None => shadow.state = State::Unknown,
+        }
+    });
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "profile-minimal", allow(dead_code))]
+pub fn reset() {
+    *SHADOWS.borrow_mut() = [None; MAX_SHADOWS];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shadow(name: &str) -> Shadow {
+        Shadow::empty(Pattern::exact(name, &GLOBAL_VARIABLE_GUID).unwrap())
+    }
+
+    fn present(data: &[u8]) -> Observed<'_> {
+        Observed::Present {
+            size: data.len(),
+            data: Some(data),
+        }
+    }
+
+    #[test]
+    fn reads_diverging_from_the_shadow() {
+        let mut secure_boot = shadow("SecureBoot");
+        // Nothing to compare against until captured.
+        assert_eq!(secure_boot.observe_read(&present(&[1])), None);
+        assert_eq!(secure_boot.observe_read(&present(&[1])), None);
+        assert_eq!(
+            secure_boot.observe_read(&present(&[0])),
+            Some(Divergence::UnobservedWrite)
+        );
+        assert_eq!(
+            secure_boot.observe_read(&present(&[7])),
+            Some(Divergence::Corruption)
+        );
+        assert_eq!(
+            secure_boot.observe_read(&Observed::Absent),
+            Some(Divergence::UnobservedWrite)
+        );
+        assert_eq!(secure_boot.observe_read(&Observed::Absent), None);
+
+        // Without the data, only the size is compared, and what is kept stays.
+        secure_boot.adopt(&present(&[1]));
+        let blind = |size| Observed::Present { size, data: None };
+        assert_eq!(secure_boot.observe_read(&blind(1)), None);
+        assert_eq!(
+            secure_boot.observe_read(&present(&[0])),
+            Some(Divergence::UnobservedWrite)
+        );
+        assert_eq!(
+            secure_boot.observe_read(&blind(2)),
+            Some(Divergence::UnobservedWrite)
+        );
+
+        // A PK that is no longer signature lists is corrupted.
+        let mut pk = shadow("PK");
+        pk.adopt(&present(&[]));
+        assert_eq!(
+            pk.observe_read(&present(&[0xff; 40])),
+            Some(Divergence::Corruption)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+// uefi-var-monitor-rust/src/signature.rs
+//
+// Appends to the image security databases. A SetVariable call with
+// EFI_VARIABLE_APPEND_WRITE on db, dbx, dbt or dbr is how revocations and new
+// trust anchors land, and also how an attacker whitelists their own boot
+// loader, so each one is alerted on with a summary of what it appends: the
+// type of the first EFI_SIGNATURE_LIST, its entry count, the owner of its
+// first entry, and how many lists there are. A payload that does not parse is
+// alerted on separately, as some firmware accepts malformed lists.
+//
+// The payload of a time-based authenticated write starts with an
+// EFI_VARIABLE_AUTHENTICATION_2 descriptor, which is skipped. The lists are
+// walked by signature_list.rs, in the library, which the fuzz targets (see
+// fuzz/) feed directly.
+
+use crate::alerts::Rule;
+use crate::classify::IMAGE_SECURITY_DATABASE_GUID;
+use crate::rules::{self, Severity};
+use crate::safety::{self, Inspection};
+use crate::set_variable::ReturnAddress;
+use crate::signature_list::parse;
+use crate::GuidFmt;
+use core::fmt;
+use r_efi::efi;
+
+/**
+ * @brief Returns whether `name` under `guid` is an image security database.
+ */
+pub fn is_signature_database(name: &str, guid: &efi::Guid) -> bool {
+    *guid == IMAGE_SECURITY_DATABASE_GUID && matches!(name, "db" | "dbx" | "dbt" | "dbr")
+}
+
+// The UEFI signature types, EFI_CERT_*_GUID.
+const SIGNATURE_TYPES: [(efi::Guid, &str); 7] = [
+    (
+        efi::Guid::from_fields(
+            0xc1c41626,
+            0x504c,
+            0x4092,
+            0xac,
+            0xa9,
+            &[0x41, 0xf9, 0x36, 0x93, 0x43, 0x28],
+        ),
+        "SHA256",
+    ),
+    (
+        efi::Guid::from_fields(
+            0x826ca512,
+            0xcf10,
+            0x4ac9,
+            0xb1,
+            0x87,
+            &[0xbe, 0x01, 0x49, 0x66, 0x31, 0xbd],
+        ),
+        "SHA1",
+    ),
+    (
+        efi::Guid::from_fields(
+            0x3c5766e8,
+            0x269c,
+            0x4e34,
+            0xaa,
+            0x14,
+            &[0xed, 0x77, 0x6e, 0x85, 0xb3, 0xb6],
+        ),
+        "RSA2048",
+    ),
+    (
+        efi::Guid::from_fields(
+            0xa5c059a1,
+            0x94e4,
+            0x4aa7,
+            0x87,
+            0xb5,
+            &[0xab, 0x15, 0x5c, 0x2b, 0xf0, 0x72],
+        ),
+        "X509",
+    ),
+    (
+        efi::Guid::from_fields(
+            0x3bd2a492,
+            0x96c0,
+            0x4079,
+            0xb4,
+            0x20,
+            &[0xfc, 0xf9, 0x8e, 0xf1, 0x03, 0xed],
+        ),
+        "X509_SHA256",
+    ),
+    (
+        efi::Guid::from_fields(
+            0x7076876e,
+            0x80c2,
+            0x4ee6,
+            0xaa,
+            0xd2,
+            &[0x28, 0xb3, 0x49, 0xa6, 0x86, 0x5b],
+        ),
+        "X509_SHA384",
+    ),
+    (
+        efi::Guid::from_fields(
+            0x446dbf63,
+            0x2502,
+            0x4cda,
+            0xbc,
+            0xfa,
+            &[0x22, 0x65, 0xd2, 0xb4, 0xa5, 0x14],
+        ),
+        "X509_SHA512",
+    ),
+];
+
+// A signature type GUID, named where it is one of the UEFI ones.
+struct SignatureType(efi::Guid);
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match SIGNATURE_TYPES.iter().find(|(guid, _)| *guid == self.0) {
+            Some((_, name)) => f.write_str(name),
+            None => write!(f, "{}", GuidFmt(&self.0)),
+        }
+    }
+}
+
+// The owner of the first entry, "none" for a list without entries.
+struct Owner(Option<efi::Guid>);
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref owner) => write!(f, "{}", GuidFmt(owner)),
+            None => f.write_str("none"),
+        }
+    }
+}
+
+/**
+ * @brief Alerts on a write appending to an image security database, with
+ *        what it appends. Other writes are ignored.
+ */
+pub fn check(
+    name: &str,
+    guid: &efi::Guid,
+    attributes: u32,
+    data_size: usize,
+    data: *const core::ffi::c_void,
+    efi_status: efi::Status,
+    caller: Option<usize>,
+) {
+    if attributes & efi::VARIABLE_APPEND_WRITE == 0 || !is_signature_database(name, guid) {
+        return;
+    }
+    let payload = match safety::inspect(name, guid, data, data_size, data_size) {
+        Inspection::Data(payload) => payload,
+        Inspection::NotInspected => {
+            return rules::emit(
+                Rule::SignatureAppend,
+                Severity::Critical,
+                format_args!(
+                    "APPEND_WRITE to {} from {}: {:#x}, payload not inspected",
+                    name,
+                    ReturnAddress(caller),
+                    efi_status.as_usize()
+                ),
+            )
+        }
+        Inspection::Redacted => {
+            return rules::emit(
+                Rule::SignatureAppend,
+                Severity::Critical,
+                format_args!(
+                    "APPEND_WRITE to {} from {}: {:#x}, <redacted>",
+                    name,
+                    ReturnAddress(caller),
+                    efi_status.as_usize()
+                ),
+            )
+        }
+    };
+    match parse(attributes, payload) {
+        Ok(summary) => rules::emit(
+            Rule::SignatureAppend,
+            Severity::Critical,
+            format_args!(
+                "APPEND_WRITE to {} from {}: {:#x}, type={} entries={} owner={} lists={}",
+                name,
+                ReturnAddress(caller),
+                efi_status.as_usize(),
+                SignatureType(summary.signature_type),
+                summary.entries,
+                Owner(summary.owner),
+                summary.lists
+            ),
+        ),
+        Err(malformed) => rules::emit(
+            Rule::SignatureAppend,
+            Severity::Critical,
+            format_args!(
+                "Malformed signature list appended to {} from {}: {:#x}, {} (Size={:08x})",
+                name,
+                ReturnAddress(caller),
+                efi_status.as_usize(),
+                malformed,
+                data_size
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+
+    #[test]
+    fn signature_types_are_named() {
+        assert_eq!(SignatureType(SIGNATURE_TYPES[0].0).to_string(), "SHA256");
+        let vendor = crate::config::UVM_VENDOR_GUID;
+        assert_eq!(
+            SignatureType(vendor).to_string(),
+            GuidFmt(&vendor).to_string()
+        );
+        assert_eq!(Owner(None).to_string(), "none");
+    }
+}
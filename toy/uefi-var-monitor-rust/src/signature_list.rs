@@ -0,0 +1,266 @@
+// uefi-var-monitor-rust/src/signature_list.rs
+//
+// The EFI_SIGNATURE_LIST walk behind the alerts on appends to the image
+// security databases (see signature.rs) and the shadow copies of them (see
+// shadow.rs). The payload comes from whoever calls SetVariable, so it is
+// taken as a slice and nothing in it is trusted: only list headers are read,
+// every offset is checked against the data, and at most MAX_SIGNATURE_LISTS
+// lists are walked, in a loop that keeps no buffer of its own (see
+// footprint.rs).
+//
+// The payload of a time-based authenticated write starts with an
+// EFI_VARIABLE_AUTHENTICATION_2 descriptor, which is skipped.
+
+use core::fmt;
+use r_efi::efi;
+
+pub const MAX_SIGNATURE_LISTS: usize = 64;
+
+// EFI_TIME, then WIN_CERTIFICATE's dwLength, wRevision, wCertificateType.
+const TIME_SIZE: usize = 16;
+const WIN_CERTIFICATE_HEADER_SIZE: usize = 8;
+// SignatureType, SignatureListSize, SignatureHeaderSize, SignatureSize.
+const SIGNATURE_LIST_HEADER_SIZE: usize = 28;
+// SignatureOwner, which starts every entry.
+const SIGNATURE_OWNER_SIZE: usize = 16;
+
+// What an appended payload holds, as far as the alert line goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    pub signature_type: efi::Guid,
+    pub entries: usize,
+    // Owner of the first entry; None for a list without entries.
+    pub owner: Option<efi::Guid>,
+    pub lists: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Malformed {
+    // The authentication descriptor does not fit in the data.
+    Authentication,
+    // The data ends inside the list header at this offset.
+    Truncated(usize),
+    // The list at this offset has sizes that do not add up.
+    ListSize(usize),
+    // No list at all.
+    Empty,
+    // More lists than walked; the first ones were fine.
+    TooManyLists,
+}
+
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Malformed::Authentication => f.write_str("authentication descriptor out of bounds"),
+            Malformed::Truncated(offset) => write!(f, "list header truncated at {:#x}", offset),
+            Malformed::ListSize(offset) => write!(f, "inconsistent list sizes at {:#x}", offset),
+            Malformed::Empty => f.write_str("no signature list"),
+            Malformed::TooManyLists => write!(f, "more than {} lists", MAX_SIGNATURE_LISTS),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/**
+ * @brief Reads a GUID stored in its little-endian binary form.
+ */
+pub fn read_guid(data: &[u8], offset: usize) -> Option<efi::Guid> {
+    let b = data.get(offset..offset.checked_add(16)?)?;
+    Some(efi::Guid::from_fields(
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        u16::from_le_bytes([b[4], b[5]]),
+        u16::from_le_bytes([b[6], b[7]]),
+        b[8],
+        b[9],
+        &[b[10], b[11], b[12], b[13], b[14], b[15]],
+    ))
+}
+
+/**
+ * @brief Returns the offset of the signature lists in the payload of a write
+ *        with `attributes`, past the authentication descriptor if any.
+ */
+pub fn lists_offset(attributes: u32, data: &[u8]) -> Result<usize, Malformed> {
+    if attributes & efi::VARIABLE_TIME_BASED_AUTHENTICATED_WRITE_ACCESS == 0 {
+        return Ok(0);
+    }
+    // dwLength covers the whole WIN_CERTIFICATE_UEFI_GUID.
+    match read_u32(data, TIME_SIZE) {
+        Some(length) if length as usize >= WIN_CERTIFICATE_HEADER_SIZE => {
+            match TIME_SIZE.checked_add(length as usize) {
+                Some(offset) if offset <= data.len() => Ok(offset),
+                _ => Err(Malformed::Authentication),
+            }
+        }
+        _ => Err(Malformed::Authentication),
+    }
+}
+
+/**
+ * @brief Parses the signature lists appended by a write with `attributes`.
+ */
+pub fn parse(attributes: u32, data: &[u8]) -> Result<Summary, Malformed> {
+    let mut offset = lists_offset(attributes, data)?;
+    let mut summary: Option<Summary> = None;
+    let mut lists = 0;
+    while offset < data.len() {
+        if lists == MAX_SIGNATURE_LISTS {
+            return Err(Malformed::TooManyLists);
+        }
+        let header = offset
+            .checked_add(SIGNATURE_LIST_HEADER_SIZE)
+            .and_then(|end| data.get(offset..end))
+            .ok_or(Malformed::Truncated(offset))?;
+        let signature_type = read_guid(header, 0).ok_or(Malformed::Truncated(offset))?;
+        let list_size = read_u32(header, 16).ok_or(Malformed::Truncated(offset))? as usize;
+        let header_size = read_u32(header, 20).ok_or(Malformed::Truncated(offset))? as usize;
+        let signature_size = read_u32(header, 24).ok_or(Malformed::Truncated(offset))? as usize;
+
+        let first_entry = SIGNATURE_LIST_HEADER_SIZE
+            .checked_add(header_size)
+            .ok_or(Malformed::ListSize(offset))?;
+        let signatures = list_size
+            .checked_sub(first_entry)
+            .ok_or(Malformed::ListSize(offset))?;
+        let end = offset
+            .checked_add(list_size)
+            .filter(|end| *end <= data.len())
+            .ok_or(Malformed::ListSize(offset))?;
+        if signature_size < SIGNATURE_OWNER_SIZE || signatures % signature_size != 0 {
+            return Err(Malformed::ListSize(offset));
+        }
+
+        if summary.is_none() {
+            let entries = signatures / signature_size;
+            // Within the list, which ends within the data.
+            let owner = match entries {
+                0 => None,
+                _ => read_guid(data, offset + first_entry),
+            };
+            summary = Some(Summary {
+                signature_type,
+                entries,
+                owner,
+                lists: 0,
+            });
+        }
+        lists += 1;
+        offset = end;
+    }
+    summary
+        .map(|summary| Summary { lists, ..summary })
+        .ok_or(Malformed::Empty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guids::UVM_VENDOR_GUID as OWNER;
+
+    // EFI_CERT_SHA256_GUID.
+    const CERT_SHA256_GUID: efi::Guid = efi::Guid::from_fields(
+        0xc1c41626,
+        0x504c,
+        0x4092,
+        0xac,
+        0xa9,
+        &[0x41, 0xf9, 0x36, 0x93, 0x43, 0x28],
+    );
+
+    fn list(signature_type: &efi::Guid, entries: usize) -> Vec<u8> {
+        let signature_size = SIGNATURE_OWNER_SIZE + 32;
+        let mut data = Vec::new();
+        data.extend_from_slice(signature_type.as_bytes());
+        let list_size = SIGNATURE_LIST_HEADER_SIZE + entries * signature_size;
+        data.extend_from_slice(&(list_size as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(signature_size as u32).to_le_bytes());
+        for _ in 0..entries {
+            data.extend_from_slice(OWNER.as_bytes());
+            data.extend_from_slice(&[0xab; 32]);
+        }
+        data
+    }
+
+    #[test]
+    fn appended_lists_are_summarized() {
+        let mut data = list(&CERT_SHA256_GUID, 3);
+        data.extend(list(&CERT_SHA256_GUID, 1));
+        assert_eq!(
+            parse(0x47, &data),
+            Ok(Summary {
+                signature_type: CERT_SHA256_GUID,
+                entries: 3,
+                owner: Some(OWNER),
+                lists: 2,
+            })
+        );
+
+        // Behind an authentication descriptor of 8 + 16 + 4 bytes.
+        let mut authenticated = vec![0u8; TIME_SIZE];
+        authenticated.extend_from_slice(&28u32.to_le_bytes());
+        authenticated.extend_from_slice(&[0; 24]);
+        authenticated.extend(list(&CERT_SHA256_GUID, 0));
+        let summary = parse(0x67, &authenticated).unwrap();
+        assert_eq!((summary.entries, summary.owner), (0, None));
+        assert_eq!(
+            parse(0x47, &authenticated).map(|_| ()),
+            Err(Malformed::ListSize(0))
+        );
+    }
+
+    #[test]
+    fn malformed_lists_are_caught() {
+        let data = list(&CERT_SHA256_GUID, 2);
+        assert_eq!(parse(0x47, &data[..20]), Err(Malformed::Truncated(0)));
+        assert_eq!(
+            parse(0x47, &data[..data.len() - 1]),
+            Err(Malformed::ListSize(0))
+        );
+        assert_eq!(parse(0x47, &[]), Err(Malformed::Empty));
+        assert_eq!(parse(0x67, &[0; 8]), Err(Malformed::Authentication));
+
+        // A signature size that does not divide the list.
+        let mut odd = data.clone();
+        odd[24] = 47;
+        assert_eq!(parse(0x47, &odd), Err(Malformed::ListSize(0)));
+        // A header size reaching past the list.
+        let mut long = data.clone();
+        long[20] = 0xff;
+        assert_eq!(parse(0x47, &long), Err(Malformed::ListSize(0)));
+
+        let empty = list(&CERT_SHA256_GUID, 0);
+        let many: Vec<u8> = empty
+            .iter()
+            .copied()
+            .cycle()
+            .take(empty.len() * (MAX_SIGNATURE_LISTS + 1))
+            .collect();
+        assert_eq!(parse(0x47, &many), Err(Malformed::TooManyLists));
+    }
+
+    // The fuzz target (see fuzz/) in short, on its seeds and lists built
+    // here, for the runs that do not have cargo-fuzz.
+    #[test]
+    fn mangled_lists_are_refused_or_read_without_panicking() {
+        // Past the byte that picks the attributes in the fuzz target.
+        let dbx = &include_bytes!("../fuzz/corpus/signature_list/dbx")[1..];
+        let authenticated = &include_bytes!("../fuzz/corpus/signature_list/dbx-authenticated")[1..];
+        assert_eq!(parse(0x47, dbx), parse(0x67, authenticated));
+        assert!(parse(0x47, dbx).is_ok());
+        let mut lists = list(&CERT_SHA256_GUID, 2);
+        lists.extend(list(&CERT_SHA256_GUID, 0));
+        crate::mangle::run(&[dbx, authenticated, &lists], 20_000, |data| {
+            for attributes in [0x47, 0x67] {
+                if let Ok(summary) = parse(attributes, data) {
+                    assert!(summary.lists >= 1 && summary.lists <= MAX_SIGNATURE_LISTS);
+                    assert!(summary.entries <= data.len() / SIGNATURE_OWNER_SIZE);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,293 @@
+// uefi-var-monitor-rust/src/sink.rs
+//
+// Registry of the sinks log! writes records to. Each sink declares the phase
+// it works in: sinks built on boot services (network, and any protocol-based
+// console) must stop at ExitBootServices, while the raw serial port and the
+// ring buffer keep working at OS runtime.
+//
+// At ExitBootServices, everything buffered is flushed first, while boot
+// services are still usable; then the boot-only sinks are disabled, the
+// runtime-only ones enabled, and a handoff marker is written through
+// whatever is left.
+//
+// no-log builds no sink at all (see logging.rs): the registry is a stub that
+// takes nothing and writes nowhere, so that the callers need no cfg of
+// their own.
+
+// The registry is only used through the stub below in no-log builds, and
+// holds nothing without a log-* feature.
+#![cfg_attr(
+    any(
+        all(not(test), feature = "no-log"),
+        not(any(feature = "log-serial", feature = "log-ring", feature = "log-net"))
+    ),
+    allow(dead_code)
+)]
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use r_efi::efi;
+
+pub const MAX_SINKS: usize = 8;
+
+pub const HANDOFF_MARKER: &str = "---- ExitBootServices: handing off to the OS ----";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkPhase {
+    // Only until ExitBootServices. Unused when log-net is disabled.
+    #[allow(dead_code)]
+    BootServices,
+    // Only from ExitBootServices on.
+    Runtime,
+    // Unused when neither log-serial nor log-ring is enabled.
+    #[allow(dead_code)]
+    Both,
+}
+
+#[derive(Clone, Copy)]
+pub struct Sink {
+    pub name: &'static str,
+    pub phase: SinkPhase,
+    pub write: fn(fmt::Arguments),
+    // Writes out anything buffered.
+    pub flush: Option<fn()>,
+    // Releases what the sink holds from boot services.
+    pub disable: Option<fn()>,
+}
+
+struct Entry {
+    sink: Option<Sink>,
+    enabled: AtomicBool,
+}
+
+pub struct Registry<const N: usize> {
+    entries: [Entry; N],
+    count: usize,
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new() -> Self {
+        Registry {
+            entries: [const {
+                Entry {
+                    sink: None,
+                    enabled: AtomicBool::new(false),
+                }
+            }; N],
+            count: 0,
+        }
+    }
+
+    fn sinks(&self) -> impl Iterator<Item = (&Sink, &AtomicBool)> {
+        self.entries
+            .get(..self.count)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|entry| entry.sink.as_ref().map(|sink| (sink, &entry.enabled)))
+    }
+
+    /**
+     * @brief Adds a sink. Registering a sink of the same name twice is a
+     *        no-op.
+     */
+    pub fn register(&mut self, sink: Sink) -> efi::Status {
+        if self
+            .sinks()
+            .any(|(registered, _)| registered.name == sink.name)
+        {
+            return efi::Status::SUCCESS;
+        }
+        let entry = match self.entries.get_mut(self.count) {
+            Some(entry) => entry,
+            None => return efi::Status::OUT_OF_RESOURCES,
+        };
+        *entry = Entry {
+            sink: Some(sink),
+            enabled: AtomicBool::new(sink.phase != SinkPhase::Runtime),
+        };
+        self.count += 1;
+        efi::Status::SUCCESS
+    }
+
+    /**
+     * @brief Writes a record to every sink enabled in the current phase.
+     */
+    pub fn write(&self, args: fmt::Arguments) {
+        for (sink, enabled) in self.sinks() {
+            if enabled.load(Ordering::Acquire) {
+                (sink.write)(args);
+            }
+        }
+    }
+
+    /**
+     * @brief Switches the sinks over to the runtime phase. Must be called
+     *        from the ExitBootServices notification.
+     */
+    pub fn exit_boot_services(&self) {
+        for (sink, enabled) in self.sinks() {
+            if let (true, Some(flush)) = (enabled.load(Ordering::Acquire), sink.flush) {
+                flush();
+            }
+        }
+        for (sink, enabled) in self.sinks() {
+            match sink.phase {
+                SinkPhase::BootServices => {
+                    enabled.store(false, Ordering::Release);
+                    if let Some(disable) = sink.disable {
+                        disable();
+                    }
+                }
+                SinkPhase::Runtime => enabled.store(true, Ordering::Release),
+                SinkPhase::Both => {}
+            }
+        }
+        self.write(format_args!("{}", HANDOFF_MARKER));
+    }
+}
+
+// Only written from efi_main, before any notification can fire.
+#[cfg(any(test, not(feature = "no-log")))]
+static mut SINKS: Registry<MAX_SINKS> = Registry::new();
+
+/**
+ * @brief Registers a sink for log!.
+ */
+#[cfg(any(test, not(feature = "no-log")))]
+pub fn register(sink: Sink) -> efi::Status {
+    unsafe { &mut *core::ptr::addr_of_mut!(SINKS) }.register(sink)
+}
+
+/**
+ * @brief Writes a record to every sink enabled in the current phase.
+ */
+#[cfg_attr(test, allow(dead_code))]
+#[cfg(any(test, not(feature = "no-log")))]
+pub fn write(args: fmt::Arguments) {
+    unsafe { &*core::ptr::addr_of!(SINKS) }.write(args)
+}
+
+/**
+ * @brief Flushes the sinks, disables the boot-only ones and writes the
+ *        handoff marker.
+ */
+#[cfg(any(test, not(feature = "no-log")))]
+pub fn exit_boot_services() {
+    unsafe { &*core::ptr::addr_of!(SINKS) }.exit_boot_services()
+}
+
+#[cfg(all(not(test), feature = "no-log"))]
+pub fn register(_sink: Sink) -> efi::Status {
+    efi::Status::SUCCESS
+}
+
+#[cfg(all(not(test), feature = "no-log"))]
+pub fn write(_args: fmt::Arguments) {}
+
+#[cfg(all(not(test), feature = "no-log"))]
+pub fn exit_boot_services() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::{String, ToString};
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static CALLS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn record(call: String) {
+        CALLS.lock().unwrap().push(call);
+    }
+
+    fn sink(name: &'static str, phase: SinkPhase) -> Sink {
+        // Plain fn pointers cannot capture the name, so there is one set per
+        // sink.
+        fn boot_write(args: fmt::Arguments) {
+            record(format!("boot write {}", args));
+        }
+        fn boot_flush() {
+            record("boot flush".to_string());
+        }
+        fn boot_disable() {
+            record("boot disable".to_string());
+        }
+        fn both_write(args: fmt::Arguments) {
+            record(format!("both write {}", args));
+        }
+        fn both_flush() {
+            record("both flush".to_string());
+        }
+        fn runtime_write(args: fmt::Arguments) {
+            record(format!("runtime write {}", args));
+        }
+        match phase {
+            SinkPhase::BootServices => Sink {
+                name,
+                phase,
+                write: boot_write,
+                flush: Some(boot_flush),
+                disable: Some(boot_disable),
+            },
+            SinkPhase::Both => Sink {
+                name,
+                phase,
+                write: both_write,
+                flush: Some(both_flush),
+                disable: None,
+            },
+            SinkPhase::Runtime => Sink {
+                name,
+                phase,
+                write: runtime_write,
+                flush: None,
+                disable: None,
+            },
+        }
+    }
+
+    #[test]
+    fn exit_boot_services_flushes_then_switches() {
+        let mut registry = Registry::<3>::new();
+        assert_eq!(
+            registry.register(sink("net", SinkPhase::BootServices)),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            registry.register(sink("serial", SinkPhase::Both)),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            registry.register(sink("serial", SinkPhase::Both)),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            registry.register(sink("late", SinkPhase::Runtime)),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(
+            registry.register(sink("full", SinkPhase::Both)),
+            efi::Status::OUT_OF_RESOURCES
+        );
+
+        registry.write(format_args!("before"));
+        registry.exit_boot_services();
+        registry.write(format_args!("after"));
+
+        let marker = HANDOFF_MARKER;
+        assert_eq!(
+            *CALLS.lock().unwrap(),
+            [
+                "boot write before".to_string(),
+                "both write before".to_string(),
+                "boot flush".to_string(),
+                "both flush".to_string(),
+                "boot disable".to_string(),
+                format!("both write {}", marker),
+                format!("runtime write {}", marker),
+                "both write after".to_string(),
+                "runtime write after".to_string(),
+            ]
+        );
+    }
+}
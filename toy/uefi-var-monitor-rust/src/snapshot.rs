@@ -0,0 +1,178 @@
+// uefi-var-monitor-rust/src/snapshot.rs
+//
+// Copies of the statistics kept to diff against later, for measuring what
+// one action costs within a boot: take a snapshot, do it, diff. They are
+// taken and diffed through the monitor's protocol (see protocol.rs) and the
+// control variable (see control.rs).
+//
+// The last SNAPSHOTS snapshots are kept, in the slots their IDs select, IDs
+// counting up from 1. A snapshot is stale once a later one took its slot, or
+// once the counters were reset, since a diff across a reset would count
+// nothing: resets bump a generation each snapshot records, which needs no
+// lock, as a reset must not fail.
+//
+// The slots, and the last diff the control variable asked for, are guarded
+// by a try-lock, like the ring buffer: a caller finding them busy fails
+// rather than waits.
+
+use crate::counters;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use r_efi::efi;
+use uvm_interface::protocol::Statistics;
+
+pub const SNAPSHOTS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    // Not an ID a snapshot was ever given.
+    Unknown,
+    // Overwritten by a later snapshot, or taken before a reset.
+    Stale,
+    // Taken or diffed concurrently.
+    Busy,
+}
+
+impl SnapshotError {
+    pub fn status(self) -> efi::Status {
+        match self {
+            SnapshotError::Unknown => efi::Status::INVALID_PARAMETER,
+            SnapshotError::Stale => efi::Status::NOT_FOUND,
+            SnapshotError::Busy => efi::Status::NOT_READY,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    id: u32,
+    generation: u32,
+    statistics: Statistics,
+}
+
+struct Snapshots {
+    slots: [Option<Slot>; SNAPSHOTS],
+    next_id: u32,
+    // The diff the control variable asked for last, read from UvmCtlDiff.
+    last_diff: Option<Statistics>,
+}
+
+static BUSY: AtomicBool = AtomicBool::new(false);
+static GENERATION: AtomicU32 = AtomicU32::new(0);
+static mut SNAPSHOTS_TAKEN: Snapshots = Snapshots {
+    slots: [None; SNAPSHOTS],
+    next_id: 1,
+    last_diff: None,
+};
+
+/**
+ * @brief Runs the closure with exclusive access to the snapshots, or fails
+ *        with Busy.
+ */
+fn with_snapshots<R>(f: impl FnOnce(&mut Snapshots) -> R) -> Result<R, SnapshotError> {
+    if BUSY.swap(true, Ordering::Acquire) {
+        return Err(SnapshotError::Busy);
+    }
+    let snapshots = unsafe { &mut *core::ptr::addr_of_mut!(SNAPSHOTS_TAKEN) };
+    let result = f(snapshots);
+    BUSY.store(false, Ordering::Release);
+    Ok(result)
+}
+
+/**
+ * @brief Keeps a copy of the current statistics, and returns its ID.
+ */
+pub fn take() -> Result<u32, SnapshotError> {
+    let statistics = counters::statistics();
+    with_snapshots(|snapshots| {
+        let id = snapshots.next_id;
+        snapshots.next_id = id.wrapping_add(1).max(1);
+        snapshots.slots[id as usize % SNAPSHOTS] = Some(Slot {
+            id,
+            generation: GENERATION.load(Ordering::Acquire),
+            statistics,
+        });
+        id
+    })
+}
+
+/**
+ * @brief Returns the current statistics minus the snapshot `id`.
+ */
+pub fn diff(id: u32) -> Result<Statistics, SnapshotError> {
+    let statistics = counters::statistics();
+    with_snapshots(|snapshots| {
+        if id == 0 || id >= snapshots.next_id {
+            return Err(SnapshotError::Unknown);
+        }
+        let generation = GENERATION.load(Ordering::Acquire);
+        match &snapshots.slots[id as usize % SNAPSHOTS] {
+            Some(slot) if slot.id == id && slot.generation == generation => {
+                Ok(statistics.since(&slot.statistics))
+            }
+            _ => Err(SnapshotError::Stale),
+        }
+    })?
+}
+
+/**
+ * @brief Diffs against the snapshot `id` and keeps the result for
+ *        last_diff.
+ */
+pub fn diff_and_keep(id: u32) -> Result<(), SnapshotError> {
+    let diff = diff(id)?;
+    with_snapshots(|snapshots| snapshots.last_diff = Some(diff))
+}
+
+/**
+ * @brief Returns the diff diff_and_keep kept last, None if there is none.
+ */
+pub fn last_diff() -> Result<Option<Statistics>, SnapshotError> {
+    with_snapshots(|snapshots| snapshots.last_diff)
+}
+
+/**
+ * @brief Makes every snapshot taken so far stale. Called when the counters
+ *        are reset.
+ */
+pub fn invalidate() {
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+#[cfg(test)]
+pub fn reset() {
+    invalidate();
+    let _ = with_snapshots(|snapshots| snapshots.last_diff = None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::top::Access;
+
+    #[test]
+    fn only_the_last_snapshots_are_diffed() {
+        let _lock = crate::mock::lock();
+        reset();
+        counters::reset();
+        let first = take().unwrap();
+        counters::count(Access::Read, efi::Status::NOT_FOUND, None);
+        let second = take().unwrap();
+        counters::count(Access::Read, efi::Status::NOT_FOUND, None);
+
+        let calls = |id| diff(id).map(|diff| diff.get_variable.by_phase.iter().sum::<u64>());
+        assert_eq!((calls(first), calls(second)), (Ok(2), Ok(1)));
+        assert_eq!(calls(0), Err(SnapshotError::Unknown));
+        assert_eq!(calls(second + 1), Err(SnapshotError::Unknown));
+
+        for _ in 0..SNAPSHOTS - 1 {
+            take().unwrap();
+        }
+        assert_eq!(calls(first), Err(SnapshotError::Stale));
+        assert_eq!(calls(second), Ok(1));
+
+        // Nor across a reset.
+        invalidate();
+        assert_eq!(calls(second), Err(SnapshotError::Stale));
+        counters::reset();
+    }
+}
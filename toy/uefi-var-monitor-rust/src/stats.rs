@@ -0,0 +1,301 @@
+// uefi-var-monitor-rust/src/stats.rs
+//
+// The monitor's statistics protocol, installed on the image handle next to
+// the control protocol (see protocol.rs) and uninstalled with it. It lets a
+// shell application read what the monitor has seen so far (see
+// tools/uvmctl):
+//
+//   get_stats         hook states, call and event counters, and the records
+//                     lost by each sink
+//   get_top           the most accessed variables (see top.rs)
+//   get_ring_header   the ring buffer header (see ring.rs), as of minor
+//                     version 1
+//   get_ring_record   a record still held in the ring buffer
+//   get_build         what the driver was built as (see banner.rs), which
+//                     later ring buffer headers end with
+//
+// Counters of features not built read as NOT_COUNTED, and the ring buffer
+// entries return UNSUPPORTED without log-ring. The layout is shared with the
+// applications calling it (see interface/src/protocol.rs).
+
+use crate::alerts::{self, RULE_COUNT};
+use crate::banner;
+#[cfg(feature = "log-ring")]
+use crate::ring;
+#[cfg(any(test, not(feature = "no-log")))]
+use crate::serial;
+#[cfg(feature = "tpm-measure")]
+use crate::tpm;
+#[cfg(feature = "enforce")]
+use crate::{enforce, get_next_variable_name, hide, lock};
+use crate::{integrity, set_variable, top};
+use r_efi::efi;
+use uvm_interface::build::BuildInfo;
+#[cfg(not(feature = "enforce"))]
+use uvm_interface::protocol::NOT_BUILT;
+// Unused when every feature is built.
+#[cfg(not(all(feature = "log-ring", feature = "tpm-measure", feature = "enforce")))]
+use uvm_interface::protocol::NOT_COUNTED;
+use uvm_interface::protocol::{
+    Stats, StatsProtocol, TopEntry, UVM_STATS_PROTOCOL_GUID, UVM_STATS_PROTOCOL_REVISION_BUILD,
+};
+#[cfg(feature = "log-ring")]
+use uvm_interface::ring::RING_MINOR_1_SIZE;
+use uvm_interface::ring::{RingHeader, RingRecord};
+
+/**
+ * @brief Returns the hook states and counters get_stats reports.
+ */
+pub fn collect() -> Stats {
+    let (reads, writes) = top::totals();
+    let mut suppressed = [0u32; RULE_COUNT];
+    alerts::suppressed(&mut suppressed);
+    let mut stats = Stats {
+        get_variable_hook: u32::from(crate::hook_state()),
+        set_variable_hook: u32::from(set_variable::state()),
+        #[cfg(feature = "enforce")]
+        get_next_variable_name_hook: u32::from(get_next_variable_name::state()),
+        #[cfg(not(feature = "enforce"))]
+        get_next_variable_name_hook: NOT_BUILT,
+        rehooked: integrity::is_rehooked() as u32,
+        reinstalls: integrity::reinstalls(),
+        reserved: 0,
+        get_variable_calls: reads,
+        set_variable_calls: writes,
+        deletion_attempts: set_variable::deletion_attempts(),
+        suppressed_alerts: suppressed.iter().map(|count| u64::from(*count)).sum(),
+        ..Stats::default()
+    };
+    #[cfg(feature = "enforce")]
+    {
+        stats.blocked_writes = enforce::blocked();
+        stats.rejected_writes = lock::rejected();
+        stats.hidden_accesses = hide::hidden();
+    }
+    #[cfg(not(feature = "enforce"))]
+    {
+        stats.blocked_writes = NOT_COUNTED;
+        stats.rejected_writes = NOT_COUNTED;
+        stats.hidden_accesses = NOT_COUNTED;
+    }
+    #[cfg(any(test, not(feature = "no-log")))]
+    {
+        stats.serial_failures = serial::failures();
+    }
+    #[cfg(all(not(test), feature = "no-log"))]
+    {
+        stats.serial_failures = NOT_COUNTED;
+    }
+    #[cfg(feature = "log-ring")]
+    if let Some(header) = ring::header() {
+        stats.ring_dropped = header.dropped;
+        stats.ring_overwritten = header.overwritten;
+    }
+    #[cfg(not(feature = "log-ring"))]
+    {
+        stats.ring_dropped = NOT_COUNTED;
+        stats.ring_overwritten = NOT_COUNTED;
+    }
+    #[cfg(feature = "tpm-measure")]
+    {
+        let (dropped, failures) = tpm::losses();
+        stats.tpm_dropped = dropped;
+        stats.tpm_failures = failures;
+    }
+    #[cfg(not(feature = "tpm-measure"))]
+    {
+        stats.tpm_dropped = NOT_COUNTED;
+        stats.tpm_failures = NOT_COUNTED;
+    }
+    stats
+}
+
+static mut PROTOCOL: StatsProtocol = StatsProtocol {
+    revision: UVM_STATS_PROTOCOL_REVISION_BUILD,
+    get_stats,
+    get_top,
+    get_ring_header,
+    get_ring_record,
+    get_build,
+};
+
+efiapi! {
+    /**
+     * @brief Fills `stats` with the hook states and counters.
+     */
+    fn get_stats(_this: *mut StatsProtocol, stats: *mut Stats) -> efi::Status {
+        if stats.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        unsafe { stats.write(collect()) };
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Fills the `*count` entries at `entries` with the most accessed
+     *        variables, most accessed first, and sets `*count` to the number
+     *        filled.
+     */
+    fn get_top(_this: *mut StatsProtocol, entries: *mut TopEntry, count: *mut usize) -> efi::Status {
+        if count.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let capacity = unsafe { *count };
+        if entries.is_null() && capacity != 0 {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        let entries = match capacity {
+            0 => &mut [][..],
+            _ => unsafe { core::slice::from_raw_parts_mut(entries, capacity) },
+        };
+        unsafe { *count = top::top(entries) };
+        efi::Status::SUCCESS
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Fills `header` with the fields of the ring buffer header of minor
+     *        version 1, which callers built against any revision have room
+     *        for.
+     */
+    fn get_ring_header(_this: *mut StatsProtocol, header: *mut RingHeader) -> efi::Status {
+        if header.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        #[cfg(feature = "log-ring")]
+        return match ring::header() {
+            Some(mut copy) => {
+                copy.format.minor = 1;
+                copy.format.size = RING_MINOR_1_SIZE as u32;
+                unsafe {
+                    core::ptr::copy_nonoverlapping(
+                        &copy as *const RingHeader as *const u8,
+                        header as *mut u8,
+                        RING_MINOR_1_SIZE,
+                    )
+                };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_READY,
+        };
+        #[cfg(not(feature = "log-ring"))]
+        return efi::Status::UNSUPPORTED;
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Fills `record` with the ring buffer record with the sequence
+     *        number. Fails with NOT_FOUND once it was overwritten.
+     */
+    fn get_ring_record(
+        _this: *mut StatsProtocol,
+        sequence: u64,
+        record: *mut RingRecord,
+    ) -> efi::Status {
+        if record.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        #[cfg(feature = "log-ring")]
+        return match ring::copy_record(sequence) {
+            Some(copy) => {
+                unsafe { record.write(copy) };
+                efi::Status::SUCCESS
+            }
+            None => efi::Status::NOT_FOUND,
+        };
+        #[cfg(not(feature = "log-ring"))]
+        {
+            let _ = sequence;
+            return efi::Status::UNSUPPORTED;
+        }
+    }
+}
+
+efiapi! {
+    /**
+     * @brief Fills `build` with what the driver was built as.
+     */
+    fn get_build(_this: *mut StatsProtocol, build: *mut BuildInfo) -> efi::Status {
+        if build.is_null() {
+            return efi::Status::INVALID_PARAMETER;
+        }
+        unsafe { build.write(banner::info()) };
+        efi::Status::SUCCESS
+    }
+}
+
+/**
+ * @brief Installs the protocol on the image handle.
+ */
+pub fn install(boot_services: &mut efi::BootServices, image_handle: efi::Handle) -> efi::Status {
+    let mut handle = image_handle;
+    (boot_services.install_protocol_interface)(
+        &mut handle,
+        &UVM_STATS_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        efi::InterfaceType::NativeInterface,
+        unsafe { &mut *core::ptr::addr_of_mut!(PROTOCOL) } as *mut _ as *mut core::ffi::c_void,
+    )
+}
+
+/**
+ * @brief Uninstalls the protocol from the image handle.
+ */
+pub fn uninstall(boot_services: &mut efi::BootServices, image_handle: efi::Handle) -> efi::Status {
+    (boot_services.uninstall_protocol_interface)(
+        image_handle,
+        &UVM_STATS_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        unsafe { &mut *core::ptr::addr_of_mut!(PROTOCOL) } as *mut _ as *mut core::ffi::c_void,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::classify::GLOBAL_VARIABLE_GUID;
+    use crate::top::Access;
+
+    #[test]
+    fn counters_and_top_variables() {
+        let _lock = crate::mock::lock();
+        top::reset();
+        let this = core::ptr::null_mut();
+        for _ in 0..2 {
+            top::count("BootOrder", &GLOBAL_VARIABLE_GUID, Access::Read);
+        }
+        top::count("Boot0001", &GLOBAL_VARIABLE_GUID, Access::Write);
+
+        let mut stats = Stats::default();
+        assert_eq!(
+            get_stats(this, core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(get_stats(this, &mut stats), efi::Status::SUCCESS);
+        assert_eq!((stats.get_variable_calls, stats.set_variable_calls), (2, 1));
+        assert_eq!(stats.get_variable_hook, u32::from(crate::HOOK_ACTIVE));
+
+        let mut entries = [TopEntry::EMPTY; 4];
+        let mut count = entries.len();
+        assert_eq!(
+            get_top(this, core::ptr::null_mut(), &mut count),
+            efi::Status::INVALID_PARAMETER
+        );
+        assert_eq!(
+            get_top(this, entries.as_mut_ptr(), &mut count),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(count, 2);
+        assert_eq!(entries[0].name(), "BootOrder");
+        assert_eq!(entries[1].name(), "Boot0001");
+        let mut count = 0;
+        assert_eq!(
+            get_top(this, core::ptr::null_mut(), &mut count),
+            efi::Status::SUCCESS
+        );
+        assert_eq!(count, 0);
+        top::reset();
+    }
+}
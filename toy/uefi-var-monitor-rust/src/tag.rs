@@ -0,0 +1,119 @@
+// uefi-var-monitor-rust/src/tag.rs
+//
+// The subsystem tags of the lines the driver writes about itself, so that
+// what a subsystem said can be told apart from its neighbours in a capture.
+// Each module logging with error!, warn!, info!, debug!, trace! or log_at!
+// names its subsystem once, at the top:
+//
+//   const TAG: Tag = tag::CONFIG;
+//
+// and passes it first (after the level, for log_at!), e.g.
+//
+//   info!(TAG, "Configuration saved to {}", CONFIG_VARIABLE);
+//
+// which is written, after the location with log-src-loc, as
+//
+//   [cfg] Configuration saved to UvmConfig
+//
+// main.rs, which does a bit of everything, names the tag at each call. The
+// records of accesses and alerts (log_record!, record!, alert!) carry none:
+// their formats are parsed, and stay as they are. Tools that look for a
+// lifecycle line look for its text anywhere in the line, not at its start.
+//
+// The tags, one per subsystem, grep-able as written:
+//
+//   [boot]        loading and unloading, the boot events, relocation at
+//                 SetVirtualAddressMap, the boot report
+//   [cfg]         configuration: the configuration variable, load options,
+//                 the log level, the protocol, build-time lists
+//   [hook]        the hooks: installing, moving and restoring them, what
+//                 they cost and count
+//   [sink]        the log sinks and what they lost
+//   [integrity]   the store and what is checked in it: the inventory,
+//                 shadows, pins, the Secure Boot mode, the space left
+//   [enforce]     the write lock, hiding and enforcement
+//   [mm]          the events of the MM module
+//
+// The type is the support crate's, which the macros take (see
+// support/src/tag.rs); the driver's tags are only made here.
+
+pub use uvm_support::tag::Tag;
+
+pub const BOOT: Tag = Tag::new("[boot]");
+pub const CONFIG: Tag = Tag::new("[cfg]");
+pub const HOOK: Tag = Tag::new("[hook]");
+// no-log has no sink to speak of.
+#[cfg_attr(feature = "no-log", allow(dead_code))]
+pub const SINK: Tag = Tag::new("[sink]");
+pub const INTEGRITY: Tag = Tag::new("[integrity]");
+// Unused without the feature building the subsystem.
+#[cfg_attr(not(feature = "enforce"), allow(dead_code))]
+pub const ENFORCE: Tag = Tag::new("[enforce]");
+#[cfg_attr(not(feature = "mm-events"), allow(dead_code))]
+pub const MM: Tag = Tag::new("[mm]");
+
+#[cfg(test)]
+pub const ALL: [Tag; 7] = [BOOT, CONFIG, HOOK, SINK, INTEGRITY, ENFORCE, MM];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    // Host tests capture what the serial sink would write (see serial.rs);
+    // the other sinks are handed each line as they would be, and must pass
+    // the tag on as the serial port does.
+    #[test]
+    fn lines_carry_their_tag_through_each_sink() {
+        let _lock = crate::mock::lock();
+        crate::level::reset();
+        crate::serial::start_capture();
+        for tag in ALL {
+            info!(tag, "Driver being loaded");
+        }
+        log_record!("G: record");
+        let records = crate::serial::without_locations(&crate::serial::take_capture());
+        let lines: Vec<&str> = records.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "[boot] Driver being loaded",
+                "[cfg] Driver being loaded",
+                "[hook] Driver being loaded",
+                "[sink] Driver being loaded",
+                "[integrity] Driver being loaded",
+                "[enforce] Driver being loaded",
+                "[mm] Driver being loaded",
+                "G: record",
+            ]
+        );
+
+        for line in lines.iter().take(ALL.len()) {
+            #[cfg(feature = "log-ring")]
+            {
+                use crate::ring::{OverflowPolicy, RingBuffer};
+                let mut ring = RingBuffer::<1>::new(OverflowPolicy::OverwriteOldest);
+                assert!(ring.push_fmt(format_args!("{}", line)));
+                let record = ring.get(0).unwrap();
+                assert_eq!(&record.data[..record.length as usize], line.as_bytes());
+            }
+            #[cfg(feature = "serial-queue")]
+            {
+                let queue = crate::queue::SpscQueue::<256>::new();
+                assert!(queue.push_fmt(format_args!("{}", line)));
+                let mut drained = Vec::new();
+                assert!(queue.drain(|record| drained.push(record.to_vec())));
+                assert_eq!(drained, [line.as_bytes().to_vec()]);
+            }
+            #[cfg(feature = "log-net")]
+            {
+                let mut buffer = [0u8; 128];
+                let size = crate::net::format_syslog(&mut buffer, format_args!("{}", line));
+                let payload = core::str::from_utf8(&buffer[..size]).unwrap();
+                assert!(payload.ends_with(&std::format!(" - - - {}", line)));
+            }
+            let tag = ALL.iter().find(|tag| line.starts_with(tag.text())).unwrap();
+            assert_eq!(line.get(tag.text().len()..), Some(" Driver being loaded"));
+        }
+    }
+}
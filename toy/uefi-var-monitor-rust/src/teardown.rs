@@ -0,0 +1,184 @@
+// uefi-var-monitor-rust/src/teardown.rs
+//
+// Cleanup actions recorded by efi_main as it acquires resources. If loading
+// fails part way, everything acquired so far is released in reverse order, so
+// that the system is never left half-monitored; the Unload handler releases
+// the same list.
+
+#[cfg(feature = "ring-dump")]
+use crate::dump;
+#[cfg(feature = "enforce")]
+use crate::get_next_variable_name;
+#[cfg(feature = "gop-alert")]
+use crate::gop;
+use crate::integrity;
+#[cfg(feature = "log-net")]
+use crate::net;
+use crate::protocol;
+#[cfg(feature = "serial-queue")]
+use crate::queue;
+use crate::set_variable;
+use crate::stats;
+use crate::tag::{self, Tag};
+#[cfg(feature = "tpm-measure")]
+use crate::tpm;
+use r_efi::efi;
+
+const TAG: Tag = tag::BOOT;
+
+pub const MAX_CLEANUPS: usize = 24;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cleanup {
+    CloseEvent(r_efi::base::Event),
+    // Put the saved GetVariable back into the Runtime Services Table.
+    Unhook,
+    // Likewise for SetVariable.
+    UnhookSetVariable,
+    // And for GetNextVariableName.
+    #[cfg(feature = "enforce")]
+    UnhookGetNextVariableName,
+    UninstallProtocol(efi::Handle),
+    UninstallStatsProtocol(efi::Handle),
+    StopIntegrity,
+    #[cfg(feature = "ring-dump")]
+    StopDump,
+    #[cfg(feature = "log-net")]
+    DisableNet,
+    #[cfg(feature = "serial-queue")]
+    StopQueue,
+    #[cfg(feature = "gop-alert")]
+    DisableGop,
+    #[cfg(feature = "tpm-measure")]
+    StopTpm,
+}
+
+impl Cleanup {
+    fn run(self, system_table: &mut efi::SystemTable) -> efi::Status {
+        let boot_services = unsafe { &mut *system_table.boot_services };
+        match self {
+            Cleanup::CloseEvent(event) => (boot_services.close_event)(event),
+            Cleanup::Unhook => crate::unhook(system_table),
+            Cleanup::UnhookSetVariable => set_variable::unhook(system_table),
+            #[cfg(feature = "enforce")]
+            Cleanup::UnhookGetNextVariableName => get_next_variable_name::unhook(system_table),
+            Cleanup::UninstallProtocol(image_handle) => {
+                protocol::uninstall(boot_services, image_handle)
+            }
+            Cleanup::UninstallStatsProtocol(image_handle) => {
+                stats::uninstall(boot_services, image_handle)
+            }
+            Cleanup::StopIntegrity => {
+                integrity::shutdown();
+                efi::Status::SUCCESS
+            }
+            #[cfg(feature = "ring-dump")]
+            Cleanup::StopDump => {
+                dump::stop();
+                efi::Status::SUCCESS
+            }
+            #[cfg(feature = "log-net")]
+            Cleanup::DisableNet => {
+                net::disable();
+                efi::Status::SUCCESS
+            }
+            #[cfg(feature = "serial-queue")]
+            Cleanup::StopQueue => {
+                queue::stop();
+                efi::Status::SUCCESS
+            }
+            #[cfg(feature = "gop-alert")]
+            Cleanup::DisableGop => {
+                gop::disable();
+                efi::Status::SUCCESS
+            }
+            #[cfg(feature = "tpm-measure")]
+            Cleanup::StopTpm => {
+                tpm::stop();
+                efi::Status::SUCCESS
+            }
+        }
+    }
+}
+
+pub struct Teardown<const N: usize> {
+    actions: [Option<Cleanup>; N],
+    count: usize,
+}
+
+impl<const N: usize> Teardown<N> {
+    pub const fn new() -> Self {
+        Teardown {
+            actions: [None; N],
+            count: 0,
+        }
+    }
+
+    /**
+     * @brief Records the action releasing a resource just acquired.
+     */
+    pub fn push(&mut self, action: Cleanup) -> efi::Status {
+        match self.actions.get_mut(self.count) {
+            Some(slot) => *slot = Some(action),
+            None => return efi::Status::OUT_OF_RESOURCES,
+        }
+        self.count += 1;
+        efi::Status::SUCCESS
+    }
+
+    /**
+     * @brief Runs the recorded actions in reverse order, logging each one. A
+     *        failed step is logged and skipped, except for the unhooks:
+     *        releasing what a live hook relies on would be worse than stopping
+     *        there, so the unwind stops and keeps that action and the ones
+     *        before it.
+     */
+    pub fn unwind(&mut self, system_table: &mut efi::SystemTable) -> efi::Status {
+        while let Some(last) = self.count.checked_sub(1) {
+            let action = match self.actions.get(last) {
+                Some(Some(action)) => *action,
+                _ => break,
+            };
+            let efi_status = action.run(system_table);
+            debug!(TAG, "Teardown: {:?} : {:#x}", action, efi_status.as_usize());
+            let unhook = match action {
+                Cleanup::Unhook | Cleanup::UnhookSetVariable => true,
+                #[cfg(feature = "enforce")]
+                Cleanup::UnhookGetNextVariableName => true,
+                _ => false,
+            };
+            if efi_status.is_error() && unhook {
+                return efi_status;
+            }
+            if let Some(slot) = self.actions.get_mut(last) {
+                *slot = None;
+            }
+            self.count = last;
+        }
+        efi::Status::SUCCESS
+    }
+}
+
+// Only used from efi_main and the Unload handler, which do not run
+// concurrently.
+static mut TEARDOWN: Teardown<MAX_CLEANUPS> = Teardown::new();
+
+/**
+ * @brief Records the action releasing a resource just acquired. If it cannot
+ *        be recorded, the resource is released right away and an error
+ *        returned.
+ */
+pub fn record(action: Cleanup, system_table: &mut efi::SystemTable) -> efi::Status {
+    let efi_status = unsafe { &mut *core::ptr::addr_of_mut!(TEARDOWN) }.push(action);
+    if efi_status.is_error() {
+        action.run(system_table);
+    }
+    efi_status
+}
+
+/**
+ * @brief Releases everything recorded so far, most recent first.
+ */
+pub fn unwind(system_table: &mut efi::SystemTable) -> efi::Status {
+    unsafe { &mut *core::ptr::addr_of_mut!(TEARDOWN) }.unwind(system_table)
+}
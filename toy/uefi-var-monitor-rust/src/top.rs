@@ -0,0 +1,352 @@
+// uefi-var-monitor-rust/src/top.rs
+//
+// How often each variable was read and written, for the table of the most
+// accessed variables the statistics protocol hands out (see stats.rs), along
+// with the total number of GetVariable and SetVariable calls seen.
+//
+// Variables are keyed by vendor GUID and the CRC32 of their name; the name is
+// kept for display, cut at TOP_NAME_SIZE bytes. profile-minimal counts by GUID
+// only, under an empty name. The table is bounded; once full, the least
+// accessed entry makes room for the new variable, so that frequently accessed
+// ones keep their place. It is used at OS runtime, possibly on several CPUs,
+// so it is only ever try-borrowed: an access that finds it busy is only
+// counted in the totals.
+//
+// Each entry carries the storage of the variable, volatile or non-volatile,
+// as the attributes of its last successful call showed it. Until one does,
+// the entry keeps the successful calls the counters of counter.rs put under
+// unknown storage, and hands them over once the storage is known; a call
+// without attributes to a variable of known storage is moved at once. The
+// counters themselves are those of counter.rs, which the driver keeps:
+// note_storage() says what to move, and counters::note_storage() in the
+// driver moves it.
+
+use crate::crc32;
+use atomic_refcell::AtomicRefCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+use uvm_interface::protocol::{
+    storage_of, StorageCounts, TopEntry, STORAGE_UNKNOWN, TOP_NAME_SIZE,
+};
+
+pub const MAX_COUNTED: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/**
+ * @brief Returns one successful call of `size` bytes, as StorageCounts.
+ */
+pub fn one_call(access: Access, size: usize) -> StorageCounts {
+    let size = size as u64;
+    match access {
+        Access::Read => StorageCounts {
+            reads: 1,
+            bytes_read: size,
+            ..StorageCounts::default()
+        },
+        Access::Write => StorageCounts {
+            writes: 1,
+            bytes_written: size,
+            ..StorageCounts::default()
+        },
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Counted {
+    name_crc32: u32,
+    entry: TopEntry,
+    // Successful calls counted as of unknown storage, while the entry's is.
+    unknown: StorageCounts,
+}
+
+pub struct Table<const N: usize> {
+    entries: [Option<Counted>; N],
+}
+
+impl<const N: usize> Table<N> {
+    pub const fn new() -> Self {
+        Table { entries: [None; N] }
+    }
+
+    fn position(&self, name_crc32: u32, guid: &efi::Guid) -> Option<usize> {
+        self.entries.iter().position(|entry| match entry {
+            Some(counted) => counted.name_crc32 == name_crc32 && counted.entry.guid == *guid,
+            None => false,
+        })
+    }
+
+    /**
+     * @brief Returns the entry of a variable, taking a free or the least
+     *        accessed one if it has none.
+     */
+    fn entry(&mut self, name: &str, guid: &efi::Guid) -> Option<&mut TopEntry> {
+        let name_crc32 = crc32::crc32(name.as_bytes());
+        let index = match self.position(name_crc32, guid) {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .entries
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, entry)| entry.map_or(0, |counted| counted.entry.accesses()))
+                    .map(|(index, _)| index)?;
+                let mut entry = TopEntry::EMPTY;
+                entry.guid = *guid;
+                let length = core::cmp::min(name.len(), TOP_NAME_SIZE);
+                entry.name[..length].copy_from_slice(&name.as_bytes()[..length]);
+                entry.name_length = length as u32;
+                *self.entries.get_mut(index)? = Some(Counted {
+                    name_crc32,
+                    entry,
+                    unknown: StorageCounts::default(),
+                });
+                index
+            }
+        };
+        self.entries
+            .get_mut(index)?
+            .as_mut()
+            .map(|counted| &mut counted.entry)
+    }
+
+    /**
+     * @brief Counts an access to a variable.
+     */
+    pub fn count(&mut self, name: &str, guid: &efi::Guid, access: Access) {
+        if let Some(entry) = self.entry(name, guid) {
+            match access {
+                Access::Read => entry.reads = entry.reads.saturating_add(1),
+                Access::Write => entry.writes = entry.writes.saturating_add(1),
+            }
+        }
+    }
+
+    /**
+     * @brief Notes the storage of a variable from the attributes of a
+     *        successful call, 0 if it had none. Returns calls counted as of
+     *        unknown storage to move to a known one, if any.
+     */
+    pub fn note_storage(
+        &mut self,
+        name: &str,
+        guid: &efi::Guid,
+        attributes: u32,
+        call: StorageCounts,
+    ) -> Option<(u32, StorageCounts)> {
+        let index = self.position(crc32::crc32(name.as_bytes()), guid)?;
+        let counted = self.entries.get_mut(index)?.as_mut()?;
+        match (storage_of(attributes), counted.entry.storage) {
+            (STORAGE_UNKNOWN, STORAGE_UNKNOWN) => {
+                let unknown = &mut counted.unknown;
+                unknown.reads = unknown.reads.saturating_add(call.reads);
+                unknown.writes = unknown.writes.saturating_add(call.writes);
+                unknown.bytes_read = unknown.bytes_read.saturating_add(call.bytes_read);
+                unknown.bytes_written = unknown.bytes_written.saturating_add(call.bytes_written);
+                None
+            }
+            (STORAGE_UNKNOWN, storage) => Some((storage, call)),
+            (storage, _) => {
+                counted.entry.storage = storage;
+                let unknown = core::mem::take(&mut counted.unknown);
+                if unknown == StorageCounts::default() {
+                    None
+                } else {
+                    Some((storage, unknown))
+                }
+            }
+        }
+    }
+
+    /**
+     * @brief Fills `entries` with the most accessed variables, most accessed
+     *        first. Returns how many were written.
+     */
+    pub fn top(&self, entries: &mut [TopEntry]) -> usize {
+        let mut taken = [false; N];
+        let mut count = 0;
+        for slot in entries.iter_mut() {
+            let best = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| taken.get(*index) == Some(&false))
+                .filter_map(|(index, entry)| entry.map(|counted| (index, counted.entry)))
+                .fold(
+                    None,
+                    |best: Option<(usize, TopEntry)>, (index, entry)| match best {
+                        Some((_, most)) if most.accesses() >= entry.accesses() => best,
+                        _ => Some((index, entry)),
+                    },
+                );
+            match best {
+                Some((index, entry)) => {
+                    if let Some(taken) = taken.get_mut(index) {
+                        *taken = true;
+                    }
+                    *slot = entry;
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+        count
+    }
+}
+
+impl<const N: usize> Default for Table<N> {
+    fn default() -> Self {
+        Table::new()
+    }
+}
+
+static TABLE: AtomicRefCell<Table<MAX_COUNTED>> = AtomicRefCell::new(Table::new());
+static READS: AtomicU64 = AtomicU64::new(0);
+static WRITES: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * @brief Counts an access to a variable, in the table unless it is in use.
+ */
+pub fn count(name: &str, guid: &efi::Guid, access: Access) {
+    match access {
+        Access::Read => READS.fetch_add(1, Ordering::Relaxed),
+        Access::Write => WRITES.fetch_add(1, Ordering::Relaxed),
+    };
+    if let Ok(mut table) = TABLE.try_borrow_mut() {
+        table.count(name, guid, access);
+    }
+}
+
+/**
+ * @brief Notes the storage of a variable after a successful call of
+ *        `size` bytes with `attributes`, 0 if it had none. Returns calls
+ *        counted as of unknown storage to move to a known one, if any.
+ */
+pub fn note_storage(
+    name: &str,
+    guid: &efi::Guid,
+    access: Access,
+    attributes: u32,
+    size: usize,
+) -> Option<(u32, StorageCounts)> {
+    match TABLE.try_borrow_mut() {
+        Ok(mut table) => table.note_storage(name, guid, attributes, one_call(access, size)),
+        Err(_) => None,
+    }
+}
+
+/**
+ * @brief Counts an access by vendor GUID alone, as profile-minimal does
+ *        without decoding the name. The entry has an empty name, and is
+ *        matched on the GUID bytes.
+ */
+pub fn count_guid(guid: &efi::Guid, access: Access) {
+    count("", guid, access);
+}
+
+/**
+ * @brief Returns the total number of reads and writes counted.
+ */
+pub fn totals() -> (u64, u64) {
+    (
+        READS.load(Ordering::Relaxed),
+        WRITES.load(Ordering::Relaxed),
+    )
+}
+
+/**
+ * @brief Fills `entries` with the most accessed variables. Returns how many
+ *        were written, none if the table is in use.
+ */
+pub fn top(entries: &mut [TopEntry]) -> usize {
+    match TABLE.try_borrow_mut() {
+        Ok(table) => table.top(entries),
+        Err(_) => 0,
+    }
+}
+
+/**
+ * @brief Empties the table and zeroes the totals.
+ */
+pub fn reset() {
+    *TABLE.borrow_mut() = Table::new();
+    READS.store(0, Ordering::Relaxed);
+    WRITES.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guids::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID};
+    use uvm_interface::protocol::STORAGE_NON_VOLATILE;
+
+    #[test]
+    fn least_accessed_entry_makes_room() {
+        let mut table = Table::<2>::new();
+        for _ in 0..3 {
+            table.count("db", &IMAGE_SECURITY_DATABASE_GUID, Access::Read);
+        }
+        table.count("db", &GLOBAL_VARIABLE_GUID, Access::Write);
+        table.count("db", &GLOBAL_VARIABLE_GUID, Access::Read);
+
+        let mut entries = [TopEntry::EMPTY; 3];
+        assert_eq!(table.top(&mut entries), 2);
+        assert_eq!(entries[0].guid, IMAGE_SECURITY_DATABASE_GUID);
+        assert_eq!((entries[0].reads, entries[0].writes), (3, 0));
+        assert_eq!(entries[1].guid, GLOBAL_VARIABLE_GUID);
+        assert_eq!((entries[1].reads, entries[1].writes), (1, 1));
+
+        // A third variable replaces the least accessed one.
+        table.count("dbx", &IMAGE_SECURITY_DATABASE_GUID, Access::Write);
+        assert_eq!(table.top(&mut entries[..1]), 1);
+        assert_eq!(entries[0].name(), "db");
+        assert_eq!(table.top(&mut entries), 2);
+        assert_eq!(entries[1].name(), "dbx");
+        assert_eq!((entries[1].reads, entries[1].writes), (0, 1));
+    }
+
+    #[test]
+    fn storage_is_learned_from_attributes() {
+        let mut table = Table::<2>::new();
+        let guid = GLOBAL_VARIABLE_GUID;
+        let read = |size| one_call(Access::Read, size);
+        // Nothing to note for a variable not in the table.
+        assert_eq!(table.note_storage("Lang", &guid, 0x7, read(4)), None);
+
+        table.count("Lang", &guid, Access::Read);
+        table.count("Lang", &guid, Access::Read);
+        assert_eq!(table.note_storage("Lang", &guid, 0, read(4)), None);
+        assert_eq!(table.note_storage("Lang", &guid, 0, read(4)), None);
+        let mut entries = [TopEntry::EMPTY; 1];
+        table.top(&mut entries);
+        assert_eq!(entries[0].storage, STORAGE_UNKNOWN);
+
+        // The attributes move both reads to non-volatile.
+        table.count("Lang", &guid, Access::Write);
+        let moved = table.note_storage("Lang", &guid, 0x7, one_call(Access::Write, 4));
+        assert_eq!(
+            moved,
+            Some((
+                STORAGE_NON_VOLATILE,
+                StorageCounts {
+                    reads: 2,
+                    bytes_read: 8,
+                    ..StorageCounts::default()
+                }
+            ))
+        );
+        table.top(&mut entries);
+        assert_eq!(entries[0].storage, STORAGE_NON_VOLATILE);
+
+        // Later calls without attributes are moved one by one.
+        assert_eq!(
+            table.note_storage("Lang", &guid, 0, read(2)),
+            Some((STORAGE_NON_VOLATILE, read(2)))
+        );
+        assert_eq!(table.note_storage("Lang", &guid, 0x7, read(2)), None);
+    }
+}
@@ -0,0 +1,342 @@
+// uefi-var-monitor-rust/src/tpm.rs
+//
+// Optional measurement of alert-class records into a TPM PCR through the
+// TCG2 protocol, so that they show up in the TCG event log and in quotes.
+// Each alert is extended as an EV_UVM_ALERT event whose data is the alert
+// text. It is only done during the boot-services phase, and silently not at
+// all on machines without a TPM.
+//
+// HashLogExtendEvent talks to the TPM and can take tens of milliseconds,
+// while alerts are raised from inside hooked calls. So alert! only copies the
+// text into a small queue, and a timer notification at TPL_CALLBACK measures
+// a bounded number of queued alerts per tick. Alerts that find the queue full
+// or busy are dropped and counted; so are those still queued at
+// ExitBootServices, from which on the protocol is not used anymore.
+//
+// The PCR is configured at build time.
+//
+//   UVM_TPM_PCR   0..23   (default: 7)
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+use r_efi::efi;
+
+pub use uefi_var_monitor::settings::DEFAULT_PCR;
+
+// EFI_TCG2_PROTOCOL_GUID {607f766c-7455-42be-930b-e4d76db2720f}
+const TCG2_PROTOCOL_GUID: efi::Guid = efi::Guid::from_fields(
+    0x607f766c,
+    0x7455,
+    0x42be,
+    0x93,
+    0x0b,
+    &[0xe4, 0xd7, 0x6d, 0xb2, 0x72, 0x0f],
+);
+
+// Event type of the measured alerts, outside the ranges the TCG assigns.
+pub const EV_UVM_ALERT: u32 = 0x5556_4d01;
+
+// EFI_TCG2_EVENT: Size, then EFI_TCG2_EVENT_HEADER (HeaderSize,
+// HeaderVersion, PCRIndex, EventType), packed.
+const EVENT_SIZE_FIELD: usize = 4;
+const EVENT_HEADER_SIZE: usize = 4 + 2 + 4 + 4;
+const EVENT_HEADER_VERSION: u16 = 1;
+
+pub const MAX_ALERT_LENGTH: usize = 160;
+const MAX_EVENT_SIZE: usize = EVENT_SIZE_FIELD + EVENT_HEADER_SIZE + MAX_ALERT_LENGTH;
+
+pub const QUEUE_LENGTH: usize = 8;
+// Alerts measured per timer tick, which bounds the time spent in it.
+const MEASUREMENTS_PER_TICK: usize = 2;
+// Interval of the measurement timer in 100ns units (100ms).
+const MEASURE_INTERVAL: u64 = 1_000_000;
+
+type HashLogExtendEvent = efiapi! {fn(
+    *mut Tcg2Protocol,
+    u64,
+    efi::PhysicalAddress,
+    u64,
+    *mut u8,
+) -> efi::Status};
+
+// Only the members up to HashLogExtendEvent are used.
+#[repr(C)]
+struct Tcg2Protocol {
+    get_capability: *mut core::ffi::c_void,
+    get_event_log: *mut core::ffi::c_void,
+    hash_log_extend_event: HashLogExtendEvent,
+}
+
+#[derive(Clone, Copy)]
+struct Alert {
+    text: [u8; MAX_ALERT_LENGTH],
+    length: usize,
+}
+
+impl fmt::Write for Alert {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            match self.text.get_mut(self.length) {
+                Some(slot) => *slot = b,
+                None => break,
+            }
+            self.length += 1;
+        }
+        Ok(())
+    }
+}
+
+pub struct Queue<const N: usize> {
+    alerts: [Alert; N],
+    first: usize,
+    count: usize,
+}
+
+impl<const N: usize> Queue<N> {
+    pub const fn new() -> Self {
+        Queue {
+            alerts: [Alert {
+                text: [0; MAX_ALERT_LENGTH],
+                length: 0,
+            }; N],
+            first: 0,
+            count: 0,
+        }
+    }
+
+    /**
+     * @brief Queues the text of an alert, truncated to MAX_ALERT_LENGTH.
+     *        Returns false if the queue is full.
+     */
+    pub fn push(&mut self, args: fmt::Arguments) -> bool {
+        if self.count == N {
+            return false;
+        }
+        let alert = &mut self.alerts[(self.first + self.count) % N];
+        alert.length = 0;
+        let _ = fmt::Write::write_fmt(alert, args);
+        self.count += 1;
+        true
+    }
+
+    /**
+     * @brief Returns the text of the oldest alert, if any, without removing
+     *        it.
+     */
+    pub fn front(&self) -> Option<&[u8]> {
+        if self.count == 0 {
+            return None;
+        }
+        let alert = self.alerts.get(self.first)?;
+        alert.text.get(..alert.length)
+    }
+
+    pub fn pop(&mut self) {
+        if self.count != 0 {
+            self.first = (self.first + 1) % N;
+            self.count -= 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+}
+
+/**
+ * @brief Writes the EFI_TCG2_EVENT for an alert into `buffer`, returning its
+ *        size. The event data is the alert text, which is also what is
+ *        hashed.
+ */
+pub fn build_event(buffer: &mut [u8; MAX_EVENT_SIZE], pcr: u32, text: &[u8]) -> usize {
+    let text = text.get(..MAX_ALERT_LENGTH).unwrap_or(text);
+    let size = EVENT_SIZE_FIELD + EVENT_HEADER_SIZE + text.len();
+    buffer[0..4].copy_from_slice(&(size as u32).to_le_bytes());
+    buffer[4..8].copy_from_slice(&(EVENT_HEADER_SIZE as u32).to_le_bytes());
+    buffer[8..10].copy_from_slice(&EVENT_HEADER_VERSION.to_le_bytes());
+    buffer[10..14].copy_from_slice(&pcr.to_le_bytes());
+    buffer[14..18].copy_from_slice(&EV_UVM_ALERT.to_le_bytes());
+    buffer[18..size].copy_from_slice(text);
+    size
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BUSY: AtomicBool = AtomicBool::new(false);
+static PCR: AtomicU32 = AtomicU32::new(DEFAULT_PCR);
+static TCG2: AtomicPtr<Tcg2Protocol> = AtomicPtr::new(core::ptr::null_mut());
+static BOOT_SERVICES: AtomicPtr<efi::BootServices> = AtomicPtr::new(core::ptr::null_mut());
+static TIMER_EVENT: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+
+// Only accessed with BUSY held.
+static mut QUEUE: Queue<QUEUE_LENGTH> = Queue::new();
+static mut EVENT: [u8; MAX_EVENT_SIZE] = [0; MAX_EVENT_SIZE];
+
+/**
+ * @brief Changes the PCR alerts are extended into.
+ */
+pub fn set_pcr(pcr: u32) {
+    PCR.store(pcr, Ordering::Release);
+}
+
+/**
+ * @brief Locates the TCG2 protocol and starts the measurement timer. Returns
+ *        NOT_FOUND if there is no TPM.
+ */
+pub fn start(system_table: &mut efi::SystemTable) -> efi::Status {
+    let boot_services = unsafe { &mut *system_table.boot_services };
+
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let mut efi_status = (boot_services.locate_protocol)(
+        &TCG2_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        return efi::Status::NOT_FOUND;
+    }
+
+    let mut event: r_efi::base::Event = core::ptr::null_mut();
+    efi_status = (boot_services.create_event)(
+        efi::EVT_TIMER | efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        handle_timer,
+        core::ptr::null_mut(),
+        &mut event,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    efi_status = (boot_services.set_timer)(event, efi::TimerDelay::TimerPeriodic, MEASURE_INTERVAL);
+    if efi_status.is_error() {
+        (boot_services.close_event)(event);
+        return efi_status;
+    }
+
+    TIMER_EVENT.store(event, Ordering::Release);
+    BOOT_SERVICES.store(boot_services, Ordering::Release);
+    TCG2.store(interface as *mut Tcg2Protocol, Ordering::Release);
+    ENABLED.store(true, Ordering::Release);
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Stops measuring for good and closes the timer. Alerts still queued
+ *        are counted as dropped. Called at ExitBootServices and unload.
+ */
+pub fn stop() {
+    if !ENABLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+    let boot_services = BOOT_SERVICES.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    let event = TIMER_EVENT.swap(core::ptr::null_mut(), Ordering::AcqRel);
+    if !boot_services.is_null() && !event.is_null() {
+        (unsafe { &*boot_services }.close_event)(event);
+    }
+    TCG2.store(core::ptr::null_mut(), Ordering::Release);
+    if !BUSY.swap(true, Ordering::Acquire) {
+        let queue = unsafe { &mut *core::ptr::addr_of_mut!(QUEUE) };
+        DROPPED.fetch_add(queue.len() as u64, Ordering::Relaxed);
+        while queue.len() != 0 {
+            queue.pop();
+        }
+        BUSY.store(false, Ordering::Release);
+    }
+}
+
+/**
+ * @brief Returns the number of alerts dropped and the number of failed
+ *        measurements so far.
+ */
+pub fn losses() -> (u64, u64) {
+    (
+        DROPPED.load(Ordering::Relaxed),
+        FAILURES.load(Ordering::Relaxed),
+    )
+}
+
+/**
+ * @brief Queues an alert for measurement. Never calls into the TPM itself.
+ */
+#[cfg_attr(test, allow(dead_code))]
+pub fn measure_alert(args: fmt::Arguments) {
+    if !ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+    if BUSY.swap(true, Ordering::Acquire) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+    if !unsafe { &mut *core::ptr::addr_of_mut!(QUEUE) }.push(args) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    BUSY.store(false, Ordering::Release);
+}
+
+efiapi! {
+    fn handle_timer(_event: r_efi::base::Event, _context: *mut core::ffi::c_void) {
+        if BUSY.swap(true, Ordering::Acquire) {
+            return;
+        }
+        // BUSY is held, so nobody else references QUEUE or EVENT.
+        let queue = unsafe { &mut *core::ptr::addr_of_mut!(QUEUE) };
+        let event = unsafe { &mut *core::ptr::addr_of_mut!(EVENT) };
+        for _ in 0..MEASUREMENTS_PER_TICK {
+            let tcg2 = TCG2.load(Ordering::Acquire);
+            let text = match queue.front() {
+                Some(text) if !tcg2.is_null() => text,
+                _ => break,
+            };
+            let size = build_event(event, PCR.load(Ordering::Acquire), text);
+            let data = &event[EVENT_SIZE_FIELD + EVENT_HEADER_SIZE..size];
+            let efi_status = (unsafe { &*tcg2 }.hash_log_extend_event)(
+                tcg2,
+                0,
+                data.as_ptr() as efi::PhysicalAddress,
+                data.len() as u64,
+                event.as_mut_ptr(),
+            );
+            if efi_status.is_error() {
+                FAILURES.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.pop();
+        }
+        BUSY.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_are_queued_and_framed() {
+        let mut queue = Queue::<2>::new();
+        assert!(queue.push(format_args!("first {}", 1)));
+        assert!(queue.push(format_args!("{:x<200}", "")));
+        assert!(!queue.push(format_args!("third")));
+        assert_eq!(queue.front(), Some(&b"first 1"[..]));
+        queue.pop();
+        assert_eq!(queue.front().map(|text| text.len()), Some(MAX_ALERT_LENGTH));
+        queue.pop();
+        assert_eq!(queue.front(), None);
+        assert!(queue.push(format_args!("again")));
+        assert_eq!(queue.len(), 1);
+
+        let mut event = [0u8; MAX_EVENT_SIZE];
+        let size = build_event(&mut event, 16, b"alert");
+        assert_eq!(size, 23);
+        assert_eq!(
+            event[..size],
+            [
+                23, 0, 0, 0, // Size
+                14, 0, 0, 0, // HeaderSize
+                1, 0, // HeaderVersion
+                16, 0, 0, 0, // PCRIndex
+                0x01, 0x4d, 0x56, 0x55, // EventType
+                b'a', b'l', b'e', b'r', b't',
+            ]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+// uefi-var-monitor-rust/support/src/level.rs
+//
+// The level of each record, which the driver writes records by (see level.rs
+// there), and the verbosities error!, warn!, info!, debug! and trace! are
+// compiled in at (see macros.rs).
+//
+// The verbosity of each module is given by a list of module=verbosity
+// entries separated by ';', the first matching a module applying, e.g. the
+// driver's UVM_LOG_MODULES:
+//
+//   filter=trace;*=info
+//
+// A module is named as in log-src-loc lines, without the crate ("filter",
+// "report", "arch::x86_64", "main" for the crate root), and names its
+// submodules too; "*" names every module. The verbosities are error, warn,
+// info, debug and trace, and off, which compiles out all five. Without an
+// entry, a module builds every one.
+
+use uvm_interface::protocol::{LEVEL_CRITICAL, LEVEL_INFO, LEVEL_TRACE, LEVEL_WARNING};
+
+// The level of a record, most severe first.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Critical = LEVEL_CRITICAL,
+    Warning = LEVEL_WARNING,
+    Info = LEVEL_INFO,
+    Trace = LEVEL_TRACE,
+}
+
+impl Level {
+    /**
+     * @brief Returns the level numbered `value`, if there is one.
+     */
+    pub fn from_u32(value: u32) -> Option<Self> {
+        [Level::Critical, Level::Warning, Level::Info, Level::Trace]
+            .iter()
+            .copied()
+            .find(|level| *level as u32 == value)
+    }
+
+    /**
+     * @brief Returns the level named `text`: critical, warning, info or
+     *        trace.
+     */
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Option<Self> {
+        match text {
+            "critical" => Some(Level::Critical),
+            "warning" => Some(Level::Warning),
+            "info" => Some(Level::Info),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+// The verbosities of the leveled macros, most severe first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verbosity {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/**
+ * @brief Returns whether a macro of `verbosity` expanded in `module_path` is
+ *        compiled in, by the module list `list`. Evaluated at build time,
+ *        where a malformed list stops the build.
+ */
+pub const fn compiled(list: &str, module_path: &str, verbosity: Verbosity) -> bool {
+    verbosity as u8 <= module_verbosity(list.as_bytes(), module_path.as_bytes()) as u8
+}
+
+// The verbosity `list` gives the module at `module_path`.
+const fn module_verbosity(list: &[u8], module_path: &[u8]) -> Verbosity {
+    // Past the crate and "::"; the crate root has no module of its own.
+    let mut start = 0;
+    while start < module_path.len() && module_path[start] != b':' {
+        start += 1;
+    }
+    let module = if start < module_path.len() {
+        module_path.split_at(start + 2).1
+    } else {
+        b"main".as_slice()
+    };
+    let mut entry = 0;
+    while entry < list.len() {
+        let mut end = entry;
+        while end < list.len() && list[end] != b';' {
+            end += 1;
+        }
+        let mut equals = entry;
+        while equals < end && list[equals] != b'=' {
+            equals += 1;
+        }
+        if equals == end {
+            panic!("UVM_LOG_MODULES: an entry is not module=verbosity");
+        }
+        if names(list, entry, equals, module) {
+            return parse_verbosity(list, equals + 1, end);
+        }
+        entry = end + 1;
+    }
+    Verbosity::Trace
+}
+
+// Whether list[start..end] names `module` or a module it is in.
+const fn names(list: &[u8], start: usize, end: usize, module: &[u8]) -> bool {
+    let length = end - start;
+    if length == 1 && list[start] == b'*' {
+        return true;
+    }
+    if module.len() < length || (module.len() > length && module[length] != b':') {
+        return false;
+    }
+    let mut index = 0;
+    while index < length {
+        if module[index] != list[start + index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
+
+const fn parse_verbosity(list: &[u8], start: usize, end: usize) -> Verbosity {
+    let verbosities = [
+        (b"off".as_slice(), Verbosity::Off),
+        (b"error".as_slice(), Verbosity::Error),
+        (b"warn".as_slice(), Verbosity::Warn),
+        (b"info".as_slice(), Verbosity::Info),
+        (b"debug".as_slice(), Verbosity::Debug),
+        (b"trace".as_slice(), Verbosity::Trace),
+    ];
+    let mut found = 0;
+    while found < verbosities.len() {
+        let name = verbosities[found].0;
+        if name.len() == end - start {
+            let mut index = 0;
+            while index < name.len() && name[index] == list[start + index] {
+                index += 1;
+            }
+            if index == name.len() {
+                return verbosities[found].1;
+            }
+        }
+        found += 1;
+    }
+    panic!("UVM_LOG_MODULES: unknown verbosity")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modules_take_the_verbosity_of_the_first_entry_naming_them() {
+        let list = b"filter=trace;arch::x86_64=off;main=warn;report=debug;*=info";
+        let verbosity = |module: &str| module_verbosity(list, module.as_bytes());
+        assert_eq!(verbosity("uefi_var_monitor::filter"), Verbosity::Trace);
+        assert_eq!(
+            verbosity("uefi_var_monitor::filter::tests"),
+            Verbosity::Trace
+        );
+        assert_eq!(verbosity("uefi_var_monitor::filtered"), Verbosity::Info);
+        assert_eq!(verbosity("uefi_var_monitor::arch::x86_64"), Verbosity::Off);
+        assert_eq!(verbosity("uefi_var_monitor::arch"), Verbosity::Info);
+        assert_eq!(verbosity("uefi_var_monitor"), Verbosity::Warn);
+        assert_eq!(verbosity("uefi_var_monitor::report"), Verbosity::Debug);
+        assert_eq!(
+            module_verbosity(b"", b"uefi_var_monitor::report"),
+            Verbosity::Trace
+        );
+        assert_eq!(
+            module_verbosity(b"*=error", b"uefi_var_monitor"),
+            Verbosity::Error
+        );
+        assert!(compiled("*=info", "uvmctl", Verbosity::Info));
+        assert!(!compiled("*=info", "uvmctl", Verbosity::Debug));
+    }
+
+    #[test]
+    fn levels_are_read_by_number_and_name() {
+        assert_eq!(Level::from_u32(LEVEL_INFO as u32), Some(Level::Info));
+        assert_eq!(Level::from_u32(4), None);
+        assert_eq!(Level::from_str("warning"), Some(Level::Warning));
+        assert_eq!(Level::from_str("loud"), None);
+    }
+}
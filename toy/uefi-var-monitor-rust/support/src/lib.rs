@@ -0,0 +1,61 @@
+// uefi-var-monitor-rust/support/src/lib.rs
+//
+// What the driver and uvmctl write their lines with, so that both follow the
+// same policy: writing a line never panics. A line that finds its writer
+// busy, or that its output drops, is lost, and the loss is counted.
+//
+//   level      the levels of records, and the verbosities the leveled macros
+//              are compiled in at, per module
+//   line       the line writer, over whatever output a binary gives it: the
+//              driver its serial port, uvmctl the console
+//   location   where a line was logged, for log-src-loc
+//   tag        the subsystem tags of the lines the driver writes about itself
+//
+// The macros, log! and the leveled ones, are in macros.rs. They write through
+// a logging module at the root of the crate using them (see there).
+//
+// The crate is no_std, as both of its users are; its tests run on the host
+// with std.
+
+#![cfg_attr(not(test), no_std)]
+
+#[macro_use]
+mod macros;
+
+pub mod level;
+pub mod line;
+pub mod location;
+pub mod tag;
+
+// The logging module the macros write through, for the tests.
+#[cfg(test)]
+mod logging {
+    use crate::level::Level;
+    use core::fmt;
+
+    pub const MODULES: &str = "macros::tests::quiet=off";
+    pub const LOCATIONS: bool = false;
+
+    std::thread_local! {
+        pub static WRITTEN: core::cell::RefCell<std::string::String> =
+            const { core::cell::RefCell::new(std::string::String::new()) };
+    }
+
+    pub fn enabled(level: Level) -> bool {
+        level <= Level::Info
+    }
+
+    pub fn write(args: fmt::Arguments) {
+        use core::fmt::Write;
+        WRITTEN.with(|written| {
+            let _ = writeln!(written.borrow_mut(), "{}", args);
+        });
+    }
+
+    /**
+     * @brief Returns the lines written by the calling test thread so far.
+     */
+    pub fn take() -> std::string::String {
+        WRITTEN.with(|written| written.take())
+    }
+}
@@ -0,0 +1,214 @@
+// uefi-var-monitor-rust/support/src/line.rs
+// Copyright © 2019 Intel Corporation
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Inspired by https://github.com/phil-opp/blog_os/blob/post-03/src/vga_buffer.rs
+// from Philipp Oppermann
+
+// A record is formatted into a line while the writer is held, and handed to
+// the output in one slice once complete: core::fmt writes a record in as many
+// fragments as it has arguments and literals, and each would otherwise take
+// the output and start it anew. A record longer than the line goes out in
+// slices of LINE_SIZE as it fills.
+//
+// Writing never panics. The writer may be held by an interrupted writer, or
+// by another CPU at OS runtime; a record finding it so is given up rather
+// than waited for. A record given up, or that the output dropped part of, is
+// counted as lost. The driver writes its serial port through one (see
+// serial.rs there), and uvmctl the console.
+
+use atomic_refcell::AtomicRefCell;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use r_efi::efi;
+
+pub const LINE_SIZE: usize = 256;
+
+// Writes bytes out, returning an error status if some were dropped.
+pub type Output = fn(&[u8]) -> efi::Status;
+
+struct Line {
+    bytes: [u8; LINE_SIZE],
+    length: usize,
+    // Whether the output dropped part of the record.
+    failed: bool,
+    output: Output,
+}
+
+impl Line {
+    const fn new(output: Output) -> Self {
+        Line {
+            bytes: [0; LINE_SIZE],
+            length: 0,
+            failed: false,
+            output,
+        }
+    }
+
+    fn flush(&mut self) {
+        let bytes = self.bytes.get(..self.length).unwrap_or(&[]);
+        if !bytes.is_empty() && (self.output)(bytes).is_error() {
+            self.failed = true;
+        }
+        self.length = 0;
+    }
+
+    // Without indexing: records are written on the paths that must not panic.
+    fn push(&mut self, byte: u8) {
+        if self.length >= LINE_SIZE {
+            self.flush();
+        }
+        if let Some(slot) = self.bytes.get_mut(self.length) {
+            *slot = byte;
+            self.length += 1;
+        }
+    }
+}
+
+impl fmt::Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
+
+pub struct LineWriter {
+    // Held while writing a record, so that records do not interleave.
+    line: AtomicRefCell<Line>,
+    failures: AtomicU64,
+}
+
+impl LineWriter {
+    pub const fn new(output: Output) -> Self {
+        LineWriter {
+            line: AtomicRefCell::new(Line::new(output)),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /**
+     * @brief Writes one record, counting it as lost if it could not be.
+     */
+    pub fn write_record(&self, args: fmt::Arguments) {
+        if self.write_line(args).is_err() {
+            self.record_failure();
+        }
+    }
+
+    /**
+     * @brief Writes `args` and a line break, in one slice if it fits
+     *        LINE_SIZE.
+     */
+    pub fn write_line(&self, args: fmt::Arguments) -> fmt::Result {
+        self.write_with(|line| fmt::Write::write_fmt(line, format_args!("{}\n", args)))
+    }
+
+    /**
+     * @brief Writes a record formatted already, and a line break, as
+     *        write_line() does but without core::fmt.
+     */
+    pub fn write_text(&self, text: &str) -> fmt::Result {
+        self.write_with(|line| {
+            for &byte in text.as_bytes().iter().chain(b"\n") {
+                line.push(byte);
+            }
+            Ok(())
+        })
+    }
+
+    /**
+     * @brief Counts a record that could not be written.
+     */
+    pub fn record_failure(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /**
+     * @brief Returns the number of records lost so far.
+     */
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /**
+     * @brief Zeroes the count of lost records.
+     */
+    pub fn reset_failures(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    fn write_with(&self, write: impl FnOnce(&mut Line) -> fmt::Result) -> fmt::Result {
+        let mut line = self.line.try_borrow_mut().map_err(|_| fmt::Error)?;
+        line.length = 0;
+        line.failed = false;
+        let result = write(&mut line);
+        line.flush();
+        if line.failed {
+            return Err(fmt::Error);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static WRITES: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+    fn output(bytes: &[u8]) -> efi::Status {
+        WRITES.lock().unwrap().push(bytes.to_vec());
+        efi::Status::SUCCESS
+    }
+
+    fn failing(_bytes: &[u8]) -> efi::Status {
+        efi::Status::DEVICE_ERROR
+    }
+
+    #[test]
+    fn records_go_out_in_one_slice_per_line() {
+        let writer = LineWriter::new(output);
+        writer.write_record(format_args!("G: {} {}", "Boot0001", 7));
+        let long = "x".repeat(LINE_SIZE + 8);
+        writer.write_text(&long).unwrap();
+        let writes = std::mem::take(&mut *WRITES.lock().unwrap());
+        assert_eq!(writes.len(), 3);
+        assert_eq!(writes[0], b"G: Boot0001 7\n");
+        assert_eq!(writes[1].len(), LINE_SIZE);
+        assert_eq!(writes[2].len(), 9);
+        assert_eq!(writer.failures(), 0);
+    }
+
+    #[test]
+    fn records_that_cannot_be_written_are_counted_rather_than_panicking() {
+        let writer = LineWriter::new(failing);
+        writer.write_record(format_args!("lost"));
+        assert!(writer.write_text("lost too").is_err());
+        writer.record_failure();
+        assert_eq!(writer.failures(), 2);
+
+        // Held by a writer it interrupted.
+        let busy = LineWriter::new(output);
+        let held = busy.line.borrow_mut();
+        busy.write_record(format_args!("given up"));
+        drop(held);
+        assert_eq!(busy.failures(), 1);
+        busy.reset_failures();
+        assert_eq!(busy.failures(), 0);
+    }
+}
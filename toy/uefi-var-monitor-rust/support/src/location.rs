@@ -0,0 +1,93 @@
+// uefi-var-monitor-rust/support/src/location.rs
+//
+// Where a line was logged, written as module::function:line without the
+// crate name; the module is that of the source file, so that src/report.rs
+// is "report", and functions in an impl come with their type. log_at! fills
+// it in with module_path!(), function!() and line!() (see macros.rs).
+
+use core::fmt;
+
+pub fn type_name_of<T>(_: &T) -> &'static str {
+    core::any::type_name::<T>()
+}
+
+pub struct Location {
+    pub module: &'static str,
+    pub function: &'static str,
+    pub line: u32,
+}
+
+// Without slicing by index, which could panic: log_at! is on the paths that
+// must not.
+fn strip_prefix<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    if text.starts_with(prefix) {
+        text.get(prefix.len()..)
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Past the crate and "::"; the crate root has no module of its own.
+        let module = self
+            .module
+            .bytes()
+            .position(|byte| byte == b':')
+            .and_then(|end| self.module.get(end + 2..))
+            .unwrap_or("main");
+        let function = strip_prefix(self.function, self.module)
+            .and_then(|function| strip_prefix(function, "::"))
+            .and_then(|function| {
+                if function.ends_with("::f") {
+                    function.get(..function.len() - 3)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(self.function);
+        write!(f, "{}::{}:{}", module, function, self.line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+
+    #[test]
+    fn locations_are_written_without_the_crate() {
+        let location = |module, function| {
+            format!(
+                "{}",
+                Location {
+                    module,
+                    function,
+                    line: 12,
+                }
+            )
+        };
+        assert_eq!(
+            location("uefi_var_monitor", "uefi_var_monitor::efi_main::f"),
+            "main::efi_main:12"
+        );
+        assert_eq!(
+            location(
+                "uefi_var_monitor::sink",
+                "uefi_var_monitor::sink::Sinks::write::f"
+            ),
+            "sink::Sinks::write:12"
+        );
+        assert_eq!(
+            location(
+                "uefi_var_monitor::report",
+                "uefi_var_monitor::report::handle_ready_to_boot::{{closure}}::f"
+            ),
+            "report::handle_ready_to_boot::{{closure}}:12"
+        );
+        assert_eq!(
+            location(module_path!(), function!()),
+            "location::tests::locations_are_written_without_the_crate:12"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+// uefi-var-monitor-rust/support/src/macros.rs
+//
+// The logging macros, apart from the outputs they end at. They come in three
+// kinds. error!, warn!, info!, debug! and trace! are for what the driver says
+// about itself (loading, events, failures), each compiled out below the
+// verbosity the module list gives its module (see level.rs) and written at
+// the level it maps to; log_at! is for those whose level is chosen at
+// runtime, or critical to be written whatever the level, and is never
+// compiled out. Each takes the subsystem tag of the line first (after the
+// level, for log_at!), written before the text (see tag.rs). With locations,
+// each line starts with where it was logged, as [module::function:line], e.g.
+//
+//   [report::handle_ready_to_boot:207] [boot] Boot report not written : 0x...
+//
+// log_record! and log_record_at! are for the records of accesses and alerts
+// (G:, S:, N:, D:, M:, I:, V:, ALERT:) and the pause markers, whose formats
+// tools parse and which stay as they are. Only the text of the location is
+// added to the binary, a module path and a function name per call site.
+//
+// log! writes a line whatever the level, for the tools that have none, such
+// as uvmctl.
+//
+// error! is written at the warning level, as warn! is: the levels are for
+// filtering, and critical is kept for alerts and the pause markers, which
+// must get through whatever the level. An error the driver recovers from is
+// not one of them.
+//
+// The macros write through the logging module at the root of the crate using
+// them, which holds what differs between binaries:
+//
+//   MODULES     the module list the leveled macros are compiled in by
+//   LOCATIONS   whether log_at! starts lines with their location
+//   enabled()   whether records of a level are written, asked before they
+//               are formatted
+//   write()     writes a record as one line; never panics, and drops what
+//               it cannot write (see line.rs)
+//
+// log! only needs write(). The module is named with crate rather than
+// $crate, so that it is the user's.
+
+#[macro_export]
+macro_rules! error {
+    ($tag:expr, $($arg:tt)*) => {
+        $crate::log_compiled!(Error, $crate::level::Level::Warning, $tag, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($tag:expr, $($arg:tt)*) => {
+        $crate::log_compiled!(Warn, $crate::level::Level::Warning, $tag, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($tag:expr, $($arg:tt)*) => {
+        $crate::log_compiled!(Info, $crate::level::Level::Info, $tag, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($tag:expr, $($arg:tt)*) => {
+        $crate::log_compiled!(Debug, $crate::level::Level::Trace, $tag, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($tag:expr, $($arg:tt)*) => {
+        $crate::log_compiled!(Trace, $crate::level::Level::Trace, $tag, $($arg)*)
+    };
+}
+
+// The constant is evaluated where the macro is expanded: a call its module
+// is not built with leaves no code and no string behind, in any build.
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! log_compiled {
+    ($verbosity:ident, $level:expr, $tag:expr, $($arg:tt)*) => {{
+        const COMPILED: bool = $crate::level::compiled(
+            crate::logging::MODULES,
+            module_path!(),
+            $crate::level::Verbosity::$verbosity,
+        );
+        if COMPILED {
+            $crate::log_at!($level, $tag, $($arg)*);
+        }
+    }};
+}
+
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! log_at {
+    ($level:expr, $tag:expr, $($arg:tt)*) => {{
+        if crate::logging::LOCATIONS {
+            $crate::log_record_at!(
+                $level,
+                "[{}] {} {}",
+                $crate::location::Location {
+                    module: module_path!(),
+                    function: $crate::function!(),
+                    line: line!(),
+                },
+                $crate::tag::Tag::text($tag),
+                format_args!($($arg)*)
+            );
+        } else {
+            $crate::log_record_at!(
+                $level,
+                "{} {}",
+                $crate::tag::Tag::text($tag),
+                format_args!($($arg)*)
+            );
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! log_record {
+    ($($arg:tt)*) => {
+        $crate::log_record_at!($crate::level::Level::Trace, $($arg)*)
+    };
+}
+
+// Logs a record of the given level, if records of that level are written.
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! log_record_at {
+    ($level:expr, $($arg:tt)*) => {{
+        if crate::logging::enabled($level) {
+            crate::logging::write(format_args!($($arg)*));
+        }
+    }};
+}
+
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        crate::logging::write(format_args!($($arg)*))
+    };
+}
+
+// The path of the function it is expanded in, from the type name of a
+// function nested in it, e.g. "uefi_var_monitor::report::handle_ready_to_boot::f".
+#[macro_export]
+macro_rules! function {
+    () => {{
+        fn f() {}
+        $crate::location::type_name_of(&f)
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::Level;
+    use crate::logging;
+    use crate::tag::Tag;
+
+    const TAG: Tag = Tag::new("[test]");
+
+    // Built with every leveled macro compiled out (see logging in lib.rs).
+    mod quiet {
+        pub fn say() {
+            warn!(super::TAG, "uvm-quiet-warn-line");
+        }
+    }
+
+    #[test]
+    fn lines_are_written_by_level_and_module() {
+        logging::take();
+        warn!(TAG, "warned {}", 1);
+        info!(TAG, "informed");
+        debug!(TAG, "not at this level");
+        log_at!(Level::Critical, TAG, "critical");
+        log_record!("G: record");
+        log!("whatever the level");
+        quiet::say();
+        assert_eq!(
+            logging::take(),
+            "[test] warned 1\n[test] informed\n[test] critical\nwhatever the level\n"
+        );
+    }
+}
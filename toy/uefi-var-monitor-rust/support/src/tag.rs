@@ -0,0 +1,21 @@
+// uefi-var-monitor-rust/support/src/tag.rs
+//
+// The tag of the subsystem a line is about, written before its text by
+// log_at! and the leveled macros. The tags themselves are the driver's, made
+// once in its tag.rs; the macros take a Tag rather than a string, so that a
+// line cannot be tagged with anything else.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tag(&'static str);
+
+impl Tag {
+    pub const fn new(text: &'static str) -> Self {
+        Tag(text)
+    }
+
+    // The text written, as a function the macros call, so that anything but
+    // a Tag passed to them fails to build.
+    pub const fn text(self) -> &'static str {
+        self.0
+    }
+}
@@ -0,0 +1,197 @@
+// uefi-var-monitor-rust/tests/firmware/hooks.rs
+//
+// The driver, built with the tests and loaded from memory: it hooks
+// GetVariable and SetVariable in the Runtime Services Table as it starts,
+// and puts the firmware's back as it is unloaded, unless another hook was
+// put on top of it meanwhile (see handle_unload in src/main.rs). The table
+// is checked for its CRC32 each time, as the OS will.
+
+use core::ptr;
+use r_efi::efi;
+use uefi_var_monitor::hook::HookSlot;
+use uefi_var_monitor::{crc32, GetVariableType};
+use uvm_interface::protocol::{Stats, StatsProtocol, HOOK_ACTIVE, UVM_STATS_PROTOCOL_GUID};
+
+static DRIVER: &[u8] = include_bytes!(env!("CARGO_BIN_EXE_uefi-var-monitor"));
+
+const GLOBAL_VARIABLE_GUID: efi::Guid = efi::Guid::from_fields(
+    0x8be4df61,
+    0x93ca,
+    0x11d2,
+    0xaa,
+    0x0d,
+    &[0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c],
+);
+
+// "PlatformLang", which OVMF sets at every boot.
+const PLATFORM_LANG: [u16; 13] = [
+    0x50, 0x6c, 0x61, 0x74, 0x66, 0x6f, 0x72, 0x6d, 0x4c, 0x61, 0x6e, 0x67, 0,
+];
+
+struct Driver(efi::Handle);
+
+impl Driver {
+    fn load() -> Self {
+        let boot_services = crate::boot_services();
+        let mut handle: efi::Handle = ptr::null_mut();
+        let efi_status = (boot_services.load_image)(
+            efi::Boolean::FALSE,
+            crate::image_handle(),
+            ptr::null_mut(),
+            DRIVER.as_ptr() as *mut core::ffi::c_void,
+            DRIVER.len(),
+            &mut handle,
+        );
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        let efi_status = (boot_services.start_image)(handle, ptr::null_mut(), ptr::null_mut());
+        assert_eq!(efi_status, efi::Status::SUCCESS);
+        Driver(handle)
+    }
+
+    fn unload(&self) -> efi::Status {
+        (crate::boot_services().unload_image)(self.0)
+    }
+}
+
+fn runtime_services() -> &'static mut efi::RuntimeServices {
+    unsafe { &mut *crate::system_table().runtime_services }
+}
+
+fn services() -> (usize, usize) {
+    let runtime_services = runtime_services();
+    (
+        runtime_services.get_variable as usize,
+        runtime_services.set_variable as usize,
+    )
+}
+
+/**
+ * @brief Returns whether the CRC32 stored in the header of the Runtime
+ *        Services Table is that of the table.
+ */
+fn crc32_is_valid() -> bool {
+    let hdr = &runtime_services().hdr;
+    let mut copy = [0u8; 256];
+    let table = unsafe {
+        core::slice::from_raw_parts(hdr as *const _ as *const u8, hdr.header_size as usize)
+    };
+    let copy = &mut copy[..table.len()];
+    copy.copy_from_slice(table);
+    // The crc32 field, after the signature, revision and header_size.
+    copy[16..20].fill(0);
+    crc32::crc32(copy) == hdr.crc32
+}
+
+fn read_platform_lang() -> efi::Status {
+    let mut name = PLATFORM_LANG;
+    let mut guid = GLOBAL_VARIABLE_GUID;
+    let mut data = [0u8; 64];
+    let mut data_size = data.len();
+    (runtime_services().get_variable)(
+        name.as_mut_ptr(),
+        &mut guid,
+        ptr::null_mut(),
+        &mut data_size,
+        data.as_mut_ptr() as *mut core::ffi::c_void,
+    )
+}
+
+fn stats() -> Option<Stats> {
+    let mut interface: *mut core::ffi::c_void = ptr::null_mut();
+    let efi_status = (crate::boot_services().locate_protocol)(
+        &UVM_STATS_PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        ptr::null_mut(),
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        return None;
+    }
+    let protocol = interface as *mut StatsProtocol;
+    let mut stats = Stats::default();
+    let efi_status = (unsafe { (*protocol).get_stats })(protocol, &mut stats);
+    assert_eq!(efi_status, efi::Status::SUCCESS);
+    Some(stats)
+}
+
+#[test_case]
+fn loading_hooks_the_table_and_unloading_restores_it() {
+    let firmware = services();
+    assert!(stats().is_none());
+
+    let driver = Driver::load();
+    let hooked = services();
+    assert_ne!(hooked.0, firmware.0);
+    assert_ne!(hooked.1, firmware.1);
+    assert!(crc32_is_valid());
+
+    let before = stats().unwrap();
+    assert_eq!(before.get_variable_hook, HOOK_ACTIVE as u32);
+    assert_eq!(before.set_variable_hook, HOOK_ACTIVE as u32);
+    assert_eq!(read_platform_lang(), efi::Status::SUCCESS);
+    assert_eq!(
+        stats().unwrap().get_variable_calls,
+        before.get_variable_calls + 1
+    );
+
+    assert_eq!(driver.unload(), efi::Status::SUCCESS);
+    assert_eq!(services(), firmware);
+    assert!(crc32_is_valid());
+    assert!(stats().is_none());
+    assert_eq!(read_platform_lang(), efi::Status::SUCCESS);
+}
+
+static ON_TOP: HookSlot<GetVariableType> = HookSlot::new();
+
+efiapi! {
+    // Another driver's hook, loaded after ours.
+    fn on_top(
+        variable_name: *mut r_efi::base::Char16,
+        vendor_guid: *mut r_efi::base::Guid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut core::ffi::c_void,
+    ) -> efi::Status {
+        ON_TOP.call(variable_name, vendor_guid, attributes, data_size, data)
+    }
+}
+
+/**
+ * @brief Puts `get_variable` in the table, as a driver hooking it would.
+ */
+fn replace_get_variable(get_variable: GetVariableType) {
+    let boot_services = crate::boot_services();
+    let tpl = (boot_services.raise_tpl)(efi::TPL_HIGH_LEVEL);
+    let runtime_services = runtime_services();
+    runtime_services.get_variable = get_variable;
+    runtime_services.hdr.crc32 = 0;
+    let mut crc32 = 0u32;
+    (boot_services.calculate_crc32)(
+        &mut runtime_services.hdr as *mut _ as *mut core::ffi::c_void,
+        runtime_services.hdr.header_size as usize,
+        &mut crc32,
+    );
+    runtime_services.hdr.crc32 = crc32;
+    (boot_services.restore_tpl)(tpl);
+}
+
+#[test_case]
+fn unloading_under_another_hook_is_refused() {
+    let firmware = services();
+    let driver = Driver::load();
+    let hook = runtime_services().get_variable;
+    ON_TOP.set(hook);
+    replace_get_variable(on_top);
+
+    // Unloading now would leave on_top calling into freed memory.
+    assert_eq!(driver.unload(), efi::Status::ACCESS_DENIED);
+    assert_eq!(
+        runtime_services().get_variable as usize,
+        on_top as GetVariableType as usize
+    );
+    assert_eq!(read_platform_lang(), efi::Status::SUCCESS);
+
+    replace_get_variable(hook);
+    assert_eq!(driver.unload(), efi::Status::SUCCESS);
+    assert_eq!(services(), firmware);
+    assert!(crc32_is_valid());
+}
@@ -0,0 +1,122 @@
+// uefi-var-monitor-rust/tests/firmware/main.rs
+//
+// Tests that only mean something in firmware: the port I/O of the serial
+// writer (serial.rs), and the hooks going into the Runtime Services Table
+// as the driver is loaded and out of it as it is unloaded (hooks.rs). They
+// are built into a UEFI application with a custom test framework, which
+// tools/ovmf-run.sh, the runner .cargo/config.toml names for the target,
+// runs in OVMF:
+//
+//   $ OVMF_CODE=OVMF_CODE.fd OVMF_VARS=OVMF_VARS.fd \
+//       cargo +nightly test --target x86_64-unknown-uefi --test firmware
+//
+// Tests are #[test_case] functions that panic on failure, as host tests do.
+// run() writes each name and result to debugcon, apart from the serial
+// output of the driver, and ends QEMU through isa-debug-exit with whether
+// they all passed; the first failure ends the run. The host unit tests need
+// std and stay on the host, hence --test firmware; on the host this crate is
+// empty.
+
+#![cfg(target_os = "uefi")]
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::run)]
+#![reexport_test_harness_main = "test_main"]
+
+// efiapi!, for the hooks of the tests.
+#[macro_use]
+extern crate uvm_interface;
+
+// The serial writer of the driver, as it is built into the driver.
+#[allow(dead_code)]
+#[path = "../../src/arch/mod.rs"]
+mod arch;
+mod hooks;
+mod serial;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicPtr, Ordering};
+use r_efi::efi;
+use x86_64::instructions::port::PortWriteOnly;
+
+// Bochs' debug console, which QEMU's -debugcon emulates. OVMF writes its own
+// debug output to 0x402, not here.
+const DEBUGCON: u16 = 0xe9;
+// QEMU's isa-debug-exit, which exits with (value << 1) | 1.
+const DEBUG_EXIT: u16 = 0xf4;
+const EXIT_SUCCESS: u32 = 0x10;
+const EXIT_FAILURE: u32 = 0x11;
+
+static IMAGE_HANDLE: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(core::ptr::null_mut());
+static SYSTEM_TABLE: AtomicPtr<efi::SystemTable> = AtomicPtr::new(core::ptr::null_mut());
+
+pub fn image_handle() -> efi::Handle {
+    IMAGE_HANDLE.load(Ordering::Acquire)
+}
+
+pub fn system_table() -> &'static mut efi::SystemTable {
+    unsafe { &mut *SYSTEM_TABLE.load(Ordering::Acquire) }
+}
+
+pub fn boot_services() -> &'static mut efi::BootServices {
+    unsafe { &mut *system_table().boot_services }
+}
+
+struct DebugCon;
+
+impl Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut port = PortWriteOnly::<u8>::new(DEBUGCON);
+        for &byte in s.as_bytes() {
+            unsafe { port.write(byte) };
+        }
+        Ok(())
+    }
+}
+
+fn exit(code: u32) -> ! {
+    unsafe { PortWriteOnly::<u32>::new(DEBUG_EXIT).write(code) };
+    // Without the device, nothing else ends the run; the runner times out.
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        let _ = write!(DebugCon, "test {} ... ", core::any::type_name::<T>());
+        self();
+        let _ = writeln!(DebugCon, "ok");
+    }
+}
+
+pub fn run(tests: &[&dyn Testable]) {
+    let _ = writeln!(DebugCon, "running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    let _ = writeln!(DebugCon, "test result: ok. {} passed", tests.len());
+    exit(EXIT_SUCCESS);
+}
+
+#[panic_handler]
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    let _ = writeln!(DebugCon, "FAILED\n{}", info);
+    exit(EXIT_FAILURE);
+}
+
+#[no_mangle]
+extern "efiapi" fn efi_main(
+    image_handle: efi::Handle,
+    system_table: *mut efi::SystemTable,
+) -> efi::Status {
+    IMAGE_HANDLE.store(image_handle, Ordering::Release);
+    SYSTEM_TABLE.store(system_table, Ordering::Release);
+    test_main();
+    efi::Status::SUCCESS
+}
@@ -0,0 +1,105 @@
+// uefi-var-monitor-rust/tests/firmware/serial.rs
+//
+// The serial writer against the UART QEMU emulates on COM1, put in loopback
+// mode so that what the writer sends comes back in its receive FIFO. The TPL
+// is raised to TPL_HIGH_LEVEL meanwhile: the console of OVMF polls the same
+// UART for input from a timer, and would take the bytes.
+
+use crate::arch::{Arch, Current};
+use r_efi::efi;
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+const COM1: u16 = 0x3f8;
+const COM1_FCR: u16 = COM1 + 2;
+const COM1_MCR: u16 = COM1 + 4;
+const COM1_LSR: u16 = COM1 + 5;
+// Enables the FIFOs and empties both.
+const FCR_RESET_FIFOS: u8 = 0x07;
+const MCR_LOOPBACK: u8 = 0x10;
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_OVERRUN: u8 = 0x02;
+const LSR_TRANSMITTER_EMPTY: u8 = 0x40;
+const FIFO_SIZE: usize = 16;
+// Polls of the line status before the UART is taken to be done.
+const POLL_LIMIT: usize = 100_000;
+
+struct Loopback {
+    mcr: u8,
+    tpl: efi::Tpl,
+}
+
+impl Loopback {
+    fn start() -> Self {
+        let tpl = (crate::boot_services().raise_tpl)(efi::TPL_HIGH_LEVEL);
+        let mut mcr = Port::<u8>::new(COM1_MCR);
+        let saved = unsafe { mcr.read() };
+        unsafe {
+            PortWriteOnly::<u8>::new(COM1_FCR).write(FCR_RESET_FIFOS);
+            mcr.write(saved | MCR_LOOPBACK);
+        }
+        let mut loopback = Loopback { mcr: saved, tpl };
+        // Whatever was received before the FIFOs were emptied.
+        while loopback.receive().is_some() {}
+        loopback.line_status();
+        loopback
+    }
+
+    fn line_status(&mut self) -> u8 {
+        unsafe { PortReadOnly::<u8>::new(COM1_LSR).read() }
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        for _ in 0..POLL_LIMIT {
+            if self.line_status() & LSR_DATA_READY != 0 {
+                return Some(unsafe { PortReadOnly::<u8>::new(COM1).read() });
+            }
+            if self.line_status() & LSR_TRANSMITTER_EMPTY != 0 {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+
+    /**
+     * @brief Returns the bytes received so far, up to the size of `buffer`.
+     */
+    fn received<'a>(&mut self, buffer: &'a mut [u8]) -> &'a [u8] {
+        let mut length = 0;
+        for slot in buffer.iter_mut() {
+            match self.receive() {
+                Some(byte) => *slot = byte,
+                None => break,
+            }
+            length += 1;
+        }
+        &buffer[..length]
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        unsafe { Port::<u8>::new(COM1_MCR).write(self.mcr) };
+        (crate::boot_services().restore_tpl)(self.tpl);
+    }
+}
+
+#[test_case]
+fn written_bytes_reach_the_uart_in_order() {
+    let mut loopback = Loopback::start();
+    assert_eq!(Current::write_bytes(b"UVM serial"), efi::Status::SUCCESS);
+    let mut buffer = [0u8; FIFO_SIZE];
+    assert_eq!(loopback.received(&mut buffer), b"UVM serial");
+}
+
+#[test_case]
+fn writes_longer_than_the_fifo_do_not_stall() {
+    let mut loopback = Loopback::start();
+    // The transmitter keeps emptying while nobody reads: the bytes past the
+    // receive FIFO are lost as an overrun, not waited on.
+    let record = [b'x'; 4 * FIFO_SIZE];
+    assert_eq!(Current::write_bytes(&record), efi::Status::SUCCESS);
+    assert_ne!(loopback.line_status() & LSR_OVERRUN, 0);
+    let mut buffer = [0u8; 4 * FIFO_SIZE];
+    assert_eq!(loopback.received(&mut buffer), &record[..FIFO_SIZE]);
+}
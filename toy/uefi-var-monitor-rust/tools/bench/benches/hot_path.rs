@@ -0,0 +1,155 @@
+// uefi-var-monitor-rust/tools/bench/benches/hot_path.rs
+//
+// The pieces of work the hooks do on each call, timed on the host with
+// criterion from the driver's library and the interface crate:
+//
+//   name      decoding a UTF-16 variable name (record::convert_name)
+//   guid      rendering a vendor GUID, by hand and through core::fmt
+//   filter    the trace filter's decision, with no patterns and with a full
+//             table that does and does not match
+//   record    building the G: and S: records
+//   crc32     of a short variable and of a page of data
+//   sha256    the same
+//
+//   $ cargo bench -p uvm-bench
+//   $ cargo bench -p uvm-bench -- filter
+//
+// These time the code, not the firmware: what a call through the hook costs
+// in place is measured by the driver (see src/overhead.rs and
+// src/benchmark.rs, uvmctl bench). Nothing here fails on a time; criterion
+// reports the change since the last run it keeps under target/criterion.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use r_efi::efi;
+use std::fmt::Write;
+use std::hint::black_box;
+use uefi_var_monitor::classify::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID};
+use uefi_var_monitor::crc32;
+use uefi_var_monitor::filter::{self, MAX_FILTERED};
+use uefi_var_monitor::pattern::PatternTable;
+use uefi_var_monitor::record::{self, GuidFmt, RecordLine, NAME_LENGTH};
+use uvm_interface::hex;
+use uvm_interface::sha256;
+
+fn utf16(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(Some(0)).collect()
+}
+
+fn name(c: &mut Criterion) {
+    let mut group = c.benchmark_group("name");
+    let longest = "A".repeat(NAME_LENGTH);
+    for name in ["PlatformLang", longest.as_str()] {
+        let characters = utf16(name);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(characters.len() - 1),
+            &characters,
+            |b, characters| {
+                let mut buffer = [0u8; NAME_LENGTH];
+                b.iter(|| record::convert_name(black_box(characters), &mut buffer).len())
+            },
+        );
+    }
+    group.finish();
+}
+
+fn guid(c: &mut Criterion) {
+    let mut group = c.benchmark_group("guid");
+    group.bench_function("text", |b| {
+        b.iter(|| hex::guid_text(black_box(&GLOBAL_VARIABLE_GUID)))
+    });
+    group.bench_function("fmt", |b| {
+        b.iter(|| {
+            let mut line = RecordLine::new();
+            let _ = write!(line, "{}", GuidFmt(black_box(&GLOBAL_VARIABLE_GUID)));
+            line
+        })
+    });
+    group.finish();
+}
+
+fn filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter");
+    let empty = PatternTable::<MAX_FILTERED>::new();
+    group.bench_function("empty", |b| {
+        b.iter(|| filter::traces(&empty, black_box("PlatformLang"), &GLOBAL_VARIABLE_GUID))
+    });
+    // A full table, with the pattern that matches last.
+    let mut full = PatternTable::<MAX_FILTERED>::new();
+    for index in 0..MAX_FILTERED - 1 {
+        let pattern = format!("8be4df61-93ca-11d2-aa0d-00e098032b8c:Unused{:02}", index);
+        assert_eq!(full.add_list(&pattern), (1, 0));
+    }
+    assert_eq!(
+        full.add_list("d719b2cb-3d3a-4596-a3bc-dad00e67656f:db*"),
+        (1, 0)
+    );
+    let calls: [(&str, &str, &efi::Guid); 2] = [
+        ("hit", "dbx", &IMAGE_SECURITY_DATABASE_GUID),
+        ("miss", "PlatformLang", &GLOBAL_VARIABLE_GUID),
+    ];
+    for (label, name, guid) in calls {
+        group.bench_function(label, |b| {
+            b.iter(|| filter::traces(&full, black_box(name), black_box(guid)))
+        });
+    }
+    group.finish();
+}
+
+fn record(c: &mut Criterion) {
+    let mut group = c.benchmark_group("record");
+    let guid = hex::guid_text(&GLOBAL_VARIABLE_GUID);
+    let guid = std::str::from_utf8(&guid).unwrap();
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            record::get_record(
+                black_box(guid),
+                Some(0x40),
+                Some(0x10),
+                black_box("PlatformLang"),
+                efi::Status::SUCCESS,
+            )
+        })
+    });
+    group.bench_function("set", |b| {
+        b.iter(|| {
+            record::set_record(
+                black_box(guid),
+                0x7,
+                0x10,
+                black_box("PlatformLang"),
+                efi::Status::WRITE_PROTECTED,
+            )
+        })
+    });
+    group.finish();
+}
+
+// A variable of a few bytes, and a page of data as a signature list may be.
+const DATA_SIZES: [usize; 2] = [16, 4096];
+
+fn crc32(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crc32");
+    for size in DATA_SIZES {
+        let data: Vec<u8> = (0..size).map(|index| index as u8).collect();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| crc32::crc32(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+fn sha256(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sha256");
+    for size in DATA_SIZES {
+        let data: Vec<u8> = (0..size).map(|index| index as u8).collect();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter(|| sha256::sha256(black_box(data)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, name, guid, filter, record, crc32, sha256);
+criterion_main!(benches);
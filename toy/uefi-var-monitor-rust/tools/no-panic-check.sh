@@ -0,0 +1,36 @@
+#!/bin/sh
+# Check that no panic is reachable in the driver (see the panic handler in
+# src/main.rs). Builds the driver in release for the UEFI target in each
+# profile, which only links if the optimizer removed every call to the panic
+# handler, then runs the host check of the hook paths, which relies on the
+# same optimizations.
+#
+#   $ tools/no-panic-check.sh
+set -eu
+
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+cd "$ROOT"
+
+check() {
+  echo "== $1"
+  if ! cargo build --release --target x86_64-unknown-uefi $1 2>build.log; then
+    cat build.log >&2
+    if grep -q uvm_panic_is_reachable build.log; then
+      echo "FAIL: a panic is reachable with $1; build with --emit=asm and look" >&2
+      echo "for the callers of rust_begin_unwind" >&2
+    fi
+    rm -f build.log
+    exit 1
+  fi
+  rm -f build.log
+}
+
+check ""
+for profile in forensics production minimal; do
+  check "--no-default-features --features profile-$profile"
+done
+
+echo "== hook paths"
+cargo test --release runtime_paths_cannot_panic
+
+echo PASS
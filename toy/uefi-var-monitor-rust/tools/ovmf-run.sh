@@ -0,0 +1,37 @@
+#!/bin/sh
+# Runs a UEFI application in OVMF: the runner cargo is given for the UEFI
+# target in .cargo/config.toml, so that the in-firmware tests run with
+#
+#   $ OVMF_CODE=OVMF_CODE.fd OVMF_VARS=OVMF_VARS.fd \
+#       cargo +nightly test --target x86_64-unknown-uefi --test firmware
+#
+# The application is started from startup.nsh. What it writes to debugcon
+# (port 0xe9) is printed here, and it ends the run through isa-debug-exit
+# (port 0xf4): writing 0x10 has QEMU exit with 33, for success; anything
+# else, or QEMU timing out or the shell resetting, is a failure. Arguments
+# after the application, such as test names, are ignored.
+set -eu
+
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+
+mkdir -p "$WORK/esp"
+cp "$1" "$WORK/esp/test.efi"
+cat > "$WORK/esp/startup.nsh" <<'NSH'
+fs0:
+test.efi
+reset -s
+NSH
+
+. "$ROOT/tools/ovmf.sh"
+status=0
+ovmf_boot "$WORK" \
+  -debugcon stdio \
+  -device isa-debug-exit,iobase=0xf4,iosize=0x04 || status=$?
+
+if [ "$status" -ne 33 ]; then
+  echo "FAIL: QEMU exited with $status" >&2
+  tr -d '\r' < "$WORK/serial.log" | tail -n 40 >&2
+  exit 1
+fi
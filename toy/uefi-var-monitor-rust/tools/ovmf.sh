@@ -0,0 +1,24 @@
+# Sourced by the scripts that run the driver in OVMF under QEMU
+# (tools/ovmf-run.sh, tools/uvmctl/ovmf-test.sh). OVMF_CODE and OVMF_VARS
+# name the firmware images.
+#
+# ovmf_boot <work> [QEMU arguments]
+#   Boots OVMF with a copy of OVMF_VARS, <work>/esp as a FAT drive and serial
+#   output in <work>/serial.log, for at most OVMF_TIMEOUT seconds (180), and
+#   returns QEMU's exit status.
+
+ovmf_boot() {
+  work=$1
+  shift
+  cp "$OVMF_VARS" "$work/vars.fd"
+  timeout "${OVMF_TIMEOUT:-180}" qemu-system-x86_64 \
+    -nodefaults \
+    -machine q35 \
+    -m 256M \
+    -display none \
+    -drive if=pflash,format=raw,readonly=on,file="$OVMF_CODE" \
+    -drive if=pflash,format=raw,file="$work/vars.fd" \
+    -drive format=raw,file=fat:rw:"$work/esp" \
+    -serial file:"$work/serial.log" \
+    "$@"
+}
@@ -0,0 +1,38 @@
+#!/bin/sh
+# Build test for the profiles (see src/profile.rs). Builds the driver for the
+# UEFI target and runs the host tests in each profile and with no-log, then
+# checks that conflicting selections are refused at compile time.
+#
+#   $ tools/profiles-test.sh
+set -eu
+
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+cd "$ROOT"
+
+for profile in forensics production minimal; do
+  echo "== profile-$profile"
+  cargo build --target x86_64-unknown-uefi --no-default-features --features "profile-$profile"
+  cargo test --no-default-features --features "profile-$profile"
+done
+
+echo "== no-log"
+cargo build --target x86_64-unknown-uefi --no-default-features --features no-log
+cargo test --no-default-features --features no-log
+
+refused() {
+  echo "== $1 (refused)"
+  if cargo check --target x86_64-unknown-uefi $1 >/dev/null 2>&1; then
+    echo "FAIL: $1 was built" >&2
+    exit 1
+  fi
+}
+
+refused "--no-default-features --features profile-forensics,profile-production"
+refused "--no-default-features --features profile-production,profile-minimal"
+# The default features include the serial sink.
+refused "--features profile-minimal"
+refused "--no-default-features --features profile-minimal,log-ring"
+refused "--features no-log"
+refused "--no-default-features --features no-log,log-net"
+
+echo PASS
@@ -0,0 +1,23 @@
+#!/bin/sh
+# Size of the driver image in each profile (see src/profile.rs), and with
+# no-log, built for the UEFI target in release. In profile-minimal nothing
+# references the log sinks, so the linker leaves them out; with no-log
+# neither the records nor their strings are built at all.
+#
+#   $ tools/size-report.sh
+set -eu
+
+ROOT=$(cd "$(dirname "$0")/.." && pwd)
+cd "$ROOT"
+
+IMAGE=target/x86_64-unknown-uefi/release/uefi-var-monitor.efi
+
+for profile in forensics production minimal; do
+  cargo build --quiet --release --target x86_64-unknown-uefi \
+    --no-default-features --features "profile-$profile"
+  printf '%-12s %8d bytes\n' "$profile" "$(wc -c <"$IMAGE")"
+done
+
+cargo build --quiet --release --target x86_64-unknown-uefi \
+  --no-default-features --features no-log
+printf '%-12s %8d bytes\n' "no-log" "$(wc -c <"$IMAGE")"
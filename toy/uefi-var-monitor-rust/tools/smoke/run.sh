@@ -0,0 +1,37 @@
+#!/bin/sh
+# End-to-end smoke test. Boots OVMF with the driver and uvmctl on a FAT
+# drive; startup.nsh loads the driver at level trace, with no latency budget,
+# reads SecureBoot, writes, reads back and deletes a test variable, signals
+# ReadyToBoot and resets. uvmlog expect then checks the serial output against
+# expected.txt: the records of those variables, in order, between the
+# markers the script echoes, and the counts of the ReadyToBoot summary.
+#
+#   $ OVMF_CODE=/usr/share/OVMF/OVMF_CODE.fd OVMF_VARS=/usr/share/OVMF/OVMF_VARS.fd \
+#       tools/smoke/run.sh
+#
+# The serial log is kept as smoke.log in the current directory.
+set -eu
+
+ROOT=$(cd "$(dirname "$0")/../.." && pwd)
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+
+(cd "$ROOT" && cargo build --target x86_64-unknown-uefi)
+(cd "$ROOT/tools/uvmctl" && cargo build --target x86_64-unknown-uefi)
+(cd "$ROOT" && cargo build -p uvmlog)
+
+mkdir -p "$WORK/esp"
+cp "$ROOT/target/x86_64-unknown-uefi/debug/uefi-var-monitor.efi" "$WORK/esp/"
+cp "$ROOT/tools/uvmctl/target/x86_64-unknown-uefi/debug/uvmctl.efi" "$WORK/esp/"
+cp "$ROOT/tools/smoke/startup.nsh" "$WORK/esp/"
+
+. "$ROOT/tools/ovmf.sh"
+ovmf_boot "$WORK" || true
+cp "$WORK/serial.log" smoke.log
+
+if "$ROOT/target/debug/uvmlog" expect smoke.log "$ROOT/tools/smoke/expected.txt"; then
+  echo PASS
+else
+  echo "FAIL: see smoke.log" >&2
+  exit 1
+fi
@@ -0,0 +1,81 @@
+#!/bin/sh
+# Acceptance test for uvmctl. Boots OVMF with the driver and uvmctl on a FAT
+# drive, loads the driver and runs every uvmctl command from startup.nsh,
+# then checks what was printed on the console (which OVMF mirrors to serial)
+# and the dump that was written.
+#
+#   $ OVMF_CODE=/usr/share/OVMF/OVMF_CODE.fd OVMF_VARS=/usr/share/OVMF/OVMF_VARS.fd \
+#       tools/uvmctl/ovmf-test.sh
+#
+# The driver is built with ring-dump, so that every command has something to
+# work on.
+set -eu
+
+ROOT=$(cd "$(dirname "$0")/../.." && pwd)
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+
+(cd "$ROOT" && cargo build --target x86_64-unknown-uefi --features ring-dump)
+(cd "$ROOT/tools/uvmctl" && cargo build --target x86_64-unknown-uefi)
+
+mkdir -p "$WORK/esp"
+cp "$ROOT/target/x86_64-unknown-uefi/debug/uefi-var-monitor.efi" "$WORK/esp/"
+cp "$ROOT/tools/uvmctl/target/x86_64-unknown-uefi/debug/uvmctl.efi" "$WORK/esp/"
+cat > "$WORK/esp/startup.nsh" <<'NSH'
+fs0:
+load uefi-var-monitor.efi
+uvmctl.efi status
+uvmctl.efi level warning
+uvmctl.efi dump ring.bin
+uvmctl.efi stats snapshot
+uvmctl.efi stats
+uvmctl.efi stats diff 1
+uvmctl.efi level trace
+uvmctl.efi selftest
+uvmctl.efi bench 100
+uvmctl.efi history
+uvmctl.efi check
+uvmctl.efi ready-to-boot
+reset -s
+NSH
+
+. "$ROOT/tools/ovmf.sh"
+ovmf_boot "$WORK" || true
+
+fail() {
+  echo "FAIL: $1" >&2
+  tr -d '\r' < "$WORK/serial.log" | tail -n 40 >&2
+  exit 1
+}
+
+expect() {
+  grep -q -- "$1" "$WORK/serial.log" || fail "no '$1' in the output"
+}
+
+expect "Monitor revision 0x20006, statistics revision 0x10000"
+expect "Hooks: GetVariable=active SetVariable=active"
+expect "Calls: GetVariable="
+expect "Lost: serial="
+expect "Top variables:"
+expect "Config: level=3"
+expect "set_level: 0x0 ok"
+expect "Wrote [0-9]* records"
+expect "Snapshot #1"
+expect "Statistics 1.0:"
+expect "Statistics 1.0 since snapshot #1:"
+expect "Self-test: pass"
+expect "Benchmark: 100 GetVariable calls"
+expect "Write history: "
+expect "get_statistics(0): 0x8000000000000005 ok"
+expect "read_history(null): 0x8000000000000002 ok"
+expect "benchmark(0): 0x8000000000000002 ok"
+expect "dump: 0x0 ok"
+expect "set_level(4): 0x8000000000000002 ok"
+expect "Calls at ReadyToBoot: GetVariable="
+expect "ReadyToBoot signalled: 0x0"
+if grep -q UNEXPECTED "$WORK/serial.log"; then
+  fail "an entry returned an unexpected status"
+fi
+# A dump starts with the ring buffer header, whose magic is "UVML".
+head -c 4 "$WORK/esp/ring.bin" | grep -q UVML || fail "ring.bin has no ring buffer header"
+echo PASS
@@ -0,0 +1,1252 @@
+// uefi-var-monitor-rust/tools/uvmctl/src/main.rs
+//
+// UEFI shell application querying and driving a loaded monitor through its
+// control and statistics protocols:
+//
+//   uvmctl [status]       hook states, counters, the most accessed
+//                         variables, records lost and the configuration
+//   uvmctl level <level>  set the level of the records written: critical,
+//                         warning, info, trace, or its number
+//   uvmctl dump <file>    write the ring buffer to <file>, on the volume
+//                         uvmctl was loaded from
+//   uvmctl save           keep the current configuration for the next
+//                         boots, in the UvmConfig variable
+//   uvmctl stats [reset]  calls by outcome, data size and phase, and
+//                         records skipped while paused; or zero them, if
+//                         the driver was built with UVM_STATS_RESET=allow
+//   uvmctl stats snapshot keep a copy of them in the monitor, and print
+//                         its ID
+//   uvmctl stats diff <id>
+//                         what was counted since the snapshot <id>
+//   uvmctl selftest       check that the hooks are in the table and see
+//                         calls, and that records reach the sinks
+//   uvmctl history        the last writes to the variables with a write
+//                         history, oldest first
+//   uvmctl bench [calls]  time GetVariable calls through the hook and
+//                         around it, 1000 of each unless given
+//   uvmctl check          go through every control entry once, checking
+//                         that invalid input is refused
+//   uvmctl ready-to-boot  signal the ReadyToBoot event group, as the boot
+//                         manager does before starting a boot option
+//
+// e.g.
+//
+//   Shell> fs0:\uvmctl.efi
+//   Monitor revision 0x20006, statistics revision 0x10000
+//   Hooks: GetVariable=active SetVariable=active GetNextVariableName=- ...
+//   ...
+//
+// A dump holds the ring buffer header, then the records still held, oldest
+// first, as laid out in interface/src/ring.rs, for tools/uvmlog to decode on
+// the host; an existing file is replaced. The header ends with the build of
+// the driver, unless the driver is too old to give it.
+// e.g. to count the variable accesses an application makes:
+//
+//   Shell> fs0:\uvmctl.efi stats snapshot
+//   Snapshot #1
+//   Shell> fs0:\app.efi
+//   Shell> fs0:\uvmctl.efi stats diff 1
+//
+// bench reports what it measured and never fails on it: times under
+// emulation vary too much from run to run. It only notes an added time above
+// BENCHMARK_NOTICE_NS.
+//
+// ready-to-boot has every ReadyToBoot notification run, the firmware's as
+// well as the monitor's: the boot report is written and the summaries are
+// logged, where a test can find them without leaving the shell (see
+// tools/smoke). The boot manager signals the group again before each boot
+// option it starts.
+//
+// check puts the level and pause state back as they were found, and leaves
+// the trace filter empty, tracing every variable. It does not save, which
+// would replace the configuration kept for the next boots.
+//
+// The protocol and ring buffer definitions come from the interface crate the
+// driver is built against too, and log!, which writes each line to ConOut,
+// from the support crate the driver logs through.
+
+#![no_main]
+#![no_std]
+
+#[macro_use]
+extern crate uvm_support;
+
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use r_efi::efi;
+use r_efi::protocols::{file, loaded_image, simple_file_system, simple_text_output};
+use uvm_interface::protocol::{
+    BenchmarkResult, CallCounts, ControlConfig, HistoryEntry, Overhead, Protocol, SelfTestResult,
+    Statistics, Stats, StatsProtocol, StorageCounts, TopEntry, HISTORY_FLAG_GAP,
+    HISTORY_FLAG_INSPECTED, HISTORY_FLAG_RUNTIME, HOOK_ACTIVE, HOOK_PASS_THROUGH, HOOK_UNUSABLE,
+    LEVEL_CRITICAL, LEVEL_INFO, LEVEL_TRACE, LEVEL_WARNING, MAX_BENCHMARK_CALLS, NOT_BUILT,
+    NOT_COUNTED, OUTCOMES, PHASE_BOOT_SERVICES, PHASE_RUNTIME, SELF_TEST_CALL_OBSERVED,
+    SELF_TEST_GET_VARIABLE_SLOT, SELF_TEST_RING, SELF_TEST_SERIAL, SELF_TEST_SET_VARIABLE_SLOT,
+    SIZE_BUCKETS, STORAGES, UVM_PROTOCOL_GUID, UVM_PROTOCOL_REVISION,
+    UVM_PROTOCOL_REVISION_BENCHMARK, UVM_PROTOCOL_REVISION_HISTORY, UVM_PROTOCOL_REVISION_SAVE,
+    UVM_PROTOCOL_REVISION_SELF_TEST, UVM_PROTOCOL_REVISION_SNAPSHOT,
+    UVM_PROTOCOL_REVISION_STATISTICS, UVM_STATS_PROTOCOL_GUID, UVM_STATS_PROTOCOL_REVISION_BUILD,
+};
+use uvm_interface::ring::{RingHeader, RingRecord, RING_MINOR};
+use uvm_support::line::LineWriter;
+
+// Not a valid level.
+const LEVEL_UNKNOWN: u32 = 4;
+
+const LEVELS: [(&str, u8); 4] = [
+    ("critical", LEVEL_CRITICAL),
+    ("warning", LEVEL_WARNING),
+    ("info", LEVEL_INFO),
+    ("trace", LEVEL_TRACE),
+];
+
+// Most accessed variables shown by status.
+const TOP_COUNT: usize = 10;
+
+// Longest command line kept, in characters.
+const MAX_COMMAND_LINE: usize = 256;
+// Longest file name, in characters without the terminator.
+const MAX_FILE_NAME: usize = 128;
+
+// Largest statistics copied, leaving room for later minor versions.
+const MAX_STATISTICS_SIZE: usize = 4096;
+
+// Writes read by history: every one the driver keeps, 8 histories of at
+// most 16 writes.
+const HISTORY_COUNT: usize = 128;
+
+// GetVariable calls bench makes each way by default.
+const BENCHMARK_CALLS: u32 = 1000;
+// Time added per call above which bench says so. The hook adds well under a
+// microsecond on the host (see suppressed_read_overhead in the driver's
+// main.rs); writing a record to serial costs far more.
+const BENCHMARK_NOTICE_NS: u64 = 10_000;
+
+// Names of the outcomes, indexed by OUTCOME_*.
+// Indexed by STORAGE_*.
+const STORAGE_NAMES: [&str; STORAGES] = ["unknown", "volatile", "non-volatile"];
+
+const OUTCOME_NAMES: [&str; OUTCOMES] = [
+    "success",
+    "not-found",
+    "buffer-too-small",
+    "invalid-parameter",
+    "write-protected",
+    "security-violation",
+    "out-of-resources",
+    "device-error",
+    "other",
+];
+
+// The checks of the self-test, by bit.
+const SELF_TEST_CHECKS: [(u32, &str); 5] = [
+    (SELF_TEST_GET_VARIABLE_SLOT, "GetVariable hook in the table"),
+    (SELF_TEST_SET_VARIABLE_SLOT, "SetVariable hook in the table"),
+    (SELF_TEST_CALL_OBSERVED, "GetVariable call seen by the hook"),
+    (SELF_TEST_SERIAL, "record written to serial"),
+    (SELF_TEST_RING, "record stored in the ring buffer"),
+];
+
+// EFI_FILE_MODE_CREATE; r-efi 3 defines file::MODE_CREATE as 0.
+const FILE_MODE_CREATE: u64 = 0x8000000000000000;
+
+const EXAMPLE_FILTER: &[u8] = b"8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*";
+const MALFORMED_FILTER: &[u8] = b"8be4df61-93ca-11d2:Boot*";
+
+// A setting, or "-" if its feature is not built.
+struct Setting(u32);
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            NOT_BUILT => f.write_str("-"),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+// A counter, or "-" if its feature is not built.
+struct Counter(u64);
+
+impl fmt::Display for Counter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            NOT_COUNTED => f.write_str("-"),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+// A hook state by name, or "-" if the hook is not built.
+struct HookState(u32);
+
+impl fmt::Display for HookState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == NOT_BUILT {
+            return f.write_str("-");
+        }
+        match self.0 {
+            state if state == u32::from(HOOK_ACTIVE) => f.write_str("active"),
+            state if state == u32::from(HOOK_PASS_THROUGH) => f.write_str("pass-through"),
+            state if state == u32::from(HOOK_UNUSABLE) => f.write_str("unusable"),
+            state => write!(f, "{}", state),
+        }
+    }
+}
+
+struct GuidFmt<'a>(&'a efi::Guid);
+
+impl fmt::Display for GuidFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low, node) = self.0.as_fields();
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low
+        )?;
+        for byte in node.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+struct ConfigFmt<'a>(&'a ControlConfig);
+
+impl fmt::Display for ConfigFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let config = self.0;
+        write!(
+            f,
+            "level={} paused={} filter={} ring-overflow={} integrity={} reinstalls={} \
+             runtime-data={} pcr={} size-factor={} size-limit={} rate-limit={} \
+             rate-sustain={} lock-absent={}",
+            config.level,
+            config.paused,
+            config.filter_entries,
+            Setting(config.ring_overflow_policy),
+            config.hook_integrity_policy,
+            config.max_reinstalls,
+            config.runtime_data_access,
+            Setting(config.tpm_pcr),
+            config.size_factor,
+            config.size_limit,
+            config.rate_limit,
+            config.rate_sustain,
+            Setting(config.lock_absent_policy),
+        )
+    }
+}
+
+struct CallCountsFmt<'a>(&'a CallCounts);
+
+impl fmt::Display for CallCountsFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = self.0;
+        write!(
+            f,
+            "  boot-services={} runtime={}\n ",
+            counts.by_phase[PHASE_BOOT_SERVICES], counts.by_phase[PHASE_RUNTIME]
+        )?;
+        for (name, count) in OUTCOME_NAMES.iter().zip(counts.by_status.iter()) {
+            if *count != 0 {
+                write!(f, " {}={}", name, count)?;
+            }
+        }
+        f.write_str("\n  sizes:")?;
+        for (bucket, count) in counts.by_size.iter().enumerate() {
+            match (bucket, *count) {
+                (_, 0) => {}
+                (0, count) => write!(f, " 0={}", count)?,
+                (bucket, count) if bucket == SIZE_BUCKETS - 1 => {
+                    write!(f, " {}+={}", 1u64 << (bucket - 1), count)?
+                }
+                (bucket, count) => write!(
+                    f,
+                    " {}-{}={}",
+                    1u64 << (bucket - 1),
+                    (1u64 << bucket) - 1,
+                    count
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct StorageCountsFmt<'a>(&'a [StorageCounts; STORAGES]);
+
+impl fmt::Display for StorageCountsFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, counts) in STORAGE_NAMES.iter().zip(self.0.iter()) {
+            write!(
+                f,
+                "\n  {}: reads={} writes={} bytes-read={} bytes-written={}",
+                name, counts.reads, counts.writes, counts.bytes_read, counts.bytes_written
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct OverheadFmt<'a>(&'a Overhead);
+
+impl fmt::Display for OverheadFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let overhead = self.0;
+        if overhead.calls == NOT_COUNTED {
+            return f.write_str("not measured");
+        }
+        match (
+            overhead.microseconds(overhead.total_ticks),
+            overhead.microseconds(overhead.max_ticks),
+        ) {
+            (Some(total), Some(max)) => write!(
+                f,
+                "{} us total across {} calls, worst case {} us",
+                total, overhead.calls, max
+            ),
+            _ => write!(
+                f,
+                "{} ticks total across {} calls, worst case {} ticks",
+                overhead.total_ticks, overhead.calls, overhead.max_ticks
+            ),
+        }
+    }
+}
+
+struct HistoryEntryFmt<'a>(&'a HistoryEntry);
+
+impl fmt::Display for HistoryEntryFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entry = self.0;
+        write!(
+            f,
+            "#{} {}:{} attributes={:08x} size={:#x} status={:#x} caller={:#x}",
+            entry.sequence,
+            GuidFmt(&entry.guid),
+            entry.name(),
+            entry.attributes,
+            entry.size,
+            entry.status,
+            entry.caller_page
+        )?;
+        if entry.flags & HISTORY_FLAG_INSPECTED != 0 {
+            write!(f, " crc32={:08x} data=", entry.crc32)?;
+            for byte in entry.data() {
+                write!(f, "{:02x}", byte)?;
+            }
+        }
+        if entry.flags & HISTORY_FLAG_RUNTIME != 0 {
+            f.write_str(" runtime")?;
+        }
+        if entry.flags & HISTORY_FLAG_GAP != 0 {
+            f.write_str(" after-gap")?;
+        }
+        Ok(())
+    }
+}
+
+// ConOut, as efi_main was given it.
+static CON_OUT: AtomicPtr<simple_text_output::Protocol> = AtomicPtr::new(core::ptr::null_mut());
+
+// Lines are written to ConOut through the line writer the driver writes its
+// serial port through (see support/src/line.rs), by log!: a line the console
+// cannot take is dropped rather than panicking.
+static CONSOLE: LineWriter = LineWriter::new(write_console);
+
+// Where log! writes (see support/src/macros.rs).
+mod logging {
+    use core::fmt;
+
+    pub fn write(args: fmt::Arguments) {
+        super::CONSOLE.write_record(args);
+    }
+}
+
+/**
+ * @brief Writes UTF-8 to ConOut, with "\n" written as "\r\n". What is not
+ *        UTF-8, such as a character cut at the end of a slice of a long
+ *        line, is written as '?'.
+ */
+fn write_console(bytes: &[u8]) -> efi::Status {
+    let con_out = CON_OUT.load(Ordering::Relaxed);
+    if con_out.is_null() {
+        return efi::Status::NOT_READY;
+    }
+    let mut buffer = [0u16; 65];
+    let mut length = 0;
+    let mut efi_status = efi::Status::SUCCESS;
+    for chunk in bytes.utf8_chunks() {
+        let invalid = if chunk.invalid().is_empty() {
+            None
+        } else {
+            Some('?')
+        };
+        for c in chunk.valid().chars().chain(invalid) {
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units).iter() {
+                if *unit == b'\n' as u16 {
+                    buffer[length] = b'\r' as u16;
+                    length += 1;
+                }
+                buffer[length] = *unit;
+                length += 1;
+            }
+            if length >= buffer.len() - 4 {
+                let flushed = flush_console(con_out, &mut buffer, &mut length);
+                if flushed.is_error() {
+                    efi_status = flushed;
+                }
+            }
+        }
+    }
+    let flushed = flush_console(con_out, &mut buffer, &mut length);
+    if flushed.is_error() {
+        return flushed;
+    }
+    efi_status
+}
+
+fn flush_console(
+    con_out: *mut simple_text_output::Protocol,
+    buffer: &mut [u16; 65],
+    length: &mut usize,
+) -> efi::Status {
+    if *length == 0 {
+        return efi::Status::SUCCESS;
+    }
+    buffer[*length] = 0;
+    *length = 0;
+    unsafe { ((*con_out).output_string)(con_out, buffer.as_mut_ptr()) }
+}
+
+// The command line the shell passed in the load options, as ASCII. Other
+// characters are kept as '?'.
+struct CommandLine {
+    buffer: [u8; MAX_COMMAND_LINE],
+    length: usize,
+}
+
+impl CommandLine {
+    fn new(image: &loaded_image::Protocol) -> Self {
+        let mut command_line = CommandLine {
+            buffer: [0; MAX_COMMAND_LINE],
+            length: 0,
+        };
+        if image.load_options.is_null() {
+            return command_line;
+        }
+        let units = unsafe {
+            core::slice::from_raw_parts(
+                image.load_options as *const u16,
+                image.load_options_size as usize / 2,
+            )
+        };
+        for unit in units.iter().take_while(|unit| **unit != 0) {
+            if command_line.length == MAX_COMMAND_LINE {
+                break;
+            }
+            command_line.buffer[command_line.length] = match *unit {
+                0x20..=0x7e => *unit as u8,
+                0x09 => b' ',
+                _ => b'?',
+            };
+            command_line.length += 1;
+        }
+        command_line
+    }
+
+    /**
+     * @brief Returns the arguments, without the name of the application.
+     */
+    fn arguments(&self) -> impl Iterator<Item = &str> {
+        // Only ASCII was stored.
+        let line = core::str::from_utf8(&self.buffer[..self.length]).unwrap_or("");
+        line.split(' ').filter(|word| !word.is_empty()).skip(1)
+    }
+}
+
+/**
+ * @brief Prints the status an entry returned, and returns whether it is the
+ *        expected one.
+ */
+fn check(what: &str, efi_status: efi::Status, expected: efi::Status) -> bool {
+    let verdict = if efi_status == expected {
+        "ok"
+    } else {
+        "UNEXPECTED"
+    };
+    log!("{}: {:#x} {}", what, efi_status.as_usize(), verdict);
+    efi_status == expected
+}
+
+/**
+ * @brief Reads and prints the configuration.
+ */
+fn show_config(protocol: *mut Protocol) -> Option<ControlConfig> {
+    let mut config = ControlConfig::default();
+    let efi_status = unsafe { ((*protocol).get_config)(protocol, &mut config) };
+    if !check("get_config", efi_status, efi::Status::SUCCESS) {
+        return None;
+    }
+    log!("Config: {}", ConfigFmt(&config));
+    Some(config)
+}
+
+/**
+ * @brief Prints the hook states, counters, most accessed variables, records
+ *        lost and the configuration.
+ */
+fn status(protocol: *mut Protocol, stats: *mut StatsProtocol) -> bool {
+    let mut counters = Stats::default();
+    let efi_status = unsafe { ((*stats).get_stats)(stats, &mut counters) };
+    if efi_status.is_error() {
+        log!("get_stats: {:#x}", efi_status.as_usize());
+        return false;
+    }
+    log!(
+        "Hooks: GetVariable={} SetVariable={} GetNextVariableName={} rehooked={} reinstalls={}",
+        HookState(counters.get_variable_hook),
+        HookState(counters.set_variable_hook),
+        HookState(counters.get_next_variable_name_hook),
+        counters.rehooked,
+        counters.reinstalls,
+    );
+    log!(
+        "Calls: GetVariable={} SetVariable={}",
+        counters.get_variable_calls,
+        counters.set_variable_calls,
+    );
+    log!(
+        "Events: deletions={} blocked={} rejected={} hidden={} alerts-suppressed={}",
+        counters.deletion_attempts,
+        Counter(counters.blocked_writes),
+        Counter(counters.rejected_writes),
+        Counter(counters.hidden_accesses),
+        counters.suppressed_alerts,
+    );
+    log!(
+        "Lost: serial={} ring-dropped={} ring-overwritten={} tpm-dropped={} tpm-failed={}",
+        counters.serial_failures,
+        Counter(counters.ring_dropped),
+        Counter(counters.ring_overwritten),
+        Counter(counters.tpm_dropped),
+        Counter(counters.tpm_failures),
+    );
+
+    let mut entries = [TopEntry::EMPTY; TOP_COUNT];
+    let mut count = entries.len();
+    let efi_status = unsafe { ((*stats).get_top)(stats, entries.as_mut_ptr(), &mut count) };
+    if efi_status.is_error() {
+        log!("get_top: {:#x}", efi_status.as_usize());
+        return false;
+    }
+    log!("Top variables:\n       reads      writes  storage       variable");
+    for entry in entries.iter().take(count) {
+        log!(
+            "  {:>10}  {:>10}  {:<12}  {}:{}",
+            entry.reads,
+            entry.writes,
+            STORAGE_NAMES
+                .get(entry.storage as usize)
+                .copied()
+                .unwrap_or("?"),
+            GuidFmt(&entry.guid),
+            entry.name()
+        );
+    }
+    show_config(protocol).is_some()
+}
+
+/**
+ * @brief Sets the level of the records written, given by name or number.
+ */
+fn set_level(protocol: *mut Protocol, argument: Option<&str>) -> bool {
+    let argument = argument.unwrap_or("");
+    let level = LEVELS
+        .iter()
+        .find(|(name, _)| *name == argument)
+        .map(|(_, level)| u32::from(*level))
+        .or_else(|| argument.parse().ok());
+    let level = match level {
+        Some(level) => level,
+        None => {
+            log!("Usage: uvmctl level critical|warning|info|trace");
+            return false;
+        }
+    };
+    let efi_status = unsafe { ((*protocol).set_level)(protocol, level) };
+    check("set_level", efi_status, efi::Status::SUCCESS)
+}
+
+/**
+ * @brief Saves the current configuration for the next boots.
+ */
+fn save(protocol: *mut Protocol) -> bool {
+    if unsafe { (*protocol).revision } < UVM_PROTOCOL_REVISION_SAVE {
+        log!(
+            "No save_config before revision {:#x}",
+            UVM_PROTOCOL_REVISION_SAVE
+        );
+        return false;
+    }
+    let efi_status = unsafe { ((*protocol).save_config)(protocol) };
+    check("save_config", efi_status, efi::Status::SUCCESS)
+}
+
+/**
+ * @brief Copies the statistics, or those since the snapshot `since`, into
+ *        `buffer`, returning the size copied, or the status and the size
+ *        needed.
+ */
+fn get_statistics(
+    protocol: *mut Protocol,
+    since: Option<u32>,
+    buffer: &mut [u8; MAX_STATISTICS_SIZE],
+    size: usize,
+) -> (efi::Status, usize) {
+    let mut size = size;
+    let pointer = buffer.as_mut_ptr() as *mut core::ffi::c_void;
+    let efi_status = unsafe {
+        match since {
+            None => ((*protocol).get_statistics)(protocol, pointer, &mut size),
+            Some(id) => ((*protocol).diff_statistics)(protocol, id, pointer, &mut size),
+        }
+    };
+    (efi_status, size)
+}
+
+/**
+ * @brief Prints the statistics, or those since the snapshot `since`.
+ */
+fn show_statistics(protocol: *mut Protocol, since: Option<u32>) -> bool {
+    let what = match since {
+        None => "get_statistics",
+        Some(_) => "diff_statistics",
+    };
+    let mut buffer = [0u8; MAX_STATISTICS_SIZE];
+    let (efi_status, size) = get_statistics(protocol, since, &mut buffer, MAX_STATISTICS_SIZE);
+    if efi_status == efi::Status::NOT_FOUND {
+        log!("{}: snapshot no longer kept", what);
+        return false;
+    }
+    if efi_status.is_error() {
+        log!(
+            "{}: {:#x}, {} bytes needed",
+            what,
+            efi_status.as_usize(),
+            size
+        );
+        return false;
+    }
+    let statistics = match Statistics::parse(&buffer[..size]) {
+        Ok(statistics) => statistics,
+        Err(error) => {
+            log!("{}: {}", what, error);
+            return false;
+        }
+    };
+    let (major, minor) = (statistics.format.major, statistics.format.minor);
+    match since {
+        Some(id) => log!("Statistics {}.{} since snapshot #{}:", major, minor, id),
+        None => log!("Statistics {}.{}:", major, minor),
+    }
+    log!(
+        "GetVariable:\n{}\nSetVariable:\n{}\nSkipped while paused: {}",
+        CallCountsFmt(&statistics.get_variable),
+        CallCountsFmt(&statistics.set_variable),
+        statistics.paused_skipped
+    );
+    // Older drivers do not measure it.
+    if statistics.format.minor >= 1 {
+        log!(
+            "GetVariable overhead: {}",
+            OverheadFmt(&statistics.overhead)
+        );
+    }
+    if statistics.format.minor >= 2 {
+        log!(
+            "Successful calls by storage:{}\nFlash writes since load: {} writes, {} bytes",
+            StorageCountsFmt(&statistics.by_storage),
+            statistics.flash_writes.writes,
+            statistics.flash_writes.bytes
+        );
+    }
+    true
+}
+
+/**
+ * @brief Prints the calls counted by outcome, data size and phase, zeroes
+ *        the counters with `reset`, or snapshots them and diffs against a
+ *        snapshot.
+ */
+fn statistics(protocol: *mut Protocol, command: Option<&str>, argument: Option<&str>) -> bool {
+    let revision = unsafe { (*protocol).revision };
+    let needed = match command {
+        Some("snapshot") | Some("diff") => UVM_PROTOCOL_REVISION_SNAPSHOT,
+        _ => UVM_PROTOCOL_REVISION_STATISTICS,
+    };
+    if revision < needed {
+        log!("Not supported before revision {:#x}", needed);
+        return false;
+    }
+    match (command, argument.map(str::parse::<u32>)) {
+        (None, _) => show_statistics(protocol, None),
+        (Some("reset"), _) => {
+            let efi_status = unsafe { ((*protocol).reset_statistics)(protocol) };
+            if efi_status == efi::Status::ACCESS_DENIED {
+                log!("reset_statistics: denied by UVM_STATS_RESET");
+                return false;
+            }
+            check("reset_statistics", efi_status, efi::Status::SUCCESS)
+        }
+        (Some("snapshot"), _) => {
+            let mut id = 0;
+            let efi_status = unsafe { ((*protocol).snapshot_statistics)(protocol, &mut id) };
+            if efi_status.is_error() {
+                log!("snapshot_statistics: {:#x}", efi_status.as_usize());
+                return false;
+            }
+            log!("Snapshot #{}", id);
+            true
+        }
+        (Some("diff"), Some(Ok(id))) => show_statistics(protocol, Some(id)),
+        _ => {
+            log!("Usage: uvmctl stats [reset | snapshot | diff <id>]");
+            false
+        }
+    }
+}
+
+/**
+ * @brief Runs the self-test and prints each check. Returns whether every
+ *        check run passed.
+ */
+fn self_test(protocol: *mut Protocol) -> bool {
+    if unsafe { (*protocol).revision } < UVM_PROTOCOL_REVISION_SELF_TEST {
+        log!(
+            "No self_test before revision {:#x}",
+            UVM_PROTOCOL_REVISION_SELF_TEST
+        );
+        return false;
+    }
+    let mut result = SelfTestResult::default();
+    let efi_status = unsafe { ((*protocol).self_test)(protocol, &mut result) };
+    if efi_status.is_error() {
+        log!("self_test: {:#x}", efi_status.as_usize());
+        return false;
+    }
+    for (check, name) in SELF_TEST_CHECKS.iter() {
+        let verdict = match (result.checked & check, result.passed & check) {
+            (0, _) => "not run",
+            (_, 0) => "FAIL",
+            _ => "pass",
+        };
+        log!("  {:<36}{}", name, verdict);
+    }
+    log!(
+        "Self-test: {}",
+        if result.failed() == 0 { "pass" } else { "FAIL" }
+    );
+    result.failed() == 0
+}
+
+/**
+ * @brief Times `calls` GetVariable calls through the hook and as many around
+ *        it, and prints what each cost. Returns whether they could be timed.
+ */
+fn benchmark(protocol: *mut Protocol, calls: Option<&str>) -> bool {
+    if unsafe { (*protocol).revision } < UVM_PROTOCOL_REVISION_BENCHMARK {
+        log!(
+            "No benchmark before revision {:#x}",
+            UVM_PROTOCOL_REVISION_BENCHMARK
+        );
+        return false;
+    }
+    let calls = match calls.map(str::parse::<u32>) {
+        None => BENCHMARK_CALLS,
+        Some(Ok(calls)) if calls > 0 && calls <= MAX_BENCHMARK_CALLS => calls,
+        Some(_) => {
+            log!(
+                "Usage: uvmctl bench [calls], from 1 to {}",
+                MAX_BENCHMARK_CALLS
+            );
+            return false;
+        }
+    };
+    let mut result = BenchmarkResult::default();
+    let efi_status = unsafe { ((*protocol).benchmark)(protocol, calls, &mut result) };
+    if efi_status.is_error() {
+        log!("benchmark: {:#x}", efi_status.as_usize());
+        return false;
+    }
+    let (hooked, bypassed) = result.per_call();
+    let added = hooked.saturating_sub(bypassed);
+    match (result.nanoseconds(hooked), result.nanoseconds(bypassed)) {
+        (Some(hooked), Some(bypassed)) => {
+            let added = hooked.saturating_sub(bypassed);
+            log!(
+                "Benchmark: {} GetVariable calls, {} ns each hooked, {} ns bypassed, {} ns added",
+                calls,
+                hooked,
+                bypassed,
+                added
+            );
+            if added > BENCHMARK_NOTICE_NS {
+                log!(
+                    "  Above {} ns added: the level or filter may trace the calls",
+                    BENCHMARK_NOTICE_NS
+                );
+            }
+        }
+        _ => {
+            log!("Benchmark: {} GetVariable calls, {} ticks each hooked, {} ticks bypassed, {} ticks added (uncalibrated)",
+                calls, hooked, bypassed, added
+            );
+        }
+    }
+    true
+}
+
+/**
+ * @brief Prints the writes kept in the write histories, oldest first.
+ */
+fn history(protocol: *mut Protocol) -> bool {
+    if unsafe { (*protocol).revision } < UVM_PROTOCOL_REVISION_HISTORY {
+        log!(
+            "No read_history before revision {:#x}",
+            UVM_PROTOCOL_REVISION_HISTORY
+        );
+        return false;
+    }
+    let mut entries = [HistoryEntry::EMPTY; HISTORY_COUNT];
+    let mut count = entries.len();
+    let efi_status =
+        unsafe { ((*protocol).read_history)(protocol, entries.as_mut_ptr(), &mut count) };
+    if efi_status.is_error() {
+        log!(
+            "read_history: {:#x}, {} entries",
+            efi_status.as_usize(),
+            count
+        );
+        return false;
+    }
+    log!("Write history: {}", count);
+    for entry in entries.iter().take(count) {
+        log!("  {}", HistoryEntryFmt(entry));
+    }
+    true
+}
+
+uvm_interface::efiapi! {
+    // The notification of the event signalled; the group's are what count.
+    fn ignore_event(_event: efi::Event, _context: *mut core::ffi::c_void) {}
+}
+
+/**
+ * @brief Signals the ReadyToBoot event group.
+ */
+fn ready_to_boot(boot_services: &mut efi::BootServices) -> bool {
+    let mut event: efi::Event = core::ptr::null_mut();
+    let efi_status = (boot_services.create_event_ex)(
+        efi::EVT_NOTIFY_SIGNAL,
+        efi::TPL_CALLBACK,
+        ignore_event,
+        core::ptr::null_mut(),
+        &efi::EVENT_GROUP_READY_TO_BOOT,
+        &mut event,
+    );
+    if efi_status.is_error() {
+        log!("create_event_ex: {:#x}", efi_status.as_usize());
+        return false;
+    }
+    let efi_status = (boot_services.signal_event)(event);
+    (boot_services.close_event)(event);
+    log!("ReadyToBoot signalled: {:#x}", efi_status.as_usize());
+    !efi_status.is_error()
+}
+
+/**
+ * @brief Opens `path` for writing on the volume the application was loaded
+ *        from, replacing an existing file.
+ */
+fn create_file(
+    boot_services: &mut efi::BootServices,
+    image: &loaded_image::Protocol,
+    path: &str,
+) -> Result<*mut file::Protocol, efi::Status> {
+    let mut name = [0u16; MAX_FILE_NAME + 1];
+    if path.len() > MAX_FILE_NAME {
+        return Err(efi::Status::INVALID_PARAMETER);
+    }
+    for (unit, c) in name.iter_mut().zip(path.bytes()) {
+        *unit = u16::from(c);
+    }
+
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.handle_protocol)(
+        image.device_handle,
+        &simple_file_system::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        &mut interface,
+    );
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    let file_system = interface as *mut simple_file_system::Protocol;
+    let mut root: *mut file::Protocol = core::ptr::null_mut();
+    let efi_status = unsafe { ((*file_system).open_volume)(file_system, &mut root) };
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+
+    let mode = file::MODE_READ | file::MODE_WRITE | FILE_MODE_CREATE;
+    let mut handle: *mut file::Protocol = core::ptr::null_mut();
+    let mut efi_status = unsafe { ((*root).open)(root, &mut handle, name.as_mut_ptr(), mode, 0) };
+    if !efi_status.is_error() {
+        // Start over from an empty file; Delete also closes the handle.
+        let _ = unsafe { ((*handle).delete)(handle) };
+        efi_status = unsafe { ((*root).open)(root, &mut handle, name.as_mut_ptr(), mode, 0) };
+    }
+    let _ = unsafe { ((*root).close)(root) };
+    if efi_status.is_error() {
+        return Err(efi_status);
+    }
+    Ok(handle)
+}
+
+/**
+ * @brief Writes `bytes` to the file.
+ */
+fn write_file(handle: *mut file::Protocol, bytes: &[u8]) -> efi::Status {
+    let mut size = bytes.len();
+    let efi_status =
+        unsafe { ((*handle).write)(handle, &mut size, bytes.as_ptr() as *mut core::ffi::c_void) };
+    if !efi_status.is_error() && size != bytes.len() {
+        return efi::Status::VOLUME_FULL;
+    }
+    efi_status
+}
+
+/**
+ * @brief Writes the ring buffer header, then the records it holds, to the
+ *        file.
+ */
+fn write_ring(
+    stats: *mut StatsProtocol,
+    handle: *mut file::Protocol,
+    header: &RingHeader,
+) -> efi::Status {
+    // The header is as long as the format it was given in says.
+    let bytes = header.as_bytes();
+    let efi_status = write_file(
+        handle,
+        bytes.get(..header.format.size as usize).unwrap_or(bytes),
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    let mut written = 0u64;
+    let mut missed = 0u64;
+    let mut record = RingRecord::EMPTY;
+    for sequence in header.first_sequence..header.next_sequence {
+        let efi_status = unsafe { ((*stats).get_ring_record)(stats, sequence, &mut record) };
+        if efi_status == efi::Status::NOT_FOUND {
+            // Overwritten since the header was read.
+            missed += 1;
+            continue;
+        }
+        if efi_status.is_error() {
+            return efi_status;
+        }
+        let efi_status = write_file(handle, record.as_bytes());
+        if efi_status.is_error() {
+            return efi_status;
+        }
+        written += 1;
+    }
+    log!(
+        "Wrote {} records (#{}..#{}), {} overwritten meanwhile",
+        written,
+        header.first_sequence,
+        header.next_sequence,
+        missed
+    );
+    efi::Status::SUCCESS
+}
+
+/**
+ * @brief Writes the ring buffer to `path`.
+ */
+fn dump(
+    boot_services: &mut efi::BootServices,
+    image: &loaded_image::Protocol,
+    stats: *mut StatsProtocol,
+    path: Option<&str>,
+) -> bool {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            log!("Usage: uvmctl dump <file>");
+            return false;
+        }
+    };
+    let mut header = RingHeader::default();
+    let efi_status = unsafe { ((*stats).get_ring_header)(stats, &mut header) };
+    if efi_status == efi::Status::UNSUPPORTED {
+        log!("dump: ring buffer not built");
+        return false;
+    }
+    if efi_status.is_error() {
+        log!("get_ring_header: {:#x}", efi_status.as_usize());
+        return false;
+    }
+    // A driver built from another tree may keep another format.
+    if let Err(error) = RingHeader::parse(header.as_bytes()) {
+        log!("get_ring_header: {}", error);
+        return false;
+    }
+    // The header given is the one of minor version 1; the build completes it.
+    if unsafe { (*stats).revision } >= UVM_STATS_PROTOCOL_REVISION_BUILD {
+        let efi_status = unsafe { ((*stats).get_build)(stats, &mut header.build) };
+        if efi_status.is_error() {
+            log!("get_build: {:#x}", efi_status.as_usize());
+            return false;
+        }
+        header.format.minor = RING_MINOR;
+        header.format.size = RingHeader::FORMAT.size;
+    }
+
+    let handle = match create_file(boot_services, image, path) {
+        Ok(handle) => handle,
+        Err(efi_status) => {
+            log!("{}: {:#x}", path, efi_status.as_usize());
+            return false;
+        }
+    };
+    let efi_status = write_ring(stats, handle, &header);
+    let _ = unsafe { ((*handle).close)(handle) };
+    check("dump", efi_status, efi::Status::SUCCESS)
+}
+
+/**
+ * @brief Goes through every control entry. Returns whether they all behaved
+ *        as expected.
+ */
+fn exercise(protocol: *mut Protocol) -> bool {
+    let config = match show_config(protocol) {
+        Some(config) => config,
+        None => return false,
+    };
+    let this = protocol;
+    let protocol = unsafe { &*protocol };
+    let mut ok = true;
+
+    ok &= check(
+        "set_level(4)",
+        (protocol.set_level)(this, LEVEL_UNKNOWN),
+        efi::Status::INVALID_PARAMETER,
+    );
+    ok &= check(
+        "set_level(trace)",
+        (protocol.set_level)(this, u32::from(LEVEL_TRACE)),
+        efi::Status::SUCCESS,
+    );
+
+    ok &= check(
+        "set_filter(malformed)",
+        (protocol.set_filter)(this, MALFORMED_FILTER.as_ptr(), MALFORMED_FILTER.len()),
+        efi::Status::INVALID_PARAMETER,
+    );
+    ok &= check(
+        "set_filter(Boot*)",
+        (protocol.set_filter)(this, EXAMPLE_FILTER.as_ptr(), EXAMPLE_FILTER.len()),
+        efi::Status::SUCCESS,
+    );
+    ok &= matches!(show_config(this), Some(config) if config.filter_entries == 1);
+    ok &= check(
+        "set_filter(empty)",
+        (protocol.set_filter)(this, core::ptr::null(), 0),
+        efi::Status::SUCCESS,
+    );
+
+    ok &= check("pause", (protocol.pause)(this), efi::Status::SUCCESS);
+    ok &= check("resume", (protocol.resume)(this), efi::Status::SUCCESS);
+
+    // Too small a buffer gets the size needed, which is then enough.
+    // reset_statistics is left alone: it depends on UVM_STATS_RESET, and
+    // would zero the counters.
+    if protocol.revision >= UVM_PROTOCOL_REVISION_STATISTICS {
+        let mut buffer = [0u8; MAX_STATISTICS_SIZE];
+        let (efi_status, needed) = get_statistics(this, None, &mut buffer, 0);
+        ok &= check(
+            "get_statistics(0)",
+            efi_status,
+            efi::Status::BUFFER_TOO_SMALL,
+        );
+        let (efi_status, size) = get_statistics(this, None, &mut buffer, needed);
+        ok &= check("get_statistics", efi_status, efi::Status::SUCCESS);
+        ok &= size == needed && Statistics::parse(&buffer[..size]).is_ok();
+    }
+    if protocol.revision >= UVM_PROTOCOL_REVISION_SNAPSHOT {
+        let mut id = 0;
+        let mut buffer = [0u8; MAX_STATISTICS_SIZE];
+        ok &= check(
+            "snapshot_statistics",
+            (protocol.snapshot_statistics)(this, &mut id),
+            efi::Status::SUCCESS,
+        );
+        let (efi_status, _) = get_statistics(this, Some(id), &mut buffer, MAX_STATISTICS_SIZE);
+        ok &= check("diff_statistics", efi_status, efi::Status::SUCCESS);
+        let (efi_status, _) = get_statistics(this, Some(0), &mut buffer, MAX_STATISTICS_SIZE);
+        ok &= check(
+            "diff_statistics(0)",
+            efi_status,
+            efi::Status::INVALID_PARAMETER,
+        );
+    }
+
+    if protocol.revision >= UVM_PROTOCOL_REVISION_HISTORY {
+        let mut count = 0;
+        let efi_status = (protocol.read_history)(this, core::ptr::null_mut(), &mut count);
+        ok &= efi_status == efi::Status::SUCCESS || efi_status == efi::Status::BUFFER_TOO_SMALL;
+        ok &= check(
+            "read_history(null)",
+            (protocol.read_history)(this, core::ptr::null_mut(), core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER,
+        );
+    }
+
+    if protocol.revision >= UVM_PROTOCOL_REVISION_BENCHMARK {
+        let mut result = BenchmarkResult::default();
+        ok &= check(
+            "benchmark(0)",
+            (protocol.benchmark)(this, 0, &mut result),
+            efi::Status::INVALID_PARAMETER,
+        );
+        ok &= check(
+            "benchmark(null)",
+            (protocol.benchmark)(this, 1, core::ptr::null_mut()),
+            efi::Status::INVALID_PARAMETER,
+        );
+    }
+
+    // Only built with the ring-dump feature.
+    let efi_status = (protocol.dump)(this);
+    if efi_status == efi::Status::UNSUPPORTED {
+        log!("dump: not built");
+    } else {
+        ok &= check("dump", efi_status, efi::Status::SUCCESS);
+    }
+
+    // Put back what was found.
+    ok &= check(
+        "set_level(restore)",
+        (protocol.set_level)(this, config.level),
+        efi::Status::SUCCESS,
+    );
+    if config.paused != 0 {
+        ok &= check(
+            "pause(restore)",
+            (protocol.pause)(this),
+            efi::Status::SUCCESS,
+        );
+    }
+    ok &= show_config(this).is_some();
+    ok
+}
+
+/**
+ * @brief Returns the interface of the protocol, if installed.
+ */
+fn locate(
+    boot_services: &mut efi::BootServices,
+    guid: &efi::Guid,
+) -> Result<*mut core::ffi::c_void, efi::Status> {
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.locate_protocol)(
+        guid as *const _ as *mut efi::Guid,
+        core::ptr::null_mut(),
+        &mut interface,
+    );
+    if efi_status.is_error() || interface.is_null() {
+        return Err(efi_status);
+    }
+    Ok(interface)
+}
+
+#[no_mangle]
+extern "efiapi" fn efi_main(
+    image_handle: efi::Handle,
+    system_table: *mut efi::SystemTable,
+) -> efi::Status {
+    let system_table = unsafe { &mut *system_table };
+    let boot_services = unsafe { &mut *system_table.boot_services };
+    CON_OUT.store(system_table.con_out, Ordering::Relaxed);
+
+    let mut interface: *mut core::ffi::c_void = core::ptr::null_mut();
+    let efi_status = (boot_services.handle_protocol)(
+        image_handle,
+        &loaded_image::PROTOCOL_GUID as *const _ as *mut efi::Guid,
+        &mut interface,
+    );
+    if efi_status.is_error() {
+        return efi_status;
+    }
+    let image = unsafe { &*(interface as *mut loaded_image::Protocol) };
+    let command_line = CommandLine::new(image);
+    let mut arguments = command_line.arguments();
+
+    let (protocol, stats) = match (
+        locate(boot_services, &UVM_PROTOCOL_GUID),
+        locate(boot_services, &UVM_STATS_PROTOCOL_GUID),
+    ) {
+        (Ok(protocol), Ok(stats)) => (protocol as *mut Protocol, stats as *mut StatsProtocol),
+        (Err(efi_status), _) | (_, Err(efi_status)) => {
+            log!("Monitor not loaded: {:#x}", efi_status.as_usize());
+            return efi::Status::NOT_FOUND;
+        }
+    };
+    let revision = unsafe { (*protocol).revision };
+    let stats_revision = unsafe { (*stats).revision };
+    log!(
+        "Monitor revision {:#x}, statistics revision {:#x}",
+        revision,
+        stats_revision
+    );
+    if revision < UVM_PROTOCOL_REVISION {
+        log!(
+            "No control entries before revision {:#x}",
+            UVM_PROTOCOL_REVISION
+        );
+        return efi::Status::INCOMPATIBLE_VERSION;
+    }
+
+    let ok = match arguments.next() {
+        None | Some("status") => status(protocol, stats),
+        Some("level") => set_level(protocol, arguments.next()),
+        Some("dump") => dump(boot_services, image, stats, arguments.next()),
+        Some("save") => save(protocol),
+        Some("stats") => statistics(protocol, arguments.next(), arguments.next()),
+        Some("selftest") => self_test(protocol),
+        Some("history") => history(protocol),
+        Some("bench") => benchmark(protocol, arguments.next()),
+        Some("check") => exercise(protocol),
+        Some("ready-to-boot") => ready_to_boot(boot_services),
+        Some(command) => {
+            log!("Unknown command {}; expected status, level, dump, save, stats, selftest, history, bench, check or ready-to-boot",
+                command
+            );
+            return efi::Status::INVALID_PARAMETER;
+        }
+    };
+    if !ok {
+        return efi::Status::ABORTED;
+    }
+    efi::Status::SUCCESS
+}
+
+#[panic_handler]
+fn panic_handler(_info: &core::panic::PanicInfo) -> ! {
+    loop {}
+}
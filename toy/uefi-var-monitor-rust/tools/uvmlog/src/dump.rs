@@ -0,0 +1,688 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/dump.rs
+//
+// A ring buffer dump, as uvmctl writes it: the header, then the records that
+// were still held, oldest first. The sizes in the header's common part give
+// where the records start and how far apart they are, so that dumps of a
+// later minor version are read for what this tool knows. Records overwritten while the dump was being
+// written are missing from it, so the first record may come after
+// header.first_sequence.
+//
+// A dump damaged on its way off the machine is read by Dump::recover, which
+// resynchronizes on the records themselves rather than on any marker: past
+// the header, a record is taken wherever one starts whose sequence number
+// follows the last one taken and is below header.next_sequence, and whose
+// kind, length and reserved bytes are those of a record. One that follows the
+// last record directly, or that is header.first_sequence, must also link up
+// from it, or from the header's anchor. Elsewhere the bytes are skipped one
+// at a time, and reported with the records lost between the records on
+// either side. A record after a gap has no record to be checked against: the
+// newest one must be the header's head, and any other is taken unless the
+// record after it in the dump is one of the dump's that does not come next,
+// as when the damage hit its sequence number.
+//
+// Dumps of minor version 2 on say which build of the driver wrote them, in the
+// words of the banner it logged at load (see interface/src/build.rs).
+
+use std::fmt::{self, Write};
+use uvm_interface::build::{BuildInfo, Identity, Names, Serial, Sources, HOOK_NAMES, SINK_NAMES};
+use uvm_interface::report::BootReport;
+use uvm_interface::ring::{
+    self, RecordText, RingHeader, RingRecord, RECORD_SET_VARIABLE, RING_CHAIN_SIZE, RING_DATA_SIZE,
+    RING_FLAG_PANICKED,
+};
+use uvm_interface::FormatError;
+
+pub struct Dump {
+    pub header: RingHeader,
+    pub records: Vec<RingRecord>,
+}
+
+// What verify found out about the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    // Every record links up, from the header's anchor to its head.
+    Verified,
+    // Every record links up, but the first ones were overwritten while the
+    // dump was written, so the first one held could not be checked.
+    VerifiedFrom(u64),
+    // The record with the sequence number does not link up.
+    Broken(u64),
+    // The record with the sequence number is missing from the dump.
+    Missing(u64),
+    // The newest record is not the header's head.
+    Head,
+}
+
+// What checking the chain against a boot report found out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Commitment {
+    // The record the report committed to is held, and is the one committed
+    // to.
+    Matches(u64),
+    Differs(u64),
+    // The report committed to no record.
+    Empty,
+    // The record the report committed to is not in the dump.
+    NotHeld(u64),
+}
+
+// Bytes of a damaged dump that did not hold the records expected there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Damage {
+    // Offset in the dump of the first byte skipped.
+    pub offset: usize,
+    // Bytes skipped, zero where records are missing between two that follow
+    // each other in the dump.
+    pub length: usize,
+    // Records missing between the records on either side, or before
+    // header.next_sequence at the end. Damage before the first record counts
+    // every record from header.first_sequence, some of which may have been
+    // overwritten while the dump was written rather than damaged.
+    pub lost: u64,
+}
+
+impl Dump {
+    /**
+     * @brief Reads a dump.
+     */
+    pub fn parse(bytes: &[u8]) -> Result<Self, FormatError> {
+        let header = RingHeader::parse(bytes)?;
+        let records =
+            bytes[header.format.size as usize..].chunks_exact(header.format.record_size as usize);
+        if !records.remainder().is_empty() {
+            return Err(FormatError::Truncated);
+        }
+        let records = records.map(RingRecord::parse).collect::<Result<_, _>>()?;
+        Ok(Dump { header, records })
+    }
+
+    /**
+     * @brief Reads what is left of a damaged dump: the records found, and
+     *        where the bytes between them were damaged. Only the header
+     *        must be whole.
+     */
+    pub fn recover(bytes: &[u8]) -> Result<(Self, Vec<Damage>), FormatError> {
+        let header = RingHeader::parse(bytes)?;
+        let size = header.format.record_size as usize;
+        let mut records: Vec<RingRecord> = Vec::new();
+        let mut damage = Vec::new();
+        let mut offset = header.format.size as usize;
+        let mut skipped = None;
+        while offset < bytes.len() {
+            let record = match bytes.get(offset..offset + size) {
+                Some(bytes) => RingRecord::parse(bytes)?,
+                None => break,
+            };
+            let next = bytes
+                .get(offset + size..offset + 2 * size)
+                .map(RingRecord::parse)
+                .transpose()?;
+            if !follows(&header, records.last(), &record, next.as_ref()) {
+                skipped.get_or_insert(offset);
+                offset += 1;
+                continue;
+            }
+            let expected = match records.last() {
+                Some(last) => last.sequence + 1,
+                None if skipped.is_some() => header.first_sequence,
+                // Overwritten while the dump was written.
+                None => record.sequence,
+            };
+            if skipped.is_some() || record.sequence != expected {
+                let start = skipped.take().unwrap_or(offset);
+                damage.push(Damage {
+                    offset: start,
+                    length: offset - start,
+                    lost: record.sequence - expected,
+                });
+            }
+            records.push(record);
+            offset += size;
+        }
+        let expected = records
+            .last()
+            .map_or(header.first_sequence, |last| last.sequence + 1);
+        let start = skipped.unwrap_or(offset);
+        if start < bytes.len() || expected < header.next_sequence {
+            damage.push(Damage {
+                offset: start,
+                length: bytes.len() - start,
+                lost: header.next_sequence.saturating_sub(expected),
+            });
+        }
+        Ok((Dump { header, records }, damage))
+    }
+
+    /**
+     * @brief Returns the dump as uvmctl writes it, in the version of this
+     *        tool. For the tests pinning the format.
+     */
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = self.header;
+        header.format = RingHeader::FORMAT;
+        header.format.flags = self.header.format.flags;
+        let mut bytes = header.as_bytes().to_vec();
+        for record in &self.records {
+            bytes.extend_from_slice(record.as_bytes());
+        }
+        bytes
+    }
+
+    /**
+     * @brief Checks that the records are consecutive and link up.
+     */
+    pub fn verify(&self) -> Chain {
+        let first = match self.records.first() {
+            Some(first) => first,
+            None => return Chain::Verified,
+        };
+        for (expected, record) in (first.sequence..).zip(&self.records) {
+            if record.sequence != expected {
+                return Chain::Missing(expected);
+            }
+        }
+        let complete = first.sequence == self.header.first_sequence;
+        let (anchor, records) = if complete {
+            (self.header.chain_anchor, &self.records[..])
+        } else {
+            (first.chain, &self.records[1..])
+        };
+        if let Err(sequence) = ring::verify_chain(anchor, records) {
+            return Chain::Broken(sequence);
+        }
+        let newest = &self.records[self.records.len() - 1];
+        if newest.sequence + 1 == self.header.next_sequence
+            && newest.chain != self.header.chain_head
+        {
+            return Chain::Head;
+        }
+        if complete {
+            Chain::Verified
+        } else {
+            Chain::VerifiedFrom(first.sequence)
+        }
+    }
+
+    /**
+     * @brief Checks that the chain goes through the head a boot report
+     *        committed to.
+     */
+    pub fn check_commitment(&self, report: &BootReport) -> Commitment {
+        if report.next_sequence == 0 {
+            return Commitment::Empty;
+        }
+        let sequence = report.next_sequence - 1;
+        match self
+            .records
+            .iter()
+            .find(|record| record.sequence == sequence)
+        {
+            Some(record) if record.chain == report.chain_head => Commitment::Matches(sequence),
+            Some(_) => Commitment::Differs(sequence),
+            None => Commitment::NotHeld(sequence),
+        }
+    }
+
+    /**
+     * @brief Writes the header, then one line per record.
+     */
+    pub fn decode(&self, out: &mut impl Write) -> fmt::Result {
+        let header = &self.header;
+        let policy = match header.policy {
+            0 => "overwrite-oldest",
+            1 => "drop-newest",
+            _ => "?",
+        };
+        writeln!(
+            out,
+            "Ring buffer format {}.{}",
+            header.format.major, header.format.minor
+        )?;
+        // Dumps of minor version 1 have no build.
+        if header.format.minor >= 2 {
+            describe_build(&header.build, out)?;
+        }
+        writeln!(
+            out,
+            "Ring buffer: {} of {} records (#{}..#{}), capacity {}, {}",
+            self.records.len(),
+            header.next_sequence - header.first_sequence,
+            header.first_sequence,
+            header.next_sequence,
+            header.capacity,
+            policy
+        )?;
+        writeln!(
+            out,
+            "Lost: {} overwritten, {} dropped",
+            header.overwritten, header.dropped
+        )?;
+        writeln!(out, "Head: {}", Link(&header.chain_head))?;
+        if header.format.flags & RING_FLAG_PANICKED != 0 {
+            writeln!(out, "The driver panicked")?;
+        }
+        for record in &self.records {
+            writeln!(out, "#{} {}", record.sequence, RecordText(record))?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * @brief Returns whether `record` has the fields of a record held in the
+ *        dump, numbered `first` or later.
+ */
+fn plausible(header: &RingHeader, record: &RingRecord, first: u64) -> bool {
+    record.sequence >= first
+        && record.sequence < header.next_sequence
+        && record.kind <= RECORD_SET_VARIABLE
+        && record.length as usize <= RING_DATA_SIZE
+        && record.reserved == [0; 5]
+}
+
+/**
+ * @brief Returns whether `record`, read from a damaged dump before `next`,
+ *        is one that may come after `last` (see Dump::recover).
+ */
+fn follows(
+    header: &RingHeader,
+    last: Option<&RingRecord>,
+    record: &RingRecord,
+    next: Option<&RingRecord>,
+) -> bool {
+    let first = last.map_or(header.first_sequence, |last| last.sequence + 1);
+    if !plausible(header, record, first) {
+        return false;
+    }
+    let previous = match last {
+        Some(last) if last.sequence + 1 == record.sequence => last.chain,
+        _ if record.sequence == header.first_sequence => header.chain_anchor,
+        _ if record.sequence + 1 == header.next_sequence => {
+            return record.chain == header.chain_head
+        }
+        _ => {
+            return next.is_none_or(|next| {
+                !plausible(header, next, header.first_sequence)
+                    || next.sequence == record.sequence + 1
+            })
+        }
+    };
+    ring::link(record, &previous) == record.chain
+}
+
+// A chain link, in hex.
+pub struct Link<'a>(pub &'a [u8; RING_CHAIN_SIZE]);
+
+impl fmt::Display for Link<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * @brief Writes the build of the driver, as its banner gave it.
+ */
+pub fn describe_build(build: &BuildInfo, out: &mut impl Write) -> fmt::Result {
+    if !build.is_recorded() {
+        return writeln!(out, "Build: not recorded");
+    }
+    writeln!(out, "Build: {}", Identity(build))?;
+    writeln!(out, "  Hooks: {}", Names(build.hooks, &HOOK_NAMES))?;
+    writeln!(out, "  Sinks: {}", Names(build.sinks, &SINK_NAMES))?;
+    writeln!(out, "  Serial: {}", Serial(build))?;
+    writeln!(out, "  Configuration: {}", Sources(build.config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uvm_interface::ring::{RING_MINOR_1_SIZE, RING_RECORD_SIZE};
+
+    const RING: &[u8] = include_bytes!("../fixtures/ring.bin");
+    const RING_TEXT: &str = include_str!("../fixtures/ring.txt");
+    const REPORT: &[u8] = include_bytes!("../fixtures/UvmBootReport");
+    // The raw records of the driver's golden table, and their text as the
+    // driver's tests pin it (see src/golden.rs).
+    const RECORDS: &[u8] = include_bytes!("../fixtures/records.bin");
+    const RECORDS_TEXT: &str = include_str!("../fixtures/records.txt");
+    const DEFERRED_TEXT: &str = include_str!("../../../fixtures/records/deferred.txt");
+
+    #[test]
+    fn fixture_round_trips() {
+        let dump = Dump::parse(RING).unwrap();
+        assert_eq!(dump.to_bytes(), RING);
+        let mut text = String::new();
+        dump.decode(&mut text).unwrap();
+        assert_eq!(text, RING_TEXT);
+        assert_eq!(dump.verify(), Chain::Verified);
+
+        let report = BootReport::parse(crate::report::variable_data(REPORT)).unwrap();
+        assert_eq!(dump.check_commitment(&report), Commitment::Matches(10));
+
+        assert_eq!(
+            Dump::parse(&RING[..RING.len() - 1]).err(),
+            Some(FormatError::Truncated)
+        );
+        assert_eq!(Dump::parse(&RING[1..]).err(), Some(FormatError::Signature));
+    }
+
+    #[test]
+    fn later_minors_are_read_and_other_majors_refused() {
+        let dump = Dump::parse(RING).unwrap();
+        // A later minor version, with a longer header and longer records.
+        let mut header = dump.header;
+        header.format.minor += 1;
+        header.format.size += 8;
+        header.format.record_size += 4;
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(&[0xee; 8]);
+        for record in &dump.records {
+            bytes.extend_from_slice(record.as_bytes());
+            bytes.extend_from_slice(&[0xee; 4]);
+        }
+        let newer = Dump::parse(&bytes).unwrap();
+        assert_eq!(newer.records.len(), dump.records.len());
+        assert_eq!(newer.to_bytes(), RING);
+        assert_eq!(newer.verify(), Chain::Verified);
+
+        header.format.major += 1;
+        let error = Dump::parse(header.as_bytes()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "format version 2.x, this build reads version 1.x only"
+        );
+        // Records shorter than this tool knows cannot be read.
+        header = dump.header;
+        header.format.record_size -= 1;
+        assert_eq!(
+            Dump::parse(header.as_bytes()).err(),
+            Some(FormatError::RecordSize)
+        );
+    }
+
+    #[test]
+    fn dumps_of_minor_1_are_read_without_a_build() {
+        let dump = Dump::parse(RING).unwrap();
+        let mut header = dump.header;
+        header.format.minor = 1;
+        header.format.size = RING_MINOR_1_SIZE as u32;
+        let mut bytes = header.as_bytes()[..RING_MINOR_1_SIZE].to_vec();
+        for record in &dump.records {
+            bytes.extend_from_slice(record.as_bytes());
+        }
+        let older = Dump::parse(&bytes).unwrap();
+        assert_eq!(older.header.build, BuildInfo::EMPTY);
+        assert_eq!(older.records.len(), dump.records.len());
+        assert_eq!(older.verify(), Chain::Verified);
+        let mut text = String::new();
+        older.decode(&mut text).unwrap();
+        let lines: Vec<&str> = RING_TEXT
+            .lines()
+            .filter(|line| !line.starts_with("Build: ") && !line.starts_with("  "))
+            .collect();
+        assert_eq!(
+            text,
+            lines.join("\n").replace("format 1.2", "format 1.1") + "\n"
+        );
+    }
+
+    #[test]
+    fn edits_and_gaps_are_found() {
+        let mut dump = Dump::parse(RING).unwrap();
+        dump.records[2].data[0] ^= 1;
+        assert_eq!(dump.verify(), Chain::Broken(dump.records[2].sequence));
+
+        let mut dump = Dump::parse(RING).unwrap();
+        let removed = dump.records.remove(3);
+        assert_eq!(dump.verify(), Chain::Missing(removed.sequence));
+
+        // Records overwritten during the dump leave the rest verifiable.
+        let mut dump = Dump::parse(RING).unwrap();
+        let second = dump.records[1].sequence;
+        dump.records.remove(0);
+        assert_eq!(dump.verify(), Chain::VerifiedFrom(second));
+
+        let mut dump = Dump::parse(RING).unwrap();
+        dump.header.chain_head[0] ^= 1;
+        assert_eq!(dump.verify(), Chain::Head);
+    }
+
+    #[test]
+    fn golden_records_decode_to_the_deferred_text() {
+        let dump = Dump::parse(RECORDS).unwrap();
+        assert_eq!(dump.to_bytes(), RECORDS);
+        assert_eq!(dump.verify(), Chain::Verified);
+        let mut text = String::new();
+        dump.decode(&mut text).unwrap();
+        // Regenerated like the driver's golden files.
+        if std::env::var_os("UVM_BLESS").is_some() {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/records.txt");
+            return std::fs::write(path, text).unwrap();
+        }
+        assert_eq!(text, RECORDS_TEXT);
+
+        // Past the header lines, each record is the deferred text of the
+        // driver behind its sequence number.
+        let records: Vec<&str> = text
+            .lines()
+            .skip_while(|line| !line.starts_with('#'))
+            .map(|line| line.split_once(' ').unwrap().1)
+            .collect();
+        assert_eq!(records, DEFERRED_TEXT.lines().collect::<Vec<_>>());
+    }
+
+    /**
+     * @brief Returns the dump of a buffer of `capacity` records, overwriting
+     *        the oldest, after `count` text records were stored, as the
+     *        driver chains them.
+     */
+    fn wrapped(capacity: u32, count: u64) -> Dump {
+        let mut header = RingHeader {
+            format: RingHeader::FORMAT,
+            capacity,
+            first_sequence: count.saturating_sub(u64::from(capacity)),
+            next_sequence: count,
+            overwritten: count.saturating_sub(u64::from(capacity)),
+            ..RingHeader::default()
+        };
+        let mut records = Vec::new();
+        for sequence in 0..count {
+            let text = format!("record {}", sequence);
+            let mut record = RingRecord::EMPTY;
+            record.sequence = sequence;
+            record.length = text.len() as u16;
+            record.data[..text.len()].copy_from_slice(text.as_bytes());
+            record.chain = ring::link(&record, &header.chain_head);
+            if sequence < header.first_sequence {
+                header.chain_anchor = record.chain;
+            } else {
+                records.push(record);
+            }
+            header.chain_head = record.chain;
+        }
+        Dump { header, records }
+    }
+
+    fn sequences(dump: &Dump) -> Vec<u64> {
+        dump.records.iter().map(|record| record.sequence).collect()
+    }
+
+    fn record_offset(dump: &Dump, index: usize) -> usize {
+        dump.header.format.size as usize + index * RING_RECORD_SIZE
+    }
+
+    #[test]
+    fn records_round_trip_across_the_wrap() {
+        // Ten records through a buffer of four: #6..#9 are held, and the slot
+        // of the oldest is not the first one.
+        let dump = wrapped(4, 10);
+        assert_eq!(sequences(&dump), [6, 7, 8, 9]);
+        let bytes = dump.to_bytes();
+        let read = Dump::parse(&bytes).unwrap();
+        assert_eq!(read.header, dump.header);
+        for (read, written) in read.records.iter().zip(&dump.records) {
+            assert_eq!(read.as_bytes(), written.as_bytes());
+        }
+        assert_eq!(read.verify(), Chain::Verified);
+        let mut text = String::new();
+        read.decode(&mut text).unwrap();
+        assert!(text.ends_with("#6 record 6\n#7 record 7\n#8 record 8\n#9 record 9\n"));
+
+        // A whole dump is recovered as it is read.
+        for dump in [wrapped(4, 10), wrapped(8, 3), Dump::parse(RING).unwrap()] {
+            let bytes = dump.to_bytes();
+            let (recovered, damage) = Dump::recover(&bytes).unwrap();
+            assert_eq!(damage, []);
+            assert_eq!(recovered.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn flipped_bytes_lose_their_record() {
+        let dump = wrapped(8, 20);
+        let bytes = dump.to_bytes();
+        // In the text, the sequence number, the kind and the chain of the
+        // fourth record; and in the middle and the newest record.
+        for (index, field) in [(3, 20), (3, 0), (3, 10), (3, 16), (5, 30), (7, 20)] {
+            let mut damaged = bytes.clone();
+            damaged[record_offset(&dump, index) + field] ^= 0x40;
+            let (recovered, damage) = Dump::recover(&damaged).unwrap();
+            let mut expected = sequences(&dump);
+            expected.remove(index);
+            assert_eq!(sequences(&recovered), expected, "{}/{}", index, field);
+            assert_eq!(
+                damage,
+                [Damage {
+                    offset: record_offset(&dump, index),
+                    length: RING_RECORD_SIZE,
+                    lost: 1,
+                }],
+                "{}/{}",
+                index,
+                field
+            );
+        }
+
+        // The oldest record, whose loss is counted from the header's first.
+        let mut damaged = bytes.clone();
+        damaged[record_offset(&dump, 0) + 28] ^= 0x40;
+        let (recovered, damage) = Dump::recover(&damaged).unwrap();
+        assert_eq!(sequences(&recovered), (13..20).collect::<Vec<_>>());
+        assert_eq!(damage[0].offset, record_offset(&dump, 0));
+        assert_eq!(damage[0].lost, 1);
+
+        // The text of a record after a gap cannot be checked: the one record
+        // its link could be checked from is gone.
+        let mut damaged = bytes.clone();
+        damaged[record_offset(&dump, 2) + 28] ^= 0x40;
+        damaged[record_offset(&dump, 3) + 28] ^= 0x40;
+        let (recovered, damage) = Dump::recover(&damaged).unwrap();
+        assert_eq!(recovered.records.len(), 7);
+        assert_eq!(damage.len(), 1);
+        assert_eq!(recovered.verify(), Chain::Missing(14));
+
+        // Two records in a row whose sequence numbers were hit.
+        let mut damaged = bytes;
+        damaged[record_offset(&dump, 2)] ^= 0x40;
+        damaged[record_offset(&dump, 3)] ^= 0x40;
+        let (recovered, damage) = Dump::recover(&damaged).unwrap();
+        assert_eq!(recovered.records.len(), 6);
+        assert_eq!(
+            damage,
+            [Damage {
+                offset: record_offset(&dump, 2),
+                length: 2 * RING_RECORD_SIZE,
+                lost: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn truncation_and_garbage_are_skipped() {
+        let dump = wrapped(8, 20);
+        let bytes = dump.to_bytes();
+
+        // Cut in the middle of the newest record.
+        let cut = record_offset(&dump, 7) + 50;
+        let (recovered, damage) = Dump::recover(&bytes[..cut]).unwrap();
+        assert_eq!(sequences(&recovered), (12..19).collect::<Vec<_>>());
+        assert_eq!(
+            damage,
+            [Damage {
+                offset: record_offset(&dump, 7),
+                length: 50,
+                lost: 1,
+            }]
+        );
+        // Cut right after a record: nothing skipped, three records lost.
+        let (_, damage) = Dump::recover(&bytes[..record_offset(&dump, 5)]).unwrap();
+        assert_eq!(
+            damage,
+            [Damage {
+                offset: record_offset(&dump, 5),
+                length: 0,
+                lost: 3,
+            }]
+        );
+        assert_eq!(
+            Dump::parse(&bytes[..cut]).err(),
+            Some(FormatError::Truncated)
+        );
+
+        // Garbage between two records, of a length that is and one that is
+        // not a multiple of the record size.
+        for length in [RING_RECORD_SIZE, 37] {
+            let at = record_offset(&dump, 4);
+            let mut damaged = bytes[..at].to_vec();
+            damaged.extend((0..length).map(|byte| byte as u8 ^ 0xa5));
+            damaged.extend_from_slice(&bytes[at..]);
+            let (recovered, damage) = Dump::recover(&damaged).unwrap();
+            assert_eq!(recovered.to_bytes(), bytes);
+            assert_eq!(
+                damage,
+                [Damage {
+                    offset: at,
+                    length,
+                    lost: 0,
+                }]
+            );
+        }
+
+        // A record that lost its place: gone from the middle, garbage in its
+        // stead at the end.
+        let mut damaged = bytes[..record_offset(&dump, 2)].to_vec();
+        damaged.extend_from_slice(&bytes[record_offset(&dump, 3)..]);
+        damaged.extend_from_slice(&[0xff; 3]);
+        let (recovered, damage) = Dump::recover(&damaged).unwrap();
+        assert_eq!(recovered.records.len(), 7);
+        assert_eq!(
+            damage,
+            [
+                Damage {
+                    offset: record_offset(&dump, 2),
+                    length: 0,
+                    lost: 1,
+                },
+                Damage {
+                    offset: record_offset(&dump, 7),
+                    length: 3,
+                    lost: 0,
+                },
+            ]
+        );
+
+        // Only the header must be whole.
+        let header = dump.header.format.size as usize;
+        let (recovered, damage) = Dump::recover(&bytes[..header]).unwrap();
+        assert!(recovered.records.is_empty());
+        assert_eq!(damage[0].lost, 8);
+        assert_eq!(
+            Dump::recover(&bytes[..header - 1]).err(),
+            Some(FormatError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,463 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/expect.rs
+//
+// The serial log of a scripted boot checked against the records it should
+// hold (see tools/smoke). An expected-output file is a list of steps, one per
+// line:
+//
+//   > <text>          a marker: the first line from there on containing
+//                     <text>, such as the shell echoing it
+//   G: ... / S: ...   a record, in the driver's format, of a variable the
+//                     script accesses; '*' in it matches any text
+//   ? G: ... / ? S: ...
+//                     the same, for a record that may be missing
+//   @ calls           the next "Calls at ReadyToBoot" summary, whose counts
+//                     must be those of the G: and S: records logged before
+//                     it
+//
+// Blank lines and lines starting with '#' are left out. The markers must be
+// found in order. After a marker, the records of the variables the file
+// names, by GUID and name, must be exactly those listed after it, in order,
+// until the next marker; the firmware's own accesses to other variables may
+// come in between and are skipped, as is everything before the first marker.
+// A '*' may not stand in the GUID or name of a record.
+//
+// The counts only add up when every call has a record: at level trace, with
+// an empty filter, no latency budget and without log-deferred. Lines are
+// taken without the escape sequences of the console the shell mirrors to
+// serial.
+
+use crate::summary::{parse_access, Access};
+use std::fmt;
+
+const SUMMARY: &str = "Calls at ReadyToBoot: ";
+const SUCCESS: &str = "0x0";
+
+#[derive(Debug, PartialEq, Eq)]
+enum Step<'a> {
+    Marker(&'a str),
+    Record { pattern: &'a str, optional: bool },
+    Calls,
+}
+
+// A mismatch between the log and the expected steps, at a line of the
+// expected-output file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+// The steps a log was found to hold.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Matched {
+    pub markers: usize,
+    pub records: usize,
+    pub summaries: usize,
+}
+
+/**
+ * @brief Returns whether `text` matches `pattern`, in which '*' stands for
+ *        any run of characters.
+ */
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts: Vec<&str> = parts.collect();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/**
+ * @brief Returns `line` without its carriage return and ANSI escape
+ *        sequences.
+ */
+fn clean(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // CSI: parameters up to a final byte in '@'..='~'.
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\r' => {}
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+fn parse(expected: &str) -> Result<Vec<(usize, Step<'_>)>, Mismatch> {
+    let mut steps = Vec::new();
+    for (index, line) in expected.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let step = if let Some(text) = line.strip_prefix("> ") {
+            Step::Marker(text)
+        } else if line == "@ calls" {
+            Step::Calls
+        } else {
+            let (pattern, optional) = match line.strip_prefix("? ") {
+                Some(pattern) => (pattern, true),
+                None => (line, false),
+            };
+            match parse_access(pattern) {
+                Some(access) if !access.guid.contains('*') && !access.name.contains('*') => {
+                    Step::Record { pattern, optional }
+                }
+                _ => {
+                    return Err(Mismatch {
+                        line: index + 1,
+                        message: format!("not a marker, record or summary: {}", line),
+                    })
+                }
+            }
+        };
+        steps.push((index + 1, step));
+    }
+    Ok(steps)
+}
+
+// Calls counted from the records, in the order of a summary: GetVariable
+// calls, the successful ones, then SetVariable's.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Counts([u64; 4]);
+
+impl Counts {
+    fn add(&mut self, access: &Access) {
+        let index = if access.write { 2 } else { 0 };
+        self.0[index] += 1;
+        let status = access.status.and_then(|status| status.split(' ').next());
+        if status == Some(SUCCESS) {
+            self.0[index + 1] += 1;
+        }
+    }
+
+    /**
+     * @brief Returns the counts a summary line logs, after SUMMARY.
+     */
+    fn parse(text: &str) -> Option<Self> {
+        let mut counts = [0; 4];
+        let mut numbers = text
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|word| !word.is_empty());
+        for count in counts.iter_mut() {
+            *count = numbers.next()?.parse().ok()?;
+        }
+        Some(Counts(counts))
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "GetVariable={} ({} ok) SetVariable={} ({} ok)",
+            self.0[0], self.0[1], self.0[2], self.0[3]
+        )
+    }
+}
+
+struct Checker<'a> {
+    lines: Vec<String>,
+    // The variables the file names, by GUID and name.
+    watched: Vec<(&'a str, &'a str)>,
+    next: usize,
+    counts: Counts,
+    matched: Matched,
+}
+
+impl Checker<'_> {
+    /**
+     * @brief Returns the record on `line` and whether it is of a variable the
+     *        file names.
+     */
+    fn access<'l>(&self, line: &'l str) -> Option<(Access<'l>, bool)> {
+        let access = parse_access(line)?;
+        let watched = self.watched.contains(&(access.guid, access.name));
+        Some((access, watched))
+    }
+
+    /**
+     * @brief Counts the records of the lines up to `end`, failing on one of a
+     *        variable the file names if `strict`.
+     */
+    fn skip_to(&mut self, end: usize, strict: bool, step: usize) -> Result<(), Mismatch> {
+        while self.next < end {
+            let line = &self.lines[self.next];
+            if let Some((access, watched)) = self.access(line) {
+                if watched && strict {
+                    return Err(Mismatch {
+                        line: step,
+                        message: format!("unexpected {}", line),
+                    });
+                }
+                self.counts.add(&access);
+            }
+            self.next += 1;
+        }
+        Ok(())
+    }
+
+    /**
+     * @brief Returns the index of the first line from the next containing
+     *        `text`.
+     */
+    fn find(&self, text: &str) -> Option<usize> {
+        self.lines[self.next..]
+            .iter()
+            .position(|line| line.contains(text))
+            .map(|index| self.next + index)
+    }
+
+    fn marker(&mut self, text: &str, started: bool, step: usize) -> Result<(), Mismatch> {
+        let found = self.find(text).ok_or_else(|| Mismatch {
+            line: step,
+            message: format!("no marker '{}'", text),
+        })?;
+        self.skip_to(found, started, step)?;
+        self.next = found + 1;
+        self.matched.markers += 1;
+        Ok(())
+    }
+
+    /**
+     * @brief Finds the next record of a variable the file names, before the
+     *        line `end`, and matches it against `pattern`.
+     */
+    fn record(
+        &mut self,
+        pattern: &str,
+        optional: bool,
+        end: usize,
+        step: usize,
+    ) -> Result<(), Mismatch> {
+        while self.next < end {
+            let line = &self.lines[self.next];
+            match self.access(line) {
+                Some((access, true)) => {
+                    if matches(pattern, line) {
+                        self.counts.add(&access);
+                        self.next += 1;
+                        self.matched.records += 1;
+                        return Ok(());
+                    }
+                    if optional {
+                        return Ok(());
+                    }
+                    return Err(Mismatch {
+                        line: step,
+                        message: format!("expected {}, found {}", pattern, line),
+                    });
+                }
+                Some((access, false)) => self.counts.add(&access),
+                None => {}
+            }
+            self.next += 1;
+        }
+        if optional {
+            return Ok(());
+        }
+        Err(Mismatch {
+            line: step,
+            message: format!("no {}", pattern),
+        })
+    }
+
+    /**
+     * @brief Finds the next summary, before the line `end`, and compares its
+     *        counts with those of the records before it.
+     */
+    fn calls(&mut self, end: usize, step: usize) -> Result<(), Mismatch> {
+        let found = self.lines[self.next..end]
+            .iter()
+            .position(|line| line.contains(SUMMARY))
+            .map(|index| self.next + index)
+            .ok_or_else(|| Mismatch {
+                line: step,
+                message: "no ReadyToBoot summary".to_string(),
+            })?;
+        self.skip_to(found, true, step)?;
+        let line = &self.lines[found];
+        let summary = &line[line.find(SUMMARY).unwrap_or(0)..];
+        if Counts::parse(&summary[SUMMARY.len()..]) != Some(self.counts) {
+            return Err(Mismatch {
+                line: step,
+                message: format!(
+                    "{}, but the records before it count {}",
+                    summary, self.counts
+                ),
+            });
+        }
+        self.next = found + 1;
+        self.matched.summaries += 1;
+        Ok(())
+    }
+}
+
+/**
+ * @brief Checks `log` against the steps of `expected`. Returns the steps
+ *        matched, or the first mismatch.
+ */
+pub fn check(log: &str, expected: &str) -> Result<Matched, Mismatch> {
+    let steps = parse(expected)?;
+    let watched = steps
+        .iter()
+        .filter_map(|(_, step)| match step {
+            Step::Record { pattern, .. } => parse_access(pattern),
+            _ => None,
+        })
+        .map(|access| (access.guid, access.name))
+        .collect();
+    let mut checker = Checker {
+        lines: log.lines().map(clean).collect(),
+        watched,
+        next: 0,
+        counts: Counts::default(),
+        matched: Matched::default(),
+    };
+    let mut started = false;
+    for (index, (step, kind)) in steps.iter().enumerate() {
+        // Records and summaries are looked for up to the next marker.
+        let next_marker = steps[index + 1..].iter().find_map(|(_, step)| match step {
+            Step::Marker(text) => Some(*text),
+            _ => None,
+        });
+        let end = match (kind, next_marker) {
+            (Step::Marker(_), _) | (_, None) => checker.lines.len(),
+            (_, Some(text)) => checker.find(text).unwrap_or(checker.lines.len()),
+        };
+        match kind {
+            Step::Marker(text) => {
+                checker.marker(text, started, *step)?;
+                started = true;
+            }
+            Step::Record { pattern, optional } => checker.record(pattern, *optional, end, *step)?,
+            Step::Calls => checker.calls(end, *step)?,
+        }
+    }
+    let last = steps.last().map_or(0, |(step, _)| *step);
+    let end = checker.lines.len();
+    checker.skip_to(end, started, last)?;
+    Ok(checker.matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED: &str = include_str!("../../smoke/expected.txt");
+    // Written after the driver's formats and the shell's output, with the
+    // shell's own reads in between: not captured from OVMF.
+    const LOG: &str = include_str!("../fixtures/smoke.log");
+
+    #[test]
+    fn wildcards_match_any_text() {
+        assert!(matches("G: * SecureBoot: 0x0*", "G: 8BE4 SecureBoot: 0x0"));
+        assert!(matches(
+            "G: * SecureBoot: 0x0*",
+            "G: 8BE4 SecureBoot: 0x0 NEW"
+        ));
+        assert!(!matches(
+            "G: * SecureBoot: 0x0",
+            "G: 8BE4 SecureBoot: 0x0 NEW"
+        ));
+        assert!(matches("a*b*b", "abb"));
+        assert!(!matches("ab*b", "ab"));
+        assert!(matches("exact", "exact"));
+        assert!(!matches("exact", "exactly"));
+        assert_eq!(clean("\x1b[0m\x1b[37;40mFS0:\\> echo\r"), "FS0:\\> echo");
+    }
+
+    #[test]
+    fn the_smoke_log_matches() {
+        assert_eq!(
+            check(LOG, EXPECTED),
+            Ok(Matched {
+                markers: 6,
+                records: 9,
+                summaries: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn mismatches_are_reported_at_their_step() {
+        let expected = "\
+> step one
+G: 8BE4DF61-93CA-11D2-AA0D-00E098032B8C Size=00000000->* SecureBoot: 0x8000000000000005
+? G: 8BE4DF61-93CA-11D2-AA0D-00E098032B8C Size=00000001->00000001 SecureBoot: 0x0
+> step two
+@ calls
+";
+        let guid = "8BE4DF61-93CA-11D2-AA0D-00E098032B8C";
+        let log = format!(
+            "\
+G: {guid} Size=00000004->00000004 Timeout: 0x0\r
+\x1b[1mstep one\r
+G: {guid} Size=00000000->00000001 SecureBoot: 0x8000000000000005\r
+G: {guid} Size=00000004->00000004 Timeout: 0x0\r
+step two\r
+S: {guid} Attributes=00000007 Size=00000004 Timeout: 0x8000000000000008\r
+[hook] Calls at ReadyToBoot: GetVariable=3 (2 ok) SetVariable=1 (0 ok)\r
+",
+            guid = guid
+        );
+        // The optional read is missing, and the records of other variables
+        // are counted but not matched.
+        assert_eq!(
+            check(&log, expected),
+            Ok(Matched {
+                markers: 2,
+                records: 1,
+                summaries: 1,
+            })
+        );
+
+        let wrong_count = log.replace("SetVariable=1", "SetVariable=2");
+        assert_eq!(check(&wrong_count, expected).unwrap_err().line, 5);
+        let wrong_status = log.replace(
+            "->00000001 SecureBoot: 0x8000000000000005",
+            "->n/a SecureBoot: 0x800000000000000e",
+        );
+        assert_eq!(check(&wrong_status, expected).unwrap_err().line, 2);
+        let extra = log.replace(
+            "step two\r",
+            &format!("G: {} Size=00000001->00000001 SecureBoot: 0x0\r\nG: {0} Size=00000001->00000001 SecureBoot: 0x0\r\nstep two\r", guid),
+        );
+        assert_eq!(check(&extra, expected).unwrap_err().line, 4);
+        let unmarked = log.replace("step two", "step 2");
+        assert_eq!(
+            check(&unmarked, expected).unwrap_err().message,
+            "no marker 'step two'"
+        );
+        assert!(check(&log, "G: 8BE4DF61-* SecureBoot: 0x0").is_err());
+    }
+}
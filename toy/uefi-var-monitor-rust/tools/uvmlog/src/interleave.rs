@@ -0,0 +1,90 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/interleave.rs
+//
+// The serial log of a driver built with per-cpu, put back in order. From
+// ExitBootServices on, such a driver writes its records queue by queue, each
+// tagged with the CPU and a sequence number (see interface/src/percpu.rs).
+// Every run of tagged lines is sorted by sequence number, and the numbers
+// missing from it, records dropped by a full or busy queue, are reported
+// where they would have been. Other lines, the records written inline before
+// the handoff and whatever else shares the port, are kept where they are.
+//
+// A record that reaches serial after an untagged line is sorted within the
+// run it arrived in only.
+
+use std::fmt::{self, Write};
+use uvm_interface::percpu::CpuTag;
+
+/**
+ * @brief Writes the lines of `log` with each run of tagged lines in order.
+ */
+pub fn interleave(log: &str, out: &mut impl Write) -> fmt::Result {
+    let mut run = Vec::new();
+    let mut next = None;
+    for line in log.lines() {
+        let line = line.trim_end_matches('\r');
+        match CpuTag::parse(line) {
+            Some((tag, _)) => run.push((tag.sequence, line)),
+            None => {
+                write_run(&mut run, &mut next, out)?;
+                writeln!(out, "{}", line)?;
+            }
+        }
+    }
+    write_run(&mut run, &mut next, out)
+}
+
+/**
+ * @brief Writes a run of tagged lines sorted, with the gaps from `next`, the
+ *        sequence number expected after the last run.
+ */
+fn write_run(
+    run: &mut Vec<(u64, &str)>,
+    next: &mut Option<u64>,
+    out: &mut impl Write,
+) -> fmt::Result {
+    run.sort_by_key(|&(sequence, _)| sequence);
+    for &(sequence, line) in run.iter() {
+        let expected = next.unwrap_or(0);
+        if sequence > expected {
+            writeln!(out, "---- #{}..#{} missing ----", expected, sequence)?;
+        }
+        writeln!(out, "{}", line)?;
+        *next = Some(sequence + 1);
+    }
+    run.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_are_sorted_and_gaps_reported() {
+        let log = "---- ExitBootServices: handing off to the OS ----\r
+@1#1 G: B\r
+@1#3 G: D\r
+@0#0 G: A\r
+@0#4 G: E\r
+console noise\r
+@2#7 G: H\r
+@0#5 G: F\r
+";
+        let mut text = String::new();
+        interleave(log, &mut text).unwrap();
+        assert_eq!(
+            text,
+            "---- ExitBootServices: handing off to the OS ----
+@0#0 G: A
+@1#1 G: B
+---- #2..#3 missing ----
+@1#3 G: D
+@0#4 G: E
+console noise
+@0#5 G: F
+---- #6..#7 missing ----
+@2#7 G: H
+"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/main.rs
+//
+// Host tool decoding what the monitor leaves behind:
+//
+//   uvmlog decode <dump>            the ring buffer header, then one line per
+//                                   record
+//   uvmlog verify <dump> [report]   check that the records link up, and that
+//                                   the chain goes through the head the boot
+//                                   report committed to
+//   uvmlog recover <dump>           what is left of a damaged dump: where it
+//                                   was damaged and how many records were
+//                                   lost, then the records found
+//   uvmlog report <report>          the boot-report variable, field by field
+//   uvmlog summary <dump>           accesses per vendor GUID and per variable
+//   uvmlog interleave <log>         a serial log of the per-CPU queues, in
+//                                   order
+//   uvmlog expect <log> <expected>  check that a serial log holds the
+//                                   records expected (see expect.rs)
+//   uvmlog replay <dump> [report]   the calls of a dump run through the
+//       [--filter <list>]           driver's decisions, and where they
+//       [--limits <list>]           differ from what it logged (see
+//                                   replay.rs)
+//
+// <dump> is a ring buffer written by uvmctl dump (see dump.rs), <report> the
+// boot-report variable, as copied from efivarfs or as bare data (see
+// report.rs), <log> the serial output of the driver (see interleave.rs).
+// verify exits with 1 if a check fails, recover if the dump was damaged,
+// replay if it differs, expect if the log does not match, and every command with 2 if its input cannot be
+// read.
+//
+// The formats come from the interface crate the driver is built against too;
+// the files under fixtures/ pin them. replay links the driver's library for
+// its decisions; fixtures/traces holds dumps of boots and what replaying them
+// writes.
+
+mod dump;
+mod expect;
+mod interleave;
+mod replay;
+mod report;
+mod summary;
+
+use dump::{Chain, Commitment, Damage, Dump, Link};
+use r_efi::efi;
+use replay::Settings;
+use std::fmt;
+use std::process;
+use summary::Summary;
+use uefi_var_monitor::alert::{parse_limits, DEFAULT_LIMITS};
+use uefi_var_monitor::pattern::PatternTable;
+use uvm_interface::report::BootReport;
+
+const USAGE: &str = "usage: uvmlog decode <dump>
+       uvmlog verify <dump> [report]
+       uvmlog recover <dump>
+       uvmlog report <report>
+       uvmlog summary <dump>
+       uvmlog interleave <log>
+       uvmlog expect <log> <expected>
+       uvmlog replay <dump> [report] [--filter <list>] [--limits <list>]";
+
+// A vendor GUID, in registry format as the driver logs it.
+pub struct GuidFmt<'a>(pub &'a efi::Guid);
+
+impl fmt::Display for GuidFmt<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low, node) = self.0.as_fields();
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+            time_low, time_mid, time_hi, clk_seq_hi, clk_seq_low
+        )?;
+        for byte in node.iter() {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * @brief Exits with a message and the status for unreadable input.
+ */
+fn fail(message: impl fmt::Display) -> ! {
+    eprintln!("uvmlog: {}", message);
+    process::exit(2);
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|error| fail(format_args!("{}: {}", path, error)))
+}
+
+fn read_dump(path: &str) -> Dump {
+    Dump::parse(&read_file(path)).unwrap_or_else(|error| fail(format_args!("{}: {}", path, error)))
+}
+
+fn read_report(path: &str) -> BootReport {
+    BootReport::parse(report::variable_data(&read_file(path)))
+        .unwrap_or_else(|error| fail(format_args!("{}: {}", path, error)))
+}
+
+/**
+ * @brief Prints what verify found out. Returns whether every check passed.
+ */
+fn verify(dump: &Dump, report: Option<&BootReport>) -> bool {
+    let mut passed = true;
+    match dump.verify() {
+        Chain::Verified => println!(
+            "Chain: verified, #{}..#{}",
+            dump.header.first_sequence, dump.header.next_sequence
+        ),
+        Chain::VerifiedFrom(sequence) => println!(
+            "Chain: verified from #{}, records before it were overwritten during the dump",
+            sequence
+        ),
+        Chain::Broken(sequence) => {
+            println!("Chain: broken at #{}", sequence);
+            passed = false;
+        }
+        Chain::Missing(sequence) => {
+            println!("Chain: #{} is missing", sequence);
+            passed = false;
+        }
+        Chain::Head => {
+            println!(
+                "Chain: the newest record is not the head {}",
+                Link(&dump.header.chain_head)
+            );
+            passed = false;
+        }
+    }
+    if let Some(report) = report {
+        match dump.check_commitment(report) {
+            Commitment::Matches(sequence) => {
+                println!("Boot report: the chain goes through #{}", sequence)
+            }
+            Commitment::Differs(sequence) => {
+                println!(
+                    "Boot report: #{} is not the record committed to ({})",
+                    sequence,
+                    Link(&report.chain_head)
+                );
+                passed = false;
+            }
+            Commitment::Empty => println!("Boot report: no record committed to"),
+            Commitment::NotHeld(sequence) => {
+                println!("Boot report: #{} committed to is not in the dump", sequence);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+/**
+ * @brief Writes where a dump was damaged, then its records. Returns whether
+ *        it was whole.
+ */
+fn recover(dump: &Dump, damage: &[Damage], out: &mut impl fmt::Write) -> bool {
+    let lost: u64 = damage.iter().map(|damage| damage.lost).sum();
+    let _ = writeln!(
+        out,
+        "Recovered {} records, {} lost to damage",
+        dump.records.len(),
+        lost
+    );
+    for damage in damage {
+        let _ = writeln!(
+            out,
+            "Damage at offset {:#x}: {} bytes skipped, {} records lost",
+            damage.offset, damage.length, damage.lost
+        );
+    }
+    let _ = dump.decode(out);
+    damage.is_empty()
+}
+
+/**
+ * @brief Returns the report path and settings of replay's arguments, or None
+ *        if they are not understood. A malformed filter list is fatal.
+ */
+fn replay_arguments<'a>(arguments: &[&'a str]) -> Option<(Option<&'a str>, Settings)> {
+    let mut report = None;
+    let mut settings = Settings {
+        filter: PatternTable::new(),
+        limits: DEFAULT_LIMITS,
+    };
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match *argument {
+            "--filter" => {
+                let list = arguments.next()?;
+                let mut filter = PatternTable::new();
+                if filter.add_list(list).1 != 0 {
+                    fail(format_args!("malformed filter list: {}", list));
+                }
+                settings.filter = filter;
+            }
+            "--limits" => settings.limits = parse_limits(arguments.next()?),
+            path if report.is_none() && !path.starts_with('-') => report = Some(path),
+            _ => return None,
+        }
+    }
+    Some((report, settings))
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+    let mut text = String::new();
+    match arguments[..] {
+        ["decode", path] => {
+            let _ = read_dump(path).decode(&mut text);
+        }
+        ["verify", path] => {
+            if !verify(&read_dump(path), None) {
+                process::exit(1);
+            }
+        }
+        ["verify", path, report] => {
+            if !verify(&read_dump(path), Some(&read_report(report))) {
+                process::exit(1);
+            }
+        }
+        ["recover", path] => {
+            let (dump, damage) = Dump::recover(&read_file(path))
+                .unwrap_or_else(|error| fail(format_args!("{}: {}", path, error)));
+            if !recover(&dump, &damage, &mut text) {
+                print!("{}", text);
+                process::exit(1);
+            }
+        }
+        ["report", path] => {
+            let _ = report::describe(&read_report(path), &mut text);
+        }
+        ["summary", path] => {
+            let _ = Summary::collect(&read_dump(path).records).write(&mut text);
+        }
+        ["interleave", path] => {
+            let log = String::from_utf8_lossy(&read_file(path)).into_owned();
+            let _ = interleave::interleave(&log, &mut text);
+        }
+        ["expect", path, expected] => {
+            let log = String::from_utf8_lossy(&read_file(path)).into_owned();
+            let expected = String::from_utf8_lossy(&read_file(expected)).into_owned();
+            match expect::check(&log, &expected) {
+                Ok(matched) => println!(
+                    "Matched {} markers, {} records, {} ReadyToBoot summaries",
+                    matched.markers, matched.records, matched.summaries
+                ),
+                Err(mismatch) => {
+                    println!("Mismatch at {}", mismatch);
+                    process::exit(1);
+                }
+            }
+        }
+        ["replay", path, ref rest @ ..] => {
+            let (report, settings) = replay_arguments(rest).unwrap_or_else(|| {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            });
+            let report = report.map(read_report);
+            let (replay, differences) =
+                replay::replay(&read_dump(path), report.as_ref(), &settings);
+            let _ = replay::write(&replay, &differences, &mut text);
+            if !differences.is_empty() {
+                print!("{}", text);
+                process::exit(1);
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+    print!("{}", text);
+}
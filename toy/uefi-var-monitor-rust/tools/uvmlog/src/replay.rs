@@ -0,0 +1,628 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/replay.rs
+//
+// A dump run through the driver's own decisions again (the library's
+// src/replay.rs): each raw GetVariable and SetVariable record, in order, as
+// the call it records. What the replay decides is compared with what the
+// unit logged:
+//
+//   the alerts of the rules replayed, Secure Boot key reads and writes: the
+//   alert a call fires is the record after it, with the same suppressed
+//   count, and no other alert of those rules is logged
+//
+//   with a boot report, the suppressed total of each rule replayed, as of
+//   the report the driver writes at ExitBootServices
+//
+// Two text records of the unit steer the replay: the calibration of the
+// cycle counter, whose ticks per second the cooldowns in seconds run on (see
+// the driver's rate.rs), and the state of the ring buffer, logged at
+// ExitBootServices, after which calls count as made at runtime.
+//
+// Only the calls the dump holds are replayed, from the oldest one held. A
+// dump whose oldest records were overwritten, or a unit whose trace filter
+// left calls unrecorded, logs alerts the replay cannot account for; calls
+// logged as text records, such as the first sighting of a new variable, are
+// not replayed either. The limits are the defaults unless given, as the
+// unit's build gave them with UVM_ALERT_LIMITS, and the trace filter is
+// none unless given; other ones show what they would have decided.
+
+use crate::dump::Dump;
+use crate::GuidFmt;
+use r_efi::efi;
+use std::fmt::{self, Write};
+use uefi_var_monitor::alert::{Access, LimitTable, Rule};
+use uefi_var_monitor::filter::MAX_FILTERED;
+use uefi_var_monitor::pattern::PatternTable;
+use uefi_var_monitor::replay::{Alert, Call, Replay};
+use uvm_interface::protocol::{CallCounts, TopEntry, OUTCOMES, PHASE_BOOT_SERVICES, PHASE_RUNTIME};
+use uvm_interface::report::BootReport;
+use uvm_interface::ring::{
+    AccessRecord, RecordText, RingRecord, ACCESS_NAME_LENGTH, ACCESS_NO_SIZE, RECORD_SET_VARIABLE,
+    RING_DATA_SIZE,
+};
+
+// The rules raised by the name, GUID and status of a call alone.
+const REPLAYED: [Rule; 2] = [Rule::SecureBootKeyRead, Rule::SecureBootKeyWrite];
+
+// The text records followed, anywhere in the record.
+const CALIBRATION: &str = "Access rate alarm at ";
+const EXIT_BOOT_SERVICES: &str = "Ring buffer holds #";
+const ALERT: &str = "ALERT: [";
+
+const OUTCOME_NAMES: [&str; OUTCOMES] = [
+    "success",
+    "not-found",
+    "buffer-too-small",
+    "invalid-parameter",
+    "write-protected",
+    "security-violation",
+    "out-of-resources",
+    "device-error",
+    "other",
+];
+
+// Most accessed variables written out.
+const TOP_SHOWN: usize = 8;
+
+pub struct Settings {
+    pub filter: PatternTable<MAX_FILTERED>,
+    pub limits: LimitTable,
+}
+
+// An alert of a rule replayed that fired, with its suppressed count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fired {
+    pub rule: Rule,
+    pub suppressed: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difference {
+    // Fired by the call recorded at the sequence number, but not logged
+    // after it.
+    NotLogged(u64, Fired),
+    // Logged at the sequence number, but fired by no call.
+    NotReplayed(u64, Fired),
+    // Logged at the sequence number with another suppressed count than the
+    // one replayed.
+    Suppressed(u64, Fired, u32),
+    // The boot report's suppressed total of the rule, and the one replayed.
+    Report(Rule, u32, u32),
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Difference::NotLogged(sequence, fired) => write!(
+                f,
+                "#{} {}: replayed ({} suppressed), not logged",
+                sequence,
+                fired.rule.name(),
+                fired.suppressed
+            ),
+            Difference::NotReplayed(sequence, fired) => write!(
+                f,
+                "#{} {}: logged ({} suppressed), not replayed",
+                sequence,
+                fired.rule.name(),
+                fired.suppressed
+            ),
+            Difference::Suppressed(sequence, fired, replayed) => write!(
+                f,
+                "#{} {}: logged ({} suppressed), replayed ({} suppressed)",
+                sequence,
+                fired.rule.name(),
+                fired.suppressed,
+                replayed
+            ),
+            Difference::Report(rule, reported, replayed) => write!(
+                f,
+                "Boot report {}: {} suppressed, replayed {}",
+                rule.name(),
+                reported,
+                replayed
+            ),
+        }
+    }
+}
+
+/**
+ * @brief Returns the call a raw record holds.
+ */
+fn call<'a>(kind: u8, access: &'a AccessRecord, name: &'a str) -> Call<'a> {
+    let size = match access.size {
+        ACCESS_NO_SIZE => None,
+        size => Some(size as usize),
+    };
+    let access_kind = match kind {
+        RECORD_SET_VARIABLE => Access::Set {
+            attributes: access.argument,
+            data_size: size.unwrap_or(0),
+        },
+        _ => Access::Get,
+    };
+    Call {
+        name,
+        guid: &access.guid,
+        access: access_kind,
+        efi_status: efi::Status::from_usize(access.status as usize),
+        size,
+        cycles: access.cycles,
+    }
+}
+
+fn name(access: &AccessRecord) -> String {
+    let length = access
+        .name
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(ACCESS_NAME_LENGTH);
+    String::from_utf16_lossy(&access.name[..length])
+}
+
+/**
+ * @brief Returns the ticks per second of a calibration record.
+ */
+fn calibration(text: &str) -> Option<u64> {
+    let rest = &text[text.find(CALIBRATION)?..];
+    let (_, ticks) = rest.strip_suffix(" ticks/s")?.rsplit_once(' ')?;
+    ticks.parse().ok()
+}
+
+/**
+ * @brief Returns the rule of the alert a record logs, if replayed, and the
+ *        suppressed count it carries, None if the record was cut before it:
+ *        "ALERT: [<severity>] <Get|Set>Variable of Secure Boot key ...",
+ *        ending with " (<count> suppressed)" unless none were.
+ */
+fn logged_alert(record: &RingRecord) -> Option<(Rule, Option<u32>)> {
+    let text = RecordText(record).to_string();
+    let rest = &text[text.find(ALERT)?..];
+    let (_, rest) = rest.split_once(']')?;
+    let rule = if rest.starts_with(" GetVariable of Secure Boot key ") {
+        Rule::SecureBootKeyRead
+    } else if rest.starts_with(" SetVariable of Secure Boot key ") {
+        Rule::SecureBootKeyWrite
+    } else {
+        return None;
+    };
+    let suppressed = match rest.strip_suffix(" suppressed)") {
+        Some(rest) => rest.rsplit_once('(')?.1.parse().ok(),
+        None if record.data().len() == RING_DATA_SIZE => None,
+        None => Some(0),
+    };
+    Some((rule, suppressed))
+}
+
+/**
+ * @brief Matches an alert logged, with its sequence number, rule and
+ *        suppressed count if known, with the one the last call fired, if
+ *        any.
+ */
+fn match_alert(
+    pending: Option<(u64, Fired)>,
+    (sequence, rule, logged): (u64, Rule, Option<u32>),
+    differences: &mut Vec<Difference>,
+) {
+    match pending {
+        Some((_, fired)) if fired.rule == rule => match logged {
+            Some(logged) if logged != fired.suppressed => {
+                let logged = Fired {
+                    rule,
+                    suppressed: logged,
+                };
+                differences.push(Difference::Suppressed(sequence, logged, fired.suppressed));
+            }
+            _ => {}
+        },
+        other => {
+            if let Some((sequence, fired)) = other {
+                differences.push(Difference::NotLogged(sequence, fired));
+            }
+            let logged = Fired {
+                rule,
+                suppressed: logged.unwrap_or(0),
+            };
+            differences.push(Difference::NotReplayed(sequence, logged));
+        }
+    }
+}
+
+/**
+ * @brief Replays the calls of `dump`. Returns the replay, as of the end of
+ *        the dump, and where it differs from what the unit logged and, if
+ *        given, reported.
+ */
+pub fn replay(
+    dump: &Dump,
+    report: Option<&BootReport>,
+    settings: &Settings,
+) -> (Replay, Vec<Difference>) {
+    let mut replay = Replay::new(settings.filter, &settings.limits);
+    let mut differences = Vec::new();
+    // The alert fired by the last call, until it is found logged.
+    let mut pending: Option<(u64, Fired)> = None;
+    let mut at_exit = None;
+    for record in &dump.records {
+        let access = match record.access() {
+            Some(access) => access,
+            None => {
+                if let Some((rule, logged)) = logged_alert(record) {
+                    let logged = (record.sequence, rule, logged);
+                    match_alert(pending.take(), logged, &mut differences);
+                    continue;
+                }
+                let text = RecordText(record).to_string();
+                if let Some(ticks_per_second) = calibration(&text) {
+                    replay.calibrate(ticks_per_second);
+                } else if text.contains(EXIT_BOOT_SERVICES) {
+                    replay.exit_boot_services();
+                    at_exit = Some(REPLAYED.map(|rule| replay.suppressed(rule)));
+                }
+                continue;
+            }
+        };
+        if let Some((sequence, fired)) = pending.take() {
+            differences.push(Difference::NotLogged(sequence, fired));
+        }
+        let name = name(&access);
+        let decision = replay.feed(&call(record.kind, &access, &name));
+        if let Some(Alert {
+            rule,
+            shown: Some(suppressed),
+            ..
+        }) = decision.alert
+        {
+            pending = Some((record.sequence, Fired { rule, suppressed }));
+        }
+    }
+    if let Some((sequence, fired)) = pending {
+        differences.push(Difference::NotLogged(sequence, fired));
+    }
+    if let Some(report) = report {
+        let replayed = at_exit.unwrap_or_else(|| REPLAYED.map(|rule| replay.suppressed(rule)));
+        for (rule, replayed) in REPLAYED.iter().zip(replayed) {
+            let reported = report.suppressed_alerts[*rule as usize];
+            if reported != replayed {
+                differences.push(Difference::Report(*rule, reported, replayed));
+            }
+        }
+    }
+    (replay, differences)
+}
+
+fn write_counts(call: &str, counts: &CallCounts, out: &mut impl Write) -> fmt::Result {
+    write!(
+        out,
+        "{}: boot-services={} runtime={}\n ",
+        call, counts.by_phase[PHASE_BOOT_SERVICES], counts.by_phase[PHASE_RUNTIME]
+    )?;
+    for (name, count) in OUTCOME_NAMES.iter().zip(counts.by_status.iter()) {
+        if *count != 0 {
+            write!(out, " {}={}", name, count)?;
+        }
+    }
+    writeln!(out)
+}
+
+/**
+ * @brief Writes what the replay counted and decided, then the differences.
+ */
+pub fn write(replay: &Replay, differences: &[Difference], out: &mut impl Write) -> fmt::Result {
+    let calls: u64 = replay
+        .get_variable
+        .by_phase
+        .iter()
+        .chain(replay.set_variable.by_phase.iter())
+        .sum();
+    writeln!(out, "Replayed {} calls, {} traced", calls, replay.traced)?;
+    write_counts("GetVariable", &replay.get_variable, out)?;
+    write_counts("SetVariable", &replay.set_variable, out)?;
+    let mut top = [TopEntry::EMPTY; TOP_SHOWN];
+    let count = replay.top(&mut top);
+    writeln!(out, "Most accessed:")?;
+    for entry in &top[..count] {
+        writeln!(
+            out,
+            "  {} {} reads={} writes={}",
+            GuidFmt(&entry.guid),
+            entry.name(),
+            entry.reads,
+            entry.writes
+        )?;
+    }
+    write!(out, "Alerts:")?;
+    for rule in REPLAYED {
+        write!(
+            out,
+            " {} fired={} suppressed={}",
+            rule.name(),
+            replay.fired[rule as usize],
+            replay.suppressed(rule)
+        )?;
+    }
+    writeln!(out)?;
+    for difference in differences {
+        writeln!(out, "{}", difference)?;
+    }
+    match differences.len() {
+        0 => writeln!(out, "Replay: as logged"),
+        count => writeln!(out, "Replay: {} differences", count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uefi_var_monitor::alert::{parse_limits, DEFAULT_LIMITS};
+    use uefi_var_monitor::classify::{GLOBAL_VARIABLE_GUID, IMAGE_SECURITY_DATABASE_GUID};
+    use uvm_interface::ring::{
+        self, access_size, RingHeader, RECORD_GET_VARIABLE, RING_CHAIN_SIZE,
+    };
+
+    const REPORT: &[u8] = include_bytes!("../fixtures/UvmBootReport");
+
+    // 1 MHz, for round cycle counts.
+    const TICKS_PER_SECOND: u64 = 1_000_000;
+
+    enum Event {
+        Text(&'static str),
+        Get(&'static str, efi::Guid, efi::Status, f64),
+        Set(&'static str, efi::Guid, u32, usize, efi::Status, f64),
+    }
+
+    use Event::*;
+
+    const SUCCESS: efi::Status = efi::Status::SUCCESS;
+    const NOT_FOUND: efi::Status = efi::Status::NOT_FOUND;
+    const SECURITY_VIOLATION: efi::Status = efi::Status::SECURITY_VIOLATION;
+
+    /**
+     * @brief Returns a boot as a unit built with the default limits logs
+     *        it, or with `cooldown`, one built with key-read=1/300s:
+     *        firmware and the loader read the keys a few times in the first
+     *        minute and again later, and something tries to append to db at
+     *        runtime.
+     */
+    fn boot(cooldown: bool) -> Vec<Event> {
+        let g = GLOBAL_VARIABLE_GUID;
+        let d = IMAGE_SECURITY_DATABASE_GUID;
+        let mut events = vec![
+            Text("[hook] Access rate alarm at 1000 calls/s over 5 s, 1000000 ticks/s"),
+            Get("PK", g, SUCCESS, 0.5),
+            Text("ALERT: [info] GetVariable of Secure Boot key PK: 0x0"),
+            Get("KEK", g, SUCCESS, 0.6),
+            Get("db", d, SUCCESS, 0.7),
+            Get("dbx", d, SUCCESS, 0.8),
+            Get("dbt", d, NOT_FOUND, 0.9),
+            Get("BootOrder", g, SUCCESS, 2.0),
+            Get("Boot0001", g, SUCCESS, 2.1),
+            Get("PK", g, SUCCESS, 61.0),
+        ];
+        if !cooldown {
+            events.push(Text(
+                "ALERT: [info] GetVariable of Secure Boot key PK: 0x0 (4 suppressed)",
+            ));
+        }
+        events.extend([
+            Get("db", d, SUCCESS, 61.2),
+            Text(match cooldown {
+                true => "Alerts suppressed: key-read=6",
+                false => "Alerts suppressed: key-read=5",
+            }),
+            Text("[sink] Ring buffer holds #0..#15, dropped=0 overwritten=0"),
+            Set("db", d, 0x67, 0x5c8, SECURITY_VIOLATION, 90.0),
+            Text(
+                "ALERT: [critical] SetVariable of Secure Boot key db Attributes=0x00000067 \
+                 Size=000005c8: 0x800000000000001a",
+            ),
+            Set("BootNext", g, 0x7, 2, SUCCESS, 95.0),
+            Get("PK", g, SUCCESS, 130.0),
+        ]);
+        if !cooldown {
+            events.push(Text(
+                "ALERT: [info] GetVariable of Secure Boot key PK: 0x0 (1 suppressed)",
+            ));
+        }
+        events
+    }
+
+    /**
+     * @brief Returns the dump of `events`, all held, as the driver chains
+     *        them.
+     */
+    fn dump(events: &[Event]) -> Dump {
+        let mut header = RingHeader {
+            format: RingHeader::FORMAT,
+            capacity: events.len() as u32,
+            next_sequence: events.len() as u64,
+            ..RingHeader::default()
+        };
+        let mut records = Vec::new();
+        let mut previous = [0u8; RING_CHAIN_SIZE];
+        for event in events {
+            let (kind, guid, name, argument, size, status, seconds) = match *event {
+                Text(text) => {
+                    // Cut as the ring cuts it.
+                    let text = &text.as_bytes()[..text.len().min(RING_DATA_SIZE)];
+                    let mut record = RingRecord::EMPTY;
+                    record.length = text.len() as u16;
+                    record.data[..text.len()].copy_from_slice(text);
+                    records.push(record);
+                    continue;
+                }
+                Get(name, guid, status, seconds) => {
+                    let size = match status {
+                        SUCCESS => Some(4),
+                        _ => None,
+                    };
+                    (
+                        RECORD_GET_VARIABLE,
+                        guid,
+                        name,
+                        access_size(Some(0x100)),
+                        access_size(size),
+                        status,
+                        seconds,
+                    )
+                }
+                Set(name, guid, attributes, size, status, seconds) => (
+                    RECORD_SET_VARIABLE,
+                    guid,
+                    name,
+                    attributes,
+                    access_size(Some(size)),
+                    status,
+                    seconds,
+                ),
+            };
+            let mut access = AccessRecord {
+                guid,
+                status: status.as_usize() as u64,
+                cycles: (seconds * TICKS_PER_SECOND as f64) as u64,
+                argument,
+                size,
+                name: [0; ACCESS_NAME_LENGTH],
+            };
+            for (c, next) in access.name.iter_mut().zip(name.encode_utf16()) {
+                *c = next;
+            }
+            records.push(RingRecord::raw(kind, &access));
+        }
+        for (sequence, record) in records.iter_mut().enumerate() {
+            record.sequence = sequence as u64;
+            record.chain = ring::link(record, &previous);
+            previous = record.chain;
+        }
+        header.chain_head = previous;
+        Dump { header, records }
+    }
+
+    fn settings(limits: &str) -> Settings {
+        Settings {
+            filter: PatternTable::new(),
+            limits: parse_limits(limits),
+        }
+    }
+
+    /**
+     * @brief Compares `dump` with the trace at `path` under fixtures/traces,
+     *        and what replaying it under the default settings writes with
+     *        the text next to it; or with UVM_BLESS set, writes both there.
+     */
+    fn check_trace(path: &str, dump: &Dump) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/traces")
+            .join(path);
+        let bytes = dump.to_bytes();
+        let (replay, differences) = replay(dump, None, &settings(""));
+        let mut text = String::new();
+        write(&replay, &differences, &mut text).unwrap();
+        if std::env::var_os("UVM_BLESS").is_some() {
+            std::fs::write(path.with_extension("bin"), bytes).unwrap();
+            std::fs::write(path.with_extension("txt"), text).unwrap();
+            return;
+        }
+        assert_eq!(std::fs::read(path.with_extension("bin")).unwrap(), bytes);
+        assert_eq!(
+            std::fs::read_to_string(path.with_extension("txt")).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn a_boot_replays_as_logged() {
+        let dump = dump(&boot(false));
+        assert_eq!(dump.verify(), crate::dump::Chain::Verified);
+        let (replay, differences) = replay(&dump, None, &settings(""));
+        assert_eq!(differences, []);
+        assert_eq!(replay.fired[Rule::SecureBootKeyRead as usize], 3);
+        assert_eq!(replay.suppressed(Rule::SecureBootKeyRead), 5);
+        assert_eq!(replay.fired[Rule::SecureBootKeyWrite as usize], 1);
+        assert_eq!(replay.get_variable.by_phase, [9, 1]);
+        assert_eq!(replay.set_variable.by_phase, [0, 2]);
+        check_trace("boot", &dump);
+
+        // The report written at ExitBootServices had 5 suppressed, whatever
+        // came after.
+        let mut report = BootReport::parse(crate::report::variable_data(REPORT)).unwrap();
+        report.suppressed_alerts = [0; 32];
+        report.suppressed_alerts[Rule::SecureBootKeyRead as usize] = 5;
+        assert_eq!(replay_with_report(&dump, &report), []);
+        report.suppressed_alerts[Rule::SecureBootKeyWrite as usize] = 1;
+        assert_eq!(
+            replay_with_report(&dump, &report),
+            [Difference::Report(Rule::SecureBootKeyWrite, 1, 0)]
+        );
+    }
+
+    fn replay_with_report(dump: &Dump, report: &BootReport) -> Vec<Difference> {
+        replay(dump, Some(report), &settings("")).1
+    }
+
+    #[test]
+    fn other_limits_are_found_and_can_be_replayed() {
+        // A unit built with UVM_ALERT_LIMITS="key-read=1/300s".
+        let dump = dump(&boot(true));
+        let (_, differences) = replay(&dump, None, &settings(""));
+        let fired = |suppressed| Fired {
+            rule: Rule::SecureBootKeyRead,
+            suppressed,
+        };
+        assert_eq!(
+            differences,
+            [
+                Difference::NotLogged(9, fired(4)),
+                Difference::NotLogged(16, fired(1)),
+            ]
+        );
+        check_trace("cooldown", &dump);
+
+        let (replay, differences) = replay(&dump, None, &settings("key-read=1/300s"));
+        assert_eq!(differences, []);
+        assert_eq!(replay.suppressed(Rule::SecureBootKeyRead), 7);
+    }
+
+    #[test]
+    fn alerts_are_matched_to_the_call_before_them() {
+        let g = GLOBAL_VARIABLE_GUID;
+        let logged = "ALERT: [info] GetVariable of Secure Boot key PK: 0x0 (2 suppressed)";
+        let dump = dump(&[
+            Get("PK", g, SUCCESS, 1.0),
+            Text(logged),
+            Text("ALERT: [critical] SetVariable of Secure Boot key KEK Attributes=0x00000027 Size=00000400: 0x0"),
+            Text("ALERT: [warning] Size of BootOrder changed"),
+        ]);
+        let fired = |rule, suppressed| Fired { rule, suppressed };
+        assert_eq!(
+            replay(&dump, None, &settings("")).1,
+            [
+                Difference::Suppressed(1, fired(Rule::SecureBootKeyRead, 2), 0),
+                Difference::NotReplayed(2, fired(Rule::SecureBootKeyWrite, 0)),
+            ]
+        );
+        assert_eq!(
+            logged_alert(&dump.records[1]),
+            Some((Rule::SecureBootKeyRead, Some(2)))
+        );
+        assert_eq!(
+            calibration("[hook] Access rate alarm at 10 calls/s over 2 s, 2400000000 ticks/s"),
+            Some(2_400_000_000)
+        );
+    }
+
+    #[test]
+    fn a_filter_changes_what_is_traced_only() {
+        let mut filter = PatternTable::new();
+        assert_eq!(
+            filter.add_list("8be4df61-93ca-11d2-aa0d-00e098032b8c:Boot*"),
+            (1, 0)
+        );
+        let settings = Settings {
+            filter,
+            limits: DEFAULT_LIMITS,
+        };
+        let (replay, differences) = replay(&dump(&boot(false)), None, &settings);
+        assert_eq!(differences, []);
+        assert_eq!(replay.traced, 3);
+    }
+}
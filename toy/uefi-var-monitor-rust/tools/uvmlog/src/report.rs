@@ -0,0 +1,262 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/report.rs
+//
+// The boot-report variable, as read from Linux: efivarfs files
+// (/sys/firmware/efi/efivars/UvmBootReport-6c8a7f3e-2d4b-4f1a-9c5e-8b2d1f7a3c90)
+// start with the 4-byte attributes, ahead of the variable data and its magic.
+// The bare data is accepted as well.
+
+use crate::dump::{self, Link};
+use crate::GuidFmt;
+use std::fmt::{self, Write};
+use uvm_interface::protocol::{
+    HistoryEntry, HISTORY_FLAG_GAP, HISTORY_FLAG_INSPECTED, HISTORY_FLAG_RUNTIME,
+};
+use uvm_interface::report::{
+    BootReport, StoreSpace, NEW_AT_RUNTIME, NEW_BEFORE_END_OF_DXE, NEW_BEFORE_EXIT_BOOT_SERVICES,
+    REPORT_MAGIC,
+};
+
+/**
+ * @brief Returns the variable data of a report file.
+ */
+pub fn variable_data(bytes: &[u8]) -> &[u8] {
+    match bytes.get(4..8) {
+        Some(magic) if magic == REPORT_MAGIC && !bytes.starts_with(&REPORT_MAGIC) => &bytes[4..],
+        _ => bytes,
+    }
+}
+
+/**
+ * @brief Writes the report, one field per line.
+ */
+pub fn describe(report: &BootReport, out: &mut impl Write) -> fmt::Result {
+    writeln!(
+        out,
+        "Boot report version {}.{} ({} bytes)",
+        report.format.major, report.format.minor, report.format.size
+    )?;
+    writeln!(
+        out,
+        "Log chain: #{} head {}",
+        report.next_sequence,
+        Link(&report.chain_head)
+    )?;
+    writeln!(
+        out,
+        "Runtime writes to boot-critical variables: {} (previous boot {})",
+        report.runtime_boot_critical_writes, report.previous_runtime_boot_critical_writes
+    )?;
+    writeln!(out, "Learned sizes: {}", report.learned_sizes().len())?;
+    for learned in report.learned_sizes() {
+        writeln!(
+            out,
+            "  {} {:08x} size={:#x} confirmations={}",
+            GuidFmt(&learned.guid),
+            learned.name_crc32,
+            learned.size,
+            learned.confirmations
+        )?;
+    }
+    write!(out, "Suppressed alerts:")?;
+    for (rule, count) in report.suppressed_alerts.iter().enumerate() {
+        if *count != 0 {
+            write!(out, " rule {}={}", rule, count)?;
+        }
+    }
+    writeln!(out)?;
+    // Reports of minor version 0 have no history.
+    if report.format.minor == 0 {
+        return Ok(());
+    }
+    writeln!(out, "Write history: {}", report.history().len())?;
+    for entry in report.history() {
+        describe_write(entry, out)?;
+    }
+    // Reports of minor version 1 have no store space.
+    if report.format.minor == 1 {
+        return Ok(());
+    }
+    describe_store("load", &report.store_at_load, out)?;
+    describe_store("ExitBootServices", &report.store_at_exit, out)?;
+    // Reports of minor version 2 have no new variables.
+    if report.format.minor == 2 {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "New variables: {} before EndOfDxe, {} before ExitBootServices, {} at runtime",
+        report.new_counts[NEW_BEFORE_END_OF_DXE as usize],
+        report.new_counts[NEW_BEFORE_EXIT_BOOT_SERVICES as usize],
+        report.new_counts[NEW_AT_RUNTIME as usize]
+    )?;
+    for variable in report.new_variables() {
+        let stage = match variable.stage {
+            NEW_BEFORE_END_OF_DXE => "before-end-of-dxe",
+            NEW_BEFORE_EXIT_BOOT_SERVICES => "before-exit-boot-services",
+            NEW_AT_RUNTIME => "runtime",
+            _ => "?",
+        };
+        writeln!(
+            out,
+            "  {} {} {}",
+            GuidFmt(&variable.guid),
+            variable.name(),
+            stage
+        )?;
+    }
+    // Reports of minor version 3 have no build.
+    if report.format.minor == 3 {
+        return Ok(());
+    }
+    dump::describe_build(&report.build, out)
+}
+
+/**
+ * @brief Writes the space in the variable store at `point`, on one line.
+ */
+fn describe_store(point: &str, space: &StoreSpace, out: &mut impl Write) -> fmt::Result {
+    if !space.is_measured() {
+        return writeln!(out, "Variable store at {}: not measured", point);
+    }
+    writeln!(
+        out,
+        "Variable store at {}: attributes={:08x} maximum={:#x} remaining={:#x} max-variable={:#x}",
+        point, space.attributes, space.maximum, space.remaining, space.max_variable_size
+    )
+}
+
+/**
+ * @brief Writes one write of the history, on one line.
+ */
+pub fn describe_write(entry: &HistoryEntry, out: &mut impl Write) -> fmt::Result {
+    write!(
+        out,
+        "  #{} {} {} attributes={:08x} size={:#x} status={:#x} caller={:#x}",
+        entry.sequence,
+        GuidFmt(&entry.guid),
+        entry.name(),
+        entry.attributes,
+        entry.size,
+        entry.status,
+        entry.caller_page
+    )?;
+    if entry.flags & HISTORY_FLAG_INSPECTED != 0 {
+        write!(out, " crc32={:08x} data=", entry.crc32)?;
+        for byte in entry.data() {
+            write!(out, "{:02x}", byte)?;
+        }
+    }
+    if entry.flags & HISTORY_FLAG_RUNTIME != 0 {
+        write!(out, " runtime")?;
+    }
+    if entry.flags & HISTORY_FLAG_GAP != 0 {
+        write!(out, " after-gap")?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uvm_interface::build::{
+        BuildInfo, CONFIG_FROM_VARIABLE, HOOK_GET_VARIABLE, HOOK_SET_VARIABLE, SERIAL_IO_PORT,
+        SINK_SERIAL,
+    };
+
+    const REPORT: &[u8] = include_bytes!("../fixtures/UvmBootReport");
+    const REPORT_TEXT: &str = include_str!("../fixtures/UvmBootReport.txt");
+
+    #[test]
+    fn fixture_round_trips() {
+        // The fixture is an efivarfs file.
+        let data = variable_data(REPORT);
+        assert_eq!(data, &REPORT[4..]);
+        assert_eq!(variable_data(data), data);
+
+        let report = BootReport::parse(data).unwrap();
+        assert_eq!(&report.as_bytes()[..data.len()], data);
+        let mut text = String::new();
+        describe(&report, &mut text).unwrap();
+        assert_eq!(text, REPORT_TEXT);
+    }
+
+    #[test]
+    fn history_store_new_variables_and_build_are_described() {
+        let mut report = BootReport {
+            format: BootReport::FORMAT,
+            ..BootReport::EMPTY
+        };
+        let mut entry = HistoryEntry {
+            guid: report.learned_sizes[0].guid,
+            sequence: 3,
+            caller_page: 0x7e5f_0000,
+            size: 2,
+            attributes: 7,
+            crc32: 0x1234_abcd,
+            flags: HISTORY_FLAG_INSPECTED | HISTORY_FLAG_GAP,
+            data_length: 2,
+            name_length: 8,
+            ..HistoryEntry::EMPTY
+        };
+        entry.name[..8].copy_from_slice(b"BootNext");
+        entry.data[..2].copy_from_slice(&[1, 0]);
+        report.history[0] = entry;
+        report.history_count = 1;
+
+        report.store_at_load = StoreSpace {
+            attributes: 7,
+            maximum: 0x8_0000,
+            remaining: 0x7_0000,
+            max_variable_size: 0x8000,
+            ..StoreSpace::EMPTY
+        };
+
+        report.new_counts = [0, 1, 0];
+        report.new_variables[0].stage = NEW_BEFORE_EXIT_BOOT_SERVICES;
+        report.new_variables[0].name[..3].copy_from_slice(b"Foo");
+        report.new_variables[0].name_length = 3;
+        report.new_variable_count = 1;
+
+        report.build = BuildInfo {
+            version: BuildInfo::text("0.1.1"),
+            profile: BuildInfo::text("production"),
+            hooks: HOOK_GET_VARIABLE | HOOK_SET_VARIABLE,
+            sinks: SINK_SERIAL,
+            config: CONFIG_FROM_VARIABLE,
+            serial: SERIAL_IO_PORT,
+            serial_base: 0x3f8,
+            ..BuildInfo::EMPTY
+        };
+
+        let mut text = String::new();
+        describe(&report, &mut text).unwrap();
+        assert!(text.ends_with(
+            "before-exit-boot-services\nBuild: UefiVarMonitor 0.1.1, production profile, release, \
+             no build ID\n  Hooks: GetVariable, SetVariable\n  Sinks: serial\n  Serial: I/O port \
+             0x3f8, line set up by the firmware, inline\n  Configuration: build defaults, \
+             UvmConfig\n"
+        ));
+        report.format.minor = 3;
+        text.clear();
+        describe(&report, &mut text).unwrap();
+        assert!(text.ends_with(
+            "Variable store at ExitBootServices: not measured\nNew variables: 0 before EndOfDxe, \
+             1 before ExitBootServices, 0 at runtime\n  00000000-0000-0000-0000-000000000000 Foo \
+             before-exit-boot-services\n"
+        ));
+        report.format.minor = 2;
+        text.clear();
+        describe(&report, &mut text).unwrap();
+        assert!(text.ends_with(
+            "Variable store at load: attributes=00000007 maximum=0x80000 remaining=0x70000 \
+             max-variable=0x8000\nVariable store at ExitBootServices: not measured\n"
+        ));
+        report.format.minor = 1;
+        text.clear();
+        describe(&report, &mut text).unwrap();
+        assert!(text.ends_with(
+            "Write history: 1\n  #3 00000000-0000-0000-0000-000000000000 BootNext attributes=00000007 \
+             size=0x2 status=0x0 caller=0x7e5f0000 crc32=1234abcd data=0100 after-gap\n"
+        ));
+    }
+}
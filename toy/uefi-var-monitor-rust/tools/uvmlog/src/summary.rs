@@ -0,0 +1,229 @@
+// uefi-var-monitor-rust/tools/uvmlog/src/summary.rs
+//
+// Accesses per vendor GUID and per variable, counted from the GetVariable
+// ("G:") and SetVariable ("S:") records of a dump:
+//
+//   G: <GUID> Size=<before>-><after> <name>: <status>
+//   S: <GUID> Attributes=<attributes> Size=<size> <name>: <status>
+//
+// Records are cut at RING_DATA_SIZE bytes, which may take the status, and the
+// end of a long name, with it; such an access is still counted, under the
+// name as far as it was kept. Raw records (see the driver's src/deferred.rs)
+// are counted from the text they stand for. Alert records are counted on
+// their own.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use uvm_interface::ring::{RecordText, RingRecord};
+
+const ERROR_BIT: u64 = 1 << 63;
+// EFI_BUFFER_TOO_SMALL, returned to callers asking for the size of a variable
+// first; not counted as a failure.
+const BUFFER_TOO_SMALL: u64 = ERROR_BIT | 5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub reads: u64,
+    pub writes: u64,
+    // Accesses that returned an error.
+    pub failures: u64,
+}
+
+#[derive(Default)]
+pub struct Summary {
+    pub guids: BTreeMap<String, Counts>,
+    pub variables: BTreeMap<(String, String), Counts>,
+    pub alerts: u64,
+    // Records neither accesses nor alerts.
+    pub other: u64,
+}
+
+// A GetVariable or SetVariable access.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Access<'a> {
+    pub write: bool,
+    pub guid: &'a str,
+    pub name: &'a str,
+    // The status and what follows it, such as a NEW tag.
+    pub status: Option<&'a str>,
+}
+
+/**
+ * @brief Returns the access a record logs, if it logs one.
+ */
+pub fn parse_access(text: &str) -> Option<Access<'_>> {
+    let (write, field) = match text.get(..3)? {
+        "G: " => (false, "Size="),
+        "S: " => (true, "Attributes="),
+        _ => return None,
+    };
+    let rest = &text[3..];
+    let guid = rest.get(..36)?;
+    let rest = rest[36..].strip_prefix(' ')?.strip_prefix(field)?;
+    // The fields before the name.
+    let fields = if write { 2 } else { 1 };
+    let rest = rest.splitn(fields + 1, ' ').nth(fields)?;
+    let (name, status) = match rest.rfind(": 0x") {
+        Some(index) => (&rest[..index], Some(&rest[index + 2..])),
+        None => (rest, None),
+    };
+    Some(Access {
+        write,
+        guid,
+        name,
+        status,
+    })
+}
+
+impl Counts {
+    fn add(&mut self, access: &Access) {
+        if access.write {
+            self.writes += 1;
+        } else {
+            self.reads += 1;
+        }
+        let status = access
+            .status
+            .and_then(|status| u64::from_str_radix(&status[2..], 16).ok());
+        if let Some(status) = status {
+            if status & ERROR_BIT != 0 && status != BUFFER_TOO_SMALL {
+                self.failures += 1;
+            }
+        }
+    }
+}
+
+impl Summary {
+    /**
+     * @brief Counts the accesses and alerts of the records.
+     */
+    pub fn collect<'a>(records: impl IntoIterator<Item = &'a RingRecord>) -> Self {
+        let mut summary = Summary::default();
+        for record in records {
+            let text = RecordText(record).to_string();
+            if let Some(access) = parse_access(&text) {
+                summary
+                    .guids
+                    .entry(access.guid.to_string())
+                    .or_default()
+                    .add(&access);
+                summary
+                    .variables
+                    .entry((access.guid.to_string(), access.name.to_string()))
+                    .or_default()
+                    .add(&access);
+            } else if text.starts_with("ALERT: ") {
+                summary.alerts += 1;
+            } else {
+                summary.other += 1;
+            }
+        }
+        summary
+    }
+
+    /**
+     * @brief Writes the counts per GUID, then per variable, most accessed
+     *        first.
+     */
+    pub fn write(&self, out: &mut impl Write) -> fmt::Result {
+        fn by_accesses<K>(counts: &BTreeMap<K, Counts>) -> Vec<(&K, &Counts)> {
+            let mut sorted: Vec<_> = counts.iter().collect();
+            sorted.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.reads + counts.writes));
+            sorted
+        }
+
+        writeln!(
+            out,
+            "{:<36} {:>7} {:>7} {:>7}",
+            "GUID", "Reads", "Writes", "Failed"
+        )?;
+        for (guid, counts) in by_accesses(&self.guids) {
+            writeln!(
+                out,
+                "{:<36} {:>7} {:>7} {:>7}",
+                guid, counts.reads, counts.writes, counts.failures
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<36} {:<24} {:>7} {:>7} {:>7}",
+            "GUID", "Variable", "Reads", "Writes", "Failed"
+        )?;
+        for ((guid, name), counts) in by_accesses(&self.variables) {
+            writeln!(
+                out,
+                "{:<36} {:<24} {:>7} {:>7} {:>7}",
+                guid, name, counts.reads, counts.writes, counts.failures
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Alerts: {}, other records: {}",
+            self.alerts, self.other
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dump::Dump;
+
+    const RING: &[u8] = include_bytes!("../fixtures/ring.bin");
+    const SUMMARY_TEXT: &str = include_str!("../fixtures/summary.txt");
+    const GLOBAL: &str = "8BE4DF61-93CA-11D2-AA0D-00E098032B8C";
+
+    #[test]
+    fn accesses_are_parsed() {
+        assert_eq!(
+            parse_access(&format!(
+                "G: {} Size=00000000->00000008 BootOrder: 0x8000000000000005",
+                GLOBAL
+            )),
+            Some(Access {
+                write: false,
+                guid: GLOBAL,
+                name: "BootOrder",
+                status: Some("0x8000000000000005"),
+            })
+        );
+        assert_eq!(
+            parse_access(&format!(
+                "S: {} Attributes=00000007 Size=00000004 Boot0001: 0x0",
+                GLOBAL
+            )),
+            Some(Access {
+                write: true,
+                guid: GLOBAL,
+                name: "Boot0001",
+                status: Some("0x0"),
+            })
+        );
+        // Cut short, with the status lost.
+        assert_eq!(
+            parse_access(&format!("G: {} Size=n/a->n/a SomeVeryLongVariab", GLOBAL)),
+            Some(Access {
+                write: false,
+                guid: GLOBAL,
+                name: "SomeVeryLongVariab",
+                status: None,
+            })
+        );
+        // Hidden accesses are logged otherwise.
+        assert_eq!(
+            parse_access(&format!("G: {} db hidden from 7 (#1)", GLOBAL)),
+            None
+        );
+    }
+
+    #[test]
+    fn fixture_summary() {
+        let dump = Dump::parse(RING).unwrap();
+        let summary = Summary::collect(&dump.records);
+        let mut text = String::new();
+        summary.write(&mut text).unwrap();
+        assert_eq!(text, SUMMARY_TEXT);
+    }
+}